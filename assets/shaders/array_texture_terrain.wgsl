@@ -0,0 +1,66 @@
+#import bevy_pbr::mesh_view_bind_group
+#import bevy_pbr::mesh_struct
+
+[[group(1), binding(0)]]
+var layers: texture_2d_array<f32>;
+[[group(1), binding(1)]]
+var layer_sampler: sampler;
+
+[[group(2), binding(0)]]
+var<uniform> mesh: Mesh;
+
+struct Vertex {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] voxel_type: u32;
+#ifdef VERTEX_COLORS
+    [[location(3)]] color: vec4<f32>;
+#endif
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] world_position: vec4<f32>;
+    [[location(1)]] world_normal: vec3<f32>;
+    [[location(2)]] light: vec4<f32>;
+    // the same for every vertex of a face, so it isn't blended between voxel types
+    [[location(3), interpolate(flat)]] voxel_type: u32;
+};
+
+[[stage(vertex)]]
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.world_position = mesh.model * vec4<f32>(vertex.position, 1.0);
+    out.world_normal = mat3x3<f32>(
+        mesh.inverse_transpose_model[0].xyz,
+        mesh.inverse_transpose_model[1].xyz,
+        mesh.inverse_transpose_model[2].xyz
+    ) * vertex.normal;
+    out.clip_position = view.view_proj * out.world_position;
+#ifdef VERTEX_COLORS
+    out.light = vertex.color;
+#else
+    out.light = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+#endif
+    out.voxel_type = vertex.voxel_type;
+    return out;
+}
+
+// projects the layer along each world axis and blends by how much the surface faces it
+fn triplanar(layer: i32, position: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+    var weights = abs(normal);
+    weights = weights / (weights.x + weights.y + weights.z);
+    return textureSample(layers, layer_sampler, fract(position.zy), layer) * weights.x
+        + textureSample(layers, layer_sampler, fract(position.xz), layer) * weights.y
+        + textureSample(layers, layer_sampler, fract(position.xy), layer) * weights.z;
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let normal = normalize(in.world_normal);
+    // voxel types without a layer of their own use the last one
+    let layer = min(i32(in.voxel_type), textureNumLayers(layers) - 1);
+    let color = triplanar(layer, in.world_position.xyz, normal);
+    let shade = 0.5 + 0.5 * max(dot(normal, normalize(vec3<f32>(0.3, 1.0, 0.5))), 0.0);
+    return vec4<f32>(color.rgb * in.light.rgb * shade, color.a);
+}
@@ -0,0 +1,111 @@
+use super::{CameraRig, TerrainAnchor};
+use bevy::{input::mouse::MouseMotion, prelude::*};
+use std::f32::consts::FRAC_PI_2;
+
+// a first person camera, WASD moves along the view direction, E and Q move up and down and
+// dragging with the right mouse button looks around
+// shift speeds movement up and control slows it down
+#[derive(Debug, Clone, Copy, Component)]
+pub struct FlyCamera {
+  // radians around the y axis
+  pub yaw: f32,
+  // radians above the horizon
+  pub pitch: f32,
+  // units per second without modifiers
+  pub speed: f32,
+}
+impl Default for FlyCamera {
+  fn default() -> Self {
+    Self {
+      yaw: 0.,
+      pitch: -0.3,
+      speed: 10.,
+    }
+  }
+}
+
+impl FlyCamera {
+  pub fn rotation(&self) -> Quat {
+    Quat::from_rotation_y(self.yaw) * Quat::from_rotation_x(self.pitch)
+  }
+}
+
+pub struct FlyCameraPlugin;
+
+impl Plugin for FlyCameraPlugin {
+  fn build(&self, app: &mut App) {
+    app.add_startup_system(setup).add_system(fly_camera_system);
+  }
+}
+
+// radians per pixel dragged
+const LOOK_SPEED: f32 = 0.003;
+const FAST_MULTIPLIER: f32 = 4.0;
+const SLOW_MULTIPLIER: f32 = 0.25;
+// keeps the camera from flipping upside down
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.05;
+const START_HEIGHT: f32 = 10.5;
+
+pub fn setup(mut commands: Commands) {
+  let fly = FlyCamera::default();
+  let position = Vec3::new(0., START_HEIGHT, 0.);
+  commands
+    .spawn_bundle(PerspectiveCameraBundle {
+      transform: Transform::from_translation(position).with_rotation(fly.rotation()),
+      ..default()
+    })
+    .insert(CameraRig {
+      focus: position,
+      zoom: 0.,
+    })
+    .insert(fly)
+    .insert(TerrainAnchor);
+}
+
+pub fn fly_camera_system(
+  time: Res<Time>,
+  keys: Res<Input<KeyCode>>,
+  buttons: Res<Input<MouseButton>>,
+  mut mouse_motion_events: EventReader<MouseMotion>,
+  mut camera_query: Query<(&mut Transform, &mut CameraRig, &mut FlyCamera)>,
+) {
+  let drag = mouse_motion_events
+    .iter()
+    .fold(Vec2::ZERO, |drag, event| drag + event.delta);
+
+  if let Ok((mut transform, mut rig, mut fly)) = camera_query.get_single_mut() {
+    if buttons.pressed(MouseButton::Right) {
+      fly.yaw -= drag.x * LOOK_SPEED;
+      fly.pitch = (fly.pitch - drag.y * LOOK_SPEED).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+    transform.rotation = fly.rotation();
+
+    let mut direction = Vec3::ZERO;
+    for (key, towards) in [
+      (KeyCode::W, transform.forward()),
+      (KeyCode::S, transform.back()),
+      (KeyCode::A, transform.left()),
+      (KeyCode::D, transform.right()),
+      (KeyCode::E, Vec3::Y),
+      (KeyCode::Q, -Vec3::Y),
+    ] {
+      if keys.pressed(key) {
+        direction += towards;
+      }
+    }
+
+    let mut speed = fly.speed;
+    if keys.pressed(KeyCode::LShift) {
+      speed *= FAST_MULTIPLIER;
+    }
+    if keys.pressed(KeyCode::LControl) {
+      speed *= SLOW_MULTIPLIER;
+    }
+    transform.translation += direction.normalize_or_zero() * speed * time.delta_seconds();
+
+    // a first person camera is always fully zoomed in on where it is
+    if rig.focus != transform.translation {
+      rig.focus = transform.translation;
+    }
+  }
+}
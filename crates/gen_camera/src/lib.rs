@@ -1,3 +1,9 @@
+mod fly;
+mod orbit;
+mod rig;
 mod rts;
 
-pub use rts::{RtsCamera, RtsCameraPlugin};
+pub use fly::{FlyCamera, FlyCameraPlugin};
+pub use orbit::{OrbitCamera, OrbitCameraPlugin};
+pub use rig::{CameraRig, TerrainAnchor};
+pub use rts::{RtsCamera, RtsCameraConfig, RtsCameraPlugin};
@@ -0,0 +1,99 @@
+use super::{CameraRig, TerrainAnchor};
+use bevy::{
+  input::mouse::{MouseMotion, MouseWheel},
+  prelude::*,
+};
+use std::f32::consts::FRAC_PI_2;
+
+// a camera circling its rig's focus, dragging with the left mouse button orbits, the middle mouse
+// button pans the focus along the ground and scrolling zooms
+#[derive(Debug, Clone, Copy, Component)]
+pub struct OrbitCamera {
+  // radians around the y axis
+  pub yaw: f32,
+  // radians above the ground plane
+  pub pitch: f32,
+}
+impl Default for OrbitCamera {
+  fn default() -> Self {
+    Self {
+      yaw: 0.,
+      pitch: 0.6,
+    }
+  }
+}
+
+impl OrbitCamera {
+  pub fn transform(&self, rig: &CameraRig) -> Transform {
+    let distance = rig.zoomed(MIN_DISTANCE, MAX_DISTANCE);
+    let rotation = Quat::from_rotation_y(self.yaw) * Quat::from_rotation_x(-self.pitch);
+    Transform::from_translation(rig.focus + rotation * Vec3::Z * distance)
+      .looking_at(rig.focus, Vec3::Y)
+  }
+}
+
+pub struct OrbitCameraPlugin;
+
+impl Plugin for OrbitCameraPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_startup_system(setup)
+      .add_system(orbit_camera_system);
+  }
+}
+
+// radians per pixel dragged
+const ORBIT_SPEED: f32 = 0.005;
+// fraction of the distance to the focus panned per pixel dragged
+const PAN_SPEED: f32 = 0.002;
+const ZOOM_SPEED: f32 = 0.05;
+const MIN_DISTANCE: f32 = 5.0;
+const MAX_DISTANCE: f32 = 200.0;
+const START_DISTANCE: f32 = 15.0;
+// keeps the camera from flipping over the focus
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.05;
+const MIN_PITCH: f32 = 0.05;
+
+pub fn setup(mut commands: Commands) {
+  let rig = CameraRig {
+    focus: Vec3::ZERO,
+    zoom: (START_DISTANCE - MIN_DISTANCE) / (MAX_DISTANCE - MIN_DISTANCE),
+  };
+  let orbit = OrbitCamera::default();
+  commands
+    .spawn_bundle(PerspectiveCameraBundle {
+      transform: orbit.transform(&rig),
+      ..default()
+    })
+    .insert(rig)
+    .insert(orbit)
+    .insert(TerrainAnchor);
+}
+
+pub fn orbit_camera_system(
+  buttons: Res<Input<MouseButton>>,
+  mut mouse_motion_events: EventReader<MouseMotion>,
+  mut mouse_wheel_events: EventReader<MouseWheel>,
+  mut camera_query: Query<(&mut Transform, &mut CameraRig, &mut OrbitCamera)>,
+) {
+  let drag = mouse_motion_events
+    .iter()
+    .fold(Vec2::ZERO, |drag, event| drag + event.delta);
+  let scroll: f32 = mouse_wheel_events.iter().map(|event| event.y).sum();
+
+  if let Ok((mut transform, mut rig, mut orbit)) = camera_query.get_single_mut() {
+    if buttons.pressed(MouseButton::Left) {
+      orbit.yaw -= drag.x * ORBIT_SPEED;
+      orbit.pitch = (orbit.pitch + drag.y * ORBIT_SPEED).clamp(MIN_PITCH, MAX_PITCH);
+    } else if buttons.pressed(MouseButton::Middle) {
+      // pan along the ground so the camera keeps its height above the focus
+      let right = Quat::from_rotation_y(orbit.yaw) * Vec3::X;
+      let back = Quat::from_rotation_y(orbit.yaw) * Vec3::Z;
+      let speed = rig.zoomed(MIN_DISTANCE, MAX_DISTANCE) * PAN_SPEED;
+      rig.focus += (-right * drag.x - back * drag.y) * speed;
+    }
+    rig.scroll(scroll, ZOOM_SPEED);
+
+    *transform = orbit.transform(&rig);
+  }
+}
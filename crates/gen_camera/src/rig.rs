@@ -0,0 +1,29 @@
+use bevy::prelude::*;
+
+// state every camera style keeps up to date, so systems that follow the camera (e.g. chunk
+// loading) work the same whichever style is used
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct CameraRig {
+  // the point on the ground the camera is looking at
+  pub focus: Vec3,
+  // 0 is fully zoomed in and 1 fully zoomed out
+  pub zoom: f32,
+}
+
+impl CameraRig {
+  // distance between `min` and `max` for the current zoom
+  pub fn zoomed(&self, min: f32, max: f32) -> f32 {
+    min + (max - min) * self.zoom.clamp(0., 1.)
+  }
+
+  // zooms by a mouse wheel delta, scrolling up zooms in
+  pub fn scroll(&mut self, scroll: f32, speed: f32) {
+    if scroll != 0. {
+      self.zoom = (self.zoom - scroll * speed).clamp(0., 1.);
+    }
+  }
+}
+
+// marks cameras terrain should be loaded around, every camera style inserts it
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct TerrainAnchor;
@@ -1,49 +1,96 @@
-use bevy::{prelude::*, window::CursorMoved};
+use super::{CameraRig, TerrainAnchor};
+use bevy::{input::mouse::MouseWheel, prelude::*, window::CursorMoved};
 
-#[derive(Component)]
+// a top-down camera that pans when the cursor nears the window edges, scrolling zooms by changing
+// its height
+#[derive(Debug, Default, Component)]
 pub struct RtsCamera;
 
+// how the rts camera moves, a resource
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtsCameraConfig {
+  // pan when the cursor nears the window edges
+  pub edge_pan: bool,
+  // the area in world space the point being looked at is kept in, left and right bound x, top
+  // and bottom bound z, e.g. the terrain's extents so the camera stays over the map
+  pub bounds: Option<Rect<f32>>,
+  // camera heights when fully zoomed in and out
+  pub min_height: f32,
+  pub max_height: f32,
+}
+impl Default for RtsCameraConfig {
+  fn default() -> Self {
+    Self {
+      edge_pan: true,
+      bounds: None,
+      min_height: 5.0,
+      max_height: 200.0,
+    }
+  }
+}
+
 pub struct RtsCameraPlugin;
 
 impl Plugin for RtsCameraPlugin {
   fn build(&self, app: &mut App) {
-    app.add_startup_system(setup).add_system(rts_camera_system);
+    app
+      .init_resource::<RtsCameraConfig>()
+      .add_startup_system(setup)
+      .add_system(rts_camera_system);
   }
 }
 
 const MOUSE_PAN_SPEED: f32 = 100.0;
 const MOUSE_PAN_MARGINS: f32 = 0.1;
+const ZOOM_SPEED: f32 = 0.05;
+const START_HEIGHT: f32 = 10.5;
 
 #[derive(Default)]
 pub struct State {
   pos: Vec2,
 }
 
-pub fn setup(mut commands: Commands) {
+pub fn setup(mut commands: Commands, config: Res<RtsCameraConfig>) {
   commands
     .spawn_bundle(PerspectiveCameraBundle {
-      transform: Transform::from_xyz(-2.0, 10.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+      transform: Transform::from_xyz(-2.0, START_HEIGHT, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
       ..default()
     })
-    .insert(RtsCamera);
+    .insert(CameraRig {
+      focus: Vec3::ZERO,
+      // equal heights leave no range to zoom in
+      zoom: ((START_HEIGHT - config.min_height)
+        / (config.max_height - config.min_height).max(f32::EPSILON))
+      .clamp(0., 1.),
+    })
+    .insert(RtsCamera)
+    .insert(TerrainAnchor);
 }
 
 pub fn rts_camera_system(
   mut state: Local<State>,
   time: Res<Time>,
+  config: Res<RtsCameraConfig>,
   windows: Res<Windows>,
   mut cursor_moved_events: EventReader<CursorMoved>,
-  mut camera_query: Query<&mut Transform, With<RtsCamera>>,
+  mut mouse_wheel_events: EventReader<MouseWheel>,
+  mut camera_query: Query<(&mut Transform, &mut CameraRig), With<RtsCamera>>,
 ) {
   // Get latest cursor location
   if let Some(event) = cursor_moved_events.iter().next_back() {
     // Adjust for window size and store in 0.0 - 1.0 range
-    let window = windows.get(event.id).expect("window not found");
-    state.pos.x = event.position.x / (window.width() as f32);
-    state.pos.y = event.position.y / (window.height() as f32);
+    // the window may have been closed since the event was sent
+    if let Some(window) = windows.get(event.id) {
+      state.pos.x = event.position.x / (window.width() as f32);
+      state.pos.y = event.position.y / (window.height() as f32);
+    }
   }
 
-  let pos = state.pos;
+  // a cursor resting at the edge doesn't pan
+  let pos = match config.edge_pan {
+    true => state.pos,
+    false => Vec2::splat(0.5),
+  };
 
   // Check if mouse is within edge margins for x
   let horizontal = if pos.x < MOUSE_PAN_MARGINS {
@@ -63,9 +110,35 @@ pub fn rts_camera_system(
     0.
   };
 
+  let scroll: f32 = mouse_wheel_events.iter().map(|event| event.y).sum();
+
   // Apply movement to camera
-  if let Ok(mut transform) = camera_query.get_single_mut() {
+  if let Ok((mut transform, mut rig)) = camera_query.get_single_mut() {
     transform.translation.x += horizontal * time.delta_seconds();
     transform.translation.z += vertical * time.delta_seconds();
+
+    rig.scroll(scroll, ZOOM_SPEED);
+
+    // move along the view direction so the point being looked at stays put
+    let forward = transform.forward();
+    if forward.y < -f32::EPSILON {
+      let height = rig.zoomed(config.min_height, config.max_height);
+      let offset = (transform.translation.y - height) / -forward.y;
+      if offset.abs() > f32::EPSILON {
+        transform.translation += forward * offset;
+      }
+      rig.focus = transform.translation + forward * (transform.translation.y / -forward.y);
+
+      // moving the camera and the point it's looking at together keeps the view angle
+      if let Some(bounds) = config.bounds {
+        let clamped = Vec3::new(
+          rig.focus.x.max(bounds.left).min(bounds.right),
+          rig.focus.y,
+          rig.focus.z.max(bounds.top).min(bounds.bottom),
+        );
+        transform.translation += clamped - rig.focus;
+        rig.focus = clamped;
+      }
+    }
   }
 }
@@ -0,0 +1,96 @@
+// cargo bench -p gen_terrain --features terrain-bench
+use bevy::{math::Vec3, tasks::TaskPool};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gen_terrain::{bench::DenseChunk, WorldGenConfig};
+
+// (name, chunk voxel length, chunk voxel height), the default layout is 23 x 10 x 23 voxels
+const CHUNK_SIZES: [(&str, i64, i64); 3] = [("small", 4, 8), ("default", 11, 10), ("tall", 16, 32)];
+// half the voxels are solid, close to the most faces a chunk can have
+const DENSITY: f64 = 0.5;
+
+fn generation(c: &mut Criterion) {
+  let pool = TaskPool::new();
+  let config = WorldGenConfig::default();
+  let mut group = c.benchmark_group("generate_chunk");
+  for (name, length, height) in CHUNK_SIZES {
+    let fixture = DenseChunk::new(length, height, DENSITY);
+    group.throughput(Throughput::Elements(fixture.voxels.len() as u64));
+    group.bench_function(BenchmarkId::from_parameter(name), |b| {
+      b.iter(|| fixture.generate(&pool, &config))
+    });
+  }
+  group.finish();
+}
+
+fn meshing(c: &mut Criterion) {
+  let mut group = c.benchmark_group("mesh_chunk");
+  for (name, length, height) in CHUNK_SIZES {
+    let fixture = DenseChunk::new(length, height, DENSITY);
+    group.throughput(Throughput::Elements(fixture.voxels.len() as u64));
+    group.bench_function(BenchmarkId::from_parameter(name), |b| {
+      b.iter(|| fixture.mesh())
+    });
+  }
+  group.finish();
+}
+
+fn conversions(c: &mut Criterion) {
+  let (_, length, height) = CHUNK_SIZES[1];
+  let fixture = DenseChunk::new(length, height, DENSITY);
+  let layout = &fixture.layout;
+  let voxels: Vec<_> = fixture.voxels.keys().copied().collect();
+  let points: Vec<Vec3> = voxels
+    .iter()
+    .map(|voxel| layout.voxel_center(voxel))
+    .collect();
+
+  let mut group = c.benchmark_group("layout");
+  group.throughput(Throughput::Elements(voxels.len() as u64));
+  group.bench_function("voxel_to_chunk", |b| {
+    b.iter(|| {
+      for voxel in voxels.iter() {
+        black_box(layout.voxel_to_chunk(black_box(voxel)));
+      }
+    })
+  });
+  group.bench_function("voxel_to_space", |b| {
+    b.iter(|| {
+      for voxel in voxels.iter() {
+        black_box(layout.voxel_to_space(black_box(voxel)));
+      }
+    })
+  });
+  group.bench_function("space_to_voxel", |b| {
+    b.iter(|| {
+      for point in points.iter() {
+        black_box(layout.space_to_voxel(black_box(point)));
+      }
+    })
+  });
+  group.bench_function("space_to_chunk", |b| {
+    b.iter(|| {
+      for point in points.iter() {
+        black_box(layout.space_to_chunk(black_box(point)));
+      }
+    })
+  });
+  group.finish();
+}
+
+fn edit_latency(c: &mut Criterion) {
+  let mut group = c.benchmark_group("edit_and_remesh");
+  for (name, length, height) in CHUNK_SIZES {
+    let mut fixture = DenseChunk::new(length, height, DENSITY);
+    let mut mesh = fixture.mesh();
+    let voxels: Vec<_> = fixture.voxels.keys().copied().collect();
+    // every iteration flips the next voxel, so edits don't keep hitting the same faces
+    let mut next = voxels.iter().cycle();
+    group.bench_function(BenchmarkId::from_parameter(name), |b| {
+      b.iter(|| fixture.edit_and_remesh(&mut mesh, *next.next().unwrap()))
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, generation, meshing, conversions, edit_latency);
+criterion_main!(benches);
@@ -1,3 +1,55 @@
 mod voxel;
 
-pub use voxel::{ChunkSpawner, VoxelTerrainPlugin};
+#[cfg(feature = "terrain-bench")]
+pub use voxel::bench;
+pub use voxel::headless;
+#[cfg(feature = "terrain-egui")]
+pub use voxel::TerrainInspectorPlugin;
+#[cfg(feature = "render")]
+pub use voxel::{
+  apply_decals, chunk_placement, generate_hex_mesh, generate_hex_mesh_with, generate_hex_voxels,
+  generate_sphere_chunk_mesh, generate_sphere_mesh, generate_sphere_voxels, mesh_chunk_lod,
+  mesh_hex_chunk, mesh_hex_chunk_with, mesh_sphere_chunk, mesh_sphere_chunk_with, mesh_tile_chunk,
+  occlusion_between, place_pois, scatter_foliage, screen_to_ray, sphere_border_voxels,
+  terrain_to_tile, tile_to_terrain, ApplyWorldSnapshot, BrushPreview, ChunkBatchPool, ChunkBounds,
+  ChunkDecals, ChunkDecorations, ChunkDespawning, ChunkDiffs, ChunkFoliage, ChunkLod,
+  ChunkLodSettings, ChunkMeshCache, ChunkMigrator, ChunkOcclusion, ChunkOcclusionSettings,
+  ChunkPipeline, ChunkPipelineBudget, ChunkPipelineStats, ChunkRetentionPolicy, ChunkSpawner,
+  ChunkSpawnerConfig, ChunkStateCounts, ChunkStore, ChunkTaskLimits, ChunkTracker,
+  ChunkVisibilitySettings, ChunkVoxelCache, ChunkVoxelMeta, ClippedChunk, CompressedVoxels,
+  ConfiguredVoxelTerrainPlugin, CubeFace, CubeHexLayout, CursorTerrainHit, DataOnlyChunk,
+  DecalEdit, Decoration, DecorationOf, DespawnDeferral, DespawningChunk, EditHistory, EditedVoxels,
+  EmptyChunk, EnclosedChunk, ExportFormat, ExportWorldMesh, FarChunk, FarChunkSettings,
+  FoliageInstance, FoliageInstances, FoliagePass, FoliageSettings, GenerationMode, HeightmapEdge,
+  HeightmapGenerator, HeightmapImage, HeightmapSettings, HeightmapTerrain, HexChunk, HexChunks,
+  HexMeshOptions, HexRing, HexSpawner, HexTerrainPlugin, HexTerrainSettings, LoadShape, LodBucket,
+  LodChanged, MergedMesh, MeshCachePolicy, MeshFaceIndex, MeshGroup, OutsideView, Poi,
+  PoiChunkMeshed, PoiId, PoiKind, PoiKindId, PoiRegistry, RegenerateTerrain, RegionId,
+  ScreenToTerrain, SnapshotError, SpawnerEnvironment, SphereChunk, SphereChunks, SphereSpawner,
+  SphereTerrainPlugin, SphereTerrainSettings, SphereVoxelLayout, StreamingAnchor,
+  StreamingAutoTune, TerrainBrush, TerrainDecorations, TerrainDiagnosticsPlugin,
+  TerrainEditorPlugin, TerrainEdits, TerrainExtents, TerrainFog, TerrainHit, TerrainJob,
+  TerrainJobFinished, TerrainJobId, TerrainJobProgress, TerrainJobs, TerrainMaterial,
+  TerrainMaterialConfig, TerrainMaterialPlugin, TerrainPregeneration, TerrainPregenerator,
+  TerrainQuery, TerrainReadiness, TerrainReadinessChanged, TerrainSchedule, TerrainStats,
+  TerrainStreaming, TerrainSystem, TerrainThumbnailPlugin, TerrainThumbnails, TerrainWorld,
+  TerrainWorlds, ThumbnailCamera, ThumbnailCaptured, ThumbnailId, ThumbnailRequest, TileChunk,
+  TileSet, TileSpawner, TileTerrainPlugin, VoxelCachePolicy, VoxelChanged, VoxelEdit, VoxelFace,
+  VoxelMeta, VoxelMetaChanged, VoxelMetaEditor, VoxelTerrainPlugin, VoxelTerrainPluginBuilder,
+  VoxelWorld, WorldGenAsset, WorldGenAssetLoader, WorldGenPasses, WorldGenSource, WorldSnapshot,
+  ATTRIBUTE_VOXEL_TYPE, CHUNK_FORMAT_VERSION, MAX_CAVITY_VOXELS, OCCLUSION_CELLS,
+  TERRAIN_MATERIAL_HANDLE,
+};
+pub use voxel::{
+  biome_at, bury, calculate_normals, calculate_tangents, fill_column_steps, fill_columns,
+  hex_column_heights, river_levels, road_levels, road_sites, trace_rivers, trace_roads,
+  ActiveGenerator, Biome, BiomeEntered, ChunkBiome, ChunkId, ChunkJob, ChunkLight, ChunkRng,
+  ChunkSeed, ChunkStep, ChunkVoxelData, ClimateMap, ColumnCache, ColumnSample, GenerateTangents,
+  GenerationContext, HeightMap, NormalMode, RegionSample, RiverFlow, TerrainError,
+  TerrainErrorEvent, TerrainGenerator, VoxelGenerator, VoxelId, VoxelRegistry, VoxelTypeId,
+  VoxelTypeInfo, WorldGenConfig, WorldRegion, WorldRegionSettings, WorldRegions,
+};
+#[cfg(all(debug_assertions, feature = "terrain-validate"))]
+pub use voxel::{stitching_problems, StitchProblem};
+#[cfg(feature = "terrain-net")]
+pub use voxel::{SyncRole, TerrainMessage, TerrainSync, TerrainSyncPlugin};
@@ -0,0 +1,106 @@
+use super::{
+  layout::CubicVoxelLayout,
+  prediction::ChunkSpawnerConfig,
+  tracker::ChunkTracker,
+  world::{TerrainWorld, TerrainWorlds},
+};
+use bevy::prelude::*;
+
+// keeps the chunks around an entity that isn't a camera loaded, e.g. an npc caravan or a
+// projectile that has to hit the terrain, without the prediction, load shapes and zoom of a
+// `ChunkSpawner`
+// chunks of spawners are spawned first, anchors share what's left of the spawn budget with
+// higher priorities going first
+// anchored chunks outside the mesh radius of every spawner are data only, so far away anchors
+// cost generation but no meshing
+#[derive(Debug, Clone, Copy, Component)]
+pub struct StreamingAnchor {
+  // rings of chunk columns kept loaded around the anchor, in the sections `ChunkSpawnerConfig`
+  // loads around spawners
+  pub radius: i64,
+  pub priority: u8,
+}
+impl Default for StreamingAnchor {
+  fn default() -> Self {
+    Self {
+      radius: 1,
+      priority: 0,
+    }
+  }
+}
+
+// gathers the chunks every anchor needs into the tracker of the anchor's world
+pub fn track_streaming_anchors(
+  layout: Res<CubicVoxelLayout>,
+  spawner_config: Res<ChunkSpawnerConfig>,
+  mut tracker: ResMut<ChunkTracker>,
+  mut worlds: ResMut<TerrainWorlds>,
+  anchors: Query<(&Transform, &StreamingAnchor, Option<&TerrainWorld>)>,
+) {
+  tracker.clear_required();
+  for (_, tracker) in worlds.trackers_mut() {
+    tracker.clear_required();
+  }
+
+  for (transform, anchor, world) in anchors.iter() {
+    let tracker = match worlds.tracker_mut(&world.copied().unwrap_or_default(), &mut tracker) {
+      Some(tracker) => tracker,
+      None => continue,
+    };
+    let center = layout.clamp_to_world(&layout.space_to_chunk(&transform.translation));
+    for column in layout.iter_chunks_spiral(&center, anchor.radius) {
+      for chunk in layout.get_column_sections(&column, spawner_config.vertical_radius) {
+        tracker.require(chunk, anchor.priority);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::layout::ChunkId;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn overlapping_anchors_should_keep_the_highest_priority(x in -20i64..20, z in -20i64..20, low in 0u8..100, high in 100u8..=255) {
+          let mut world = World::new();
+          let layout = CubicVoxelLayout::default();
+          world.insert_resource(ChunkSpawnerConfig { vertical_radius: 0, ..Default::default() });
+          world.insert_resource(ChunkTracker::default());
+          world.insert_resource(TerrainWorlds::default());
+
+          let center = layout.clamp_to_world(&ChunkId::new(x, 0, z));
+          let position = layout.chunk_to_space(&center);
+          world
+              .spawn()
+              .insert(Transform::from_translation(position))
+              .insert(StreamingAnchor { radius: 2, priority: low });
+          world
+              .spawn()
+              .insert(Transform::from_translation(position))
+              .insert(StreamingAnchor { radius: 0, priority: high });
+          world.insert_resource(layout.clone());
+
+          let mut stage = SystemStage::single_threaded().with_system(track_streaming_anchors);
+          stage.run(&mut world);
+
+          let tracker = world.get_resource::<ChunkTracker>().unwrap();
+          assert_eq!(tracker.unloaded_required().count(), 25);
+          assert_eq!(tracker.required_priority(&center), Some(high));
+          for chunk in layout.get_chunk_neighbors(&center, 2) {
+              assert_eq!(tracker.required_priority(&chunk), Some(low));
+          }
+          assert!(!tracker.is_required(&(center + ChunkId::new(3, 0, 0))));
+
+          // requirements follow the anchors
+          let anchors: Vec<_> = world.query_filtered::<Entity, With<StreamingAnchor>>().iter(&world).collect();
+          for anchor in anchors {
+              world.despawn(anchor);
+          }
+          stage.run(&mut world);
+          assert_eq!(world.get_resource::<ChunkTracker>().unwrap().unloaded_required().count(), 0);
+      }
+  }
+}
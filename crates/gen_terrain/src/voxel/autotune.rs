@@ -0,0 +1,124 @@
+use super::{lod::ChunkLodSettings, pipeline::ChunkPipelineBudget};
+use bevy::{
+  diagnostic::{Diagnostics, FrameTimeDiagnosticsPlugin},
+  prelude::*,
+};
+use std::ops::RangeInclusive;
+
+// scales the per frame streaming budgets with the frame time, insert it to let it take over
+// `ChunkPipelineBudget::spawns_per_frame`, `ChunkPipelineBudget::meshes_per_frame` and
+// `ChunkLodSettings::mesh_submissions_per_frame`
+// frame times come from `FrameTimeDiagnosticsPlugin`, budgets are left alone without it
+pub struct StreamingAutoTune {
+  // budgets shrink while frames take longer than this
+  pub target_frame_ms: f64,
+  // budgets only grow while frames take less than this share of the target, so they don't
+  // flip-flop around it
+  pub headroom: f64,
+  // share of each budget's range it shrinks and grows by per second
+  pub shrink_rate: f32,
+  pub grow_rate: f32,
+  pub spawns_per_frame: RangeInclusive<usize>,
+  pub meshes_per_frame: RangeInclusive<usize>,
+  pub mesh_submissions_per_frame: RangeInclusive<usize>,
+  // where the budgets are within their ranges, 0 at their minimums and 1 at their maximums
+  scale: f32,
+}
+impl Default for StreamingAutoTune {
+  fn default() -> Self {
+    Self {
+      target_frame_ms: 1000. / 60.,
+      headroom: 0.8,
+      shrink_rate: 2.0,
+      grow_rate: 0.25,
+      spawns_per_frame: 4..=64,
+      meshes_per_frame: 2..=16,
+      mesh_submissions_per_frame: 2..=32,
+      scale: 0.5,
+    }
+  }
+}
+
+impl StreamingAutoTune {
+  pub fn scale(&self) -> f32 {
+    self.scale
+  }
+
+  // moves the budgets toward what a frame that took `frame_ms` leaves room for, `seconds` since
+  // the last frame
+  pub fn tune(&mut self, frame_ms: f64, seconds: f32) {
+    if frame_ms > self.target_frame_ms {
+      self.scale -= self.shrink_rate * seconds;
+    } else if frame_ms < self.target_frame_ms * self.headroom {
+      self.scale += self.grow_rate * seconds;
+    }
+    self.scale = self.scale.clamp(0., 1.);
+  }
+
+  pub fn budget(&self, range: &RangeInclusive<usize>) -> usize {
+    let (min, max) = (*range.start(), *range.end().max(range.start()));
+    min + ((max - min) as f32 * self.scale).round() as usize
+  }
+}
+
+pub fn tune_streaming_budgets(
+  tune: Option<ResMut<StreamingAutoTune>>,
+  diagnostics: Option<Res<Diagnostics>>,
+  time: Res<Time>,
+  mut budget: ResMut<ChunkPipelineBudget>,
+  mut lod_settings: ResMut<ChunkLodSettings>,
+) {
+  let mut tune = match tune {
+    Some(tune) => tune,
+    None => return,
+  };
+  // the average smooths over single slow frames e.g. a chunk store flush
+  let frame_seconds = diagnostics
+    .as_ref()
+    .and_then(|diagnostics| diagnostics.get(FrameTimeDiagnosticsPlugin::FRAME_TIME))
+    .and_then(|frame_time| frame_time.average());
+  let frame_seconds = match frame_seconds {
+    Some(frame_seconds) => frame_seconds,
+    None => return,
+  };
+
+  tune.tune(frame_seconds * 1000., time.delta_seconds());
+  let spawns = tune.budget(&tune.spawns_per_frame);
+  let meshes = tune.budget(&tune.meshes_per_frame);
+  let submissions = tune.budget(&tune.mesh_submissions_per_frame);
+  // only touched when they change so the settings don't look changed every frame
+  if budget.spawns_per_frame != spawns || budget.meshes_per_frame != meshes {
+    budget.spawns_per_frame = spawns;
+    budget.meshes_per_frame = meshes;
+  }
+  if lod_settings.mesh_submissions_per_frame != submissions {
+    lod_settings.mesh_submissions_per_frame = submissions;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn budgets_should_shrink_on_slow_frames_and_grow_with_headroom(frames in prop::collection::vec((1f64..50., 0.001f32..0.1), 1..50)) {
+          let mut tune = StreamingAutoTune::default();
+          for (frame_ms, seconds) in frames {
+              let before = (tune.budget(&tune.spawns_per_frame), tune.budget(&tune.meshes_per_frame));
+              tune.tune(frame_ms, seconds);
+              let after = (tune.budget(&tune.spawns_per_frame), tune.budget(&tune.meshes_per_frame));
+
+              if frame_ms > tune.target_frame_ms {
+                  prop_assert!(after.0 <= before.0 && after.1 <= before.1);
+              } else {
+                  prop_assert!(after.0 >= before.0 && after.1 >= before.1);
+              }
+              prop_assert!(tune.spawns_per_frame.contains(&after.0));
+              prop_assert!(tune.meshes_per_frame.contains(&after.1));
+              prop_assert!(tune.mesh_submissions_per_frame.contains(&tune.budget(&tune.mesh_submissions_per_frame)));
+          }
+      }
+  }
+}
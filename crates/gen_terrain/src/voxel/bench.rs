@@ -0,0 +1,126 @@
+use super::{
+  generator::{GenerationContext, TerrainGenerator, VoxelGenerator, WorldGenConfig},
+  light::ChunkLight,
+  registry::{VoxelRegistry, VoxelTypeId},
+  ChunkId, VoxelId,
+};
+use bevy::{prelude::*, tasks::TaskPool};
+use std::collections::{HashMap, HashSet};
+
+// the benchmarks need the layout and the mesher without an app around them
+pub use super::{
+  layout::CubicVoxelLayout,
+  mesher::{mesh_chunk, patch_mesh, MeshFaceIndex},
+};
+
+// a chunk with everything the generator and the mesher need, built without an app so only the
+// measured work is timed
+// voxels are solid or air depending on their hash, at `density` most voxels get a face on every
+// side, which is a lot worse than generated terrain
+pub struct DenseChunk {
+  pub layout: CubicVoxelLayout,
+  pub registry: VoxelRegistry,
+  pub chunk: ChunkId,
+  pub voxels: HashMap<VoxelId, VoxelTypeId>,
+  pub light: ChunkLight,
+  // read from the mesh passed to the first `edit_and_remesh` like the first patch of a chunk does
+  pub index: Option<MeshFaceIndex>,
+}
+
+impl DenseChunk {
+  // `density` is the share of solid voxels in [0, 1]
+  pub fn new(chunk_voxel_length: i64, chunk_voxel_height: i64, density: f64) -> Self {
+    let layout = CubicVoxelLayout::new(
+      ChunkId::default(),
+      1.0,
+      chunk_voxel_length,
+      chunk_voxel_height,
+    );
+    let registry = VoxelRegistry::default();
+    let chunk = ChunkId::new(1, 0, -1);
+    let voxels: HashMap<_, _> = layout
+      .iter_chunk_voxels(&chunk)
+      .map(|voxel| {
+        let solid = (voxel.stable_hash() % 1000) as f64 / 1000. < density;
+        let voxel_type = match solid {
+          true => VoxelTypeId::DIRT,
+          false => VoxelTypeId::AIR,
+        };
+        (voxel, voxel_type)
+      })
+      .collect();
+    let light = ChunkLight::compute(&voxels, &registry);
+    Self {
+      layout,
+      registry,
+      chunk,
+      voxels,
+      light,
+      index: None,
+    }
+  }
+
+  // runs the default generator for the chunk like the pipeline would, on the calling thread
+  pub fn generate(
+    &self,
+    pool: &TaskPool,
+    config: &WorldGenConfig,
+  ) -> HashMap<VoxelId, VoxelTypeId> {
+    let context = GenerationContext::new(
+      self.chunk,
+      config.clone(),
+      self.registry.clone(),
+      pool.clone(),
+    )
+    .with_wrapping(self.layout.wrapping_voxels());
+    let buffer = self
+      .layout
+      .iter_chunk_voxels(&self.chunk)
+      .map(|voxel| (voxel, VoxelTypeId::AIR))
+      .collect();
+    (VoxelGenerator.load_voxel_data(context, buffer))().voxels
+  }
+
+  pub fn mesh(&self) -> Mesh {
+    mesh_chunk(
+      &self.layout,
+      &self.registry,
+      &self.chunk,
+      &self.voxels,
+      &self.light,
+    )
+  }
+
+  // what an edit costs before the new mesh can be shown: the voxel is flipped between solid
+  // and air, the light is updated around it and `mesh` is patched around the voxel
+  pub fn edit_and_remesh(&mut self, mesh: &mut Mesh, voxel: VoxelId) {
+    if let Some(voxel_type) = self.voxels.get_mut(&voxel) {
+      *voxel_type = if *voxel_type == VoxelTypeId::AIR {
+        VoxelTypeId::DIRT
+      } else {
+        VoxelTypeId::AIR
+      };
+    }
+    let changed = HashSet::from([voxel]);
+    self
+      .light
+      .relight(&self.voxels, &self.registry, &changed, |_| None);
+    let index = self.index.get_or_insert_with(|| {
+      MeshFaceIndex::of(mesh, &self.layout, &self.chunk).unwrap_or_default()
+    });
+    patch_mesh(
+      mesh,
+      index,
+      &self.layout,
+      &self.registry,
+      &self.chunk,
+      &self.voxels,
+      &self.light,
+      &changed,
+      None,
+      Default::default(),
+      Default::default(),
+    );
+    self.light.clear_relit();
+  }
+}
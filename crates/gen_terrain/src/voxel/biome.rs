@@ -0,0 +1,236 @@
+#[cfg(feature = "render")]
+use super::{
+  environment::SpawnerEnvironment, layout::CubicVoxelLayout, world::TerrainWorld, ChunkSpawner,
+};
+use super::{
+  generator::{sample_noise, WorldGenConfig},
+  ChunkId, VoxelId,
+};
+use bevy::prelude::*;
+use noise::{Fbm, MultiFractal, Seedable};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "render")]
+use std::collections::HashMap;
+
+// voxels per unit of climate noise, biomes are a lot larger than hills
+const CLIMATE_SCALE: f64 = 512.0;
+// keeps the climate noise independent of the height noise, which uses the seed directly
+const TEMPERATURE_SEED_TAG: u64 = 0x7e3a_9c51;
+const MOISTURE_SEED_TAG: u64 = 0x3f18_d06b;
+// climate units over which one biome fades into the next
+const BIOME_BLEND: f64 = 0.1;
+// biomes blended into a column's height, weaker ones are dropped
+const MAX_BLENDED_BIOMES: usize = 3;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Biome {
+  #[default]
+  Plains,
+  Forest,
+  Desert,
+  Tundra,
+}
+
+impl Biome {
+  pub const ALL: [Biome; 4] = [Biome::Plains, Biome::Forest, Biome::Desert, Biome::Tundra];
+
+  // `temperature` and `moisture` are in [-1, 1]
+  pub fn from_climate(temperature: f64, moisture: f64) -> Self {
+    match (temperature, moisture) {
+      (t, _) if t < -0.3 => Biome::Tundra,
+      (t, m) if t > 0.3 && m < 0. => Biome::Desert,
+      (_, m) if m > 0.2 => Biome::Forest,
+      _ => Biome::Plains,
+    }
+  }
+
+  // like `from_climate` but each biome fades into the next near the thresholds, strongest first
+  // the weights of the strongest few biomes sum up to 1
+  pub fn weights_from_climate(temperature: f64, moisture: f64) -> Vec<(Biome, f64)> {
+    // the same rules as `from_climate`, each takes its share of what's left by the ones before
+    let tundra = 1. - above(temperature, -0.3);
+    let desert = (1. - tundra) * above(temperature, 0.3) * (1. - above(moisture, 0.));
+    let forest = (1. - tundra - desert) * above(moisture, 0.2);
+    let plains = 1. - tundra - desert - forest;
+
+    let mut weights = vec![
+      (Biome::Plains, plains),
+      (Biome::Forest, forest),
+      (Biome::Desert, desert),
+      (Biome::Tundra, tundra),
+    ];
+    weights.retain(|(_, weight)| *weight > 0.);
+    weights.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    weights.truncate(MAX_BLENDED_BIOMES);
+    let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+    for (_, weight) in weights.iter_mut() {
+      *weight /= total;
+    }
+    weights
+  }
+
+  // voxels the surface is raised above the config's base height
+  pub fn height_offset(&self) -> f64 {
+    match self {
+      Biome::Plains => 0.,
+      Biome::Forest => 1.,
+      Biome::Desert => -1.,
+      Biome::Tundra => 2.,
+    }
+  }
+
+  // multiplies the config's amplitude, plains are flatter and tundra is hillier
+  pub fn roughness(&self) -> f64 {
+    match self {
+      Biome::Plains => 0.5,
+      Biome::Forest => 1.,
+      Biome::Desert => 0.75,
+      Biome::Tundra => 1.5,
+    }
+  }
+}
+
+// 0 below `threshold` and 1 above it, smoothly going from one to the other within the blend width
+fn above(value: f64, threshold: f64) -> f64 {
+  let t = ((value - threshold) / BIOME_BLEND + 0.5).clamp(0., 1.);
+  t * t * (3. - 2. * t)
+}
+
+// the climate noise of a world, build it once to sample many columns
+pub struct ClimateMap {
+  temperature: Fbm,
+  moisture: Fbm,
+  wrapping: Option<(i64, i64)>,
+}
+
+impl ClimateMap {
+  pub fn new(config: &WorldGenConfig) -> Self {
+    let noise = |tag: u64| {
+      Fbm::new()
+        .set_seed((config.seed ^ tag) as u32)
+        .set_octaves(2)
+    };
+    Self {
+      temperature: noise(TEMPERATURE_SEED_TAG),
+      moisture: noise(MOISTURE_SEED_TAG),
+      wrapping: config.wrapping,
+    }
+  }
+
+  // in [-1, 1] for the column at voxel (x, z)
+  pub fn temperature(&self, x: i64, z: i64) -> f64 {
+    sample_noise(
+      &self.temperature,
+      x as f64,
+      z as f64,
+      CLIMATE_SCALE,
+      self.wrapping,
+    )
+  }
+
+  // in [-1, 1] for the column at voxel (x, z)
+  pub fn moisture(&self, x: i64, z: i64) -> f64 {
+    sample_noise(
+      &self.moisture,
+      x as f64,
+      z as f64,
+      CLIMATE_SCALE,
+      self.wrapping,
+    )
+  }
+
+  pub fn biome(&self, x: i64, z: i64) -> Biome {
+    Biome::from_climate(self.temperature(x, z), self.moisture(x, z))
+  }
+
+  // see `Biome::weights_from_climate`
+  pub fn biome_weights(&self, x: i64, z: i64) -> Vec<(Biome, f64)> {
+    Biome::weights_from_climate(self.temperature(x, z), self.moisture(x, z))
+  }
+}
+
+// the biome of the column containing `voxel`
+pub fn biome_at(config: &WorldGenConfig, voxel: &VoxelId) -> Biome {
+  ClimateMap::new(config).biome(voxel.x(), voxel.z())
+}
+
+// the biome of a chunk, taken from its center column
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub struct ChunkBiome(pub Biome);
+
+// sent when a spawner moves onto a chunk of another biome or goes underground or back to the
+// surface, and once for every new spawner, e.g. to crossfade ambient sounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BiomeEntered {
+  pub spawner: Entity,
+  pub biome: Biome,
+  pub chunk: ChunkId,
+  // see `SpawnerEnvironment::underground`
+  pub underground: bool,
+}
+
+// only spawners of the primary world, see `TerrainWorld`
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity)]
+pub fn track_spawner_biomes(
+  config: Res<WorldGenConfig>,
+  layout: Res<CubicVoxelLayout>,
+  mut entered: EventWriter<BiomeEntered>,
+  mut last: Local<HashMap<Entity, (Biome, bool)>>,
+  spawners: Query<
+    (
+      Entity,
+      &Transform,
+      Option<&TerrainWorld>,
+      Option<&SpawnerEnvironment>,
+    ),
+    With<ChunkSpawner>,
+  >,
+) {
+  let mut current = HashMap::new();
+  for (spawner, transform, world, environment) in spawners.iter() {
+    if !world.copied().unwrap_or_default().is_primary() {
+      continue;
+    }
+    let position = transform.translation;
+    let chunk = layout.clamp_to_world(&layout.space_to_chunk(&position));
+    let biome = biome_at(&config, &layout.get_center_voxel(&chunk));
+    let underground = matches!(environment, Some(environment) if environment.underground);
+
+    if last.get(&spawner) != Some(&(biome, underground)) {
+      entered.send(BiomeEntered {
+        spawner,
+        biome,
+        chunk,
+        underground,
+      });
+    }
+    current.insert(spawner, (biome, underground));
+  }
+  // forgets despawned spawners
+  *last = current;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn biome_weights_should_agree_with_biomes(temperature in -1f64..1., moisture in -1f64..1.) {
+          let weights = Biome::weights_from_climate(temperature, moisture);
+          let total: f64 = weights.iter().map(|(_, weight)| weight).sum();
+          prop_assert!((total - 1.).abs() < 1e-9);
+          prop_assert!(weights.len() <= MAX_BLENDED_BIOMES);
+
+          // away from the thresholds only the biome itself is left
+          let near_threshold = [temperature + 0.3, temperature - 0.3, moisture, moisture - 0.2]
+              .iter()
+              .any(|distance| distance.abs() < BIOME_BLEND / 2.);
+          if !near_threshold {
+              prop_assert_eq!(weights, vec![(Biome::from_climate(temperature, moisture), 1.)]);
+          }
+      }
+  }
+}
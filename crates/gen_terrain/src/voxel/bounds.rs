@@ -0,0 +1,285 @@
+use super::{
+  decoration::TerrainDecorations, foliage::FoliageSettings, layout::CubicVoxelLayout,
+  registry::VoxelRegistry, world::TerrainWorld, wrap::chunk_placement, Chunk, ChunkId,
+  ChunkSpawner, ChunkVoxelData, VoxelId,
+};
+use bevy::{math::Vec3A, prelude::*, render::primitives::Sphere};
+
+// world space box around the solid voxels of a chunk, for culling, physics broadphases and spatial
+// queries that shouldn't assume every chunk is full to the top
+// chunks start out with the bounds of the whole chunk, they shrink to the solid voxels once the
+// voxels are generated and follow edits after that
+// chunks without solid voxels get a flat box at the bottom of the chunk
+// liquids count as solid here, they're drawn and have to be culled like the ground
+// the decorations and foliage on top of the ground are culled with their chunk, the bounds of
+// chunks with solid voxels are grown by as far as those reach
+#[derive(Debug, Clone, Copy, PartialEq, Component)]
+pub struct ChunkBounds {
+  pub min: Vec3,
+  pub max: Vec3,
+}
+
+impl ChunkBounds {
+  // every voxel the chunk can hold
+  pub fn full(layout: &CubicVoxelLayout, chunk: &ChunkId) -> Self {
+    let length = layout.chunk_voxel_length();
+    let min = layout.get_voxel(chunk, -length, 0, -length);
+    let max = layout.get_voxel(chunk, length + 1, layout.chunk_voxel_height(), length + 1);
+    Self {
+      min: layout.voxel_to_space(&min),
+      max: layout.voxel_to_space(&max),
+    }
+  }
+
+  // as wide as the whole chunk and as high as its solid voxels reach
+  pub fn from_voxels(
+    layout: &CubicVoxelLayout,
+    chunk: &ChunkId,
+    voxel_data: &ChunkVoxelData,
+    registry: &VoxelRegistry,
+  ) -> Self {
+    let full = Self::full(layout, chunk);
+    let heights = voxel_data
+      .voxels
+      .iter()
+      .filter(|(_, voxel_type)| registry.is_solid(**voxel_type))
+      .map(|(voxel, _)| voxel.y())
+      .fold(None, |range: Option<(i64, i64)>, y| match range {
+        Some((low, high)) => Some((low.min(y), high.max(y))),
+        None => Some((y, y)),
+      });
+    let height = |y: i64| layout.voxel_to_space(&VoxelId::new(0, y, 0)).y;
+    let (min_y, max_y) = match heights {
+      Some((low, high)) => (height(low), height(high + 1)),
+      None => (full.min.y, full.min.y),
+    };
+    Self {
+      min: Vec3::new(full.min.x, min_y, full.min.z),
+      max: Vec3::new(full.max.x, max_y, full.max.z),
+    }
+  }
+
+  // grown by what's placed on top of the ground, `clearance` is how far it reaches up from the top
+  // of the ground and sideways out of the chunk
+  // flat boxes of chunks without solid voxels have nothing on top of them and aren't grown
+  pub fn with_clearance(self, clearance: Vec3) -> Self {
+    if self.max.y <= self.min.y {
+      return self;
+    }
+    Self {
+      min: self.min - Vec3::new(clearance.x, 0., clearance.z),
+      max: self.max + clearance,
+    }
+  }
+
+  // the same box moved along with a chunk, e.g. to where `chunk_placement` put it
+  pub fn translated(self, offset: Vec3) -> Self {
+    Self {
+      min: self.min + offset,
+      max: self.max + offset,
+    }
+  }
+
+  pub fn center(&self) -> Vec3 {
+    (self.min + self.max) * 0.5
+  }
+
+  pub fn half_extents(&self) -> Vec3 {
+    (self.max - self.min) * 0.5
+  }
+
+  // points on the faces of the box are inside it
+  pub fn contains(&self, point: Vec3) -> bool {
+    point.cmpge(self.min).all() && point.cmple(self.max).all()
+  }
+
+  // boxes that only touch intersect
+  pub fn intersects(&self, other: &ChunkBounds) -> bool {
+    self.min.cmple(other.max).all() && other.min.cmple(self.max).all()
+  }
+
+  // a sphere enclosing the box, e.g. for `Frustum::intersects_sphere`
+  pub fn bounding_sphere(&self) -> Sphere {
+    Sphere {
+      center: Vec3A::from(self.center()),
+      radius: self.half_extents().length(),
+    }
+  }
+}
+
+// how far decorations and foliage reach from the top of the voxel they're placed on, the tallest
+// decoration of any biome counts for every chunk, meshes that aren't loaded yet are left out
+pub fn placed_clearance(
+  layout: &CubicVoxelLayout,
+  decorations: &TerrainDecorations,
+  foliage: Option<&FoliageSettings>,
+  meshes: &Assets<Mesh>,
+) -> Vec3 {
+  decorations
+    .biomes
+    .values()
+    .flatten()
+    .filter_map(|decoration| meshes.get(&decoration.mesh)?.compute_aabb())
+    .map(|aabb| {
+      let (center, half_extents) = (Vec3::from(aabb.center), Vec3::from(aabb.half_extents));
+      let reach = center.abs() + half_extents;
+      // decorations are turned around y
+      let sideways = Vec2::new(reach.x, reach.z).length();
+      Vec3::new(sideways, center.y + half_extents.y, sideways)
+    })
+    .fold(placed_foliage_clearance(layout, foliage), Vec3::max)
+}
+
+// blades sway sideways in the wind by up to `wind_strength` of their height
+fn placed_foliage_clearance(layout: &CubicVoxelLayout, foliage: Option<&FoliageSettings>) -> Vec3 {
+  foliage.map_or(Vec3::ZERO, |foliage| {
+    let height = foliage.max_height.max(foliage.min_height) * layout.voxel_side_length();
+    let sway = foliage.wind_strength.abs() * height;
+    Vec3::new(sway, height, sway)
+  })
+}
+
+// shrinks the bounds of chunks whose voxels arrived or changed to their solid voxels and what's
+// placed on them, every chunk's bounds are updated when decorations or foliage change
+// in a wrapping world they're around the image of the chunk `chunk_placement` put next to the
+// spawners, like its mesh
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn update_chunk_bounds(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  decorations: Res<TerrainDecorations>,
+  foliage: Option<Res<FoliageSettings>>,
+  meshes: Res<Assets<Mesh>>,
+  mut mesh_events: EventReader<AssetEvent<Mesh>>,
+  mut clearance: Local<Vec3>,
+  spawners: Query<(&Transform, Option<&TerrainWorld>), (With<ChunkSpawner>, Without<Chunk>)>,
+  query: Query<(
+    Entity,
+    &Chunk,
+    &ChunkVoxelData,
+    ChangeTrackers<ChunkVoxelData>,
+    Option<&TerrainWorld>,
+    Option<&ChunkBounds>,
+  )>,
+) {
+  let is_decoration = |handle: &Handle<Mesh>| {
+    decorations
+      .biomes
+      .values()
+      .flatten()
+      .any(|decoration| decoration.mesh == *handle)
+  };
+  let meshes_changed = mesh_events.iter().any(|event| match event {
+    AssetEvent::Created { handle } | AssetEvent::Modified { handle } => is_decoration(handle),
+    AssetEvent::Removed { .. } => false,
+  });
+  let mut grown = false;
+  if decorations.is_changed()
+    || foliage.as_ref().map_or(false, |f| f.is_changed())
+    || meshes_changed
+  {
+    let updated = placed_clearance(&layout, &decorations, foliage.as_deref(), &meshes);
+    grown = updated != *clearance;
+    *clearance = updated;
+  }
+
+  for (entity, chunk, voxel_data, voxels_changed, world, bounds) in query.iter() {
+    if !grown && !voxels_changed.is_changed() {
+      continue;
+    }
+    let world = world.copied().unwrap_or_default();
+    let spawners = spawners
+      .iter()
+      .filter(|(_, spawner_world)| spawner_world.copied().unwrap_or_default() == world)
+      .map(|(spawner, _)| spawner.translation);
+    let offset = chunk_placement(&layout, &chunk.id, spawners) - layout.chunk_to_space(&chunk.id);
+    let updated = ChunkBounds::from_voxels(&layout, &chunk.id, voxel_data, &registry)
+      .with_clearance(*clearance)
+      .translated(offset);
+    if bounds != Some(&updated) {
+      commands.entity(entity).insert(updated);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{foliage::scatter_foliage, registry::VoxelTypeId, seed::ChunkRng};
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn bounds_should_enclose_the_solid_voxels(x in -100i64..100, y in 0i64..4, z in -100i64..100, solid in prop::collection::vec(any::<bool>(), 100)) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 0.5, 2, 4)
+              .with_vertical_sections(4);
+          let chunk = ChunkId::new(x, y, z);
+          let registry = VoxelRegistry::default();
+          let voxel_data = ChunkVoxelData {
+              voxels: layout
+                  .get_chunk_voxels(&chunk)
+                  .into_iter()
+                  .zip(solid.iter())
+                  .map(|(voxel, solid)| (voxel, if *solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+                  .collect(),
+          };
+          let full = ChunkBounds::full(&layout, &chunk);
+          let bounds = ChunkBounds::from_voxels(&layout, &chunk, &voxel_data, &registry);
+          prop_assert!(full.contains(bounds.min) && full.contains(bounds.max));
+
+          for (voxel, voxel_type) in voxel_data.voxels.iter() {
+              let (min, max) = (layout.voxel_to_space(voxel), layout.voxel_to_space(voxel) + Vec3::splat(0.5));
+              prop_assert!(full.contains(min) && full.contains(max));
+              if *voxel_type == VoxelTypeId::DIRT {
+                  prop_assert!(bounds.contains(min) && bounds.contains(max));
+              }
+          }
+          // the box is as low as the lowest and as high as the highest solid voxel
+          if solid.iter().any(|solid| *solid) {
+              let heights = voxel_data.voxels.iter().filter(|(_, voxel_type)| **voxel_type == VoxelTypeId::DIRT).map(|(voxel, _)| voxel.y());
+              let (low, high) = (heights.clone().min().unwrap(), heights.max().unwrap());
+              prop_assert_eq!(bounds.min.y, layout.voxel_to_space(&VoxelId::new(0, low, 0)).y);
+              prop_assert_eq!(bounds.max.y, layout.voxel_to_space(&VoxelId::new(0, high + 1, 0)).y);
+          } else {
+              prop_assert_eq!(bounds.min.y, bounds.max.y);
+          }
+      }
+
+      #[test]
+      fn grown_bounds_should_enclose_the_foliage(x in -100i64..100, z in -100i64..100, seed in any::<u64>(), heights in prop::collection::vec(0i64..4, 25)) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 0.5, 2, 4);
+          let chunk = ChunkId::new(x, 0, z);
+          let registry = VoxelRegistry::default();
+          let center = layout.get_center_voxel(&chunk);
+          let voxel_data = ChunkVoxelData {
+              voxels: layout
+                  .iter_chunk_voxels(&chunk)
+                  .map(|voxel| {
+                      let column = (voxel.x() - center.x() + 2) * 5 + voxel.z() - center.z() + 2;
+                      (voxel, if voxel.y() < heights[column as usize] { VoxelTypeId::DIRT } else { VoxelTypeId::AIR })
+                  })
+                  .collect(),
+          };
+          let settings = FoliageSettings::default();
+          let clearance = placed_foliage_clearance(&layout, Some(&settings));
+          let bounds = ChunkBounds::from_voxels(&layout, &chunk, &voxel_data, &registry).with_clearance(clearance);
+          // blades are placed relative to the chunk, give or take rounding
+          let loose = ChunkBounds { min: bounds.min - Vec3::splat(1e-4), max: bounds.max + Vec3::splat(1e-4) };
+          let origin = layout.chunk_to_space(&chunk);
+          let blades = scatter_foliage(&layout, &registry, &chunk, &voxel_data.voxels, &settings, 1., &mut ChunkRng::new(seed, &chunk, "foliage"));
+          prop_assert_eq!(blades.is_empty(), heights.iter().all(|height| *height == 0));
+          for blade in blades {
+              let [bx, by, bz, height] = blade.position_height;
+              let base = origin + Vec3::new(bx, by, bz);
+              let sway = settings.wind_strength * height;
+              prop_assert!(loose.contains(base));
+              prop_assert!(loose.contains(base + Vec3::new(sway, height, -sway)));
+          }
+
+          // chunks without solid voxels aren't grown
+          let empty = ChunkBounds::from_voxels(&layout, &chunk, &ChunkVoxelData::default(), &registry);
+          prop_assert_eq!(empty.with_clearance(clearance), empty);
+      }
+  }
+}
@@ -0,0 +1,270 @@
+use super::{
+  generator::WorldGenConfig,
+  layout::CubicVoxelLayout,
+  lod::ChunkLodSettings,
+  mesher::{GenerateTangents, NormalMode},
+  pipeline::{ChunkPipelineBudget, ChunkTaskLimits, GenerationMode},
+  prediction::ChunkSpawnerConfig,
+  retention::ChunkRetentionPolicy,
+  tile::TileTerrainPlugin,
+  VoxelTerrainPlugin,
+};
+use bevy::prelude::*;
+
+// configures the terrain plugin in one place, see `VoxelTerrainPlugin::builder`
+// whatever isn't set is left to the resources inserted before the plugin is added, or to their
+// defaults
+#[derive(Debug, Default, Clone)]
+pub struct VoxelTerrainPluginBuilder {
+  voxel_size: Option<f32>,
+  // voxels from the center of a chunk to its sides, and voxels in a section
+  chunk_size: Option<(i64, i64)>,
+  vertical_sections: Option<i64>,
+  // chunk columns around a wrapping world along x and z
+  wrapping: Option<(i64, i64)>,
+  seed: Option<u64>,
+  spawn_radius: Option<i64>,
+  zoom_spawn_radius: Option<i64>,
+  despawn_ring_margin: Option<i64>,
+  normal_mode: Option<NormalMode>,
+  tangents: Option<bool>,
+  generation_mode: Option<GenerationMode>,
+  budget: Option<ChunkPipelineBudget>,
+  task_limits: Option<ChunkTaskLimits>,
+}
+
+impl VoxelTerrainPluginBuilder {
+  // side length of a voxel in world units
+  pub fn voxel_size(mut self, voxel_size: f32) -> Self {
+    self.voxel_size = Some(voxel_size);
+    self
+  }
+
+  // chunks are `1 + 2 * voxel_length` voxels across and `voxel_height` voxels high
+  pub fn chunk_size(mut self, voxel_length: i64, voxel_height: i64) -> Self {
+    self.chunk_size = Some((voxel_length, voxel_height));
+    self
+  }
+
+  // chunks stacked in each column
+  pub fn vertical_sections(mut self, vertical_sections: i64) -> Self {
+    self.vertical_sections = Some(vertical_sections);
+    self
+  }
+
+  // see `CubicVoxelLayout::with_wrapping`
+  pub fn wrapping(mut self, columns_x: i64, columns_z: i64) -> Self {
+    self.wrapping = Some((columns_x, columns_z));
+    self
+  }
+
+  pub fn seed(mut self, seed: u64) -> Self {
+    self.seed = Some(seed);
+    self
+  }
+
+  // rings of chunk columns loaded around a spawner, see `ChunkSpawnerConfig::spawn_radius`
+  pub fn spawn_radius(mut self, rings: i64) -> Self {
+    self.spawn_radius = Some(rings);
+    self
+  }
+
+  // extra rings of data-only chunks around a fully zoomed out spawner
+  pub fn zoom_spawn_radius(mut self, rings: i64) -> Self {
+    self.zoom_spawn_radius = Some(rings);
+    self
+  }
+
+  // rings beyond the spawn radius chunks are kept loaded in, see `ChunkRetentionPolicy`
+  pub fn despawn_ring_margin(mut self, rings: i64) -> Self {
+    self.despawn_ring_margin = Some(rings);
+    self
+  }
+
+  pub fn normal_mode(mut self, normal_mode: NormalMode) -> Self {
+    self.normal_mode = Some(normal_mode);
+    self
+  }
+
+  pub fn tangents(mut self, tangents: bool) -> Self {
+    self.tangents = Some(tangents);
+    self
+  }
+
+  pub fn generation_mode(mut self, generation_mode: GenerationMode) -> Self {
+    self.generation_mode = Some(generation_mode);
+    self
+  }
+
+  pub fn budget(mut self, budget: ChunkPipelineBudget) -> Self {
+    self.budget = Some(budget);
+    self
+  }
+
+  // generation and meshing jobs running on the async compute pool at once
+  pub fn task_limits(
+    mut self,
+    max_concurrent_generation: usize,
+    max_concurrent_meshing: usize,
+  ) -> Self {
+    self.task_limits = Some(ChunkTaskLimits {
+      max_concurrent_generation,
+      max_concurrent_meshing,
+    });
+    self
+  }
+
+  // panics if the chunk layout set up here isn't valid, see `CubicVoxelLayout::validate`
+  pub fn build(self) -> ConfiguredVoxelTerrainPlugin {
+    ConfiguredVoxelTerrainPlugin { settings: self }
+  }
+
+  pub fn build_tiles(self) -> TileTerrainPlugin {
+    TileTerrainPlugin { settings: self }
+  }
+
+  // the same settings for chunks one voxel high in a single section, see `TileTerrainPlugin`
+  pub(super) fn flattened(&self, layout: &CubicVoxelLayout) -> Self {
+    let voxel_length = self
+      .chunk_size
+      .map_or(layout.chunk_voxel_length(), |(voxel_length, _)| {
+        voxel_length
+      });
+    Self {
+      chunk_size: Some((voxel_length, 1)),
+      vertical_sections: Some(1),
+      ..self.clone()
+    }
+  }
+
+  // inserts the resources for whatever was set, on top of the ones already in the app
+  pub(super) fn insert_resources(&self, app: &mut App) {
+    if self.voxel_size.is_some()
+      || self.chunk_size.is_some()
+      || self.vertical_sections.is_some()
+      || self.wrapping.is_some()
+    {
+      let layout = app
+        .world
+        .get_resource::<CubicVoxelLayout>()
+        .cloned()
+        .unwrap_or_default();
+      let (voxel_length, voxel_height) = self
+        .chunk_size
+        .unwrap_or((layout.chunk_voxel_length(), layout.chunk_voxel_height()));
+      let mut updated = CubicVoxelLayout::new(
+        layout.origin,
+        self.voxel_size.unwrap_or(layout.voxel_side_length()),
+        voxel_length,
+        voxel_height,
+      )
+      .with_vertical_sections(self.vertical_sections.unwrap_or(layout.vertical_sections()));
+      if let Some((columns_x, columns_z)) = self.wrapping.or_else(|| layout.wrapping()) {
+        updated = updated.with_wrapping(columns_x, columns_z);
+      }
+      if let Err(err) = updated.validate() {
+        panic!("invalid terrain plugin settings: {}", err);
+      }
+      app.insert_resource(updated);
+    }
+
+    app
+      .init_resource::<WorldGenConfig>()
+      .init_resource::<ChunkLodSettings>()
+      .init_resource::<ChunkSpawnerConfig>()
+      .init_resource::<ChunkRetentionPolicy>()
+      .init_resource::<NormalMode>()
+      .init_resource::<GenerateTangents>()
+      .init_resource::<GenerationMode>()
+      .init_resource::<ChunkPipelineBudget>()
+      .init_resource::<ChunkTaskLimits>();
+    let world = &mut app.world;
+    if let Some(seed) = self.seed {
+      world.resource_mut::<WorldGenConfig>().seed = seed;
+    }
+    if let Some(rings) = self.spawn_radius {
+      world.resource_mut::<ChunkSpawnerConfig>().spawn_radius = rings;
+    }
+    if let Some(rings) = self.zoom_spawn_radius {
+      world.resource_mut::<ChunkLodSettings>().zoom_spawn_radius = rings;
+    }
+    if let Some(rings) = self.despawn_ring_margin {
+      world
+        .resource_mut::<ChunkRetentionPolicy>()
+        .despawn_ring_margin = rings;
+    }
+    if let Some(normal_mode) = self.normal_mode {
+      *world.resource_mut::<NormalMode>() = normal_mode;
+    }
+    if let Some(tangents) = self.tangents {
+      *world.resource_mut::<GenerateTangents>() = GenerateTangents(tangents);
+    }
+    if let Some(generation_mode) = self.generation_mode {
+      *world.resource_mut::<GenerationMode>() = generation_mode;
+    }
+    if let Some(budget) = self.budget {
+      *world.resource_mut::<ChunkPipelineBudget>() = budget;
+    }
+    if let Some(task_limits) = self.task_limits {
+      *world.resource_mut::<ChunkTaskLimits>() = task_limits;
+    }
+  }
+}
+
+// the terrain plugin with the settings of a `VoxelTerrainPluginBuilder`, they're inserted before
+// the terrain plugin is added
+pub struct ConfiguredVoxelTerrainPlugin {
+  pub(super) settings: VoxelTerrainPluginBuilder,
+}
+
+impl Plugin for ConfiguredVoxelTerrainPlugin {
+  fn build(&self, app: &mut App) {
+    self.settings.insert_resources(app);
+    app.add_plugin(VoxelTerrainPlugin);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn builders_should_only_replace_what_they_set(seed in any::<u64>(), length in 1i64..16, sections in 1i64..8, radius in 0i64..8) {
+          let mut app = App::new();
+          app.insert_resource(CubicVoxelLayout::default().with_vertical_sections(sections));
+          app.insert_resource(ChunkRetentionPolicy { min_resident_seconds: 0., ..Default::default() });
+          VoxelTerrainPlugin::builder()
+              .seed(seed)
+              .chunk_size(length, 4)
+              .spawn_radius(radius)
+              .tangents(true)
+              .build()
+              .settings
+              .insert_resources(&mut app);
+
+          let layout = app.world.resource::<CubicVoxelLayout>();
+          prop_assert_eq!(layout.chunk_voxel_length(), length);
+          prop_assert_eq!(layout.chunk_voxel_height(), 4);
+          prop_assert_eq!(layout.vertical_sections(), sections);
+          prop_assert_eq!(app.world.resource::<WorldGenConfig>().seed, seed);
+          prop_assert_eq!(app.world.resource::<ChunkSpawnerConfig>().spawn_radius, radius);
+          prop_assert_eq!(app.world.resource::<ChunkLodSettings>().mesh_radius, ChunkLodSettings::default().mesh_radius);
+          prop_assert_eq!(app.world.resource::<ChunkRetentionPolicy>().min_resident_seconds, 0.);
+          prop_assert_eq!(*app.world.resource::<GenerateTangents>(), GenerateTangents(true));
+          prop_assert_eq!(*app.world.resource::<NormalMode>(), NormalMode::default());
+      }
+  }
+
+  #[test]
+  #[should_panic(expected = "invalid terrain plugin settings")]
+  fn builders_should_reject_chunks_without_voxels() {
+    let mut app = App::new();
+    VoxelTerrainPlugin::builder()
+      .chunk_size(0, 4)
+      .build()
+      .settings
+      .insert_resources(&mut app);
+  }
+}
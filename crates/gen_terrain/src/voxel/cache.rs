@@ -0,0 +1,330 @@
+use super::{layout::CubicVoxelLayout, registry::VoxelTypeId, ChunkId, ChunkVoxelData};
+use bevy::prelude::*;
+use std::{
+  collections::{HashMap, VecDeque},
+  mem,
+};
+
+// how long despawned chunk meshes are kept around for fast respawns
+pub struct MeshCachePolicy {
+  // seconds a cached mesh is kept before it becomes eligible for collection
+  pub max_age: f64,
+  // estimated size of all cached meshes before the oldest ones are collected early
+  pub max_bytes: usize,
+  // maximum number of cached meshes freed in a single frame
+  pub evictions_per_frame: usize,
+}
+impl Default for MeshCachePolicy {
+  fn default() -> Self {
+    Self {
+      max_age: 30.0,
+      max_bytes: 64 * 1024 * 1024,
+      evictions_per_frame: 4,
+    }
+  }
+}
+
+struct CachedMesh {
+  handle: Handle<Mesh>,
+  cached_at: f64,
+  bytes: usize,
+}
+
+#[derive(Default)]
+pub struct ChunkMeshCache {
+  entries: HashMap<ChunkId, CachedMesh>,
+  // insertion order doubles as age order, entries that were taken or replaced are
+  // skipped when they come up for eviction
+  order: VecDeque<(ChunkId, f64)>,
+  total_bytes: usize,
+}
+
+impl ChunkMeshCache {
+  pub fn insert(&mut self, chunk: ChunkId, handle: Handle<Mesh>, bytes: usize, now: f64) {
+    if let Some(replaced) = self.entries.insert(
+      chunk,
+      CachedMesh {
+        handle,
+        cached_at: now,
+        bytes,
+      },
+    ) {
+      self.total_bytes -= replaced.bytes;
+    }
+    self.total_bytes += bytes;
+    self.order.push_back((chunk, now));
+  }
+
+  pub fn take(&mut self, chunk: &ChunkId) -> Option<Handle<Mesh>> {
+    let entry = self.entries.remove(chunk)?;
+    self.total_bytes -= entry.bytes;
+    Some(entry.handle)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn total_bytes(&self) -> usize {
+    self.total_bytes
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+    self.order.clear();
+    self.total_bytes = 0;
+  }
+
+  // frees the oldest mesh if it is past its age or the cache is over its memory cap
+  fn evict_oldest(&mut self, policy: &MeshCachePolicy, now: f64) -> bool {
+    while let Some((chunk, cached_at)) = self.order.front().copied() {
+      let live = matches!(self.entries.get(&chunk), Some(entry) if entry.cached_at == cached_at);
+      if !live {
+        self.order.pop_front();
+        continue;
+      }
+
+      if now - cached_at < policy.max_age && self.total_bytes <= policy.max_bytes {
+        return false;
+      }
+
+      self.order.pop_front();
+      // dropping the last strong handle frees the mesh asset
+      self.take(&chunk);
+      return true;
+    }
+    false
+  }
+}
+
+pub fn estimate_mesh_bytes(mesh: &Mesh) -> usize {
+  let stride = mesh.get_mesh_vertex_buffer_layout().layout().array_stride as usize;
+  let indices = mesh.get_index_buffer_bytes().map_or(0, |bytes| bytes.len());
+  mesh.count_vertices() * stride + indices
+}
+
+pub fn collect_stale_meshes(
+  time: Res<Time>,
+  policy: Res<MeshCachePolicy>,
+  mut cache: ResMut<ChunkMeshCache>,
+) {
+  let now = time.seconds_since_startup();
+  for _ in 0..policy.evictions_per_frame {
+    if !cache.evict_oldest(&policy, now) {
+      break;
+    }
+  }
+}
+
+// how many despawned chunks keep their voxels in memory, so coming back to an area doesn't
+// generate it again
+pub struct VoxelCachePolicy {
+  // the least recently despawned chunks are evicted beyond this, 0 turns the cache off
+  pub max_chunks: usize,
+}
+impl Default for VoxelCachePolicy {
+  fn default() -> Self {
+    Self { max_chunks: 256 }
+  }
+}
+
+// the voxels of a chunk run length encoded in layout order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedVoxels {
+  runs: Vec<(VoxelTypeId, u16)>,
+}
+
+impl CompressedVoxels {
+  // voxels missing from `voxel_data` come back as air
+  pub fn compress(layout: &CubicVoxelLayout, chunk: &ChunkId, voxel_data: &ChunkVoxelData) -> Self {
+    let mut runs: Vec<(VoxelTypeId, u16)> = Vec::new();
+    for voxel in layout.iter_chunk_voxels(chunk) {
+      let voxel_type = voxel_data
+        .voxels
+        .get(&voxel)
+        .copied()
+        .unwrap_or(VoxelTypeId::AIR);
+      match runs.last_mut() {
+        Some((run_type, length)) if *run_type == voxel_type && *length < u16::MAX => *length += 1,
+        _ => runs.push((voxel_type, 1)),
+      }
+    }
+    Self { runs }
+  }
+
+  pub fn decompress(&self, layout: &CubicVoxelLayout, chunk: &ChunkId) -> ChunkVoxelData {
+    let types = self
+      .runs
+      .iter()
+      .flat_map(|(voxel_type, length)| std::iter::repeat(*voxel_type).take(*length as usize));
+    ChunkVoxelData {
+      voxels: layout.iter_chunk_voxels(chunk).zip(types).collect(),
+    }
+  }
+
+  pub fn bytes(&self) -> usize {
+    self.runs.len() * mem::size_of::<(VoxelTypeId, u16)>()
+  }
+
+  // voxels of all runs, the voxel count of the layout the chunk was compressed with
+  pub fn voxel_count(&self) -> usize {
+    self.runs.iter().map(|(_, length)| *length as usize).sum()
+  }
+
+  pub(super) fn runs(&self) -> &[(VoxelTypeId, u16)] {
+    &self.runs
+  }
+
+  pub(super) fn from_runs(runs: Vec<(VoxelTypeId, u16)>) -> Self {
+    Self { runs }
+  }
+}
+
+// voxels of recently despawned chunks of the primary world, filled as chunks are despawned and
+// taken when they're spawned again
+// cleared with `RegenerateTerrain` since the cached voxels are of the old generator
+#[derive(Default)]
+pub struct ChunkVoxelCache {
+  entries: HashMap<ChunkId, CompressedVoxels>,
+  // least recently despawned first
+  order: VecDeque<ChunkId>,
+  total_bytes: usize,
+}
+
+impl ChunkVoxelCache {
+  pub fn insert(&mut self, chunk: ChunkId, voxels: CompressedVoxels, policy: &VoxelCachePolicy) {
+    if policy.max_chunks == 0 {
+      return;
+    }
+    self.total_bytes += voxels.bytes();
+    if let Some(replaced) = self.entries.insert(chunk, voxels) {
+      self.total_bytes -= replaced.bytes();
+      self.order.retain(|cached| *cached != chunk);
+    }
+    self.order.push_back(chunk);
+
+    while self.entries.len() > policy.max_chunks {
+      let evicted = match self.order.pop_front() {
+        Some(evicted) => evicted,
+        None => break,
+      };
+      if let Some(entry) = self.entries.remove(&evicted) {
+        self.total_bytes -= entry.bytes();
+      }
+    }
+  }
+
+  pub fn take(&mut self, chunk: &ChunkId) -> Option<CompressedVoxels> {
+    let entry = self.entries.remove(chunk)?;
+    self.total_bytes -= entry.bytes();
+    self.order.retain(|cached| cached != chunk);
+    Some(entry)
+  }
+
+  pub fn contains(&self, chunk: &ChunkId) -> bool {
+    self.entries.contains_key(chunk)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  pub fn total_bytes(&self) -> usize {
+    self.total_bytes
+  }
+
+  pub fn clear(&mut self) {
+    self.entries.clear();
+    self.order.clear();
+    self.total_bytes = 0;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn voxels_should_survive_compression(x in -100i64..100, z in -100i64..100, solid in prop::collection::vec(any::<bool>(), 36)) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4);
+          let chunk = ChunkId::new(x, 0, z);
+          let voxel_data = ChunkVoxelData {
+              voxels: layout
+                  .iter_chunk_voxels(&chunk)
+                  .zip(solid.iter())
+                  .map(|(voxel, solid)| (voxel, if *solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+                  .collect(),
+          };
+          let compressed = CompressedVoxels::compress(&layout, &chunk, &voxel_data);
+          prop_assert_eq!(compressed.decompress(&layout, &chunk).voxels, voxel_data.voxels);
+          // a chunk of one voxel type is a single run
+          let uniform = CompressedVoxels::compress(&layout, &chunk, &ChunkVoxelData::default());
+          prop_assert_eq!(uniform.bytes(), mem::size_of::<(VoxelTypeId, u16)>());
+      }
+
+      #[test]
+      fn cache_should_evict_the_least_recently_despawned(max_chunks in 1usize..8, inserted in 0i64..16) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4);
+          let policy = VoxelCachePolicy { max_chunks };
+          let mut cache = ChunkVoxelCache::default();
+          for x in 0..inserted {
+              let chunk = ChunkId::new(x, 0, 0);
+              cache.insert(chunk, CompressedVoxels::compress(&layout, &chunk, &ChunkVoxelData::default()), &policy);
+          }
+          prop_assert_eq!(cache.len(), (inserted as usize).min(max_chunks));
+          for x in 0..inserted {
+              let kept = x >= inserted - max_chunks as i64;
+              prop_assert_eq!(cache.contains(&ChunkId::new(x, 0, 0)), kept);
+          }
+          for x in 0..inserted {
+              cache.take(&ChunkId::new(x, 0, 0));
+          }
+          prop_assert!(cache.is_empty());
+          prop_assert_eq!(cache.total_bytes(), 0);
+      }
+
+      #[test]
+      fn mesh_cache_should_free_the_oldest_meshes_down_to_its_budget(sizes in prop::collection::vec(1usize..1000, 0..24), max_bytes in 0usize..8000, max_age in 1f64..32., evictions_per_frame in 1usize..4) {
+          let policy = MeshCachePolicy { max_age, max_bytes, evictions_per_frame };
+          let mut cache = ChunkMeshCache::default();
+          for (x, bytes) in sizes.iter().enumerate() {
+              cache.insert(ChunkId::new(x as i64, 0, 0), Handle::default(), *bytes, x as f64);
+          }
+          // frames of `collect_stale_meshes` until one runs out of meshes to free
+          let now = sizes.len() as f64;
+          loop {
+              let freed = (0..policy.evictions_per_frame)
+                  .take_while(|_| cache.evict_oldest(&policy, now))
+                  .count();
+              if freed < policy.evictions_per_frame {
+                  break;
+              }
+          }
+          prop_assert!(cache.total_bytes() <= max_bytes);
+
+          // the kept meshes are the newest ones
+          let kept: Vec<bool> = (0..sizes.len())
+              .map(|x| cache.entries.contains_key(&ChunkId::new(x as i64, 0, 0)))
+              .collect();
+          let oldest_kept = kept.iter().position(|kept| *kept).unwrap_or(sizes.len());
+          prop_assert!(kept[oldest_kept..].iter().all(|kept| *kept));
+          prop_assert_eq!(cache.total_bytes(), sizes[oldest_kept..].iter().sum::<usize>());
+          // and the newest freed one had to go
+          if oldest_kept > 0 {
+              let newest_freed = oldest_kept - 1;
+              let too_old = now - newest_freed as f64 >= max_age;
+              prop_assert!(too_old || cache.total_bytes() + sizes[newest_freed] > max_bytes);
+          }
+      }
+  }
+}
@@ -0,0 +1,74 @@
+use super::query::{TerrainHit, TerrainQuery};
+use bevy::{prelude::*, render::camera::RenderTarget};
+
+// casts a ray from the cursor through this camera into the terrain every frame, the result is
+// kept in `CursorTerrainHit`
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ScreenToTerrain {
+  // world units, terrain farther away than this isn't hit
+  pub max_distance: f32,
+}
+impl Default for ScreenToTerrain {
+  fn default() -> Self {
+    Self {
+      max_distance: 1000.0,
+    }
+  }
+}
+
+// the terrain under the cursor, e.g. for selection boxes or building placement
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CursorTerrainHit {
+  // None while the cursor is outside the window or over the sky or terrain that isn't loaded
+  pub hit: Option<TerrainHit>,
+  // the ray the hit was found with
+  pub ray: Option<(Vec3, Vec3)>,
+}
+
+// origin and direction of the ray through a point of the window, `cursor` is in logical pixels
+// from the bottom left like `Window::cursor_position`
+pub fn screen_to_ray(
+  camera: &Camera,
+  transform: &GlobalTransform,
+  window: &Window,
+  cursor: Vec2,
+) -> Option<(Vec3, Vec3)> {
+  let size = Vec2::new(window.width(), window.height());
+  if size.min_element() <= 0. {
+    return None;
+  }
+  let ndc = cursor / size * 2. - Vec2::ONE;
+  // depth is reversed, 1 is the near plane and the far plane is at 0 for perspective projections
+  let ndc_to_world = transform.compute_matrix() * camera.projection_matrix.inverse();
+  let near = ndc_to_world.project_point3(ndc.extend(1.));
+  let far = ndc_to_world.project_point3(ndc.extend(0.5));
+  let direction = (far - near).normalize_or_zero();
+  (direction != Vec3::ZERO).then(|| (near, direction))
+}
+
+pub fn update_cursor_terrain_hit(
+  windows: Res<Windows>,
+  terrain: TerrainQuery,
+  mut cursor_hit: ResMut<CursorTerrainHit>,
+  cameras: Query<(&Camera, &GlobalTransform, &ScreenToTerrain)>,
+) {
+  // the first camera rendering to the window the cursor is in
+  let found = cameras
+    .iter()
+    .find_map(|(camera, transform, screen_to_terrain)| {
+      let window = match &camera.target {
+        RenderTarget::Window(id) => windows.get(*id)?,
+        RenderTarget::Image(_) => return None,
+      };
+      let ray = screen_to_ray(camera, transform, window, window.cursor_position()?)?;
+      Some((ray, screen_to_terrain.max_distance))
+    });
+
+  *cursor_hit = match found {
+    Some(((origin, direction), max_distance)) => CursorTerrainHit {
+      hit: terrain.raycast(origin, direction, max_distance),
+      ray: Some((origin, direction)),
+    },
+    None => CursorTerrainHit::default(),
+  };
+}
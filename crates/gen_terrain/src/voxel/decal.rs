@@ -0,0 +1,269 @@
+use super::{
+  edit::{voxels_in_sphere, TerrainEdits},
+  layout::CubicVoxelLayout,
+  meta::VoxelMeta,
+  registry::{VoxelRegistry, VoxelTypeId},
+  snapshot::{Reader, SnapshotError},
+  store::ChunkStore,
+  tracker::ChunkTracker,
+  ChunkVoxelData, DirtyChunk, VoxelId,
+};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+// one of the six faces of a voxel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoxelFace {
+  PositiveX,
+  NegativeX,
+  Top,
+  Bottom,
+  PositiveZ,
+  NegativeZ,
+}
+
+impl VoxelFace {
+  pub const ALL: [VoxelFace; 6] = [
+    VoxelFace::PositiveX,
+    VoxelFace::NegativeX,
+    VoxelFace::Top,
+    VoxelFace::Bottom,
+    VoxelFace::PositiveZ,
+    VoxelFace::NegativeZ,
+  ];
+
+  // from a voxel to the neighbor this face looks at
+  pub fn offset(&self) -> VoxelId {
+    match self {
+      VoxelFace::PositiveX => VoxelId::new(1, 0, 0),
+      VoxelFace::NegativeX => VoxelId::new(-1, 0, 0),
+      VoxelFace::Top => VoxelId::new(0, 1, 0),
+      VoxelFace::Bottom => VoxelId::new(0, -1, 0),
+      VoxelFace::PositiveZ => VoxelId::new(0, 0, 1),
+      VoxelFace::NegativeZ => VoxelId::new(0, 0, -1),
+    }
+  }
+
+  // the face of `voxel` that looks at `facing`, None unless they're neighbors across a face
+  pub fn between(voxel: &VoxelId, facing: &VoxelId) -> Option<Self> {
+    let offset = *facing - *voxel;
+    Self::ALL.into_iter().find(|face| face.offset() == offset)
+  }
+}
+
+// color overlays on the faces of the voxels of one chunk, alpha is how strongly a face is tinted
+// e.g. scorch marks of battle damage, they don't change the voxels and are baked into the vertex
+// colors when the chunk is meshed
+// they're kept in `VoxelMeta` with the rest of what's known about a chunk's voxels, and removed
+// when an edit replaces their voxel
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChunkDecals {
+  faces: HashMap<(VoxelId, VoxelFace), Color>,
+}
+
+impl ChunkDecals {
+  pub fn get(&self, voxel: &VoxelId, face: VoxelFace) -> Option<Color> {
+    self.faces.get(&(*voxel, face)).copied()
+  }
+
+  // lays `tint` over whatever is already on the face
+  pub fn paint(&mut self, voxel: VoxelId, face: VoxelFace, tint: Color) {
+    let painted = match self.faces.get(&(voxel, face)) {
+      Some(under) => blend(*under, tint),
+      None => tint,
+    };
+    self.faces.insert((voxel, face), painted);
+  }
+
+  // replaces whatever is on the face, e.g. with a decal read back from a save
+  pub fn set(&mut self, voxel: VoxelId, face: VoxelFace, tint: Color) {
+    self.faces.insert((voxel, face), tint);
+  }
+
+  pub fn remove(&mut self, voxel: &VoxelId, face: VoxelFace) -> Option<Color> {
+    self.faces.remove(&(*voxel, face))
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&(VoxelId, VoxelFace), &Color)> {
+    self.faces.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.faces.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.faces.is_empty()
+  }
+
+  // faces are saved as their index in `VoxelFace::ALL` and tints as rgba
+  pub(super) fn write(&self, bytes: &mut Vec<u8>) {
+    bytes.extend_from_slice(&(self.faces.len() as u32).to_le_bytes());
+    for ((voxel, face), tint) in self.faces.iter() {
+      bytes.extend_from_slice(&voxel.x().to_le_bytes());
+      bytes.extend_from_slice(&voxel.y().to_le_bytes());
+      bytes.extend_from_slice(&voxel.z().to_le_bytes());
+      bytes.push(
+        VoxelFace::ALL
+          .iter()
+          .position(|f| f == face)
+          .unwrap_or_default() as u8,
+      );
+      for value in tint.as_rgba_f32() {
+        bytes.extend_from_slice(&value.to_le_bytes());
+      }
+    }
+  }
+
+  pub(super) fn read(reader: &mut Reader) -> Result<Self, SnapshotError> {
+    let mut decals = Self::default();
+    for _ in 0..reader.u32()? {
+      let voxel = VoxelId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+      let index = reader.u8()?;
+      let face = *VoxelFace::ALL
+        .get(index as usize)
+        .ok_or(SnapshotError::UnknownDecalFace(index))?;
+      let tint = Color::rgba(reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?);
+      decals.set(voxel, face, tint);
+    }
+    Ok(decals)
+  }
+}
+
+// `over` laid on top of `under`
+fn blend(under: Color, over: Color) -> Color {
+  let alpha = over.a() + under.a() * (1. - over.a());
+  if alpha <= 0. {
+    return Color::NONE;
+  }
+  let mix = |under: f32, over_value: f32| {
+    (over_value * over.a() + under * under.a() * (1. - over.a())) / alpha
+  };
+  Color::rgba(
+    mix(under.r(), over.r()),
+    mix(under.g(), over.g()),
+    mix(under.b(), over.b()),
+    alpha,
+  )
+}
+
+// a vertex color of a face with a decal's tint multiplied in
+pub(super) fn tint_vertex_color(color: [f32; 4], tint: Color) -> [f32; 4] {
+  let [r, g, b, a] = tint.as_rgba_f32();
+  let tinted = |value: f32, tint: f32| value * (1. - a + a * tint);
+  [
+    tinted(color[0], r),
+    tinted(color[1], g),
+    tinted(color[2], b),
+    color[3],
+  ]
+}
+
+// queued with `TerrainEdits`, decals are only painted on loaded chunks
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecalEdit {
+  Paint(VoxelId, VoxelFace, Color),
+  // tints the exposed faces of the solid voxels within `radius` of `center`, fading out towards
+  // the edge, e.g. the scorched rim of a crater
+  Scorch {
+    center: Vec3,
+    radius: f32,
+    tint: Color,
+  },
+  // removes the decals of every voxel within `radius` of `center`
+  Clear {
+    center: Vec3,
+    radius: f32,
+  },
+}
+
+impl DecalEdit {
+  // the faces to tint, or to clear where the tint is None
+  // `get` reads the current voxel type and returns None for voxels that aren't loaded
+  fn resolve(
+    &self,
+    layout: &CubicVoxelLayout,
+    registry: &VoxelRegistry,
+    get: impl Fn(&VoxelId) -> Option<VoxelTypeId>,
+  ) -> Vec<(VoxelId, VoxelFace, Option<Color>)> {
+    match self {
+      DecalEdit::Paint(voxel, face, tint) => match get(voxel) {
+        Some(_) => vec![(*voxel, *face, Some(*tint))],
+        None => Vec::new(),
+      },
+      DecalEdit::Scorch {
+        center,
+        radius,
+        tint,
+      } => voxels_in_sphere(layout, *center, *radius)
+        .filter(|voxel| matches!(get(voxel), Some(v) if registry.is_solid(v)))
+        .flat_map(|voxel| {
+          let fade = 1. - layout.voxel_center(&voxel).distance(*center) / radius.max(f32::EPSILON);
+          let mut tint = *tint;
+          tint.set_a(tint.a() * fade.clamp(0., 1.));
+          VoxelFace::ALL
+            .into_iter()
+            .filter(
+              |face| matches!(get(&(voxel + face.offset())), Some(v) if !registry.is_opaque(v)),
+            )
+            .map(move |face| (voxel, face, Some(tint)))
+            .collect::<Vec<_>>()
+        })
+        .collect(),
+      DecalEdit::Clear { center, radius } => voxels_in_sphere(layout, *center, *radius)
+        .flat_map(|voxel| VoxelFace::ALL.map(|face| (voxel, face, None)))
+        .collect(),
+    }
+  }
+}
+
+// paints the queued decals and remeshes the chunks they're on, after the voxel edits of the
+// frame so a crater can be carved and scorched at once
+// decals only apply to the primary world
+#[allow(clippy::too_many_arguments)]
+pub fn apply_decal_edits(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  tracker: Res<ChunkTracker>,
+  mut edits: ResMut<TerrainEdits>,
+  mut meta: ResMut<VoxelMeta>,
+  mut store: ResMut<ChunkStore>,
+  chunks: Query<&ChunkVoxelData>,
+) {
+  if edits.decals.is_empty() {
+    return;
+  }
+  let get = |voxel: &VoxelId| {
+    let entity = tracker.entity(&layout.voxel_owner(voxel)?)?;
+    chunks.get(entity).ok()?.voxels.get(voxel).copied()
+  };
+
+  let mut changed = HashSet::new();
+  for edit in std::mem::take(&mut edits.decals) {
+    for (voxel, face, tint) in edit.resolve(&layout, &registry, get) {
+      let owner = match layout.voxel_owner(&voxel) {
+        Some(owner) => owner,
+        None => continue,
+      };
+      match tint {
+        Some(tint) => {
+          meta.paint_decal(owner, voxel, face, tint);
+          changed.insert(owner);
+        }
+        None => {
+          if meta.remove_decal(&owner, &voxel, face) {
+            changed.insert(owner);
+          }
+        }
+      }
+    }
+  }
+
+  for chunk in changed {
+    store.mark_dirty(chunk);
+    if let Some(entity) = tracker.entity(&chunk) {
+      commands.entity(entity).insert(DirtyChunk);
+    }
+  }
+}
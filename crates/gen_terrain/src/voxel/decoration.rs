@@ -0,0 +1,261 @@
+use super::{
+  biome::{Biome, ChunkBiome},
+  layout::CubicVoxelLayout,
+  registry::{VoxelRegistry, VoxelTypeId},
+  seed::{ChunkRng, ChunkSeed},
+  Chunk, ChunkId, ChunkVoxelData, VoxelId,
+};
+use bevy::{prelude::*, render::view::RenderLayers};
+use std::collections::HashMap;
+
+// a small mesh scattered over the surface of chunks in a biome, e.g. grass, flowers or pebbles
+#[derive(Debug, Clone)]
+pub struct Decoration {
+  pub mesh: Handle<Mesh>,
+  // `TerrainDecorations::default_material` is used when none is set
+  pub material: Option<Handle<StandardMaterial>>,
+  // chance of each surface voxel getting one
+  pub density: f32,
+}
+
+// decorations of each biome, register them at startup
+// all decorations of a kind share their mesh and material so they're cheap to draw
+#[derive(Default)]
+pub struct TerrainDecorations {
+  pub biomes: HashMap<Biome, Vec<Decoration>>,
+  pub default_material: Handle<StandardMaterial>,
+}
+
+impl TerrainDecorations {
+  pub fn register_decoration(
+    &mut self,
+    biome: Biome,
+    mesh: Handle<Mesh>,
+    density: f32,
+  ) -> &mut Decoration {
+    let decorations = self.biomes.entry(biome).or_default();
+    decorations.push(Decoration {
+      mesh,
+      material: None,
+      density: density.clamp(0., 1.),
+    });
+    decorations.last_mut().unwrap()
+  }
+
+  pub fn get(&self, biome: &Biome) -> &[Decoration] {
+    self.biomes.get(biome).map_or(&[], |d| d.as_slice())
+  }
+}
+
+// the decoration entities of a chunk, they're children of the chunk so they're despawned with it
+#[derive(Debug, Default, Component)]
+pub struct ChunkDecorations(pub Vec<Entity>);
+
+// marks a decoration entity
+#[derive(Debug, Default, Component)]
+pub struct DecorationOf(pub ChunkId);
+
+// where decorations go in a chunk, as (index into `decorations`, transform relative to the chunk)
+// surface voxels are solid voxels with a non-solid voxel above them in the same chunk, each gets
+// at most one decoration
+pub fn scatter(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  decorations: &[Decoration],
+  rng: &mut ChunkRng,
+) -> Vec<(usize, Transform)> {
+  if decorations.is_empty() {
+    return Vec::new();
+  }
+  let surface = surface_voxels(registry, voxels);
+
+  let origin = layout.chunk_to_space(chunk);
+  let side = layout.voxel_side_length();
+  let mut placed = Vec::new();
+  for voxel in surface {
+    // every voxel draws the same numbers no matter what's placed so registering a decoration
+    // doesn't move the others
+    let roll = rng.next_f32();
+    let offset = Vec3::new(rng.next_f32(), 0., rng.next_f32());
+    let angle = rng.range_f32(0.0..std::f32::consts::TAU);
+
+    let mut threshold = 0.;
+    for (index, decoration) in decorations.iter().enumerate() {
+      threshold += decoration.density;
+      if roll < threshold {
+        let top = layout.voxel_to_space(&(voxel + VoxelId::new(0, 1, 0))) - origin;
+        placed.push((
+          index,
+          Transform::from_translation(top + offset * side)
+            .with_rotation(Quat::from_rotation_y(angle)),
+        ));
+        break;
+      }
+    }
+  }
+  placed
+}
+
+// ground voxels with air above them in the same chunk, in a stable order, the beds of rivers
+// and the water on them aren't surface
+pub(super) fn surface_voxels(
+  registry: &VoxelRegistry,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+) -> Vec<VoxelId> {
+  let mut surface: Vec<_> = voxels
+    .iter()
+    .filter(|(voxel, voxel_type)| {
+      let above = **voxel + VoxelId::new(0, 1, 0);
+      registry.is_collidable(**voxel_type)
+        && matches!(voxels.get(&above), Some(v) if !registry.is_solid(*v))
+    })
+    .map(|(voxel, _)| *voxel)
+    .collect();
+  // the map's order changes between runs, the same seed has to give the same decorations
+  surface.sort_by_key(|voxel| (voxel.x(), voxel.z(), voxel.y()));
+  surface
+}
+
+pub fn setup_decorations(
+  mut decorations: ResMut<TerrainDecorations>,
+  materials: Option<ResMut<Assets<StandardMaterial>>>,
+) {
+  if let Some(mut materials) = materials {
+    decorations.default_material = materials.add(Color::rgb(0.3, 0.6, 0.2).into());
+  }
+}
+
+// chunks are decorated once they're meshed and again whenever their voxels change
+#[allow(clippy::type_complexity)]
+pub fn decorate_chunks(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  decorations: Res<TerrainDecorations>,
+  chunks: Query<
+    (
+      Entity,
+      &Chunk,
+      &ChunkVoxelData,
+      &ChunkSeed,
+      Option<&ChunkBiome>,
+      Option<&ChunkDecorations>,
+      Option<&RenderLayers>,
+    ),
+    (
+      With<Handle<Mesh>>,
+      Or<(Without<ChunkDecorations>, Changed<ChunkVoxelData>)>,
+    ),
+  >,
+) {
+  for (entity, chunk, voxel_data, seed, biome, existing, layers) in chunks.iter() {
+    if let Some(ChunkDecorations(existing)) = existing {
+      for decoration in existing.iter() {
+        commands.entity(*decoration).despawn_recursive();
+      }
+    }
+
+    let kinds = decorations.get(&biome.copied().unwrap_or_default().0);
+    let mut rng = seed.rng("decorations");
+    let children: Vec<Entity> = scatter(
+      &layout,
+      &registry,
+      &chunk.id,
+      &voxel_data.voxels,
+      kinds,
+      &mut rng,
+    )
+    .into_iter()
+    .map(|(index, transform)| {
+      let kind = &kinds[index];
+      let mut decoration = commands.spawn_bundle(PbrBundle {
+        mesh: kind.mesh.clone(),
+        material: kind
+          .material
+          .clone()
+          .unwrap_or_else(|| decorations.default_material.clone()),
+        transform,
+        ..default()
+      });
+      // render layers aren't inherited, decorations are drawn with the world they're in
+      if let Some(layers) = layers {
+        decoration.insert(*layers);
+      }
+      decoration.insert(DecorationOf(chunk.id)).id()
+    })
+    .collect();
+
+    commands
+      .entity(entity)
+      .push_children(&children)
+      .insert(ChunkDecorations(children));
+  }
+}
+
+// visibility isn't inherited, decorations are hidden with their chunk
+pub fn sync_decoration_visibility(
+  chunks: Query<(&Visibility, &ChunkDecorations), Changed<Visibility>>,
+  mut decorations: Query<&mut Visibility, (With<DecorationOf>, Without<ChunkDecorations>)>,
+) {
+  for (chunk_visibility, ChunkDecorations(children)) in chunks.iter() {
+    for child in children.iter() {
+      if let Ok(mut visibility) = decorations.get_mut(*child) {
+        if visibility.is_visible != chunk_visibility.is_visible {
+          visibility.is_visible = chunk_visibility.is_visible;
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  fn decoration(density: f32) -> Decoration {
+    Decoration {
+      mesh: Handle::default(),
+      material: None,
+      density,
+    }
+  }
+
+  proptest! {
+      #[test]
+      fn decorations_should_sit_on_surface_voxels(seed in any::<u64>(), heights in prop::collection::vec(0i64..6, 9), density in 0f32..1.) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 1.0, 1, 8);
+          let chunk = ChunkId::new(0, 0, 0);
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| {
+                  let column = ((voxel.x() + 1) * 3 + voxel.z() + 1) as usize;
+                  let solid = voxel.y() < heights[column];
+                  (voxel, if solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR })
+              })
+              .collect();
+          let kinds = [decoration(density * 0.5), decoration(density * 0.5)];
+
+          let placed = scatter(&layout, &VoxelRegistry::default(), &chunk, &voxels, &kinds, &mut ChunkRng::new(seed, &chunk, "decorations"));
+          let again = scatter(&layout, &VoxelRegistry::default(), &chunk, &voxels, &kinds, &mut ChunkRng::new(seed, &chunk, "decorations"));
+          assert_eq!(placed.len(), again.len());
+
+          let origin = layout.chunk_to_space(&chunk);
+          for (index, transform) in placed.iter() {
+              assert!(*index < kinds.len());
+              let below = layout.space_to_voxel(&(origin + transform.translation - Vec3::Y * 0.5));
+              let above = layout.space_to_voxel(&(origin + transform.translation + Vec3::Y * 0.5));
+              assert_eq!(voxels.get(&below), Some(&VoxelTypeId::DIRT));
+              assert_eq!(voxels.get(&above), Some(&VoxelTypeId::AIR));
+          }
+          let surface = heights.iter().filter(|height| **height > 0).count();
+          assert!(placed.len() <= surface);
+          if density == 0. {
+              assert!(placed.is_empty());
+          }
+      }
+  }
+}
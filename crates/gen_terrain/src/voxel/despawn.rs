@@ -0,0 +1,218 @@
+use super::{
+  cache::{self, ChunkMeshCache, ChunkVoxelCache, CompressedVoxels, VoxelCachePolicy},
+  in_spawner_range,
+  layout::CubicVoxelLayout,
+  lod::ClippedChunk,
+  prediction::ChunkSpawnerConfig,
+  retention::ChunkRetentionPolicy,
+  tracker::ChunkTracker,
+  world::{TerrainWorld, TerrainWorlds},
+  Chunk, ChunkId, ChunkSpawner, ChunkVoxelData, EditedChunk,
+};
+use bevy::prelude::*;
+use std::sync::{Arc, Weak};
+
+// sent a frame before a chunk that went out of range is despawned, so games can save whatever
+// they attached to it, e.g. loot markers or fog of war
+// read it before `TerrainSystem::Despawn` of the next frame, the chunk is despawned then unless
+// something holds on to `defer_despawn`
+// chunks thrown away by `RegenerateTerrain`, a removed world or a teleport are despawned right
+// away without one
+#[derive(Debug, Clone)]
+pub struct ChunkDespawning {
+  pub entity: Entity,
+  pub chunk: ChunkId,
+  pub world: TerrainWorld,
+  deferrals: Weak<()>,
+}
+
+impl ChunkDespawning {
+  // keeps the chunk around until every deferral is dropped, `None` if it's already gone
+  // the chunk stays loaded in the meantime, spawners coming back don't spawn it again
+  pub fn defer_despawn(&self) -> Option<DespawnDeferral> {
+    self.deferrals.upgrade().map(DespawnDeferral)
+  }
+}
+
+// held by a game that isn't done with a despawning chunk yet, see `ChunkDespawning`
+#[derive(Debug, Clone)]
+pub struct DespawnDeferral(Arc<()>);
+
+// marks a chunk that was announced with `ChunkDespawning` and is despawned once nothing defers it
+#[derive(Debug, Component)]
+pub struct DespawningChunk {
+  deferrals: Arc<()>,
+}
+
+impl DespawningChunk {
+  // the marker and the event to announce it with
+  pub(super) fn announce(
+    entity: Entity,
+    chunk: ChunkId,
+    world: TerrainWorld,
+  ) -> (Self, ChunkDespawning) {
+    let deferrals = Arc::new(());
+    let event = ChunkDespawning {
+      entity,
+      chunk,
+      world,
+      deferrals: Arc::downgrade(&deferrals),
+    };
+    (Self { deferrals }, event)
+  }
+
+  pub fn is_deferred(&self) -> bool {
+    Arc::strong_count(&self.deferrals) > 1
+  }
+}
+
+// despawns the chunks announced in an earlier frame that nothing defers anymore, chunks that were
+// pinned or required in the meantime or that a spawner came back for stay loaded
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn finish_chunk_despawns(
+  mut commands: Commands,
+  time: Res<Time>,
+  layout: Res<CubicVoxelLayout>,
+  meshes: Res<Assets<Mesh>>,
+  voxel_cache_policy: Res<VoxelCachePolicy>,
+  policy: Res<ChunkRetentionPolicy>,
+  spawner_config: Res<ChunkSpawnerConfig>,
+  mut tracker: ResMut<ChunkTracker>,
+  mut worlds: ResMut<TerrainWorlds>,
+  mut mesh_cache: ResMut<ChunkMeshCache>,
+  mut voxel_cache: ResMut<ChunkVoxelCache>,
+  query: Query<(
+    Entity,
+    &Chunk,
+    &DespawningChunk,
+    Option<&Handle<Mesh>>,
+    Option<&ChunkVoxelData>,
+    Option<&EditedChunk>,
+    Option<&ClippedChunk>,
+    Option<&TerrainWorld>,
+  )>,
+  sites: Query<(&ChunkSpawner, Option<&TerrainWorld>)>,
+) {
+  for (entity, chunk, despawning, mesh, voxel_data, edited, clipped, world) in query.iter() {
+    if despawning.is_deferred() {
+      continue;
+    }
+    let world = world.copied().unwrap_or_default();
+    let tracker = match worlds.tracker_mut(&world, &mut tracker) {
+      Some(tracker) => tracker,
+      // the world was removed
+      None => {
+        commands.entity(entity).despawn_recursive();
+        continue;
+      }
+    };
+    let in_range = in_spawner_range(
+      &layout,
+      &policy,
+      &spawner_config,
+      sites.iter(),
+      &chunk.id,
+      world,
+    );
+    if in_range || tracker.is_pinned(&chunk.id) || tracker.is_required(&chunk.id) {
+      commands.entity(entity).remove::<DespawningChunk>();
+      continue;
+    }
+
+    if tracker.try_despawn(&chunk.id, 0., 0.) {
+      // edited chunks regenerate differently from their mesh, so they aren't cached
+      // the cache is keyed by chunk id, so only chunks of the primary world are cached
+      if let (Some(handle), None, true) = (mesh, edited, world.is_primary()) {
+        let bytes = meshes.get(handle).map_or(0, cache::estimate_mesh_bytes);
+        mesh_cache.insert(
+          chunk.id,
+          handle.clone(),
+          bytes,
+          time.seconds_since_startup(),
+        );
+      }
+      // clipped chunks only have their surface, edits are in the voxels and applied again anyway
+      if let (Some(voxel_data), None, true) = (voxel_data, clipped, world.is_primary()) {
+        voxel_cache.insert(
+          chunk.id,
+          CompressedVoxels::compress(&layout, &chunk.id, voxel_data),
+          &voxel_cache_policy,
+        );
+      }
+    }
+    commands.entity(entity).despawn_recursive();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{layout::CubicVoxelLayout, testing::TerrainTestApp};
+  use proptest::prelude::*;
+
+  proptest! {
+      #![proptest_config(ProptestConfig::with_cases(8))]
+
+      #[test]
+      fn deferred_chunks_should_outlive_the_others(steps in 10i64..16) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4);
+          let path = (0..=steps).map(|x| layout.chunk_to_space(&ChunkId::new(x, 0, 0)) + Vec3::splat(0.5));
+          let mut test = TerrainTestApp::new(layout).with_path(path);
+
+          // the first chunk announced is held on to, the rest are let go
+          let mut deferred: Option<(ChunkDespawning, DespawnDeferral)> = None;
+          let mut released = Vec::new();
+          for _ in 0..steps * 10 {
+              test.tick();
+              let events = test.app.world.resource::<Events<ChunkDespawning>>();
+              for event in events.iter_current_update_events() {
+                  match (deferred.is_none(), event.defer_despawn()) {
+                      (true, Some(deferral)) => deferred = Some((event.clone(), deferral)),
+                      _ => released.push(event.entity),
+                  }
+              }
+          }
+          let (event, deferral) = deferred.expect("a chunk was announced");
+          prop_assert!(!released.is_empty());
+          for entity in released {
+              prop_assert!(test.app.world.get_entity(entity).is_none());
+          }
+          prop_assert!(test.app.world.get_entity(event.entity).is_some());
+          prop_assert_eq!(test.chunk_entity(&event.chunk), Some(event.entity));
+
+          drop(deferral);
+          test.tick();
+          prop_assert!(test.app.world.get_entity(event.entity).is_none());
+          test.assert_chunk_unloaded(&event.chunk);
+          prop_assert!(event.defer_despawn().is_none());
+      }
+
+      #[test]
+      fn chunks_should_stay_when_a_spawner_comes_back_before_they_despawn(steps in 10i64..16) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4);
+          let out = (0..=steps).map(|x| layout.chunk_to_space(&ChunkId::new(x, 0, 0)) + Vec3::splat(0.5));
+          let back = (0..=steps).rev().map(|x| layout.chunk_to_space(&ChunkId::new(x, 0, 0)) + Vec3::splat(0.5));
+          let mut test = TerrainTestApp::new(layout).with_path(out.chain(back).collect::<Vec<_>>());
+
+          // the first chunk announced is held on to until the spawner is back where it started
+          let mut deferred: Option<(ChunkDespawning, DespawnDeferral)> = None;
+          for _ in 0..steps * 2 + 2 {
+              test.tick();
+              let events = test.app.world.resource::<Events<ChunkDespawning>>();
+              for event in events.iter_current_update_events() {
+                  if deferred.is_none() {
+                      deferred = event.defer_despawn().map(|deferral| (event.clone(), deferral));
+                  }
+              }
+          }
+          let (event, deferral) = deferred.expect("a chunk was announced");
+          drop(deferral);
+          for _ in 0..4 {
+              test.tick();
+          }
+          prop_assert!(test.app.world.get_entity(event.entity).is_some());
+          prop_assert_eq!(test.chunk_entity(&event.chunk), Some(event.entity));
+          prop_assert!(test.app.world.get::<DespawningChunk>(event.entity).is_none());
+      }
+  }
+}
@@ -0,0 +1,82 @@
+use super::{
+  pipeline::{ChunkPipeline, ChunkPipelineStats},
+  tracker::ChunkTracker,
+};
+use bevy::{
+  diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+  prelude::*,
+};
+
+// chunk streaming diagnostics, add `LogDiagnosticsPlugin` to log them or read them from
+// `Diagnostics` e.g. in a debug overlay
+#[derive(Default)]
+pub struct TerrainDiagnosticsPlugin;
+
+impl Plugin for TerrainDiagnosticsPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<Diagnostics>()
+      .add_startup_system(Self::setup_system)
+      .add_system_to_stage(CoreStage::PostUpdate, Self::diagnostic_system);
+  }
+}
+
+impl TerrainDiagnosticsPlugin {
+  pub const CHUNKS_LOADED: DiagnosticId =
+    DiagnosticId::from_u128(217485495894801959282326774804668732565);
+  pub const CHUNKS_MESHED_PER_SECOND: DiagnosticId =
+    DiagnosticId::from_u128(273175265345121290115922162208703117616);
+  // from submitting a chunk's generation task until its voxels are ready
+  pub const VOXEL_GENERATION_MS: DiagnosticId =
+    DiagnosticId::from_u128(191660842782256256905798172666457804799);
+  pub const MESH_GENERATION_MS: DiagnosticId =
+    DiagnosticId::from_u128(119344632588282185526834835042556108830);
+  // voxel and mesh tasks submitted whose results haven't been applied yet
+  pub const PENDING_TASKS: DiagnosticId =
+    DiagnosticId::from_u128(305225474836519886228022669857657748945);
+
+  pub fn setup_system(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(Self::CHUNKS_LOADED, "chunks_loaded", 1));
+    diagnostics.add(Diagnostic::new(
+      Self::CHUNKS_MESHED_PER_SECOND,
+      "chunks_meshed_per_second",
+      20,
+    ));
+    diagnostics
+      .add(Diagnostic::new(Self::VOXEL_GENERATION_MS, "voxel_generation", 20).with_suffix("ms"));
+    diagnostics
+      .add(Diagnostic::new(Self::MESH_GENERATION_MS, "mesh_generation", 20).with_suffix("ms"));
+    diagnostics.add(Diagnostic::new(Self::PENDING_TASKS, "pending_tasks", 1));
+  }
+
+  pub fn diagnostic_system(
+    mut diagnostics: ResMut<Diagnostics>,
+    mut last: Local<ChunkPipelineStats>,
+    time: Res<Time>,
+    tracker: Res<ChunkTracker>,
+    pipeline: Res<ChunkPipeline>,
+    stats: Res<ChunkPipelineStats>,
+  ) {
+    diagnostics.add_measurement(Self::CHUNKS_LOADED, tracker.len() as f64);
+    diagnostics.add_measurement(Self::PENDING_TASKS, pipeline.in_flight() as f64);
+
+    let meshes = stats.meshes_built - last.meshes_built;
+    if time.delta_seconds_f64() > 0. {
+      diagnostics.add_measurement(
+        Self::CHUNKS_MESHED_PER_SECOND,
+        meshes as f64 / time.delta_seconds_f64(),
+      );
+    }
+    // frames without finished tasks keep the previous averages
+    if meshes > 0 {
+      let seconds = stats.mesh_seconds - last.mesh_seconds;
+      diagnostics.add_measurement(Self::MESH_GENERATION_MS, seconds * 1000. / meshes as f64);
+    }
+    let voxels = stats.voxels_loaded - last.voxels_loaded;
+    if voxels > 0 {
+      let seconds = stats.voxel_seconds - last.voxel_seconds;
+      diagnostics.add_measurement(Self::VOXEL_GENERATION_MS, seconds * 1000. / voxels as f64);
+    }
+    *last = stats.clone();
+  }
+}
@@ -0,0 +1,586 @@
+use super::{
+  decal::{DecalEdit, VoxelFace},
+  layout::CubicVoxelLayout,
+  meta::VoxelMetaEditor,
+  registry::{VoxelRegistry, VoxelTypeId},
+  snapshot::ChunkDiffs,
+  store::ChunkStore,
+  world::TerrainWorld,
+  Chunk, ChunkId, ChunkVoxelData, EditedChunk, VoxelId,
+};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// the ring around a flattened area over which it fades into the surrounding terrain is this
+// fraction of the area's radius wide
+const FLATTEN_FALLOFF: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoxelEdit {
+  Set(VoxelId, VoxelTypeId),
+  CarveSphere {
+    center: Vec3,
+    radius: f32,
+  },
+  FillSphere {
+    center: Vec3,
+    radius: f32,
+    voxel_type: VoxelTypeId,
+  },
+  FillBox {
+    min: Vec3,
+    max: Vec3,
+    voxel_type: VoxelTypeId,
+  },
+  PaintSurface {
+    center: Vec3,
+    radius: f32,
+    voxel_type: VoxelTypeId,
+  },
+  // `height` is in world space, only x and z of `center` are used
+  Flatten {
+    center: Vec3,
+    radius: f32,
+    height: f32,
+    voxel_type: VoxelTypeId,
+  },
+}
+
+impl VoxelEdit {
+  // resolve the edit into individual voxel changes
+  // `get` reads the current voxel type and returns None for voxels that aren't loaded
+  fn resolve(
+    &self,
+    layout: &CubicVoxelLayout,
+    registry: &VoxelRegistry,
+    get: impl Fn(&VoxelId) -> Option<VoxelTypeId>,
+  ) -> Vec<(VoxelId, VoxelTypeId)> {
+    match self {
+      VoxelEdit::Set(voxel, voxel_type) => vec![(*voxel, *voxel_type)],
+      VoxelEdit::CarveSphere { center, radius } => voxels_in_sphere(layout, *center, *radius)
+        .map(|voxel| (voxel, VoxelTypeId::AIR))
+        .collect(),
+      VoxelEdit::FillSphere {
+        center,
+        radius,
+        voxel_type,
+      } => voxels_in_sphere(layout, *center, *radius)
+        .map(|voxel| (voxel, *voxel_type))
+        .collect(),
+      VoxelEdit::FillBox {
+        min,
+        max,
+        voxel_type,
+      } => layout
+        .get_voxels_in_aabb(min, max)
+        .map(|voxel| (voxel, *voxel_type))
+        .collect(),
+      VoxelEdit::PaintSurface {
+        center,
+        radius,
+        voxel_type,
+      } => voxels_in_sphere(layout, *center, *radius)
+        .filter(|voxel| {
+          // a surface voxel is a ground voxel that is exposed from above, liquids aren't ground
+          let above = *voxel + VoxelId::new(0, 1, 0);
+          matches!(get(voxel), Some(v) if registry.is_collidable(v))
+            && !matches!(get(&above), Some(v) if registry.is_collidable(v))
+        })
+        .map(|voxel| (voxel, *voxel_type))
+        .collect(),
+      VoxelEdit::Flatten {
+        center,
+        radius,
+        height,
+        voxel_type,
+      } => flatten(
+        layout,
+        registry,
+        get,
+        *center,
+        *radius,
+        *height,
+        *voxel_type,
+      ),
+    }
+  }
+}
+
+// raises or lowers every column within `radius` of `center` to `height`, columns in the falloff
+// ring beyond it are moved less the farther out they are
+// columns are filled from their old surface up, so caves underneath are left alone, and columns
+// that aren't fully loaded are skipped
+fn flatten(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  get: impl Fn(&VoxelId) -> Option<VoxelTypeId>,
+  center: Vec3,
+  radius: f32,
+  height: f32,
+  voxel_type: VoxelTypeId,
+) -> Vec<(VoxelId, VoxelTypeId)> {
+  let side = layout.voxel_side_length();
+  let falloff = (radius * FLATTEN_FALLOFF).max(side);
+  let outer = radius + falloff;
+  let columns = layout.get_voxels_in_aabb(
+    &Vec3::new(center.x - outer, 0., center.z - outer),
+    &Vec3::new(center.x + outer, 0., center.z + outer),
+  );
+
+  let mut changes = Vec::new();
+  for column in columns {
+    let column_center = layout.voxel_center(&column);
+    let distance = Vec2::new(column_center.x - center.x, column_center.z - center.z).length();
+    if distance > outer {
+      continue;
+    }
+    let voxels: Option<Vec<_>> = (0..layout.world_voxel_height())
+      .map(|y| {
+        let voxel = VoxelId::new(column.x(), y, column.z());
+        get(&voxel).map(|voxel_type| (voxel, voxel_type))
+      })
+      .collect();
+    let voxels = match voxels {
+      Some(voxels) => voxels,
+      None => continue,
+    };
+
+    // the top of the highest ground voxel, or the bottom of the world, liquids are filled in or
+    // left standing like air
+    let surface = voxels
+      .iter()
+      .filter(|(_, voxel_type)| registry.is_collidable(*voxel_type))
+      .map(|(voxel, _)| layout.voxel_to_space(voxel).y + side)
+      .fold(
+        layout
+          .voxel_to_space(&VoxelId::new(column.x(), 0, column.z()))
+          .y,
+        f32::max,
+      );
+    let t = ((distance - radius) / falloff).clamp(0., 1.);
+    let target = height + (surface - height) * t * t * (3. - 2. * t);
+
+    for (voxel, old) in voxels {
+      let y = layout.voxel_center(&voxel).y;
+      if y >= target && registry.is_collidable(old) {
+        changes.push((voxel, VoxelTypeId::AIR));
+      } else if y < target && y > surface && !registry.is_collidable(old) {
+        changes.push((voxel, voxel_type));
+      }
+    }
+  }
+  changes
+}
+
+pub(super) fn voxels_in_sphere(
+  layout: &CubicVoxelLayout,
+  center: Vec3,
+  radius: f32,
+) -> impl Iterator<Item = VoxelId> + '_ {
+  let extent = Vec3::splat(radius);
+  layout
+    .get_voxels_in_aabb(&(center - extent), &(center + extent))
+    .filter(move |voxel| layout.voxel_center(voxel).distance(center) <= radius)
+}
+
+// sent for every voxel of a loaded chunk that changed, after the chunk's voxel data is updated, so
+// lighting, navigation or networking can follow the changes without diffing whole chunks
+// brushes, snapshots and network sync change voxels through `TerrainEdits` like any other edit,
+// saved changes read after the chunk loaded are sent too, regenerated chunks aren't, decals
+// don't change voxels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoxelChanged {
+  pub chunk: ChunkId,
+  pub voxel: VoxelId,
+  pub old: VoxelTypeId,
+  pub new: VoxelTypeId,
+}
+
+// voxels of a chunk edited since its mesh was last updated, small edits patch the existing mesh
+// instead of remeshing the whole chunk
+#[derive(Debug, Default, Component)]
+pub struct EditedVoxels(pub HashSet<VoxelId>);
+
+// queue of world-space edits, applied to loaded chunks by `apply_voxel_edits`
+// voxels in chunks that aren't loaded are left untouched
+#[derive(Default)]
+pub struct TerrainEdits {
+  queue: Vec<VoxelEdit>,
+  // applied by `decal::apply_decal_edits` after the voxel edits
+  pub(super) decals: Vec<DecalEdit>,
+}
+
+impl TerrainEdits {
+  pub fn push(&mut self, edit: VoxelEdit) {
+    self.queue.push(edit);
+  }
+
+  pub fn set_voxel(&mut self, voxel: VoxelId, voxel_type: VoxelTypeId) {
+    self.push(VoxelEdit::Set(voxel, voxel_type));
+  }
+
+  pub fn carve_sphere(&mut self, center: Vec3, radius: f32) {
+    self.push(VoxelEdit::CarveSphere { center, radius });
+  }
+
+  pub fn fill_sphere(&mut self, center: Vec3, radius: f32, voxel_type: VoxelTypeId) {
+    self.push(VoxelEdit::FillSphere {
+      center,
+      radius,
+      voxel_type,
+    });
+  }
+
+  pub fn fill_box(&mut self, min: Vec3, max: Vec3, voxel_type: VoxelTypeId) {
+    self.push(VoxelEdit::FillBox {
+      min: min.min(max),
+      max: min.max(max),
+      voxel_type,
+    });
+  }
+
+  pub fn paint_surface(&mut self, center: Vec3, radius: f32, voxel_type: VoxelTypeId) {
+    self.push(VoxelEdit::PaintSurface {
+      center,
+      radius,
+      voxel_type,
+    });
+  }
+
+  // levels the ground around `center` to `height` in world space, e.g. to place a building
+  // the plateau blends into the surrounding terrain over a ring half as wide as `radius`
+  pub fn flatten_area(&mut self, center: Vec3, radius: f32, height: f32, voxel_type: VoxelTypeId) {
+    self.push(VoxelEdit::Flatten {
+      center,
+      radius,
+      height,
+      voxel_type,
+    });
+  }
+
+  pub fn push_decal(&mut self, edit: DecalEdit) {
+    self.decals.push(edit);
+  }
+
+  // tints one face of a voxel without changing it, see `ChunkDecals`
+  pub fn paint_decal(&mut self, voxel: VoxelId, face: VoxelFace, tint: Color) {
+    self.push_decal(DecalEdit::Paint(voxel, face, tint));
+  }
+
+  // e.g. right after `carve_sphere` for the scorched rim of a crater
+  pub fn scorch_sphere(&mut self, center: Vec3, radius: f32, tint: Color) {
+    self.push_decal(DecalEdit::Scorch {
+      center,
+      radius,
+      tint,
+    });
+  }
+
+  pub fn clear_decals(&mut self, center: Vec3, radius: f32) {
+    self.push_decal(DecalEdit::Clear { center, radius });
+  }
+
+  // whether no voxel edits are queued, queued decals don't count
+  pub fn is_empty(&self) -> bool {
+    self.queue.is_empty()
+  }
+}
+
+// a voxel changed by an edit, with its type before and after
+type EditedVoxel = (VoxelId, VoxelTypeId, VoxelTypeId);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HistoryStep {
+  Undo,
+  Redo,
+}
+
+// what `apply_voxel_edits` works through, queued edits first
+enum QueuedEdit {
+  Edit(VoxelEdit),
+  History(HistoryStep),
+}
+
+// the edits applied by `apply_voxel_edits`, one entry per queued edit, for undo and redo in e.g.
+// a level editor
+// undone and redone voxels go through the same path as edits, so they're remeshed, saved and sent
+// as `VoxelChanged` like any other edit, voxels in chunks that aren't loaded are left untouched
+pub struct EditHistory {
+  // edits past this many are forgotten, oldest first
+  pub capacity: usize,
+  done: VecDeque<Vec<EditedVoxel>>,
+  undone: Vec<Vec<EditedVoxel>>,
+  requests: Vec<HistoryStep>,
+}
+impl Default for EditHistory {
+  fn default() -> Self {
+    Self {
+      capacity: 64,
+      done: VecDeque::new(),
+      undone: Vec::new(),
+      requests: Vec::new(),
+    }
+  }
+}
+
+impl EditHistory {
+  // reverts the last edit, after the edits queued this frame are applied
+  pub fn undo(&mut self) {
+    self.requests.push(HistoryStep::Undo);
+  }
+
+  // applies the last undone edit again, new edits clear what can be redone
+  pub fn redo(&mut self) {
+    self.requests.push(HistoryStep::Redo);
+  }
+
+  // as of the last time edits were applied
+  pub fn can_undo(&self) -> bool {
+    !self.done.is_empty()
+  }
+
+  pub fn can_redo(&self) -> bool {
+    !self.undone.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.done.clear();
+    self.undone.clear();
+  }
+
+  fn record(&mut self, edit: Vec<EditedVoxel>) {
+    if edit.is_empty() {
+      return;
+    }
+    self.undone.clear();
+    self.done.push_back(edit);
+    while self.done.len() > self.capacity {
+      self.done.pop_front();
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn apply_voxel_edits(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  mut edits: ResMut<TerrainEdits>,
+  mut history: ResMut<EditHistory>,
+  mut diffs: ResMut<ChunkDiffs>,
+  mut meta: VoxelMetaEditor,
+  mut store: ResMut<ChunkStore>,
+  mut changed: EventWriter<VoxelChanged>,
+  mut query: Query<(
+    Entity,
+    &Chunk,
+    &mut ChunkVoxelData,
+    Option<&mut EditedVoxels>,
+    Option<&TerrainWorld>,
+  )>,
+) {
+  if edits.is_empty() && history.requests.is_empty() {
+    return;
+  }
+
+  // edits only apply to the primary world
+  let mut chunks: HashMap<_, _> = query
+    .iter_mut()
+    .filter(|(.., world)| world.copied().unwrap_or_default().is_primary())
+    .map(|(entity, chunk, voxel_data, edited, _)| (chunk.id, (entity, voxel_data, edited)))
+    .collect();
+  let mut edited_chunks = HashSet::new();
+  // chunks edited for the first time since they were meshed
+  let mut new_edits: HashMap<Entity, HashSet<VoxelId>> = HashMap::new();
+
+  let steps: Vec<_> = history.requests.drain(..).collect();
+  let queued = edits
+    .queue
+    .drain(..)
+    .map(QueuedEdit::Edit)
+    .chain(steps.into_iter().map(QueuedEdit::History));
+  for edit in queued {
+    let changes = match &edit {
+      QueuedEdit::Edit(edit) => edit.resolve(&layout, &registry, |voxel| {
+        chunks
+          .get(&layout.voxel_owner(voxel)?)
+          .and_then(|(_, voxel_data, _)| voxel_data.voxels.get(voxel).copied())
+      }),
+      QueuedEdit::History(HistoryStep::Undo) => match history.done.pop_back() {
+        Some(edited) => {
+          let changes = edited
+            .iter()
+            .rev()
+            .map(|(voxel, old, _)| (*voxel, *old))
+            .collect();
+          history.undone.push(edited);
+          changes
+        }
+        None => continue,
+      },
+      QueuedEdit::History(HistoryStep::Redo) => match history.undone.pop() {
+        Some(edited) => {
+          let changes = edited
+            .iter()
+            .map(|(voxel, _, new)| (*voxel, *new))
+            .collect();
+          history.done.push_back(edited);
+          changes
+        }
+        None => continue,
+      },
+    };
+
+    let mut applied = Vec::new();
+    for (voxel, voxel_type) in changes {
+      let owner = match layout.voxel_owner(&voxel) {
+        Some(owner) => owner,
+        None => continue,
+      };
+      if let Some((entity, voxel_data, edited)) = chunks.get_mut(&owner) {
+        // only overwrite voxels the chunk already has and avoid
+        // triggering change detection for no-op edits
+        if let Some(old) = voxel_data.voxels.get(&voxel).copied() {
+          if old == voxel_type {
+            continue;
+          }
+          voxel_data.voxels.insert(voxel, voxel_type);
+          applied.push((voxel, old, voxel_type));
+          changed.send(VoxelChanged {
+            chunk: owner,
+            voxel,
+            old,
+            new: voxel_type,
+          });
+          diffs.record(owner, voxel, voxel_type);
+          store.mark_dirty(owner);
+          // metadata and decals describe the voxel that was replaced
+          meta.remove(&voxel);
+          meta.remove_decals(&voxel);
+          match edited {
+            Some(edited) => {
+              edited.0.insert(voxel);
+            }
+            None => {
+              new_edits.entry(*entity).or_default().insert(voxel);
+            }
+          }
+          edited_chunks.insert(*entity);
+        }
+      }
+    }
+    // undoing and redoing only moves edits between the stacks
+    if let QueuedEdit::Edit(_) = edit {
+      history.record(applied);
+    }
+  }
+
+  for entity in edited_chunks {
+    commands.entity(entity).insert(EditedChunk);
+  }
+  for (entity, voxels) in new_edits {
+    commands.entity(entity).insert(EditedVoxels(voxels));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::meta::{VoxelMeta, VoxelMetaChanged};
+  use bevy::ecs::event::Events;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn flattened_areas_should_reach_the_target_height(ground in 1i64..15, height in 1i64..15, radius in 1f32..4.) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 8, 16);
+          let registry = VoxelRegistry::default();
+          let mut voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&ChunkId::default())
+              .into_iter()
+              .map(|voxel| (voxel, if voxel.y() < ground { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let center = layout.voxel_center(&VoxelId::new(0, 0, 0));
+          let edit = VoxelEdit::Flatten { center, radius, height: height as f32, voxel_type: VoxelTypeId::DIRT };
+          for (voxel, voxel_type) in edit.resolve(&layout, &registry, |voxel| voxels.get(voxel).copied()) {
+              voxels.insert(voxel, voxel_type);
+          }
+
+          let column_height = |x: i64, z: i64| {
+              (0..16).filter(|y| registry.is_solid(voxels[&VoxelId::new(x, *y, z)])).count() as i64
+          };
+          let outer = radius * (1. + FLATTEN_FALLOFF);
+          for x in -7i64..=7 {
+              for z in -7i64..=7 {
+                  let distance = Vec2::new(x as f32, z as f32).length();
+                  if distance <= radius {
+                      prop_assert_eq!(column_height(x, z), height);
+                  } else if distance > outer.max(radius + 1.) {
+                      prop_assert_eq!(column_height(x, z), ground);
+                  } else {
+                      // the falloff ring stays between the plateau and the old ground
+                      let h = column_height(x, z);
+                      prop_assert!(h >= ground.min(height) && h <= ground.max(height));
+                  }
+              }
+          }
+      }
+
+      #[test]
+      fn undo_and_redo_should_restore_voxels(
+          edits in prop::collection::vec((0i64..3, 0i64..3, 0i64..3, 0u16..3), 1..8),
+          undos in 0usize..10,
+      ) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 3);
+          let chunk = ChunkId::new(0, 0, 0);
+          let voxel = |x: i64, y: i64, z: i64| layout.get_voxel(&chunk, x - 1, y, z - 1);
+          let mut world = World::new();
+          world.insert_resource(layout.clone());
+          world.insert_resource(VoxelRegistry::default());
+          world.insert_resource(TerrainEdits::default());
+          world.insert_resource(EditHistory::default());
+          world.insert_resource(ChunkDiffs::default());
+          world.insert_resource(ChunkStore::default());
+          world.insert_resource(VoxelMeta::default());
+          world.insert_resource(Events::<VoxelMetaChanged>::default());
+          world.insert_resource(Events::<VoxelChanged>::default());
+          let initial: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, VoxelTypeId::AIR))
+              .collect();
+          let entity = world
+              .spawn()
+              .insert(Chunk { id: chunk, ..Default::default() })
+              .insert(ChunkVoxelData { voxels: initial.clone() })
+              .id();
+          let mut stage = SystemStage::single_threaded().with_system(apply_voxel_edits);
+          let voxels = |world: &World| world.get::<ChunkVoxelData>(entity).unwrap().voxels.clone();
+
+          // every edit is applied in its own frame so each state can be compared against
+          let mut states = vec![initial];
+          for (x, y, z, voxel_type) in edits {
+              world.resource_mut::<TerrainEdits>().set_voxel(voxel(x, y, z), VoxelTypeId(voxel_type));
+              stage.run(&mut world);
+              let current = voxels(&world);
+              if current != *states.last().unwrap() {
+                  states.push(current);
+              }
+          }
+
+          let undos = undos.min(states.len() - 1);
+          for _ in 0..undos {
+              world.resource_mut::<EditHistory>().undo();
+          }
+          stage.run(&mut world);
+          prop_assert_eq!(&voxels(&world), &states[states.len() - 1 - undos]);
+
+          for _ in 0..undos {
+              world.resource_mut::<EditHistory>().redo();
+          }
+          stage.run(&mut world);
+          prop_assert_eq!(&voxels(&world), states.last().unwrap());
+          prop_assert!(!world.resource::<EditHistory>().can_redo());
+      }
+  }
+}
@@ -0,0 +1,154 @@
+use super::{
+  cursor::CursorTerrainHit,
+  edit::TerrainEdits,
+  layout::CubicVoxelLayout,
+  registry::{VoxelRegistry, VoxelTypeId},
+};
+use bevy::{input::mouse::MouseWheel, prelude::*};
+
+const NUMBER_KEYS: [KeyCode; 9] = [
+  KeyCode::Key1,
+  KeyCode::Key2,
+  KeyCode::Key3,
+  KeyCode::Key4,
+  KeyCode::Key5,
+  KeyCode::Key6,
+  KeyCode::Key7,
+  KeyCode::Key8,
+  KeyCode::Key9,
+];
+// how much one wheel step grows or shrinks the brush
+const BRUSH_SCROLL_FACTOR: f32 = 1.1;
+// held to size the brush with the wheel, which is left to the camera otherwise
+const BRUSH_SIZE_KEYS: [KeyCode; 2] = [KeyCode::LControl, KeyCode::RControl];
+const PREVIEW_ALPHA: f32 = 0.3;
+
+// sandbox editing at the cursor: number keys pick the voxel type, the wheel sizes the brush while
+// control is held, left click digs and right click places
+// edits through the cameras given a `ScreenToTerrain`
+#[derive(Default)]
+pub struct TerrainEditorPlugin;
+
+impl Plugin for TerrainEditorPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<TerrainBrush>()
+      .add_startup_system(spawn_brush_preview)
+      .add_system(control_brush)
+      .add_system(apply_brush.after(control_brush))
+      .add_system(update_brush_preview.after(control_brush));
+  }
+}
+
+// the brush of `TerrainEditorPlugin`
+pub struct TerrainBrush {
+  // placed by right clicks, number key n picks the voxel type with id n
+  pub voxel_type: VoxelTypeId,
+  // world units
+  pub radius: f32,
+  pub min_radius: f32,
+  pub max_radius: f32,
+}
+impl Default for TerrainBrush {
+  fn default() -> Self {
+    Self {
+      voxel_type: VoxelTypeId::DIRT,
+      radius: 1.5,
+      min_radius: 0.5,
+      max_radius: 16.0,
+    }
+  }
+}
+
+// marks the translucent sphere showing the brush volume at the cursor
+#[derive(Debug, Default, Component)]
+pub struct BrushPreview;
+
+pub fn spawn_brush_preview(
+  mut commands: Commands,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+  commands
+    .spawn_bundle(PbrBundle {
+      mesh: meshes.add(Mesh::from(shape::UVSphere {
+        radius: 1.0,
+        sectors: 24,
+        stacks: 12,
+      })),
+      material: materials.add(StandardMaterial {
+        base_color: Color::rgba(1., 1., 1., PREVIEW_ALPHA),
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..default()
+      }),
+      visibility: Visibility { is_visible: false },
+      ..default()
+    })
+    .insert(BrushPreview);
+}
+
+pub fn control_brush(
+  keys: Res<Input<KeyCode>>,
+  registry: Res<VoxelRegistry>,
+  mut wheel: EventReader<MouseWheel>,
+  mut brush: ResMut<TerrainBrush>,
+) {
+  for (index, key) in NUMBER_KEYS.iter().enumerate() {
+    let voxel_type = VoxelTypeId(index as u16 + 1);
+    if keys.just_pressed(*key) && registry.get(voxel_type).is_some() {
+      brush.voxel_type = voxel_type;
+    }
+  }
+
+  let scroll: f32 = wheel.iter().map(|event| event.y).sum();
+  if scroll != 0. && BRUSH_SIZE_KEYS.iter().any(|key| keys.pressed(*key)) {
+    brush.radius =
+      (brush.radius * BRUSH_SCROLL_FACTOR.powf(scroll)).clamp(brush.min_radius, brush.max_radius);
+  }
+}
+
+// one edit per click, at the voxel under the cursor
+pub fn apply_brush(
+  buttons: Res<Input<MouseButton>>,
+  layout: Res<CubicVoxelLayout>,
+  cursor: Res<CursorTerrainHit>,
+  brush: Res<TerrainBrush>,
+  mut edits: ResMut<TerrainEdits>,
+) {
+  let hit = match cursor.hit {
+    Some(hit) => hit,
+    None => return,
+  };
+  if buttons.just_pressed(MouseButton::Left) {
+    edits.carve_sphere(hit.position, brush.radius);
+  }
+  if buttons.just_pressed(MouseButton::Right) {
+    // placed voxels grow out of the face under the cursor
+    let center = layout.voxel_center(&hit.adjacent_voxel());
+    edits.fill_sphere(center, brush.radius, brush.voxel_type);
+  }
+}
+
+pub fn update_brush_preview(
+  registry: Res<VoxelRegistry>,
+  cursor: Res<CursorTerrainHit>,
+  brush: Res<TerrainBrush>,
+  mut materials: ResMut<Assets<StandardMaterial>>,
+  mut previews: Query<
+    (&mut Transform, &mut Visibility, &Handle<StandardMaterial>),
+    With<BrushPreview>,
+  >,
+) {
+  for (mut transform, mut visibility, material) in previews.iter_mut() {
+    visibility.is_visible = cursor.hit.is_some();
+    if let Some(hit) = cursor.hit {
+      *transform = Transform::from_translation(hit.position).with_scale(Vec3::splat(brush.radius));
+    }
+    if brush.is_changed() {
+      if let Some(material) = materials.get_mut(material) {
+        material.base_color = *registry.color(brush.voxel_type).set_a(PREVIEW_ALPHA);
+      }
+    }
+  }
+}
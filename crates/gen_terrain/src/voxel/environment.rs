@@ -0,0 +1,152 @@
+use super::{
+  layout::CubicVoxelLayout, query::TerrainQuery, world::TerrainWorld, ChunkSpawner, ChunkVoxelData,
+  VoxelId,
+};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// voxels above a spawner sampled to tell whether it's underground
+const UNDERGROUND_PROBE_VOXELS: i64 = 16;
+// share of the sampled voxels that have to be solid for a spawner to be underground
+const UNDERGROUND_DENSITY: f32 = 0.25;
+// the cavity flood fill stops here, larger caves are as good as open
+pub const MAX_CAVITY_VOXELS: usize = 4096;
+
+// the surroundings of a spawner, updated every frame e.g. to switch to cave lighting or pull the
+// camera in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Component)]
+pub struct SpawnerEnvironment {
+  // most of the voxels right above the spawner are solid
+  pub underground: bool,
+  // world units below the top of the highest solid voxel of the spawner's column, None while the
+  // column isn't loaded
+  pub depth: Option<f32>,
+  // empty voxels connected to the spawner's voxel, up to `MAX_CAVITY_VOXELS`
+  // spawners on the surface or next to chunks that aren't loaded always get the maximum
+  pub cavity_voxels: usize,
+}
+
+impl SpawnerEnvironment {
+  // the spawner isn't shut in by a small cave
+  pub fn is_open(&self) -> bool {
+    self.cavity_voxels >= MAX_CAVITY_VOXELS
+  }
+}
+
+// `TerrainQuery` only sees the primary world, spawners of other worlds don't get an environment
+// the cavity is only flood filled again once the spawner moves to another voxel or voxels change
+#[allow(clippy::type_complexity)]
+pub fn update_spawner_environments(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  terrain: TerrainQuery,
+  changed_chunks: Query<(), Changed<ChunkVoxelData>>,
+  unloaded_chunks: RemovedComponents<ChunkVoxelData>,
+  // the voxel each spawner's cavity was last filled from
+  mut cavity_starts: Local<HashMap<Entity, VoxelId>>,
+  mut spawners: Query<
+    (
+      Entity,
+      &Transform,
+      Option<&TerrainWorld>,
+      Option<&mut SpawnerEnvironment>,
+    ),
+    With<ChunkSpawner>,
+  >,
+) {
+  let voxels_changed =
+    changed_chunks.iter().next().is_some() || unloaded_chunks.iter().next().is_some();
+  let mut seen = HashSet::new();
+  for (entity, transform, world, environment) in spawners.iter_mut() {
+    seen.insert(entity);
+    if !world.copied().unwrap_or_default().is_primary() {
+      continue;
+    }
+    let position = transform.translation;
+    let underground = is_underground(&layout, &terrain, position);
+    let start = layout.space_to_voxel(&position);
+    let cached = match (&environment, cavity_starts.get(&entity)) {
+      (Some(environment), Some(filled_from)) if *filled_from == start && !voxels_changed => {
+        Some(environment.cavity_voxels)
+      }
+      _ => None,
+    };
+    let cavity_voxels = match (underground, cached) {
+      (false, _) => {
+        cavity_starts.remove(&entity);
+        MAX_CAVITY_VOXELS
+      }
+      (true, Some(cached)) => cached,
+      (true, None) => {
+        cavity_starts.insert(entity, start);
+        cavity_voxels(&layout, &terrain, start)
+      }
+    };
+    let updated = SpawnerEnvironment {
+      underground,
+      depth: terrain
+        .surface_height(position.x, position.z)
+        .map(|surface| (surface - position.y).max(0.)),
+      cavity_voxels,
+    };
+
+    match environment {
+      // only touched when something changed so `Changed<SpawnerEnvironment>` is meaningful
+      Some(mut environment) => {
+        if *environment != updated {
+          *environment = updated;
+        }
+      }
+      None => {
+        commands.entity(entity).insert(updated);
+      }
+    }
+  }
+  cavity_starts.retain(|entity, _| seen.contains(entity));
+}
+
+// voxels of chunks that aren't loaded yet don't count either way
+fn is_underground(layout: &CubicVoxelLayout, terrain: &TerrainQuery, position: Vec3) -> bool {
+  let step = Vec3::Y * layout.voxel_side_length();
+  let (solid, sampled) = (1..=UNDERGROUND_PROBE_VOXELS)
+    .filter_map(|i| terrain.is_solid(position + step * i as f32))
+    .fold((0, 0), |(solid, sampled), is_solid| {
+      (solid + is_solid as usize, sampled + 1)
+    });
+  sampled > 0 && solid as f32 >= sampled as f32 * UNDERGROUND_DENSITY
+}
+
+// flood fills the empty voxels connected to `start`, reaching the sky or a chunk that isn't
+// loaded counts as open
+fn cavity_voxels(layout: &CubicVoxelLayout, terrain: &TerrainQuery, start: VoxelId) -> usize {
+  let neighbors = [
+    VoxelId::new(1, 0, 0),
+    VoxelId::new(-1, 0, 0),
+    VoxelId::new(0, 1, 0),
+    VoxelId::new(0, -1, 0),
+    VoxelId::new(0, 0, 1),
+    VoxelId::new(0, 0, -1),
+  ];
+  let mut visited = HashSet::from([start]);
+  let mut open = VecDeque::from([start]);
+  let mut cavity = 0;
+  while let Some(voxel) = open.pop_front() {
+    if voxel.y() >= layout.world_voxel_height() {
+      return MAX_CAVITY_VOXELS;
+    }
+    match terrain.is_voxel_solid(&voxel) {
+      Some(true) => continue,
+      Some(false) => cavity += 1,
+      None => return MAX_CAVITY_VOXELS,
+    }
+    if cavity >= MAX_CAVITY_VOXELS {
+      return MAX_CAVITY_VOXELS;
+    }
+    for neighbor in neighbors.iter().map(|offset| voxel + *offset) {
+      if visited.insert(neighbor) {
+        open.push_back(neighbor);
+      }
+    }
+  }
+  cavity
+}
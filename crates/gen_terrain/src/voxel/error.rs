@@ -0,0 +1,103 @@
+#[cfg(feature = "render")]
+use super::snapshot::SnapshotError;
+use super::{generator::WorldGenConfig, layout::CubicVoxelLayout};
+use bevy::prelude::*;
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum TerrainError {
+  // the layout can't tile the world with its dimensions
+  InvalidLayout(&'static str),
+  // the generator config would produce garbage, e.g. a scale of 0
+  InvalidConfig(&'static str),
+  // the terrain material config can't be drawn, e.g. fog that ends before it starts
+  InvalidMaterial(&'static str),
+  // the spawner is marked fresh but hasn't loaded a chunk
+  SpawnerNotLoaded(Entity),
+  Io(io::Error),
+  #[cfg(feature = "render")]
+  Snapshot(SnapshotError),
+}
+impl fmt::Display for TerrainError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TerrainError::InvalidLayout(reason) => write!(f, "invalid layout: {}", reason),
+      TerrainError::InvalidConfig(reason) => write!(f, "invalid world gen config: {}", reason),
+      TerrainError::InvalidMaterial(reason) => write!(f, "invalid terrain material: {}", reason),
+      TerrainError::SpawnerNotLoaded(spawner) => {
+        write!(f, "spawner {:?} hasn't loaded a chunk", spawner)
+      }
+      TerrainError::Io(err) => write!(f, "{}", err),
+      #[cfg(feature = "render")]
+      TerrainError::Snapshot(err) => write!(f, "{}", err),
+    }
+  }
+}
+impl std::error::Error for TerrainError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      TerrainError::Io(err) => Some(err),
+      #[cfg(feature = "render")]
+      TerrainError::Snapshot(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+impl From<io::Error> for TerrainError {
+  fn from(err: io::Error) -> Self {
+    TerrainError::Io(err)
+  }
+}
+#[cfg(feature = "render")]
+impl From<SnapshotError> for TerrainError {
+  fn from(err: SnapshotError) -> Self {
+    TerrainError::Snapshot(err)
+  }
+}
+
+// sent by terrain systems instead of panicking, they carry on without whatever failed
+// the terrain plugin logs these, games can read them as well to recover, e.g. by restoring a
+// backup of a region that couldn't be read
+#[derive(Debug)]
+pub struct TerrainErrorEvent {
+  pub error: TerrainError,
+  // what the system was doing, e.g. "loading the region of ChunkId(1, 0, 2)"
+  pub context: String,
+}
+impl TerrainErrorEvent {
+  pub fn new(error: impl Into<TerrainError>, context: impl Into<String>) -> Self {
+    Self {
+      error: error.into(),
+      context: context.into(),
+    }
+  }
+}
+
+// checks the layout and config whenever they're replaced
+#[cfg(feature = "render")]
+pub fn validate_terrain_settings(
+  layout: Res<CubicVoxelLayout>,
+  config: Res<WorldGenConfig>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  if layout.is_changed() {
+    if let Err(err) = layout.validate() {
+      errors.send(TerrainErrorEvent::new(err, "validating the voxel layout"));
+    }
+  }
+  if config.is_changed() {
+    if let Err(err) = config.validate() {
+      errors.send(TerrainErrorEvent::new(
+        err,
+        "validating the world gen config",
+      ));
+    }
+  }
+}
+
+#[cfg(feature = "render")]
+pub fn log_terrain_errors(mut errors: EventReader<TerrainErrorEvent>) {
+  for event in errors.iter() {
+    error!("failed {}: {}", event.context, event.error);
+  }
+}
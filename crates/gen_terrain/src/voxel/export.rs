@@ -0,0 +1,413 @@
+use super::{
+  material::ATTRIBUTE_VOXEL_TYPE,
+  registry::{VoxelRegistry, VoxelTypeId},
+  Chunk, TerrainMaterialConfig,
+};
+use bevy::{
+  prelude::*,
+  render::mesh::{Indices, VertexAttributeValues},
+  tasks::IoTaskPool,
+};
+use std::{
+  collections::HashMap,
+  fmt::Write as _,
+  fs, io,
+  path::{Path, PathBuf},
+};
+
+// writes the meshes of all loaded chunks merged into one file, e.g. to inspect the terrain in
+// blender or to use it as static level geometry
+// the format is picked from the extension, `.obj` (with a `.mtl` next to it) or `.gltf` (with a
+// `.bin` next to it)
+#[derive(Debug, Clone)]
+pub struct ExportWorldMesh(pub PathBuf);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+  Obj,
+  Gltf,
+}
+impl ExportFormat {
+  pub fn from_path(path: &Path) -> Option<Self> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+      "obj" => Some(ExportFormat::Obj),
+      "gltf" => Some(ExportFormat::Gltf),
+      _ => None,
+    }
+  }
+}
+
+// world-space geometry of all chunk triangles sharing a color
+#[derive(Debug, Default, Clone)]
+pub struct MeshGroup {
+  pub base_color: [f32; 4],
+  pub positions: Vec<[f32; 3]>,
+  pub normals: Vec<[f32; 3]>,
+  pub uvs: Vec<[f32; 2]>,
+  pub indices: Vec<u32>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MergedMesh {
+  pub groups: Vec<MeshGroup>,
+}
+
+impl MergedMesh {
+  // adds a triangle list mesh placed with `transform`, meshes without positions are skipped
+  // triangles are colored by `color_of` the voxel type of their first vertex, `None` for meshes
+  // without voxel types
+  pub fn add(
+    &mut self,
+    mesh: &Mesh,
+    transform: &GlobalTransform,
+    color_of: impl Fn(Option<VoxelTypeId>) -> [f32; 4],
+  ) {
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+      Some(VertexAttributeValues::Float32x3(positions)) => positions,
+      _ => return,
+    };
+    let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+      Some(VertexAttributeValues::Float32x3(normals)) => normals.clone(),
+      _ => vec![[0., 1., 0.]; positions.len()],
+    };
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+      Some(VertexAttributeValues::Float32x2(uvs)) => uvs.clone(),
+      _ => vec![[0., 0.]; positions.len()],
+    };
+    let voxel_types = match mesh.attribute(ATTRIBUTE_VOXEL_TYPE) {
+      Some(VertexAttributeValues::Uint32(voxel_types)) => Some(voxel_types),
+      _ => None,
+    };
+    let indices: Vec<u32> = match mesh.indices() {
+      Some(Indices::U32(indices)) => indices.clone(),
+      Some(Indices::U16(indices)) => indices.iter().map(|i| *i as u32).collect(),
+      None => (0..positions.len() as u32).collect(),
+    };
+    let matrix = transform.compute_matrix();
+
+    // vertices are copied into each group that uses them
+    let mut remapped: HashMap<(usize, u32), u32> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+      let voxel_type = voxel_types
+        .and_then(|voxel_types| voxel_types.get(triangle[0] as usize))
+        .map(|id| VoxelTypeId(*id as u16));
+      let group_index = self.group_index(color_of(voxel_type));
+      let group = &mut self.groups[group_index];
+
+      for index in triangle {
+        let new_index = *remapped.entry((group_index, *index)).or_insert_with(|| {
+          let i = *index as usize;
+          group
+            .positions
+            .push(matrix.transform_point3(Vec3::from(positions[i])).to_array());
+          group.normals.push(
+            matrix
+              .transform_vector3(Vec3::from(normals[i]))
+              .normalize_or_zero()
+              .to_array(),
+          );
+          group.uvs.push(uvs[i]);
+          group.positions.len() as u32 - 1
+        });
+        group.indices.push(new_index);
+      }
+    }
+  }
+
+  // identical colors end up in the same group even if they're from different meshes
+  fn group_index(&mut self, base_color: [f32; 4]) -> usize {
+    match self
+      .groups
+      .iter()
+      .position(|group| group.base_color == base_color)
+    {
+      Some(index) => index,
+      None => {
+        self.groups.push(MeshGroup {
+          base_color,
+          ..default()
+        });
+        self.groups.len() - 1
+      }
+    }
+  }
+
+  pub fn vertex_count(&self) -> usize {
+    self.groups.iter().map(|group| group.positions.len()).sum()
+  }
+
+  pub fn triangle_count(&self) -> usize {
+    self
+      .groups
+      .iter()
+      .map(|group| group.indices.len() / 3)
+      .sum()
+  }
+
+  // returns the obj and the mtl it references by `mtl_name`
+  pub fn to_obj(&self, mtl_name: &str) -> (String, String) {
+    let mut obj = format!("mtllib {}\n", mtl_name);
+    let mut mtl = String::new();
+    // obj indices are 1-based and global across groups
+    let mut offset = 1;
+
+    for (i, group) in self.groups.iter().enumerate() {
+      let [r, g, b, a] = group.base_color;
+      let _ = writeln!(mtl, "newmtl terrain_{}\nKd {} {} {}\nd {}", i, r, g, b, a);
+
+      let _ = writeln!(obj, "o terrain_{}\nusemtl terrain_{}", i, i);
+      for [x, y, z] in group.positions.iter() {
+        let _ = writeln!(obj, "v {} {} {}", x, y, z);
+      }
+      for [x, y, z] in group.normals.iter() {
+        let _ = writeln!(obj, "vn {} {} {}", x, y, z);
+      }
+      for [u, v] in group.uvs.iter() {
+        // obj uvs start at the bottom left
+        let _ = writeln!(obj, "vt {} {}", u, 1. - v);
+      }
+      for triangle in group.indices.chunks_exact(3) {
+        let _ = write!(obj, "f");
+        for index in triangle {
+          let i = index + offset;
+          let _ = write!(obj, " {}/{}/{}", i, i, i);
+        }
+        let _ = writeln!(obj);
+      }
+      offset += group.positions.len() as u32;
+    }
+    (obj, mtl)
+  }
+
+  // returns the gltf json and the binary buffer it references by `bin_name`
+  // every group becomes a primitive of a single mesh, without triangles there's an empty scene and
+  // no buffer since gltf doesn't allow empty meshes or buffers
+  pub fn to_gltf(&self, bin_name: &str) -> (String, Vec<u8>) {
+    let mut buffer = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut primitives = Vec::new();
+    let mut materials = Vec::new();
+
+    // appends a buffer view and its accessor, returning the accessor index
+    let mut push = |bytes: &[u8], target: u32, accessor: String| {
+      let offset = buffer.len();
+      buffer.extend_from_slice(bytes);
+      // views are kept 4-byte aligned
+      while buffer.len() % 4 != 0 {
+        buffer.push(0);
+      }
+      views.push(format!(
+        r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+        offset,
+        bytes.len(),
+        target
+      ));
+      accessors.push(accessor.replace("VIEW", &(views.len() - 1).to_string()));
+      accessors.len() - 1
+    };
+
+    for (i, group) in self.groups.iter().enumerate() {
+      if group.positions.is_empty() || group.indices.is_empty() {
+        continue;
+      }
+      let (min, max) = group.positions.iter().fold(
+        (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+        |(min, max), p| (min.min(Vec3::from(*p)), max.max(Vec3::from(*p))),
+      );
+      let count = group.positions.len();
+
+      let position = push(
+        &f32_bytes(group.positions.iter().flatten()),
+        34962,
+        format!(
+          r#"{{"bufferView":VIEW,"componentType":5126,"count":{},"type":"VEC3","min":[{},{},{}],"max":[{},{},{}]}}"#,
+          count, min.x, min.y, min.z, max.x, max.y, max.z
+        ),
+      );
+      let normal = push(
+        &f32_bytes(group.normals.iter().flatten()),
+        34962,
+        format!(
+          r#"{{"bufferView":VIEW,"componentType":5126,"count":{},"type":"VEC3"}}"#,
+          count
+        ),
+      );
+      let uv = push(
+        &f32_bytes(group.uvs.iter().flatten()),
+        34962,
+        format!(
+          r#"{{"bufferView":VIEW,"componentType":5126,"count":{},"type":"VEC2"}}"#,
+          count
+        ),
+      );
+      let indices: Vec<u8> = group.indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+      let index = push(
+        &indices,
+        34963,
+        format!(
+          r#"{{"bufferView":VIEW,"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+          group.indices.len()
+        ),
+      );
+
+      let [r, g, b, a] = group.base_color;
+      materials.push(format!(
+        r#"{{"name":"terrain_{}","pbrMetallicRoughness":{{"baseColorFactor":[{},{},{},{}],"metallicFactor":0}}}}"#,
+        i, r, g, b, a
+      ));
+      primitives.push(format!(
+        r#"{{"attributes":{{"POSITION":{},"NORMAL":{},"TEXCOORD_0":{}}},"indices":{},"material":{}}}"#,
+        position,
+        normal,
+        uv,
+        index,
+        materials.len() - 1
+      ));
+    }
+
+    let asset = r#""asset":{"version":"2.0","generator":"gen_terrain"},"scene":0"#;
+    if primitives.is_empty() {
+      return (format!(r#"{{{},"scenes":[{{}}]}}"#, asset), buffer);
+    }
+    let json = format!(
+      r#"{{{},"scenes":[{{"nodes":[0]}}],"nodes":[{{"name":"terrain","mesh":0}}],"meshes":[{{"primitives":[{}]}}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"uri":"{}","byteLength":{}}}]}}"#,
+      asset,
+      primitives.join(","),
+      materials.join(","),
+      accessors.join(","),
+      views.join(","),
+      bin_name,
+      buffer.len()
+    );
+    (json, buffer)
+  }
+
+  pub fn write(&self, path: &Path) -> io::Result<()> {
+    let format = ExportFormat::from_path(path).ok_or_else(|| {
+      io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("can't export to {:?}, expected .obj or .gltf", path),
+      )
+    })?;
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+
+    // the companion file sits next to the main one and is referenced by file name
+    let (extension, companion) = match format {
+      ExportFormat::Obj => ("mtl", path.with_extension("mtl")),
+      ExportFormat::Gltf => ("bin", path.with_extension("bin")),
+    };
+    let companion_name = companion
+      .file_name()
+      .and_then(|name| name.to_str())
+      .map(String::from)
+      .unwrap_or_else(|| format!("terrain.{}", extension));
+
+    match format {
+      ExportFormat::Obj => {
+        let (obj, mtl) = self.to_obj(&companion_name);
+        fs::write(&companion, mtl)?;
+        fs::write(path, obj)
+      }
+      ExportFormat::Gltf => {
+        let (json, bin) = self.to_gltf(&companion_name);
+        if !bin.is_empty() {
+          fs::write(&companion, bin)?;
+        }
+        fs::write(path, json)
+      }
+    }
+  }
+}
+
+fn f32_bytes<'a>(values: impl Iterator<Item = &'a f32>) -> Vec<u8> {
+  values.flat_map(|v| v.to_le_bytes()).collect()
+}
+
+// geometry is gathered on the main thread, the files are written in the background
+pub fn export_world_mesh(
+  mut events: EventReader<ExportWorldMesh>,
+  io_pool: Res<IoTaskPool>,
+  meshes: Res<Assets<Mesh>>,
+  material_config: Res<TerrainMaterialConfig>,
+  registry: Res<VoxelRegistry>,
+  chunks: Query<(&Handle<Mesh>, &GlobalTransform), With<Chunk>>,
+) {
+  for ExportWorldMesh(path) in events.iter() {
+    let mut merged = MergedMesh::default();
+    for (mesh, transform) in chunks.iter() {
+      let mesh = match meshes.get(mesh) {
+        Some(mesh) => mesh,
+        None => continue,
+      };
+      // the colors the terrain material would show, without lighting
+      merged.add(mesh, transform, |voxel_type| {
+        material_config
+          .color(&registry, voxel_type.unwrap_or(VoxelTypeId::DIRT))
+          .as_linear_rgba_f32()
+      });
+    }
+    info!(
+      "exporting {} vertices and {} triangles to {:?}",
+      merged.vertex_count(),
+      merged.triangle_count(),
+      path
+    );
+
+    let path = path.clone();
+    io_pool
+      .spawn(async move {
+        if let Err(err) = merged.write(&path) {
+          error!("failed to export terrain to {:?}: {}", path, err);
+        }
+      })
+      .detach();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bevy::render::render_resource::PrimitiveTopology;
+  use proptest::prelude::*;
+
+  fn quad() -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+      Mesh::ATTRIBUTE_POSITION,
+      vec![[0., 0., 0.], [1., 0., 0.], [1., 0., 1.], [0., 0., 1.]],
+    );
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0., 1., 0.]; 4]);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, vec![[0., 0.]; 4]);
+    mesh.set_indices(Some(Indices::U32(vec![0, 2, 1, 0, 3, 2])));
+    mesh
+  }
+
+  proptest! {
+      #[test]
+      fn merged_chunks_should_keep_their_geometry(offsets in prop::collection::vec((-100f32..100., -100f32..100., any::<bool>()), 0..20)) {
+          let mut merged = MergedMesh::default();
+          for (x, z, red) in offsets.iter() {
+              let color = if *red { [1., 0., 0., 1.] } else { [1., 1., 1., 1.] };
+              merged.add(&quad(), &GlobalTransform::from_xyz(*x, 0., *z), |_| color);
+          }
+
+          assert_eq!(merged.vertex_count(), 4 * offsets.len());
+          assert_eq!(merged.triangle_count(), 2 * offsets.len());
+          for group in merged.groups.iter() {
+              assert!(group.indices.iter().all(|i| (*i as usize) < group.positions.len()));
+          }
+
+          let (obj, _) = merged.to_obj("terrain.mtl");
+          assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), 2 * offsets.len());
+          let (json, bin) = merged.to_gltf("terrain.bin");
+          // positions, normals, uvs and indices
+          assert_eq!(bin.len(), offsets.len() * 4 * (12 + 12 + 8) + offsets.len() * 6 * 4);
+          // empty meshes and buffers aren't valid gltf
+          assert_eq!(json.contains("primitives"), !offsets.is_empty());
+          assert!(!json.contains(r#""byteLength":0"#));
+      }
+  }
+}
@@ -0,0 +1,357 @@
+use super::{
+  layout::CubicVoxelLayout, material::TerrainMaterialConfig, registry::VoxelRegistry, terrain_set,
+  Chunk, ChunkVoxelData, TerrainSystem,
+};
+use bevy::{
+  core_pipeline::Opaque3d,
+  ecs::system::{lifetimeless::*, SystemParamItem},
+  pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup},
+  prelude::*,
+  reflect::TypeUuid,
+  render::{
+    mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+    render_asset::RenderAssets,
+    render_component::{ExtractComponent, ExtractComponentPlugin},
+    render_phase::{
+      AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase,
+      SetItemPipeline, TrackedRenderPass,
+    },
+    render_resource::*,
+    renderer::RenderDevice,
+    view::{ExtractedView, NoFrustumCulling},
+    RenderApp, RenderStage,
+  },
+};
+use bytemuck::{Pod, Zeroable};
+
+pub const FAR_CHUNK_SHADER_HANDLE: HandleUntyped =
+  HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5d1e_9a3c_4b27_f081);
+
+// chunks farther than `distance` world units from every spawner aren't meshed, they're drawn as a single
+// instanced tile at their surface height instead
+pub struct FarChunkSettings {
+  pub distance: f32,
+  pub color: Color,
+}
+impl Default for FarChunkSettings {
+  fn default() -> Self {
+    Self {
+      distance: 250.0,
+      color: Color::rgb(0.4, 0.1, 0.3),
+    }
+  }
+}
+
+// marks a chunk that is rendered as a far tile
+#[derive(Debug, Clone, Copy, Component)]
+pub struct FarChunk {
+  pub height: f32,
+}
+
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct FarChunkInstance {
+  // xyz is the tile center, w the tile side length
+  pub position_scale: [f32; 4],
+  pub color: [f32; 4],
+}
+
+// per-instance data for the shared far tile mesh
+#[derive(Debug, Default, Clone, Component)]
+pub struct FarChunkInstances(pub Vec<FarChunkInstance>);
+impl ExtractComponent for FarChunkInstances {
+  type Query = &'static FarChunkInstances;
+  type Filter = ();
+
+  fn extract_component(item: bevy::ecs::query::QueryItem<Self::Query>) -> Self {
+    item.clone()
+  }
+}
+
+pub struct FarChunkPlugin;
+
+impl Plugin for FarChunkPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<FarChunkSettings>()
+      .add_startup_system(setup_far_chunk_tiles)
+      .add_system_set(
+        terrain_set(TerrainSystem::Track)
+          .after(TerrainSystem::Apply)
+          .with_system(assign_far_chunks)
+          .with_system(update_far_chunk_instances),
+      );
+
+    // without a renderer (headless apps) far chunks are just left unmeshed
+    if let Some(mut shaders) = app.world.get_resource_mut::<Assets<Shader>>() {
+      shaders.set_untracked(
+        FAR_CHUNK_SHADER_HANDLE,
+        Shader::from_wgsl(include_str!("far_chunks.wgsl")),
+      );
+    } else {
+      return;
+    }
+
+    app.add_plugin(ExtractComponentPlugin::<FarChunkInstances>::default());
+    if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+      render_app
+        .add_render_command::<Opaque3d, DrawFarChunks>()
+        .init_resource::<FarChunkPipeline>()
+        .init_resource::<SpecializedMeshPipelines<FarChunkPipeline>>()
+        .add_system_to_stage(RenderStage::Queue, queue_far_chunks)
+        .add_system_to_stage(RenderStage::Prepare, prepare_far_chunk_buffers);
+    }
+  }
+}
+
+fn setup_far_chunk_tiles(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+  commands.spawn().insert_bundle((
+    meshes.add(Mesh::from(shape::Plane { size: 1.0 })),
+    Transform::default(),
+    GlobalTransform::default(),
+    FarChunkInstances::default(),
+    Visibility::default(),
+    ComputedVisibility::default(),
+    // instances are spread over the whole world, the tile mesh aabb means nothing
+    NoFrustumCulling,
+  ));
+}
+
+#[allow(clippy::type_complexity)]
+pub fn assign_far_chunks(
+  mut commands: Commands,
+  settings: Res<FarChunkSettings>,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  chunks: Query<(Entity, &Chunk, Option<&ChunkVoxelData>, Option<&FarChunk>)>,
+) {
+  for (entity, chunk, voxel_data, far_chunk) in chunks.iter() {
+    let far = chunk.distance_to_nearest_spawner > settings.distance;
+
+    match (far, far_chunk, voxel_data) {
+      (true, None, Some(voxel_data)) => {
+        let top = voxel_data
+          .voxels
+          .iter()
+          .filter(|(_, voxel_type)| registry.is_solid(**voxel_type))
+          .map(|(voxel, _)| voxel.y() + 1)
+          .max()
+          .unwrap_or(0);
+        let height = top as f32 * layout.voxel_side_length();
+        commands.entity(entity).insert(FarChunk { height });
+      }
+      (false, Some(_), _) => {
+        commands.entity(entity).remove::<FarChunk>();
+      }
+      _ => {}
+    }
+  }
+}
+
+pub fn update_far_chunk_instances(
+  settings: Res<FarChunkSettings>,
+  material: Res<TerrainMaterialConfig>,
+  layout: Res<CubicVoxelLayout>,
+  far_chunks: Query<(&Chunk, &FarChunk)>,
+  mut tiles: Query<&mut FarChunkInstances>,
+) {
+  let instances: Vec<_> = far_chunks
+    .iter()
+    .map(|(chunk, far_chunk)| {
+      let center = layout.voxel_center(&layout.get_center_voxel(&chunk.id));
+      // tiles are faded as a whole, the spawner stands in for the camera
+      // blended in linear color like the fog of the chunk material
+      let color = match material.fog.filter(|fog| fog.validate().is_ok()) {
+        Some(fog) => {
+          let amount = fog.amount(chunk.distance_to_nearest_spawner);
+          let [r, g, b, a] = settings.color.as_linear_rgba_f32();
+          let [fr, fg, fb, _] = fog.color.as_linear_rgba_f32();
+          [
+            r + (fr - r) * amount,
+            g + (fg - g) * amount,
+            b + (fb - b) * amount,
+            a,
+          ]
+        }
+        None => settings.color.as_linear_rgba_f32(),
+      };
+      FarChunkInstance {
+        position_scale: [
+          center.x,
+          far_chunk.height,
+          center.z,
+          layout.chunk_side_length(),
+        ],
+        color,
+      }
+    })
+    .collect();
+
+  for mut tile in tiles.iter_mut() {
+    tile.0 = instances.clone();
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_far_chunks(
+  opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
+  far_chunk_pipeline: Res<FarChunkPipeline>,
+  msaa: Res<Msaa>,
+  mut pipelines: ResMut<SpecializedMeshPipelines<FarChunkPipeline>>,
+  mut pipeline_cache: ResMut<PipelineCache>,
+  meshes: Res<RenderAssets<Mesh>>,
+  tiles: Query<(Entity, &MeshUniform, &Handle<Mesh>), With<FarChunkInstances>>,
+  mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>)>,
+) {
+  let draw_far_chunks = opaque_3d_draw_functions
+    .read()
+    .get_id::<DrawFarChunks>()
+    .unwrap();
+
+  let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples);
+
+  for (view, mut opaque_phase) in views.iter_mut() {
+    let view_row_2 = view.transform.compute_matrix().row(2);
+    for (entity, mesh_uniform, mesh_handle) in tiles.iter() {
+      if let Some(mesh) = meshes.get(mesh_handle) {
+        let key = msaa_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+        let pipeline = pipelines
+          .specialize(&mut pipeline_cache, &far_chunk_pipeline, key, &mesh.layout)
+          .unwrap();
+        opaque_phase.add(Opaque3d {
+          entity,
+          pipeline,
+          draw_function: draw_far_chunks,
+          distance: view_row_2.dot(mesh_uniform.transform.col(3)),
+        });
+      }
+    }
+  }
+}
+
+#[derive(Component)]
+pub struct FarChunkBuffer {
+  buffer: Buffer,
+  length: usize,
+}
+
+fn prepare_far_chunk_buffers(
+  mut commands: Commands,
+  query: Query<(Entity, &FarChunkInstances)>,
+  render_device: Res<RenderDevice>,
+) {
+  for (entity, instances) in query.iter() {
+    let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+      label: Some("far chunk instance buffer"),
+      contents: bytemuck::cast_slice(instances.0.as_slice()),
+      usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+    });
+    commands.entity(entity).insert(FarChunkBuffer {
+      buffer,
+      length: instances.0.len(),
+    });
+  }
+}
+
+pub struct FarChunkPipeline {
+  mesh_pipeline: MeshPipeline,
+}
+
+impl FromWorld for FarChunkPipeline {
+  fn from_world(world: &mut World) -> Self {
+    let mesh_pipeline = world.get_resource::<MeshPipeline>().unwrap();
+    FarChunkPipeline {
+      mesh_pipeline: mesh_pipeline.clone(),
+    }
+  }
+}
+
+impl SpecializedMeshPipeline for FarChunkPipeline {
+  type Key = MeshPipelineKey;
+
+  fn specialize(
+    &self,
+    key: Self::Key,
+    layout: &MeshVertexBufferLayout,
+  ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+    let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+    descriptor.vertex.shader = FAR_CHUNK_SHADER_HANDLE.typed::<Shader>();
+    descriptor.vertex.buffers.push(VertexBufferLayout {
+      array_stride: std::mem::size_of::<FarChunkInstance>() as u64,
+      step_mode: VertexStepMode::Instance,
+      attributes: vec![
+        // locations 0-2 are taken by the position, normal and uv attributes
+        VertexAttribute {
+          format: VertexFormat::Float32x4,
+          offset: 0,
+          shader_location: 3,
+        },
+        VertexAttribute {
+          format: VertexFormat::Float32x4,
+          offset: VertexFormat::Float32x4.size(),
+          shader_location: 4,
+        },
+      ],
+    });
+    descriptor.fragment.as_mut().unwrap().shader = FAR_CHUNK_SHADER_HANDLE.typed::<Shader>();
+    descriptor.layout = Some(vec![
+      self.mesh_pipeline.view_layout.clone(),
+      self.mesh_pipeline.mesh_layout.clone(),
+    ]);
+
+    Ok(descriptor)
+  }
+}
+
+type DrawFarChunks = (
+  SetItemPipeline,
+  SetMeshViewBindGroup<0>,
+  SetMeshBindGroup<1>,
+  DrawFarChunkTiles,
+);
+
+pub struct DrawFarChunkTiles;
+impl EntityRenderCommand for DrawFarChunkTiles {
+  type Param = (
+    SRes<RenderAssets<Mesh>>,
+    SQuery<Read<Handle<Mesh>>>,
+    SQuery<Read<FarChunkBuffer>>,
+  );
+
+  #[inline]
+  fn render<'w>(
+    _view: Entity,
+    item: Entity,
+    (meshes, mesh_query, buffer_query): SystemParamItem<'w, '_, Self::Param>,
+    pass: &mut TrackedRenderPass<'w>,
+  ) -> RenderCommandResult {
+    let mesh_handle = mesh_query.get(item).unwrap();
+    let instance_buffer = match buffer_query.get_inner(item) {
+      Ok(buffer) => buffer,
+      Err(_) => return RenderCommandResult::Failure,
+    };
+
+    let gpu_mesh = match meshes.into_inner().get(mesh_handle) {
+      Some(gpu_mesh) => gpu_mesh,
+      None => return RenderCommandResult::Failure,
+    };
+
+    pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+    pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+
+    match &gpu_mesh.buffer_info {
+      GpuBufferInfo::Indexed {
+        buffer,
+        index_format,
+        count,
+      } => {
+        pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+        pass.draw_indexed(0..*count, 0, 0..instance_buffer.length as u32);
+      }
+      GpuBufferInfo::NonIndexed { vertex_count } => {
+        pass.draw(0..*vertex_count, 0..instance_buffer.length as u32);
+      }
+    }
+    RenderCommandResult::Success
+  }
+}
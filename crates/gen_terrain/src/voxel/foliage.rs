@@ -0,0 +1,618 @@
+use super::{
+  biome::{Biome, ChunkBiome},
+  decoration::surface_voxels,
+  layout::CubicVoxelLayout,
+  lod::ChunkLod,
+  registry::{VoxelRegistry, VoxelTypeId},
+  seed::{ChunkRng, ChunkSeed},
+  terrain_set, Chunk, ChunkId, ChunkVoxelData, TerrainSystem, VoxelId,
+};
+use bevy::{
+  core_pipeline::Opaque3d,
+  ecs::system::{lifetimeless::*, SystemParamItem},
+  pbr::{MeshPipeline, MeshPipelineKey, MeshUniform, SetMeshBindGroup, SetMeshViewBindGroup},
+  prelude::*,
+  reflect::TypeUuid,
+  render::{
+    mesh::{GpuBufferInfo, Indices, MeshVertexBufferLayout},
+    render_asset::RenderAssets,
+    render_phase::{
+      AddRenderCommand, DrawFunctions, EntityRenderCommand, RenderCommandResult, RenderPhase,
+      SetItemPipeline, TrackedRenderPass,
+    },
+    render_resource::*,
+    renderer::{RenderDevice, RenderQueue},
+    view::{ExtractedView, NoFrustumCulling},
+    RenderApp, RenderStage,
+  },
+};
+use bytemuck::{Pod, Zeroable};
+use std::collections::HashMap;
+
+pub const FOLIAGE_SHADER_HANDLE: HandleUntyped =
+  HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x9e27_c4d0_15ab_6f38);
+
+// the blade every foliage instance draws, one unit tall with its base at the origin
+pub const FOLIAGE_BLADE_HANDLE: HandleUntyped =
+  HandleUntyped::weak_from_u64(Mesh::TYPE_UUID, 0x3b6f_a819_d2c4_7e05);
+
+// grass blades on the surface of meshed chunks, drawn instanced in one call per chunk and swayed
+// by the wind in the vertex shader
+pub struct FoliageSettings {
+  // chance of a surface voxel growing a blade in each biome, biomes without one stay bare
+  pub density: HashMap<Biome, f32>,
+  // voxel types blades grow on
+  pub grows_on: Vec<VoxelTypeId>,
+  // blade heights in voxel side lengths, each blade picks one in between
+  pub min_height: f32,
+  pub max_height: f32,
+  pub color: Color,
+  // chunks with a lower detail than this don't grow foliage
+  pub max_lod: u8,
+  // direction the wind blows on the ground plane
+  pub wind_direction: Vec2,
+  // how far the tip of a one unit tall blade sways in world units
+  pub wind_strength: f32,
+  // sways per second
+  pub wind_frequency: f32,
+}
+impl Default for FoliageSettings {
+  fn default() -> Self {
+    Self {
+      density: HashMap::from([
+        (Biome::Plains, 0.6),
+        (Biome::Forest, 0.35),
+        (Biome::Tundra, 0.1),
+      ]),
+      grows_on: vec![VoxelTypeId::DIRT],
+      min_height: 0.3,
+      max_height: 0.8,
+      color: Color::rgb(0.35, 0.6, 0.2),
+      max_lod: 0,
+      wind_direction: Vec2::new(1., 0.3),
+      wind_strength: 0.15,
+      wind_frequency: 0.5,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Pod, Zeroable)]
+#[repr(C)]
+pub struct FoliageInstance {
+  // xyz is the base of the blade relative to its chunk, w the blade height
+  pub position_height: [f32; 4],
+  // w offsets the sway so neighboring blades don't move in lockstep
+  pub color_phase: [f32; 4],
+}
+
+// the blades of a chunk, on a child entity of the chunk so they're despawned with it
+#[derive(Debug, Default, Clone, Component)]
+pub struct FoliageInstances(pub Vec<FoliageInstance>);
+
+// the blades of a chunk in the render world, only set in the frames they changed, the buffer made
+// from them is kept until then
+#[derive(Component)]
+pub struct ExtractedFoliage(Option<Vec<FoliageInstance>>);
+
+// the foliage entity of a chunk, `None` if the chunk is bare
+#[derive(Debug, Default, Component)]
+pub struct ChunkFoliage(pub Option<Entity>);
+
+// where blades grow in a chunk, surface voxels of the `grows_on` types each get at most one
+pub fn scatter_foliage(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  settings: &FoliageSettings,
+  density: f32,
+  rng: &mut ChunkRng,
+) -> Vec<FoliageInstance> {
+  if density <= 0. {
+    return Vec::new();
+  }
+  let origin = layout.chunk_to_space(chunk);
+  let side = layout.voxel_side_length();
+  let [r, g, b, _] = settings.color.as_linear_rgba_f32();
+  let (min_height, max_height) = (
+    settings.min_height.min(settings.max_height),
+    settings.max_height.max(settings.min_height),
+  );
+
+  let mut instances = Vec::new();
+  for voxel in surface_voxels(registry, voxels) {
+    // every voxel draws the same numbers whether it grows a blade or not, so density changes
+    // don't move the other blades
+    let roll = rng.next_f32();
+    let offset = Vec3::new(rng.next_f32(), 0., rng.next_f32());
+    let height = min_height + (max_height - min_height) * rng.next_f32();
+    let shade = rng.range_f32(0.85..1.15);
+    let phase = rng.range_f32(0.0..std::f32::consts::TAU);
+    if roll >= density || !settings.grows_on.contains(&voxels[&voxel]) {
+      continue;
+    }
+
+    let base = layout.voxel_to_space(&(voxel + VoxelId::new(0, 1, 0))) - origin + offset * side;
+    instances.push(FoliageInstance {
+      position_height: [base.x, base.y, base.z, height * side],
+      color_phase: [r * shade, g * shade, b * shade, phase],
+    });
+  }
+  instances
+}
+
+pub struct FoliagePlugin;
+
+impl Plugin for FoliagePlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<FoliageSettings>().add_system_set(
+      terrain_set(TerrainSystem::Track)
+        .after(TerrainSystem::Apply)
+        .with_system(grow_chunk_foliage)
+        .with_system(sync_foliage_visibility),
+    );
+
+    // without a renderer (headless apps) the blades are placed but never drawn
+    if let Some(mut shaders) = app.world.get_resource_mut::<Assets<Shader>>() {
+      shaders.set_untracked(
+        FOLIAGE_SHADER_HANDLE,
+        Shader::from_wgsl(include_str!("foliage.wgsl")),
+      );
+    } else {
+      return;
+    }
+    app
+      .world
+      .resource_mut::<Assets<Mesh>>()
+      .set_untracked(FOLIAGE_BLADE_HANDLE, blade_mesh());
+
+    if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+      render_app
+        .add_render_command::<Opaque3d, DrawFoliage>()
+        .init_resource::<FoliagePipeline>()
+        .init_resource::<FoliageBuffers>()
+        .init_resource::<SpecializedMeshPipelines<FoliagePipeline>>()
+        .add_system_to_stage(RenderStage::Extract, extract_foliage)
+        .add_system_to_stage(RenderStage::Extract, extract_foliage_wind)
+        .add_system_to_stage(RenderStage::Prepare, prepare_foliage_buffers)
+        .add_system_to_stage(RenderStage::Prepare, prepare_foliage_wind)
+        .add_system_to_stage(RenderStage::Queue, queue_foliage);
+    }
+  }
+}
+
+// two crossed quads so blades look full from every side
+fn blade_mesh() -> Mesh {
+  let mut positions = Vec::new();
+  let mut normals = Vec::new();
+  let mut uvs = Vec::new();
+  let mut indices = Vec::new();
+  for normal in [Vec3::X, Vec3::Z] {
+    let across = normal.cross(Vec3::Y) * 0.5;
+    let first = positions.len() as u32;
+    for (corner, uv) in [
+      (-across, [0., 1.]),
+      (across, [1., 1.]),
+      (across + Vec3::Y, [1., 0.]),
+      (-across + Vec3::Y, [0., 0.]),
+    ] {
+      positions.push(corner.to_array());
+      normals.push(normal.to_array());
+      uvs.push(uv);
+    }
+    indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+  }
+
+  let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+  mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+  mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+  mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+  mesh.set_indices(Some(Indices::U32(indices)));
+  mesh
+}
+
+// chunks grow foliage once they're meshed and again whenever their voxels or the settings change
+#[allow(clippy::type_complexity)]
+pub fn grow_chunk_foliage(
+  mut commands: Commands,
+  settings: Res<FoliageSettings>,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  chunks: Query<
+    (
+      Entity,
+      &Chunk,
+      &ChunkVoxelData,
+      ChangeTrackers<ChunkVoxelData>,
+      &ChunkSeed,
+      Option<&ChunkBiome>,
+      Option<&ChunkLod>,
+      Option<&ChunkFoliage>,
+    ),
+    With<Handle<Mesh>>,
+  >,
+) {
+  for (entity, chunk, voxel_data, voxels_changed, seed, biome, lod, foliage) in chunks.iter() {
+    let detailed = lod.copied().unwrap_or_default().0 <= settings.max_lod;
+    let existing = foliage.and_then(|foliage| foliage.0);
+    let outdated = match foliage {
+      None => true,
+      Some(_) => settings.is_changed() || voxels_changed.is_changed(),
+    };
+    if !outdated && detailed == existing.is_some() {
+      continue;
+    }
+
+    let density = match detailed {
+      true => {
+        let biome = biome.copied().unwrap_or_default().0;
+        settings.density.get(&biome).copied().unwrap_or(0.)
+      }
+      false => 0.,
+    };
+    let instances = scatter_foliage(
+      &layout,
+      &registry,
+      &chunk.id,
+      &voxel_data.voxels,
+      &settings,
+      density,
+      &mut seed.rng("foliage"),
+    );
+
+    let foliage = match (existing, instances.is_empty()) {
+      (Some(existing), true) => {
+        commands.entity(existing).despawn_recursive();
+        None
+      }
+      (Some(existing), false) => {
+        commands
+          .entity(existing)
+          .insert(FoliageInstances(instances));
+        Some(existing)
+      }
+      (None, true) => None,
+      (None, false) => {
+        let child = commands
+          .spawn_bundle((
+            FOLIAGE_BLADE_HANDLE.typed::<Mesh>(),
+            Transform::default(),
+            GlobalTransform::default(),
+            FoliageInstances(instances),
+            Visibility::default(),
+            ComputedVisibility::default(),
+            // blades cover the whole chunk, the blade mesh aabb means nothing
+            NoFrustumCulling,
+          ))
+          .id();
+        commands.entity(entity).push_children(&[child]);
+        Some(child)
+      }
+    };
+    commands.entity(entity).insert(ChunkFoliage(foliage));
+  }
+}
+
+// visibility isn't inherited, foliage is hidden with its chunk
+pub fn sync_foliage_visibility(
+  chunks: Query<(&Visibility, &ChunkFoliage), Changed<Visibility>>,
+  mut foliage: Query<&mut Visibility, (With<FoliageInstances>, Without<ChunkFoliage>)>,
+) {
+  for (chunk_visibility, chunk_foliage) in chunks.iter() {
+    if let Some(Ok(mut visibility)) = chunk_foliage.0.map(|child| foliage.get_mut(child)) {
+      if visibility.is_visible != chunk_visibility.is_visible {
+        visibility.is_visible = chunk_visibility.is_visible;
+      }
+    }
+  }
+}
+
+// laid out to match `FoliageWind` in foliage.wgsl
+#[derive(Debug, Default, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct FoliageWindUniform {
+  // x and y are the direction scaled by the strength, z is the frequency in radians per second and
+  // w the time in seconds
+  wind: [f32; 4],
+}
+
+fn extract_foliage(
+  mut commands: Commands,
+  foliage: Query<(Entity, &FoliageInstances, ChangeTrackers<FoliageInstances>)>,
+  mut previous_len: Local<usize>,
+) {
+  let mut extracted = Vec::with_capacity(*previous_len);
+  for (entity, instances, tracker) in foliage.iter() {
+    let changed = tracker.is_changed().then(|| instances.0.clone());
+    extracted.push((entity, (ExtractedFoliage(changed),)));
+  }
+  *previous_len = extracted.len();
+  commands.insert_or_spawn_batch(extracted);
+}
+
+fn extract_foliage_wind(mut commands: Commands, time: Res<Time>, settings: Res<FoliageSettings>) {
+  let direction = settings.wind_direction.normalize_or_zero() * settings.wind_strength;
+  commands.insert_resource(FoliageWindUniform {
+    wind: [
+      direction.x,
+      direction.y,
+      settings.wind_frequency * std::f32::consts::TAU,
+      // wraps every hour so the sway doesn't lose precision in long sessions
+      (time.seconds_since_startup() % 3600.) as f32,
+    ],
+  });
+}
+
+fn prepare_foliage_wind(
+  wind: Res<FoliageWindUniform>,
+  pipeline: Res<FoliagePipeline>,
+  render_queue: Res<RenderQueue>,
+) {
+  render_queue.write_buffer(&pipeline.wind_buffer, 0, bytemuck::bytes_of(&*wind));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_foliage(
+  opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
+  foliage_pipeline: Res<FoliagePipeline>,
+  msaa: Res<Msaa>,
+  mut pipelines: ResMut<SpecializedMeshPipelines<FoliagePipeline>>,
+  mut pipeline_cache: ResMut<PipelineCache>,
+  meshes: Res<RenderAssets<Mesh>>,
+  foliage: Query<(Entity, &MeshUniform, &Handle<Mesh>), With<ExtractedFoliage>>,
+  mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>)>,
+) {
+  let draw_foliage = opaque_3d_draw_functions
+    .read()
+    .get_id::<DrawFoliage>()
+    .unwrap();
+
+  let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples);
+
+  for (view, mut opaque_phase) in views.iter_mut() {
+    let view_row_2 = view.transform.compute_matrix().row(2);
+    for (entity, mesh_uniform, mesh_handle) in foliage.iter() {
+      if let Some(mesh) = meshes.get(mesh_handle) {
+        let key = msaa_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
+        let pipeline = pipelines
+          .specialize(&mut pipeline_cache, &foliage_pipeline, key, &mesh.layout)
+          .unwrap();
+        opaque_phase.add(Opaque3d {
+          entity,
+          pipeline,
+          draw_function: draw_foliage,
+          distance: view_row_2.dot(mesh_uniform.transform.col(3)),
+        });
+      }
+    }
+  }
+}
+
+pub struct FoliageBuffer {
+  buffer: Buffer,
+  length: usize,
+}
+
+// render world entities are respawned every frame, so the instance buffers are kept here by the
+// entity of their foliage
+#[derive(Default)]
+pub struct FoliageBuffers(HashMap<Entity, FoliageBuffer>);
+
+fn prepare_foliage_buffers(
+  query: Query<(Entity, &ExtractedFoliage)>,
+  mut buffers: ResMut<FoliageBuffers>,
+  render_device: Res<RenderDevice>,
+) {
+  for (entity, foliage) in query.iter() {
+    if let Some(instances) = &foliage.0 {
+      let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+        label: Some("foliage instance buffer"),
+        contents: bytemuck::cast_slice(instances.as_slice()),
+        usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+      });
+      buffers.0.insert(
+        entity,
+        FoliageBuffer {
+          buffer,
+          length: instances.len(),
+        },
+      );
+    }
+  }
+  // foliage that's despawned isn't extracted anymore
+  buffers.0.retain(|entity, _| query.get(*entity).is_ok());
+}
+
+pub struct FoliagePipeline {
+  mesh_pipeline: MeshPipeline,
+  wind_layout: BindGroupLayout,
+  wind_buffer: Buffer,
+  wind_bind_group: BindGroup,
+}
+
+impl FromWorld for FoliagePipeline {
+  fn from_world(world: &mut World) -> Self {
+    let mesh_pipeline = world.get_resource::<MeshPipeline>().unwrap();
+    let render_device = world.get_resource::<RenderDevice>().unwrap();
+    let wind_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("foliage wind layout"),
+      entries: &[BindGroupLayoutEntry {
+        binding: 0,
+        visibility: ShaderStages::VERTEX,
+        ty: BindingType::Buffer {
+          ty: BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: BufferSize::new(std::mem::size_of::<FoliageWindUniform>() as u64),
+        },
+        count: None,
+      }],
+    });
+    let wind_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+      label: Some("foliage wind uniform"),
+      contents: bytemuck::bytes_of(&FoliageWindUniform::default()),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+    let wind_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+      label: Some("foliage wind bind group"),
+      entries: &[BindGroupEntry {
+        binding: 0,
+        resource: wind_buffer.as_entire_binding(),
+      }],
+      layout: &wind_layout,
+    });
+    FoliagePipeline {
+      mesh_pipeline: mesh_pipeline.clone(),
+      wind_layout,
+      wind_buffer,
+      wind_bind_group,
+    }
+  }
+}
+
+impl SpecializedMeshPipeline for FoliagePipeline {
+  type Key = MeshPipelineKey;
+
+  fn specialize(
+    &self,
+    key: Self::Key,
+    layout: &MeshVertexBufferLayout,
+  ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+    let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+    descriptor.vertex.shader = FOLIAGE_SHADER_HANDLE.typed::<Shader>();
+    descriptor.vertex.buffers.push(VertexBufferLayout {
+      array_stride: std::mem::size_of::<FoliageInstance>() as u64,
+      step_mode: VertexStepMode::Instance,
+      attributes: vec![
+        // locations 0-2 are taken by the position, normal and uv attributes
+        VertexAttribute {
+          format: VertexFormat::Float32x4,
+          offset: 0,
+          shader_location: 3,
+        },
+        VertexAttribute {
+          format: VertexFormat::Float32x4,
+          offset: VertexFormat::Float32x4.size(),
+          shader_location: 4,
+        },
+      ],
+    });
+    descriptor.fragment.as_mut().unwrap().shader = FOLIAGE_SHADER_HANDLE.typed::<Shader>();
+    // blades are single quads seen from both sides
+    descriptor.primitive.cull_mode = None;
+    descriptor.layout = Some(vec![
+      self.mesh_pipeline.view_layout.clone(),
+      self.mesh_pipeline.mesh_layout.clone(),
+      self.wind_layout.clone(),
+    ]);
+
+    Ok(descriptor)
+  }
+}
+
+type DrawFoliage = (
+  SetItemPipeline,
+  SetMeshViewBindGroup<0>,
+  SetMeshBindGroup<1>,
+  SetFoliageWindBindGroup<2>,
+  DrawFoliageBlades,
+);
+
+pub struct SetFoliageWindBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetFoliageWindBindGroup<I> {
+  type Param = SRes<FoliagePipeline>;
+
+  fn render<'w>(
+    _view: Entity,
+    _item: Entity,
+    pipeline: SystemParamItem<'w, '_, Self::Param>,
+    pass: &mut TrackedRenderPass<'w>,
+  ) -> RenderCommandResult {
+    pass.set_bind_group(I, &pipeline.into_inner().wind_bind_group, &[]);
+    RenderCommandResult::Success
+  }
+}
+
+pub struct DrawFoliageBlades;
+impl EntityRenderCommand for DrawFoliageBlades {
+  type Param = (
+    SRes<RenderAssets<Mesh>>,
+    SQuery<Read<Handle<Mesh>>>,
+    SRes<FoliageBuffers>,
+  );
+
+  #[inline]
+  fn render<'w>(
+    _view: Entity,
+    item: Entity,
+    (meshes, mesh_query, buffers): SystemParamItem<'w, '_, Self::Param>,
+    pass: &mut TrackedRenderPass<'w>,
+  ) -> RenderCommandResult {
+    let mesh_handle = mesh_query.get(item).unwrap();
+    let instance_buffer = match buffers.into_inner().0.get(&item) {
+      Some(buffer) => buffer,
+      None => return RenderCommandResult::Failure,
+    };
+
+    let gpu_mesh = match meshes.into_inner().get(mesh_handle) {
+      Some(gpu_mesh) => gpu_mesh,
+      None => return RenderCommandResult::Failure,
+    };
+
+    pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+    pass.set_vertex_buffer(1, instance_buffer.buffer.slice(..));
+
+    match &gpu_mesh.buffer_info {
+      GpuBufferInfo::Indexed {
+        buffer,
+        index_format,
+        count,
+      } => {
+        pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+        pass.draw_indexed(0..*count, 0, 0..instance_buffer.length as u32);
+      }
+      GpuBufferInfo::NonIndexed { vertex_count } => {
+        pass.draw(0..*vertex_count, 0..instance_buffer.length as u32);
+      }
+    }
+    RenderCommandResult::Success
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn blades_should_grow_on_the_surface(seed in any::<u64>(), heights in prop::collection::vec(0i64..6, 9), density in 0f32..1.) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 1.0, 1, 8);
+          let chunk = ChunkId::new(0, 0, 0);
+          let voxels: HashMap<_, _> = layout
+              .iter_chunk_voxels(&chunk)
+              .map(|voxel| {
+                  let column = ((voxel.x() + 1) * 3 + voxel.z() + 1) as usize;
+                  let solid = voxel.y() < heights[column];
+                  (voxel, if solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR })
+              })
+              .collect();
+          let settings = FoliageSettings::default();
+          let registry = VoxelRegistry::default();
+          let grow = |density: f32| scatter_foliage(&layout, &registry, &chunk, &voxels, &settings, density, &mut ChunkRng::new(seed, &chunk, "foliage"));
+
+          let blades = grow(density);
+          let origin = layout.chunk_to_space(&chunk);
+          for blade in blades.iter() {
+              let [x, y, z, height] = blade.position_height;
+              let base = origin + Vec3::new(x, y, z);
+              assert_eq!(voxels.get(&layout.space_to_voxel(&(base - Vec3::Y * 0.5))), Some(&VoxelTypeId::DIRT));
+              assert_eq!(voxels.get(&layout.space_to_voxel(&(base + Vec3::Y * 0.5))), Some(&VoxelTypeId::AIR));
+              assert!((settings.min_height..=settings.max_height).contains(&height));
+          }
+          // thicker foliage keeps the blades of thinner foliage in place
+          let thicker = grow((density + 0.2).min(1.));
+          assert!(blades.iter().all(|blade| thicker.contains(blade)));
+          assert!(grow(0.).is_empty());
+      }
+  }
+}
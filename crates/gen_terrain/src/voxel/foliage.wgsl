@@ -0,0 +1,48 @@
+#import bevy_pbr::mesh_view_bind_group
+#import bevy_pbr::mesh_struct
+
+[[group(1), binding(0)]]
+var<uniform> mesh: Mesh;
+
+struct FoliageWind {
+    // x and y are the direction scaled by the strength, z is the frequency and w the time
+    wind: vec4<f32>;
+};
+
+[[group(2), binding(0)]]
+var<uniform> wind: FoliageWind;
+
+struct Vertex {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+
+    [[location(3)]] i_pos_height: vec4<f32>;
+    [[location(4)]] i_color_phase: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let height = vertex.i_pos_height.w;
+    var position = vertex.position * height;
+    // the base stays put and the tip sways the most
+    let sway = sin(wind.wind.w * wind.wind.z + vertex.i_color_phase.w) * vertex.position.y * height;
+    position = position + vec3<f32>(wind.wind.x, 0.0, wind.wind.y) * sway;
+    let world_position = mesh.model * vec4<f32>(position + vertex.i_pos_height.xyz, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = view.view_proj * world_position;
+    // darker toward the base, which the neighboring blades shade
+    out.color = vec4<f32>(vertex.i_color_phase.rgb * (0.6 + 0.4 * vertex.position.y), 1.0);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return in.color;
+}
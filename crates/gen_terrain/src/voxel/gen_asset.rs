@@ -0,0 +1,269 @@
+use super::{
+  biome::Biome,
+  error::{TerrainError, TerrainErrorEvent},
+  foliage::FoliageSettings,
+  generator::WorldGenConfig,
+  registry::VoxelRegistry,
+};
+use bevy::{
+  asset::{AssetLoader, LoadContext, LoadedAsset},
+  prelude::*,
+  reflect::TypeUuid,
+  utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// generation parameters and passes authored in a `.gen.ron` file, e.g. `assets/terrain.gen.ron`:
+//   (
+//     config: (seed: 42, scale: 96.0, regions: Some((sea_level: -0.1, rivers_per_region: 6))),
+//     passes: (foliage: Some((density: {Plains: 0.8}, grows_on: ["dirt"]))),
+//   )
+// fields left out keep their defaults
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize, TypeUuid)]
+#[serde(default)]
+#[uuid = "5b0f6a3e-8c1d-4d7a-9e62-3f1b2c7d9a40"]
+pub struct WorldGenAsset {
+  pub config: WorldGenConfig,
+  pub passes: WorldGenPasses,
+}
+
+// the passes run over chunks once they're generated, passes left out keep the settings the game
+// set up in code
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldGenPasses {
+  pub foliage: Option<FoliagePass>,
+}
+
+// `FoliageSettings` with voxel types by name, colors are srgb
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FoliagePass {
+  pub density: HashMap<Biome, f32>,
+  pub grows_on: Vec<String>,
+  pub min_height: f32,
+  pub max_height: f32,
+  pub color: [f32; 3],
+  pub max_lod: u8,
+  pub wind_direction: (f32, f32),
+  pub wind_strength: f32,
+  pub wind_frequency: f32,
+}
+impl Default for FoliagePass {
+  fn default() -> Self {
+    let settings = FoliageSettings::default();
+    let [r, g, b, _] = settings.color.as_rgba_f32();
+    Self {
+      density: settings.density,
+      grows_on: vec!["dirt".to_string()],
+      min_height: settings.min_height,
+      max_height: settings.max_height,
+      color: [r, g, b],
+      max_lod: settings.max_lod,
+      wind_direction: (settings.wind_direction.x, settings.wind_direction.y),
+      wind_strength: settings.wind_strength,
+      wind_frequency: settings.wind_frequency,
+    }
+  }
+}
+
+impl FoliagePass {
+  pub fn settings(&self, registry: &VoxelRegistry) -> Result<FoliageSettings, TerrainError> {
+    if !self
+      .density
+      .values()
+      .all(|density| (0. ..=1.).contains(density))
+    {
+      return Err(TerrainError::InvalidConfig(
+        "foliage densities should be between 0 and 1",
+      ));
+    }
+    if !(self.min_height.is_finite() && self.min_height >= 0. && self.min_height <= self.max_height)
+    {
+      return Err(TerrainError::InvalidConfig(
+        "foliage heights should be positive with the min below the max",
+      ));
+    }
+    let wind = [
+      self.wind_direction.0,
+      self.wind_direction.1,
+      self.wind_strength,
+      self.wind_frequency,
+    ];
+    if !(self.max_height.is_finite() && wind.iter().all(|value| value.is_finite())) {
+      return Err(TerrainError::InvalidConfig(
+        "foliage heights and wind should be finite",
+      ));
+    }
+    let grows_on = self
+      .grows_on
+      .iter()
+      .map(|name| registry.id_of(name))
+      .collect::<Option<Vec<_>>>()
+      .ok_or(TerrainError::InvalidConfig(
+        "foliage grows on a voxel type that isn't registered",
+      ))?;
+    let [r, g, b] = self.color;
+    Ok(FoliageSettings {
+      density: self.density.clone(),
+      grows_on,
+      min_height: self.min_height,
+      max_height: self.max_height,
+      color: Color::rgb(r, g, b),
+      max_lod: self.max_lod,
+      wind_direction: Vec2::new(self.wind_direction.0, self.wind_direction.1),
+      wind_strength: self.wind_strength,
+      wind_frequency: self.wind_frequency,
+    })
+  }
+}
+
+impl WorldGenAsset {
+  pub fn from_ron(bytes: &[u8]) -> Result<Self, ron::Error> {
+    ron::de::from_bytes(bytes)
+  }
+}
+
+#[derive(Default)]
+pub struct WorldGenAssetLoader;
+
+impl AssetLoader for WorldGenAssetLoader {
+  fn load<'a>(
+    &'a self,
+    bytes: &'a [u8],
+    load_context: &'a mut LoadContext,
+  ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+    Box::pin(async move {
+      load_context.set_default_asset(LoadedAsset::new(WorldGenAsset::from_ron(bytes)?));
+      Ok(())
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["gen.ron"]
+  }
+}
+
+// drives `WorldGenConfig` and the passes from an asset instead of code, insert it with the handle:
+//   commands.insert_resource(WorldGenSource(asset_server.load("terrain.gen.ron")));
+// the config is replaced once the asset is loaded and again whenever it changes, which
+// regenerates the terrain, so with `AssetServerSettings::watch_for_changes` saving the file
+// reloads the world
+// assets that don't validate are reported and leave everything as it was
+pub struct WorldGenSource(pub Handle<WorldGenAsset>);
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_world_gen_asset(
+  source: Option<Res<WorldGenSource>>,
+  assets: Res<Assets<WorldGenAsset>>,
+  registry: Res<VoxelRegistry>,
+  mut asset_events: EventReader<AssetEvent<WorldGenAsset>>,
+  mut config: ResMut<WorldGenConfig>,
+  mut foliage: ResMut<FoliageSettings>,
+  mut applied_passes: Local<WorldGenPasses>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  let source = match source {
+    Some(source) => source,
+    None => return,
+  };
+  let asset_changed = asset_events.iter().any(|event| match event {
+    AssetEvent::Created { handle } | AssetEvent::Modified { handle } => *handle == source.0,
+    AssetEvent::Removed { .. } => false,
+  });
+  if !asset_changed && !source.is_changed() {
+    return;
+  }
+  // not loaded yet, there'll be an event once it is
+  let asset = match assets.get(&source.0) {
+    Some(asset) => asset,
+    None => return,
+  };
+  let foliage_settings = asset.config.validate().and_then(|_| {
+    let pass = asset.passes.foliage.as_ref();
+    pass.map(|pass| pass.settings(&registry)).transpose()
+  });
+  let foliage_settings = match foliage_settings {
+    Ok(foliage_settings) => foliage_settings,
+    Err(err) => {
+      errors.send(TerrainErrorEvent::new(
+        err,
+        format!("applying the world gen asset {:?}", source.0),
+      ));
+      return;
+    }
+  };
+  // saving the file without changing the parameters doesn't regenerate anything
+  if *config != asset.config {
+    info!("applying world gen config from {:?}", source.0);
+    *config = asset.config.clone();
+  }
+  if *applied_passes != asset.passes {
+    if let Some(foliage_settings) = foliage_settings {
+      *foliage = foliage_settings;
+    }
+    *applied_passes = asset.passes.clone();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{region::WorldRegionSettings, registry::VoxelTypeId};
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn assets_should_round_trip_and_default_what_they_leave_out(seed in any::<u64>(), scale in 1f64..256., regions in any::<bool>(), foliage in any::<bool>()) {
+          let asset = WorldGenAsset {
+              config: WorldGenConfig {
+                  seed,
+                  scale,
+                  regions: regions.then(WorldRegionSettings::default),
+                  ..Default::default()
+              },
+              passes: WorldGenPasses { foliage: foliage.then(FoliagePass::default) },
+          };
+          let ron = ron::to_string(&asset).unwrap();
+          prop_assert_eq!(WorldGenAsset::from_ron(ron.as_bytes()).unwrap(), asset);
+
+          let partial = format!("(config: (seed: {}, regions: Some((cells: 8))))", seed);
+          let parsed = WorldGenAsset::from_ron(partial.as_bytes()).unwrap();
+          prop_assert_eq!(parsed.config.seed, seed);
+          prop_assert_eq!(parsed.config.scale, WorldGenConfig::default().scale);
+          let regions = parsed.config.regions.unwrap();
+          prop_assert_eq!(regions.cells, 8);
+          prop_assert_eq!(regions.cell_size, WorldRegionSettings::default().cell_size);
+          prop_assert_eq!(parsed.passes, WorldGenPasses::default());
+      }
+
+      #[test]
+      fn foliage_passes_should_only_grow_on_registered_types(density in -1f32..2., min_height in -1f32..2., road in any::<bool>()) {
+          let registry = VoxelRegistry::default();
+          let grows_on = match road {
+              true => vec!["dirt".to_string(), "road".to_string()],
+              false => vec!["dirt".to_string(), "moss".to_string()],
+          };
+          let pass = FoliagePass {
+              density: HashMap::from([(Biome::Forest, density)]),
+              grows_on,
+              min_height,
+              max_height: 1.,
+              ..Default::default()
+          };
+          let valid = road && (0. ..=1.).contains(&density) && (0. ..=1.).contains(&min_height);
+          match pass.settings(&registry) {
+              Ok(settings) => {
+                  prop_assert!(valid);
+                  prop_assert_eq!(settings.grows_on, vec![VoxelTypeId::DIRT, VoxelTypeId::ROAD]);
+                  prop_assert_eq!(settings.density[&Biome::Forest], density);
+              }
+              Err(err) => {
+                  prop_assert!(!valid);
+                  prop_assert!(matches!(err, TerrainError::InvalidConfig(_)));
+              }
+          }
+      }
+  }
+}
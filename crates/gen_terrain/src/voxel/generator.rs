@@ -1,25 +1,796 @@
-use super::VoxelId;
-use bevy::{
-  prelude::*,
-  tasks::{AsyncComputeTaskPool, Task},
+use super::{
+  biome::{Biome, ClimateMap},
+  error::TerrainError,
+  region::{WorldRegionSettings, WorldRegions},
+  registry::{VoxelRegistry, VoxelTypeId},
+  seed::{ChunkRng, ChunkSeed},
+  ChunkId, ChunkVoxelData, VoxelId,
 };
-use std::collections::HashMap;
+use bevy::tasks::TaskPool;
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+};
+
+// chunks are generated in up to this many batches of columns in parallel
+const MAX_COLUMN_BATCHES: usize = 8;
+// chunks with fewer columns per batch than this use fewer batches, also the columns filled per
+// step by `fill_column_steps`
+const MIN_BATCH_COLUMNS: usize = 64;
+// keeps column streams apart from the chunk streams with the same coordinates
+const COLUMN_SEED_TAG: u64 = 0xc01d_5eed;
+
+// the work of generating or meshing a chunk, built on the main thread and run elsewhere
+pub type ChunkJob<T> = Box<dyn FnOnce() -> T + Send>;
+
+// a chunk job split into steps, `run_chunk_jobs` can stop in between steps once the frame's share
+// of time is used up, workers run the steps back to back
+pub enum ChunkStep<T> {
+  Done(T),
+  Next(Box<dyn FnOnce() -> ChunkStep<T> + Send>),
+}
+
+impl<T: Send + 'static> ChunkStep<T> {
+  // the whole job in a single step
+  pub fn job(job: ChunkJob<T>) -> Self {
+    Self::Next(Box::new(move || Self::Done(job())))
+  }
+
+  // calls `step` once per step until it returns true, then finishes with the state it left
+  pub fn repeat(mut state: T, mut step: impl FnMut(&mut T) -> bool + Send + 'static) -> Self {
+    Self::Next(Box::new(move || match step(&mut state) {
+      true => Self::Done(state),
+      false => Self::repeat(state, step),
+    }))
+  }
+
+  pub fn map<U: Send + 'static>(self, f: impl FnOnce(T) -> U + Send + 'static) -> ChunkStep<U> {
+    match self {
+      Self::Done(value) => ChunkStep::Done(f(value)),
+      Self::Next(step) => ChunkStep::Next(Box::new(move || step().map(f))),
+    }
+  }
+
+  // runs the remaining steps right away
+  pub fn finish(self) -> T {
+    let mut step = self;
+    loop {
+      match step {
+        Self::Done(value) => return value,
+        Self::Next(next) => step = next(),
+      }
+    }
+  }
+}
+
+// everything that determines what the generator produces for a chunk
+// changing it at runtime regenerates the loaded terrain
+// fields left out when it's deserialized keep their defaults
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldGenConfig {
+  pub seed: u64,
+  // voxels per unit of noise, larger values give smoother terrain
+  pub scale: f64,
+  // surface height in voxels where the noise is 0
+  pub base_height: f64,
+  // how far in voxels the surface goes above and below the base height
+  pub amplitude: f64,
+  pub octaves: usize,
+  pub persistence: f64,
+  pub lacunarity: f64,
+  // continents and rivers laid over the noise, without them the noise alone shapes the surface
+  pub regions: Option<WorldRegionSettings>,
+  // voxel columns along x and z after which the noise repeats, so a wrapping world doesn't tear at
+  // its edge, generation jobs take it from the layout
+  #[serde(skip)]
+  pub wrapping: Option<(i64, i64)>,
+}
+impl Default for WorldGenConfig {
+  fn default() -> Self {
+    Self {
+      seed: 0x5eed,
+      scale: 64.0,
+      base_height: 4.0,
+      amplitude: 4.0,
+      octaves: 4,
+      persistence: 0.5,
+      lacunarity: 2.0,
+      regions: None,
+      wrapping: None,
+    }
+  }
+}
+
+impl WorldGenConfig {
+  pub fn validate(&self) -> Result<(), TerrainError> {
+    if !(self.scale.is_finite() && self.scale > 0.) {
+      return Err(TerrainError::InvalidConfig("scale should be positive"));
+    }
+    if !(self.amplitude.is_finite() && self.amplitude >= 0.) {
+      return Err(TerrainError::InvalidConfig("amplitude can't be negative"));
+    }
+    if !(self.lacunarity.is_finite() && self.lacunarity > 0.) {
+      return Err(TerrainError::InvalidConfig("lacunarity should be positive"));
+    }
+    if !(self.base_height.is_finite() && self.persistence.is_finite()) {
+      return Err(TerrainError::InvalidConfig(
+        "base height and persistence should be finite",
+      ));
+    }
+    if let Some(regions) = &self.regions {
+      regions.validate()?;
+    }
+    Ok(())
+  }
+}
+
+// what a generator knows about the chunk it's generating
+#[derive(Debug, Clone)]
+pub struct GenerationContext {
+  pub chunk: ChunkId,
+  pub config: WorldGenConfig,
+  // look up the ids of the voxel types the generator places by name
+  pub registry: VoxelRegistry,
+  // threads to split up the work of a single chunk between, e.g. with `fill_columns`, not the
+  // ones chunk jobs run on, see `ChunkBatchPool`
+  pub pool: TaskPool,
+  // columns of this chunk already sampled, shared by clones of the context so passes run after the
+  // generator don't sample the same noise again
+  pub columns: ColumnCache,
+  // distant chunks only need their surface, voxels more than this many voxels below the surface
+  // can be left as `VoxelTypeId::UNKNOWN` instead of being generated
+  pub surface_clip: Option<i64>,
+  // continents and rivers shared with the other chunks, when the config has region settings
+  pub regions: WorldRegions,
+}
+
+impl GenerationContext {
+  pub fn new(
+    chunk: ChunkId,
+    config: WorldGenConfig,
+    registry: VoxelRegistry,
+    pool: TaskPool,
+  ) -> Self {
+    Self {
+      chunk,
+      config,
+      registry,
+      pool,
+      columns: ColumnCache::default(),
+      surface_clip: None,
+      regions: WorldRegions::default(),
+    }
+  }
+
+  pub fn with_regions(mut self, regions: WorldRegions) -> Self {
+    self.regions = regions;
+    self
+  }
+
+  // the noise repeats after `wrapping` voxel columns, see `CubicVoxelLayout::wrapping_voxels`
+  pub fn with_wrapping(mut self, wrapping: Option<(i64, i64)>) -> Self {
+    self.config.wrapping = wrapping;
+    self
+  }
+
+  pub fn with_surface_clip(mut self, surface_clip: Option<i64>) -> Self {
+    self.surface_clip = surface_clip;
+    self
+  }
+
+  // whether a generator can skip a voxel `depth` voxels below the surface
+  pub fn is_clipped(&self, depth: f64) -> bool {
+    matches!(self.surface_clip, Some(clip) if depth > clip as f64)
+  }
+
+  // how many voxels at the bottom of `column`, which goes from the bottom up, are clipped under a
+  // surface at `height`, generators can set them to `VoxelTypeId::UNKNOWN` without evaluating them
+  pub fn clipped_voxels(&self, height: f64, column: &[(VoxelId, VoxelTypeId)]) -> usize {
+    column.partition_point(|(voxel, _)| self.is_clipped(height - voxel.y() as f64))
+  }
+
+  // whether every voxel of `buffer` is clipped under a surface that's nowhere lower than `lowest`,
+  // generators can fill those chunks with `VoxelTypeId::UNKNOWN` without sampling a single column
+  pub fn is_buried(&self, buffer: &HashMap<VoxelId, VoxelTypeId>, lowest: f64) -> bool {
+    matches!(buffer.keys().map(|voxel| voxel.y()).max(), Some(top) if self.is_clipped(lowest - top as f64))
+  }
+
+  pub fn chunk_seed(&self) -> ChunkSeed {
+    ChunkSeed::new(self.config.seed, &self.chunk)
+  }
+
+  // deterministic random stream for one generation pass
+  pub fn rng(&self, purpose: &str) -> ChunkRng {
+    self.chunk_seed().rng(purpose)
+  }
+
+  // deterministic random stream for one pass over the column at voxel (x, z) of this chunk, the
+  // same whichever batch or thread generates the column
+  pub fn column_rng(&self, x: i64, z: i64, purpose: &str) -> ChunkRng {
+    ChunkRng::new(
+      self.config.seed ^ COLUMN_SEED_TAG,
+      &ChunkId::new(x, self.chunk.y(), z),
+      purpose,
+    )
+  }
+}
+
+// the 2d values of a column the built-in generators sample, generators without rivers or roads
+// leave those out
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ColumnSample {
+  pub surface_height: f64,
+  pub water_level: Option<f64>,
+  pub on_road: bool,
+}
+
+// the samples of the columns of one chunk, keyed by voxel (x, z)
+// safe to use from the batches of `fill_columns`
+#[derive(Debug, Clone, Default)]
+pub struct ColumnCache {
+  samples: Arc<RwLock<HashMap<(i64, i64), ColumnSample>>>,
+}
+
+impl ColumnCache {
+  // the cached sample of the column, sampled on the first call
+  pub fn get_or_sample(
+    &self,
+    x: i64,
+    z: i64,
+    sample: impl FnOnce() -> ColumnSample,
+  ) -> ColumnSample {
+    if let Some(cached) = self.get(x, z) {
+      return cached;
+    }
+    // sampled without the lock so other batches aren't held up, a column is only filled by one
+    // batch so it's rarely sampled twice
+    let sampled = sample();
+    *self
+      .samples
+      .write()
+      .unwrap()
+      .entry((x, z))
+      .or_insert(sampled)
+  }
+
+  pub fn get(&self, x: i64, z: i64) -> Option<ColumnSample> {
+    self.samples.read().unwrap().get(&(x, z)).copied()
+  }
+
+  pub fn len(&self) -> usize {
+    self.samples.read().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+// fills the columns of a chunk's voxel buffer in batches on `pool`, `fill` gets the (x, z) of a
+// column and its voxels from the bottom up
+// `fill` should only depend on the column it's given, e.g. by using `column_rng` instead of one
+// stream for the whole chunk, so the chunk comes out the same however its columns are batched
+pub fn fill_columns(
+  pool: &TaskPool,
+  buffer: HashMap<VoxelId, VoxelTypeId>,
+  fill: impl Fn(i64, i64, &mut [(VoxelId, VoxelTypeId)]) + Send + Sync,
+) -> HashMap<VoxelId, VoxelTypeId> {
+  let mut columns = columns_of(buffer);
+
+  // small chunks aren't worth splitting, neither is anything without worker threads
+  let batches = if cfg!(feature = "terrain-wasm") || pool.thread_num() <= 1 {
+    1
+  } else {
+    (columns.len() / MIN_BATCH_COLUMNS).clamp(1, MAX_COLUMN_BATCHES)
+  };
+  let fill = &fill;
+  if batches == 1 {
+    for ((x, z), voxels) in columns.iter_mut() {
+      fill(*x, *z, voxels);
+    }
+  } else {
+    let batch_columns = (columns.len() + batches - 1) / batches;
+    pool.scope(|scope| {
+      for batch in columns.chunks_mut(batch_columns) {
+        scope.spawn(async move {
+          for ((x, z), voxels) in batch.iter_mut() {
+            fill(*x, *z, voxels);
+          }
+        });
+      }
+    });
+  }
+
+  columns.into_iter().flat_map(|(_, voxels)| voxels).collect()
+}
 
-#[derive(Debug)]
-pub enum VoxelType {
-  Air,
-  //  Dirt,
+// same as `fill_columns` but a batch of columns per step and no worker threads, so
+// `run_chunk_jobs` can stop generating in between batches
+pub fn fill_column_steps(
+  buffer: HashMap<VoxelId, VoxelTypeId>,
+  fill: impl Fn(i64, i64, &mut [(VoxelId, VoxelTypeId)]) + Send + 'static,
+) -> ChunkStep<HashMap<VoxelId, VoxelTypeId>> {
+  ChunkStep::Next(Box::new(move || {
+    let mut filled = 0;
+    ChunkStep::repeat(columns_of(buffer), move |columns| {
+      let end = (filled + MIN_BATCH_COLUMNS).min(columns.len());
+      for ((x, z), voxels) in columns[filled..end].iter_mut() {
+        fill(*x, *z, voxels);
+      }
+      filled = end;
+      filled == columns.len()
+    })
+    .map(|columns| columns.into_iter().flat_map(|(_, voxels)| voxels).collect())
+  }))
 }
 
+// every voxel of a chunk that's clipped as a whole, see `GenerationContext::is_buried`
+pub fn bury(buffer: HashMap<VoxelId, VoxelTypeId>) -> HashMap<VoxelId, VoxelTypeId> {
+  buffer
+    .into_keys()
+    .map(|voxel| (voxel, VoxelTypeId::UNKNOWN))
+    .collect()
+}
+
+// the voxels of each column from the bottom up
+fn columns_of(
+  buffer: HashMap<VoxelId, VoxelTypeId>,
+) -> Vec<((i64, i64), Vec<(VoxelId, VoxelTypeId)>)> {
+  let mut by_column: HashMap<(i64, i64), Vec<(VoxelId, VoxelTypeId)>> = HashMap::new();
+  for (voxel, voxel_type) in buffer {
+    by_column
+      .entry((voxel.x(), voxel.z()))
+      .or_default()
+      .push((voxel, voxel_type));
+  }
+  let mut columns: Vec<_> = by_column.into_iter().collect();
+  for (_, voxels) in columns.iter_mut() {
+    voxels.sort_unstable_by_key(|(voxel, _)| voxel.y());
+  }
+  columns
+}
+
+// fills chunks with voxels, implement this to plug a custom generator into the terrain
+// `buffer` has every voxel of the chunk set to air, the returned task should fill it in
+// generators that know where the surface is should honor `GenerationContext::surface_clip`, the
+// rest can ignore it
+pub trait TerrainGenerator: Send + Sync + 'static {
+  // the returned job does the actual work, it runs on a worker thread or, with the `terrain-wasm`
+  // feature, on the main thread in between frames
+  fn load_voxel_data(
+    &self,
+    context: GenerationContext,
+    buffer: HashMap<VoxelId, VoxelTypeId>,
+  ) -> ChunkJob<ChunkVoxelData>;
+
+  // same as `load_voxel_data` but split into steps, used instead of it when jobs run inline so
+  // `run_chunk_jobs` can stop in between steps, the default runs `load_voxel_data` in one step
+  fn load_voxel_steps(
+    &self,
+    context: GenerationContext,
+    buffer: HashMap<VoxelId, VoxelTypeId>,
+  ) -> ChunkStep<ChunkVoxelData> {
+    ChunkStep::job(self.load_voxel_data(context, buffer))
+  }
+}
+
+// the generator used by the terrain plugin, insert it before adding the plugin to replace the
+// default generator
+pub struct ActiveGenerator(pub Box<dyn TerrainGenerator>);
+impl ActiveGenerator {
+  pub fn new(generator: impl TerrainGenerator) -> Self {
+    Self(Box::new(generator))
+  }
+}
+impl Default for ActiveGenerator {
+  fn default() -> Self {
+    Self::new(VoxelGenerator)
+  }
+}
+
+// the 2D noise at voxel (x, z) with `scale` voxels per unit of noise
+// with `wrapping` it's sampled on a torus in 4D instead, so it repeats after that many voxel columns
+// along x and z and the columns on either side of the edge of the world match
+pub(super) fn sample_noise(
+  noise: &Fbm,
+  x: f64,
+  z: f64,
+  scale: f64,
+  wrapping: Option<(i64, i64)>,
+) -> f64 {
+  match wrapping {
+    Some((period_x, period_z)) => {
+      // circles as long as the world is wide keep the features the same size
+      let around = |value: f64, period: i64| {
+        let (angle, radius) = (
+          std::f64::consts::TAU * value / period as f64,
+          period as f64 / (std::f64::consts::TAU * scale),
+        );
+        (angle.cos() * radius, angle.sin() * radius)
+      };
+      let ((ax, bx), (az, bz)) = (around(x, period_x), around(z, period_z));
+      noise.get([ax, bx, az, bz])
+    }
+    None => noise.get([x / scale, z / scale]),
+  }
+}
+
+// the surface of the default generator, build it once to sample many columns
+// biomes raise and roughen the surface, columns near biome borders blend the biomes around them
+// so heights don't jump at the border
+// with region settings continents raise the surface above the sea, roads flatten it and rivers
+// carve their beds into it
+pub struct HeightMap {
+  noise: Fbm,
+  climate: ClimateMap,
+  base_height: f64,
+  amplitude: f64,
+  scale: f64,
+  config: WorldGenConfig,
+  regions: WorldRegions,
+}
+
+impl HeightMap {
+  pub fn new(config: &WorldGenConfig) -> Self {
+    Self {
+      noise: Fbm::new()
+        .set_seed(config.seed as u32)
+        .set_octaves(config.octaves.clamp(1, Fbm::MAX_OCTAVES))
+        .set_persistence(config.persistence)
+        .set_lacunarity(config.lacunarity),
+      climate: ClimateMap::new(config),
+      base_height: config.base_height,
+      amplitude: config.amplitude,
+      scale: config.scale,
+      config: config.clone(),
+      regions: WorldRegions::default(),
+    }
+  }
+
+  // samples regions from a shared cache instead of one of its own
+  pub fn with_regions(mut self, regions: WorldRegions) -> Self {
+    self.regions = regions;
+    self
+  }
+
+  // surface height in voxels of the column at voxel (x, z)
+  pub fn height(&self, x: i64, z: i64) -> f64 {
+    let height = self.bank_height(x, z);
+    match (
+      &self.config.regions,
+      self.regions.sample(&self.config, x, z),
+    ) {
+      (Some(settings), Some(region)) => match region.flow {
+        // deepest in the middle of the river, level with the water at its banks
+        Some(flow) => height.min(flow.bed - settings.river_depth * region.river),
+        None => height,
+      },
+      _ => height,
+    }
+  }
+
+  // surface height before rivers carve their beds into it
+  fn bank_height(&self, x: i64, z: i64) -> f64 {
+    match (
+      &self.config.regions,
+      self.regions.sample(&self.config, x, z),
+    ) {
+      (Some(settings), Some(region)) => {
+        // the climate the region sampled once for its whole area
+        let biomes = Biome::weights_from_climate(region.temperature, region.moisture);
+        let height = self.biome_noise_height(x, z, &biomes)
+          + settings.continent_height * (region.continent - settings.sea_level);
+        match region.road_level {
+          Some(level) => height + (level - height) * region.road,
+          None => height,
+        }
+      }
+      _ => self.noise_height(x, z),
+    }
+  }
+
+  // height of the water surface in voxels over the column at voxel (x, z), for columns in a river
+  // water doesn't rise above the ground the river was carved into, where the banks are lower
+  // than the river it would stand next to them as a wall
+  pub fn water_level(&self, x: i64, z: i64) -> Option<f64> {
+    let level = self.regions.sample(&self.config, x, z)?.flow?.level;
+    Some(level.min(self.bank_height(x, z)))
+  }
+
+  // whether the column at voxel (x, z) is paved, on a road and not under a river crossing it
+  pub fn on_road(&self, x: i64, z: i64) -> bool {
+    matches!(self.regions.sample(&self.config, x, z), Some(region) if region.road >= 1. && region.flow.is_none())
+  }
+
+  // surface height from the noise and biomes alone, without continents, roads and rivers
+  pub fn noise_height(&self, x: i64, z: i64) -> f64 {
+    self.biome_noise_height(x, z, &self.climate.biome_weights(x, z))
+  }
+
+  // same as `noise_height` with the biomes already known
+  fn biome_noise_height(&self, x: i64, z: i64, biomes: &[(Biome, f64)]) -> f64 {
+    let sample = sample_noise(
+      &self.noise,
+      x as f64,
+      z as f64,
+      self.scale,
+      self.config.wrapping,
+    );
+    let (offset, roughness) =
+      biomes
+        .iter()
+        .fold((0., 0.), |(offset, roughness), (biome, weight)| {
+          (
+            offset + biome.height_offset() * weight,
+            roughness + biome.roughness() * weight,
+          )
+        });
+    self.base_height + offset + sample * self.amplitude * roughness
+  }
+
+  // the lowest and highest surface heights any column can have
+  pub fn bounds(&self) -> (f64, f64) {
+    let (low, high) = Biome::ALL
+      .iter()
+      .map(|biome| {
+        let center = self.base_height + biome.height_offset();
+        let reach = (self.amplitude * biome.roughness()).abs();
+        (center - reach, center + reach)
+      })
+      .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), (l, h)| {
+        (low.min(l), high.max(h))
+      });
+    match &self.config.regions {
+      Some(settings) => {
+        // continent values are in [-1, 1]
+        let (lowest, highest) = (
+          settings.continent_height * (-1. - settings.sea_level),
+          settings.continent_height * (1. - settings.sea_level),
+        );
+        // river levels are up to two voxels below the lowest ground they crossed
+        (
+          low + lowest.min(highest) - 2. - settings.river_depth.max(0.),
+          high + lowest.max(highest) - settings.river_depth.min(0.),
+        )
+      }
+      None => (low, high),
+    }
+  }
+}
+
+// fractal noise heightmap, everything below the surface is dirt
+// rivers fill their beds with water up to their water level, columns of water under waterfalls
+// the top voxel of road columns is road
 #[derive(Default)]
 pub struct VoxelGenerator;
 
-impl VoxelGenerator {
-  pub fn load_voxel_data(
+impl TerrainGenerator for VoxelGenerator {
+  fn load_voxel_data(
+    &self,
+    context: GenerationContext,
+    buffer: HashMap<VoxelId, VoxelTypeId>,
+  ) -> ChunkJob<ChunkVoxelData> {
+    Box::new(move || {
+      let height_map = HeightMap::new(&context.config).with_regions(context.regions.clone());
+      if context.is_buried(&buffer, height_map.bounds().0) {
+        return ChunkVoxelData {
+          voxels: bury(buffer),
+        };
+      }
+      let voxels = fill_columns(&context.pool, buffer, |x, z, column| {
+        VoxelGenerator::fill_column(&context, &height_map, x, z, column)
+      });
+
+      ChunkVoxelData { voxels }
+    })
+  }
+
+  fn load_voxel_steps(
     &self,
-    thread_pool: &Res<AsyncComputeTaskPool>,
-    buffer: HashMap<VoxelId, VoxelType>,
-  ) -> Task<super::ChunkVoxelData> {
-    thread_pool.spawn(async move { super::ChunkVoxelData { voxels: buffer } })
+    context: GenerationContext,
+    buffer: HashMap<VoxelId, VoxelTypeId>,
+  ) -> ChunkStep<ChunkVoxelData> {
+    ChunkStep::Next(Box::new(move || {
+      let height_map = HeightMap::new(&context.config).with_regions(context.regions.clone());
+      if context.is_buried(&buffer, height_map.bounds().0) {
+        return ChunkStep::Done(ChunkVoxelData {
+          voxels: bury(buffer),
+        });
+      }
+      fill_column_steps(buffer, move |x, z, column| {
+        VoxelGenerator::fill_column(&context, &height_map, x, z, column)
+      })
+      .map(|voxels| ChunkVoxelData { voxels })
+    }))
+  }
+}
+
+impl VoxelGenerator {
+  fn fill_column(
+    context: &GenerationContext,
+    height_map: &HeightMap,
+    x: i64,
+    z: i64,
+    column: &mut [(VoxelId, VoxelTypeId)],
+  ) {
+    let ColumnSample {
+      surface_height: height,
+      water_level,
+      on_road,
+    } = context.columns.get_or_sample(x, z, || ColumnSample {
+      surface_height: height_map.height(x, z),
+      // only worlds with regions have rivers and roads
+      water_level: context
+        .config
+        .regions
+        .as_ref()
+        .and_then(|_| height_map.water_level(x, z)),
+      on_road: context.config.regions.is_some() && height_map.on_road(x, z),
+    });
+    let clipped = context.clipped_voxels(height, column);
+    for (_, voxel_type) in column[..clipped].iter_mut() {
+      *voxel_type = VoxelTypeId::UNKNOWN;
+    }
+    for (voxel, voxel_type) in column[clipped..].iter_mut() {
+      let y = voxel.y() as f64;
+      if on_road && y < height && y >= height - 1. {
+        *voxel_type = VoxelTypeId::ROAD;
+      } else if y < height {
+        *voxel_type = VoxelTypeId::DIRT;
+      } else if matches!(water_level, Some(level) if y < level) {
+        *voxel_type = VoxelTypeId::WATER;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::layout::CubicVoxelLayout;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn configs_should_need_a_positive_scale(scale in -10f64..10., amplitude in 0f64..10.) {
+          let config = WorldGenConfig { scale, amplitude, ..Default::default() };
+          prop_assert_eq!(config.validate().is_ok(), scale > 0.);
+          let config = WorldGenConfig { scale: f64::NAN, ..Default::default() };
+          prop_assert!(matches!(config.validate(), Err(TerrainError::InvalidConfig(_))));
+      }
+
+      #[test]
+      fn batched_columns_should_match_a_single_pass(seed in any::<u64>(), length in 1i64..12) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, length, 4);
+          let context = GenerationContext::new(
+              ChunkId::new(1, 0, -2),
+              WorldGenConfig { seed, ..Default::default() },
+              VoxelRegistry::default(),
+              TaskPool::new(),
+          );
+          let buffer: HashMap<_, _> = layout
+              .get_chunk_voxels(&context.chunk)
+              .into_iter()
+              .map(|voxel| (voxel, VoxelTypeId::AIR))
+              .collect();
+          let fill = |x: i64, z: i64, voxel: &VoxelId| {
+              let mut rng = context.column_rng(x, z, "generator test");
+              // every voxel of a column draws once, from the bottom up
+              (0..=voxel.y() - layout.get_center_voxel(&context.chunk).y())
+                  .map(|_| rng.chance(0.5))
+                  .last()
+                  .unwrap()
+          };
+
+          let batched = fill_columns(&context.pool, buffer.clone(), |x, z, column| {
+              for (voxel, voxel_type) in column.iter_mut() {
+                  if fill(x, z, voxel) {
+                      *voxel_type = VoxelTypeId::DIRT;
+                  }
+              }
+          });
+          prop_assert_eq!(batched.len(), buffer.len());
+          for (voxel, voxel_type) in batched {
+              let expected = if fill(voxel.x(), voxel.z(), &voxel) { VoxelTypeId::DIRT } else { VoxelTypeId::AIR };
+              prop_assert_eq!(voxel_type, expected);
+          }
+
+          // generating in steps comes out the same
+          let stepped = VoxelGenerator.load_voxel_steps(context.clone(), buffer.clone()).finish();
+          prop_assert_eq!(stepped.voxels, VoxelGenerator.load_voxel_data(context, buffer)().voxels);
+      }
+
+      #[test]
+      fn voxels_should_follow_the_height_map(seed in any::<u64>(), length in 1i64..6) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, length, 4);
+          let context = GenerationContext::new(
+              ChunkId::new(-1, 0, 2),
+              WorldGenConfig { seed, ..Default::default() },
+              VoxelRegistry::default(),
+              TaskPool::new(),
+          );
+          let buffer: HashMap<_, _> = layout
+              .iter_chunk_voxels(&context.chunk)
+              .map(|voxel| (voxel, VoxelTypeId::AIR))
+              .collect();
+          let voxels = VoxelGenerator.load_voxel_data(context.clone(), buffer)().voxels;
+
+          // one sample per column, the same the height map gives
+          let height_map = HeightMap::new(&context.config);
+          let side = 2 * length + 1;
+          prop_assert_eq!(context.columns.len() as i64, side * side);
+          for (voxel, voxel_type) in voxels.iter() {
+              let sample = context.columns.get(voxel.x(), voxel.z()).unwrap();
+              prop_assert_eq!(sample.surface_height, height_map.height(voxel.x(), voxel.z()));
+              prop_assert_eq!(*voxel_type == VoxelTypeId::DIRT, (voxel.y() as f64) < sample.surface_height);
+          }
+          // later passes reuse the samples
+          let (x, z) = voxels.keys().next().map(|voxel| (voxel.x(), voxel.z())).unwrap();
+          let cached = context.columns.get_or_sample(x, z, || unreachable!());
+          prop_assert_eq!(Some(cached), context.columns.get(x, z));
+      }
+
+      #[test]
+      fn rivers_should_fill_their_beds_with_water(seed in any::<u64>(), x in -20i64..20, z in -20i64..20) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 16);
+          let regions = WorldRegionSettings {
+              cell_size: 4,
+              cells: 8,
+              continent_scale: 256.,
+              sea_level: -0.5,
+              rivers_per_region: 8,
+              ..Default::default()
+          };
+          let config = WorldGenConfig { seed, base_height: 20., regions: Some(regions), ..Default::default() };
+          let context = GenerationContext::new(ChunkId::new(x, 0, z), config.clone(), VoxelRegistry::default(), TaskPool::new());
+          let buffer = layout
+              .iter_chunk_voxels(&context.chunk)
+              .map(|voxel| (voxel, VoxelTypeId::AIR))
+              .collect();
+          let voxels = VoxelGenerator.load_voxel_data(context, buffer)().voxels;
+
+          // a height map with a cache of its own agrees with the one the chunk was generated with
+          let height_map = HeightMap::new(&config);
+          for (voxel, voxel_type) in voxels.iter() {
+              let (y, height) = (voxel.y() as f64, height_map.height(voxel.x(), voxel.z()));
+              let water = matches!(height_map.water_level(voxel.x(), voxel.z()), Some(level) if y >= height && y < level);
+              prop_assert_eq!(*voxel_type == VoxelTypeId::WATER, water);
+          }
+      }
+
+      #[test]
+      fn clipped_chunks_should_keep_their_surface(seed in any::<u64>(), clip in 0i64..6, chunk_y in -3i64..1) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 16);
+          let generate = |surface_clip| {
+              let context = GenerationContext::new(
+                  ChunkId::new(3, chunk_y, -1),
+                  WorldGenConfig { seed, base_height: 10., ..Default::default() },
+                  VoxelRegistry::default(),
+                  TaskPool::new(),
+              )
+              .with_surface_clip(surface_clip);
+              let buffer = layout
+                  .iter_chunk_voxels(&context.chunk)
+                  .map(|voxel| (voxel, VoxelTypeId::AIR))
+                  .collect();
+              VoxelGenerator.load_voxel_data(context, buffer)().voxels
+          };
+          let full = generate(None);
+          let clipped = generate(Some(clip));
+          prop_assert!(!full.values().any(|voxel_type| *voxel_type == VoxelTypeId::UNKNOWN));
+
+          let height_map = HeightMap::new(&WorldGenConfig { seed, base_height: 10., ..Default::default() });
+          for (voxel, voxel_type) in clipped.iter() {
+              let depth = height_map.height(voxel.x(), voxel.z()) - voxel.y() as f64;
+              if depth > clip as f64 {
+                  prop_assert_eq!(*voxel_type, VoxelTypeId::UNKNOWN);
+              } else {
+                  prop_assert_eq!(*voxel_type, full[voxel]);
+              }
+          }
+      }
   }
 }
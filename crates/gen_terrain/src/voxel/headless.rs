@@ -0,0 +1,160 @@
+#[cfg(feature = "render")]
+use super::material::ATTRIBUTE_VOXEL_TYPE;
+use super::{
+  generator::{GenerationContext, TerrainGenerator, VoxelGenerator, WorldGenConfig},
+  layout::CubicVoxelLayout,
+  light::ChunkLight,
+  mesher::mesh_chunk_data,
+  region::WorldRegions,
+  registry::{VoxelRegistry, VoxelTypeId},
+  ChunkId, ChunkVoxelData,
+};
+use bevy::tasks::TaskPool;
+#[cfg(feature = "render")]
+use bevy::{
+  prelude::*,
+  render::{mesh::Indices, render_resource::PrimitiveTopology},
+};
+
+// the geometry of a chunk mesh in plain vectors, for servers and tools that have no use for a
+// `Mesh` asset
+// vertices are relative to the chunk, like the meshes of chunk entities
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MeshData {
+  pub positions: Vec<[f32; 3]>,
+  pub normals: Vec<[f32; 3]>,
+  pub uvs: Vec<[f32; 2]>,
+  // either empty or one per vertex
+  pub colors: Vec<[f32; 4]>,
+  // either empty or one per vertex
+  pub voxel_types: Vec<u32>,
+  // triangle list
+  pub indices: Vec<u32>,
+}
+
+impl MeshData {
+  pub fn is_empty(&self) -> bool {
+    self.indices.is_empty()
+  }
+
+  pub fn triangle_count(&self) -> usize {
+    self.indices.len() / 3
+  }
+}
+
+#[cfg(feature = "render")]
+impl From<MeshData> for Mesh {
+  fn from(data: MeshData) -> Self {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, data.positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, data.normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, data.uvs);
+    if !data.colors.is_empty() {
+      mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, data.colors);
+    }
+    if !data.voxel_types.is_empty() {
+      mesh.insert_attribute(ATTRIBUTE_VOXEL_TYPE, data.voxel_types);
+    }
+    mesh.set_indices(Some(Indices::U32(data.indices)));
+    mesh
+  }
+}
+
+// generates and meshes chunks on the calling thread without an app, the results are the same
+// the terrain plugin would produce for the same layout, registry and config
+pub struct HeadlessTerrain {
+  pub layout: CubicVoxelLayout,
+  pub registry: VoxelRegistry,
+  pub config: WorldGenConfig,
+  generator: Box<dyn TerrainGenerator>,
+  pool: TaskPool,
+  // regions are generated once for all the chunks generated with this terrain
+  regions: WorldRegions,
+}
+
+impl HeadlessTerrain {
+  pub fn new(layout: CubicVoxelLayout, config: WorldGenConfig) -> Self {
+    Self {
+      layout,
+      registry: VoxelRegistry::default(),
+      config,
+      generator: Box::new(VoxelGenerator),
+      pool: TaskPool::new(),
+      regions: WorldRegions::default(),
+    }
+  }
+
+  pub fn with_registry(mut self, registry: VoxelRegistry) -> Self {
+    self.registry = registry;
+    self
+  }
+
+  pub fn with_generator(mut self, generator: impl TerrainGenerator) -> Self {
+    self.generator = Box::new(generator);
+    self
+  }
+
+  pub fn generate_chunk(&self, chunk: &ChunkId) -> ChunkVoxelData {
+    let context = GenerationContext::new(
+      *chunk,
+      self.config.clone(),
+      self.registry.clone(),
+      self.pool.clone(),
+    )
+    .with_regions(self.regions.clone())
+    .with_wrapping(self.layout.wrapping_voxels());
+    let buffer = self
+      .layout
+      .iter_chunk_voxels(chunk)
+      .map(|voxel| (voxel, VoxelTypeId::AIR))
+      .collect();
+    (self.generator.load_voxel_data(context, buffer))()
+  }
+
+  // meshes a chunk on its own, faces toward other chunks are always emitted like they are for
+  // chunk entities
+  pub fn mesh_chunk(&self, voxel_data: &ChunkVoxelData) -> MeshData {
+    let chunk = match voxel_data.voxels.keys().next() {
+      Some(voxel) => self.layout.voxel_to_chunk(voxel),
+      None => return MeshData::default(),
+    };
+    let light = ChunkLight::compute(&voxel_data.voxels, &self.registry);
+    mesh_chunk_data(
+      &self.layout,
+      &self.registry,
+      &chunk,
+      &voxel_data.voxels,
+      &light,
+    )
+  }
+}
+
+#[cfg(all(test, feature = "render"))]
+mod tests {
+  use super::*;
+  use crate::voxel::mesher::mesh_chunk;
+  use bevy::render::mesh::VertexAttributeValues;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn headless_meshes_should_match_chunk_meshes(seed in any::<u64>(), x in -4i64..4, z in -4i64..4) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 8);
+          let terrain = HeadlessTerrain::new(layout, WorldGenConfig { seed, ..Default::default() });
+          let chunk = ChunkId::new(x, 0, z);
+          let voxel_data = terrain.generate_chunk(&chunk);
+          prop_assert_eq!(voxel_data.voxels.len(), terrain.layout.iter_chunk_voxels(&chunk).count());
+
+          let data = terrain.mesh_chunk(&voxel_data);
+          let light = ChunkLight::compute(&voxel_data.voxels, &terrain.registry);
+          let mesh = mesh_chunk(&terrain.layout, &terrain.registry, &chunk, &voxel_data.voxels, &light);
+          let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+              Some(VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+              _ => vec![],
+          };
+          prop_assert_eq!(&data.positions, &positions);
+          prop_assert_eq!(data.triangle_count(), mesh.indices().map_or(0, |indices| indices.len()) / 3);
+          prop_assert_eq!(data.colors.len(), data.positions.len());
+      }
+  }
+}
@@ -0,0 +1,490 @@
+use super::{
+  error::{TerrainError, TerrainErrorEvent},
+  generator::{
+    bury, fill_column_steps, fill_columns, ChunkJob, ChunkStep, ColumnSample, GenerationContext,
+    TerrainGenerator,
+  },
+  layout::CubicVoxelLayout,
+  regen::RegenerateTerrain,
+  registry::VoxelTypeId,
+  ChunkVoxelData, VoxelId,
+};
+use bevy::{
+  prelude::*,
+  render::{render_resource::TextureFormat, texture::TextureFormatPixelInfo},
+};
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+// what the surface does past the borders of the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeightmapEdge {
+  // the outermost pixels stretch out forever
+  Clamp,
+  // the image tiles the world
+  Repeat,
+  // the image tiles the world with every other tile flipped, so tiles meet without seams
+  Mirror,
+  // flat ground at this height in voxels
+  Flat(f64),
+}
+
+// how the pixels of a heightmap map to voxel columns
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightmapSettings {
+  // surface height in voxels of black pixels
+  pub base_height: f64,
+  // voxels between the surface of black and white pixels
+  pub vertical_scale: f64,
+  // voxel columns per pixel on each axis, surfaces between pixel centers are interpolated
+  pub voxels_per_pixel: f64,
+  // the voxel column the center of the top left pixel is at, the image's x goes along +x and
+  // its y along +z
+  pub origin: (i64, i64),
+  pub edge: HeightmapEdge,
+}
+impl Default for HeightmapSettings {
+  fn default() -> Self {
+    Self {
+      base_height: 0.,
+      vertical_scale: 32.,
+      voxels_per_pixel: 1.,
+      origin: (0, 0),
+      edge: HeightmapEdge::Clamp,
+    }
+  }
+}
+
+impl HeightmapSettings {
+  pub fn validate(&self) -> Result<(), TerrainError> {
+    if !(self.voxels_per_pixel.is_finite() && self.voxels_per_pixel > 0.) {
+      return Err(TerrainError::InvalidConfig(
+        "voxels per pixel should be positive",
+      ));
+    }
+    if !(self.base_height.is_finite() && self.vertical_scale.is_finite()) {
+      return Err(TerrainError::InvalidConfig(
+        "heightmap base height and vertical scale should be finite",
+      ));
+    }
+    Ok(())
+  }
+}
+
+// the first channel of every pixel of an image, integer formats are scaled to [0, 1]
+#[derive(Debug, Clone)]
+pub struct HeightmapImage {
+  width: i64,
+  height: i64,
+  samples: Vec<f32>,
+}
+
+impl HeightmapImage {
+  pub fn new(width: u32, height: u32, samples: Vec<f32>) -> Result<Self, TerrainError> {
+    if width == 0 || height == 0 || samples.len() != width as usize * height as usize {
+      return Err(TerrainError::InvalidConfig(
+        "heightmap samples don't match its size",
+      ));
+    }
+    Ok(Self {
+      width: width as i64,
+      height: height as i64,
+      samples,
+    })
+  }
+
+  // 8 and 16 bit integer and 32 bit float images are supported, which covers pngs and hdr files
+  pub fn from_image(image: &Image) -> Result<Self, TerrainError> {
+    let format = image.texture_descriptor.format;
+    let decode: fn(&[u8]) -> f32 = match format {
+      TextureFormat::R8Unorm
+      | TextureFormat::Rg8Unorm
+      | TextureFormat::Rgba8Unorm
+      | TextureFormat::Rgba8UnormSrgb
+      | TextureFormat::Bgra8Unorm
+      | TextureFormat::Bgra8UnormSrgb => |pixel| pixel[0] as f32 / u8::MAX as f32,
+      TextureFormat::R16Uint | TextureFormat::Rg16Uint | TextureFormat::Rgba16Uint => {
+        |pixel| u16::from_le_bytes([pixel[0], pixel[1]]) as f32 / u16::MAX as f32
+      }
+      TextureFormat::R32Float | TextureFormat::Rg32Float | TextureFormat::Rgba32Float => {
+        |pixel| f32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]])
+      }
+      _ => {
+        return Err(TerrainError::InvalidConfig(
+          "heightmap image format isn't supported",
+        ))
+      }
+    };
+    let size = image.texture_descriptor.size;
+    let samples = image
+      .data
+      .chunks_exact(format.pixel_size())
+      .take(size.width as usize * size.height as usize)
+      .map(decode)
+      .collect();
+    Self::new(size.width, size.height, samples)
+  }
+
+  // width and height in pixels
+  pub fn size(&self) -> (i64, i64) {
+    (self.width, self.height)
+  }
+
+  // the pixel at (x, y), pixels outside the image follow `edge`, `None` for flat edges
+  pub fn pixel(&self, x: i64, y: i64, edge: HeightmapEdge) -> Option<f32> {
+    let wrap = |value: i64, size: i64| match edge {
+      HeightmapEdge::Clamp => Some(value.clamp(0, size - 1)),
+      HeightmapEdge::Repeat => Some(value.rem_euclid(size)),
+      HeightmapEdge::Mirror => {
+        let value = value.rem_euclid(2 * size);
+        Some(if value < size {
+          value
+        } else {
+          2 * size - 1 - value
+        })
+      }
+      HeightmapEdge::Flat(_) => (0..size).contains(&value).then(|| value),
+    };
+    let (x, y) = (wrap(x, self.width)?, wrap(y, self.height)?);
+    Some(self.samples[(y * self.width + x) as usize])
+  }
+
+  // interpolated between the four nearest pixel centers, pixel (0, 0) is centered on (0, 0)
+  // `None` only past a flat edge, i.e. outside the outermost pixel centers
+  pub fn sample(&self, x: f64, y: f64, edge: HeightmapEdge) -> Option<f32> {
+    let edge = match edge {
+      HeightmapEdge::Flat(_) => {
+        let inside = |value: f64, size: i64| (0.0..=(size - 1) as f64).contains(&value);
+        if !inside(x, self.width) || !inside(y, self.height) {
+          return None;
+        }
+        // the last row and column have no pixel past them to blend toward
+        HeightmapEdge::Clamp
+      }
+      edge => edge,
+    };
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = ((x - x0) as f32, (y - y0) as f32);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+    let top = self.pixel(x0, y0, edge)? * (1. - tx) + self.pixel(x0 + 1, y0, edge)? * tx;
+    let bottom = self.pixel(x0, y0 + 1, edge)? * (1. - tx) + self.pixel(x0 + 1, y0 + 1, edge)? * tx;
+    Some(top * (1. - ty) + bottom * ty)
+  }
+}
+
+// a decoded heightmap with the settings it was decoded for
+struct HeightmapSurface {
+  heightmap: HeightmapImage,
+  settings: HeightmapSettings,
+  // the lowest the surface gets anywhere
+  lowest: f64,
+}
+
+impl HeightmapSurface {
+  fn new(heightmap: HeightmapImage, settings: HeightmapSettings) -> Self {
+    let (min, max) = heightmap
+      .samples
+      .iter()
+      .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), sample| {
+        (min.min(*sample), max.max(*sample))
+      });
+    let lowest = settings.base_height
+      + (min as f64 * settings.vertical_scale).min(max as f64 * settings.vertical_scale);
+    // columns past the image that have no pixel to sample
+    let outside = match settings.edge {
+      HeightmapEdge::Flat(height) => height,
+      _ => settings.base_height,
+    };
+    Self {
+      heightmap,
+      settings,
+      lowest: lowest.min(outside),
+    }
+  }
+
+  // surface height in voxels of the column at voxel (x, z), between columns it's interpolated
+  fn height(&self, x: f64, z: f64) -> f64 {
+    let settings = &self.settings;
+    let px = (x - settings.origin.0 as f64) / settings.voxels_per_pixel;
+    let py = (z - settings.origin.1 as f64) / settings.voxels_per_pixel;
+    match self.heightmap.sample(px, py, settings.edge) {
+      Some(value) => settings.base_height + value as f64 * settings.vertical_scale,
+      None => match settings.edge {
+        HeightmapEdge::Flat(height) => height,
+        _ => settings.base_height,
+      },
+    }
+  }
+}
+
+// the area of the world the terrain was made for, x and z in world space, published by terrains
+// that have an end e.g. heightmaps, cameras can use it to stay over the map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainExtents {
+  pub min: Vec2,
+  pub max: Vec2,
+}
+
+impl TerrainExtents {
+  // the voxel columns the image's pixels are sampled for
+  fn of_heightmap(
+    layout: &CubicVoxelLayout,
+    heightmap: &HeightmapImage,
+    settings: &HeightmapSettings,
+  ) -> Self {
+    let (width, height) = heightmap.size();
+    let columns =
+      |pixels: i64| ((pixels - 1) as f64 * settings.voxels_per_pixel).floor() as i64 + 1;
+    let (x, z) = settings.origin;
+    let min = layout.voxel_to_space(&VoxelId::new(x, 0, z));
+    let max = layout.voxel_to_space(&VoxelId::new(x + columns(width), 0, z + columns(height)));
+    Self {
+      min: Vec2::new(min.x, min.z),
+      max: Vec2::new(max.x, max.z),
+    }
+  }
+
+  pub fn contains(&self, point: Vec2) -> bool {
+    point.cmpge(self.min).all() && point.cmple(self.max).all()
+  }
+}
+
+type SharedSurface = Arc<Mutex<Option<Arc<HeightmapSurface>>>>;
+
+// streams terrain from a grayscale heightmap image instead of noise, e.g. real world elevation
+// data or a hand painted island, insert it together with its generator:
+//   let terrain = HeightmapTerrain::new(asset_server.load("island.png"), default());
+//   commands.insert_resource(ActiveGenerator::new(terrain.generator()));
+//   commands.insert_resource(terrain);
+// chunks spawned before the image is loaded stay empty, the terrain is generated again once it's
+// loaded and whenever the image or the settings change
+pub struct HeightmapTerrain {
+  pub image: Handle<Image>,
+  pub settings: HeightmapSettings,
+  surface: SharedSurface,
+}
+
+impl HeightmapTerrain {
+  pub fn new(image: Handle<Image>, settings: HeightmapSettings) -> Self {
+    Self {
+      image,
+      settings,
+      surface: default(),
+    }
+  }
+
+  // generates chunks from whatever image this resource last loaded
+  pub fn generator(&self) -> HeightmapGenerator {
+    HeightmapGenerator {
+      surface: self.surface.clone(),
+    }
+  }
+
+  pub fn is_loaded(&self) -> bool {
+    self.surface.lock().unwrap().is_some()
+  }
+
+  // world-space height of the smooth surface the voxels are cut from at world (x, z), None until
+  // the image is loaded
+  pub fn surface_height(&self, layout: &CubicVoxelLayout, x: f32, z: f32) -> Option<f32> {
+    let surface = self.surface.lock().unwrap().clone()?;
+    let side = layout.voxel_side_length();
+    let origin = layout.space_to_voxel(&Vec3::ZERO);
+    let height = surface.height(
+      (x / side) as f64 + origin.x() as f64,
+      (z / side) as f64 + origin.z() as f64,
+    );
+    Some((height as f32 - origin.y() as f32) * side)
+  }
+}
+
+// everything below the heightmap's surface is dirt
+pub struct HeightmapGenerator {
+  surface: SharedSurface,
+}
+
+impl TerrainGenerator for HeightmapGenerator {
+  fn load_voxel_data(
+    &self,
+    context: GenerationContext,
+    buffer: HashMap<VoxelId, VoxelTypeId>,
+  ) -> ChunkJob<ChunkVoxelData> {
+    let surface = self.surface.lock().unwrap().clone();
+    Box::new(move || {
+      let surface = match surface {
+        Some(surface) => surface,
+        None => return ChunkVoxelData { voxels: buffer },
+      };
+      if context.is_buried(&buffer, surface.lowest) {
+        return ChunkVoxelData {
+          voxels: bury(buffer),
+        };
+      }
+      let voxels = fill_columns(&context.pool, buffer, |x, z, column| {
+        fill_column(&context, &surface, x, z, column)
+      });
+
+      ChunkVoxelData { voxels }
+    })
+  }
+
+  fn load_voxel_steps(
+    &self,
+    context: GenerationContext,
+    buffer: HashMap<VoxelId, VoxelTypeId>,
+  ) -> ChunkStep<ChunkVoxelData> {
+    let surface = match self.surface.lock().unwrap().clone() {
+      Some(surface) => surface,
+      None => return ChunkStep::Done(ChunkVoxelData { voxels: buffer }),
+    };
+    if context.is_buried(&buffer, surface.lowest) {
+      return ChunkStep::Done(ChunkVoxelData {
+        voxels: bury(buffer),
+      });
+    }
+    fill_column_steps(buffer, move |x, z, column| {
+      fill_column(&context, &surface, x, z, column)
+    })
+    .map(|voxels| ChunkVoxelData { voxels })
+  }
+}
+
+fn fill_column(
+  context: &GenerationContext,
+  surface: &HeightmapSurface,
+  x: i64,
+  z: i64,
+  column: &mut [(VoxelId, VoxelTypeId)],
+) {
+  let height = context
+    .columns
+    .get_or_sample(x, z, || ColumnSample {
+      surface_height: surface.height(x as f64, z as f64),
+      ..Default::default()
+    })
+    .surface_height;
+  let clipped = context.clipped_voxels(height, column);
+  for (_, voxel_type) in column[..clipped].iter_mut() {
+    *voxel_type = VoxelTypeId::UNKNOWN;
+  }
+  for (voxel, voxel_type) in column[clipped..].iter_mut() {
+    if (voxel.y() as f64) < height {
+      *voxel_type = VoxelTypeId::DIRT;
+    }
+  }
+}
+
+// decodes the heightmap image once it's loaded and again whenever it or the settings change
+// and publishes the area it covers as `TerrainExtents`
+pub fn load_heightmap_terrain(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  terrain: Option<Res<HeightmapTerrain>>,
+  images: Res<Assets<Image>>,
+  mut image_events: EventReader<AssetEvent<Image>>,
+  mut regenerate: EventWriter<RegenerateTerrain>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  let terrain = match terrain {
+    Some(terrain) => terrain,
+    None => return,
+  };
+  let image_changed = image_events.iter().any(|event| match event {
+    AssetEvent::Created { handle } | AssetEvent::Modified { handle } => *handle == terrain.image,
+    AssetEvent::Removed { .. } => false,
+  });
+  if !image_changed && !terrain.is_changed() {
+    return;
+  }
+  // not loaded yet, there'll be an event once it is
+  let image = match images.get(&terrain.image) {
+    Some(image) => image,
+    None => return,
+  };
+
+  let heightmap = terrain
+    .settings
+    .validate()
+    .and_then(|_| HeightmapImage::from_image(image));
+  match heightmap {
+    Ok(heightmap) => {
+      commands.insert_resource(TerrainExtents::of_heightmap(
+        &layout,
+        &heightmap,
+        &terrain.settings,
+      ));
+      *terrain.surface.lock().unwrap() = Some(Arc::new(HeightmapSurface::new(
+        heightmap,
+        terrain.settings.clone(),
+      )));
+      regenerate.send(RegenerateTerrain);
+    }
+    Err(error) => errors.send(TerrainErrorEvent::new(error, "loading the heightmap")),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::ChunkId;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn edges_should_wrap_into_the_image(width in 1u32..8, height in 1u32..8, x in -50i64..50, y in -50i64..50) {
+          let samples: Vec<_> = (0..width * height).map(|i| i as f32).collect();
+          let heightmap = HeightmapImage::new(width, height, samples).unwrap();
+          let inside = |x: i64, y: i64| (0..width as i64).contains(&x) && (0..height as i64).contains(&y);
+
+          for edge in [HeightmapEdge::Clamp, HeightmapEdge::Repeat, HeightmapEdge::Mirror] {
+              assert!(heightmap.pixel(x, y, edge).is_some());
+          }
+          assert_eq!(heightmap.pixel(x, y, HeightmapEdge::Flat(0.)).is_some(), inside(x, y));
+          if inside(x, y) {
+              let expected = Some((y * width as i64 + x) as f32);
+              assert_eq!(heightmap.pixel(x, y, HeightmapEdge::Clamp), expected);
+              assert_eq!(heightmap.pixel(x, y, HeightmapEdge::Mirror), expected);
+              assert_eq!(heightmap.sample(x as f64, y as f64, HeightmapEdge::Repeat), expected);
+              assert_eq!(heightmap.sample(x as f64, y as f64, HeightmapEdge::Flat(0.)), expected);
+          }
+          // mirrored tiles meet their neighbors with the same pixel
+          let mirrored = heightmap.pixel(-1 - x, y, HeightmapEdge::Mirror);
+          assert_eq!(mirrored, heightmap.pixel(x, y, HeightmapEdge::Mirror));
+      }
+
+      #[test]
+      fn surface_should_follow_the_pixels(value in 0u8..=255, scale in 1f64..64., x in 0i64..4, z in 0i64..4) {
+          let mut image = Image::default();
+          image.texture_descriptor.size.width = 4;
+          image.texture_descriptor.size.height = 4;
+          image.texture_descriptor.format = TextureFormat::R8Unorm;
+          image.data = vec![value; 16];
+          let surface = HeightmapSurface::new(
+              HeightmapImage::from_image(&image).unwrap(),
+              HeightmapSettings { base_height: 2., vertical_scale: scale, ..Default::default() },
+          );
+          let expected = 2. + (value as f32 / 255.) as f64 * scale;
+          assert!((surface.height(x as f64, z as f64) - expected).abs() < 1e-4);
+          assert!(surface.lowest <= surface.height(x as f64, z as f64) + 1e-4);
+      }
+
+      #[test]
+      fn extents_should_cover_every_pixel(width in 1u32..8, height in 1u32..8, voxels_per_pixel in 0.5f64..4., x in -20i64..20, z in -20i64..20) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 0.5, 2, 4);
+          let heightmap = HeightmapImage::new(width, height, vec![0.; (width * height) as usize]).unwrap();
+          let settings = HeightmapSettings { voxels_per_pixel, origin: (x, z), ..Default::default() };
+          let extents = TerrainExtents::of_heightmap(&layout, &heightmap, &settings);
+          for (px, py) in [(0, 0), (width - 1, height - 1)] {
+              let column = VoxelId::new(
+                  x + (px as f64 * voxels_per_pixel).floor() as i64,
+                  0,
+                  z + (py as f64 * voxels_per_pixel).floor() as i64,
+              );
+              let center = layout.voxel_center(&column);
+              prop_assert!(extents.contains(Vec2::new(center.x, center.z)));
+          }
+      }
+  }
+}
@@ -0,0 +1,290 @@
+use super::{ChunkId, VoxelId};
+use bevy::prelude::*;
+
+// axial directions ordered counter-clockwise starting from +x
+// side `i` of a hex (between corners `i` and `i + 1`) faces direction `i`
+pub const HEX_DIRECTIONS: [(i64, i64); 6] = [(1, 0), (0, 1), (-1, 1), (-1, 0), (0, -1), (1, -1)];
+
+const SQRT_3: f32 = 1.732_050_8;
+
+// pointy-top hex columns addressed by axial coordinates, a voxel id is (q, y, r)
+// chunks are hexagons of hex columns with a radius of `chunk_radius` hexes, so the x and z of
+// chunk ids are themselves axial coordinates on the (coarser) grid of chunk centers, y is the
+// vertical section
+#[derive(Debug, Clone)]
+pub struct CubeHexLayout {
+  // distance from a hex center to its corners
+  hex_size: f32,
+  voxel_height: f32,
+  chunk_radius: i64,
+  chunk_voxel_height: i64,
+}
+
+impl CubeHexLayout {
+  pub fn new(hex_size: f32, voxel_height: f32, chunk_radius: i64, chunk_voxel_height: i64) -> Self {
+    Self {
+      hex_size,
+      voxel_height,
+      chunk_radius,
+      chunk_voxel_height,
+    }
+  }
+
+  #[inline]
+  pub fn hex_size(&self) -> f32 {
+    self.hex_size
+  }
+
+  #[inline]
+  pub fn voxel_height(&self) -> f32 {
+    self.voxel_height
+  }
+
+  #[inline]
+  pub fn chunk_radius(&self) -> i64 {
+    self.chunk_radius
+  }
+
+  #[inline]
+  pub fn chunk_voxel_height(&self) -> i64 {
+    self.chunk_voxel_height
+  }
+
+  #[inline]
+  pub fn hexes_per_chunk(&self) -> i64 {
+    3 * self.chunk_radius * self.chunk_radius + 3 * self.chunk_radius + 1
+  }
+
+  // offsets between the center hexes of chunks adjacent along the chunk grid's q and r axes
+  #[inline]
+  fn chunk_basis(&self) -> ((i64, i64), (i64, i64)) {
+    let r = self.chunk_radius;
+    ((2 * r + 1, -r), (r, r + 1))
+  }
+
+  pub fn get_center_voxel(&self, chunk: &ChunkId) -> VoxelId {
+    let ((aq, ar), (bq, br)) = self.chunk_basis();
+    VoxelId::new(
+      chunk.x() * aq + chunk.z() * bq,
+      chunk.y() * self.chunk_voxel_height,
+      chunk.x() * ar + chunk.z() * br,
+    )
+  }
+
+  // number of steps between two hex columns, ignoring height
+  pub fn hex_distance(a: &VoxelId, b: &VoxelId) -> i64 {
+    let dq = a.x() - b.x();
+    let dr = a.z() - b.z();
+    (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+  }
+
+  pub fn hex_neighbor(voxel: &VoxelId, side: usize) -> VoxelId {
+    let (dq, dr) = HEX_DIRECTIONS[side];
+    *voxel + VoxelId::new(dq, 0, dr)
+  }
+
+  // axial coordinates exactly `radius` steps away from `center`, for hex columns or chunk columns
+  pub fn ring(center: (i64, i64), radius: i64) -> HexRing {
+    let (sq, sr) = HEX_DIRECTIONS[4];
+    HexRing {
+      position: (center.0 + sq * radius, center.1 + sr * radius),
+      radius: radius.max(0),
+      side: 0,
+      step: 0,
+    }
+  }
+
+  // `center` followed by the rings around it out to `radius`, nearest first
+  pub fn spiral(center: (i64, i64), radius: i64) -> impl Iterator<Item = (i64, i64)> {
+    (0..=radius).flat_map(move |ring| Self::ring(center, ring))
+  }
+
+  pub fn get_chunk_neighbors(&self, chunk: &ChunkId, distance: i64) -> Vec<ChunkId> {
+    let center = (chunk.x(), chunk.z());
+    Self::spiral(center, distance)
+      .skip(1)
+      .map(|(x, z)| ChunkId::new(x, chunk.y(), z))
+      .collect()
+  }
+
+  // rings of chunks between two columns on the chunk grid, vertical sections aren't counted
+  pub fn chunk_step_distance(&self, a: &ChunkId, b: &ChunkId) -> i64 {
+    let (dx, dz) = (a.x() - b.x(), a.z() - b.z());
+    (dx.abs() + dz.abs() + (dx + dz).abs()) / 2
+  }
+
+  // world units between the centers of two chunks
+  pub fn world_distance(&self, a: &ChunkId, b: &ChunkId) -> f32 {
+    (self.chunk_to_space(a) - self.chunk_to_space(b)).length()
+  }
+
+  pub fn get_chunk_voxels(&self, chunk: &ChunkId) -> Vec<VoxelId> {
+    self.iter_chunk_voxels(chunk).collect()
+  }
+
+  // same as `get_chunk_voxels` without collecting them first
+  pub fn iter_chunk_voxels(&self, chunk: &ChunkId) -> impl Iterator<Item = VoxelId> {
+    let center = self.get_center_voxel(chunk);
+    let height = self.chunk_voxel_height;
+    Self::spiral((center.x(), center.z()), self.chunk_radius)
+      .flat_map(move |(q, r)| (0..height).map(move |y| VoxelId::new(q, center.y() + y, r)))
+  }
+
+  pub fn voxel_to_chunk(&self, voxel: &VoxelId) -> ChunkId {
+    // invert the chunk basis to get an approximate chunk, then pick the chunk among it and its
+    // neighbors whose center is within the chunk radius
+    let ((aq, ar), (bq, br)) = self.chunk_basis();
+    let n = self.hexes_per_chunk() as f64;
+    let (q, r) = (voxel.x() as f64, voxel.z() as f64);
+    let a = ((br as f64) * q - (bq as f64) * r) / n;
+    let b = ((aq as f64) * r - (ar as f64) * q) / n;
+    let approx = (a.round() as i64, b.round() as i64);
+    let section = voxel.y().div_euclid(self.chunk_voxel_height.max(1));
+
+    Self::spiral(approx, 1)
+      .map(|(x, z)| ChunkId::new(x, section, z))
+      .find(|chunk| Self::hex_distance(voxel, &self.get_center_voxel(chunk)) <= self.chunk_radius)
+      .expect("hex chunks should tile the plane")
+  }
+
+  pub fn voxel_to_space(&self, voxel: &VoxelId) -> Vec3 {
+    let q = voxel.x() as f32;
+    let r = voxel.z() as f32;
+    Vec3::new(
+      self.hex_size * SQRT_3 * (q + r * 0.5),
+      voxel.y() as f32 * self.voxel_height,
+      self.hex_size * 1.5 * r,
+    )
+  }
+
+  pub fn space_to_voxel(&self, space: &Vec3) -> VoxelId {
+    let q = (SQRT_3 / 3.0 * space.x - space.z / 3.0) / self.hex_size;
+    let r = (2.0 / 3.0 * space.z) / self.hex_size;
+
+    // round in cube coordinates, fixing up the component with the largest rounding error
+    let s = -q - r;
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+      rq = -rr - rs;
+    } else if dr > ds {
+      rr = -rq - rs;
+    }
+
+    let y = (space.y / self.voxel_height).floor();
+    VoxelId::new(rq as i64, y as i64, rr as i64)
+  }
+
+  pub fn chunk_to_space(&self, chunk: &ChunkId) -> Vec3 {
+    self.voxel_to_space(&self.get_center_voxel(chunk))
+  }
+
+  pub fn space_to_chunk(&self, space: &Vec3) -> ChunkId {
+    self.voxel_to_chunk(&self.space_to_voxel(space))
+  }
+
+  // corner `i` of a hex centered on the origin, corners are ordered counter-clockwise
+  pub fn hex_corner(&self, i: usize) -> Vec3 {
+    let angle = (60.0 * i as f32 - 30.0).to_radians();
+    Vec3::new(self.hex_size * angle.cos(), 0., self.hex_size * angle.sin())
+  }
+}
+impl Default for CubeHexLayout {
+  fn default() -> Self {
+    Self::new(1.0, 1.0, 6, 10)
+  }
+}
+
+// walks a ring one side at a time, starting at the corner in direction 4, see `CubeHexLayout::ring`
+#[derive(Debug, Clone)]
+pub struct HexRing {
+  position: (i64, i64),
+  radius: i64,
+  side: usize,
+  step: i64,
+}
+
+impl Iterator for HexRing {
+  type Item = (i64, i64);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    if self.side >= 6 {
+      return None;
+    }
+    let current = self.position;
+    // a ring of radius 0 is just the center
+    if self.radius == 0 {
+      self.side = 6;
+      return Some(current);
+    }
+    let (dq, dr) = HEX_DIRECTIONS[self.side];
+    self.position = (current.0 + dq, current.1 + dr);
+    self.step += 1;
+    if self.step == self.radius {
+      self.step = 0;
+      self.side += 1;
+    }
+    Some(current)
+  }
+
+  fn size_hint(&self) -> (usize, Option<usize>) {
+    let remaining = match self.radius {
+      0 => usize::from(self.side < 6),
+      radius => ((6 - self.side as i64) * radius - self.step) as usize,
+    };
+    (remaining, Some(remaining))
+  }
+}
+impl ExactSizeIterator for HexRing {}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+  use std::collections::HashSet;
+
+  proptest! {
+      #[test]
+      fn chunk_voxels_should_resolve_to_chunk(x in -1000i64..=1000, y in -1000i64..=1000, radius in 0i64..=8) {
+          let layout = CubeHexLayout::new(1.0, 1.0, radius, 1);
+          let chunk = ChunkId::new(x, 0, y);
+          let voxels = layout.get_chunk_voxels(&chunk);
+          assert_eq!(voxels.len() as i64, layout.hexes_per_chunk());
+          for voxel in voxels {
+              assert_eq!(layout.voxel_to_chunk(&voxel), chunk, "voxel {:?}", voxel);
+          }
+      }
+
+      #[test]
+      fn chunk_should_have_appropriate_number_of_neighbors(x in -1000i64..=1000, y in -1000i64..=1000, distance in 1i64..10) {
+          let layout = CubeHexLayout::default();
+          let chunk = ChunkId::new(x, 0, y);
+          let neighbors = layout.get_chunk_neighbors(&chunk, distance);
+          assert_eq!(neighbors.len() as i64, 3 * distance * (distance + 1));
+          for neighbor in neighbors {
+              assert!((1..=distance).contains(&layout.chunk_step_distance(&chunk, &neighbor)));
+          }
+      }
+
+      #[test]
+      fn rings_should_surround_the_center(q in -1000i64..=1000, r in -1000i64..=1000, radius in 0i64..10) {
+          let center = VoxelId::new(q, 0, r);
+          let ring = CubeHexLayout::ring((q, r), radius);
+          assert_eq!(ring.len() as i64, (6 * radius).max(1));
+          let hexes: HashSet<_> = ring.map(|(q, r)| VoxelId::new(q, 0, r)).collect();
+          assert_eq!(hexes.len() as i64, (6 * radius).max(1));
+          for hex in hexes.iter() {
+              assert_eq!(CubeHexLayout::hex_distance(&center, hex), radius);
+          }
+          assert_eq!(CubeHexLayout::spiral((q, r), radius).count() as i64, 3 * radius * (radius + 1) + 1);
+      }
+
+      #[test]
+      fn voxel_space_coordinates_should_be_reversible(q in -10000i64..=10000, y in 0i64..50, r in -10000i64..=10000, size in 0.5f32..10.0) {
+          let layout = CubeHexLayout::new(size, 1.0, 6, 50);
+          let voxel = VoxelId::new(q, y, r);
+          let space = layout.voxel_to_space(&voxel) + Vec3::new(0., 0.5, 0.);
+          assert_eq!(layout.space_to_voxel(&space), voxel);
+      }
+  }
+}
@@ -0,0 +1,328 @@
+use super::{
+  chunk_material,
+  generator::WorldGenConfig,
+  hex::CubeHexLayout,
+  lod::{ChunkLod, ChunkLodSettings},
+  mesher::{self, HexMeshOptions, NormalMode},
+  registry::{VoxelRegistry, VoxelTypeId},
+  terrain_set,
+  tracker::ChunkTracker,
+  ChunkId, ChunkVoxelData, TerrainSystem,
+};
+use bevy::{
+  prelude::*,
+  tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use std::collections::HashMap;
+
+// streams a hex map laid out by the `CubeHexLayout` resource around `HexSpawner`s
+// add it after the terrain plugin, hex chunks use its registry, world gen config, lod settings and
+// material
+// like the planet, hex chunks are spawned, generated and meshed by the systems here and have a
+// `HexChunk` in place of a `Chunk`
+#[derive(Default)]
+pub struct HexTerrainPlugin;
+
+impl Plugin for HexTerrainPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<CubeHexLayout>()
+      .init_resource::<HexTerrainSettings>()
+      .init_resource::<HexChunks>()
+      .add_system_set(
+        terrain_set(TerrainSystem::Generate)
+          .after(TerrainSystem::Spawn)
+          .with_system(spawn_hex_chunks)
+          .with_system(finish_hex_voxels.after(spawn_hex_chunks)),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Mesh)
+          .after(TerrainSystem::Track)
+          .with_system(mesh_hex_chunks),
+      );
+  }
+}
+
+pub struct HexTerrainSettings {
+  // rings of chunks loaded around each spawner, chunks a ring farther out are kept until the
+  // spawner moves on
+  pub spawn_radius: i64,
+  pub spawns_per_frame: usize,
+  pub meshes_per_frame: usize,
+  // chunks at this `ChunkLod` or lower detail are meshed as hex caps without walls
+  pub caps_only_lod: u8,
+}
+impl Default for HexTerrainSettings {
+  fn default() -> Self {
+    Self {
+      spawn_radius: 4,
+      spawns_per_frame: 4,
+      meshes_per_frame: 4,
+      caps_only_lod: 2,
+    }
+  }
+}
+
+// the loaded chunks of the hex map and their entities
+// hex maps are a single section tall, `CubeHexLayout::chunk_voxel_height` is as tall as columns get
+#[derive(Default)]
+pub struct HexChunks {
+  tracker: ChunkTracker,
+}
+
+impl HexChunks {
+  pub fn entity(&self, chunk: &ChunkId) -> Option<Entity> {
+    self.tracker.entity(chunk)
+  }
+
+  pub fn is_loaded(&self, chunk: &ChunkId) -> bool {
+    self.tracker.is_loaded(chunk)
+  }
+
+  pub fn len(&self) -> usize {
+    self.tracker.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.tracker.is_empty()
+  }
+}
+
+// loads the hex map's chunks around the entity, e.g. the camera of a strategy game
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct HexSpawner;
+
+// a chunk of the hex map, its entity sits at `chunk_to_space`
+#[derive(Debug, Clone, Copy, Component)]
+pub struct HexChunk {
+  pub id: ChunkId,
+}
+
+#[derive(Component)]
+struct HexVoxelTask(Task<ChunkVoxelData>);
+
+#[derive(Component)]
+struct HexMeshTask(Task<Mesh>);
+
+// marks a chunk whose voxels, neighbors or lod changed since it was meshed
+#[derive(Debug, Default, Component)]
+struct HexChunkDirty;
+
+// the voxels of a chunk of the hex map, the world gen noise sampled at the center of each column
+// so the ground is `base_height` voxels high give or take `amplitude`
+pub fn generate_hex_voxels(
+  layout: &CubeHexLayout,
+  config: &WorldGenConfig,
+  chunk: &ChunkId,
+) -> ChunkVoxelData {
+  let noise = Fbm::new()
+    .set_seed(config.seed as u32)
+    .set_octaves(config.octaves.clamp(1, Fbm::MAX_OCTAVES))
+    .set_persistence(config.persistence)
+    .set_lacunarity(config.lacunarity);
+  let mut heights = HashMap::new();
+  let voxels = layout
+    .iter_chunk_voxels(chunk)
+    .map(|voxel| {
+      let height = *heights.entry((voxel.x(), voxel.z())).or_insert_with(|| {
+        let center = layout.voxel_to_space(&voxel);
+        let point = [
+          center.x as f64 / config.scale,
+          center.z as f64 / config.scale,
+        ];
+        config.base_height + noise.get(point) * config.amplitude
+      });
+      let voxel_type = match (voxel.y() as f64) < height {
+        true => VoxelTypeId::DIRT,
+        false => VoxelTypeId::AIR,
+      };
+      (voxel, voxel_type)
+    })
+    .collect();
+  ChunkVoxelData { voxels }
+}
+
+// spawns the chunks around the spawners nearest first, despawns the ones they left behind and
+// moves the rest to the lod of their distance to the nearest spawner
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_hex_chunks(
+  mut commands: Commands,
+  thread_pool: Res<AsyncComputeTaskPool>,
+  time: Res<Time>,
+  layout: Res<CubeHexLayout>,
+  config: Res<WorldGenConfig>,
+  lod_settings: Res<ChunkLodSettings>,
+  settings: Res<HexTerrainSettings>,
+  mut chunks: ResMut<HexChunks>,
+  spawners: Query<&Transform, With<HexSpawner>>,
+  lods: Query<&ChunkLod, With<HexChunk>>,
+) {
+  let now = time.seconds_since_startup();
+  // rings to the nearest spawner of every chunk that's kept
+  let mut kept: HashMap<ChunkId, i64> = HashMap::new();
+  for transform in spawners.iter() {
+    let center = layout.space_to_chunk(&transform.translation).with_y(0);
+    let nearby =
+      std::iter::once(center).chain(layout.get_chunk_neighbors(&center, settings.spawn_radius + 1));
+    for chunk in nearby {
+      let rings = layout.chunk_step_distance(&center, &chunk);
+      let nearest = kept.entry(chunk).or_insert(rings);
+      *nearest = rings.min(*nearest);
+    }
+  }
+
+  let loaded: Vec<ChunkId> = chunks.tracker.iter().copied().collect();
+  for chunk in loaded {
+    let entity = match chunks.entity(&chunk) {
+      Some(entity) => entity,
+      None => continue,
+    };
+    match kept.get(&chunk) {
+      Some(rings) => {
+        let lod = ChunkLod(lod_settings.lod(*rings, 0.));
+        if lods.get(entity).ok() != Some(&lod) {
+          commands.entity(entity).insert(lod).insert(HexChunkDirty);
+        }
+      }
+      None => {
+        chunks.tracker.try_despawn(&chunk, now, 0.);
+        commands.entity(entity).despawn_recursive();
+      }
+    }
+  }
+
+  let mut wanted: Vec<(i64, ChunkId)> = kept
+    .into_iter()
+    .filter(|(chunk, rings)| *rings <= settings.spawn_radius && !chunks.is_loaded(chunk))
+    .map(|(chunk, rings)| (rings, chunk))
+    .collect();
+  wanted.sort_by_key(|(rings, chunk)| (*rings, chunk.x(), chunk.z()));
+  for (rings, chunk) in wanted.into_iter().take(settings.spawns_per_frame) {
+    let (task_layout, config) = ((*layout).clone(), config.clone());
+    let task = thread_pool.spawn(async move { generate_hex_voxels(&task_layout, &config, &chunk) });
+    let entity = commands
+      .spawn()
+      .insert(Transform::from_translation(layout.chunk_to_space(&chunk)))
+      .insert(GlobalTransform::default())
+      .insert(HexChunk { id: chunk })
+      .insert(ChunkLod(lod_settings.lod(rings, 0.)))
+      .insert(HexVoxelTask(task))
+      .id();
+    chunks.tracker.try_spawn(&chunk, now);
+    chunks.tracker.register_entity(chunk, entity);
+  }
+}
+
+// neighbors meshed before the voxels of a chunk arrived walled it off, they're meshed again
+fn finish_hex_voxels(
+  mut commands: Commands,
+  layout: Res<CubeHexLayout>,
+  chunks: Res<HexChunks>,
+  mut tasks: Query<(Entity, &HexChunk, &mut HexVoxelTask)>,
+) {
+  for (entity, chunk, mut task) in tasks.iter_mut() {
+    let voxel_data = match future::block_on(future::poll_once(&mut task.0)) {
+      Some(voxel_data) => voxel_data,
+      None => continue,
+    };
+    commands
+      .entity(entity)
+      .remove::<HexVoxelTask>()
+      .insert(voxel_data)
+      .insert(HexChunkDirty);
+    for neighbor in layout
+      .get_chunk_neighbors(&chunk.id, 1)
+      .iter()
+      .filter_map(|neighbor| chunks.entity(neighbor))
+    {
+      commands.entity(neighbor).insert(HexChunkDirty);
+    }
+  }
+}
+
+// walls towards loaded neighbors at least as tall are culled, far chunks are meshed without walls
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn mesh_hex_chunks(
+  mut commands: Commands,
+  thread_pool: Res<AsyncComputeTaskPool>,
+  layout: Res<CubeHexLayout>,
+  registry: Res<VoxelRegistry>,
+  normal_mode: Res<NormalMode>,
+  settings: Res<HexTerrainSettings>,
+  chunks: Res<HexChunks>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  voxels: Query<&ChunkVoxelData, With<HexChunk>>,
+  dirty: Query<
+    (Entity, &HexChunk, &ChunkVoxelData, Option<&ChunkLod>),
+    (With<HexChunkDirty>, Without<HexMeshTask>),
+  >,
+  mut tasks: Query<(Entity, &mut HexMeshTask)>,
+) {
+  for (entity, mut task) in tasks.iter_mut() {
+    if let Some(mesh) = future::block_on(future::poll_once(&mut task.0)) {
+      // not a bundle, it would move the chunk back to the origin
+      commands
+        .entity(entity)
+        .remove::<HexMeshTask>()
+        .insert(meshes.add(mesh))
+        .insert(chunk_material())
+        .insert(Visibility::default())
+        .insert(ComputedVisibility::default());
+    }
+  }
+
+  for (entity, chunk, voxel_data, lod) in dirty.iter().take(settings.meshes_per_frame) {
+    let caps_only = lod.copied().unwrap_or_default().0 >= settings.caps_only_lod;
+    let neighbor_heights = match caps_only {
+      true => HashMap::new(),
+      false => layout
+        .get_chunk_neighbors(&chunk.id, 1)
+        .iter()
+        .filter_map(|neighbor| voxels.get(chunks.entity(neighbor)?).ok())
+        .flat_map(|neighbor| mesher::hex_column_heights(&registry, &neighbor.voxels))
+        .collect(),
+    };
+    let job = mesher::generate_hex_mesh_with(
+      &layout,
+      &registry,
+      chunk.id,
+      &voxel_data.voxels,
+      HexMeshOptions {
+        neighbor_heights,
+        caps_only,
+      },
+      *normal_mode,
+    );
+    commands
+      .entity(entity)
+      .remove::<HexChunkDirty>()
+      .insert(HexMeshTask(thread_pool.spawn(async move { job() })));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn hex_chunks_should_be_solid_up_to_their_surface(x in -4i64..4, z in -4i64..4, seed in any::<u64>()) {
+          let layout = CubeHexLayout::new(1.0, 1.0, 3, 10);
+          let config = WorldGenConfig { seed, ..Default::default() };
+          let chunk = ChunkId::new(x, 0, z);
+          let voxels = generate_hex_voxels(&layout, &config, &chunk).voxels;
+          prop_assert_eq!(voxels.len() as i64, layout.hexes_per_chunk() * 10);
+
+          // columns are solid from the bottom up and air above
+          for (voxel, voxel_type) in voxels.iter() {
+              if *voxel_type == VoxelTypeId::DIRT && voxel.y() > 0 {
+                  let below = *voxel - crate::voxel::VoxelId::new(0, 1, 0);
+                  prop_assert_eq!(voxels.get(&below), Some(&VoxelTypeId::DIRT));
+              }
+          }
+      }
+  }
+}
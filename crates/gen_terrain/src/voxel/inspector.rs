@@ -0,0 +1,155 @@
+use super::{
+  diagnostics::TerrainDiagnosticsPlugin, generator::WorldGenConfig, layout::CubicVoxelLayout,
+  lod::ChunkLodSettings, regen::RegenerateTerrain, retention::ChunkRetentionPolicy,
+};
+use bevy::{diagnostic::Diagnostics, prelude::*};
+use bevy_egui::{egui, EguiContext, EguiPlugin};
+
+// side panel for tuning generation, layout and streaming parameters while the app runs
+pub struct TerrainInspectorPlugin;
+
+impl Plugin for TerrainInspectorPlugin {
+  fn build(&self, app: &mut App) {
+    if !app.world.contains_resource::<EguiContext>() {
+      app.add_plugin(EguiPlugin);
+    }
+    app.add_system(terrain_inspector_ui);
+  }
+}
+
+#[derive(Default)]
+pub struct InspectorState {
+  // generation parameters being edited, applied on regenerate or as they change when `live`
+  draft: Option<WorldGenConfig>,
+  live: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn terrain_inspector_ui(
+  mut state: Local<InspectorState>,
+  mut egui_context: ResMut<EguiContext>,
+  layout: Res<CubicVoxelLayout>,
+  mut config: ResMut<WorldGenConfig>,
+  mut lod: ResMut<ChunkLodSettings>,
+  mut retention: ResMut<ChunkRetentionPolicy>,
+  mut regenerate: EventWriter<RegenerateTerrain>,
+  diagnostics: Option<Res<Diagnostics>>,
+) {
+  let InspectorState { draft, live } = &mut *state;
+  let draft = draft.get_or_insert_with(|| config.clone());
+
+  egui::SidePanel::left("terrain_inspector").show(egui_context.ctx_mut(), |ui| {
+    ui.heading("Generation");
+    let mut seed = draft.seed as i64;
+    ui.add(egui::DragValue::new(&mut seed).prefix("seed "));
+    draft.seed = seed as u64;
+    ui.add(egui::Slider::new(&mut draft.scale, 1.0..=512.0).text("scale"));
+    ui.add(egui::Slider::new(&mut draft.base_height, 0.0..=64.0).text("base height"));
+    ui.add(egui::Slider::new(&mut draft.amplitude, 0.0..=64.0).text("amplitude"));
+    ui.add(egui::Slider::new(&mut draft.octaves, 1..=12).text("octaves"));
+    ui.add(egui::Slider::new(&mut draft.persistence, 0.0..=1.0).text("persistence"));
+    ui.add(egui::Slider::new(&mut draft.lacunarity, 1.0..=4.0).text("lacunarity"));
+    ui.checkbox(live, "regenerate as values change");
+    let apply = ui.button("Regenerate").clicked();
+
+    if *draft != *config && (apply || *live) {
+      // the config change regenerates the terrain
+      *config = draft.clone();
+    } else if apply {
+      regenerate.send(RegenerateTerrain);
+    }
+
+    ui.separator();
+    ui.heading("Layout");
+    ui.label(format!("voxel size {}", layout.voxel_side_length()));
+    ui.label(format!("chunk size {}", layout.chunk_side_length()));
+    ui.label(format!(
+      "chunk height {} voxels",
+      layout.chunk_voxel_height()
+    ));
+
+    ui.separator();
+    ui.heading("Streaming");
+    let mut mesh_radius = lod.mesh_radius;
+    let mut zoom_spawn_radius = lod.zoom_spawn_radius;
+    let mut rings_per_lod = lod.rings_per_lod;
+    let mut max_lod = lod.max_lod;
+    ui.add(egui::Slider::new(&mut mesh_radius, 0..=8).text("mesh radius"));
+    ui.add(egui::Slider::new(&mut zoom_spawn_radius, 0..=8).text("zoomed out extra radius"));
+    ui.add(egui::Slider::new(&mut rings_per_lod, 1..=8).text("rings per lod"));
+    ui.add(egui::Slider::new(&mut max_lod, 0..=8).text("max lod"));
+    if (mesh_radius, zoom_spawn_radius, rings_per_lod, max_lod)
+      != (
+        lod.mesh_radius,
+        lod.zoom_spawn_radius,
+        lod.rings_per_lod,
+        lod.max_lod,
+      )
+    {
+      lod.mesh_radius = mesh_radius;
+      lod.zoom_spawn_radius = zoom_spawn_radius;
+      lod.rings_per_lod = rings_per_lod;
+      lod.max_lod = max_lod;
+    }
+
+    ui.separator();
+    ui.heading("Retention");
+    let mut despawn_ring_margin = retention.despawn_ring_margin;
+    let mut min_resident_seconds = retention.min_resident_seconds;
+    let mut base_grace_seconds = retention.base_grace_seconds;
+    ui.add(egui::Slider::new(&mut despawn_ring_margin, 0..=8).text("despawn margin"));
+    ui.add(egui::Slider::new(&mut min_resident_seconds, 0.0..=60.0).text("min resident seconds"));
+    ui.add(egui::Slider::new(&mut base_grace_seconds, 0.0..=30.0).text("grace seconds"));
+    if (
+      despawn_ring_margin,
+      min_resident_seconds,
+      base_grace_seconds,
+    ) != (
+      retention.despawn_ring_margin,
+      retention.min_resident_seconds,
+      retention.base_grace_seconds,
+    ) {
+      retention.despawn_ring_margin = despawn_ring_margin;
+      retention.min_resident_seconds = min_resident_seconds;
+      retention.base_grace_seconds = base_grace_seconds;
+    }
+
+    // only there when `TerrainDiagnosticsPlugin` is added
+    let diagnostics = match &diagnostics {
+      Some(diagnostics) => diagnostics,
+      None => return,
+    };
+    let readings: Vec<_> = [
+      (TerrainDiagnosticsPlugin::CHUNKS_LOADED, "chunks loaded", ""),
+      (
+        TerrainDiagnosticsPlugin::CHUNKS_MESHED_PER_SECOND,
+        "chunks meshed",
+        "/s",
+      ),
+      (
+        TerrainDiagnosticsPlugin::VOXEL_GENERATION_MS,
+        "voxel generation",
+        " ms",
+      ),
+      (
+        TerrainDiagnosticsPlugin::MESH_GENERATION_MS,
+        "mesh generation",
+        " ms",
+      ),
+      (TerrainDiagnosticsPlugin::PENDING_TASKS, "pending tasks", ""),
+    ]
+    .into_iter()
+    .filter_map(|(id, name, suffix)| {
+      let value = diagnostics.get(id)?.average()?;
+      Some(format!("{} {:.1}{}", name, value, suffix))
+    })
+    .collect();
+    if !readings.is_empty() {
+      ui.separator();
+      ui.heading("Diagnostics");
+      for reading in readings {
+        ui.label(reading);
+      }
+    }
+  });
+}
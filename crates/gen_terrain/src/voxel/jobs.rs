@@ -0,0 +1,274 @@
+use super::{
+  layout::CubicVoxelLayout, prediction::ChunkSpawnerConfig, tracker::ChunkTracker, ChunkId,
+  ChunkVoxelData,
+};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TerrainJobId(pub u64);
+
+// a batch of chunks of the primary world that are kept loaded until their voxels are generated,
+// e.g. the area around a spawn point or everything a regeneration threw away
+// pregeneration jobs don't load their chunks, they're done once they're saved to the store
+#[derive(Debug, Clone)]
+pub struct TerrainJob {
+  pub name: String,
+  // chunks of the job are spawned with the budget streaming anchors get, higher priorities first
+  pub priority: u8,
+  remaining: HashSet<ChunkId>,
+  total: usize,
+  loads_chunks: bool,
+  // what the last `TerrainJobProgress` said was done
+  reported: Option<usize>,
+}
+
+impl TerrainJob {
+  pub fn done(&self) -> usize {
+    self.total - self.remaining.len()
+  }
+
+  pub fn total(&self) -> usize {
+    self.total
+  }
+
+  // in [0, 1], jobs without chunks are done
+  pub fn fraction(&self) -> f32 {
+    match self.total {
+      0 => 1.,
+      total => self.done() as f32 / total as f32,
+    }
+  }
+
+  pub fn is_pending(&self, chunk: &ChunkId) -> bool {
+    self.remaining.contains(chunk)
+  }
+}
+
+// bulk terrain operations, each reports `TerrainJobProgress` as its chunks are generated and
+// `TerrainJobFinished` once all of them are
+#[derive(Debug, Default)]
+pub struct TerrainJobs {
+  next_id: u64,
+  jobs: Vec<(TerrainJobId, TerrainJob)>,
+}
+
+impl TerrainJobs {
+  pub fn submit(
+    &mut self,
+    name: impl Into<String>,
+    chunks: impl IntoIterator<Item = ChunkId>,
+    priority: u8,
+  ) -> TerrainJobId {
+    self.push(name.into(), chunks, priority, true)
+  }
+
+  // a job whose chunks are done when `complete` says so instead of when they're loaded
+  pub(super) fn submit_detached(
+    &mut self,
+    name: impl Into<String>,
+    chunks: impl IntoIterator<Item = ChunkId>,
+  ) -> TerrainJobId {
+    self.push(name.into(), chunks, 0, false)
+  }
+
+  pub(super) fn complete(&mut self, id: TerrainJobId, chunk: &ChunkId) {
+    if let Some((_, job)) = self.jobs.iter_mut().find(|(job_id, _)| *job_id == id) {
+      job.remaining.remove(chunk);
+    }
+  }
+
+  // every section spawners load around `radius` rings of chunk columns, like the area
+  // `TerrainReadiness` waits for
+  pub fn submit_area(
+    &mut self,
+    name: impl Into<String>,
+    layout: &CubicVoxelLayout,
+    spawner_config: &ChunkSpawnerConfig,
+    center: ChunkId,
+    radius: i64,
+    priority: u8,
+  ) -> TerrainJobId {
+    self.submit(
+      name,
+      area_chunks(layout, spawner_config, center, radius),
+      priority,
+    )
+  }
+
+  pub fn get(&self, id: TerrainJobId) -> Option<&TerrainJob> {
+    self
+      .jobs
+      .iter()
+      .find(|(job_id, _)| *job_id == id)
+      .map(|(_, job)| job)
+  }
+
+  // unfinished jobs in the order they were submitted
+  pub fn iter(&self) -> impl Iterator<Item = (TerrainJobId, &TerrainJob)> {
+    self.jobs.iter().map(|(id, job)| (*id, job))
+  }
+
+  // stops loading or pregenerating the job's chunks, it doesn't finish
+  pub fn cancel(&mut self, id: TerrainJobId) -> bool {
+    let count = self.jobs.len();
+    self.jobs.retain(|(job_id, _)| *job_id != id);
+    self.jobs.len() != count
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.jobs.is_empty()
+  }
+
+  fn push(
+    &mut self,
+    name: String,
+    chunks: impl IntoIterator<Item = ChunkId>,
+    priority: u8,
+    loads_chunks: bool,
+  ) -> TerrainJobId {
+    let remaining: HashSet<_> = chunks.into_iter().collect();
+    let id = TerrainJobId(self.next_id);
+    self.next_id += 1;
+    self.jobs.push((
+      id,
+      TerrainJob {
+        name,
+        priority,
+        total: remaining.len(),
+        remaining,
+        loads_chunks,
+        reported: None,
+      },
+    ));
+    id
+  }
+}
+
+pub(super) fn area_chunks(
+  layout: &CubicVoxelLayout,
+  spawner_config: &ChunkSpawnerConfig,
+  center: ChunkId,
+  radius: i64,
+) -> Vec<ChunkId> {
+  layout
+    .iter_chunks_spiral(&layout.clamp_to_world(&center), radius)
+    .flat_map(|column| layout.get_column_sections(&column, spawner_config.vertical_radius))
+    .collect()
+}
+
+// sent when a job starts and whenever chunks of it were done since
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerrainJobProgress {
+  pub job: TerrainJobId,
+  pub done: usize,
+  pub total: usize,
+}
+
+// sent once after the last chunk of a job was done, the job is gone from `TerrainJobs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerrainJobFinished {
+  pub job: TerrainJobId,
+  pub total: usize,
+}
+
+// runs after the anchors so the requirements they clear every frame include the jobs' chunks
+pub fn require_job_chunks(jobs: Res<TerrainJobs>, mut tracker: ResMut<ChunkTracker>) {
+  for (_, job) in jobs.jobs.iter().filter(|(_, job)| job.loads_chunks) {
+    for chunk in job.remaining.iter() {
+      tracker.require(*chunk, job.priority);
+    }
+  }
+}
+
+pub fn update_terrain_jobs(
+  mut jobs: ResMut<TerrainJobs>,
+  tracker: Res<ChunkTracker>,
+  mut progress: EventWriter<TerrainJobProgress>,
+  mut finished: EventWriter<TerrainJobFinished>,
+  generated: Query<(), With<ChunkVoxelData>>,
+) {
+  if jobs.is_empty() {
+    return;
+  }
+  let is_generated = |chunk: &ChunkId| {
+    tracker
+      .entity(chunk)
+      .map_or(false, |entity| generated.get(entity).is_ok())
+  };
+
+  for (id, job) in jobs.jobs.iter_mut() {
+    if job.loads_chunks {
+      job.remaining.retain(|chunk| !is_generated(chunk));
+    }
+    if job.reported != Some(job.done()) {
+      job.reported = Some(job.done());
+      progress.send(TerrainJobProgress {
+        job: *id,
+        done: job.done(),
+        total: job.total,
+      });
+    }
+    if job.remaining.is_empty() {
+      info!("terrain job {:?} ({}) finished", id, job.name);
+      finished.send(TerrainJobFinished {
+        job: *id,
+        total: job.total,
+      });
+    }
+  }
+  jobs.jobs.retain(|(_, job)| !job.remaining.is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bevy::ecs::event::Events;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn jobs_should_finish_once_their_chunks_are_generated(generated in prop::collection::vec(any::<bool>(), 1..20)) {
+          let mut world = World::new();
+          world.insert_resource(Events::<TerrainJobProgress>::default());
+          world.insert_resource(Events::<TerrainJobFinished>::default());
+
+          let chunks: Vec<_> = (0..generated.len() as i64).map(|x| ChunkId::new(x, 0, 0)).collect();
+          let mut jobs = TerrainJobs::default();
+          let job = jobs.submit("test", chunks.clone(), 3);
+          world.insert_resource(jobs);
+          world.insert_resource(ChunkTracker::default());
+
+          let mut stage = SystemStage::single_threaded()
+              .with_system(require_job_chunks)
+              .with_system(update_terrain_jobs.after(require_job_chunks));
+          stage.run(&mut world);
+          let tracker = world.get_resource::<ChunkTracker>().unwrap();
+          prop_assert!(chunks.iter().all(|chunk| tracker.required_priority(chunk) == Some(3)));
+
+          // chunks that are spawned but still generating don't count
+          let mut tracker = ChunkTracker::default();
+          for (chunk, generated) in chunks.iter().zip(generated.iter()) {
+              let mut entity = world.spawn();
+              if *generated {
+                  entity.insert(ChunkVoxelData::default());
+              }
+              tracker.register_entity(*chunk, entity.id());
+          }
+          world.insert_resource(tracker);
+          stage.run(&mut world);
+
+          let done = generated.iter().filter(|generated| **generated).count();
+          let all = done == generated.len();
+          let jobs = world.get_resource::<TerrainJobs>().unwrap();
+          prop_assert_eq!(jobs.get(job).map(|job| job.done()), (!all).then(|| done));
+          let progress = world.get_resource::<Events<TerrainJobProgress>>().unwrap();
+          let reported: Vec<_> = progress.iter_current_update_events().copied().collect();
+          // the job reported its start in the first update
+          prop_assert_eq!(reported.first().map(|progress| progress.done), Some(0));
+          prop_assert_eq!(reported.last().copied(), Some(TerrainJobProgress { job, done, total: generated.len() }));
+          let finished = world.get_resource::<Events<TerrainJobFinished>>().unwrap();
+          prop_assert_eq!(finished.iter_current_update_events().count(), usize::from(all));
+      }
+  }
+}
@@ -1,28 +1,34 @@
+use super::error::TerrainError;
 use bevy::{
-  math::Mat2,
   prelude::*,
   tasks::{AsyncComputeTaskPool, Task},
 };
-use lazy_static::*;
+use serde::{Deserialize, Serialize};
 use std::{
   hash::Hash,
   ops::{Add, Sub},
 };
 
-lazy_static! {
-  static ref ROTATE_4X: [Mat2; 4] = [
-    Mat2::from_cols_array(&[0.0, 1.0, -1.0, 0.0]),
-    Mat2::from_cols_array(&[-1.0, 0.0, 0.0, -1.0]),
-    Mat2::from_cols_array(&[0.0, -1.0, 1.0, 0.0]),
-    Mat2::from_cols_array(&[1.0, 0.0, 0.0, 1.0])
-  ];
-}
-
-#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Default, Eq, Hash)]
-pub struct ChunkId(i64, i64);
+const ADJACENT_OFFSETS: [(i64, i64); 8] = [
+  (-1, -1),
+  (0, -1),
+  (1, -1),
+  (-1, 0),
+  (1, 0),
+  (-1, 1),
+  (0, 1),
+  (1, 1),
+];
+
+// x and z index chunk columns on the ground plane, y is the vertical section within a column
+#[derive(
+  Clone, Copy, PartialEq, PartialOrd, Debug, Default, Eq, Hash, Reflect, Serialize, Deserialize,
+)]
+#[reflect(PartialEq, Hash)]
+pub struct ChunkId(i64, i64, i64);
 impl ChunkId {
-  pub fn new(x: i64, y: i64) -> Self {
-    Self(x, y)
+  pub fn new(x: i64, y: i64, z: i64) -> Self {
+    Self(x, y, z)
   }
 
   #[inline]
@@ -34,13 +40,48 @@ impl ChunkId {
   pub fn y(&self) -> i64 {
     self.1
   }
+
+  #[inline]
+  pub fn z(&self) -> i64 {
+    self.2
+  }
+
+  // the same column at another section
+  #[inline]
+  pub fn with_y(&self, y: i64) -> Self {
+    Self(self.0, y, self.2)
+  }
+
+  // the 8 chunks in the same section sharing an edge or a corner with this one
+  pub fn adjacent(&self) -> impl Iterator<Item = ChunkId> {
+    let center = *self;
+    ADJACENT_OFFSETS
+      .iter()
+      .map(move |(x, z)| center.saturating_offset(*x, *z))
+  }
+
+  // the column `x` and `z` columns away, stops at the edges of the coordinate range instead of
+  // overflowing
+  #[inline]
+  pub fn saturating_offset(&self, x: i64, z: i64) -> Self {
+    Self(self.0.saturating_add(x), self.1, self.2.saturating_add(z))
+  }
+
+  // unlike `Hash` it's the same on every platform and release, so it can be stored
+  pub fn stable_hash(&self) -> u64 {
+    stable_hash(self.0, self.1, self.2)
+  }
 }
 impl Add for ChunkId {
   type Output = Self;
 
   #[inline]
   fn add(self, other: Self) -> Self {
-    Self(self.x() + other.x(), self.y() + other.y())
+    Self(
+      self.x() + other.x(),
+      self.y() + other.y(),
+      self.z() + other.z(),
+    )
   }
 }
 impl Sub for ChunkId {
@@ -48,13 +89,24 @@ impl Sub for ChunkId {
 
   #[inline]
   fn sub(self, other: Self) -> Self {
-    Self(self.x() - other.x(), self.y() - other.y())
+    Self(
+      self.x() - other.x(),
+      self.y() - other.y(),
+      self.z() - other.z(),
+    )
   }
 }
 
-#[derive(Clone, Copy, PartialEq, PartialOrd, Debug, Default, Eq, Hash)]
+#[derive(
+  Clone, Copy, PartialEq, PartialOrd, Debug, Default, Eq, Hash, Reflect, Serialize, Deserialize,
+)]
+#[reflect(PartialEq, Hash)]
 pub struct VoxelId(i64, i64, i64);
 impl VoxelId {
+  pub fn new(x: i64, y: i64, z: i64) -> Self {
+    Self(x, y, z)
+  }
+
   #[inline]
   pub fn x(&self) -> i64 {
     self.0
@@ -69,6 +121,11 @@ impl VoxelId {
   pub fn z(&self) -> i64 {
     self.2
   }
+
+  // see `ChunkId::stable_hash`
+  pub fn stable_hash(&self) -> u64 {
+    stable_hash(self.0, self.1, self.2)
+  }
 }
 impl Add for VoxelId {
   type Output = Self;
@@ -95,11 +152,37 @@ impl Sub for VoxelId {
   }
 }
 
+// fnv-1a over the little endian coordinates
+fn stable_hash(x: i64, y: i64, z: i64) -> u64 {
+  [x, y, z]
+    .iter()
+    .flat_map(|coordinate| coordinate.to_le_bytes())
+    .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+      (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// voxel ownership rules, every system that maps between space, voxels and chunks
+// must go through these methods instead of redoing the math:
+//  - a voxel occupies the half-open cell [v * side, (v + 1) * side) on every axis (relative to
+//    the origin), so a point exactly on a voxel face belongs to the voxel above it
+//  - a chunk owns the voxels within `chunk_voxel_length` of its center voxel on x and z and
+//    the `chunk_voxel_height` voxels of its section on y, chunks never overlap so each voxel has
+//    exactly one owner, voxels below the first or above the last section have none
+//  - reads of a voxel outside a chunk always resolve through its owner (`voxel_owner`),
+//    chunks don't keep copies of their neighbors' border voxels
+//  - in a wrapping world chunks and voxels go by their ids within the world, `space_to_voxel`
+//    and `voxel_to_chunk` wrap whatever is outside of it
+#[derive(Debug, Clone)]
 pub struct CubicVoxelLayout {
   pub origin: ChunkId,
   voxel_side_length: f32,
   chunk_voxel_length: i64,
   chunk_voxel_height: i64,
+  // chunks stacked in each column, sections go from 0 up to this
+  vertical_sections: i64,
+  // chunk columns around the world along x and z, None for an endless world
+  wrap: Option<(i64, i64)>,
 }
 
 impl CubicVoxelLayout {
@@ -108,6 +191,33 @@ impl CubicVoxelLayout {
     self.chunk_voxel_full_length() as f32 * self.voxel_side_length
   }
 
+  #[inline]
+  pub fn voxel_side_length(&self) -> f32 {
+    self.voxel_side_length
+  }
+
+  // voxels from the center of a chunk to its sides
+  #[inline]
+  pub fn chunk_voxel_length(&self) -> i64 {
+    self.chunk_voxel_length
+  }
+
+  #[inline]
+  pub fn chunk_voxel_height(&self) -> i64 {
+    self.chunk_voxel_height
+  }
+
+  #[inline]
+  pub fn vertical_sections(&self) -> i64 {
+    self.vertical_sections
+  }
+
+  // voxels from the bottom of the world to the top of the highest section
+  #[inline]
+  pub fn world_voxel_height(&self) -> i64 {
+    self.chunk_voxel_height * self.vertical_sections
+  }
+
   #[inline]
   pub fn chunk_voxel_full_length(&self) -> i64 {
     1 + (self.chunk_voxel_length * 2)
@@ -117,16 +227,17 @@ impl CubicVoxelLayout {
   pub fn get_center_voxel(&self, chunk: &ChunkId) -> VoxelId {
     VoxelId(
       chunk.x() * self.chunk_voxel_full_length(),
-      0,
-      chunk.y() * self.chunk_voxel_full_length(),
+      chunk.y() * self.chunk_voxel_height,
+      chunk.z() * self.chunk_voxel_full_length(),
     )
   }
 
   #[inline]
   pub fn get_voxel(&self, chunk: &ChunkId, x: i64, y: i64, z: i64) -> VoxelId {
     let vx = x + (chunk.x() * self.chunk_voxel_full_length());
-    let vz = z + (chunk.y() * self.chunk_voxel_full_length());
-    VoxelId(vx, y, vz)
+    let vy = y + (chunk.y() * self.chunk_voxel_height);
+    let vz = z + (chunk.z() * self.chunk_voxel_full_length());
+    VoxelId(vx, vy, vz)
   }
 
   pub fn new(
@@ -140,37 +251,200 @@ impl CubicVoxelLayout {
       voxel_side_length,
       chunk_voxel_length,
       chunk_voxel_height,
+      vertical_sections: 1,
+      wrap: None,
+    }
+  }
+
+  // like `new` but refuses dimensions the layout can't tile the world with
+  pub fn try_new(
+    origin: ChunkId,
+    voxel_side_length: f32,
+    chunk_voxel_length: i64,
+    chunk_voxel_height: i64,
+  ) -> Result<Self, TerrainError> {
+    let layout = Self::new(
+      origin,
+      voxel_side_length,
+      chunk_voxel_length,
+      chunk_voxel_height,
+    );
+    layout.validate()?;
+    Ok(layout)
+  }
+
+  pub fn validate(&self) -> Result<(), TerrainError> {
+    if !(self.voxel_side_length.is_finite() && self.voxel_side_length > 0.) {
+      return Err(TerrainError::InvalidLayout(
+        "voxel side length should be positive",
+      ));
+    }
+    if self.chunk_voxel_length < 1 {
+      return Err(TerrainError::InvalidLayout(
+        "chunks should reach at least one voxel past their center",
+      ));
+    }
+    if self.chunk_voxel_height < 1 {
+      return Err(TerrainError::InvalidLayout(
+        "chunks should be at least one voxel high",
+      ));
+    }
+    Ok(())
+  }
+
+  pub fn with_vertical_sections(mut self, vertical_sections: i64) -> Self {
+    self.vertical_sections = vertical_sections.max(1);
+    self
+  }
+
+  // wraps the world around after `columns_x` chunk columns along x and `columns_z` along z, like
+  // the surface of a planet the player can circumnavigate, sections don't wrap
+  // chunks go by the id of their image in [0, columns) on both axes, `chunk_to_space` places them
+  // there and `chunk_to_space_near` next to whoever is looking at them
+  pub fn with_wrapping(mut self, columns_x: i64, columns_z: i64) -> Self {
+    self.wrap = Some((columns_x.max(1), columns_z.max(1)));
+    self
+  }
+
+  pub fn wrapping(&self) -> Option<(i64, i64)> {
+    self.wrap
+  }
+
+  // voxel columns along x and z after which the world wraps around
+  pub fn wrapping_voxels(&self) -> Option<(i64, i64)> {
+    let length = self.chunk_voxel_full_length();
+    self
+      .wrap
+      .map(|(columns_x, columns_z)| (columns_x * length, columns_z * length))
+  }
+
+  // the id every image of `chunk` goes by, `chunk` itself in an endless world
+  pub fn wrap_chunk(&self, chunk: &ChunkId) -> ChunkId {
+    wrap_columns(self.wrap, chunk)
+  }
+
+  // the voxel of the chunk `wrap_chunk` gives for the owner of `voxel`
+  pub fn wrap_voxel(&self, voxel: &VoxelId) -> VoxelId {
+    wrap_voxel_columns(self.wrap, self.chunk_voxel_length, voxel)
+  }
+
+  // chunk columns from `from` to `to` along x and z, around the world if that's shorter
+  pub fn column_offset(&self, from: &ChunkId, to: &ChunkId) -> (i64, i64) {
+    match self.wrap {
+      Some((columns_x, columns_z)) => {
+        // shortest of the two ways around
+        let around = |from: i64, to: i64, columns: i64| {
+          let offset = (to.rem_euclid(columns) - from.rem_euclid(columns)).rem_euclid(columns);
+          if offset > columns / 2 {
+            offset - columns
+          } else {
+            offset
+          }
+        };
+        (
+          around(from.x(), to.x(), columns_x),
+          around(from.z(), to.z(), columns_z),
+        )
+      }
+      None => (
+        to.x().saturating_sub(from.x()),
+        to.z().saturating_sub(from.z()),
+      ),
+    }
+  }
+
+  // the image of `chunk` closest to `near`, e.g. to walk a path across the edge of a wrapping
+  // world, `chunk` itself in an endless world
+  pub fn nearest_image(&self, chunk: &ChunkId, near: &ChunkId) -> ChunkId {
+    let (x, z) = self.column_offset(near, chunk);
+    near.saturating_offset(x, z).with_y(chunk.y())
+  }
+
+  // where the image of `chunk` closest to the point `near` is, the same as `chunk_to_space` in an
+  // endless world
+  pub fn chunk_to_space_near(&self, chunk: &ChunkId, near: &Vec3) -> Vec3 {
+    if self.wrap.is_none() {
+      return self.chunk_to_space(chunk);
     }
+    let unwrapped = self.voxel_to_unwrapped_chunk(&self.space_to_unwrapped_voxel(near));
+    self.chunk_to_space(&self.nearest_image(chunk, &unwrapped))
+  }
+
+  // the image of the chunk at `space` it's in, it changes when `space` goes across the edge of a
+  // wrapping world unlike `space_to_chunk`
+  pub fn space_to_unwrapped_chunk(&self, space: &Vec3) -> ChunkId {
+    self.voxel_to_unwrapped_chunk(&self.space_to_unwrapped_voxel(space))
+  }
+
+  // the section of the world closest to `chunk` in the same column, spawners above or below
+  // the world load the sections nearest to them
+  pub fn clamp_to_world(&self, chunk: &ChunkId) -> ChunkId {
+    chunk.with_y(chunk.y().clamp(0, self.vertical_sections - 1))
+  }
+
+  // sections of the column of `chunk` within `distance` sections of it
+  pub fn get_column_sections(
+    &self,
+    chunk: &ChunkId,
+    distance: i64,
+  ) -> impl Iterator<Item = ChunkId> {
+    let chunk = *chunk;
+    let min = (chunk.y() - distance).max(0);
+    let max = (chunk.y() + distance).min(self.vertical_sections - 1);
+    (min..=max).map(move |y| chunk.with_y(y))
   }
 
   pub fn get_chunk_neighbors(&self, chunk: &ChunkId, distance: i64) -> Vec<ChunkId> {
-    (1..=distance)
-      .flat_map(move |ring| {
-        (0..(2 * ring)).flat_map(move |offset| {
-          ROTATE_4X
-            .iter()
-            .map(move |rot| rot.mul_vec2(Vec2::new((-ring + offset) as f32, -ring as f32)))
-            .map(move |v2| *chunk + ChunkId::new(v2.x as i64, v2.y as i64))
-        })
-      })
-      .collect()
+    self.iter_chunks_spiral(chunk, distance).skip(1).collect()
+  }
+
+  // `center` followed by the columns around it ring by ring out to `radius` rings, so nearer
+  // chunks always come first, each ring is walked clockwise starting from its -x -z corner
+  // in a wrapping world the chunks are wrapped, spirals wider than the world visit chunks again
+  pub fn iter_chunks_spiral(&self, center: &ChunkId, radius: i64) -> impl Iterator<Item = ChunkId> {
+    let (center, wrap) = (*center, self.wrap);
+    (0..=radius)
+      .flat_map(move |ring| Self::iter_chunks_ring(center, ring))
+      .map(move |chunk| wrap_columns(wrap, &chunk))
+  }
+
+  // the columns exactly `ring` chunks away from `center`, just `center` for ring 0
+  pub fn iter_chunks_ring(center: ChunkId, ring: i64) -> impl Iterator<Item = ChunkId> {
+    let steps = if ring == 0 { 1 } else { 8 * ring.max(0) };
+    (0..steps).map(move |step| {
+      if ring == 0 {
+        return center;
+      }
+      let (side, offset) = (step / (2 * ring), step % (2 * ring));
+      let (x, z) = match side {
+        0 => (-ring + offset, -ring),
+        1 => (ring, -ring + offset),
+        2 => (ring - offset, ring),
+        _ => (-ring, ring - offset),
+      };
+      center.saturating_offset(x, z)
+    })
   }
 
   pub fn get_chunk_voxels(&self, chunk: &ChunkId) -> Vec<VoxelId> {
-    (0..self.chunk_voxel_full_length())
-      .flat_map(|x| {
-        (0..self.chunk_voxel_full_length()).flat_map(move |z| {
-          (0..self.chunk_voxel_height).map(move |y| {
-            self.get_voxel(
-              chunk,
-              x - self.chunk_voxel_length,
-              y,
-              z - self.chunk_voxel_length,
-            )
-          })
+    self.iter_chunk_voxels(chunk).collect()
+  }
+
+  // same as `get_chunk_voxels` without collecting them first, e.g. to fill a generation buffer
+  pub fn iter_chunk_voxels(&self, chunk: &ChunkId) -> impl Iterator<Item = VoxelId> + '_ {
+    let chunk = *chunk;
+    (0..self.chunk_voxel_full_length()).flat_map(move |x| {
+      (0..self.chunk_voxel_full_length()).flat_map(move |z| {
+        (0..self.chunk_voxel_height).map(move |y| {
+          self.get_voxel(
+            &chunk,
+            x - self.chunk_voxel_length,
+            y,
+            z - self.chunk_voxel_length,
+          )
         })
       })
-      .collect()
+    })
   }
 
   pub fn chunk_to_space(&self, chunk: &ChunkId) -> Vec3 {
@@ -178,9 +452,27 @@ impl CubicVoxelLayout {
   }
 
   pub fn voxel_to_chunk(&self, voxel: &VoxelId) -> ChunkId {
+    self.wrap_chunk(&self.voxel_to_unwrapped_chunk(voxel))
+  }
+
+  fn voxel_to_unwrapped_chunk(&self, voxel: &VoxelId) -> ChunkId {
     let x = (voxel.x() + self.chunk_voxel_length).div_euclid(self.chunk_voxel_full_length());
-    let y = (voxel.z() + self.chunk_voxel_length).div_euclid(self.chunk_voxel_full_length());
-    ChunkId::new(x, y)
+    let y = voxel.y().div_euclid(self.chunk_voxel_height.max(1));
+    let z = (voxel.z() + self.chunk_voxel_length).div_euclid(self.chunk_voxel_full_length());
+    ChunkId::new(x, y, z)
+  }
+
+  // the only chunk allowed to store the voxel
+  pub fn voxel_owner(&self, voxel: &VoxelId) -> Option<ChunkId> {
+    if (0..self.world_voxel_height()).contains(&voxel.y()) {
+      Some(self.voxel_to_chunk(voxel))
+    } else {
+      None
+    }
+  }
+
+  pub fn voxel_center(&self, voxel: &VoxelId) -> Vec3 {
+    self.voxel_to_space(voxel) + Vec3::splat(self.voxel_side_length * 0.5)
   }
 
   pub fn voxel_to_space(&self, voxel: &VoxelId) -> Vec3 {
@@ -192,23 +484,138 @@ impl CubicVoxelLayout {
     Vec3::new(x, y, z)
   }
 
+  // in a wrapping world it's the voxel of the wrapped chunk
   pub fn space_to_voxel(&self, space: &Vec3) -> VoxelId {
+    self.wrap_voxel(&self.space_to_unwrapped_voxel(space))
+  }
+
+  fn space_to_unwrapped_voxel(&self, space: &Vec3) -> VoxelId {
     let center = self.get_center_voxel(&self.origin);
-    let divisor = self.voxel_side_length as i64;
-    let x = (space.x as i64).div_euclid(divisor);
-    let y = (space.y as i64).div_euclid(self.chunk_voxel_height as i64);
-    let z = (space.z as i64).div_euclid(divisor);
-    VoxelId(x, y, z) + center
+    let scaled = (*space / self.voxel_side_length).floor();
+    VoxelId(scaled.x as i64, scaled.y as i64, scaled.z as i64) + center
+  }
+
+  // all voxels overlapping the box between `min` and `max`, the box is closed so a voxel whose
+  // lower face touches `max` is included
+  pub fn get_voxels_in_aabb(&self, min: &Vec3, max: &Vec3) -> impl Iterator<Item = VoxelId> {
+    let min = self.space_to_unwrapped_voxel(min);
+    let max = self.space_to_unwrapped_voxel(max);
+    let (wrap, length) = (self.wrap, self.chunk_voxel_length);
+    (min.x()..=max.x()).flat_map(move |x| {
+      (min.y()..=max.y()).flat_map(move |y| {
+        (min.z()..=max.z()).map(move |z| wrap_voxel_columns(wrap, length, &VoxelId(x, y, z)))
+      })
+    })
+  }
+
+  // voxels a ray passes through in order, each with the distance at which the ray enters it and
+  // the normal of the face it enters through (zero for the voxel the ray starts in)
+  pub fn get_voxels_along_ray(
+    &self,
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+  ) -> impl Iterator<Item = (VoxelId, f32, Vec3)> {
+    let direction = direction.normalize_or_zero();
+    let side = self.voxel_side_length;
+    let step = direction.signum();
+    let first = self.space_to_unwrapped_voxel(&origin);
+    let (wrap, length) = (self.wrap, self.chunk_voxel_length);
+    // distance to the next face crossed on each axis and between faces, axes the ray doesn't
+    // move along are never crossed
+    let next_face = self.voxel_to_space(&first) + step.max(Vec3::ZERO) * side;
+    let mut crossing = Vec3::ZERO;
+    let mut between = Vec3::ZERO;
+    for axis in 0..3 {
+      if direction[axis] == 0. {
+        crossing[axis] = f32::INFINITY;
+        between[axis] = f32::INFINITY;
+      } else {
+        crossing[axis] = (next_face[axis] - origin[axis]) / direction[axis];
+        between[axis] = side / direction[axis].abs();
+      }
+    }
+
+    let mut current = Some((first, 0., Vec3::ZERO));
+    std::iter::from_fn(move || {
+      let (voxel, distance, normal) = current?;
+      let axis = if crossing.x < crossing.y && crossing.x < crossing.z {
+        0
+      } else if crossing.y < crossing.z {
+        1
+      } else {
+        2
+      };
+      let mut offset = [0; 3];
+      offset[axis] = step[axis] as i64;
+      let mut entered = Vec3::ZERO;
+      entered[axis] = -step[axis];
+      current = Some((
+        voxel + VoxelId(offset[0], offset[1], offset[2]),
+        crossing[axis],
+        entered,
+      ))
+      .filter(|(_, next, _)| *next <= max_distance);
+      crossing[axis] += between[axis];
+      Some((wrap_voxel_columns(wrap, length, &voxel), distance, normal))
+    })
   }
 
   pub fn space_to_chunk(&self, space: &Vec3) -> ChunkId {
     self.voxel_to_chunk(&self.space_to_voxel(space))
   }
 
-  pub fn get_chunk_distance(&self, a: &ChunkId, b: &ChunkId) -> f32 {
-    (self.chunk_to_space(a) - self.chunk_to_space(b)).length()
+  // rings of chunks between two columns, i.e. the `distance` at which `get_chunk_neighbors` of
+  // one includes the other, vertical sections aren't counted
+  // in a wrapping world it's the shorter way around
+  pub fn chunk_step_distance(&self, a: &ChunkId, b: &ChunkId) -> i64 {
+    let (x, z) = self.column_offset(a, b);
+    x.saturating_abs().max(z.saturating_abs())
   }
+
+  // world units between the origins of two chunks, the shorter way around in a wrapping world
+  pub fn world_distance(&self, a: &ChunkId, b: &ChunkId) -> f32 {
+    let (x, z) = self.column_offset(a, b);
+    let sections = b.y() as f32 - a.y() as f32;
+    Vec3::new(
+      x as f32 * self.chunk_side_length(),
+      sections * self.chunk_voxel_height as f32 * self.voxel_side_length,
+      z as f32 * self.chunk_side_length(),
+    )
+    .length()
+  }
+}
+fn wrap_columns(wrap: Option<(i64, i64)>, chunk: &ChunkId) -> ChunkId {
+  match wrap {
+    Some((columns_x, columns_z)) => ChunkId::new(
+      chunk.x().rem_euclid(columns_x),
+      chunk.y(),
+      chunk.z().rem_euclid(columns_z),
+    ),
+    None => *chunk,
+  }
+}
+
+// see `CubicVoxelLayout::wrap_voxel`
+fn wrap_voxel_columns(
+  wrap: Option<(i64, i64)>,
+  chunk_voxel_length: i64,
+  voxel: &VoxelId,
+) -> VoxelId {
+  let (columns_x, columns_z) = match wrap {
+    Some(wrap) => wrap,
+    None => return *voxel,
+  };
+  let full = 1 + chunk_voxel_length * 2;
+  let around =
+    |v: i64, columns: i64| (v + chunk_voxel_length).rem_euclid(columns * full) - chunk_voxel_length;
+  VoxelId(
+    around(voxel.x(), columns_x),
+    voxel.y(),
+    around(voxel.z(), columns_z),
+  )
 }
+
 impl Default for CubicVoxelLayout {
   fn default() -> Self {
     Self::new(ChunkId::default(), 1.0, 11, 10)
@@ -221,10 +628,52 @@ mod tests {
   use super::*;
   use proptest::prelude::*;
 
+  #[test]
+  fn stable_hash_should_not_change() {
+    // stored hashes depend on this value, changing it breaks existing save files
+    assert_eq!(ChunkId::new(1, -2, 3).stable_hash(), 0xadf8_1e59_2c2c_f47e);
+    assert_eq!(VoxelId::new(1, -2, 3).stable_hash(), 0xadf8_1e59_2c2c_f47e);
+  }
+
   proptest! {
+      #[test]
+      fn rays_should_step_through_adjacent_voxels(
+          origin in prop::array::uniform3(-50f32..50.),
+          direction in prop::array::uniform3(-1f32..1.),
+          side in 0.5f32..2.,
+      ) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), side, 4, 4);
+          let (origin, direction) = (Vec3::from(origin), Vec3::from(direction));
+          prop_assume!(direction.length() > 0.01);
+          let voxels: Vec<_> = layout.get_voxels_along_ray(origin, direction, 20.).collect();
+
+          prop_assert_eq!(voxels[0], (layout.space_to_voxel(&origin), 0., Vec3::ZERO));
+          for pair in voxels.windows(2) {
+              let ((previous, previous_distance, _), (voxel, distance, normal)) = (pair[0], pair[1]);
+              // the ray enters each voxel through the face shared with the previous one
+              let offset = previous - voxel;
+              prop_assert_eq!(Vec3::new(offset.x() as f32, offset.y() as f32, offset.z() as f32), normal);
+              prop_assert!(distance >= previous_distance && distance <= 20.);
+              let entry = origin + direction.normalize() * distance;
+              let corner = layout.voxel_to_space(&voxel);
+              prop_assert!((entry - (corner + Vec3::splat(side / 2.))).abs().max_element() <= side / 2. + 1e-3);
+          }
+      }
+
+      #[test]
+      fn chunks_should_roundtrip_through_reflection(x in -1000i64..1000, y in -1000i64..1000, z in -1000i64..1000) {
+          // scenes store the dynamic clone and apply it to a default value when loading
+          let chunk = ChunkId::new(x, y, z);
+          let mut loaded = ChunkId::default();
+          loaded.apply(chunk.clone_value().as_ref());
+          prop_assert_eq!(loaded, chunk);
+          prop_assert_eq!(loaded.reflect_partial_eq(&chunk), Some(true));
+          prop_assert!(loaded.reflect_hash().is_some());
+      }
+
       #[test]
       fn chunk_should_have_appropriate_number_of_neighbors(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..50, distance in 1i64..10) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let chunk = layout.voxel_to_chunk(&voxel);
           let count =  layout.get_chunk_neighbors(&chunk, distance).len();
@@ -234,21 +683,59 @@ mod tests {
 
       #[test]
       fn neighbor_should_have_correct_distance(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..50, distance in 1i64..10) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let chunk = layout.voxel_to_chunk(&voxel);
           for neighbor in layout.get_chunk_neighbors(&chunk, distance) {
+              assert!((1..=distance).contains(&layout.chunk_step_distance(&chunk, &neighbor)));
               let diff = neighbor - chunk;
               let x = diff.x().abs();
-              let y = diff.y().abs();
-              let max = if x > y { x } else { y };
+              let z = diff.z().abs();
+              let max = if x > z { x } else { z };
               assert!(max <= distance);
           }
       }
 
+      #[test]
+      fn neighbor_math_should_not_overflow(x in prop::sample::select(vec![i64::MIN, i64::MIN + 1, -1, 0, 1, i64::MAX - 1, i64::MAX]), z in prop::sample::select(vec![i64::MIN, 0, i64::MAX])) {
+          let layout = CubicVoxelLayout::default();
+          let chunk = ChunkId::new(x, 0, z);
+          for neighbor in layout.get_chunk_neighbors(&chunk, 2).into_iter().chain(chunk.adjacent()) {
+              prop_assert!(layout.chunk_step_distance(&chunk, &neighbor) <= 2);
+          }
+          prop_assert!(layout.chunk_step_distance(&ChunkId::new(i64::MIN, 0, 0), &ChunkId::new(i64::MAX, 0, 0)) == i64::MAX);
+      }
+
+      #[test]
+      fn wrapped_voxels_should_belong_to_wrapped_chunks(x in -10000i64..10000, z in -10000i64..10000, columns in 1i64..16, voxel_length in 0i64..8) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, voxel_length, 4).with_wrapping(columns, columns);
+          let voxel = VoxelId(x, 0, z);
+          let wrapped = layout.wrap_voxel(&voxel);
+          let chunk = layout.voxel_to_chunk(&voxel);
+          prop_assert_eq!(layout.voxel_to_chunk(&wrapped), chunk);
+          prop_assert!((0..columns).contains(&chunk.x()) && (0..columns).contains(&chunk.z()));
+          prop_assert!(layout.get_chunk_voxels(&chunk).contains(&wrapped));
+      }
+
+      #[test]
+      fn spiral_should_visit_each_chunk_once_nearest_first(x in -1000i64..=1000, z in -1000i64..=1000, radius in 0i64..10) {
+          let layout = CubicVoxelLayout::default();
+          let center = ChunkId::new(x, 0, z);
+          let spiral: Vec<_> = layout.iter_chunks_spiral(&center, radius).collect();
+          assert_eq!(spiral.len() as i64, (2 * radius + 1) * (2 * radius + 1));
+          assert_eq!(spiral[0], center);
+          let unique: std::collections::HashSet<_> = spiral.iter().collect();
+          assert_eq!(unique.len(), spiral.len());
+          let rings: Vec<_> = spiral.iter().map(|chunk| layout.chunk_step_distance(&center, chunk)).collect();
+          assert!(rings.windows(2).all(|pair| pair[0] <= pair[1]));
+          assert!(rings.iter().all(|ring| *ring <= radius));
+          let outer: Vec<_> = CubicVoxelLayout::iter_chunks_ring(center, radius).collect();
+          assert_eq!(&outer[..], &spiral[spiral.len() - outer.len()..]);
+      }
+
       #[test]
       fn neighbor_should_be_mutual(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..50, distance in 1i64..10) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let chunk = layout.voxel_to_chunk(&voxel);
           for neighbor in layout.get_chunk_neighbors(&chunk, distance) {
@@ -261,7 +748,7 @@ mod tests {
 
       #[test]
       fn chunk_space_coordinates_should_be_zero_when_at_origin(x1 in -10000i64..=10000, y1 in -10000i64..=10000, voxel_length in 1i64..50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let coords = layout.chunk_to_space(&layout.origin);
           assert_eq!(coords.x, 0.0);
           assert_eq!(coords.y, 0.0);
@@ -270,7 +757,7 @@ mod tests {
 
       #[test]
       fn voxel_space_coordinates_should_be_reversible(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..=50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let space_coords = layout.voxel_to_space(&voxel);
           let result = layout.space_to_voxel(&space_coords);
@@ -279,7 +766,7 @@ mod tests {
 
       #[test]
       fn chunk_space_coordinates_should_be_reversible(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..=50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let chunk = layout.voxel_to_chunk(&voxel);
           let space_coords = layout.chunk_to_space(&chunk);
@@ -289,7 +776,7 @@ mod tests {
 
       #[test]
       fn voxel_should_resolve_to_same_chunk_in_space(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..=50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let space_coords = layout.voxel_to_space(&voxel);
           let space_chunk = layout.space_to_chunk(&space_coords);
@@ -299,7 +786,7 @@ mod tests {
 
       #[test]
       fn voxel_to_chunk_xz_distance_should_be_voxel_length_or_less(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..=50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let chunk = layout.voxel_to_chunk(&voxel);
           let chunk_center = layout.get_center_voxel(&chunk);
@@ -310,7 +797,7 @@ mod tests {
 
       #[test]
       fn voxel_to_chunk_vertical_distance_should_be_voxel_length_or_less(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..=50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
           let voxel = VoxelId(x2, 0, z2);
           let chunk = layout.voxel_to_chunk(&voxel);
           let chunk_center = layout.get_center_voxel(&chunk);
@@ -321,7 +808,7 @@ mod tests {
 
       #[test]
       fn voxel_to_chunk_should_return_same_value_for_same_chunk(x1 in -10000i64..=10000, y1 in -10000i64..=10000, ring_num in 0i64..10, index in 0i64..1000, voxel_length in 1i64..=50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, voxel_length);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
 
           // find a random chunk via neighbors
           let mut chunk = ChunkId::default();
@@ -335,9 +822,31 @@ mod tests {
           }
       }
 
+      #[test]
+      fn space_to_voxel_should_floor_to_containing_voxel(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x in -10000f32..10000f32, y in -100f32..100f32, z in -10000f32..10000f32, voxel_length in 1i64..=50) {
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
+          let space = Vec3::new(x, y, z);
+          let voxel = layout.space_to_voxel(&space);
+          let min = layout.voxel_to_space(&voxel);
+          let max = min + Vec3::splat(layout.voxel_side_length());
+          assert!(min.cmple(space).all() && max.cmpgt(space).all(), "{:?} not in {:?}..{:?}", space, min, max);
+      }
+
+      #[test]
+      fn chunk_border_should_belong_to_one_chunk(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -1000i64..=1000, z2 in -1000i64..=1000, voxel_length in 1i64..=50) {
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, voxel_length);
+          let chunk = ChunkId(x2, 0, z2);
+          // the lower corner of a chunk's first voxel is shared with three other chunks
+          let corner = layout.get_voxel(&chunk, -voxel_length, 0, -voxel_length);
+          let space = layout.voxel_to_space(&corner);
+          assert_eq!(layout.space_to_chunk(&space), chunk);
+          assert_eq!(layout.voxel_owner(&corner), Some(chunk));
+          assert_eq!(layout.voxel_owner(&VoxelId(corner.x(), -1, corner.z())), None);
+      }
+
       #[test]
       fn chunk_should_have_correct_number_of_voxels(x1 in -10000i64..=10000, y1 in -10000i64..=10000, x2 in -10000i64..=10000, z2 in -10000i64..=10000, voxel_length in 1i64..=50, height in 0i64..=50) {
-          let layout = CubicVoxelLayout::new(ChunkId(x1, y1), 1.0, voxel_length, height);
+          let layout = CubicVoxelLayout::new(ChunkId(x1, 0, y1), 1.0, voxel_length, height);
 
           let voxel = VoxelId(x2, 0, z2);
           let chunk = layout.voxel_to_chunk(&voxel);
@@ -345,5 +854,18 @@ mod tests {
           let expected = (layout.chunk_voxel_full_length() * layout.chunk_voxel_full_length()) * height; // 6 triangle cross-sections (excl center), each section has a number of voxels equal to the nth triangle number * height
           assert_eq!(expected, voxel_count);
       }
+
+      #[test]
+      fn stacked_section_voxels_should_resolve_to_section(x1 in -1000i64..=1000, z1 in -1000i64..=1000, voxel_length in 1i64..=8, height in 1i64..=8, sections in 1i64..=4, section in 0i64..4) {
+          let layout = CubicVoxelLayout::new(ChunkId(0, 0, 0), 1.0, voxel_length, height).with_vertical_sections(sections);
+          let chunk = layout.clamp_to_world(&ChunkId(x1, section, z1));
+
+          for voxel in layout.get_chunk_voxels(&chunk) {
+              assert_eq!(layout.voxel_to_chunk(&voxel), chunk);
+              assert_eq!(layout.voxel_owner(&voxel), Some(chunk));
+          }
+          let above = layout.get_voxel(&chunk.with_y(sections), 0, 0, 0);
+          assert_eq!(layout.voxel_owner(&above), None);
+      }
   }
 }
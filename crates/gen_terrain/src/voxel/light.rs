@@ -0,0 +1,432 @@
+#[cfg(feature = "render")]
+use super::{
+  edit::EditedVoxels,
+  layout::CubicVoxelLayout,
+  tracker::ChunkTracker,
+  world::{TerrainWorld, TerrainWorlds},
+  ChunkVoxelData,
+};
+use super::{
+  registry::{VoxelRegistry, VoxelTypeId},
+  VoxelId,
+};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub const MAX_LIGHT: u8 = 15;
+
+pub(super) const NEIGHBOR_OFFSETS: [(i64, i64, i64); 6] = [
+  (1, 0, 0),
+  (-1, 0, 0),
+  (0, 1, 0),
+  (0, -1, 0),
+  (0, 0, 1),
+  (0, 0, -1),
+];
+
+// light levels of the voxels of a chunk, kept next to its `ChunkVoxelData`
+// each level packs sunlight in the high nibble and block light in the low nibble
+// light spreads into a chunk from the border voxels of the neighbors that are loaded, the sky is
+// open above chunks without a loaded chunk on top, other voxels outside the chunk are dark
+#[derive(Debug, Default, Clone, Component)]
+pub struct ChunkLight {
+  levels: HashMap<VoxelId, u8>,
+  // voxels whose level changed since the chunk's mesh was last patched, the faces looking at them
+  // are shaded again by the next patch
+  relit: HashSet<VoxelId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+  Sun,
+  Block,
+}
+
+impl ChunkLight {
+  // light of a chunk on its own, as if none of its neighbors were loaded
+  pub fn compute(voxels: &HashMap<VoxelId, VoxelTypeId>, registry: &VoxelRegistry) -> Self {
+    Self::compute_with_border(voxels, registry, |_| None)
+  }
+
+  // `border` is the packed level of a voxel outside the chunk, None if its chunk isn't loaded
+  pub fn compute_with_border(
+    voxels: &HashMap<VoxelId, VoxelTypeId>,
+    registry: &VoxelRegistry,
+    border: impl Fn(&VoxelId) -> Option<u8>,
+  ) -> Self {
+    let mut light = Self::default();
+    let mut before = HashMap::new();
+    for channel in [Channel::Sun, Channel::Block] {
+      let mut queue = VecDeque::new();
+      for (voxel, voxel_type) in voxels.iter() {
+        let source = source(channel, voxel, *voxel_type, voxels, registry, &border);
+        if source > 0 {
+          light.set(channel, *voxel, source, &mut before);
+          queue.push_back(*voxel);
+        }
+      }
+      light.flood(channel, voxels, registry, queue, &mut before);
+    }
+    light
+  }
+
+  pub fn sunlight(&self, voxel: &VoxelId) -> u8 {
+    self.levels.get(voxel).map_or(0, |level| level >> 4)
+  }
+
+  pub fn block_light(&self, voxel: &VoxelId) -> u8 {
+    self.levels.get(voxel).map_or(0, |level| level & 0x0f)
+  }
+
+  // brightest of both channels
+  pub fn level(&self, voxel: &VoxelId) -> u8 {
+    self.sunlight(voxel).max(self.block_light(voxel))
+  }
+
+  // both channels packed like they're stored, e.g. for the `border` of a neighbor
+  pub fn packed(&self, voxel: &VoxelId) -> u8 {
+    self.levels.get(voxel).copied().unwrap_or_default()
+  }
+
+  pub fn relit(&self) -> &HashSet<VoxelId> {
+    &self.relit
+  }
+
+  // once the chunk's mesh is patched with the current levels
+  pub fn clear_relit(&mut self) {
+    self.relit.clear();
+  }
+
+  // takes the levels of `computed`, returning the voxels whose level changed, they're also added
+  // to `relit`
+  pub fn update(&mut self, computed: ChunkLight) -> HashSet<VoxelId> {
+    let changed: HashSet<_> = self
+      .levels
+      .keys()
+      .chain(computed.levels.keys())
+      .filter(|voxel| self.levels.get(voxel) != computed.levels.get(voxel))
+      .copied()
+      .collect();
+    self.relit.extend(changed.iter().copied());
+    self.levels = computed.levels;
+    changed
+  }
+
+  // updates the light around `changed` voxels whose type or border light changed, instead of
+  // computing the whole chunk again
+  // the light `changed` voxels spread is taken back first, then the voxels that went dark are lit
+  // again from their own sources and from the neighbors that kept their light
+  // returns the voxels whose level changed, they're also added to `relit`
+  pub fn relight(
+    &mut self,
+    voxels: &HashMap<VoxelId, VoxelTypeId>,
+    registry: &VoxelRegistry,
+    changed: &HashSet<VoxelId>,
+    border: impl Fn(&VoxelId) -> Option<u8>,
+  ) -> HashSet<VoxelId> {
+    // the level every voxel had before it was first touched
+    let mut before = HashMap::new();
+    for channel in [Channel::Sun, Channel::Block] {
+      let mut darkened = Vec::new();
+      let mut removals: VecDeque<_> = changed
+        .iter()
+        .filter(|voxel| voxels.contains_key(voxel))
+        .map(|voxel| (*voxel, self.get(channel, voxel)))
+        .collect();
+      let mut queue = VecDeque::new();
+      for (voxel, _) in removals.iter() {
+        self.set(channel, *voxel, 0, &mut before);
+        darkened.push(*voxel);
+      }
+      while let Some((voxel, level)) = removals.pop_front() {
+        for (x, y, z) in NEIGHBOR_OFFSETS {
+          let neighbor = voxel + VoxelId::new(x, y, z);
+          let neighbor_level = self.get(channel, &neighbor);
+          if neighbor_level == 0 || !voxels.contains_key(&neighbor) {
+            continue;
+          }
+          // whatever the voxel could have lit goes dark, the rest lights the gap again
+          if neighbor_level <= spread(channel, level, (x, y, z)) {
+            self.set(channel, neighbor, 0, &mut before);
+            darkened.push(neighbor);
+            removals.push_back((neighbor, neighbor_level));
+          } else {
+            queue.push_back(neighbor);
+          }
+        }
+      }
+      for voxel in darkened {
+        let voxel_type = voxels[&voxel];
+        let source = source(channel, &voxel, voxel_type, voxels, registry, &border);
+        if source > self.get(channel, &voxel) {
+          self.set(channel, voxel, source, &mut before);
+          queue.push_back(voxel);
+        }
+      }
+      self.flood(channel, voxels, registry, queue, &mut before);
+    }
+    // voxels that went dark and were lit to the same level again didn't change
+    let changed: HashSet<_> = before
+      .into_iter()
+      .filter(|(voxel, level)| self.packed(voxel) != *level)
+      .map(|(voxel, _)| voxel)
+      .collect();
+    self.relit.extend(changed.iter().copied());
+    changed
+  }
+
+  fn get(&self, channel: Channel, voxel: &VoxelId) -> u8 {
+    match channel {
+      Channel::Sun => self.sunlight(voxel),
+      Channel::Block => self.block_light(voxel),
+    }
+  }
+
+  // `before` keeps the level the voxel had before it was first set
+  fn set(
+    &mut self,
+    channel: Channel,
+    voxel: VoxelId,
+    value: u8,
+    before: &mut HashMap<VoxelId, u8>,
+  ) {
+    let level = self.levels.entry(voxel).or_insert(0);
+    before.entry(voxel).or_insert(*level);
+    *level = match channel {
+      Channel::Sun => (*level & 0x0f) | (value << 4),
+      Channel::Block => (*level & 0xf0) | value,
+    };
+  }
+
+  // breadth first spread through non-opaque voxels of the chunk
+  fn flood(
+    &mut self,
+    channel: Channel,
+    voxels: &HashMap<VoxelId, VoxelTypeId>,
+    registry: &VoxelRegistry,
+    mut queue: VecDeque<VoxelId>,
+    before: &mut HashMap<VoxelId, u8>,
+  ) {
+    while let Some(voxel) = queue.pop_front() {
+      let level = self.get(channel, &voxel);
+      for (x, y, z) in NEIGHBOR_OFFSETS {
+        let neighbor = voxel + VoxelId::new(x, y, z);
+        if !matches!(voxels.get(&neighbor), Some(v) if !registry.is_opaque(*v)) {
+          continue;
+        }
+        let spread = spread(channel, level, (x, y, z));
+        if self.get(channel, &neighbor) < spread {
+          self.set(channel, neighbor, spread, before);
+          queue.push_back(neighbor);
+        }
+      }
+    }
+  }
+}
+
+// what `level` spreads to the neighbor at `offset`, one level is lost per step except for full
+// sunlight falling straight down
+fn spread(channel: Channel, level: u8, offset: (i64, i64, i64)) -> u8 {
+  match (channel, offset) {
+    (Channel::Sun, (0, -1, 0)) if level == MAX_LIGHT => MAX_LIGHT,
+    _ => level.saturating_sub(1),
+  }
+}
+
+// the light a voxel gets without the other voxels of its chunk: what it emits, and what its
+// neighbors outside the chunk spread into it
+fn source(
+  channel: Channel,
+  voxel: &VoxelId,
+  voxel_type: VoxelTypeId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  registry: &VoxelRegistry,
+  border: &impl Fn(&VoxelId) -> Option<u8>,
+) -> u8 {
+  let emitted = match channel {
+    Channel::Sun => 0,
+    Channel::Block => registry.light_emission(voxel_type).min(MAX_LIGHT),
+  };
+  if registry.is_opaque(voxel_type) {
+    return emitted;
+  }
+  NEIGHBOR_OFFSETS
+    .into_iter()
+    .filter_map(|(x, y, z)| {
+      let neighbor = *voxel + VoxelId::new(x, y, z);
+      if voxels.contains_key(&neighbor) {
+        return None;
+      }
+      let level = match (border(&neighbor), channel, (x, y, z)) {
+        (Some(level), Channel::Sun, _) => level >> 4,
+        (Some(level), Channel::Block, _) => level & 0x0f,
+        // the sky
+        (None, Channel::Sun, (0, 1, 0)) => MAX_LIGHT,
+        (None, ..) => 0,
+      };
+      // light from above falls down into the voxel
+      Some(spread(channel, level, (-x, -y, -z)))
+    })
+    .fold(emitted, u8::max)
+}
+
+// chunks whose voxels changed are relit around their edited voxels, or in full when those aren't
+// known, chunks get their first light once their voxels load
+// voxels next to a border whose light changed are relit in the neighbor the next frame, so light
+// spreads across chunk and section borders
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity)]
+pub fn update_chunk_light(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  tracker: Res<ChunkTracker>,
+  worlds: Res<TerrainWorlds>,
+  mut borders: Local<HashMap<Entity, HashSet<VoxelId>>>,
+  changed: Query<(Entity, Option<&EditedVoxels>), Changed<ChunkVoxelData>>,
+  mut chunks: Query<(
+    &ChunkVoxelData,
+    Option<&mut ChunkLight>,
+    Option<&TerrainWorld>,
+  )>,
+) {
+  // `None` relights the whole chunk
+  let mut work: HashMap<Entity, Option<HashSet<VoxelId>>> = borders
+    .drain()
+    .map(|(entity, voxels)| (entity, Some(voxels)))
+    .collect();
+  for (entity, edited) in changed.iter() {
+    let lit = matches!(chunks.get(entity), Ok((_, Some(_), _)));
+    let edited = edited.filter(|edited| lit && !edited.0.is_empty());
+    match (work.get_mut(&entity), edited) {
+      (Some(Some(voxels)), Some(edited)) => voxels.extend(edited.0.iter().copied()),
+      (_, Some(edited)) => {
+        work.insert(entity, Some(edited.0.clone()));
+      }
+      (_, None) => {
+        work.insert(entity, None);
+      }
+    }
+  }
+
+  for (entity, voxels) in work {
+    // the chunk's own light is taken out while its neighbors are read
+    let mut light = match chunks.get_mut(entity) {
+      Ok((_, Some(mut light), _)) => Some(std::mem::take(&mut *light)),
+      Ok(_) => None,
+      Err(_) => continue,
+    };
+    let (voxel_data, _, world) = chunks.get(entity).unwrap();
+    let world = world.copied().unwrap_or_default();
+    let tracker = worlds.tracker(&world, &tracker);
+    let neighbor = |voxel: &VoxelId| {
+      let entity = tracker?.entity(&layout.voxel_owner(voxel)?)?;
+      Some((entity, chunks.get(entity).ok()?.1?))
+    };
+    let border = |voxel: &VoxelId| neighbor(voxel).map(|(_, light)| light.packed(voxel));
+
+    let relit = match (light.as_mut(), voxels) {
+      (Some(light), Some(voxels)) => light.relight(&voxel_data.voxels, &registry, &voxels, border),
+      (Some(light), None) => {
+        let computed = ChunkLight::compute_with_border(&voxel_data.voxels, &registry, border);
+        light.update(computed)
+      }
+      // the neighbors lit their border as if this chunk wasn't there
+      (None, _) => {
+        light = Some(ChunkLight::compute_with_border(
+          &voxel_data.voxels,
+          &registry,
+          border,
+        ));
+        voxel_data.voxels.keys().copied().collect()
+      }
+    };
+
+    // neighbors that are lit relight their voxels next to the ones that changed
+    for voxel in relit.iter() {
+      for (x, y, z) in NEIGHBOR_OFFSETS {
+        let outside = *voxel + VoxelId::new(x, y, z);
+        if voxel_data.voxels.contains_key(&outside) {
+          continue;
+        }
+        if let Some((neighbor, _)) = neighbor(&outside) {
+          borders.entry(neighbor).or_default().insert(outside);
+        }
+      }
+    }
+
+    let light = light.unwrap_or_default();
+    match chunks.get_mut(entity) {
+      Ok((_, Some(mut existing), _)) => *existing = light,
+      _ => {
+        commands.entity(entity).insert(light);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn light_should_decay_from_lamp(height in 2i64..8, lamp_y in 0i64..8) {
+          // a sealed box of dirt with one lamp and an air pocket around it
+          let registry = VoxelRegistry::default();
+          let mut voxels = HashMap::new();
+          for x in -3i64..=3 {
+              for z in -3i64..=3 {
+                  for y in 0..height + 2 {
+                      let inside = x.abs() < 3 && z.abs() < 3 && y > 0 && y <= height;
+                      let voxel_type = if inside { VoxelTypeId::AIR } else { VoxelTypeId::DIRT };
+                      voxels.insert(VoxelId::new(x, y, z), voxel_type);
+                  }
+              }
+          }
+          let lamp = VoxelId::new(0, 1 + lamp_y.min(height - 1), 0);
+          voxels.insert(lamp, VoxelTypeId::LAMP);
+
+          let light = ChunkLight::compute(&voxels, &registry);
+          for (voxel, voxel_type) in voxels.iter() {
+              assert_eq!(light.sunlight(voxel), 0);
+              if !registry.is_solid(*voxel_type) {
+                  let d = voxel.x().abs() + voxel.z().abs() + (voxel.y() - lamp.y()).abs();
+                  let expected = registry.light_emission(VoxelTypeId::LAMP).saturating_sub(d as u8);
+                  assert_eq!(light.block_light(voxel), expected, "voxel {:?}", voxel);
+              }
+          }
+      }
+
+      #[test]
+      fn relight_should_match_a_full_compute(seed in 0i64..1000, voxels in prop::collection::vec(0u8..4, 216), edits in prop::collection::vec((0i64..6, 0i64..6, 0i64..6, 0u8..4), 1..8)) {
+          let registry = VoxelRegistry::default();
+          let types = [VoxelTypeId::AIR, VoxelTypeId::AIR, VoxelTypeId::DIRT, VoxelTypeId::LAMP];
+          let mut voxels: HashMap<_, _> = voxels
+              .iter()
+              .enumerate()
+              .map(|(i, t)| (VoxelId::new(i as i64 % 6, i as i64 / 6 % 6, i as i64 / 36), types[*t as usize]))
+              .collect();
+          // neighbors are loaded on some sides, with whatever light they have
+          let border = |voxel: &VoxelId| {
+              let hash = (voxel.x() * 7 + voxel.y() * 13 + voxel.z() * 5 + seed).rem_euclid(97);
+              (hash % 3 != 0).then(|| (hash % 16) as u8 | ((hash / 6 % 16) as u8) << 4)
+          };
+          let mut light = ChunkLight::compute_with_border(&voxels, &registry, border);
+
+          let mut changed = HashSet::new();
+          for (x, y, z, t) in edits {
+              voxels.insert(VoxelId::new(x, y, z), types[t as usize]);
+              changed.insert(VoxelId::new(x, y, z));
+          }
+          let before = light.clone();
+          let relit = light.relight(&voxels, &registry, &changed, border);
+          let full = ChunkLight::compute_with_border(&voxels, &registry, border);
+          for voxel in voxels.keys() {
+              prop_assert_eq!(light.packed(voxel), full.packed(voxel), "voxel {:?}", voxel);
+              prop_assert_eq!(relit.contains(voxel), before.packed(voxel) != full.packed(voxel), "voxel {:?}", voxel);
+          }
+      }
+  }
+}
@@ -0,0 +1,166 @@
+use super::{
+  layout::CubicVoxelLayout, shape, world::TerrainWorld, Chunk, ChunkId, ChunkSpawner, DirtyChunk,
+};
+use bevy::prelude::*;
+
+// picks the mesh detail of chunks from their distance to the nearest spawner and how far out
+// that spawner is zoomed, zoomed out spawners see more of the world in less detail
+pub struct ChunkLodSettings {
+  // rings of chunks covered by each lod level when fully zoomed in
+  pub rings_per_lod: i64,
+  pub max_lod: u8,
+  // lod levels every chunk is demoted by when fully zoomed out
+  pub zoom_demotion: f32,
+  // rings of meshed chunks around a spawner
+  pub mesh_radius: i64,
+  // extra rings of data-only chunks spawned around a fully zoomed out spawner
+  pub zoom_spawn_radius: i64,
+  // mesh tasks submitted per frame, highest detail chunks nearest the center of the view first
+  pub mesh_submissions_per_frame: usize,
+  // chunks spawned at least this many rings from every spawner only generate the voxels within
+  // `clip_depth` of the surface, they're generated in full once a spawner comes closer
+  pub clip_rings: Option<i64>,
+  pub clip_depth: i64,
+}
+impl Default for ChunkLodSettings {
+  fn default() -> Self {
+    Self {
+      rings_per_lod: 2,
+      max_lod: 3,
+      zoom_demotion: 2.0,
+      mesh_radius: 2,
+      zoom_spawn_radius: 3,
+      mesh_submissions_per_frame: 16,
+      clip_rings: Some(4),
+      clip_depth: 4,
+    }
+  }
+}
+
+impl ChunkLodSettings {
+  // rings of chunks to spawn around a spawner at the given zoom
+  #[deprecated(note = "chunks are spawned out to `ChunkSpawnerConfig::spawn_rings`")]
+  pub fn spawn_radius(&self, zoom: f32) -> i64 {
+    self.mesh_radius + (zoom.clamp(0., 1.) * self.zoom_spawn_radius as f32).round() as i64
+  }
+
+  // `rings` is the chunk step distance to the spawner
+  pub fn lod(&self, rings: i64, zoom: f32) -> u8 {
+    let level =
+      (rings / self.rings_per_lod.max(1)) as f32 + zoom.clamp(0., 1.) * self.zoom_demotion;
+    (level.max(0.) as u8).min(self.max_lod)
+  }
+}
+
+// mesh detail of a chunk, 0 is full detail
+// chunks switch detail by swapping in the new mesh once it's built, there's no crossfade
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Component)]
+pub struct ChunkLod(pub u8);
+
+// how far a chunk is from the nearest spawner in lod levels, unlike `ChunkLod` it ignores the
+// zoom, e.g. for games to run chunk contents at a lower tick rate the farther out they are
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Component)]
+pub struct LodBucket(pub u8);
+
+// sent when a chunk gets its first `LodBucket` or moves to another one
+#[derive(Debug, Clone)]
+pub struct LodChanged {
+  pub entity: Entity,
+  pub chunk: ChunkId,
+  pub previous: Option<LodBucket>,
+  pub bucket: LodBucket,
+}
+
+// marks a chunk outside the mesh radius of every spawner, its voxels are loaded but not meshed
+#[derive(Debug, Default, Component)]
+pub struct DataOnlyChunk;
+
+// marks a chunk generated with only the voxels near its surface, the rest are
+// `VoxelTypeId::UNKNOWN`
+#[derive(Debug, Default, Component)]
+pub struct ClippedChunk {
+  // the full voxels were requested and replace the clipped ones when they're ready
+  pub(super) regenerating: bool,
+}
+
+// how far `point` is off the line through the center of a view looking along `view`'s forward,
+// points behind the view count from its position
+pub fn off_view_center(view: &Transform, point: Vec3) -> f32 {
+  let offset = point - view.translation;
+  let forward = view.forward();
+  (offset - forward * offset.dot(forward).max(0.)).length()
+}
+
+#[allow(clippy::type_complexity)]
+pub fn assign_chunk_lods(
+  mut commands: Commands,
+  settings: Res<ChunkLodSettings>,
+  layout: Res<CubicVoxelLayout>,
+  sites: Query<(&Transform, &ChunkSpawner, Option<&TerrainWorld>)>,
+  chunks: Query<(
+    Entity,
+    &Chunk,
+    Option<&ChunkLod>,
+    Option<&DataOnlyChunk>,
+    Option<&Handle<Mesh>>,
+    Option<&TerrainWorld>,
+  )>,
+) {
+  let sites: Vec<_> = sites
+    .iter()
+    .map(|(transform, site, world)| {
+      (
+        layout.space_to_chunk(&transform.translation),
+        site.zoom,
+        site.load_shape,
+        shape::heading(transform),
+        world.copied().unwrap_or_default(),
+      )
+    })
+    .collect();
+  if sites.is_empty() {
+    return;
+  }
+
+  for (entity, chunk, lod, data_only, mesh, world) in chunks.iter() {
+    // spawners only see the chunks of their own world
+    let world = world.copied().unwrap_or_default();
+    let sites: Vec<_> = sites
+      .iter()
+      .filter(|(_, _, _, _, site_world)| *site_world == world)
+      .collect();
+    let (rings, zoom) = match sites
+      .iter()
+      .map(|(site_chunk, zoom, ..)| (layout.chunk_step_distance(&chunk.id, site_chunk), *zoom))
+      .min_by_key(|(rings, _)| *rings)
+    {
+      Some(nearest) => nearest,
+      None => continue,
+    };
+    // chunks are meshed if they're within the mesh radius of any spawner, in its load shape
+    let meshed = sites.iter().any(|(site_chunk, _, load_shape, heading, _)| {
+      let (x, z) = layout.column_offset(site_chunk, &chunk.id);
+      load_shape.contains(x, z, settings.mesh_radius, *heading)
+    });
+
+    let mut entity = commands.entity(entity);
+    match (!meshed, data_only) {
+      (true, None) => {
+        entity.insert(DataOnlyChunk);
+      }
+      (false, Some(_)) => {
+        entity.remove::<DataOnlyChunk>();
+      }
+      _ => {}
+    }
+
+    let new_lod = ChunkLod(settings.lod(rings, zoom));
+    if lod != Some(&new_lod) {
+      entity.insert(new_lod);
+      // chunks that were already meshed are remeshed at the new detail
+      if mesh.is_some() {
+        entity.insert(DirtyChunk);
+      }
+    }
+  }
+}
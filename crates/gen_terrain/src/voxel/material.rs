@@ -0,0 +1,550 @@
+use super::{
+  error::{TerrainError, TerrainErrorEvent},
+  registry::{VoxelRegistry, VoxelTypeId},
+  terrain_set, TerrainSystem,
+};
+use bevy::{
+  ecs::system::{lifetimeless::SRes, SystemParamItem},
+  pbr::{MaterialPipeline, MaterialPlugin},
+  prelude::*,
+  reflect::TypeUuid,
+  render::{
+    mesh::{MeshVertexAttribute, MeshVertexBufferLayout},
+    render_asset::{PrepareAssetError, RenderAsset, RenderAssets},
+    render_resource::*,
+    renderer::{RenderDevice, RenderQueue},
+    texture::{BevyDefault, GpuImage},
+  },
+};
+use bytemuck::{Pod, Zeroable};
+use std::collections::HashMap;
+
+pub const TERRAIN_SHADER_HANDLE: HandleUntyped =
+  HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x2b8e_41f7_93ca_0d56);
+
+// every chunk shares this material, so changing the config recolors all of them at once
+pub const TERRAIN_MATERIAL_HANDLE: HandleUntyped =
+  HandleUntyped::weak_from_u64(TerrainMaterial::TYPE_UUID, 0x7c03_d5a1_6e49_b812);
+
+// stands in for the atlas when there's none
+const WHITE_IMAGE_HANDLE: HandleUntyped =
+  HandleUntyped::weak_from_u64(Image::TYPE_UUID, 0x41d9_8f26_c07e_3ab5);
+
+// stands in for the normal atlas when there's none, every texel points straight out
+const FLAT_NORMAL_IMAGE_HANDLE: HandleUntyped =
+  HandleUntyped::weak_from_u64(Image::TYPE_UUID, 0x9a52_e30c_17b4_6df8);
+
+// the id of the voxel type a vertex belongs to, set by the cube, hex and sphere meshers
+// every vertex of a face has the same id, so custom materials can read it with flat
+// interpolation, e.g. to pick a layer of an array texture
+pub const ATTRIBUTE_VOXEL_TYPE: MeshVertexAttribute =
+  MeshVertexAttribute::new("Voxel_Type", 0x5f1c_93a2, VertexFormat::Uint32);
+
+// the palette has a texel per voxel type and webgl2 only guarantees textures this wide, types past
+// the end are drawn like the last one
+const MAX_PALETTE_SIZE: usize = 2048;
+
+// fades the terrain into `color` from `start` to `end` world units away from the camera
+// with `end` at the edge of the loaded world and `color` matching the `ClearColor`, chunks fade in
+// instead of popping in as they load
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainFog {
+  pub color: Color,
+  pub start: f32,
+  pub end: f32,
+}
+
+impl TerrainFog {
+  // 0 for no fog up to 1 for only fog
+  pub fn amount(&self, distance: f32) -> f32 {
+    ((distance - self.start) / (self.end - self.start).max(f32::EPSILON)).clamp(0., 1.)
+  }
+
+  // the shader divides by the length of the fog, materials leave out fog that isn't valid
+  pub fn validate(&self) -> Result<(), TerrainError> {
+    if !(self.start.is_finite() && self.end.is_finite() && self.start < self.end) {
+      return Err(TerrainError::InvalidMaterial(
+        "fog should end after it starts",
+      ));
+    }
+    Ok(())
+  }
+}
+
+// how chunks are colored, changes are applied to every chunk material without remeshing
+// e.g. a day/night cycle can animate the ambient tint and sun direction every frame
+pub struct TerrainMaterialConfig {
+  // overrides the colors of the voxel registry
+  pub colors: HashMap<VoxelTypeId, Color>,
+  // one image split into a grid of tiles, voxel types pick theirs with `VoxelTypeInfo::atlas_index`
+  // tiles are projected along the world axes (triplanar) and tinted by the voxel color
+  pub atlas: Option<Handle<Image>>,
+  // columns and rows of tiles in the atlas, tiles are numbered row by row
+  pub atlas_size: UVec2,
+  // tile repeats per world unit
+  pub texture_scale: f32,
+  // tangent space normal maps laid out like `atlas`, stored in linear color, voxel types pick
+  // theirs with `normal_maps`
+  // tiles are stretched over each voxel face along the mesh uvs, so chunks need tangents, see
+  // `GenerateTangents`
+  pub normal_atlas: Option<Handle<Image>>,
+  pub normal_maps: HashMap<VoxelTypeId, u32>,
+  // multiplies the final color of every chunk
+  pub ambient: Color,
+  // direction the sunlight travels, faces pointing away from it are shaded darker
+  pub sun_direction: Vec3,
+  // also fades far chunk tiles by their distance to the nearest spawner
+  pub fog: Option<TerrainFog>,
+}
+impl Default for TerrainMaterialConfig {
+  fn default() -> Self {
+    Self {
+      colors: HashMap::new(),
+      atlas: None,
+      atlas_size: UVec2::ONE,
+      texture_scale: 0.25,
+      normal_atlas: None,
+      normal_maps: HashMap::new(),
+      ambient: Color::WHITE,
+      sun_direction: Vec3::new(-0.3, -1.0, -0.5),
+      fog: None,
+    }
+  }
+}
+
+impl TerrainMaterialConfig {
+  pub fn color(&self, registry: &VoxelRegistry, voxel_type: VoxelTypeId) -> Color {
+    self
+      .colors
+      .get(&voxel_type)
+      .copied()
+      .unwrap_or_else(|| registry.color(voxel_type))
+  }
+}
+
+// laid out to match `TerrainMaterial` in terrain.wgsl, every field is 16 bytes for std140
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+struct TerrainMaterialUniform {
+  ambient: [f32; 4],
+  // w is unused
+  sun_direction: [f32; 4],
+  // x is the texture scale, y and z are the columns and rows of the atlas, w is unused
+  texture_scale: [f32; 4],
+  fog_color: [f32; 4],
+  // x and y are where the fog starts and ends, z is 1 with fog and 0 without, w is unused
+  fog: [f32; 4],
+}
+
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "3e0b6a8d-54f2-4c1e-9a77-c2d18e5f0b43"]
+pub struct TerrainMaterial {
+  uniform: TerrainMaterialUniform,
+  // the palette texture has a column per voxel type, colors in the first row and tiles in the second
+  colors: Vec<[f32; 4]>,
+  // x is the atlas tile and y the normal atlas tile, -1 for types without them
+  tiles: Vec<[f32; 4]>,
+  atlas: Option<Handle<Image>>,
+  normal_atlas: Option<Handle<Image>>,
+}
+
+impl TerrainMaterial {
+  pub fn new(config: &TerrainMaterialConfig, registry: &VoxelRegistry) -> Self {
+    let atlas_size = config.atlas_size.max(UVec2::ONE);
+    let mut uniform = TerrainMaterialUniform {
+      ambient: config.ambient.as_linear_rgba_f32(),
+      sun_direction: config
+        .sun_direction
+        .try_normalize()
+        .unwrap_or(-Vec3::Y)
+        .extend(0.)
+        .to_array(),
+      texture_scale: [
+        config.texture_scale,
+        atlas_size.x as f32,
+        atlas_size.y as f32,
+        0.,
+      ],
+      fog_color: [0.; 4],
+      fog: [0.; 4],
+    };
+    if let Some(fog) = config.fog.filter(|fog| fog.validate().is_ok()) {
+      uniform.fog_color = fog.color.as_linear_rgba_f32();
+      uniform.fog = [fog.start, fog.end, 1., 0.];
+    }
+
+    let size = registry.len().clamp(1, MAX_PALETTE_SIZE);
+    let mut colors = vec![Color::WHITE.as_linear_rgba_f32(); size];
+    let mut tiles = vec![[-1., -1., 0., 0.]; size];
+    for (id, info) in registry.iter().take(size) {
+      let slot = id.0 as usize;
+      colors[slot] = config.color(registry, id).as_linear_rgba_f32();
+      if let (Some(_), Some(tile)) = (&config.atlas, info.atlas_index) {
+        tiles[slot][0] = tile as f32;
+      }
+      if let (Some(_), Some(tile)) = (&config.normal_atlas, config.normal_maps.get(&id)) {
+        tiles[slot][1] = *tile as f32;
+      }
+    }
+    Self {
+      uniform,
+      colors,
+      tiles,
+      atlas: config.atlas.clone(),
+      normal_atlas: config.normal_atlas.clone(),
+    }
+  }
+}
+
+pub struct GpuTerrainMaterial {
+  _buffer: Buffer,
+  _palette: Texture,
+  bind_group: BindGroup,
+}
+
+impl RenderAsset for TerrainMaterial {
+  type ExtractedAsset = TerrainMaterial;
+  type PreparedAsset = GpuTerrainMaterial;
+  type Param = (
+    SRes<RenderDevice>,
+    SRes<RenderQueue>,
+    SRes<MaterialPipeline<Self>>,
+    SRes<RenderAssets<Image>>,
+  );
+
+  fn extract_asset(&self) -> Self::ExtractedAsset {
+    self.clone()
+  }
+
+  fn prepare_asset(
+    material: Self::ExtractedAsset,
+    (render_device, render_queue, pipeline, images): &mut SystemParamItem<Self::Param>,
+  ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+    let atlas_handle = material
+      .atlas
+      .clone()
+      .unwrap_or_else(|| WHITE_IMAGE_HANDLE.typed());
+    let normal_handle = material
+      .normal_atlas
+      .clone()
+      .unwrap_or_else(|| FLAT_NORMAL_IMAGE_HANDLE.typed());
+    // the atlases are still loading
+    let (atlas, normal_atlas): (&GpuImage, &GpuImage) =
+      match (images.get(&atlas_handle), images.get(&normal_handle)) {
+        (Some(atlas), Some(normal_atlas)) => (atlas, normal_atlas),
+        _ => return Err(PrepareAssetError::RetryNextUpdate(material)),
+      };
+
+    let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+      label: Some("terrain material uniform"),
+      contents: bytemuck::bytes_of(&material.uniform),
+      usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+    });
+    let palette = render_device.create_texture_with_data(
+      render_queue,
+      &TextureDescriptor {
+        label: Some("terrain material palette"),
+        size: Extent3d {
+          width: material.colors.len() as u32,
+          height: 2,
+          depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba32Float,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+      },
+      bytemuck::cast_slice(&[material.colors.as_slice(), material.tiles.as_slice()].concat()),
+    );
+    let palette_view = palette.create_view(&TextureViewDescriptor::default());
+    let sampler = render_device.create_sampler(&SamplerDescriptor {
+      // tiles are repeated in the shader, repeating the atlas would bleed the opposite edge in
+      address_mode_u: AddressMode::ClampToEdge,
+      address_mode_v: AddressMode::ClampToEdge,
+      mag_filter: FilterMode::Linear,
+      min_filter: FilterMode::Linear,
+      ..default()
+    });
+
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+      label: Some("terrain material bind group"),
+      entries: &[
+        BindGroupEntry {
+          binding: 0,
+          resource: buffer.as_entire_binding(),
+        },
+        BindGroupEntry {
+          binding: 1,
+          resource: BindingResource::TextureView(&atlas.texture_view),
+        },
+        BindGroupEntry {
+          binding: 2,
+          resource: BindingResource::Sampler(&sampler),
+        },
+        BindGroupEntry {
+          binding: 3,
+          resource: BindingResource::TextureView(&normal_atlas.texture_view),
+        },
+        BindGroupEntry {
+          binding: 4,
+          resource: BindingResource::TextureView(&palette_view),
+        },
+      ],
+      layout: &pipeline.material_layout,
+    });
+
+    Ok(GpuTerrainMaterial {
+      _buffer: buffer,
+      _palette: palette,
+      bind_group,
+    })
+  }
+}
+
+impl Material for TerrainMaterial {
+  fn vertex_shader(_: &AssetServer) -> Option<Handle<Shader>> {
+    Some(TERRAIN_SHADER_HANDLE.typed())
+  }
+
+  fn fragment_shader(_: &AssetServer) -> Option<Handle<Shader>> {
+    Some(TERRAIN_SHADER_HANDLE.typed())
+  }
+
+  fn bind_group(material: &<Self as RenderAsset>::PreparedAsset) -> &BindGroup {
+    &material.bind_group
+  }
+
+  fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("terrain material layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Buffer {
+            ty: BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: BufferSize::new(std::mem::size_of::<TerrainMaterialUniform>() as u64),
+          },
+          count: None,
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+          },
+          count: None,
+        },
+        BindGroupLayoutEntry {
+          binding: 2,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None,
+        },
+        BindGroupLayoutEntry {
+          binding: 3,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+          },
+          count: None,
+        },
+        // read with `textureLoad`, 32 bit float textures can't be filtered everywhere
+        BindGroupLayoutEntry {
+          binding: 4,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: false },
+            view_dimension: TextureViewDimension::D2,
+            multisampled: false,
+          },
+          count: None,
+        },
+      ],
+    })
+  }
+
+  fn specialize(
+    _pipeline: &MaterialPipeline<Self>,
+    descriptor: &mut RenderPipelineDescriptor,
+    layout: &MeshVertexBufferLayout,
+  ) -> Result<(), SpecializedMeshPipelineError> {
+    let mut attributes = vec![
+      Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+      Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+    ];
+    let mut shader_defs = Vec::new();
+    // hex and sphere meshes have no colors, they're lit fully
+    if layout.contains(Mesh::ATTRIBUTE_COLOR) {
+      attributes.push(Mesh::ATTRIBUTE_COLOR.at_shader_location(2));
+      shader_defs.push(String::from("VERTEX_COLORS"));
+    }
+    // meshes without voxel types are colored as dirt
+    if layout.contains(ATTRIBUTE_VOXEL_TYPE) {
+      attributes.push(ATTRIBUTE_VOXEL_TYPE.at_shader_location(3));
+      shader_defs.push(String::from("VOXEL_TYPES"));
+    }
+    // only chunks meshed with tangents are normal mapped
+    if layout.contains(Mesh::ATTRIBUTE_TANGENT) && layout.contains(Mesh::ATTRIBUTE_UV_0) {
+      attributes.push(Mesh::ATTRIBUTE_TANGENT.at_shader_location(4));
+      attributes.push(Mesh::ATTRIBUTE_UV_0.at_shader_location(5));
+      shader_defs.push(String::from("NORMAL_MAPS"));
+    }
+
+    descriptor.vertex.buffers = vec![layout.get_layout(&attributes)?];
+    descriptor
+      .vertex
+      .shader_defs
+      .extend(shader_defs.iter().cloned());
+    if let Some(fragment) = descriptor.fragment.as_mut() {
+      fragment.shader_defs.extend(shader_defs);
+    }
+    Ok(())
+  }
+}
+
+pub struct TerrainMaterialPlugin;
+
+impl Plugin for TerrainMaterialPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<TerrainMaterialConfig>();
+
+    // without a renderer (headless apps) there's nothing to color
+    if let Some(mut shaders) = app.world.get_resource_mut::<Assets<Shader>>() {
+      shaders.set_untracked(
+        TERRAIN_SHADER_HANDLE,
+        Shader::from_wgsl(include_str!("terrain.wgsl")),
+      );
+    } else {
+      return;
+    }
+    let mut images = app.world.resource_mut::<Assets<Image>>();
+    images.set_untracked(
+      WHITE_IMAGE_HANDLE,
+      Image::new_fill(
+        Extent3d::default(),
+        TextureDimension::D2,
+        &[255, 255, 255, 255],
+        TextureFormat::bevy_default(),
+      ),
+    );
+    images.set_untracked(
+      FLAT_NORMAL_IMAGE_HANDLE,
+      Image::new_fill(
+        Extent3d::default(),
+        TextureDimension::D2,
+        &[128, 128, 255, 255],
+        TextureFormat::Rgba8Unorm,
+      ),
+    );
+
+    app
+      .add_plugin(MaterialPlugin::<TerrainMaterial>::default())
+      .add_system_set(
+        terrain_set(TerrainSystem::Prepare).with_system(apply_terrain_material_config),
+      );
+  }
+}
+
+pub fn apply_terrain_material_config(
+  config: Res<TerrainMaterialConfig>,
+  registry: Option<Res<VoxelRegistry>>,
+  mut materials: ResMut<Assets<TerrainMaterial>>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  let registry_changed = matches!(&registry, Some(registry) if registry.is_changed());
+  if config.is_changed() {
+    if let Some(Err(err)) = config.fog.map(|fog| fog.validate()) {
+      errors.send(TerrainErrorEvent::new(err, "applying the terrain fog"));
+    }
+  }
+  if config.is_changed() || registry_changed {
+    let registry = registry.map(|r| (*r).clone()).unwrap_or_default();
+    if registry_changed && registry.len() > MAX_PALETTE_SIZE {
+      warn!(
+        "{} voxel types don't fit the terrain palette, types past {} are drawn like the last one",
+        registry.len(),
+        MAX_PALETTE_SIZE
+      );
+    }
+    materials.set_untracked(
+      TERRAIN_MATERIAL_HANDLE,
+      TerrainMaterial::new(&config, &registry),
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::registry::VoxelTypeInfo;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn config_should_map_to_palette_by_voxel_id(r in 0f32..1., g in 0f32..1., b in 0f32..1., tile in 0u32..16, textured in any::<bool>()) {
+          let mut registry = VoxelRegistry::default();
+          let stone = registry.register(VoxelTypeInfo {
+              atlas_index: Some(tile),
+              ..VoxelTypeInfo::solid("stone", Color::rgb(b, g, r))
+          });
+          let mut config = TerrainMaterialConfig::default();
+          config.colors.insert(VoxelTypeId::LAMP, Color::rgb(r, g, b));
+          if textured {
+              config.atlas = Some(Handle::default());
+          }
+
+          let material = TerrainMaterial::new(&config, &registry);
+          let lamp = VoxelTypeId::LAMP.0 as usize;
+          let slot = stone.0 as usize;
+          assert_eq!(material.colors.len(), registry.len());
+          assert_eq!(material.colors[lamp], Color::rgb(r, g, b).as_linear_rgba_f32());
+          assert_eq!(material.colors[slot], Color::rgb(b, g, r).as_linear_rgba_f32());
+          let expected = if textured { tile as f32 } else { -1. };
+          assert_eq!(material.tiles[slot][0], expected);
+          assert_eq!(material.tiles[lamp][0], -1.);
+      }
+
+      #[test]
+      fn normal_maps_should_need_a_normal_atlas(tile in 0u32..16, with_atlas in any::<bool>()) {
+          let registry = VoxelRegistry::default();
+          let mut config = TerrainMaterialConfig::default();
+          config.normal_maps.insert(VoxelTypeId::DIRT, tile);
+          if with_atlas {
+              config.normal_atlas = Some(Handle::default());
+          }
+
+          let material = TerrainMaterial::new(&config, &registry);
+          let dirt = VoxelTypeId::DIRT.0 as usize;
+          let lamp = VoxelTypeId::LAMP.0 as usize;
+          let expected = if with_atlas { tile as f32 } else { -1. };
+          assert_eq!(material.tiles[dirt][1], expected);
+          assert_eq!(material.tiles[lamp][1], -1.);
+          // normal maps don't texture the color
+          assert_eq!(material.tiles[dirt][0], -1.);
+      }
+
+      #[test]
+      fn fog_should_thicken_with_distance(start in 0f32..500., length in 0f32..500., a in 0f32..2000., b in 0f32..2000.) {
+          let fog = TerrainFog { color: Color::WHITE, start, end: start + length };
+          let (near, far) = (a.min(b), a.max(b));
+          assert!(fog.amount(near) <= fog.amount(far));
+          assert!((0.0..=1.0).contains(&fog.amount(near)));
+          assert_eq!(fog.amount(start.min(near)), 0.);
+          assert_eq!(fog.amount(start + length + 1.), 1.);
+
+          // fog without a length is left out of the material
+          let valid = fog.end > fog.start;
+          prop_assert_eq!(fog.validate().is_ok(), valid);
+          let config = TerrainMaterialConfig { fog: Some(fog), ..Default::default() };
+          let material = TerrainMaterial::new(&config, &VoxelRegistry::default());
+          prop_assert_eq!(material.uniform.fog[2], if valid { 1. } else { 0. });
+      }
+  }
+}
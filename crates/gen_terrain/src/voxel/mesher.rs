@@ -1,17 +1,243 @@
-use super::{generator::VoxelType, VoxelId};
+#[cfg(feature = "render")]
+use super::{
+  decal::{self, ChunkDecals, VoxelFace},
+  generator::{ChunkJob, ChunkStep},
+  hex::CubeHexLayout,
+  material::ATTRIBUTE_VOXEL_TYPE,
+  sphere::SphereVoxelLayout,
+};
+use super::{
+  headless::MeshData,
+  layout::CubicVoxelLayout,
+  light::{ChunkLight, MAX_LIGHT},
+  registry::{VoxelRegistry, VoxelTypeId},
+  ChunkId, VoxelId,
+};
+use bevy::prelude::*;
+#[cfg(feature = "render")]
 use bevy::{
-  prelude::*,
-  tasks::{AsyncComputeTaskPool, Task},
+  render::mesh::{Indices, VertexAttributeValues},
+  tasks::TaskPool,
 };
 use std::collections::HashMap;
+#[cfg(feature = "render")]
+use std::collections::HashSet;
+
+// face offset, normal and corners (counter-clockwise when viewed from outside) of a unit cube
+type CubeFace = ((i64, i64, i64), [f32; 3], [[f32; 3]; 4]);
+const CUBE_FACES: [CubeFace; 6] = [
+  (
+    (1, 0, 0),
+    [1., 0., 0.],
+    [[1., 0., 0.], [1., 1., 0.], [1., 1., 1.], [1., 0., 1.]],
+  ),
+  (
+    (-1, 0, 0),
+    [-1., 0., 0.],
+    [[0., 0., 0.], [0., 0., 1.], [0., 1., 1.], [0., 1., 0.]],
+  ),
+  (
+    (0, 1, 0),
+    [0., 1., 0.],
+    [[0., 1., 0.], [0., 1., 1.], [1., 1., 1.], [1., 1., 0.]],
+  ),
+  (
+    (0, -1, 0),
+    [0., -1., 0.],
+    [[0., 0., 0.], [1., 0., 0.], [1., 0., 1.], [0., 0., 1.]],
+  ),
+  (
+    (0, 0, 1),
+    [0., 0., 1.],
+    [[0., 0., 1.], [1., 0., 1.], [1., 1., 1.], [0., 1., 1.]],
+  ),
+  (
+    (0, 0, -1),
+    [0., 0., -1.],
+    [[0., 0., 0.], [0., 1., 0.], [1., 1., 0.], [1., 0., 0.]],
+  ),
+];
+
+const FACE_UVS: [[f32; 2]; 4] = [[0., 0.], [0., 1.], [1., 1.], [1., 0.]];
+
+// darkest a face gets, so unlit caves aren't pitch black
+const MIN_BRIGHTNESS: f32 = 0.05;
+
+// chunks are meshed in up to this many horizontal slabs in parallel
+#[cfg(feature = "render")]
+const MAX_MESH_SLABS: usize = 8;
+// chunks with fewer voxels per slab than this use fewer slabs, also the most voxels meshed per step
+// by `generate_mesh_steps`
+#[cfg(feature = "render")]
+const MIN_SLAB_VOXELS: usize = 4096;
+// cells of lower detail meshes are at most 2^this voxels a side
+#[cfg(feature = "render")]
+const MAX_LOD_SHIFT: u8 = 16;
+
+// how vertex normals of chunk meshes are computed, changing it remeshes the loaded chunks
+// vertices are only smoothed with vertices of the same chunk, so seams can show at chunk borders
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NormalMode {
+  // each face has its own normal, right for blocky voxels
+  #[default]
+  Flat,
+  // vertices at the same position share the average normal of the faces around them
+  Smooth,
+  // like `Smooth` but larger faces pull the normal further toward theirs
+  AreaWeighted,
+}
+
+// normals of a triangle list for `mode`
+pub fn calculate_normals(
+  positions: &[[f32; 3]],
+  indices: &[u32],
+  mode: NormalMode,
+) -> Vec<[f32; 3]> {
+  let mut normals = vec![Vec3::ZERO; positions.len()];
+  // smoothed normals are summed per position since faces don't share vertices
+  let mut shared: HashMap<[i32; 3], Vec3> = HashMap::new();
+  let key = |index: usize| positions[index].map(|v| (v * 1024.).round() as i32);
+
+  for triangle in indices.chunks_exact(3) {
+    let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+    // its length is twice the triangle's area
+    let cross = (b - a).cross(c - a);
+    let face = match mode {
+      NormalMode::AreaWeighted => cross,
+      NormalMode::Flat | NormalMode::Smooth => cross.normalize_or_zero(),
+    };
+    for index in triangle.iter().map(|i| *i as usize) {
+      match mode {
+        NormalMode::Flat => normals[index] = face,
+        NormalMode::Smooth | NormalMode::AreaWeighted => {
+          *shared.entry(key(index)).or_default() += face;
+        }
+      }
+    }
+  }
+
+  if mode != NormalMode::Flat {
+    for (index, normal) in normals.iter_mut().enumerate() {
+      if let Some(sum) = shared.get(&key(index)) {
+        *normal = sum.normalize_or_zero();
+      }
+    }
+  }
+  normals
+    .into_iter()
+    .map(|normal| normal.to_array())
+    .collect()
+}
+
+// recomputes the normals of a mesh built by the mesher, flat normals are already right
+#[cfg(feature = "render")]
+pub fn apply_normal_mode(mesh: &mut Mesh, mode: NormalMode) {
+  if mode == NormalMode::Flat {
+    return;
+  }
+  let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+    Some(VertexAttributeValues::Float32x3(positions)) => positions,
+    _ => return,
+  };
+  let normals = match mesh.indices() {
+    Some(Indices::U32(indices)) => calculate_normals(positions, indices, mode),
+    _ => return,
+  };
+  mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+}
+
+// whether chunk meshes get tangents, which normal mapped materials need, changing it remeshes
+// the loaded chunks
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GenerateTangents(pub bool);
+
+// tangents of a triangle list along its uvs, w is the handedness of the bitangent
+// each vertex gets the sum of the tangents of its triangles made perpendicular to its normal
+pub fn calculate_tangents(
+  positions: &[[f32; 3]],
+  normals: &[[f32; 3]],
+  uvs: &[[f32; 2]],
+  indices: &[u32],
+) -> Vec<[f32; 4]> {
+  let mut tangents = vec![Vec3::ZERO; positions.len()];
+  let mut bitangents = vec![Vec3::ZERO; positions.len()];
+  for triangle in indices.chunks_exact(3) {
+    let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+    let (edge1, edge2) = (
+      Vec3::from(positions[b]) - Vec3::from(positions[a]),
+      Vec3::from(positions[c]) - Vec3::from(positions[a]),
+    );
+    let (uv1, uv2) = (
+      Vec2::from(uvs[b]) - Vec2::from(uvs[a]),
+      Vec2::from(uvs[c]) - Vec2::from(uvs[a]),
+    );
+    let determinant = uv1.x * uv2.y - uv2.x * uv1.y;
+    // the uvs of the triangle are degenerate
+    if determinant.abs() < f32::EPSILON {
+      continue;
+    }
+    let tangent = (edge1 * uv2.y - edge2 * uv1.y) / determinant;
+    let bitangent = (edge2 * uv1.x - edge1 * uv2.x) / determinant;
+    for index in [a, b, c] {
+      tangents[index] += tangent;
+      bitangents[index] += bitangent;
+    }
+  }
+
+  tangents
+    .iter()
+    .zip(bitangents.iter())
+    .zip(normals.iter())
+    .map(|((tangent, bitangent), normal)| {
+      let normal = Vec3::from(*normal);
+      let tangent = (*tangent - normal * normal.dot(*tangent))
+        .try_normalize()
+        .unwrap_or_else(|| normal.any_orthonormal_vector());
+      let handedness = if normal.cross(tangent).dot(*bitangent) < 0. {
+        -1.
+      } else {
+        1.
+      };
+      tangent.extend(handedness).to_array()
+    })
+    .collect()
+}
+
+// adds tangents to a mesh built by the mesher, after its normals are final
+#[cfg(feature = "render")]
+pub fn apply_tangents(mesh: &mut Mesh) {
+  let tangents = match (
+    mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+    mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+    mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+    mesh.indices(),
+  ) {
+    (
+      Some(VertexAttributeValues::Float32x3(positions)),
+      Some(VertexAttributeValues::Float32x3(normals)),
+      Some(VertexAttributeValues::Float32x2(uvs)),
+      Some(Indices::U32(indices)),
+    ) => calculate_tangents(positions, normals, uvs, indices),
+    _ => return,
+  };
+  mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, tangents);
+}
 
-// TODO: lod
 // TODO: use asset loader and return Handle<Mesh> instead of blocking
+#[cfg(feature = "render")]
+#[allow(clippy::too_many_arguments)]
 pub fn generate_mesh(
-  thread_pool: &Res<AsyncComputeTaskPool>,
-  _voxels: &HashMap<VoxelId, VoxelType>,
-  _lod: u8,
-) -> Task<Mesh> {
+  pool: &TaskPool,
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+  lod: u8,
+  decals: Option<&ChunkDecals>,
+  normal_mode: NormalMode,
+  tangents: GenerateTangents,
+) -> ChunkJob<Mesh> {
   // how do we use the voxel data?
   // we cannot move the voxel data out of the ecs system
   // for now we could clone it but maybe the voxel data needs to sit somewhere else
@@ -20,5 +246,1607 @@ pub fn generate_mesh(
   // the mesh hmmm... maybe we need some sort of double buffer?
   // edits are made in the front buffer while we use the back buffer to generate the mesh
   // we swap buffers if there are changes in the front buffer and mesh generation is complete
-  thread_pool.spawn(async move { Mesh::from(shape::Plane { size: 1.0 * 23. }) })
+  let layout = layout.clone();
+  let registry = registry.clone();
+  let voxels = voxels.clone();
+  let light = light.clone();
+  let decals = decals.cloned();
+  let pool = pool.clone();
+  Box::new(move || {
+    // small chunks aren't worth splitting, neither is anything without worker threads
+    // lower detail meshes are cheap enough as they are
+    let slabs = if cfg!(feature = "terrain-wasm") || pool.thread_num() <= 1 || lod > 0 {
+      1
+    } else {
+      (voxels.len() / MIN_SLAB_VOXELS).clamp(1, MAX_MESH_SLABS)
+    };
+    let mesh = match lod {
+      0 => mesh_chunk_slabs(&pool, &layout, &registry, &chunk, &voxels, &light, slabs),
+      _ => mesh_chunk_lod(&layout, &registry, &chunk, &voxels, &light, lod),
+    };
+    finish_mesh(
+      mesh,
+      &layout,
+      &chunk,
+      lod,
+      decals.as_ref(),
+      normal_mode,
+      tangents,
+    )
+  })
+}
+
+// same as `generate_mesh` but a slab of the chunk per step and no worker threads, so
+// `run_chunk_jobs` can stop meshing in between slabs
+#[cfg(feature = "render")]
+#[allow(clippy::too_many_arguments)]
+pub fn generate_mesh_steps(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+  lod: u8,
+  decals: Option<&ChunkDecals>,
+  normal_mode: NormalMode,
+  tangents: GenerateTangents,
+) -> ChunkStep<Mesh> {
+  let layout = layout.clone();
+  let registry = registry.clone();
+  let voxels = voxels.clone();
+  let light = light.clone();
+  let decals = decals.cloned();
+  // lower detail meshes are cheap enough to build in one go
+  if lod > 0 {
+    return ChunkStep::job(Box::new(move || {
+      let mesh = mesh_chunk_lod(&layout, &registry, &chunk, &voxels, &light, lod);
+      finish_mesh(
+        mesh,
+        &layout,
+        &chunk,
+        lod,
+        decals.as_ref(),
+        normal_mode,
+        tangents,
+      )
+    }));
+  }
+
+  let finish_layout = layout.clone();
+  ChunkStep::Next(Box::new(move || {
+    let slab_count = (voxels.len() / MIN_SLAB_VOXELS).max(1);
+    let mut slabs = vec![Vec::new(); slab_count];
+    for (voxel, voxel_type) in voxels.iter() {
+      slabs[slab_of(&layout, &chunk, voxel, slab_count)].push((*voxel, *voxel_type));
+    }
+    ChunkStep::repeat((slabs, MeshBuilder::default()), move |(slabs, builder)| {
+      if let Some(slab) = slabs.pop() {
+        let to_mesh = slab.iter().map(|(voxel, voxel_type)| (voxel, voxel_type));
+        mesh_voxels(
+          &layout, &registry, &chunk, to_mesh, &voxels, &light, builder,
+        );
+      }
+      slabs.is_empty()
+    })
+    .map(move |(_, builder)| {
+      let mesh = builder.build();
+      finish_mesh(
+        mesh,
+        &finish_layout,
+        &chunk,
+        0,
+        decals.as_ref(),
+        normal_mode,
+        tangents,
+      )
+    })
+  }))
+}
+
+// what's done to a chunk's mesh once its faces are in
+#[cfg(feature = "render")]
+fn finish_mesh(
+  mut mesh: Mesh,
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+  lod: u8,
+  decals: Option<&ChunkDecals>,
+  normal_mode: NormalMode,
+  tangents: GenerateTangents,
+) -> Mesh {
+  // lower detail meshes are too far out for decals to show
+  if let (0, Some(decals)) = (lod, decals) {
+    apply_decals(&mut mesh, layout, chunk, decals);
+  }
+  apply_normal_mode(&mut mesh, normal_mode);
+  if tangents.0 {
+    apply_tangents(&mut mesh);
+  }
+  mesh
+}
+
+// emits the faces of solid voxels that face a non-opaque voxel of another type, faces toward
+// voxels of other chunks are always emitted
+// faces are shaded with the light of the voxel they face, baked into the vertex colors
+#[cfg(feature = "render")]
+pub fn mesh_chunk(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+) -> Mesh {
+  mesh_chunk_data(layout, registry, chunk, voxels, light).into()
+}
+
+// same as `mesh_chunk` but the geometry is left in plain vectors
+pub fn mesh_chunk_data(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+) -> MeshData {
+  let mut builder = MeshBuilder::default();
+  mesh_voxels(
+    layout,
+    registry,
+    chunk,
+    voxels.iter(),
+    voxels,
+    light,
+    &mut builder,
+  );
+  builder.into_data()
+}
+
+// same as `mesh_chunk` but the chunk is split into `slab_count` horizontal slabs meshed in
+// parallel on `pool`, the slabs are joined into a single mesh
+#[cfg(feature = "render")]
+pub fn mesh_chunk_slabs(
+  pool: &TaskPool,
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+  slab_count: usize,
+) -> Mesh {
+  if slab_count <= 1 {
+    return mesh_chunk(layout, registry, chunk, voxels, light);
+  }
+
+  let mut slabs = vec![Vec::new(); slab_count];
+  for (voxel, voxel_type) in voxels.iter() {
+    slabs[slab_of(layout, chunk, voxel, slab_count)].push((voxel, voxel_type));
+  }
+
+  let builders = pool.scope(|scope| {
+    for slab in slabs.iter() {
+      scope.spawn(async move {
+        let mut builder = MeshBuilder::default();
+        mesh_voxels(
+          layout,
+          registry,
+          chunk,
+          slab.iter().copied(),
+          voxels,
+          light,
+          &mut builder,
+        );
+        builder
+      });
+    }
+  });
+
+  let mut builder = MeshBuilder::default();
+  for slab in builders {
+    builder.append(slab);
+  }
+  builder.build()
+}
+
+// which of `slab_count` horizontal slabs of the chunk the voxel is in, from the bottom up
+#[cfg(feature = "render")]
+fn slab_of(
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+  voxel: &VoxelId,
+  slab_count: usize,
+) -> usize {
+  let height = layout.chunk_voxel_height().max(1);
+  let bottom = layout.get_center_voxel(chunk).y();
+  ((voxel.y() - bottom).clamp(0, height - 1) as usize * slab_count) / height as usize
+}
+
+// which quads of a mesh made by `mesh_chunk` are the faces of which voxel, so `patch_mesh` can
+// splice the faces of edited voxels in place instead of reading the whole mesh back
+// kept on the chunk next to its mesh, it's built from the mesh the first time the chunk is patched
+// and dropped when the chunk gets a new mesh
+#[cfg(feature = "render")]
+#[derive(Debug, Default, Clone, PartialEq, Component)]
+pub struct MeshFaceIndex {
+  // the voxel each quad is a face of and the voxel the face looks at, in mesh order
+  quads: Vec<(VoxelId, VoxelId)>,
+  by_voxel: HashMap<VoxelId, Vec<usize>>,
+}
+
+#[cfg(feature = "render")]
+impl MeshFaceIndex {
+  // `None` if the mesh wasn't made by `mesh_chunk`
+  pub fn of(mesh: &Mesh, layout: &CubicVoxelLayout, chunk: &ChunkId) -> Option<Self> {
+    let builder = MeshBuilder::from_quads(mesh)?;
+    let mut index = Self::default();
+    for quad in 0..builder.positions.len() / 4 {
+      let (voxel, facing, _) = builder.quad_voxels(quad, layout, chunk);
+      index.push(voxel, facing);
+    }
+    Some(index)
+  }
+
+  pub fn len(&self) -> usize {
+    self.quads.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.quads.is_empty()
+  }
+
+  fn push(&mut self, voxel: VoxelId, facing: VoxelId) {
+    self
+      .by_voxel
+      .entry(voxel)
+      .or_default()
+      .push(self.quads.len());
+    self.quads.push((voxel, facing));
+  }
+
+  // the last quad takes the place of `quad`, like `MeshBuilder::swap_remove_quad`
+  fn swap_remove(&mut self, quad: usize) {
+    let last = self.quads.len() - 1;
+    let (voxel, _) = self.quads.swap_remove(quad);
+    if let Some(quads) = self.by_voxel.get_mut(&voxel) {
+      quads.retain(|q| *q != quad);
+      if quads.is_empty() {
+        self.by_voxel.remove(&voxel);
+      }
+    }
+    if quad != last {
+      let (moved, _) = self.quads[quad];
+      for q in self.by_voxel.entry(moved).or_default().iter_mut() {
+        if *q == last {
+          *q = quad;
+        }
+      }
+    }
+  }
+
+  fn quads_of(&self, voxel: &VoxelId) -> &[usize] {
+    self
+      .by_voxel
+      .get(voxel)
+      .map(|quads| quads.as_slice())
+      .unwrap_or_default()
+  }
+}
+
+// updates a mesh made by `mesh_chunk` after `changed` voxels were edited, the faces of the changed
+// voxels and their neighbors are spliced out and emitted again, and the faces looking at voxels in
+// `light.relit()` are shaded again, the rest of the mesh isn't touched
+// flat normals and tangents are written per face, smoothed normals are shared with the faces
+// around them so they're computed over the whole mesh again
+// returns false if the mesh wasn't made by `mesh_chunk` or doesn't match `index`, it's left
+// untouched and needs a full remesh
+#[cfg(feature = "render")]
+#[allow(clippy::too_many_arguments)]
+pub fn patch_mesh(
+  mesh: &mut Mesh,
+  index: &mut MeshFaceIndex,
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+  changed: &HashSet<VoxelId>,
+  decals: Option<&ChunkDecals>,
+  normal_mode: NormalMode,
+  tangents: GenerateTangents,
+) -> bool {
+  // flat tangents are spliced along with the rest, smoothed ones are computed again
+  let flat_tangents = tangents.0 && normal_mode == NormalMode::Flat;
+  let tangent_count = match mesh.attribute(Mesh::ATTRIBUTE_TANGENT) {
+    Some(VertexAttributeValues::Float32x4(values)) => values.len(),
+    _ => 0,
+  };
+  let mut builder = match MeshBuilder::take_quads(mesh) {
+    Some(builder)
+      if builder.positions.len() == index.len() * 4
+        && (!flat_tangents || tangent_count == builder.positions.len()) =>
+    {
+      builder
+    }
+    Some(builder) => {
+      builder.put_back(mesh);
+      return false;
+    }
+    None => return false,
+  };
+  let mut vertex_tangents = match mesh.attribute_mut(Mesh::ATTRIBUTE_TANGENT) {
+    Some(VertexAttributeValues::Float32x4(values)) if flat_tangents => std::mem::take(values),
+    _ => Vec::new(),
+  };
+  let shade = |voxel: &VoxelId, facing: &VoxelId| {
+    let level = match voxels.get(facing) {
+      Some(_) => light.level(facing),
+      None => MAX_LIGHT,
+    };
+    let color = brightness_color(level);
+    let tint = VoxelFace::between(voxel, facing)
+      .and_then(|face| decals.and_then(|decals| decals.get(voxel, face)));
+    match tint {
+      Some(tint) => decal::tint_vertex_color(color, tint),
+      None => color,
+    }
+  };
+
+  let affected: HashSet<VoxelId> = changed
+    .iter()
+    .flat_map(|voxel| {
+      std::iter::once(*voxel).chain(
+        CUBE_FACES
+          .iter()
+          .map(move |((x, y, z), _, _)| *voxel + VoxelId::new(*x, *y, *z)),
+      )
+    })
+    .collect();
+
+  // removed from the back so the quads moved into the gaps are never ones still to be removed
+  let mut removed: Vec<usize> = affected
+    .iter()
+    .flat_map(|voxel| index.quads_of(voxel).iter().copied())
+    .collect();
+  removed.sort_unstable_by(|a, b| b.cmp(a));
+  for quad in removed {
+    builder.swap_remove_quad(quad);
+    if flat_tangents {
+      swap_remove_vertices(&mut vertex_tangents, quad);
+    }
+    index.swap_remove(quad);
+  }
+
+  // faces of the rest of the mesh looking at voxels whose light changed
+  for relit in light
+    .relit()
+    .iter()
+    .filter(|voxel| !affected.contains(voxel))
+  {
+    for ((x, y, z), ..) in CUBE_FACES.iter() {
+      let neighbor = *relit + VoxelId::new(*x, *y, *z);
+      for quad in index.quads_of(&neighbor) {
+        let (voxel, facing) = index.quads[*quad];
+        if facing == *relit {
+          let color = shade(&voxel, &facing);
+          builder.colors[quad * 4..quad * 4 + 4].fill(color);
+        }
+      }
+    }
+  }
+
+  for voxel in affected.iter() {
+    let mut faces = MeshBuilder::default();
+    mesh_voxels(
+      layout,
+      registry,
+      chunk,
+      voxels.get_key_value(voxel).into_iter(),
+      voxels,
+      light,
+      &mut faces,
+    );
+    for quad in 0..faces.positions.len() / 4 {
+      let (_, facing, _) = faces.quad_voxels(quad, layout, chunk);
+      let color = shade(voxel, &facing);
+      faces.colors[quad * 4..quad * 4 + 4].fill(color);
+      index.push(*voxel, facing);
+    }
+    if flat_tangents {
+      vertex_tangents.extend(calculate_tangents(
+        &faces.positions,
+        &faces.normals,
+        &faces.uvs,
+        &faces.indices,
+      ));
+    }
+    builder.append(faces);
+  }
+
+  builder.put_back(mesh);
+  if normal_mode != NormalMode::Flat {
+    apply_normal_mode(mesh, normal_mode);
+    if tangents.0 {
+      apply_tangents(mesh);
+    }
+  } else if flat_tangents {
+    mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, vertex_tangents);
+  }
+  true
+}
+
+// the 4 vertices of the last quad take the place of the ones of `quad`
+#[cfg(feature = "render")]
+fn swap_remove_vertices<T: Copy>(values: &mut Vec<T>, quad: usize) {
+  let last = values.len() / 4 - 1;
+  if quad != last {
+    values.copy_within(last * 4..last * 4 + 4, quad * 4);
+  }
+  values.truncate(last * 4);
+}
+
+// multiplies the tints of `decals` into the vertex colors of the faces they're on, for meshes made
+// by `mesh_chunk` after their light is baked in, other meshes are left untouched
+#[cfg(feature = "render")]
+pub fn apply_decals(
+  mesh: &mut Mesh,
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+  decals: &ChunkDecals,
+) {
+  if decals.is_empty() {
+    return;
+  }
+  let mut builder = match MeshBuilder::from_quads(mesh) {
+    Some(builder) => builder,
+    None => return,
+  };
+  for quad in 0..builder.positions.len() / 4 {
+    let (voxel, facing, _) = builder.quad_voxels(quad, layout, chunk);
+    let tint = VoxelFace::between(&voxel, &facing).and_then(|face| decals.get(&voxel, face));
+    if let Some(tint) = tint {
+      for color in builder.colors[quad * 4..quad * 4 + 4].iter_mut() {
+        *color = decal::tint_vertex_color(*color, tint);
+      }
+    }
+  }
+  mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, builder.colors);
+}
+
+// every face of a mesh made by `mesh_chunk` as the voxel it belongs to and the voxel it looks at,
+// `None` if the mesh wasn't made by `mesh_chunk`
+#[cfg(all(debug_assertions, feature = "terrain-validate"))]
+pub fn mesh_faces(
+  mesh: &Mesh,
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+) -> Option<Vec<(VoxelId, VoxelId)>> {
+  let builder = MeshBuilder::from_quads(mesh)?;
+  Some(
+    (0..builder.positions.len() / 4)
+      .map(|quad| {
+        let (voxel, facing, _) = builder.quad_voxels(quad, layout, chunk);
+        (voxel, facing)
+      })
+      .collect(),
+  )
+}
+
+// adds the faces of `to_mesh`, `voxels` are all the voxels of the chunk
+fn mesh_voxels<'a>(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  to_mesh: impl Iterator<Item = (&'a VoxelId, &'a VoxelTypeId)>,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+  builder: &mut MeshBuilder,
+) {
+  let origin = layout.chunk_to_space(chunk);
+  let side = layout.voxel_side_length();
+
+  for (voxel, voxel_type) in to_mesh {
+    if !registry.is_solid(*voxel_type) {
+      continue;
+    }
+    let base = layout.voxel_to_space(voxel) - origin;
+
+    for face in CUBE_FACES.iter() {
+      let ((x, y, z), ..) = face;
+      let facing = *voxel + VoxelId::new(*x, *y, *z);
+      let level = match voxels.get(&facing) {
+        // faces between voxels of the same transparent type are hidden too, e.g. inside water
+        Some(neighbor) if registry.is_opaque(*neighbor) || neighbor == voxel_type => continue,
+        Some(_) => light.level(&facing),
+        None => MAX_LIGHT,
+      };
+      push_face(
+        builder,
+        face,
+        base,
+        Vec3::splat(side),
+        brightness_color(level),
+        *voxel_type,
+      );
+    }
+  }
+}
+
+// adds the face of the box from `min` to `min + size`
+fn push_face(
+  builder: &mut MeshBuilder,
+  (_, normal, corners): &CubeFace,
+  min: Vec3,
+  size: Vec3,
+  color: [f32; 4],
+  voxel_type: VoxelTypeId,
+) {
+  let normal = Vec3::from(*normal);
+  let indices: Vec<u32> = corners
+    .iter()
+    .zip(FACE_UVS)
+    .map(|(corner, uv)| {
+      let index = builder.vertex(min + Vec3::from(*corner) * size, normal, uv);
+      builder.colors.push(color);
+      builder.voxel_types.push(voxel_type.0 as u32);
+      index
+    })
+    .collect();
+  builder.triangle(indices[0], indices[1], indices[2]);
+  builder.triangle(indices[0], indices[2], indices[3]);
+}
+
+// same as `mesh_chunk` at lod 0, higher lod levels mesh cells of `2^lod` voxels a side instead of
+// single voxels, a cell is solid if any of its voxels are and takes the type most of them have
+// the coarser surface doesn't line up with neighbors meshed at other levels, so the sides of the
+// chunk are sealed with skirts instead of the faces of its border cells, each border column hangs
+// one from the top of its highest solid cell down to the bottom of the chunk
+#[cfg(feature = "render")]
+pub fn mesh_chunk_lod(
+  layout: &CubicVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  light: &ChunkLight,
+  lod: u8,
+) -> Mesh {
+  if lod == 0 {
+    return mesh_chunk(layout, registry, chunk, voxels, light);
+  }
+
+  let step = 1i64 << lod.min(MAX_LOD_SHIFT);
+  let length = layout.chunk_voxel_length();
+  let (across, height) = (
+    layout.chunk_voxel_full_length(),
+    layout.chunk_voxel_height(),
+  );
+  let (cells_across, cells_high) = ((across + step - 1) / step, (height + step - 1) / step);
+  let lowest = layout.get_voxel(chunk, -length, 0, -length);
+  let within = |x: i64, z: i64| (0..cells_across).contains(&x) && (0..cells_across).contains(&z);
+
+  // the solid voxel types and the brightest light of each cell, cells are counted from the
+  // lowest corner of the chunk
+  let mut types: HashMap<(i64, i64, i64), HashMap<VoxelTypeId, usize>> = HashMap::new();
+  let mut levels: HashMap<(i64, i64, i64), u8> = HashMap::new();
+  for (voxel, voxel_type) in voxels.iter() {
+    let local = *voxel - lowest;
+    if !(0..across).contains(&local.x())
+      || !(0..height).contains(&local.y())
+      || !(0..across).contains(&local.z())
+    {
+      continue;
+    }
+    let cell = (local.x() / step, local.y() / step, local.z() / step);
+    if registry.is_solid(*voxel_type) {
+      *types
+        .entry(cell)
+        .or_default()
+        .entry(*voxel_type)
+        .or_default() += 1;
+    } else {
+      let level = levels.entry(cell).or_default();
+      *level = (*level).max(light.level(voxel));
+    }
+  }
+  let cells: HashMap<_, _> = types
+    .into_iter()
+    .filter_map(|(cell, counts)| {
+      let (voxel_type, _) = counts
+        .into_iter()
+        .max_by_key(|(voxel_type, count)| (*count, std::cmp::Reverse(*voxel_type)))?;
+      Some((cell, voxel_type))
+    })
+    .collect();
+
+  // the corners of a cell relative to the chunk, cells on the far sides of the chunk are cut short
+  let origin = layout.chunk_to_space(chunk);
+  let corner = |x: i64, y: i64, z: i64| {
+    layout.voxel_to_space(&(lowest + VoxelId::new(x.min(across), y.min(height), z.min(across))))
+      - origin
+  };
+  let bounds = |(x, y, z): (i64, i64, i64)| {
+    (
+      corner(x * step, y * step, z * step),
+      corner((x + 1) * step, (y + 1) * step, (z + 1) * step),
+    )
+  };
+
+  let mut builder = MeshBuilder::default();
+  let mut tops: HashMap<(i64, i64), i64> = HashMap::new();
+  for (&(x, y, z), voxel_type) in cells.iter() {
+    let top = tops.entry((x, z)).or_insert(y);
+    *top = (*top).max(y);
+
+    let (min, max) = bounds((x, y, z));
+    for face in CUBE_FACES.iter() {
+      let ((dx, dy, dz), ..) = face;
+      let neighbor = (x + dx, y + dy, z + dz);
+      if !within(neighbor.0, neighbor.2) {
+        continue;
+      }
+      let level = match cells.get(&neighbor) {
+        _ if !(0..cells_high).contains(&neighbor.1) => MAX_LIGHT,
+        Some(other) if registry.is_opaque(*other) || other == voxel_type => continue,
+        _ => levels.get(&neighbor).copied().unwrap_or_default(),
+      };
+      push_face(
+        &mut builder,
+        face,
+        min,
+        max - min,
+        brightness_color(level),
+        *voxel_type,
+      );
+    }
+  }
+
+  let floor = corner(0, 0, 0).y;
+  for (&(x, z), &y) in tops.iter() {
+    let (min, max) = bounds((x, y, z));
+    let min = Vec3::new(min.x, floor, min.z);
+    for face in CUBE_FACES.iter() {
+      let ((dx, dy, dz), ..) = face;
+      if *dy != 0 || within(x + dx, z + dz) {
+        continue;
+      }
+      push_face(
+        &mut builder,
+        face,
+        min,
+        max - min,
+        brightness_color(MAX_LIGHT),
+        cells[&(x, y, z)],
+      );
+    }
+  }
+  builder.build()
+}
+
+fn brightness_color(level: u8) -> [f32; 4] {
+  // each level is a fixed fraction dimmer than the one above it
+  let brightness = 0.8f32.powi((MAX_LIGHT - level.min(MAX_LIGHT)) as i32);
+  let value = MIN_BRIGHTNESS + (1. - MIN_BRIGHTNESS) * brightness;
+  [value, value, value, 1.]
+}
+
+// how `mesh_hex_chunk_with` meshes a chunk of a `CubeHexLayout`
+#[cfg(feature = "render")]
+#[derive(Debug, Clone, Default)]
+pub struct HexMeshOptions {
+  // heights of the columns of neighboring chunks along the border, see `hex_column_heights`
+  // walls towards neighbors at least as tall are culled like the walls within the chunk, columns
+  // that aren't in here are treated as empty so borders towards unloaded chunks stay closed
+  pub neighbor_heights: HashMap<VoxelId, i64>,
+  // only the top caps without any walls, e.g. for far chunks of a large hex map where the steps
+  // between columns are too small to see
+  pub caps_only: bool,
+}
+
+// builds a mesh of extruded hex columns for a chunk of a `CubeHexLayout`
+// each column is as tall as its highest solid voxel, walls are only emitted where a column is taller
+// than its neighbor so walls shared by columns of the same height are culled
+// columns in other chunks are treated as empty so chunk borders are always closed
+#[cfg(feature = "render")]
+pub fn generate_hex_mesh(
+  layout: &CubeHexLayout,
+  registry: &VoxelRegistry,
+  chunk: ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  normal_mode: NormalMode,
+) -> ChunkJob<Mesh> {
+  generate_hex_mesh_with(
+    layout,
+    registry,
+    chunk,
+    voxels,
+    HexMeshOptions::default(),
+    normal_mode,
+  )
+}
+
+// same as `generate_hex_mesh` with walls culled against loaded neighbors or left out, see
+// `HexMeshOptions`
+#[cfg(feature = "render")]
+pub fn generate_hex_mesh_with(
+  layout: &CubeHexLayout,
+  registry: &VoxelRegistry,
+  chunk: ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  options: HexMeshOptions,
+  normal_mode: NormalMode,
+) -> ChunkJob<Mesh> {
+  let layout = layout.clone();
+  let registry = registry.clone();
+  let voxels = voxels.clone();
+  Box::new(move || {
+    let mut mesh = mesh_hex_chunk_with(&layout, &registry, &chunk, &voxels, &options);
+    apply_normal_mode(&mut mesh, normal_mode);
+    mesh
+  })
+}
+
+// columns in other chunks are treated as empty so chunk borders are always closed
+#[cfg(feature = "render")]
+pub fn mesh_hex_chunk(
+  layout: &CubeHexLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+) -> Mesh {
+  mesh_hex_chunk_with(layout, registry, chunk, voxels, &HexMeshOptions::default())
+}
+
+#[cfg(feature = "render")]
+pub fn mesh_hex_chunk_with(
+  layout: &CubeHexLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  options: &HexMeshOptions,
+) -> Mesh {
+  let heights = hex_column_heights(registry, voxels);
+  let origin = layout.chunk_to_space(chunk);
+  let mut builder = MeshBuilder::default();
+
+  for (column, height) in heights.iter() {
+    if *height == 0 {
+      continue;
+    }
+    let center = layout.voxel_to_space(column) - origin;
+    let top = *height as f32 * layout.voxel_height();
+    // the whole column is drawn as its highest voxel
+    let voxel_type = voxels[&VoxelId::new(column.x(), height - 1, column.z())];
+    let corners: Vec<Vec3> = (0..6).map(|i| center + layout.hex_corner(i)).collect();
+
+    // top cap
+    let cap_center = builder.typed_vertex(center + Vec3::Y * top, Vec3::Y, [0.5, 0.5], voxel_type);
+    let cap_corners: Vec<u32> = corners
+      .iter()
+      .map(|corner| {
+        let offset = (*corner - center) / (2. * layout.hex_size());
+        builder.typed_vertex(
+          *corner + Vec3::Y * top,
+          Vec3::Y,
+          [0.5 + offset.x, 0.5 + offset.z],
+          voxel_type,
+        )
+      })
+      .collect();
+    for i in 0..6 {
+      builder.triangle(cap_center, cap_corners[(i + 1) % 6], cap_corners[i]);
+    }
+    if options.caps_only {
+      continue;
+    }
+
+    // walls facing lower neighbors
+    for (side, corner) in corners.iter().enumerate() {
+      let neighbor = CubeHexLayout::hex_neighbor(column, side);
+      let neighbor_height = heights
+        .get(&neighbor)
+        .or_else(|| options.neighbor_heights.get(&neighbor))
+        .copied()
+        .unwrap_or(0);
+      if neighbor_height >= *height {
+        continue;
+      }
+      let bottom = neighbor_height as f32 * layout.voxel_height();
+      let next = corners[(side + 1) % 6];
+      let normal = (layout.hex_corner(side) + layout.hex_corner((side + 1) % 6)).normalize();
+      let (v0, v1) = (bottom / layout.voxel_height(), top / layout.voxel_height());
+
+      let a = builder.typed_vertex(*corner + Vec3::Y * bottom, normal, [0., v0], voxel_type);
+      let b = builder.typed_vertex(next + Vec3::Y * bottom, normal, [1., v0], voxel_type);
+      let c = builder.typed_vertex(next + Vec3::Y * top, normal, [1., v1], voxel_type);
+      let d = builder.typed_vertex(*corner + Vec3::Y * top, normal, [0., v1], voxel_type);
+      builder.triangle(a, c, b);
+      builder.triangle(a, d, c);
+    }
+  }
+
+  builder.build()
+}
+
+// neighbor offsets of a voxel of a `SphereVoxelLayout` and the corners of the face towards them,
+// see `SphereVoxelLayout::voxel_corners`
+#[cfg(feature = "render")]
+const SPHERE_FACES: [((i64, i64, i64), [usize; 4]); 6] = [
+  ((0, 1, 0), [4, 5, 6, 7]),
+  ((0, -1, 0), [0, 3, 2, 1]),
+  ((1, 0, 0), [1, 2, 6, 5]),
+  ((-1, 0, 0), [3, 0, 4, 7]),
+  ((0, 0, 1), [2, 3, 7, 6]),
+  ((0, 0, -1), [0, 1, 5, 4]),
+];
+
+// builds a mesh of the voxel faces of a chunk of a `SphereVoxelLayout`, relative to the chunk's
+// `chunk_to_space`
+// faces between opaque voxels are culled, voxels in other chunks are treated as empty so chunk
+// borders are always closed, see `generate_sphere_chunk_mesh` for meshes of loaded neighbors
+#[cfg(feature = "render")]
+pub fn generate_sphere_mesh(
+  layout: &SphereVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  normal_mode: NormalMode,
+) -> ChunkJob<Mesh> {
+  let layout = layout.clone();
+  let registry = registry.clone();
+  let voxels = voxels.clone();
+  Box::new(move || {
+    let mut mesh = mesh_sphere_chunk(&layout, &registry, &chunk, &voxels);
+    apply_normal_mode(&mut mesh, normal_mode);
+    mesh
+  })
+}
+
+// the mesh of a chunk next to the border voxels of its loaded neighbors, in the frame of an entity
+// at `chunk_to_space` rotated by `chunk_rotation`
+#[cfg(feature = "render")]
+pub fn generate_sphere_chunk_mesh(
+  layout: &SphereVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  neighbors: HashMap<VoxelId, VoxelTypeId>,
+  normal_mode: NormalMode,
+) -> ChunkJob<Mesh> {
+  let layout = layout.clone();
+  let registry = registry.clone();
+  let voxels = voxels.clone();
+  Box::new(move || {
+    let mut mesh = mesh_sphere_chunk_with(&layout, &registry, &chunk, &voxels, &neighbors);
+    let rotation = layout.chunk_rotation(&chunk).inverse();
+    for attribute in [Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_NORMAL] {
+      if let Some(VertexAttributeValues::Float32x3(values)) = mesh.attribute_mut(attribute) {
+        for value in values.iter_mut() {
+          *value = (rotation * Vec3::from(*value)).into();
+        }
+      }
+    }
+    apply_normal_mode(&mut mesh, normal_mode);
+    mesh
+  })
+}
+
+#[cfg(feature = "render")]
+pub fn mesh_sphere_chunk(
+  layout: &SphereVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+) -> Mesh {
+  mesh_sphere_chunk_with(layout, registry, chunk, voxels, &HashMap::new())
+}
+
+// like `mesh_sphere_chunk`, faces towards the voxels of other chunks in `neighbors` are culled like
+// the faces inside the chunk, see `sphere_border_voxels`
+#[cfg(feature = "render")]
+pub fn mesh_sphere_chunk_with(
+  layout: &SphereVoxelLayout,
+  registry: &VoxelRegistry,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  neighbors: &HashMap<VoxelId, VoxelTypeId>,
+) -> Mesh {
+  let origin = layout.chunk_to_space(chunk);
+  let mut builder = MeshBuilder::default();
+
+  for (voxel, voxel_type) in voxels.iter() {
+    if !registry.is_solid(*voxel_type) {
+      continue;
+    }
+    let corners = layout.voxel_corners(voxel).map(|corner| corner - origin);
+    let center = layout.voxel_center(voxel) - origin;
+    for (offset, face) in SPHERE_FACES {
+      let neighbor = sphere_neighbor(layout, voxel, offset)
+        .and_then(|neighbor| voxels.get(&neighbor).or_else(|| neighbors.get(&neighbor)));
+      if matches!(neighbor, Some(neighbor) if registry.is_opaque(*neighbor)) {
+        continue;
+      }
+      // the sides of a column lean with the sphere, wind every face so it looks away from the
+      // middle of its voxel
+      let mut quad = face.map(|i| corners[i]);
+      let mut normal = (quad[1] - quad[0])
+        .cross(quad[2] - quad[0])
+        .normalize_or_zero();
+      let middle = quad.iter().sum::<Vec3>() * 0.25;
+      if normal.dot(middle - center) < 0. {
+        quad.reverse();
+        normal = -normal;
+      }
+      let uvs = [[0., 0.], [1., 0.], [1., 1.], [0., 1.]];
+      let indices: Vec<u32> = quad
+        .iter()
+        .zip(uvs)
+        .map(|(corner, uv)| builder.typed_vertex(*corner, normal, uv, *voxel_type))
+        .collect();
+      builder.triangle(indices[0], indices[1], indices[2]);
+      builder.triangle(indices[0], indices[2], indices[3]);
+    }
+  }
+
+  builder.build()
+}
+
+// the voxel next to `voxel` towards `offset` of `SPHERE_FACES`, columns next to each other on the
+// sphere can be on different faces of the cube
+#[cfg(feature = "render")]
+fn sphere_neighbor(
+  layout: &SphereVoxelLayout,
+  voxel: &VoxelId,
+  (x, y, z): (i64, i64, i64),
+) -> Option<VoxelId> {
+  match y {
+    0 => layout.offset_voxel(voxel, x, z),
+    _ => Some(*voxel + VoxelId::new(0, y, 0)),
+  }
+}
+
+// the voxels of other chunks next to the voxels of a sphere chunk, for `mesh_sphere_chunk_with`
+// `get` looks up voxels of loaded chunks, voxels of chunks that aren't loaded are left out
+#[cfg(feature = "render")]
+pub fn sphere_border_voxels(
+  layout: &SphereVoxelLayout,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  get: impl Fn(&VoxelId) -> Option<VoxelTypeId>,
+) -> HashMap<VoxelId, VoxelTypeId> {
+  voxels
+    .keys()
+    .flat_map(|voxel| {
+      SPHERE_FACES
+        .iter()
+        .filter_map(|(offset, _)| sphere_neighbor(layout, voxel, *offset))
+    })
+    .filter(|neighbor| !voxels.contains_key(neighbor))
+    .filter_map(|neighbor| Some((neighbor, get(&neighbor)?)))
+    .collect()
+}
+
+// a quad facing +z for the highest voxel of each column that has a tile, laid out in 2D with
+// terrain x along x and terrain z down y, see `TileTerrainPlugin`
+// `tiles` are the uv rects (min and max) in the atlas of the voxel types that are drawn
+#[cfg(feature = "render")]
+pub fn mesh_tile_chunk(
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  tiles: &HashMap<VoxelTypeId, (Vec2, Vec2)>,
+) -> Mesh {
+  let mut columns: HashMap<(i64, i64), VoxelId> = HashMap::new();
+  for (voxel, voxel_type) in voxels.iter() {
+    if !tiles.contains_key(voxel_type) {
+      continue;
+    }
+    let top = columns.entry((voxel.x(), voxel.z())).or_insert(*voxel);
+    if voxel.y() > top.y() {
+      *top = *voxel;
+    }
+  }
+
+  let origin = layout.chunk_to_space(chunk);
+  let side = layout.voxel_side_length();
+  let mut builder = MeshBuilder::default();
+  for voxel in columns.values() {
+    let (min, max) = tiles[&voxels[voxel]];
+    let offset = layout.voxel_to_space(voxel) - origin;
+    let (left, top) = (offset.x, -offset.z);
+    let corners = [
+      (Vec3::new(left, top - side, 0.), [min.x, max.y]),
+      (Vec3::new(left + side, top - side, 0.), [max.x, max.y]),
+      (Vec3::new(left + side, top, 0.), [max.x, min.y]),
+      (Vec3::new(left, top, 0.), [min.x, min.y]),
+    ];
+    let indices = corners.map(|(corner, uv)| builder.vertex(corner, Vec3::Z, uv));
+    builder.triangle(indices[0], indices[1], indices[2]);
+    builder.triangle(indices[0], indices[2], indices[3]);
+  }
+
+  builder.build()
+}
+
+// height of each hex column in voxels, i.e. one above its highest solid voxel, keyed by the
+// column's voxel at y 0
+pub fn hex_column_heights(
+  registry: &VoxelRegistry,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+) -> HashMap<VoxelId, i64> {
+  let mut heights = HashMap::new();
+  for (voxel, voxel_type) in voxels.iter() {
+    let height = heights
+      .entry(VoxelId::new(voxel.x(), 0, voxel.z()))
+      .or_insert(0);
+    if registry.is_solid(*voxel_type) {
+      *height = (voxel.y() + 1).max(*height);
+    }
+  }
+  heights
+}
+
+#[derive(Default)]
+struct MeshBuilder {
+  positions: Vec<[f32; 3]>,
+  normals: Vec<[f32; 3]>,
+  uvs: Vec<[f32; 2]>,
+  // optional, either empty or one per vertex
+  colors: Vec<[f32; 4]>,
+  // optional, either empty or one per vertex
+  voxel_types: Vec<u32>,
+  indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+  fn vertex(&mut self, position: Vec3, normal: Vec3, uv: [f32; 2]) -> u32 {
+    self.positions.push(position.to_array());
+    self.normals.push(normal.to_array());
+    self.uvs.push(uv);
+    self.positions.len() as u32 - 1
+  }
+
+  // a vertex that tells the material which voxel type it belongs to, a builder's vertices should
+  // either all have voxel types or none
+  #[cfg(feature = "render")]
+  fn typed_vertex(
+    &mut self,
+    position: Vec3,
+    normal: Vec3,
+    uv: [f32; 2],
+    voxel_type: VoxelTypeId,
+  ) -> u32 {
+    self.voxel_types.push(voxel_type.0 as u32);
+    self.vertex(position, normal, uv)
+  }
+
+  // appends the geometry of another builder, both should either have colors and voxel types or
+  // not
+  #[cfg(feature = "render")]
+  fn append(&mut self, other: MeshBuilder) {
+    let offset = self.positions.len() as u32;
+    self.positions.extend(other.positions);
+    self.normals.extend(other.normals);
+    self.uvs.extend(other.uvs);
+    self.colors.extend(other.colors);
+    self.voxel_types.extend(other.voxel_types);
+    self
+      .indices
+      .extend(other.indices.into_iter().map(|i| i + offset));
+  }
+
+  // reads back a mesh made of quads that each have 4 vertices and 2 triangles in order, with
+  // colors and voxel types, as built by `mesh_voxels`
+  #[cfg(feature = "render")]
+  fn from_quads(mesh: &Mesh) -> Option<Self> {
+    let builder = Self {
+      positions: match mesh.attribute(Mesh::ATTRIBUTE_POSITION)? {
+        VertexAttributeValues::Float32x3(values) => values.clone(),
+        _ => return None,
+      },
+      normals: match mesh.attribute(Mesh::ATTRIBUTE_NORMAL)? {
+        VertexAttributeValues::Float32x3(values) => values.clone(),
+        _ => return None,
+      },
+      uvs: match mesh.attribute(Mesh::ATTRIBUTE_UV_0)? {
+        VertexAttributeValues::Float32x2(values) => values.clone(),
+        _ => return None,
+      },
+      colors: match mesh.attribute(Mesh::ATTRIBUTE_COLOR)? {
+        VertexAttributeValues::Float32x4(values) => values.clone(),
+        _ => return None,
+      },
+      voxel_types: match mesh.attribute(ATTRIBUTE_VOXEL_TYPE)? {
+        VertexAttributeValues::Uint32(values) => values.clone(),
+        _ => return None,
+      },
+      indices: match mesh.indices()? {
+        Indices::U32(indices) => indices.clone(),
+        _ => return None,
+      },
+    };
+
+    let quads = builder.positions.len() / 4;
+    let is_quads = quads * 4 == builder.positions.len()
+      && builder.indices.len() == quads * 6
+      && builder
+        .indices
+        .chunks_exact(6)
+        .enumerate()
+        .all(|(quad, indices)| indices.iter().all(|i| *i as usize / 4 == quad));
+    if !is_quads {
+      return None;
+    }
+    Some(builder)
+  }
+
+  // like `from_quads` but the attributes are moved out of the mesh instead of copied, they're
+  // moved back with `put_back`, the mesh is left untouched if it isn't made of quads
+  #[cfg(feature = "render")]
+  fn take_quads(mesh: &mut Mesh) -> Option<Self> {
+    let is_quads = matches!(
+      (
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+        mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+        mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+        mesh.attribute(Mesh::ATTRIBUTE_COLOR),
+        mesh.attribute(ATTRIBUTE_VOXEL_TYPE),
+        mesh.indices(),
+      ),
+      (
+        Some(VertexAttributeValues::Float32x3(positions)),
+        Some(VertexAttributeValues::Float32x3(_)),
+        Some(VertexAttributeValues::Float32x2(_)),
+        Some(VertexAttributeValues::Float32x4(_)),
+        Some(VertexAttributeValues::Uint32(_)),
+        Some(Indices::U32(indices)),
+      ) if positions.len() % 4 == 0 && indices.len() == positions.len() / 4 * 6
+    );
+    if !is_quads {
+      return None;
+    }
+    let mut builder = Self::default();
+    if let Some(VertexAttributeValues::Float32x3(values)) =
+      mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    {
+      builder.positions = std::mem::take(values);
+    }
+    if let Some(VertexAttributeValues::Float32x3(values)) =
+      mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL)
+    {
+      builder.normals = std::mem::take(values);
+    }
+    if let Some(VertexAttributeValues::Float32x2(values)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+    {
+      builder.uvs = std::mem::take(values);
+    }
+    if let Some(VertexAttributeValues::Float32x4(values)) =
+      mesh.attribute_mut(Mesh::ATTRIBUTE_COLOR)
+    {
+      builder.colors = std::mem::take(values);
+    }
+    if let Some(VertexAttributeValues::Uint32(values)) = mesh.attribute_mut(ATTRIBUTE_VOXEL_TYPE) {
+      builder.voxel_types = std::mem::take(values);
+    }
+    if let Some(Indices::U32(indices)) = mesh.indices_mut() {
+      builder.indices = std::mem::take(indices);
+    }
+    Some(builder)
+  }
+
+  #[cfg(feature = "render")]
+  fn put_back(self, mesh: &mut Mesh) {
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, self.positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, self.colors);
+    mesh.insert_attribute(ATTRIBUTE_VOXEL_TYPE, self.voxel_types);
+    mesh.set_indices(Some(Indices::U32(self.indices)));
+  }
+
+  // the last quad takes the place of `quad`, quads of `from_quads` have their own 4 vertices
+  #[cfg(feature = "render")]
+  fn swap_remove_quad(&mut self, quad: usize) {
+    let last = self.positions.len() / 4 - 1;
+    swap_remove_vertices(&mut self.positions, quad);
+    swap_remove_vertices(&mut self.normals, quad);
+    swap_remove_vertices(&mut self.uvs, quad);
+    swap_remove_vertices(&mut self.colors, quad);
+    swap_remove_vertices(&mut self.voxel_types, quad);
+    if quad != last {
+      for i in 0..6 {
+        self.indices[quad * 6 + i] = self.indices[last * 6 + i] - last as u32 * 4 + quad as u32 * 4;
+      }
+    }
+    self.indices.truncate(last * 6);
+  }
+
+  // the voxel a quad of `from_quads` is a face of, the voxel the face looks at and the face's
+  // own normal, the stored normals may be smoothed
+  #[cfg(feature = "render")]
+  fn quad_voxels(
+    &self,
+    quad: usize,
+    layout: &CubicVoxelLayout,
+    chunk: &ChunkId,
+  ) -> (VoxelId, VoxelId, Vec3) {
+    let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(self.positions[quad * 4 + i]));
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+    let center = self.positions[quad * 4..quad * 4 + 4]
+      .iter()
+      .fold(Vec3::ZERO, |sum, corner| sum + Vec3::from(*corner))
+      / 4.;
+    let origin = layout.chunk_to_space(chunk);
+    let voxel =
+      layout.space_to_voxel(&(origin + center - normal * layout.voxel_side_length() * 0.5));
+    let facing = voxel
+      + VoxelId::new(
+        normal.x.round() as i64,
+        normal.y.round() as i64,
+        normal.z.round() as i64,
+      );
+    (voxel, facing, normal)
+  }
+
+  // vertices are expected in counter-clockwise order when viewed from the front
+  fn triangle(&mut self, a: u32, b: u32, c: u32) {
+    self.indices.extend([a, b, c]);
+  }
+
+  #[cfg(feature = "render")]
+  fn build(self) -> Mesh {
+    self.into_data().into()
+  }
+
+  fn into_data(self) -> MeshData {
+    MeshData {
+      positions: self.positions,
+      normals: self.normals,
+      uvs: self.uvs,
+      colors: self.colors,
+      voxel_types: self.voxel_types,
+      indices: self.indices,
+    }
+  }
+}
+
+#[cfg(all(test, feature = "render"))]
+mod tests {
+  use super::*;
+  use crate::voxel::{registry::VoxelTypeInfo, seed::ChunkRng};
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn flat_hex_chunk_should_only_have_border_walls(radius in 0i64..6, height in 1i64..8) {
+          let layout = CubeHexLayout::new(1.0, 1.0, radius, 8);
+          let chunk = ChunkId::new(2, 0, -3);
+          let voxels = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if voxel.y() < height { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+
+          let mesh = mesh_hex_chunk(&layout, &VoxelRegistry::default(), &chunk, &voxels);
+          let border_walls = 6 * (2 * radius + 1);
+          let expected = 7 * layout.hexes_per_chunk() + 4 * border_walls;
+          assert_eq!(mesh.count_vertices() as i64, expected);
+      }
+
+      #[test]
+      fn hex_walls_should_be_culled_by_neighbors_and_caps_only(radius in 0i64..6, height in 1i64..8, step in 0i64..3) {
+          let layout = CubeHexLayout::new(1.0, 1.0, radius, 8);
+          let chunk = ChunkId::new(-1, 0, 2);
+          let voxels = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if voxel.y() < height { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let registry = VoxelRegistry::default();
+          let heights = hex_column_heights(&registry, &voxels);
+          // the columns around the chunk are `step` lower than it
+          let neighbor_heights = heights
+              .keys()
+              .flat_map(|column| (0..6).map(move |side| CubeHexLayout::hex_neighbor(column, side)))
+              .filter(|neighbor| !heights.contains_key(neighbor))
+              .map(|neighbor| (neighbor, height - step))
+              .collect();
+
+          let options = HexMeshOptions { neighbor_heights, caps_only: false };
+          let mesh = mesh_hex_chunk_with(&layout, &registry, &chunk, &voxels, &options);
+          let border_walls = if step > 0 { 6 * (2 * radius + 1) } else { 0 };
+          prop_assert_eq!(mesh.count_vertices() as i64, 7 * layout.hexes_per_chunk() + 4 * border_walls);
+
+          let options = HexMeshOptions { caps_only: true, ..Default::default() };
+          let mesh = mesh_hex_chunk_with(&layout, &registry, &chunk, &voxels, &options);
+          prop_assert_eq!(mesh.count_vertices() as i64, 7 * layout.hexes_per_chunk());
+      }
+
+      #[test]
+      fn hex_columns_should_be_drawn_as_their_top_voxel_type(radius in 0i64..6, x in -8i64..8, z in -8i64..8) {
+          let layout = CubeHexLayout::new(1.0, 1.0, radius, 8);
+          let chunk = ChunkId::new(x, 0, z);
+          let top = |voxel: &VoxelId| if (voxel.x() + voxel.z()).rem_euclid(2) == 0 { VoxelTypeId::DIRT } else { VoxelTypeId::ROAD };
+          let height = |voxel: &VoxelId| 1 + voxel.x().rem_euclid(3);
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| {
+                  let voxel_type = match voxel.y().cmp(&(height(&voxel) - 1)) {
+                      std::cmp::Ordering::Less => VoxelTypeId::LAMP,
+                      std::cmp::Ordering::Equal => top(&voxel),
+                      std::cmp::Ordering::Greater => VoxelTypeId::AIR,
+                  };
+                  (voxel, voxel_type)
+              })
+              .collect();
+
+          let registry = VoxelRegistry::default();
+          let options = HexMeshOptions { caps_only: true, ..Default::default() };
+          let mesh = mesh_hex_chunk_with(&layout, &registry, &chunk, &voxels, &options);
+          let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+              Some(VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+              _ => unreachable!(),
+          };
+          let voxel_types = match mesh.attribute(ATTRIBUTE_VOXEL_TYPE) {
+              Some(VertexAttributeValues::Uint32(voxel_types)) => voxel_types.clone(),
+              _ => unreachable!(),
+          };
+          prop_assert_eq!(voxel_types.len(), positions.len());
+          // every cap is its center and six corners
+          let origin = layout.chunk_to_space(&chunk);
+          for (cap, types) in positions.chunks(7).zip(voxel_types.chunks(7)) {
+              let center = origin + Vec3::from(cap[0]) * Vec3::new(1., 0., 1.);
+              let column = layout.space_to_voxel(&center);
+              prop_assert!(types.iter().all(|voxel_type| *voxel_type == top(&column).0 as u32));
+          }
+      }
+
+      #[test]
+      fn sphere_chunk_should_only_mesh_outer_faces(face in 0usize..6, u in 0i64..4, v in 0i64..4, height in 1i64..4) {
+          let layout = SphereVoxelLayout::new(32., 1., 4, 3, 4);
+          let chunk = layout.chunk_id(crate::voxel::sphere::CubeFace::ALL[face], u, v, 0);
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if voxel.y() < height { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+
+          let mesh = mesh_sphere_chunk(&layout, &VoxelRegistry::default(), &chunk, &voxels);
+          // a solid slab: its top, bottom and the four sides along the chunk border
+          let (size, height) = (layout.chunk_voxels(), height);
+          let expected = 4 * (2 * size * size + 4 * size * height);
+          prop_assert_eq!(mesh.count_vertices() as i64, expected);
+          // triangles are wound counter-clockwise around their normals
+          let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+              Some(VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+              _ => unreachable!(),
+          };
+          let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+              Some(VertexAttributeValues::Float32x3(normals)) => normals.clone(),
+              _ => unreachable!(),
+          };
+          let indices: Vec<usize> = mesh.indices().unwrap().iter().collect();
+          for triangle in indices.chunks(3) {
+              let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| Vec3::from(positions[i]));
+              prop_assert!((b - a).cross(c - a).dot(Vec3::from(normals[triangle[0]])) > 0.);
+          }
+      }
+
+      #[test]
+      fn sphere_chunks_should_not_wall_off_their_neighbors(face in 0usize..6, u in 0i64..4, v in 0i64..4, height in 1i64..4) {
+          let layout = SphereVoxelLayout::new(32., 1., 4, 3, 4);
+          let slab = |chunk: &ChunkId| -> HashMap<VoxelId, VoxelTypeId> {
+              layout
+                  .get_chunk_voxels(chunk)
+                  .into_iter()
+                  .map(|voxel| (voxel, if voxel.y() < height { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+                  .collect()
+          };
+          let chunk = layout.chunk_id(crate::voxel::sphere::CubeFace::ALL[face], u, v, 0);
+          let voxels = slab(&chunk);
+          let loaded: HashMap<_, _> = layout
+              .get_chunk_neighbors(&chunk, 1)
+              .iter()
+              .flat_map(|neighbor| slab(neighbor))
+              .collect();
+          let neighbors = sphere_border_voxels(&layout, &voxels, |voxel| loaded.get(voxel).copied());
+
+          // the slab goes on in every direction, across the edges of the cube too, only its top and
+          // bottom are left
+          let mesh = mesh_sphere_chunk_with(&layout, &VoxelRegistry::default(), &chunk, &voxels, &neighbors);
+          let size = layout.chunk_voxels();
+          prop_assert_eq!(mesh.count_vertices() as i64, 4 * 2 * size * size);
+      }
+
+      #[test]
+      fn slab_mesh_should_match_single_mesh(seed in any::<u64>(), height in 1i64..12, slabs in 1usize..8) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 3, height);
+          let chunk = ChunkId::new(1, 0, -1);
+          let mut rng = ChunkRng::new(seed, &chunk, "mesher test");
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if rng.chance(0.5) { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let registry = VoxelRegistry::default();
+          let light = ChunkLight::compute(&voxels, &registry);
+
+          let single = mesh_chunk(&layout, &registry, &chunk, &voxels, &light);
+          let slabs = mesh_chunk_slabs(&TaskPool::new(), &layout, &registry, &chunk, &voxels, &light, slabs);
+          assert_eq!(slabs.count_vertices(), single.count_vertices());
+          assert_eq!(slabs.indices().map(|i| i.len()), single.indices().map(|i| i.len()));
+
+          // meshing a slab per step too
+          let stepped = generate_mesh_steps(&layout, &registry, chunk, &voxels, &light, 0, None, NormalMode::default(), GenerateTangents(false)).finish();
+          assert_eq!(stepped.count_vertices(), single.count_vertices());
+          assert_eq!(stepped.indices().map(|i| i.len()), single.indices().map(|i| i.len()));
+      }
+
+      #[test]
+      fn lod_meshes_should_seal_their_sides_with_skirts(voxel_length in 1i64..6, ground in 1i64..=8, lod in 1u8..4) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, voxel_length, 8);
+          let chunk = ChunkId::new(-1, 0, 2);
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if voxel.y() < ground { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let registry = VoxelRegistry::default();
+          let light = ChunkLight::compute(&voxels, &registry);
+          let mesh = MeshBuilder::from_quads(&mesh_chunk_lod(&layout, &registry, &chunk, &voxels, &light, lod))
+              .expect("mesh is made of quads");
+
+          // a top and a bottom face per column of cells and a skirt per column on the sides
+          let step = 1i64 << lod;
+          let columns = (layout.chunk_voxel_full_length() + step - 1) / step;
+          prop_assert_eq!(mesh.positions.len() as i64, 4 * (2 * columns * columns + 4 * columns));
+          // the ground is rounded up to the top of its cells and skirts reach the bottom of the chunk
+          let top = (((ground - 1) / step + 1) * step).min(8) as f32;
+          prop_assert!(mesh.positions.iter().all(|position| position[1] == 0. || position[1] == top));
+          let skirts = mesh.normals.iter().filter(|normal| normal[1] == 0.).count() as i64;
+          prop_assert_eq!(skirts, 4 * 4 * columns);
+      }
+
+      #[test]
+      fn patched_mesh_should_match_full_remesh(seed in any::<u64>(), height in 1i64..8, edits in prop::collection::vec((0i64..7, 0i64..8, 0i64..7, 0u8..4), 1..6)) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 3, height);
+          let chunk = ChunkId::new(0, 0, 0);
+          let mut rng = ChunkRng::new(seed, &chunk, "mesher test");
+          let mut voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if rng.chance(0.5) { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let mut registry = VoxelRegistry::default();
+          let glass = registry.register(VoxelTypeInfo {
+              transparent: true,
+              ..VoxelTypeInfo::solid("glass", Color::WHITE)
+          });
+          let mut light = ChunkLight::compute(&voxels, &registry);
+          let mut mesh = mesh_chunk(&layout, &registry, &chunk, &voxels, &light);
+          let mut index = MeshFaceIndex::of(&mesh, &layout, &chunk).expect("chunk meshes are made of quads");
+
+          let mut changed = HashSet::new();
+          for (x, y, z, id) in edits {
+              let voxel = layout.get_voxel(&chunk, x - 3, y.min(height - 1), z - 3);
+              let voxel_type = [VoxelTypeId::AIR, VoxelTypeId::DIRT, VoxelTypeId::LAMP, glass][id as usize];
+              voxels.insert(voxel, voxel_type);
+              changed.insert(voxel);
+          }
+          light.update(ChunkLight::compute(&voxels, &registry));
+          assert!(patch_mesh(&mut mesh, &mut index, &layout, &registry, &chunk, &voxels, &light, &changed, None, NormalMode::Flat, GenerateTangents(false)));
+
+          let full = mesh_chunk(&layout, &registry, &chunk, &voxels, &light);
+          assert_eq!(quads(&mesh), quads(&full));
+          // the spliced index still describes the patched mesh
+          let rebuilt = MeshFaceIndex::of(&mesh, &layout, &chunk).expect("patched meshes are made of quads");
+          assert_eq!(index.quads, rebuilt.quads);
+      }
+
+      #[test]
+      fn smoothed_normals_should_be_shared_by_vertices_at_the_same_position(seed in any::<u64>(), area_weighted in any::<bool>()) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 4);
+          let chunk = ChunkId::new(0, 0, 0);
+          let mut rng = ChunkRng::new(seed, &chunk, "mesher test");
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if rng.chance(0.5) { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let registry = VoxelRegistry::default();
+          let mut mesh = mesh_chunk(&layout, &registry, &chunk, &voxels, &ChunkLight::compute(&voxels, &registry));
+          let flat = MeshBuilder::from_quads(&mesh).expect("mesh is made of quads");
+          let mode = if area_weighted { NormalMode::AreaWeighted } else { NormalMode::Smooth };
+          apply_normal_mode(&mut mesh, mode);
+          let smooth = MeshBuilder::from_quads(&mesh).expect("mesh is made of quads");
+
+          let mut by_position: HashMap<[i64; 3], [f32; 3]> = HashMap::new();
+          for (position, normal) in smooth.positions.iter().zip(smooth.normals.iter()) {
+              let shared = by_position.entry(position.map(|v| (v * 1000.).round() as i64)).or_insert(*normal);
+              prop_assert!((Vec3::from(*shared) - Vec3::from(*normal)).length() < 1e-5);
+          }
+          // flat normals are recovered from the corners when the mesh is patched
+          prop_assert_eq!(calculate_normals(&flat.positions, &flat.indices, NormalMode::Flat), flat.normals);
+      }
+
+      #[test]
+      fn tangents_should_follow_the_face_uvs(seed in any::<u64>()) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 4);
+          let chunk = ChunkId::new(0, 0, 0);
+          let mut rng = ChunkRng::new(seed, &chunk, "mesher test");
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if rng.chance(0.5) { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let registry = VoxelRegistry::default();
+          let mesh = MeshBuilder::from_quads(&mesh_chunk(&layout, &registry, &chunk, &voxels, &ChunkLight::compute(&voxels, &registry)))
+              .expect("mesh is made of quads");
+          let tangents = calculate_tangents(&mesh.positions, &mesh.normals, &mesh.uvs, &mesh.indices);
+
+          for (index, tangent) in tangents.iter().enumerate() {
+              let (tangent, handedness) = (Vec3::new(tangent[0], tangent[1], tangent[2]), tangent[3]);
+              let normal = Vec3::from(mesh.normals[index]);
+              prop_assert!((tangent.length() - 1.).abs() < 1e-5);
+              prop_assert!(tangent.dot(normal).abs() < 1e-5);
+              prop_assert!(handedness == 1. || handedness == -1.);
+
+              // u grows along the tangent and v along the bitangent
+              let quad = index / 4 * 4;
+              let bitangent = normal.cross(tangent) * handedness;
+              for other in quad..quad + 4 {
+                  let offset = Vec3::from(mesh.positions[other]) - Vec3::from(mesh.positions[index]);
+                  let uv = Vec2::from(mesh.uvs[other]) - Vec2::from(mesh.uvs[index]);
+                  prop_assert!((offset.dot(tangent) - uv.x).abs() < 1e-5);
+                  prop_assert!((offset.dot(bitangent) - uv.y).abs() < 1e-5);
+              }
+          }
+      }
+
+      #[test]
+      fn decals_should_only_tint_their_faces(x in -1i64..=1, z in -1i64..=1, face in 0usize..6, tint in 0f32..1., strength in 0f32..=1.) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 3);
+          let chunk = ChunkId::new(0, 0, 0);
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if voxel.y() == 1 { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let registry = VoxelRegistry::default();
+          let light = ChunkLight::compute(&voxels, &registry);
+          let plain = mesh_chunk(&layout, &registry, &chunk, &voxels, &light);
+
+          let voxel = layout.get_voxel(&chunk, x, 1, z);
+          let face = VoxelFace::ALL[face];
+          let mut decals = ChunkDecals::default();
+          decals.paint(voxel, face, Color::rgba(tint, tint, tint, strength));
+          let mut decaled = plain.clone();
+          apply_decals(&mut decaled, &layout, &chunk, &decals);
+
+          let (plain, decaled) = (MeshBuilder::from_quads(&plain).unwrap(), MeshBuilder::from_quads(&decaled).unwrap());
+          prop_assert_eq!(&plain.positions, &decaled.positions);
+          for quad in 0..plain.positions.len() / 4 {
+              let (quad_voxel, facing, _) = plain.quad_voxels(quad, &layout, &chunk);
+              let expected = match quad_voxel == voxel && facing == voxel + face.offset() {
+                  true => decal::tint_vertex_color(plain.colors[quad * 4], Color::rgba(tint, tint, tint, strength)),
+                  false => plain.colors[quad * 4],
+              };
+              prop_assert_eq!(decaled.colors[quad * 4], expected);
+          }
+      }
+  }
+
+  // every quad of a mesh as its corners, color and voxel type, in a stable order
+  fn quads(mesh: &Mesh) -> Vec<(Vec<[i64; 3]>, [i64; 4], u32)> {
+    let builder = MeshBuilder::from_quads(mesh).expect("mesh is made of quads");
+    let mut quads: Vec<_> = (0..builder.positions.len() / 4)
+      .map(|quad| {
+        let mut corners: Vec<_> = builder.positions[quad * 4..quad * 4 + 4]
+          .iter()
+          .map(|p| p.map(|v| (v * 1000.).round() as i64))
+          .collect();
+        corners.sort_unstable();
+        let color = builder.colors[quad * 4].map(|v| (v * 1000.).round() as i64);
+        (corners, color, builder.voxel_types[quad * 4])
+      })
+      .collect();
+    quads.sort_unstable();
+    quads
+  }
 }
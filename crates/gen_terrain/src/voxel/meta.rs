@@ -0,0 +1,281 @@
+use super::{
+  decal::{ChunkDecals, VoxelFace},
+  layout::CubicVoxelLayout,
+  ChunkId, VoxelId,
+};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use std::collections::HashMap;
+
+// sparse gameplay data of the voxels of one chunk, e.g. crop growth stage or damage
+// what the value means is up to the game, voxels without an entry have no metadata
+// the decals on the faces of the chunk's voxels are kept alongside
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChunkVoxelMeta {
+  values: HashMap<VoxelId, u32>,
+  decals: ChunkDecals,
+}
+
+impl ChunkVoxelMeta {
+  pub fn get(&self, voxel: &VoxelId) -> Option<u32> {
+    self.values.get(voxel).copied()
+  }
+
+  // returns the previous value
+  pub fn set(&mut self, voxel: VoxelId, value: u32) -> Option<u32> {
+    self.values.insert(voxel, value)
+  }
+
+  pub fn remove(&mut self, voxel: &VoxelId) -> Option<u32> {
+    self.values.remove(voxel)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&VoxelId, &u32)> {
+    self.values.iter()
+  }
+
+  pub fn decals(&self) -> &ChunkDecals {
+    &self.decals
+  }
+
+  // voxels with a value, decals aren't counted
+  pub fn len(&self) -> usize {
+    self.values.len()
+  }
+
+  // no values and no decals
+  pub fn is_empty(&self) -> bool {
+    self.values.is_empty() && self.decals.is_empty()
+  }
+}
+
+// metadata and decals of every voxel in the world grouped by owning chunk
+// like `ChunkDiffs` it's kept for chunks that aren't loaded, so it survives despawning, is saved by
+// the `ChunkStore` and carried in world snapshots, values survive regenerating, decals don't
+// changes to values should go through `VoxelMetaEditor` so `VoxelMetaChanged` is sent
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VoxelMeta {
+  chunks: HashMap<ChunkId, ChunkVoxelMeta>,
+}
+
+impl VoxelMeta {
+  pub fn get(&self, chunk: &ChunkId, voxel: &VoxelId) -> Option<u32> {
+    self.chunks.get(chunk)?.get(voxel)
+  }
+
+  pub fn set(&mut self, chunk: ChunkId, voxel: VoxelId, value: u32) -> Option<u32> {
+    self.chunks.entry(chunk).or_default().set(voxel, value)
+  }
+
+  pub fn remove(&mut self, chunk: &ChunkId, voxel: &VoxelId) -> Option<u32> {
+    let meta = self.chunks.get_mut(chunk)?;
+    let previous = meta.remove(voxel);
+    if meta.is_empty() {
+      self.chunks.remove(chunk);
+    }
+    previous
+  }
+
+  pub fn chunk(&self, chunk: &ChunkId) -> Option<&ChunkVoxelMeta> {
+    self.chunks.get(chunk)
+  }
+
+  pub fn decals(&self, chunk: &ChunkId) -> Option<&ChunkDecals> {
+    Some(self.chunks.get(chunk)?.decals()).filter(|decals| !decals.is_empty())
+  }
+
+  // lays `tint` over whatever is already on the face, see `ChunkDecals::paint`
+  pub fn paint_decal(&mut self, chunk: ChunkId, voxel: VoxelId, face: VoxelFace, tint: Color) {
+    self
+      .chunks
+      .entry(chunk)
+      .or_default()
+      .decals
+      .paint(voxel, face, tint);
+  }
+
+  // replaces whatever is on the face, e.g. with a decal read back from a save
+  pub fn set_decal(&mut self, chunk: ChunkId, voxel: VoxelId, face: VoxelFace, tint: Color) {
+    self
+      .chunks
+      .entry(chunk)
+      .or_default()
+      .decals
+      .set(voxel, face, tint);
+  }
+
+  // returns whether there was a decal to remove
+  pub fn remove_decal(&mut self, chunk: &ChunkId, voxel: &VoxelId, face: VoxelFace) -> bool {
+    let meta = match self.chunks.get_mut(chunk) {
+      Some(meta) => meta,
+      None => return false,
+    };
+    let removed = meta.decals.remove(voxel, face).is_some();
+    if meta.is_empty() {
+      self.chunks.remove(chunk);
+    }
+    removed
+  }
+
+  // removes the decals of every face of `voxel`
+  pub fn remove_voxel_decals(&mut self, chunk: &ChunkId, voxel: &VoxelId) -> bool {
+    VoxelFace::ALL.into_iter().fold(false, |removed, face| {
+      self.remove_decal(chunk, voxel, face) || removed
+    })
+  }
+
+  // removes every decal, returning the chunks that had any
+  pub fn clear_decals(&mut self) -> Vec<ChunkId> {
+    let decaled = self
+      .chunks
+      .iter()
+      .filter(|(_, meta)| !meta.decals.is_empty())
+      .map(|(chunk, _)| *chunk)
+      .collect();
+    for meta in self.chunks.values_mut() {
+      meta.decals = ChunkDecals::default();
+    }
+    self.chunks.retain(|_, meta| !meta.is_empty());
+    decaled
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&ChunkId, &ChunkVoxelMeta)> {
+    self.chunks.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.chunks.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.chunks.is_empty()
+  }
+
+  // changes needed to turn `self` into `other`
+  fn changes_to(&self, other: &VoxelMeta) -> Vec<VoxelMetaChanged> {
+    let removed = self.iter().flat_map(|(chunk, meta)| {
+      meta
+        .iter()
+        .filter(|(voxel, _)| other.get(chunk, voxel).is_none())
+        .map(|(voxel, _)| VoxelMetaChanged {
+          chunk: *chunk,
+          voxel: *voxel,
+          value: None,
+        })
+    });
+    let updated = other.iter().flat_map(|(chunk, meta)| {
+      meta
+        .iter()
+        .filter(|(voxel, value)| self.get(chunk, voxel) != Some(**value))
+        .map(|(voxel, value)| VoxelMetaChanged {
+          chunk: *chunk,
+          voxel: *voxel,
+          value: Some(*value),
+        })
+    });
+    removed.chain(updated).collect()
+  }
+}
+
+// sent whenever the metadata of a voxel changes, `value` is None when it was removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VoxelMetaChanged {
+  pub chunk: ChunkId,
+  pub voxel: VoxelId,
+  pub value: Option<u32>,
+}
+
+// reads and writes voxel metadata by world voxel, sending `VoxelMetaChanged` for every change
+#[derive(SystemParam)]
+pub struct VoxelMetaEditor<'w, 's> {
+  layout: Res<'w, CubicVoxelLayout>,
+  meta: ResMut<'w, VoxelMeta>,
+  events: EventWriter<'w, 's, VoxelMetaChanged>,
+}
+
+impl<'w, 's> VoxelMetaEditor<'w, 's> {
+  pub fn get(&self, voxel: &VoxelId) -> Option<u32> {
+    self.meta.get(&self.layout.voxel_owner(voxel)?, voxel)
+  }
+
+  // voxels outside the world can't have metadata and are ignored
+  pub fn set(&mut self, voxel: VoxelId, value: u32) {
+    let chunk = match self.layout.voxel_owner(&voxel) {
+      Some(chunk) => chunk,
+      None => return,
+    };
+    if self.meta.set(chunk, voxel, value) != Some(value) {
+      self.events.send(VoxelMetaChanged {
+        chunk,
+        voxel,
+        value: Some(value),
+      });
+    }
+  }
+
+  pub fn remove(&mut self, voxel: &VoxelId) {
+    let chunk = match self.layout.voxel_owner(voxel) {
+      Some(chunk) => chunk,
+      None => return,
+    };
+    if self.meta.remove(&chunk, voxel).is_some() {
+      self.events.send(VoxelMetaChanged {
+        chunk,
+        voxel: *voxel,
+        value: None,
+      });
+    }
+  }
+
+  // chunks that have metadata or decals
+  pub fn chunks(&self) -> impl Iterator<Item = &ChunkId> {
+    self.meta.chunks.keys()
+  }
+
+  pub fn decals(&self, chunk: &ChunkId) -> Option<&ChunkDecals> {
+    self.meta.decals(chunk)
+  }
+
+  // removes the decals of every face of `voxel`, they aren't announced with `VoxelMetaChanged`
+  pub fn remove_decals(&mut self, voxel: &VoxelId) -> bool {
+    match self.layout.voxel_owner(voxel) {
+      Some(chunk) => self.meta.remove_voxel_decals(&chunk, voxel),
+      None => false,
+    }
+  }
+
+  // swaps in metadata from somewhere else, e.g. a world snapshot
+  pub fn replace(&mut self, meta: VoxelMeta) {
+    let changes = self.meta.changes_to(&meta);
+    *self.meta = meta;
+    self.events.send_batch(changes.into_iter());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn changes_should_turn_meta_into_other(before in prop::collection::vec((-3i64..3, -10i64..10, 0u32..4), 0..30), after in prop::collection::vec((-3i64..3, -10i64..10, 0u32..4), 0..30)) {
+          let build = |entries: &Vec<(i64, i64, u32)>| {
+              let mut meta = VoxelMeta::default();
+              for (chunk, x, value) in entries {
+                  meta.set(ChunkId::new(*chunk, 0, 0), VoxelId::new(*x, 0, 0), *value);
+              }
+              meta
+          };
+          let (before, after) = (build(&before), build(&after));
+
+          let mut result = before.clone();
+          for change in before.changes_to(&after) {
+              match change.value {
+                  Some(value) => result.set(change.chunk, change.voxel, value),
+                  None => result.remove(&change.chunk, &change.voxel),
+              };
+          }
+          assert_eq!(result, after);
+      }
+  }
+}
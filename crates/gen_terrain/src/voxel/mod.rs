@@ -1,20 +1,283 @@
+use bevy::prelude::*;
+#[cfg(feature = "render")]
 use bevy::{
-  prelude::*,
-  tasks::{AsyncComputeTaskPool, Task},
+  ecs::{schedule::ShouldRun, system::SystemParam},
+  render::primitives::Frustum,
+  tasks::{AsyncComputeTaskPool, ComputeTaskPool},
 };
-use futures_lite::future;
 use std::collections::HashMap;
+#[cfg(feature = "render")]
+use std::{
+  collections::{HashSet, VecDeque},
+  marker::PhantomData,
+};
 
 // module organization doesn't make sense
 // maybe the layout abstraction doesn't work
 // because all the other modules depend on the layout
 // mesh, voxel generation, voxelId and chunkId meaning etc
+
+// the modules that aren't behind the `render` feature generate and mesh chunks without the
+// renderer, see `headless`
+#[cfg(feature = "render")]
+mod anchor;
+#[cfg(feature = "render")]
+mod autotune;
+#[cfg(feature = "terrain-bench")]
+pub mod bench;
+mod biome;
+#[cfg(feature = "render")]
+mod bounds;
+#[cfg(feature = "render")]
+mod builder;
+#[cfg(feature = "render")]
+mod cache;
+#[cfg(feature = "render")]
+mod cursor;
+#[cfg(feature = "render")]
+mod decal;
+#[cfg(feature = "render")]
+mod decoration;
+#[cfg(feature = "render")]
+mod despawn;
+#[cfg(feature = "render")]
+mod diagnostics;
+#[cfg(feature = "render")]
+mod edit;
+#[cfg(feature = "render")]
+mod editor;
+#[cfg(feature = "render")]
+mod environment;
+mod error;
+#[cfg(feature = "render")]
+mod export;
+#[cfg(feature = "render")]
+mod far_chunks;
+#[cfg(feature = "render")]
+mod foliage;
+#[cfg(feature = "render")]
+mod gen_asset;
 mod generator;
+pub mod headless;
+#[cfg(feature = "render")]
+mod heightmap;
+#[cfg(feature = "render")]
+mod hex;
+#[cfg(feature = "render")]
+mod hexmap;
+#[cfg(feature = "terrain-egui")]
+mod inspector;
+#[cfg(feature = "render")]
+mod jobs;
 mod layout;
+mod light;
+#[cfg(feature = "render")]
+mod lod;
+#[cfg(feature = "render")]
+mod material;
 mod mesher;
+#[cfg(feature = "render")]
+mod meta;
+#[cfg(feature = "terrain-net")]
+mod net;
+#[cfg(feature = "render")]
+mod occlusion;
+#[cfg(feature = "render")]
+mod pipeline;
+#[cfg(feature = "render")]
+mod planet;
+#[cfg(feature = "render")]
+mod poi;
+#[cfg(feature = "render")]
+mod prediction;
+#[cfg(feature = "render")]
+mod pregen;
+#[cfg(feature = "render")]
+mod query;
+#[cfg(feature = "render")]
+mod readiness;
+#[cfg(feature = "render")]
+mod regen;
+mod region;
+mod registry;
+#[cfg(feature = "render")]
+mod retention;
+mod seed;
+#[cfg(feature = "render")]
+mod shape;
+#[cfg(feature = "render")]
+mod snapshot;
+#[cfg(feature = "render")]
+mod sphere;
+#[cfg(feature = "render")]
+mod stats;
+#[cfg(feature = "render")]
+mod store;
+#[cfg(feature = "render")]
+mod streaming;
+#[cfg(all(test, feature = "render"))]
+pub(crate) mod testing;
+#[cfg(feature = "render")]
+mod thumbnail;
+#[cfg(feature = "render")]
+mod tile;
+#[cfg(feature = "render")]
 mod tracker;
+#[cfg(all(debug_assertions, feature = "terrain-validate"))]
+mod validate;
+#[cfg(feature = "render")]
+mod visibility;
+#[cfg(feature = "render")]
+mod world;
+#[cfg(feature = "render")]
+mod wrap;
 
-use layout::*;
+#[cfg(feature = "render")]
+pub use anchor::StreamingAnchor;
+#[cfg(feature = "render")]
+pub use autotune::StreamingAutoTune;
+pub use biome::{biome_at, Biome, BiomeEntered, ChunkBiome, ClimateMap};
+#[cfg(feature = "render")]
+pub use bounds::ChunkBounds;
+#[cfg(feature = "render")]
+pub use builder::{ConfiguredVoxelTerrainPlugin, VoxelTerrainPluginBuilder};
+#[cfg(feature = "render")]
+pub use cache::{
+  ChunkMeshCache, ChunkVoxelCache, CompressedVoxels, MeshCachePolicy, VoxelCachePolicy,
+};
+#[cfg(feature = "render")]
+pub use cursor::{screen_to_ray, CursorTerrainHit, ScreenToTerrain};
+#[cfg(feature = "render")]
+pub use decal::{ChunkDecals, DecalEdit, VoxelFace};
+#[cfg(feature = "render")]
+pub use decoration::{ChunkDecorations, Decoration, DecorationOf, TerrainDecorations};
+#[cfg(feature = "render")]
+pub use despawn::{ChunkDespawning, DespawnDeferral, DespawningChunk};
+#[cfg(feature = "render")]
+pub use diagnostics::TerrainDiagnosticsPlugin;
+#[cfg(feature = "render")]
+pub use edit::{EditHistory, EditedVoxels, TerrainEdits, VoxelChanged, VoxelEdit};
+#[cfg(feature = "render")]
+pub use editor::{BrushPreview, TerrainBrush, TerrainEditorPlugin};
+#[cfg(feature = "render")]
+pub use environment::{SpawnerEnvironment, MAX_CAVITY_VOXELS};
+pub use error::{TerrainError, TerrainErrorEvent};
+#[cfg(feature = "render")]
+pub use export::{ExportFormat, ExportWorldMesh, MergedMesh, MeshGroup};
+#[cfg(feature = "render")]
+pub use far_chunks::{FarChunk, FarChunkSettings};
+#[cfg(feature = "render")]
+pub use foliage::{
+  scatter_foliage, ChunkFoliage, FoliageInstance, FoliageInstances, FoliageSettings,
+};
+#[cfg(feature = "render")]
+pub use gen_asset::{
+  FoliagePass, WorldGenAsset, WorldGenAssetLoader, WorldGenPasses, WorldGenSource,
+};
+pub use generator::{
+  bury, fill_column_steps, fill_columns, ActiveGenerator, ChunkJob, ChunkStep, ColumnCache,
+  ColumnSample, GenerationContext, HeightMap, TerrainGenerator, VoxelGenerator, WorldGenConfig,
+};
+#[cfg(feature = "render")]
+pub use heightmap::{
+  HeightmapEdge, HeightmapGenerator, HeightmapImage, HeightmapSettings, HeightmapTerrain,
+  TerrainExtents,
+};
+#[cfg(feature = "render")]
+pub use hex::{CubeHexLayout, HexRing};
+#[cfg(feature = "render")]
+pub use hexmap::{
+  generate_hex_voxels, HexChunk, HexChunks, HexSpawner, HexTerrainPlugin, HexTerrainSettings,
+};
+#[cfg(feature = "terrain-egui")]
+pub use inspector::TerrainInspectorPlugin;
+#[cfg(feature = "render")]
+pub use jobs::{TerrainJob, TerrainJobFinished, TerrainJobId, TerrainJobProgress, TerrainJobs};
+pub use layout::{ChunkId, VoxelId};
+pub use light::ChunkLight;
+#[cfg(feature = "render")]
+pub use lod::{ChunkLod, ChunkLodSettings, ClippedChunk, DataOnlyChunk, LodBucket, LodChanged};
+#[cfg(feature = "render")]
+pub use material::{
+  TerrainFog, TerrainMaterial, TerrainMaterialConfig, TerrainMaterialPlugin, ATTRIBUTE_VOXEL_TYPE,
+  TERRAIN_MATERIAL_HANDLE,
+};
+#[cfg(feature = "render")]
+pub use mesher::{
+  apply_decals, generate_hex_mesh, generate_hex_mesh_with, generate_sphere_chunk_mesh,
+  generate_sphere_mesh, mesh_chunk_lod, mesh_hex_chunk, mesh_hex_chunk_with, mesh_sphere_chunk,
+  mesh_sphere_chunk_with, mesh_tile_chunk, sphere_border_voxels, HexMeshOptions, MeshFaceIndex,
+};
+pub use mesher::{
+  calculate_normals, calculate_tangents, hex_column_heights, GenerateTangents, NormalMode,
+};
+#[cfg(feature = "render")]
+pub use meta::{ChunkVoxelMeta, VoxelMeta, VoxelMetaChanged, VoxelMetaEditor};
+#[cfg(feature = "terrain-net")]
+pub use net::{SyncRole, TerrainMessage, TerrainSync, TerrainSyncPlugin};
+#[cfg(feature = "render")]
+pub use occlusion::{occlusion_between, ChunkOcclusion, ChunkOcclusionSettings, OCCLUSION_CELLS};
+#[cfg(feature = "render")]
+pub use pipeline::{
+  ChunkBatchPool, ChunkPipeline, ChunkPipelineBudget, ChunkPipelineStats, ChunkTaskLimits,
+  GenerationMode,
+};
+#[cfg(feature = "render")]
+pub use planet::{
+  generate_sphere_voxels, SphereChunk, SphereChunks, SphereSpawner, SphereTerrainPlugin,
+  SphereTerrainSettings,
+};
+#[cfg(feature = "render")]
+pub use poi::{place_pois, Poi, PoiChunkMeshed, PoiId, PoiKind, PoiKindId, PoiRegistry};
+#[cfg(feature = "render")]
+pub use prediction::ChunkSpawnerConfig;
+#[cfg(feature = "render")]
+pub use pregen::{TerrainPregeneration, TerrainPregenerator};
+#[cfg(feature = "render")]
+pub use query::{TerrainHit, TerrainQuery, VoxelWorld};
+#[cfg(feature = "render")]
+pub use readiness::{TerrainReadiness, TerrainReadinessChanged};
+#[cfg(feature = "render")]
+pub use regen::RegenerateTerrain;
+pub use region::{
+  river_levels, road_levels, road_sites, trace_rivers, trace_roads, RegionSample, RiverFlow,
+  WorldRegion, WorldRegionSettings, WorldRegions,
+};
+pub use registry::{VoxelRegistry, VoxelTypeId, VoxelTypeInfo};
+#[cfg(feature = "render")]
+pub use retention::ChunkRetentionPolicy;
+pub use seed::{ChunkRng, ChunkSeed};
+#[cfg(feature = "render")]
+pub use shape::LoadShape;
+#[cfg(feature = "render")]
+pub use snapshot::{ApplyWorldSnapshot, ChunkDiffs, SnapshotError, WorldSnapshot};
+#[cfg(feature = "render")]
+pub use sphere::{CubeFace, SphereVoxelLayout};
+#[cfg(feature = "render")]
+pub use stats::{ChunkStateCounts, TerrainStats};
+#[cfg(feature = "render")]
+pub use store::{ChunkMigrator, ChunkStore, RegionId, CHUNK_FORMAT_VERSION};
+#[cfg(feature = "render")]
+pub use streaming::TerrainStreaming;
+#[cfg(feature = "render")]
+pub use thumbnail::{
+  TerrainThumbnailPlugin, TerrainThumbnails, ThumbnailCamera, ThumbnailCaptured, ThumbnailId,
+  ThumbnailRequest,
+};
+#[cfg(feature = "render")]
+pub use tile::{
+  terrain_to_tile, tile_to_terrain, TileChunk, TileSet, TileSpawner, TileTerrainPlugin,
+};
+#[cfg(feature = "render")]
+pub use tracker::ChunkTracker;
+#[cfg(all(debug_assertions, feature = "terrain-validate"))]
+pub use validate::{stitching_problems, StitchProblem};
+#[cfg(feature = "render")]
+pub use visibility::{ChunkVisibilitySettings, EmptyChunk, EnclosedChunk, OutsideView};
+#[cfg(feature = "render")]
+pub use world::{TerrainWorld, TerrainWorlds};
+#[cfg(feature = "render")]
+pub use wrap::chunk_placement;
 
 // #[derive(Debug)]
 // pub enum VoxelTerrainEvents {
@@ -22,188 +285,1312 @@ use layout::*;
 //   ChunkDespawned(Entity)
 // }
 
+// how quickly the measured heading change rate follows the actual heading changes
+#[cfg(feature = "render")]
+const HEADING_SMOOTHING_SECONDS: f32 = 0.5;
+// chunks per task when working out their distances to the spawners
+#[cfg(feature = "render")]
+const DISTANCE_BATCH_SIZE: usize = 64;
+
+#[cfg(feature = "render")]
 #[derive(Default, Debug, Component)]
 pub struct ChunkSpawner {
   pub last_loaded_chunk: Option<ChunkId>,
   pub fresh: bool,
+  // world units per second, measured from the transform between frames
+  pub velocity: Vec3,
+  // smoothed heading change on the ground plane in radians per second
+  pub turn_rate: f32,
+  // 0 is fully zoomed in and 1 fully zoomed out, set by whatever drives the spawner
+  pub zoom: f32,
+  pub load_shape: LoadShape,
+  last_position: Option<Vec3>,
+  spawn_radius: i64,
+  // heading on the ground plane the last load was shaped for
+  load_heading: Vec2,
+  // chunks ahead of the spawner that were preloaded with the last load
+  predicted_path: Vec<ChunkId>,
+  // chunks along where the spawner looked that were preloaded with the last load
+  view_path: Vec<ChunkId>,
+  // chunks of the last load that haven't been spawned yet, nearest first
+  pending: VecDeque<ChunkId>,
+  // jumped farther than `ChunkSpawnerConfig::teleport_distance` this frame
+  teleported: bool,
+}
+
+#[cfg(feature = "render")]
+impl ChunkSpawner {
+  // chunks waiting for their turn to be spawned
+  pub fn pending_chunks(&self) -> usize {
+    self.pending.len()
+  }
+
+  pub fn teleported(&self) -> bool {
+    self.teleported
+  }
 }
 
+#[cfg(feature = "render")]
 #[derive(Debug, Default, Component)]
 pub struct Chunk {
   pub id: ChunkId,
+  // world units to the chunk the nearest spawner is on
   pub distance_to_nearest_spawner: f32,
+  // rings of chunks to the chunk the nearest spawner is on
+  pub steps_to_nearest_spawner: i64,
+  pub out_of_range_seconds: f32,
 }
 
 #[derive(Debug, Default, Component)]
 pub struct ChunkVoxelData {
-  pub voxels: HashMap<VoxelId, generator::VoxelType>,
+  pub voxels: HashMap<VoxelId, registry::VoxelTypeId>,
 }
 
+// marks a chunk whose voxels changed since its mesh was generated
+#[cfg(feature = "render")]
+#[derive(Debug, Default, Component)]
+pub struct DirtyChunk;
+
+// marks a chunk whose voxels were edited since it was generated
+#[cfg(feature = "render")]
+#[derive(Debug, Default, Component)]
+pub struct EditedChunk;
+
+// the terrain systems run as sets in this order every frame, order your own systems relative to
+// them e.g. `.after(TerrainSystem::Apply)` to see this frame's edits
+#[cfg(feature = "render")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, SystemLabel)]
+pub enum TerrainSystem {
+  // picks up new settings, world gen assets and regenerations before anything is spawned
+  Prepare,
+  // follows the spawners, spawns chunks around them and starts generating their voxels
+  Spawn,
+  // restores saved regions and applies finished voxel and mesh tasks
+  Generate,
+  // applies edits and snapshots to voxel data and relights the edited chunks
+  Apply,
+  // follows the applied voxels, e.g. chunk bounds, occlusion, decorations and spawner environments
+  Track,
+  // patches edited meshes and starts meshing chunks whose voxels changed
+  Mesh,
+  // despawns chunks out of range and evicts stale cached meshes
+  Despawn,
+  // saves, reports and exports what changed this frame
+  Report,
+  // within `Generate`, loading saved regions
+  Persistence,
+  // within `Apply`, relighting edited chunks
+  Lighting,
+}
+
+#[cfg(feature = "render")]
+impl TerrainSystem {
+  #[deprecated(note = "renamed to `TerrainSystem::Mesh`")]
+  #[allow(non_upper_case_globals)]
+  pub const Meshing: TerrainSystem = TerrainSystem::Mesh;
+}
+
+// sets of terrain systems that are switched off, e.g. disable `Spawn` and `Despawn` to freeze the
+// loaded area while still applying edits
+#[cfg(feature = "render")]
+#[derive(Debug, Default)]
+pub struct TerrainSchedule {
+  disabled: HashSet<TerrainSystem>,
+}
+
+#[cfg(feature = "render")]
+impl TerrainSchedule {
+  pub fn enable(&mut self, set: TerrainSystem) {
+    self.disabled.remove(&set);
+  }
+
+  pub fn disable(&mut self, set: TerrainSystem) {
+    self.disabled.insert(set);
+  }
+
+  pub fn is_enabled(&self, set: TerrainSystem) -> bool {
+    !self.disabled.contains(&set)
+  }
+}
+
+#[cfg(feature = "render")]
+fn terrain_set(set: TerrainSystem) -> SystemSet {
+  SystemSet::new()
+    .label(set)
+    .with_run_criteria(move |schedule: Res<TerrainSchedule>| {
+      if schedule.is_enabled(set) {
+        ShouldRun::Yes
+      } else {
+        ShouldRun::No
+      }
+    })
+}
+
+// streams, generates and meshes voxel terrain around `ChunkSpawner`s
+// `VoxelTerrainPlugin` uses whatever settings resources were inserted before it's added,
+// `VoxelTerrainPlugin::builder()` sets them up in one place
+#[cfg(feature = "render")]
 #[derive(Default)]
 pub struct VoxelTerrainPlugin;
 
+#[cfg(feature = "render")]
+impl VoxelTerrainPlugin {
+  pub fn builder() -> builder::VoxelTerrainPluginBuilder {
+    default()
+  }
+}
+
+#[cfg(feature = "render")]
 impl Plugin for VoxelTerrainPlugin {
   fn build(&self, app: &mut App) {
     app
+      .register_type::<ChunkId>()
+      .register_type::<VoxelId>()
       .init_resource::<tracker::ChunkTracker>()
-      .init_resource::<generator::VoxelGenerator>()
+      .init_resource::<cursor::CursorTerrainHit>()
+      .init_resource::<mesher::NormalMode>()
+      .init_resource::<mesher::GenerateTangents>()
+      .init_resource::<generator::ActiveGenerator>()
+      .init_resource::<generator::WorldGenConfig>()
+      .init_resource::<region::WorldRegions>()
+      .init_resource::<registry::VoxelRegistry>()
       .init_resource::<layout::CubicVoxelLayout>()
-      .add_system(spawn_chunks)
-      .add_system(calc_chunk_distances)
-      .add_system(load_voxels)
-      .add_system(build_chunk_mesh)
-      .add_system(attach_chunk_mesh)
-      .add_system(despawn_chunks);
+      .init_resource::<edit::TerrainEdits>()
+      .init_resource::<edit::EditHistory>()
+      .init_resource::<cache::ChunkMeshCache>()
+      .init_resource::<cache::MeshCachePolicy>()
+      .init_resource::<cache::ChunkVoxelCache>()
+      .init_resource::<cache::VoxelCachePolicy>()
+      .init_resource::<pipeline::GenerationMode>()
+      .init_resource::<pipeline::ChunkTaskLimits>()
+      .init_resource::<pipeline::ChunkBatchPool>()
+      .init_resource::<pipeline::ChunkPipeline>()
+      .init_resource::<pipeline::ChunkPipelineBudget>()
+      .init_resource::<pipeline::ChunkPipelineStats>()
+      .init_resource::<retention::ChunkRetentionPolicy>()
+      .init_resource::<snapshot::ChunkDiffs>()
+      .init_resource::<meta::VoxelMeta>()
+      .init_resource::<store::ChunkStore>()
+      .init_resource::<lod::ChunkLodSettings>()
+      .init_resource::<occlusion::ChunkOcclusionSettings>()
+      .init_resource::<prediction::ChunkSpawnerConfig>()
+      .init_resource::<visibility::ChunkVisibilitySettings>()
+      .init_resource::<decoration::TerrainDecorations>()
+      .init_resource::<streaming::TerrainStreaming>()
+      .init_resource::<readiness::TerrainReadiness>()
+      .init_resource::<jobs::TerrainJobs>()
+      .init_resource::<pregen::TerrainPregeneration>()
+      .init_resource::<stats::TerrainStats>()
+      .init_resource::<world::TerrainWorlds>()
+      .init_resource::<poi::PoiRegistry>()
+      .init_resource::<TerrainSchedule>()
+      .add_event::<snapshot::ApplyWorldSnapshot>()
+      .add_event::<regen::RegenerateTerrain>()
+      .add_event::<meta::VoxelMetaChanged>()
+      .add_event::<edit::VoxelChanged>()
+      .add_event::<biome::BiomeEntered>()
+      .add_event::<export::ExportWorldMesh>()
+      .add_event::<error::TerrainErrorEvent>()
+      .add_event::<readiness::TerrainReadinessChanged>()
+      .add_event::<jobs::TerrainJobProgress>()
+      .add_event::<jobs::TerrainJobFinished>()
+      .add_event::<despawn::ChunkDespawning>()
+      .add_event::<lod::LodChanged>()
+      .add_event::<poi::PoiChunkMeshed>()
+      .add_asset::<gen_asset::WorldGenAsset>()
+      .init_asset_loader::<gen_asset::WorldGenAssetLoader>()
+      .add_plugin(far_chunks::FarChunkPlugin)
+      .add_plugin(foliage::FoliagePlugin)
+      .add_plugin(material::TerrainMaterialPlugin)
+      .add_startup_system(decoration::setup_decorations)
+      // the tracker is cleared before spawners look at it, or chunks they spawn in the meantime
+      // would be spawned again
+      .add_system_set(
+        terrain_set(TerrainSystem::Prepare)
+          .with_system(regen::regenerate_terrain)
+          .with_system(autotune::tune_streaming_budgets)
+          .with_system(pipeline::apply_task_limits)
+          .with_system(heightmap::load_heightmap_terrain.before(regen::regenerate_terrain))
+          .with_system(gen_asset::apply_world_gen_asset.before(regen::regenerate_terrain))
+          .with_system(error::validate_terrain_settings),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Spawn)
+          .after(TerrainSystem::Prepare)
+          .with_system(track_spawner_motion)
+          .with_system(anchor::track_streaming_anchors)
+          .with_system(jobs::require_job_chunks.after(anchor::track_streaming_anchors))
+          .with_system(
+            spawn_chunks
+              .after(track_spawner_motion)
+              .after(jobs::require_job_chunks),
+          )
+          .with_system(cancel_teleported_loads.after(spawn_chunks))
+          .with_system(unclip_near_chunks.after(spawn_chunks))
+          .with_system(calc_chunk_distances.after(spawn_chunks))
+          .with_system(lod::assign_chunk_lods)
+          .with_system(visibility::update_chunk_visibility),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Generate)
+          .after(TerrainSystem::Spawn)
+          .with_system(store::load_chunk_regions.label(TerrainSystem::Persistence))
+          .with_system(pipeline::apply_chunk_results.after(TerrainSystem::Persistence)),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Apply)
+          .after(TerrainSystem::Generate)
+          .with_system(snapshot::apply_world_snapshots)
+          .with_system(edit::apply_voxel_edits.before(TerrainSystem::Lighting))
+          .with_system(decal::apply_decal_edits.after(edit::apply_voxel_edits))
+          .with_system(light::update_chunk_light.label(TerrainSystem::Lighting)),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Track)
+          .after(TerrainSystem::Apply)
+          .with_system(bounds::update_chunk_bounds)
+          .with_system(occlusion::update_chunk_occlusion)
+          .with_system(wrap::place_wrapped_chunks)
+          .with_system(environment::update_spawner_environments)
+          .with_system(cursor::update_cursor_terrain_hit)
+          .with_system(biome::track_spawner_biomes.after(environment::update_spawner_environments))
+          .with_system(decoration::decorate_chunks)
+          .with_system(decoration::sync_decoration_visibility),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Mesh)
+          .after(TerrainSystem::Track)
+          .with_system(patch_edited_meshes.before(build_chunk_mesh))
+          .with_system(visibility::classify_changed_chunks.before(build_chunk_mesh))
+          .with_system(remesh_on_normal_mode_change.before(build_chunk_mesh))
+          .with_system(build_chunk_mesh),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Despawn)
+          .after(TerrainSystem::Mesh)
+          .with_system(despawn::finish_chunk_despawns.before(despawn_chunks))
+          .with_system(despawn_chunks)
+          .with_system(cache::collect_stale_meshes),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Report)
+          .after(TerrainSystem::Despawn)
+          .with_system(readiness::update_terrain_readiness)
+          .with_system(pregen::pregenerate_chunks.before(jobs::update_terrain_jobs))
+          .with_system(jobs::update_terrain_jobs)
+          .with_system(poi::announce_poi_chunks)
+          .with_system(stats::update_terrain_stats)
+          .with_system(store::flush_chunk_store)
+          .with_system(export::export_world_mesh),
+      )
+      // after the exit flush so its errors are logged before the app shuts down
+      .add_system_set_to_stage(
+        CoreStage::Last,
+        terrain_set(TerrainSystem::Report)
+          .with_system(store::flush_chunk_store_on_exit)
+          .with_system(error::log_terrain_errors.after(store::flush_chunk_store_on_exit)),
+      );
+
+    // catches mesher bugs at chunk borders, far too slow to leave on
+    #[cfg(all(debug_assertions, feature = "terrain-validate"))]
+    app.add_system_set(
+      terrain_set(TerrainSystem::Report)
+        .after(TerrainSystem::Despawn)
+        .with_system(validate::validate_chunk_stitching),
+    );
+
+    // synchronous jobs (and every job without worker threads) are run in between frames, before
+    // the update so the chunks they were submitted for have been spawned by then
+    app.add_system_set_to_stage(
+      CoreStage::PreUpdate,
+      terrain_set(TerrainSystem::Generate).with_system(pipeline::run_chunk_jobs),
+    );
   }
 }
 
+#[cfg(feature = "render")]
+pub fn track_spawner_motion(
+  time: Res<Time>,
+  layout: Res<layout::CubicVoxelLayout>,
+  spawner_config: Res<prediction::ChunkSpawnerConfig>,
+  mut query: Query<(&Transform, &mut ChunkSpawner)>,
+) {
+  let delta = time.delta_seconds();
+  if delta <= 0. {
+    return;
+  }
+
+  for (transform, mut site) in query.iter_mut() {
+    let position = transform.translation;
+    site.teleported = false;
+    if let Some(last_position) = site.last_position {
+      // a jump isn't motion, predicting along it would preload a corridor across the map
+      let jumped = layout.chunk_step_distance(
+        &layout.space_to_chunk(&last_position),
+        &layout.space_to_chunk(&position),
+      );
+      if jumped > spawner_config.teleport_distance {
+        info!("spawner teleported {} chunks", jumped);
+        site.teleported = true;
+        site.velocity = Vec3::ZERO;
+        site.turn_rate = 0.;
+        site.last_position = Some(position);
+        continue;
+      }
+
+      let velocity = (position - last_position) / delta;
+
+      // heading changes are only meaningful while moving
+      let previous_heading = Vec2::new(site.velocity.x, site.velocity.z);
+      let heading = Vec2::new(velocity.x, velocity.z);
+      let turn_rate = if previous_heading.length_squared() > f32::EPSILON
+        && heading.length_squared() > f32::EPSILON
+      {
+        previous_heading.angle_between(heading).abs() / delta
+      } else {
+        0.
+      };
+
+      let blend = (delta / HEADING_SMOOTHING_SECONDS).min(1.);
+      site.turn_rate += (turn_rate - site.turn_rate) * blend;
+      site.velocity = velocity;
+    }
+    site.last_position = Some(position);
+  }
+}
+
+#[cfg(feature = "render")]
+#[allow(clippy::too_many_arguments)]
 pub fn spawn_chunks(
   mut commands: Commands,
-  thread_pool: Res<AsyncComputeTaskPool>,
+  time: Res<Time>,
+  generation: ChunkGeneration,
   layout: Res<layout::CubicVoxelLayout>,
-  generator: Res<generator::VoxelGenerator>,
+  config: Res<generator::WorldGenConfig>,
+  pipeline: Res<pipeline::ChunkPipeline>,
+  budget: Res<pipeline::ChunkPipelineBudget>,
+  lod_settings: Res<lod::ChunkLodSettings>,
+  spawner_config: Res<prediction::ChunkSpawnerConfig>,
   mut tracker: ResMut<tracker::ChunkTracker>,
-  mut query: Query<(&Transform, &mut ChunkSpawner)>,
+  mut mesh_cache: ResMut<cache::ChunkMeshCache>,
+  mut voxel_cache: ResMut<cache::ChunkVoxelCache>,
+  streaming: Res<streaming::TerrainStreaming>,
+  mut worlds: ResMut<world::TerrainWorlds>,
+  mut query: Query<(
+    &Transform,
+    Option<&Frustum>,
+    Option<&world::TerrainWorld>,
+    &mut ChunkSpawner,
+  )>,
 ) {
-  for (transform, mut site) in query.iter_mut() {
+  if streaming.is_paused() {
+    return;
+  }
+
+  for (transform, frustum, world, mut site) in query.iter_mut() {
+    // spawners of removed worlds keep their chunks until they're despawned
+    if !worlds.contains(&world.copied().unwrap_or_default()) {
+      continue;
+    }
+
     // find which chunk we're currently on
-    let current_chunk = layout.space_to_chunk(&transform.translation);
+    let current_chunk = layout.clamp_to_world(&layout.space_to_chunk(&transform.translation));
+    let spawn_radius = spawner_config.spawn_rings(&lod_settings, site.zoom);
+    let heading = shape::heading(transform);
+
+    // where the spawner is headed, fast spawners would otherwise outrun chunk loading
+    let predicted_position =
+      transform.translation + site.velocity * spawner_config.prediction_seconds;
+    let predicted_path = prediction::wrapped_path(
+      &layout,
+      current_chunk,
+      layout.clamp_to_world(&layout.space_to_chunk(&predicted_position)),
+      spawner_config.max_prediction_chunks,
+    );
+    let view_path = match spawner_config.view_distance {
+      Some(distance) => {
+        prediction::view_path(&layout, transform, distance, spawner_config.max_view_chunks)
+      }
+      None => Vec::new(),
+    };
 
-    // skip this site if it hasn't moved chunks since the last load
+    // skip this site if it hasn't moved chunks, zoomed, turned, changed course or looked
+    // elsewhere since the last load
     if let Some(last_loaded) = site.last_loaded_chunk {
-      if last_loaded == current_chunk {
+      let turned = site.load_shape.depends_on_heading()
+        && site.load_heading.angle_between(heading).abs() > shape::LOAD_HEADING_TOLERANCE;
+      if last_loaded == current_chunk
+        && site.spawn_radius == spawn_radius
+        && site.predicted_path == predicted_path
+        && site.view_path == view_path
+        && !turned
+      {
         continue;
       }
     }
 
-    // find neighboring chunks
-    let neighbors = layout.get_chunk_neighbors(&current_chunk, 2);
+    // find the chunks along the view first so long sight lines fill in before the ground behind
+    // the spawner, then neighboring chunks, then a narrower corridor along the predicted path
+    let in_view: Vec<_> = view_path
+      .iter()
+      .flat_map(|chunk| layout.iter_chunks_spiral(chunk, spawner_config.view_radius))
+      .collect();
+    let neighbors = site
+      .load_shape
+      .get_columns(&layout, &current_chunk, spawn_radius, heading);
+    let ahead: Vec<_> = predicted_path
+      .iter()
+      .flat_map(|chunk| layout.iter_chunks_spiral(chunk, spawner_config.prediction_radius))
+      .collect();
+
+    // spawn the sections of every column around the spawner's section
+    let mut sections: Vec<_> = std::iter::once(current_chunk)
+      .chain(in_view)
+      .chain(neighbors)
+      .chain(ahead)
+      .flat_map(|column| layout.get_column_sections(&column, spawner_config.vertical_radius))
+      .collect();
+    // sections in view are generated first so the visible terrain shows up sooner, chunks of a
+    // wrapping world are looked for where they'll be placed next to the spawner
+    if let Some(frustum) = frustum {
+      sections.sort_by_key(|chunk| {
+        let placed = wrap::chunk_placement(&layout, chunk, [transform.translation]);
+        !visibility::in_view(
+          &[frustum],
+          &visibility::chunk_bounding_sphere_at(&layout, placed),
+          0.,
+        )
+      });
+    }
 
-    // spawn chunks
-    for chunk in std::iter::once(current_chunk).chain(neighbors) {
-      if tracker.try_spawn(&chunk) {
-        // println!("Spawning {:?}", chunk);
-        let pos = layout.chunk_to_space(&chunk);
+    // a new load replaces whatever is left of the previous one
+    site.pending = sections.into();
 
-        let voxel_buffer = layout
-          .get_chunk_voxels(&chunk)
-          .into_iter()
-          .map(|id| (id, generator::VoxelType::Air))
-          .collect();
+    site.fresh = true;
+    site.last_loaded_chunk = Some(current_chunk);
+    site.spawn_radius = spawn_radius;
+    site.load_heading = heading;
+    site.predicted_path = predicted_path;
+    site.view_path = view_path;
+  }
 
-        // TODO: the voxel data might be better off in a resource
-        // this allows access to the voxel data from an async task
-        let load_voxels_task = generator.load_voxel_data(&thread_pool, voxel_buffer);
+  // pinned chunks are loaded even if no spawner is near them
+  let mut to_spawn: Vec<_> = tracker
+    .pinned()
+    .filter(|chunk| !tracker.is_loaded(chunk))
+    .map(|chunk| (world::TerrainWorld::PRIMARY, *chunk))
+    .chain(worlds.unloaded_pins())
+    .collect();
 
-        // create entities for chunks
-        commands
-          .spawn()
-          .insert(Transform::from_translation(pos))
-          .insert(Chunk {
-            id: chunk,
-            distance_to_nearest_spawner: 0., // will be computed by another system
-          })
-          .insert(load_voxels_task);
+  // spawners take turns so each of them gets its nearest chunks first, the rest wait for the next
+  // frames instead of all spawning at once
+  let mut sites: Vec<_> = query
+    .iter_mut()
+    .map(|(_, _, world, site)| (world.copied().unwrap_or_default(), site))
+    .collect();
+  while to_spawn.len() < budget.spawns_per_frame {
+    let mut queued = false;
+    for (world, site) in sites.iter_mut() {
+      let loaded = match worlds.tracker(world, &tracker) {
+        Some(loaded) => loaded,
+        None => continue,
+      };
+      while let Some(chunk) = site.pending.pop_front() {
+        if !loaded.is_loaded(&chunk) && !to_spawn.contains(&(*world, chunk)) {
+          to_spawn.push((*world, chunk));
+          queued = true;
+          break;
+        }
       }
+      if to_spawn.len() >= budget.spawns_per_frame {
+        break;
+      }
+    }
+    if !queued {
+      break;
     }
+  }
 
-    site.fresh = true;
-    site.last_loaded_chunk = Some(current_chunk);
+  // streaming anchors get whatever budget the spawners left, higher priorities first
+  if to_spawn.len() < budget.spawns_per_frame {
+    let mut required: Vec<_> = tracker
+      .unloaded_required()
+      .map(|(chunk, priority)| (world::TerrainWorld::PRIMARY, chunk, priority))
+      .chain(worlds.unloaded_required())
+      .collect();
+    required.sort_by_key(|(.., priority)| std::cmp::Reverse(*priority));
+    for (world, chunk, _) in required {
+      if to_spawn.len() >= budget.spawns_per_frame {
+        break;
+      }
+      if !to_spawn.contains(&(world, chunk)) {
+        to_spawn.push((world, chunk));
+      }
+    }
+  }
+
+  // spawn chunks
+  for (world, chunk) in to_spawn {
+    let config = match worlds.config(&world, &config) {
+      Some(config) => config.clone(),
+      None => continue,
+    };
+    let tracker = match worlds.tracker_mut(&world, &mut tracker) {
+      Some(tracker) => tracker,
+      None => continue,
+    };
+    if tracker.try_spawn(&chunk, time.seconds_since_startup()) {
+      // println!("Spawning {:?}", chunk);
+      let pos = wrap::chunk_placement(
+        &layout,
+        &chunk,
+        query
+          .iter()
+          .filter(|(_, _, site_world, _)| site_world.copied().unwrap_or_default() == world)
+          .map(|(transform, ..)| transform.translation),
+      );
+
+      // distant chunks only need their surface, chunks kept for something other than a spawner
+      // may be looked at up close
+      let rings = query
+        .iter()
+        .filter(|(_, _, site_world, _)| site_world.copied().unwrap_or_default() == world)
+        .filter_map(|(.., site)| site.last_loaded_chunk)
+        .map(|center| layout.chunk_step_distance(&chunk, &center))
+        .min();
+      // voxels of a chunk despawned not long ago are taken from the cache instead of generated,
+      // cached voxels are of the primary world and never clipped
+      let cached_voxels = match world.is_primary() {
+        true => voxel_cache.take(&chunk),
+        false => None,
+      };
+      let clipped = matches!((lod_settings.clip_rings, rings), (Some(clip), Some(rings)) if rings >= clip)
+        && cached_voxels.is_none()
+        && !tracker.is_pinned(&chunk)
+        && !tracker.is_required(&chunk);
+
+      let context = generation
+        .context(chunk, config)
+        .with_surface_clip(clipped.then(|| lod_settings.clip_depth));
+      let chunk_seed = context.chunk_seed();
+      let chunk_biome = biome::biome_at(&context.config, &layout.get_center_voxel(&chunk));
+      let load_voxels_job = match cached_voxels {
+        Some(cached) => {
+          let layout = (*layout).clone();
+          generator::ChunkStep::job(Box::new(move || cached.decompress(&layout, &chunk)))
+        }
+        None if world.is_primary() => generation.load_stored_voxels(&layout, context),
+        None => generation.load_voxels(&layout, context),
+      };
+
+      // create entities for chunks
+      let mut entity = commands.spawn();
+      entity
+        .insert(Transform::from_translation(pos))
+        .insert(Chunk {
+          id: chunk,
+          // distances will be computed by another system
+          ..default()
+        })
+        .insert(chunk_seed)
+        .insert(
+          bounds::ChunkBounds::full(&layout, &chunk)
+            .translated(pos - layout.chunk_to_space(&chunk)),
+        )
+        .insert(world)
+        .insert(biome::ChunkBiome(chunk_biome));
+      if !world.is_primary() {
+        entity.insert(world.render_layer());
+      }
+      if clipped {
+        entity.insert(lod::ClippedChunk::default());
+      }
+      pipeline.submit_voxel_steps(&generation.thread_pool, entity.id(), load_voxels_job);
+      tracker.register_entity(chunk, entity.id());
+
+      // reuse the mesh from the last time this chunk was loaded, cached meshes are of the primary
+      // world
+      let cached = match world.is_primary() {
+        true => mesh_cache.take(&chunk),
+        false => None,
+      };
+      if let Some(mesh) = cached {
+        entity.insert_bundle(MaterialMeshBundle {
+          mesh,
+          material: chunk_material(),
+          transform: Transform::from_translation(pos),
+          ..default()
+        });
+      }
+    }
+  }
+}
+
+// what the systems that start chunk generation jobs share
+#[cfg(feature = "render")]
+#[derive(SystemParam)]
+pub struct ChunkGeneration<'w, 's> {
+  thread_pool: Res<'w, AsyncComputeTaskPool>,
+  batch_pool: Res<'w, pipeline::ChunkBatchPool>,
+  generator: Res<'w, generator::ActiveGenerator>,
+  registry: Res<'w, registry::VoxelRegistry>,
+  regions: Res<'w, region::WorldRegions>,
+  store: Res<'w, store::ChunkStore>,
+  layout: Res<'w, layout::CubicVoxelLayout>,
+  pipeline: Res<'w, pipeline::ChunkPipeline>,
+  #[system_param(ignore)]
+  marker: PhantomData<&'s ()>,
+}
+
+#[cfg(feature = "render")]
+impl<'w, 's> ChunkGeneration<'w, 's> {
+  fn context(
+    &self,
+    chunk: ChunkId,
+    config: generator::WorldGenConfig,
+  ) -> generator::GenerationContext {
+    generator::GenerationContext::new(
+      chunk,
+      config,
+      self.registry.clone(),
+      self.batch_pool.0.clone(),
+    )
+    .with_regions(self.regions.clone())
+    .with_wrapping(self.layout.wrapping_voxels())
+  }
+
+  fn load_voxels(
+    &self,
+    layout: &layout::CubicVoxelLayout,
+    context: generator::GenerationContext,
+  ) -> generator::ChunkStep<ChunkVoxelData> {
+    // TODO: the voxel data might be better off in a resource
+    // this allows access to the voxel data from an async task
+    let voxel_buffer = layout
+      .iter_chunk_voxels(&context.chunk)
+      .map(|id| (id, registry::VoxelTypeId::AIR))
+      .collect();
+    // inline jobs are split into steps so they can stop in between, jobs on worker threads can
+    // split up the chunk between threads instead
+    match self.pipeline.mode().is_synchronous() {
+      true => self.generator.0.load_voxel_steps(context, voxel_buffer),
+      false => generator::ChunkStep::job(self.generator.0.load_voxel_data(context, voxel_buffer)),
+    }
+  }
+
+  // chunks pregenerated into the store are read instead of generated, they're generated after
+  // all if they aren't there or are of another config
+  // only for chunks of the primary world, clipped chunks are always generated
+  fn load_stored_voxels(
+    &self,
+    layout: &layout::CubicVoxelLayout,
+    context: generator::GenerationContext,
+  ) -> generator::ChunkStep<ChunkVoxelData> {
+    let chunk = context.chunk;
+    let fingerprint = store::config_fingerprint(&context.config);
+    let generated = match self.store.generated_voxels() {
+      Some(generated) if context.surface_clip.is_none() => generated,
+      _ => return self.load_voxels(layout, context),
+    };
+    let generate = self.load_voxels(layout, context);
+    let layout = layout.clone();
+    let registry = (*self.registry).clone();
+    generator::ChunkStep::Next(Box::new(move || {
+      match generated.read(fingerprint, &layout, &chunk, &registry) {
+        Some(voxels) => generator::ChunkStep::Done(voxels.decompress(&layout, &chunk)),
+        None => generate,
+      }
+    }))
+  }
+}
+
+// clipped chunks a spawner comes close to are generated again in full, they keep their clipped
+// voxels and mesh until the full voxels are ready
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn unclip_near_chunks(
+  generation: ChunkGeneration,
+  layout: Res<layout::CubicVoxelLayout>,
+  config: Res<generator::WorldGenConfig>,
+  pipeline: Res<pipeline::ChunkPipeline>,
+  lod_settings: Res<lod::ChunkLodSettings>,
+  tracker: Res<tracker::ChunkTracker>,
+  worlds: Res<world::TerrainWorlds>,
+  sites: Query<(&ChunkSpawner, Option<&world::TerrainWorld>)>,
+  mut chunks: Query<
+    (
+      Entity,
+      &Chunk,
+      &mut lod::ClippedChunk,
+      Option<&world::TerrainWorld>,
+    ),
+    With<ChunkVoxelData>,
+  >,
+) {
+  for (entity, chunk, mut clipped, world) in chunks.iter_mut() {
+    if clipped.regenerating {
+      continue;
+    }
+    let world = world.copied().unwrap_or_default();
+    let near = sites
+      .iter()
+      .filter(|(_, site_world)| site_world.copied().unwrap_or_default() == world)
+      .filter_map(|(site, _)| site.last_loaded_chunk)
+      .any(|center| {
+        !matches!(lod_settings.clip_rings, Some(clip) if layout.chunk_step_distance(&chunk.id, &center) >= clip)
+      });
+    let kept = matches!(worlds.tracker(&world, &tracker), Some(tracker)
+      if tracker.is_pinned(&chunk.id) || tracker.is_required(&chunk.id));
+    if !near && !kept {
+      continue;
+    }
+    let config = match worlds.config(&world, &config) {
+      Some(config) => config.clone(),
+      None => continue,
+    };
+
+    info!("generating clipped chunk {:?} in full", chunk.id);
+    let context = generation.context(chunk.id, config);
+    let job = match world.is_primary() {
+      true => generation.load_stored_voxels(&layout, context),
+      false => generation.load_voxels(&layout, context),
+    };
+    pipeline.submit_voxel_steps(&generation.thread_pool, entity, job);
+    clipped.regenerating = true;
   }
 }
 
+// distances from every chunk to the nearest spawner of its world (for LODs and despawning), worked
+// out again whenever a spawner loads somewhere new or chunks near a spawner haven't got a
+// `LodBucket` yet
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn calc_chunk_distances(
+  mut commands: Commands,
   layout: Res<layout::CubicVoxelLayout>,
-  mut query: Query<&mut Chunk>,
-  mut site_query: Query<&mut ChunkSpawner>,
+  lod_settings: Res<lod::ChunkLodSettings>,
+  pool: Res<ComputeTaskPool>,
+  mut query: Query<(
+    Entity,
+    &mut Chunk,
+    Option<&world::TerrainWorld>,
+    Option<&lod::LodBucket>,
+  )>,
+  unbucketed: Query<Option<&world::TerrainWorld>, (With<Chunk>, Without<lod::LodBucket>)>,
+  mut site_query: Query<(Entity, Option<&world::TerrainWorld>, &mut ChunkSpawner)>,
+  mut lod_changed: EventWriter<lod::LodChanged>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+  // spawners reported as not loaded, they're reported again only after they've loaded
+  mut not_loaded: Local<HashSet<Entity>>,
 ) {
-  let mut fresh_sites = site_query
-    .iter_mut()
-    .filter(|site| site.fresh)
-    .collect::<Vec<_>>();
-  if fresh_sites.len() == 0 {
+  let mut refresh = false;
+  let mut sites = Vec::new();
+  for (entity, site_world, mut site) in site_query.iter_mut() {
+    refresh |= site.fresh;
+    match site.last_loaded_chunk {
+      Some(site_chunk) => {
+        not_loaded.remove(&entity);
+        sites.push((site_world.copied().unwrap_or_default(), site_chunk));
+      }
+      None if site.fresh && not_loaded.insert(entity) => errors.send(TerrainErrorEvent::new(
+        TerrainError::SpawnerNotLoaded(entity),
+        "calculating chunk distances",
+      )),
+      None => {}
+    }
+    if site.fresh {
+      site.fresh = false;
+    }
+  }
+  // chunks of worlds without spawners can't be bucketed, they don't count
+  refresh |= unbucketed.iter().any(|chunk_world| {
+    let chunk_world = chunk_world.copied().unwrap_or_default();
+    sites
+      .iter()
+      .any(|(site_world, _)| *site_world == chunk_world)
+  });
+  if !refresh {
     return;
   }
 
-  // compute chunk distances (for LODs and despawning)
-  for mut chunk in query.iter_mut() {
-    let mut min_distance = std::f32::MAX;
-    for site in fresh_sites.iter_mut() {
-      site.fresh = false;
+  let (changed_tx, changed_rx) = crossbeam_channel::unbounded();
+  query.par_for_each_mut(
+    &pool,
+    DISTANCE_BATCH_SIZE,
+    |(entity, mut chunk, chunk_world, bucket)| {
+      // spawners are only near the chunks of their own world
+      let chunk_world = chunk_world.copied().unwrap_or_default();
+      let nearest = sites
+        .iter()
+        .filter(|(site_world, _)| *site_world == chunk_world)
+        .map(|(_, site_chunk)| {
+          (
+            layout.world_distance(&chunk.id, site_chunk),
+            layout.chunk_step_distance(&chunk.id, site_chunk),
+          )
+        })
+        .reduce(|(distance, steps), (other_distance, other_steps)| {
+          (distance.min(other_distance), steps.min(other_steps))
+        });
+      let (distance, steps) = match nearest {
+        Some(nearest) => nearest,
+        None => return,
+      };
+      if chunk.distance_to_nearest_spawner != distance || chunk.steps_to_nearest_spawner != steps {
+        chunk.distance_to_nearest_spawner = distance;
+        chunk.steps_to_nearest_spawner = steps;
+      }
 
-      min_distance = layout
-        .get_chunk_distance(
-          &chunk.id,
-          &site
-            .last_loaded_chunk
-            .expect("a fresh site should have a loaded chunk"),
-        )
-        .min(min_distance);
-      chunk.distance_to_nearest_spawner = min_distance;
-    }
+      let new_bucket = lod::LodBucket(lod_settings.lod(steps, 0.));
+      if bucket != Some(&new_bucket) {
+        changed_tx
+          .send(lod::LodChanged {
+            entity,
+            chunk: chunk.id,
+            previous: bucket.copied(),
+            bucket: new_bucket,
+          })
+          .unwrap();
+      }
+    },
+  );
+  drop(changed_tx);
+
+  for changed in changed_rx.try_iter() {
+    commands.entity(changed.entity).insert(changed.bucket);
+    lod_changed.send(changed);
   }
 }
 
-pub fn load_voxels(
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn build_chunk_mesh(
   mut commands: Commands,
-  mut tasks: Query<(Entity, &Chunk, &mut Task<ChunkVoxelData>)>,
+  thread_pool: Res<AsyncComputeTaskPool>,
+  batch_pool: Res<pipeline::ChunkBatchPool>,
+  layout: Res<layout::CubicVoxelLayout>,
+  registry: Res<registry::VoxelRegistry>,
+  pipeline: Res<pipeline::ChunkPipeline>,
+  lod_settings: Res<lod::ChunkLodSettings>,
+  visibility_settings: Res<visibility::ChunkVisibilitySettings>,
+  normal_mode: Res<mesher::NormalMode>,
+  tangents: Res<mesher::GenerateTangents>,
+  tracker: Res<tracker::ChunkTracker>,
+  worlds: Res<world::TerrainWorlds>,
+  meta: Res<meta::VoxelMeta>,
+  neighbors: Query<&ChunkVoxelData>,
+  views: Query<(&Transform, Option<&world::TerrainWorld>), (With<ChunkSpawner>, With<Frustum>)>,
+  query: Query<
+    (
+      Entity,
+      &Chunk,
+      &ChunkVoxelData,
+      &light::ChunkLight,
+      Option<&lod::ChunkLod>,
+      Option<&visibility::OutsideView>,
+      Option<&world::TerrainWorld>,
+    ),
+    (
+      Without<pipeline::MeshPending>,
+      Without<far_chunks::FarChunk>,
+      Without<lod::DataOnlyChunk>,
+      Without<visibility::EnclosedChunk>,
+      Without<visibility::EmptyChunk>,
+      Or<(Without<Handle<Mesh>>, With<DirtyChunk>)>,
+    ),
+  >,
 ) {
-  // check if voxel data load task is complete
-  for (entity, chunk, mut task) in tasks.iter_mut() {
-    if let Some(voxel_data) = future::block_on(future::poll_once(&mut *task)) {
-      info!("voxels loaded for {:?}", chunk.id);
-      // Add our new PbrBundle of components to our tagged entity
+  let view_distance = |chunk: &Chunk, world: Option<&world::TerrainWorld>| {
+    let world = world.copied().unwrap_or_default();
+    views
+      .iter()
+      .filter(|(_, view_world)| view_world.copied().unwrap_or_default() == world)
+      .map(|(view, _)| {
+        let origin = layout.chunk_to_space_near(&chunk.id, &view.translation);
+        let center = visibility::chunk_bounding_sphere_at(&layout, origin).center;
+        lod::off_view_center(view, center.into())
+      })
+      .reduce(f32::min)
+      // spawners without a camera stand in for the view
+      .unwrap_or(chunk.distance_to_nearest_spawner)
+  };
+  // chunks in view are meshed first, then full detail chunks nearest the center of the view
+  let mut candidates: Vec<_> = query
+    .iter()
+    .map(|candidate| (view_distance(candidate.1, candidate.6), candidate))
+    .collect();
+  candidates.sort_by(
+    |(a_view, (_, _, _, _, a_lod, a_outside, _)), (b_view, (_, _, _, _, b_lod, b_outside, _))| {
+      let a_lod = a_lod.copied().unwrap_or_default();
+      let b_lod = b_lod.copied().unwrap_or_default();
+      a_outside
+        .is_some()
+        .cmp(&b_outside.is_some())
+        .then(a_lod.0.cmp(&b_lod.0))
+        .then(a_view.total_cmp(b_view))
+    },
+  );
+
+  for (_, (entity, chunk, voxel_data, light, lod, _, world)) in candidates
+    .into_iter()
+    .take(lod_settings.mesh_submissions_per_frame)
+  {
+    let tracker = worlds.tracker(&world.copied().unwrap_or_default(), &tracker);
+    // caves never reach into chunks walled in by solid chunks, they stay unmeshed until dug into
+    let enclosed = visibility_settings.cull_enclosed
+      && visibility::is_walled_in(&layout, &chunk.id, |neighbor| {
+        let entity = tracker?.entity(neighbor)?;
+        let voxel_data = neighbors.get(entity).ok()?;
+        Some(visibility::is_opaque_chunk(voxel_data, &registry))
+      })
+      && visibility::is_opaque_chunk(voxel_data, &registry);
+    if enclosed {
       commands
         .entity(entity)
-        .insert(voxel_data)
-        .remove::<Task<ChunkVoxelData>>();
+        .insert(visibility::EnclosedChunk)
+        .remove::<DirtyChunk>()
+        .remove::<Handle<Mesh>>();
+      continue;
     }
+
+    let lod = lod.copied().unwrap_or_default();
+    // decals are painted on the primary world only
+    let chunk_decals = match world.copied().unwrap_or_default().is_primary() {
+      true => meta.decals(&chunk.id),
+      false => None,
+    };
+    // inline jobs mesh a slab per step so they can stop in between
+    let gen_mesh_job = match pipeline.mode().is_synchronous() {
+      true => mesher::generate_mesh_steps(
+        &layout,
+        &registry,
+        chunk.id,
+        &voxel_data.voxels,
+        light,
+        lod.0,
+        chunk_decals,
+        *normal_mode,
+        *tangents,
+      ),
+      false => generator::ChunkStep::job(mesher::generate_mesh(
+        &batch_pool.0,
+        &layout,
+        &registry,
+        chunk.id,
+        &voxel_data.voxels,
+        light,
+        lod.0,
+        chunk_decals,
+        *normal_mode,
+        *tangents,
+      )),
+    };
+    info!("generating mesh for {:?}", chunk.id);
+    pipeline.submit_mesh_steps(&thread_pool, entity, gen_mesh_job);
+
+    commands
+      .entity(entity)
+      .insert(pipeline::MeshPending)
+      .remove::<DirtyChunk>();
   }
 }
 
-pub fn build_chunk_mesh(
+// also when tangents are turned on or off
+#[cfg(feature = "render")]
+pub fn remesh_on_normal_mode_change(
   mut commands: Commands,
-  thread_pool: Res<AsyncComputeTaskPool>,
-  query: Query<(Entity, &Chunk, &ChunkVoxelData), Without<Handle<Mesh>>>,
+  normal_mode: Res<mesher::NormalMode>,
+  tangents: Res<mesher::GenerateTangents>,
+  chunks: Query<Entity, (With<Chunk>, With<Handle<Mesh>>)>,
 ) {
-  for (entity, chunk, voxel_data) in query.iter() {
-    let gen_mesh_task = mesher::generate_mesh(&thread_pool, &voxel_data.voxels, 0);
-    info!("generating mesh for {:?}", chunk.id);
-
-    commands.entity(entity).insert(gen_mesh_task);
+  let changed = |changed: bool, added: bool| changed && !added;
+  if !changed(normal_mode.is_changed(), normal_mode.is_added())
+    && !changed(tangents.is_changed(), tangents.is_added())
+  {
+    return;
+  }
+  for entity in chunks.iter() {
+    commands.entity(entity).insert(DirtyChunk);
   }
 }
 
-pub fn attach_chunk_mesh(
-  layout: Res<layout::CubicVoxelLayout>,
+// edits of up to this many voxels in a chunk patch its mesh, larger ones remesh the chunk
+#[cfg(feature = "render")]
+const MAX_PATCHED_VOXELS: usize = 64;
+
+// small edits are applied to the chunk's mesh right away so digging feels responsive
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn patch_edited_meshes(
   mut commands: Commands,
+  layout: Res<layout::CubicVoxelLayout>,
+  registry: Res<registry::VoxelRegistry>,
+  normal_mode: Res<mesher::NormalMode>,
+  tangents: Res<mesher::GenerateTangents>,
+  meta: Res<meta::VoxelMeta>,
   mut meshes: ResMut<Assets<Mesh>>,
-  mut materials: ResMut<Assets<StandardMaterial>>,
-  mut tasks: Query<(Entity, &Chunk, &mut Task<Mesh>), Without<Handle<Mesh>>>,
+  mut query: Query<(
+    Entity,
+    &Chunk,
+    &ChunkVoxelData,
+    &mut light::ChunkLight,
+    Option<&mut edit::EditedVoxels>,
+    Option<&Handle<Mesh>>,
+    Option<&mut mesher::MeshFaceIndex>,
+    Option<&pipeline::MeshPending>,
+    Option<&lod::ChunkLod>,
+    Option<&world::TerrainWorld>,
+  )>,
 ) {
-  for (entity, chunk, mut task) in tasks.iter_mut() {
-    if let Some(mesh) = future::block_on(future::poll_once(&mut *task)) {
-      info!("generated mesh for {:?}", chunk.id);
-
-      commands.entity(entity).insert_bundle(PbrBundle {
-        mesh: meshes.add(mesh),
-        material: materials.add(Color::rgb(0.5, 0.0, 0.3).into()),
-        transform: Transform::from_translation(layout.chunk_to_space(&chunk.id)),
-        ..default()
-      });
+  for (entity, chunk, voxel_data, mut light, edited, mesh, index, pending, lod, world) in
+    query.iter_mut()
+  {
+    // light spreading in from a neighbor changes the mesh without an edit
+    let changed = edited
+      .filter(|edited| !edited.0.is_empty())
+      .map(|mut edited| std::mem::take(&mut edited.0))
+      .unwrap_or_default();
+    if changed.is_empty() && light.relit().is_empty() {
+      continue;
+    }
+
+    // a mesh task in flight was started before the edit, the chunk is meshed again once it's done
+    // lower detail meshes aren't made of voxel faces, they're always meshed again
+    let full_detail = !matches!(lod, Some(lod) if lod.0 > 0);
+    let meshed = mesh.is_some() || pending.is_some();
+    let mesh = match (mesh, pending) {
+      (Some(handle), None) if full_detail && changed.len() <= MAX_PATCHED_VOXELS => {
+        meshes.get_mut(handle)
+      }
+      _ => None,
+    };
+    let patched = match mesh {
+      Some(mesh) => {
+        // the index is read from the mesh once, later patches keep it up to date
+        let mut built = None;
+        let index = match index {
+          Some(index) => Some(index.into_inner()),
+          None => {
+            built = mesher::MeshFaceIndex::of(mesh, &layout, &chunk.id);
+            built.as_mut()
+          }
+        };
+        // decals are painted on the primary world only
+        let decals = match world.copied().unwrap_or_default().is_primary() {
+          true => meta.decals(&chunk.id),
+          false => None,
+        };
+        let patched = match index {
+          Some(index) => mesher::patch_mesh(
+            mesh,
+            index,
+            &layout,
+            &registry,
+            &chunk.id,
+            &voxel_data.voxels,
+            &light,
+            &changed,
+            decals,
+            *normal_mode,
+            *tangents,
+          ),
+          None => false,
+        };
+        if let (true, Some(built)) = (patched, built) {
+          commands.entity(entity).insert(built);
+        }
+        patched
+      }
+      None => false,
+    };
+    // a full remesh shades every face with the current light
+    light.clear_relit();
+    if !patched && (meshed || !changed.is_empty()) {
+      commands.entity(entity).insert(DirtyChunk);
     }
   }
 }
 
+// whether a spawner of the chunk's world keeps it loaded
+// chunks are despawned a few rings farther out than they're spawned so that moving back and
+// forth over a chunk border doesn't reload the chunks at the edge
+// chunks preloaded ahead of a spawner are kept while it's still headed or looking their way
+#[cfg(feature = "render")]
+fn in_spawner_range<'a>(
+  layout: &layout::CubicVoxelLayout,
+  policy: &retention::ChunkRetentionPolicy,
+  spawner_config: &prediction::ChunkSpawnerConfig,
+  mut sites: impl Iterator<Item = (&'a ChunkSpawner, Option<&'a world::TerrainWorld>)>,
+  chunk: &ChunkId,
+  world: world::TerrainWorld,
+) -> bool {
+  let sections_within = |center: &ChunkId| {
+    (chunk.y() - center.y()).abs() <= spawner_config.vertical_radius + policy.despawn_ring_margin
+  };
+  let within = |center: &ChunkId, radius: i64| {
+    layout.chunk_step_distance(chunk, center) <= radius + policy.despawn_ring_margin
+      && sections_within(center)
+  };
+  sites.any(|(site, site_world)| {
+    if site_world.copied().unwrap_or_default() != world {
+      return false;
+    }
+    let in_shape = |center: &ChunkId| {
+      // the shorter way around in a wrapping world, so chunks across the seam stay
+      let (x, z) = layout.column_offset(center, chunk);
+      site.load_shape.contains(
+        x,
+        z,
+        site.spawn_radius + policy.despawn_ring_margin,
+        site.load_heading,
+      ) && sections_within(center)
+    };
+    matches!(site.last_loaded_chunk, Some(center) if in_shape(&center))
+      || site
+        .predicted_path
+        .iter()
+        .any(|ahead| within(ahead, spawner_config.prediction_radius))
+      || site
+        .view_path
+        .iter()
+        .any(|in_view| within(in_view, spawner_config.view_radius))
+  })
+}
+
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn despawn_chunks(
   mut commands: Commands,
+  time: Res<Time>,
+  layout: Res<layout::CubicVoxelLayout>,
+  policy: Res<retention::ChunkRetentionPolicy>,
+  spawner_config: Res<prediction::ChunkSpawnerConfig>,
+  mut tracker: ResMut<tracker::ChunkTracker>,
+  streaming: Res<streaming::TerrainStreaming>,
+  mut worlds: ResMut<world::TerrainWorlds>,
+  mut despawning: EventWriter<despawn::ChunkDespawning>,
+  sites: Query<(&ChunkSpawner, Option<&world::TerrainWorld>)>,
+  mut qry: Query<
+    (Entity, &mut Chunk, Option<&world::TerrainWorld>),
+    Without<despawn::DespawningChunk>,
+  >,
+) {
+  if streaming.is_paused() {
+    return;
+  }
+  let grace_seconds = policy.grace_seconds(sites.iter().map(|(site, _)| site));
+
+  for (entity, mut chunk, world) in qry.iter_mut() {
+    let world = world.copied().unwrap_or_default();
+    let tracker = match worlds.tracker_mut(&world, &mut tracker) {
+      Some(tracker) => tracker,
+      // the world was removed
+      None => {
+        commands.entity(entity).despawn_recursive();
+        continue;
+      }
+    };
+
+    let in_range = in_spawner_range(
+      &layout,
+      &policy,
+      &spawner_config,
+      sites.iter(),
+      &chunk.id,
+      world,
+    );
+    if in_range || tracker.is_pinned(&chunk.id) || tracker.is_required(&chunk.id) {
+      if chunk.out_of_range_seconds != 0. {
+        chunk.out_of_range_seconds = 0.;
+      }
+      continue;
+    }
+
+    chunk.out_of_range_seconds += time.delta_seconds();
+    if chunk.out_of_range_seconds >= grace_seconds
+      && tracker.can_despawn(
+        &chunk.id,
+        time.seconds_since_startup(),
+        policy.min_resident_seconds,
+      )
+    {
+      // games get a frame to save what they attached to the chunk, `finish_chunk_despawns`
+      // despawns it after that
+      let (marker, event) = despawn::DespawningChunk::announce(entity, chunk.id, world);
+      commands.entity(entity).insert(marker);
+      despawning.send(event);
+    }
+  }
+}
+
+// once a spawner teleports, the chunks still generating around where it was are despawned
+// right away and their jobs are cancelled, instead of finishing and then waiting out the grace
+// period, so the new location gets the workers
+#[cfg(feature = "render")]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn cancel_teleported_loads(
+  mut commands: Commands,
+  layout: Res<layout::CubicVoxelLayout>,
+  policy: Res<retention::ChunkRetentionPolicy>,
+  spawner_config: Res<prediction::ChunkSpawnerConfig>,
+  pipeline: Res<pipeline::ChunkPipeline>,
   mut tracker: ResMut<tracker::ChunkTracker>,
-  qry: Query<(Entity, &Chunk)>,
+  mut worlds: ResMut<world::TerrainWorlds>,
+  sites: Query<(&ChunkSpawner, Option<&world::TerrainWorld>)>,
+  generating: Query<(Entity, &Chunk, Option<&world::TerrainWorld>), Without<ChunkVoxelData>>,
 ) {
-  for (entity, chunk) in qry.iter() {
-    // TODO: figure out proper criteria for despawning
-    if chunk.distance_to_nearest_spawner > 10000.0 && tracker.try_despawn(&chunk.id) {
+  let teleported: HashSet<_> = sites
+    .iter()
+    .filter(|(site, _)| site.teleported)
+    .map(|(_, world)| world.copied().unwrap_or_default())
+    .collect();
+  if teleported.is_empty() {
+    return;
+  }
+
+  let mut cancelled = 0;
+  for (entity, chunk, world) in generating.iter() {
+    let world = world.copied().unwrap_or_default();
+    if !teleported.contains(&world) {
+      continue;
+    }
+    // teleported spawners have no predicted path, only the chunks around spawners and along
+    // their view count
+    let in_range = sites.iter().any(|(site, site_world)| {
+      let within = |center: &ChunkId, radius: i64| {
+        layout.chunk_step_distance(&chunk.id, center) <= radius + policy.despawn_ring_margin
+          && (chunk.id.y() - center.y()).abs()
+            <= spawner_config.vertical_radius + policy.despawn_ring_margin
+      };
+      site_world.copied().unwrap_or_default() == world
+        && (matches!(site.last_loaded_chunk, Some(center) if within(&center, site.spawn_radius))
+          || site
+            .view_path
+            .iter()
+            .any(|in_view| within(in_view, spawner_config.view_radius)))
+    });
+    let tracker = match worlds.tracker_mut(&world, &mut tracker) {
+      Some(tracker) => tracker,
+      None => continue,
+    };
+    if in_range || tracker.is_pinned(&chunk.id) || tracker.is_required(&chunk.id) {
+      continue;
+    }
+    // the minimum residency is for chunks that made it, these never did
+    if tracker.try_despawn(&chunk.id, 0., 0.) {
+      pipeline.cancel(entity);
       commands.entity(entity).despawn_recursive();
+      cancelled += 1;
     }
   }
+  if cancelled > 0 {
+    info!("cancelled {} chunks left behind by a teleport", cancelled);
+  }
+}
+
+// colors come from the `VoxelRegistry` and `TerrainMaterialConfig`, so all chunks share the one
+// material
+#[cfg(feature = "render")]
+fn chunk_material() -> Handle<material::TerrainMaterial> {
+  material::TERRAIN_MATERIAL_HANDLE.typed()
 }
@@ -0,0 +1,565 @@
+use super::{
+  edit::{TerrainEdits, VoxelChanged, VoxelEdit},
+  registry::{VoxelRegistry, VoxelTypeId},
+  snapshot::{ChunkDiffs, Reader, SnapshotError},
+  terrain_set, ChunkId, TerrainSystem, VoxelId,
+};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+const MESSAGE_MAGIC: &[u8; 4] = b"VXNT";
+const MESSAGE_VERSION: u8 = 1;
+
+const TAG_CHUNK_SNAPSHOT: u8 = 0;
+const TAG_CHUNK_DELTA: u8 = 1;
+const TAG_REQUEST_CHUNK: u8 = 2;
+const TAG_EDIT: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncRole {
+  // owns the terrain, applies edits and broadcasts what changed
+  Server,
+  // mirrors the server's terrain and asks it for edits
+  Client,
+}
+
+// what a server and its clients exchange to keep their terrain in sync, the transport is up to the
+// game, messages are sent as the bytes of `to_bytes`
+// chunks only carry the voxels that deviate from the generator, clients generate the rest locally
+// from the config of a `WorldSnapshot`
+#[derive(Debug, Clone, PartialEq)]
+pub enum TerrainMessage {
+  // every deviation of a chunk as of `revision`, sent in reply to `RequestChunk`
+  ChunkSnapshot {
+    chunk: ChunkId,
+    revision: u32,
+    voxels: Vec<(VoxelId, VoxelTypeId)>,
+  },
+  // voxels changed by the edits that took the chunk from `revision - 1` to `revision`
+  ChunkDelta {
+    chunk: ChunkId,
+    revision: u32,
+    changes: Vec<(VoxelId, VoxelTypeId)>,
+  },
+  // sent by clients that missed a delta of the chunk
+  RequestChunk(ChunkId),
+  // an edit a client wants the server to apply
+  Edit(VoxelEdit),
+}
+
+impl TerrainMessage {
+  // voxel types are sent as ids, both ends need to register the same types in the same order
+  pub fn to_bytes(&self, registry: &VoxelRegistry) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MESSAGE_MAGIC);
+    bytes.push(MESSAGE_VERSION);
+    bytes.extend_from_slice(&registry_fingerprint(registry).to_le_bytes());
+    match self {
+      TerrainMessage::ChunkSnapshot {
+        chunk,
+        revision,
+        voxels,
+      } => {
+        bytes.push(TAG_CHUNK_SNAPSHOT);
+        write_chunk(&mut bytes, chunk);
+        bytes.extend_from_slice(&revision.to_le_bytes());
+        write_voxels(&mut bytes, voxels);
+      }
+      TerrainMessage::ChunkDelta {
+        chunk,
+        revision,
+        changes,
+      } => {
+        bytes.push(TAG_CHUNK_DELTA);
+        write_chunk(&mut bytes, chunk);
+        bytes.extend_from_slice(&revision.to_le_bytes());
+        write_voxels(&mut bytes, changes);
+      }
+      TerrainMessage::RequestChunk(chunk) => {
+        bytes.push(TAG_REQUEST_CHUNK);
+        write_chunk(&mut bytes, chunk);
+      }
+      TerrainMessage::Edit(edit) => {
+        bytes.push(TAG_EDIT);
+        write_edit(&mut bytes, edit);
+      }
+    }
+    bytes
+  }
+
+  pub fn from_bytes(bytes: &[u8], registry: &VoxelRegistry) -> Result<Self, SnapshotError> {
+    let mut reader = Reader(bytes);
+    if reader.take(4)? != MESSAGE_MAGIC {
+      return Err(SnapshotError::BadMagic);
+    }
+    let version = reader.u8()?;
+    if version != MESSAGE_VERSION {
+      return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    if reader.u64()? != registry_fingerprint(registry) {
+      return Err(SnapshotError::RegistryMismatch);
+    }
+    let tag = reader.u8()?;
+    let message = match tag {
+      TAG_CHUNK_SNAPSHOT => TerrainMessage::ChunkSnapshot {
+        chunk: read_chunk(&mut reader)?,
+        revision: reader.u32()?,
+        voxels: read_voxels(&mut reader, registry)?,
+      },
+      TAG_CHUNK_DELTA => TerrainMessage::ChunkDelta {
+        chunk: read_chunk(&mut reader)?,
+        revision: reader.u32()?,
+        changes: read_voxels(&mut reader, registry)?,
+      },
+      TAG_REQUEST_CHUNK => TerrainMessage::RequestChunk(read_chunk(&mut reader)?),
+      TAG_EDIT => TerrainMessage::Edit(read_edit(&mut reader, registry)?),
+      _ => return Err(SnapshotError::UnknownMessage(tag)),
+    };
+    Ok(message)
+  }
+}
+
+// fnv-1a over the registered names, messages from a differently registered world are rejected
+// instead of silently swapping voxel types
+fn registry_fingerprint(registry: &VoxelRegistry) -> u64 {
+  let mut hash = 0xcbf29ce484222325u64;
+  for (_, info) in registry.iter() {
+    for byte in info.name.bytes().chain(std::iter::once(0)) {
+      hash ^= byte as u64;
+      hash = hash.wrapping_mul(0x100000001b3);
+    }
+  }
+  hash
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk: &ChunkId) {
+  bytes.extend_from_slice(&chunk.x().to_le_bytes());
+  bytes.extend_from_slice(&chunk.y().to_le_bytes());
+  bytes.extend_from_slice(&chunk.z().to_le_bytes());
+}
+
+fn read_chunk(reader: &mut Reader<'_>) -> Result<ChunkId, SnapshotError> {
+  Ok(ChunkId::new(reader.i64()?, reader.i64()?, reader.i64()?))
+}
+
+fn write_voxel(bytes: &mut Vec<u8>, voxel: &VoxelId, voxel_type: &VoxelTypeId) {
+  bytes.extend_from_slice(&voxel.x().to_le_bytes());
+  bytes.extend_from_slice(&voxel.y().to_le_bytes());
+  bytes.extend_from_slice(&voxel.z().to_le_bytes());
+  bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+}
+
+fn write_voxels(bytes: &mut Vec<u8>, voxels: &[(VoxelId, VoxelTypeId)]) {
+  bytes.extend_from_slice(&(voxels.len() as u32).to_le_bytes());
+  for (voxel, voxel_type) in voxels {
+    write_voxel(bytes, voxel, voxel_type);
+  }
+}
+
+fn read_voxel_type(
+  reader: &mut Reader<'_>,
+  registry: &VoxelRegistry,
+) -> Result<VoxelTypeId, SnapshotError> {
+  let id = reader.u16()?;
+  match registry.get(VoxelTypeId(id)) {
+    Some(_) => Ok(VoxelTypeId(id)),
+    None => Err(SnapshotError::UnknownVoxelType(id)),
+  }
+}
+
+fn read_voxels(
+  reader: &mut Reader<'_>,
+  registry: &VoxelRegistry,
+) -> Result<Vec<(VoxelId, VoxelTypeId)>, SnapshotError> {
+  let mut voxels = Vec::new();
+  for _ in 0..reader.u32()? {
+    voxels.push(read_voxel(reader, registry)?);
+  }
+  Ok(voxels)
+}
+
+fn read_voxel(
+  reader: &mut Reader<'_>,
+  registry: &VoxelRegistry,
+) -> Result<(VoxelId, VoxelTypeId), SnapshotError> {
+  let voxel = VoxelId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+  Ok((voxel, read_voxel_type(reader, registry)?))
+}
+
+fn write_vec3(bytes: &mut Vec<u8>, v: Vec3) {
+  for value in v.to_array() {
+    bytes.extend_from_slice(&value.to_le_bytes());
+  }
+}
+
+fn read_vec3(reader: &mut Reader<'_>) -> Result<Vec3, SnapshotError> {
+  Ok(Vec3::new(reader.f32()?, reader.f32()?, reader.f32()?))
+}
+
+fn write_edit(bytes: &mut Vec<u8>, edit: &VoxelEdit) {
+  match edit {
+    VoxelEdit::Set(voxel, voxel_type) => {
+      bytes.push(0);
+      write_voxel(bytes, voxel, voxel_type);
+    }
+    VoxelEdit::CarveSphere { center, radius } => {
+      bytes.push(1);
+      write_vec3(bytes, *center);
+      bytes.extend_from_slice(&radius.to_le_bytes());
+    }
+    VoxelEdit::FillBox {
+      min,
+      max,
+      voxel_type,
+    } => {
+      bytes.push(2);
+      write_vec3(bytes, *min);
+      write_vec3(bytes, *max);
+      bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+    }
+    VoxelEdit::PaintSurface {
+      center,
+      radius,
+      voxel_type,
+    } => {
+      bytes.push(3);
+      write_vec3(bytes, *center);
+      bytes.extend_from_slice(&radius.to_le_bytes());
+      bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+    }
+    VoxelEdit::FillSphere {
+      center,
+      radius,
+      voxel_type,
+    } => {
+      bytes.push(4);
+      write_vec3(bytes, *center);
+      bytes.extend_from_slice(&radius.to_le_bytes());
+      bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+    }
+    VoxelEdit::Flatten {
+      center,
+      radius,
+      height,
+      voxel_type,
+    } => {
+      bytes.push(5);
+      write_vec3(bytes, *center);
+      bytes.extend_from_slice(&radius.to_le_bytes());
+      bytes.extend_from_slice(&height.to_le_bytes());
+      bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+    }
+  }
+}
+
+fn read_edit(
+  reader: &mut Reader<'_>,
+  registry: &VoxelRegistry,
+) -> Result<VoxelEdit, SnapshotError> {
+  let tag = reader.u8()?;
+  let edit = match tag {
+    0 => {
+      let (voxel, voxel_type) = read_voxel(reader, registry)?;
+      VoxelEdit::Set(voxel, voxel_type)
+    }
+    1 => VoxelEdit::CarveSphere {
+      center: read_vec3(reader)?,
+      radius: reader.f32()?,
+    },
+    2 => VoxelEdit::FillBox {
+      min: read_vec3(reader)?,
+      max: read_vec3(reader)?,
+      voxel_type: read_voxel_type(reader, registry)?,
+    },
+    3 => VoxelEdit::PaintSurface {
+      center: read_vec3(reader)?,
+      radius: reader.f32()?,
+      voxel_type: read_voxel_type(reader, registry)?,
+    },
+    4 => VoxelEdit::FillSphere {
+      center: read_vec3(reader)?,
+      radius: reader.f32()?,
+      voxel_type: read_voxel_type(reader, registry)?,
+    },
+    5 => VoxelEdit::Flatten {
+      center: read_vec3(reader)?,
+      radius: reader.f32()?,
+      height: reader.f32()?,
+      voxel_type: read_voxel_type(reader, registry)?,
+    },
+    _ => return Err(SnapshotError::UnknownMessage(tag)),
+  };
+  Ok(edit)
+}
+
+// the sync state of one end of the connection, feed it the messages received from the other end
+// with `receive` and send whatever `drain_outgoing` returns
+// servers broadcast their outgoing messages to every client, clients send theirs to the server
+pub struct TerrainSync {
+  role: SyncRole,
+  // edits applied to each chunk, chunks that were never edited are at revision 0
+  revisions: HashMap<ChunkId, u32>,
+  // on servers, every voxel sent in a delta, what a client needs to catch up on a chunk
+  sent: ChunkDiffs,
+  // on clients, chunks waiting for a snapshot after a missed delta
+  requested: HashSet<ChunkId>,
+  incoming: Vec<TerrainMessage>,
+  outgoing: Vec<TerrainMessage>,
+}
+
+impl TerrainSync {
+  pub fn new(role: SyncRole) -> Self {
+    Self {
+      role,
+      revisions: HashMap::new(),
+      sent: ChunkDiffs::default(),
+      requested: HashSet::new(),
+      incoming: Vec::new(),
+      outgoing: Vec::new(),
+    }
+  }
+
+  pub fn role(&self) -> SyncRole {
+    self.role
+  }
+
+  pub fn revision(&self, chunk: &ChunkId) -> u32 {
+    self.revisions.get(chunk).copied().unwrap_or(0)
+  }
+
+  // queues a message for `apply_terrain_messages`, requests for chunk snapshots are answered
+  // right away with the message to send back to whoever asked
+  pub fn receive(&mut self, message: TerrainMessage) -> Option<TerrainMessage> {
+    match (self.role, message) {
+      (SyncRole::Server, TerrainMessage::RequestChunk(chunk)) => Some(self.chunk_snapshot(&chunk)),
+      (_, message) => {
+        self.incoming.push(message);
+        None
+      }
+    }
+  }
+
+  // clients don't edit their terrain directly, the edit comes back as a delta once the server
+  // applied it
+  pub fn request_edit(&mut self, edit: VoxelEdit) {
+    self.outgoing.push(TerrainMessage::Edit(edit));
+  }
+
+  pub fn drain_outgoing(&mut self) -> impl Iterator<Item = TerrainMessage> + '_ {
+    self.outgoing.drain(..)
+  }
+
+  // the deviations of a chunk as of its current revision
+  pub fn chunk_snapshot(&self, chunk: &ChunkId) -> TerrainMessage {
+    let mut voxels: Vec<_> = self
+      .sent
+      .get(chunk)
+      .map(|voxels| voxels.iter().map(|(v, t)| (*v, *t)).collect())
+      .unwrap_or_default();
+    voxels.sort_by_key(|(voxel, _)| (voxel.x(), voxel.y(), voxel.z()));
+    TerrainMessage::ChunkSnapshot {
+      chunk: *chunk,
+      revision: self.revision(chunk),
+      voxels,
+    }
+  }
+}
+
+// replicates terrain edits between a server and its clients over the game's own transport
+pub struct TerrainSyncPlugin {
+  pub role: SyncRole,
+}
+
+impl Plugin for TerrainSyncPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .insert_resource(TerrainSync::new(self.role))
+      .add_system_set(terrain_set(TerrainSystem::Prepare).with_system(apply_terrain_messages))
+      .add_system_set(
+        terrain_set(TerrainSystem::Track)
+          .after(TerrainSystem::Apply)
+          .with_system(collect_terrain_deltas),
+      );
+  }
+}
+
+// servers queue the edits clients asked for, clients apply the snapshots and deltas of the server
+pub fn apply_terrain_messages(
+  mut sync: ResMut<TerrainSync>,
+  mut edits: ResMut<TerrainEdits>,
+  mut diffs: ResMut<ChunkDiffs>,
+) {
+  let sync = &mut *sync;
+  for message in std::mem::take(&mut sync.incoming) {
+    let (chunk, revision, voxels) = match (sync.role, message) {
+      (SyncRole::Server, TerrainMessage::Edit(edit)) => {
+        edits.push(edit);
+        continue;
+      }
+      (
+        SyncRole::Client,
+        TerrainMessage::ChunkSnapshot {
+          chunk,
+          revision,
+          voxels,
+        },
+      ) => {
+        sync.requested.remove(&chunk);
+        (chunk, revision, voxels)
+      }
+      (
+        SyncRole::Client,
+        TerrainMessage::ChunkDelta {
+          chunk,
+          revision,
+          changes,
+        },
+      ) => {
+        let current = sync.revision(&chunk);
+        if revision <= current || sync.requested.contains(&chunk) {
+          continue;
+        }
+        // a delta went missing, the snapshot brings the chunk up to date
+        if revision != current + 1 {
+          sync.requested.insert(chunk);
+          sync.outgoing.push(TerrainMessage::RequestChunk(chunk));
+          continue;
+        }
+        (chunk, revision, changes)
+      }
+      (role, message) => {
+        warn!("{:?} ignored terrain message {:?}", role, message);
+        continue;
+      }
+    };
+
+    // chunks that aren't loaded get the voxels from the diffs once they load
+    sync.revisions.insert(chunk, revision);
+    for (voxel, voxel_type) in voxels {
+      diffs.record(chunk, voxel, voxel_type);
+      edits.set_voxel(voxel, voxel_type);
+    }
+  }
+}
+
+// turns the voxels changed this frame into one delta per edited chunk
+pub fn collect_terrain_deltas(
+  mut sync: ResMut<TerrainSync>,
+  mut changed: EventReader<VoxelChanged>,
+) {
+  if sync.role != SyncRole::Server {
+    return;
+  }
+  // only the last change of a voxel matters
+  let mut chunks: HashMap<ChunkId, HashMap<VoxelId, VoxelTypeId>> = HashMap::new();
+  for change in changed.iter() {
+    chunks
+      .entry(change.chunk)
+      .or_default()
+      .insert(change.voxel, change.new);
+  }
+
+  let sync = &mut *sync;
+  for (chunk, voxels) in chunks {
+    let mut changes: Vec<_> = voxels.into_iter().collect();
+    changes.sort_by_key(|(voxel, _)| (voxel.x(), voxel.y(), voxel.z()));
+    for (voxel, voxel_type) in &changes {
+      sync.sent.record(chunk, *voxel, *voxel_type);
+    }
+    let revision = sync.revisions.entry(chunk).or_default();
+    *revision += 1;
+    sync.outgoing.push(TerrainMessage::ChunkDelta {
+      chunk,
+      revision: *revision,
+      changes,
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bevy::ecs::event::Events;
+  use proptest::prelude::*;
+
+  fn voxels() -> impl Strategy<Value = Vec<(VoxelId, VoxelTypeId)>> {
+    prop::collection::vec(
+      (-1000i64..1000, 0i64..256, -1000i64..1000, 0u16..3)
+        .prop_map(|(x, y, z, t)| (VoxelId::new(x, y, z), VoxelTypeId(t))),
+      0..50,
+    )
+  }
+
+  proptest! {
+      #[test]
+      fn messages_should_roundtrip(x in -100i64..100, y in 0i64..4, z in -100i64..100, revision in any::<u32>(), voxels in voxels(), radius in 0f32..10.) {
+          let registry = VoxelRegistry::default();
+          let chunk = ChunkId::new(x, y, z);
+          let messages = vec![
+              TerrainMessage::ChunkSnapshot { chunk, revision, voxels: voxels.clone() },
+              TerrainMessage::ChunkDelta { chunk, revision, changes: voxels },
+              TerrainMessage::RequestChunk(chunk),
+              TerrainMessage::Edit(VoxelEdit::Set(VoxelId::new(x, y, z), VoxelTypeId::DIRT)),
+              TerrainMessage::Edit(VoxelEdit::CarveSphere { center: Vec3::new(x as f32, 0., z as f32), radius }),
+              TerrainMessage::Edit(VoxelEdit::FillBox { min: Vec3::ZERO, max: Vec3::splat(radius), voxel_type: VoxelTypeId::LAMP }),
+              TerrainMessage::Edit(VoxelEdit::PaintSurface { center: Vec3::ONE, radius, voxel_type: VoxelTypeId::DIRT }),
+              TerrainMessage::Edit(VoxelEdit::FillSphere { center: Vec3::Y, radius, voxel_type: VoxelTypeId::LAMP }),
+              TerrainMessage::Edit(VoxelEdit::Flatten { center: Vec3::X, radius, height: 3., voxel_type: VoxelTypeId::DIRT }),
+          ];
+          for message in messages {
+              let bytes = message.to_bytes(&registry);
+              assert_eq!(TerrainMessage::from_bytes(&bytes, &registry), Ok(message));
+          }
+      }
+
+      #[test]
+      fn deltas_should_bring_clients_up_to_date(edits in prop::collection::vec(voxels(), 1..5), dropped in 0usize..4) {
+          let chunk = ChunkId::new(0, 0, 0);
+          let mut server = World::new();
+          server.insert_resource(TerrainSync::new(SyncRole::Server));
+          server.insert_resource(Events::<VoxelChanged>::default());
+          let mut client = World::new();
+          client.insert_resource(TerrainSync::new(SyncRole::Client));
+          client.insert_resource(ChunkDiffs::default());
+          client.insert_resource(TerrainEdits::default());
+          let mut server_stage = SystemStage::single_threaded().with_system(collect_terrain_deltas);
+          let mut client_stage = SystemStage::single_threaded().with_system(apply_terrain_messages);
+          let mut server_diffs = ChunkDiffs::default();
+
+          for (index, voxels) in edits.iter().enumerate() {
+              // every batch changes at least one voxel so it's sent as a delta
+              let mut changed = server.get_resource_mut::<Events<VoxelChanged>>().unwrap();
+              let fresh = (VoxelId::new(index as i64, 300, 0), VoxelTypeId::DIRT);
+              for (voxel, new) in voxels.iter().chain(std::iter::once(&fresh)) {
+                  server_diffs.record(chunk, *voxel, *new);
+                  changed.send(VoxelChanged { chunk, voxel: *voxel, old: VoxelTypeId::AIR, new: *new });
+              }
+              server_stage.run(&mut server);
+
+              let mut server_sync = server.get_resource_mut::<TerrainSync>().unwrap();
+              let mut client_sync = client.get_resource_mut::<TerrainSync>().unwrap();
+              for message in server_sync.drain_outgoing().collect::<Vec<_>>() {
+                  // a dropped delta is noticed with the next one, which makes the client ask for a
+                  // snapshot instead
+                  if index == dropped && index + 1 < edits.len() {
+                      continue;
+                  }
+                  client_sync.receive(message);
+              }
+              client_stage.run(&mut client);
+
+              let mut client_sync = client.get_resource_mut::<TerrainSync>().unwrap();
+              for request in client_sync.drain_outgoing().collect::<Vec<_>>() {
+                  let reply = server_sync.receive(request).unwrap();
+                  client_sync.receive(reply);
+              }
+          }
+          client_stage.run(&mut client);
+
+          let server_sync = server.get_resource::<TerrainSync>().unwrap();
+          let client_sync = client.get_resource::<TerrainSync>().unwrap();
+          assert_eq!(client_sync.revision(&chunk), server_sync.revision(&chunk));
+          assert_eq!(client.get_resource::<ChunkDiffs>().unwrap().get(&chunk), server_diffs.get(&chunk));
+      }
+  }
+}
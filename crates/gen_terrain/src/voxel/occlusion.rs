@@ -0,0 +1,223 @@
+use super::{
+  layout::CubicVoxelLayout, registry::VoxelRegistry, Chunk, ChunkId, ChunkVoxelData, VoxelId,
+};
+use bevy::prelude::*;
+
+// cells of the occlusion grid along each axis of a chunk
+pub const OCCLUSION_CELLS: usize = 4;
+
+// which chunks keep a `ChunkOcclusion`
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkOcclusionSettings {
+  // rings of chunks around the nearest spawner, chunks farther out drop their grid
+  pub radius: i64,
+}
+impl Default for ChunkOcclusionSettings {
+  fn default() -> Self {
+    Self { radius: 3 }
+  }
+}
+
+// a coarse grid of how solid the voxels of a chunk are, for audio propagation or rough line of
+// sight checks that shouldn't raycast every voxel, see `occlusion_between`
+// only opaque voxels count, sound and sight pass through water and glass
+// chunks near a spawner get one once their voxels arrive and it follows edits after that
+#[derive(Debug, Clone, PartialEq, Component)]
+pub struct ChunkOcclusion {
+  // the fraction of opaque voxels of each cell from 0 to 255, x first, then z, then y
+  cells: [u8; OCCLUSION_CELLS * OCCLUSION_CELLS * OCCLUSION_CELLS],
+}
+
+impl ChunkOcclusion {
+  pub fn from_voxels(
+    layout: &CubicVoxelLayout,
+    chunk: &ChunkId,
+    voxel_data: &ChunkVoxelData,
+    registry: &VoxelRegistry,
+  ) -> Self {
+    let mut solid = [0u32; OCCLUSION_CELLS * OCCLUSION_CELLS * OCCLUSION_CELLS];
+    let mut total = [0u32; OCCLUSION_CELLS * OCCLUSION_CELLS * OCCLUSION_CELLS];
+    for (voxel, voxel_type) in voxel_data.voxels.iter() {
+      let index = match Self::cell_of(layout, chunk, voxel) {
+        Some(cell) => Self::index(cell),
+        None => continue,
+      };
+      total[index] += 1;
+      if registry.is_opaque(*voxel_type) {
+        solid[index] += 1;
+      }
+    }
+    let mut cells = [0; OCCLUSION_CELLS * OCCLUSION_CELLS * OCCLUSION_CELLS];
+    for (cell, (solid, total)) in cells.iter_mut().zip(solid.iter().zip(total.iter())) {
+      *cell = (*solid * 255 + total / 2).checked_div(*total).unwrap_or(0) as u8;
+    }
+    Self { cells }
+  }
+
+  // solidity of a cell from 0 for only air (or water) to 1 for only opaque voxels
+  pub fn solidity(&self, (x, y, z): (usize, usize, usize)) -> f32 {
+    self.cells[Self::index((x, y, z))] as f32 / 255.
+  }
+
+  // solidity of the cell around a point, 0 outside the chunk
+  pub fn solidity_at(&self, layout: &CubicVoxelLayout, chunk: &ChunkId, point: &Vec3) -> f32 {
+    Self::cell_of(layout, chunk, &layout.space_to_voxel(point))
+      .map_or(0., |cell| self.solidity(cell))
+  }
+
+  // the mean solidity of the whole chunk
+  pub fn mean(&self) -> f32 {
+    self.cells.iter().map(|cell| *cell as f32).sum::<f32>() / (self.cells.len() as f32 * 255.)
+  }
+
+  // the cell a voxel of the chunk falls in, `None` for voxels of other chunks
+  pub fn cell_of(
+    layout: &CubicVoxelLayout,
+    chunk: &ChunkId,
+    voxel: &VoxelId,
+  ) -> Option<(usize, usize, usize)> {
+    let origin = layout.get_voxel(
+      chunk,
+      -layout.chunk_voxel_length(),
+      0,
+      -layout.chunk_voxel_length(),
+    );
+    let (length, height) = (
+      layout.chunk_voxel_full_length(),
+      layout.chunk_voxel_height().max(1),
+    );
+    let (x, y, z) = (
+      voxel.x() - origin.x(),
+      voxel.y() - origin.y(),
+      voxel.z() - origin.z(),
+    );
+    if !(0..length).contains(&x) || !(0..height).contains(&y) || !(0..length).contains(&z) {
+      return None;
+    }
+    let cells = OCCLUSION_CELLS as i64;
+    Some((
+      (x * cells / length) as usize,
+      (y * cells / height) as usize,
+      (z * cells / length) as usize,
+    ))
+  }
+
+  fn index((x, y, z): (usize, usize, usize)) -> usize {
+    x + z * OCCLUSION_CELLS + y * OCCLUSION_CELLS * OCCLUSION_CELLS
+  }
+}
+
+// how much solid terrain lies between two points, the solidity of the cells along the segment
+// weighed by how far the segment runs through them, in world units of fully solid terrain
+// chunks `grids` has no grid for count as air, e.g. to muffle a sound by what's in the way
+pub fn occlusion_between<'a>(
+  layout: &CubicVoxelLayout,
+  from: Vec3,
+  to: Vec3,
+  grids: impl Fn(&ChunkId) -> Option<&'a ChunkOcclusion>,
+) -> f32 {
+  let length = from.distance(to);
+  // a few samples per cell so thin cells aren't skipped
+  let cell_size =
+    layout.voxel_side_length() * layout.chunk_voxel_full_length() as f32 / OCCLUSION_CELLS as f32;
+  let samples = ((length / cell_size * 4.).ceil() as usize).max(1);
+  let step = length / samples as f32;
+  (0..samples)
+    .map(|i| {
+      let point = from.lerp(to, (i as f32 + 0.5) / samples as f32);
+      let chunk = layout.space_to_chunk(&point);
+      grids(&chunk).map_or(0., |grid| grid.solidity_at(layout, &chunk, &point)) * step
+    })
+    .sum()
+}
+
+// keeps the grids of chunks near spawners in step with their voxels
+#[allow(clippy::type_complexity)]
+pub fn update_chunk_occlusion(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  settings: Res<ChunkOcclusionSettings>,
+  query: Query<(
+    Entity,
+    &Chunk,
+    &ChunkVoxelData,
+    Option<&ChunkOcclusion>,
+    ChangeTrackers<ChunkVoxelData>,
+  )>,
+) {
+  for (entity, chunk, voxel_data, occlusion, changes) in query.iter() {
+    let near = chunk.steps_to_nearest_spawner <= settings.radius;
+    match (near, occlusion) {
+      (false, Some(_)) => {
+        commands.entity(entity).remove::<ChunkOcclusion>();
+      }
+      (true, Some(occlusion)) if changes.is_changed() => {
+        let updated = ChunkOcclusion::from_voxels(&layout, &chunk.id, voxel_data, &registry);
+        if *occlusion != updated {
+          commands.entity(entity).insert(updated);
+        }
+      }
+      (true, None) => {
+        commands.entity(entity).insert(ChunkOcclusion::from_voxels(
+          &layout, &chunk.id, voxel_data, &registry,
+        ));
+      }
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::registry::VoxelTypeId;
+  use proptest::prelude::*;
+  use std::collections::HashMap;
+
+  proptest! {
+      #[test]
+      fn occlusion_should_follow_the_solid_voxels(x in -50i64..50, z in -50i64..50, length in 1i64..5, height in 1i64..12, level in 0i64..12, flooded in any::<bool>()) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, length, height);
+          let chunk = ChunkId::new(x, 0, z);
+          let registry = VoxelRegistry::default();
+          // solid up to `level`, water doesn't occlude
+          let above_level = if flooded { VoxelTypeId::WATER } else { VoxelTypeId::AIR };
+          let voxel_data = ChunkVoxelData {
+              voxels: layout
+                  .get_chunk_voxels(&chunk)
+                  .into_iter()
+                  .map(|voxel| (voxel, if voxel.y() < level { VoxelTypeId::DIRT } else { above_level }))
+                  .collect(),
+          };
+          let occlusion = ChunkOcclusion::from_voxels(&layout, &chunk, &voxel_data, &registry);
+
+          let mut solid: HashMap<(usize, usize, usize), (u32, u32)> = HashMap::new();
+          for (voxel, voxel_type) in voxel_data.voxels.iter() {
+              let cell = ChunkOcclusion::cell_of(&layout, &chunk, voxel).expect("voxels of the chunk have a cell");
+              let counts = solid.entry(cell).or_default();
+              counts.0 += (*voxel_type == VoxelTypeId::DIRT) as u32;
+              counts.1 += 1;
+          }
+          for (cell, (count, total)) in solid {
+              prop_assert!((occlusion.solidity(cell) - count as f32 / total as f32).abs() <= 1. / 255.);
+          }
+          prop_assert!(ChunkOcclusion::cell_of(&layout, &ChunkId::new(x + 1, 0, z), &layout.get_center_voxel(&chunk)).is_none());
+
+          // a segment along the top layer runs through solid cells only as far as they reach up
+          let grids = |id: &ChunkId| (*id == chunk).then(|| &occlusion);
+          let side = layout.chunk_side_length() * 0.49;
+          let center = layout.chunk_to_space(&chunk) + Vec3::splat(layout.voxel_side_length() * 0.5);
+          let above = Vec3::new(center.x, height as f32 - 0.5, center.z);
+          let across = occlusion_between(&layout, above - Vec3::X * side, above + Vec3::X * side, grids);
+          prop_assert!((0. ..=2. * side + 1e-3).contains(&across));
+          if level >= height {
+              prop_assert!((across - 2. * side).abs() < 1e-2);
+          }
+          if level == 0 {
+              prop_assert_eq!(occlusion.mean(), 0.);
+              prop_assert_eq!(across, 0.);
+          }
+      }
+  }
+}
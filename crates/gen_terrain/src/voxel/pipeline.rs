@@ -0,0 +1,721 @@
+use super::{
+  chunk_material,
+  generator::{ChunkJob, ChunkStep},
+  layout::CubicVoxelLayout,
+  lod::ClippedChunk,
+  mesher::MeshFaceIndex,
+  registry::VoxelRegistry,
+  snapshot::ChunkDiffs,
+  visibility,
+  world::TerrainWorld,
+  Chunk, ChunkVoxelData, DirtyChunk, EditedChunk,
+};
+use bevy::{
+  prelude::*,
+  tasks::{AsyncComputeTaskPool, TaskPool, TaskPoolBuilder},
+  utils::Instant,
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::{
+  collections::{HashSet, VecDeque},
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+  },
+};
+
+// marks a chunk with a mesh task in flight
+#[derive(Debug, Default, Component)]
+pub struct MeshPending;
+
+// how many chunk entities are spawned and finished results are applied to them per frame
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkPipelineBudget {
+  // chunks left over wait in their spawner's queue, pinned chunks are always spawned
+  pub spawns_per_frame: usize,
+  pub voxels_per_frame: usize,
+  pub meshes_per_frame: usize,
+  #[deprecated(note = "set `GenerationMode::Synchronous { max_micros_per_frame }` instead")]
+  pub job_millis_per_frame: f64,
+}
+impl Default for ChunkPipelineBudget {
+  #[allow(deprecated)]
+  fn default() -> Self {
+    Self {
+      spawns_per_frame: 32,
+      voxels_per_frame: 16,
+      meshes_per_frame: 8,
+      job_millis_per_frame: WASM_MICROS_PER_FRAME as f64 / 1000.,
+    }
+  }
+}
+
+// share of each frame spent running chunk jobs with the `terrain-wasm` feature unless
+// `GenerationMode` says otherwise
+const WASM_MICROS_PER_FRAME: u64 = 4000;
+
+// where chunk generation and meshing jobs run, insert it before adding the plugin to change it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenerationMode {
+  // on the async compute pool, results are applied in whatever order they finish
+  Async,
+  // inline in `run_chunk_jobs` in submission order until the frame's budget is used up, at least
+  // one step runs every frame, so the same inputs load the same chunks on the same frames
+  // e.g. for headless tests, `u64::MAX` runs every queued job
+  Synchronous { max_micros_per_frame: u64 },
+}
+impl Default for GenerationMode {
+  fn default() -> Self {
+    if cfg!(feature = "terrain-wasm") {
+      Self::Synchronous {
+        max_micros_per_frame: WASM_MICROS_PER_FRAME,
+      }
+    } else {
+      Self::Async
+    }
+  }
+}
+impl GenerationMode {
+  // there are no worker threads with the `terrain-wasm` feature, jobs always run inline there
+  pub fn is_synchronous(&self) -> bool {
+    cfg!(feature = "terrain-wasm") || matches!(self, Self::Synchronous { .. })
+  }
+
+  fn max_micros_per_frame(&self) -> u64 {
+    match self {
+      Self::Synchronous {
+        max_micros_per_frame,
+      } => *max_micros_per_frame,
+      Self::Async => WASM_MICROS_PER_FRAME,
+    }
+  }
+}
+
+// how many generation and meshing jobs run on the async compute pool at once, so chunk
+// streaming leaves threads to the game's own async work, jobs past the limit wait for a free
+// worker in submission order
+// changes apply to the jobs submitted from then on, a limit of 0 still runs one job at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkTaskLimits {
+  pub max_concurrent_generation: usize,
+  pub max_concurrent_meshing: usize,
+}
+impl Default for ChunkTaskLimits {
+  fn default() -> Self {
+    Self {
+      max_concurrent_generation: usize::MAX,
+      max_concurrent_meshing: usize::MAX,
+    }
+  }
+}
+impl ChunkTaskLimits {
+  // at most `threads` of the pool are busy with terrain jobs, split between generation and
+  // meshing, generation gets the odd one out
+  pub fn dedicated(threads: usize) -> Self {
+    let threads = threads.max(2);
+    Self {
+      max_concurrent_generation: threads - threads / 2,
+      max_concurrent_meshing: threads / 2,
+    }
+  }
+}
+
+// the threads a job splits its chunk between, e.g. the column batches of `fill_columns` and the
+// slabs of `mesh_chunk_slabs`
+// jobs wait for their batches while holding a thread of the async compute pool, batches spawned
+// on that same pool could be stuck behind the jobs waiting for them
+// synchronous jobs get a pool of one thread, which chunks aren't split between
+#[derive(Clone)]
+pub struct ChunkBatchPool(pub TaskPool);
+impl FromWorld for ChunkBatchPool {
+  fn from_world(world: &mut World) -> Self {
+    let mode = world
+      .get_resource::<GenerationMode>()
+      .copied()
+      .unwrap_or_default();
+    let mut builder = TaskPoolBuilder::new().thread_name("terrain chunk batches".to_string());
+    if mode.is_synchronous() {
+      builder = builder.num_threads(1);
+    }
+    Self(builder.build())
+  }
+}
+
+// a step of a job, returns the rest of the job if there's more to do
+struct Job(Box<dyn FnOnce() -> Option<Job> + Send>);
+
+// async jobs of one kind waiting for one of at most `limit` workers, a worker runs jobs until
+// there are none left
+#[derive(Clone)]
+struct WorkerQueue(Arc<Mutex<QueuedJobs>>);
+
+struct QueuedJobs {
+  jobs: VecDeque<Job>,
+  workers: usize,
+  limit: usize,
+}
+
+impl WorkerQueue {
+  fn new(limit: usize) -> Self {
+    Self(Arc::new(Mutex::new(QueuedJobs {
+      jobs: VecDeque::new(),
+      workers: 0,
+      limit: limit.max(1),
+    })))
+  }
+
+  fn push(&self, thread_pool: &AsyncComputeTaskPool, job: Job) {
+    self.0.lock().unwrap().jobs.push_back(job);
+    self.spawn_workers(thread_pool);
+  }
+
+  // workers beyond a lowered limit stop after their current job
+  fn set_limit(&self, thread_pool: &AsyncComputeTaskPool, limit: usize) {
+    self.0.lock().unwrap().limit = limit.max(1);
+    self.spawn_workers(thread_pool);
+  }
+
+  // starts a worker for each waiting job while there's room under the limit
+  fn spawn_workers(&self, thread_pool: &AsyncComputeTaskPool) {
+    let mut queued = self.0.lock().unwrap();
+    let wanted = queued
+      .jobs
+      .len()
+      .min(queued.limit.saturating_sub(queued.workers));
+    queued.workers += wanted;
+    for _ in 0..wanted {
+      let queue = self.clone();
+      thread_pool.spawn(async move { queue.work() }).detach();
+    }
+  }
+
+  fn work(&self) {
+    loop {
+      let job = {
+        let mut queued = self.0.lock().unwrap();
+        match queued.jobs.pop_front() {
+          Some(job) if queued.workers <= queued.limit => job,
+          // the worker count changes under the same lock jobs are pushed with, so a job is never
+          // left waiting without a worker
+          popped => {
+            if let Some(job) = popped {
+              queued.jobs.push_front(job);
+            }
+            queued.workers -= 1;
+            return;
+          }
+        }
+      };
+      let mut job = Some(job);
+      while let Some(step) = job {
+        job = (step.0)();
+      }
+    }
+  }
+
+  fn waiting(&self) -> usize {
+    self.0.lock().unwrap().jobs.len()
+  }
+
+  fn workers(&self) -> usize {
+    self.0.lock().unwrap().workers
+  }
+}
+
+// runs the steps of a job and sends its result on
+struct Forward<T> {
+  sender: Sender<(Entity, T, f64)>,
+  entity: Entity,
+  submitted: Instant,
+  in_flight: Arc<AtomicUsize>,
+  cancelled: Arc<Mutex<HashSet<Entity>>>,
+}
+
+impl<T: Send + 'static> Forward<T> {
+  fn job(self, steps: ChunkStep<T>) -> Job {
+    Job(Box::new(move || {
+      if self.cancelled.lock().unwrap().remove(&self.entity) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        return None;
+      }
+      let result = match steps {
+        ChunkStep::Done(result) => result,
+        ChunkStep::Next(step) => match step() {
+          ChunkStep::Done(result) => result,
+          next => return Some(self.job(next)),
+        },
+      };
+      // the receiver lives as long as the app, so this only fails during shutdown
+      let _ = self
+        .sender
+        .send((self.entity, result, self.submitted.elapsed().as_secs_f64()));
+      None
+    }))
+  }
+}
+
+// runs the steps of a job that isn't for a chunk entity and sends its result on
+fn detached<T: Send + 'static>(steps: ChunkStep<T>, sender: Sender<T>) -> Job {
+  Job(Box::new(move || {
+    let result = match steps {
+      ChunkStep::Done(result) => result,
+      ChunkStep::Next(step) => match step() {
+        ChunkStep::Done(result) => result,
+        next => return Some(detached(next, sender)),
+      },
+    };
+    // the receiver may be gone during shutdown
+    let _ = sender.send(result);
+    None
+  }))
+}
+
+// results carry the seconds from submission until the task finished
+type ResultChannel<T> = (Sender<(Entity, T, f64)>, Receiver<(Entity, T, f64)>);
+
+// worker tasks push their results here instead of being polled from every chunk entity
+pub struct ChunkPipeline {
+  voxels: ResultChannel<ChunkVoxelData>,
+  meshes: ResultChannel<Mesh>,
+  // submitted tasks whose results haven't been applied yet
+  in_flight: Arc<AtomicUsize>,
+  // chunks whose jobs are skipped if they haven't started yet
+  cancelled: Arc<Mutex<HashSet<Entity>>>,
+  mode: GenerationMode,
+  // synchronous jobs wait here for `run_chunk_jobs`
+  jobs: Mutex<VecDeque<Job>>,
+  // async jobs wait here for a worker of their kind
+  generation: WorkerQueue,
+  meshing: WorkerQueue,
+  limits: Mutex<ChunkTaskLimits>,
+}
+impl ChunkPipeline {
+  pub fn new(mode: GenerationMode) -> Self {
+    Self::with_limits(mode, ChunkTaskLimits::default())
+  }
+
+  pub fn with_limits(mode: GenerationMode, limits: ChunkTaskLimits) -> Self {
+    Self {
+      voxels: unbounded(),
+      meshes: unbounded(),
+      in_flight: Arc::new(AtomicUsize::new(0)),
+      cancelled: Default::default(),
+      mode,
+      jobs: Mutex::new(VecDeque::new()),
+      generation: WorkerQueue::new(limits.max_concurrent_generation),
+      meshing: WorkerQueue::new(limits.max_concurrent_meshing),
+      limits: Mutex::new(limits),
+    }
+  }
+}
+impl FromWorld for ChunkPipeline {
+  fn from_world(world: &mut World) -> Self {
+    Self::with_limits(
+      world
+        .get_resource::<GenerationMode>()
+        .copied()
+        .unwrap_or_default(),
+      world
+        .get_resource::<ChunkTaskLimits>()
+        .copied()
+        .unwrap_or_default(),
+    )
+  }
+}
+
+// running totals of the results applied by the pipeline, `TerrainDiagnosticsPlugin` turns them
+// into rates and averages
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChunkPipelineStats {
+  pub voxels_loaded: usize,
+  // seconds from submission until the voxels were ready, summed over every loaded chunk
+  pub voxel_seconds: f64,
+  pub meshes_built: usize,
+  pub mesh_seconds: f64,
+}
+
+impl ChunkPipeline {
+  pub fn submit_voxels(
+    &self,
+    thread_pool: &AsyncComputeTaskPool,
+    entity: Entity,
+    job: ChunkJob<ChunkVoxelData>,
+  ) {
+    self.submit_voxel_steps(thread_pool, entity, ChunkStep::job(job));
+  }
+
+  pub fn submit_voxel_steps(
+    &self,
+    thread_pool: &AsyncComputeTaskPool,
+    entity: Entity,
+    steps: ChunkStep<ChunkVoxelData>,
+  ) {
+    self.in_flight.fetch_add(1, Ordering::Relaxed);
+    self.run(
+      thread_pool,
+      &self.generation,
+      self.forward(self.voxels.0.clone(), entity, steps),
+    );
+  }
+
+  pub fn submit_mesh(
+    &self,
+    thread_pool: &AsyncComputeTaskPool,
+    entity: Entity,
+    job: ChunkJob<Mesh>,
+  ) {
+    self.submit_mesh_steps(thread_pool, entity, ChunkStep::job(job));
+  }
+
+  pub fn submit_mesh_steps(
+    &self,
+    thread_pool: &AsyncComputeTaskPool,
+    entity: Entity,
+    steps: ChunkStep<Mesh>,
+  ) {
+    self.in_flight.fetch_add(1, Ordering::Relaxed);
+    self.run(
+      thread_pool,
+      &self.meshing,
+      self.forward(self.meshes.0.clone(), entity, steps),
+    );
+  }
+
+  // a generation job without a chunk entity, e.g. to pregenerate a chunk, it waits in the same
+  // queue and under the same limits as the chunks' jobs and its result is sent to `sender`
+  pub fn submit_detached<T: Send + 'static>(
+    &self,
+    thread_pool: &AsyncComputeTaskPool,
+    steps: ChunkStep<T>,
+    sender: Sender<T>,
+  ) {
+    self.run(thread_pool, &self.generation, detached(steps, sender));
+  }
+
+  // jobs of the chunk that haven't started yet or are in between steps are dropped, results of
+  // jobs that already finished are thrown away when they're applied to the despawned chunk
+  pub fn cancel(&self, entity: Entity) {
+    self.cancelled.lock().unwrap().insert(entity);
+  }
+
+  fn forward<T: Send + 'static>(
+    &self,
+    sender: Sender<(Entity, T, f64)>,
+    entity: Entity,
+    steps: ChunkStep<T>,
+  ) -> Job {
+    Forward {
+      sender,
+      entity,
+      submitted: Instant::now(),
+      in_flight: self.in_flight.clone(),
+      cancelled: self.cancelled.clone(),
+    }
+    .job(steps)
+  }
+
+  fn run(&self, thread_pool: &AsyncComputeTaskPool, queue: &WorkerQueue, job: Job) {
+    if self.mode.is_synchronous() {
+      self.jobs.lock().unwrap().push_back(job);
+    } else {
+      queue.push(thread_pool, job);
+    }
+  }
+
+  pub fn mode(&self) -> GenerationMode {
+    self.mode
+  }
+
+  pub fn limits(&self) -> ChunkTaskLimits {
+    *self.limits.lock().unwrap()
+  }
+
+  // raising a limit starts workers for the jobs already waiting right away
+  pub fn set_limits(&self, thread_pool: &AsyncComputeTaskPool, limits: ChunkTaskLimits) {
+    *self.limits.lock().unwrap() = limits;
+    self
+      .generation
+      .set_limit(thread_pool, limits.max_concurrent_generation);
+    self
+      .meshing
+      .set_limit(thread_pool, limits.max_concurrent_meshing);
+  }
+
+  // jobs waiting for `run_chunk_jobs`, or for a free worker in the async mode
+  pub fn queued_jobs(&self) -> usize {
+    self.jobs.lock().unwrap().len() + self.generation.waiting() + self.meshing.waiting()
+  }
+
+  // generation and meshing jobs running on the async compute pool
+  pub fn running_jobs(&self) -> (usize, usize) {
+    (self.generation.workers(), self.meshing.workers())
+  }
+
+  // tasks still running plus finished results waiting for their turn to be applied
+  pub fn in_flight(&self) -> usize {
+    self.in_flight.load(Ordering::Relaxed)
+  }
+
+  fn applied(&self, entity: Entity) {
+    self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    // the chunk was cancelled after its job started
+    let mut cancelled = self.cancelled.lock().unwrap();
+    if !cancelled.is_empty() {
+      cancelled.remove(&entity);
+    }
+  }
+
+  pub fn pending_voxels(&self) -> usize {
+    self.voxels.1.len()
+  }
+
+  pub fn pending_meshes(&self) -> usize {
+    self.meshes.1.len()
+  }
+}
+
+// hands changes of `ChunkTaskLimits` to the pipeline
+pub fn apply_task_limits(
+  thread_pool: Res<AsyncComputeTaskPool>,
+  limits: Res<ChunkTaskLimits>,
+  pipeline: Res<ChunkPipeline>,
+) {
+  if limits.is_changed() && pipeline.limits() != *limits {
+    pipeline.set_limits(&thread_pool, *limits);
+  }
+}
+
+// runs queued jobs in submission order until the frame's share of time is used up, jobs split
+// into steps stop in between steps and carry on first thing the next frame
+pub fn run_chunk_jobs(pipeline: Res<ChunkPipeline>) {
+  let started = Instant::now();
+  let budget = pipeline.mode.max_micros_per_frame();
+  loop {
+    let job = match pipeline.jobs.lock().unwrap().pop_front() {
+      Some(job) => job,
+      None => return,
+    };
+    if let Some(rest) = (job.0)() {
+      pipeline.jobs.lock().unwrap().push_front(rest);
+    }
+    if started.elapsed().as_micros() >= budget as u128 {
+      return;
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn apply_chunk_results(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  budget: Res<ChunkPipelineBudget>,
+  pipeline: Res<ChunkPipeline>,
+  mut stats: ResMut<ChunkPipelineStats>,
+  diffs: Res<ChunkDiffs>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  chunks: Query<(
+    &Chunk,
+    Option<&Handle<Mesh>>,
+    Option<&TerrainWorld>,
+    Option<&ClippedChunk>,
+    Option<&Transform>,
+  )>,
+) {
+  for (entity, mut voxel_data, seconds) in
+    pipeline.voxels.1.try_iter().take(budget.voxels_per_frame)
+  {
+    pipeline.applied(entity);
+    stats.voxels_loaded += 1;
+    stats.voxel_seconds += seconds;
+    // the chunk may have been despawned while its task was running
+    let (chunk, mesh, world, clipped, _) = match chunks.get(entity) {
+      Ok(result) => result,
+      Err(_) => continue,
+    };
+    info!("voxels loaded for {:?}", chunk.id);
+    // the full voxels of a clipped chunk replace the clipped ones
+    if matches!(clipped, Some(clipped) if clipped.regenerating) {
+      commands.entity(entity).remove::<ClippedChunk>();
+      if mesh.is_some() {
+        commands.entity(entity).insert(DirtyChunk);
+      }
+    }
+
+    // restore edits made the last time this chunk was loaded (or received from a server)
+    // edits are only recorded for the primary world
+    let diff = match world.copied().unwrap_or_default().is_primary() {
+      true => diffs.get(&chunk.id),
+      false => None,
+    };
+    if let Some(diff) = diff {
+      for (voxel, voxel_type) in diff {
+        if let Some(existing) = voxel_data.voxels.get_mut(voxel) {
+          *existing = *voxel_type;
+        }
+      }
+      commands.entity(entity).insert(EditedChunk);
+    }
+    // empty chunks skip meshing altogether, solid ones are checked once their neighbors load
+    if visibility::is_empty_chunk(&voxel_data, &registry) {
+      visibility::mark_empty_chunk(&mut commands.entity(entity));
+    }
+    commands.entity(entity).insert(voxel_data);
+  }
+
+  for (entity, mesh, seconds) in pipeline.meshes.1.try_iter().take(budget.meshes_per_frame) {
+    pipeline.applied(entity);
+    stats.meshes_built += 1;
+    stats.mesh_seconds += seconds;
+    let (chunk, existing_mesh, .., transform) = match chunks.get(entity) {
+      Ok(result) => result,
+      Err(_) => continue,
+    };
+    info!("generated mesh for {:?}", chunk.id);
+    commands
+      .entity(entity)
+      .remove::<MeshPending>()
+      .remove::<MeshFaceIndex>();
+
+    // remeshed chunks reuse their mesh asset
+    if let Some(handle) = existing_mesh {
+      meshes.set_untracked(handle, mesh);
+      continue;
+    }
+
+    // chunks keep where they were spawned, in a wrapping world that's next to the spawner and not
+    // necessarily `chunk_to_space`
+    let transform = transform
+      .copied()
+      .unwrap_or_else(|| Transform::from_translation(layout.chunk_to_space(&chunk.id)));
+    commands.entity(entity).insert_bundle(MaterialMeshBundle {
+      mesh: meshes.add(mesh),
+      material: chunk_material(),
+      transform,
+      ..default()
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bevy::tasks::TaskPool;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn synchronous_jobs_should_run_in_between_frames(count in 1usize..16, budget in prop::sample::select(vec![0, u64::MAX])) {
+          let mut world = World::new();
+          world.insert_resource(GenerationMode::Synchronous { max_micros_per_frame: budget });
+          let pipeline = ChunkPipeline::from_world(&mut world);
+          let thread_pool = AsyncComputeTaskPool(TaskPool::new());
+          let entities: Vec<_> = (0..count).map(|_| world.spawn().id()).collect();
+          for entity in entities.iter() {
+              pipeline.submit_voxels(&thread_pool, *entity, Box::new(ChunkVoxelData::default));
+          }
+          // nothing runs until the pipeline's turn in the frame
+          prop_assert_eq!(pipeline.queued_jobs(), count);
+          prop_assert_eq!(pipeline.pending_voxels(), 0);
+          world.insert_resource(pipeline);
+
+          let mut stage = SystemStage::single_threaded().with_system(run_chunk_jobs);
+          stage.run(&mut world);
+          // a budget of 0 still runs one job a frame
+          let frames = if budget == 0 { count } else { 1 };
+          for _ in 1..frames {
+              stage.run(&mut world);
+          }
+          let pipeline = world.resource::<ChunkPipeline>();
+          prop_assert_eq!(pipeline.queued_jobs(), 0);
+          let finished: Vec<_> = pipeline.voxels.1.try_iter().map(|(entity, _, _)| entity).collect();
+          prop_assert_eq!(finished, entities);
+      }
+
+      #[test]
+      fn synchronous_jobs_should_stop_in_between_steps(steps in 1usize..8, cancel_at in 0usize..8) {
+          let mut world = World::new();
+          let pipeline = ChunkPipeline::new(GenerationMode::Synchronous { max_micros_per_frame: 0 });
+          let thread_pool = AsyncComputeTaskPool(TaskPool::new());
+          let entity = world.spawn().id();
+          let ran = Arc::new(AtomicUsize::new(0));
+          let counter = ran.clone();
+          let job = ChunkStep::repeat(0, move |done| {
+              counter.fetch_add(1, Ordering::SeqCst);
+              *done += 1;
+              *done == steps
+          });
+          pipeline.submit_voxel_steps(&thread_pool, entity, job.map(|_| ChunkVoxelData::default()));
+          world.insert_resource(pipeline);
+
+          // a budget of 0 runs a single step a frame
+          let mut stage = SystemStage::single_threaded().with_system(run_chunk_jobs);
+          for frame in 1..=steps {
+              if frame - 1 == cancel_at {
+                  world.resource::<ChunkPipeline>().cancel(entity);
+              }
+              stage.run(&mut world);
+              let expected = if cancel_at < frame { cancel_at } else { frame };
+              prop_assert_eq!(ran.load(Ordering::SeqCst), expected);
+          }
+          let pipeline = world.resource::<ChunkPipeline>();
+          prop_assert_eq!(pipeline.queued_jobs(), 0);
+          prop_assert_eq!(pipeline.pending_voxels(), usize::from(cancel_at >= steps));
+          prop_assert_eq!(pipeline.in_flight(), usize::from(cancel_at >= steps));
+      }
+
+      #[test]
+      fn async_jobs_should_stay_within_the_limits(count in 1usize..24, limit in 1usize..4) {
+          let pipeline = ChunkPipeline::with_limits(
+              GenerationMode::Async,
+              ChunkTaskLimits { max_concurrent_generation: limit, ..Default::default() },
+          );
+          let thread_pool = AsyncComputeTaskPool(TaskPool::new());
+          let running = Arc::new(AtomicUsize::new(0));
+          let most = Arc::new(AtomicUsize::new(0));
+          let mut world = World::new();
+          for _ in 0..count {
+              let (running, most) = (running.clone(), most.clone());
+              pipeline.submit_voxels(&thread_pool, world.spawn().id(), Box::new(move || {
+                  let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                  most.fetch_max(now, Ordering::SeqCst);
+                  std::thread::sleep(std::time::Duration::from_millis(1));
+                  running.fetch_sub(1, Ordering::SeqCst);
+                  ChunkVoxelData::default()
+              }));
+          }
+          let started = Instant::now();
+          while pipeline.pending_voxels() < count && started.elapsed().as_secs() < 10 {
+              std::thread::yield_now();
+          }
+          prop_assert_eq!(pipeline.pending_voxels(), count);
+          prop_assert!(most.load(Ordering::SeqCst) <= limit);
+          prop_assert_eq!(pipeline.queued_jobs(), 0);
+      }
+
+      #[test]
+      fn cancelled_jobs_should_not_run(count in 1usize..16, cancel in prop::collection::vec(any::<bool>(), 16)) {
+          let mut world = World::new();
+          let pipeline = ChunkPipeline::new(GenerationMode::Synchronous { max_micros_per_frame: u64::MAX });
+          let thread_pool = AsyncComputeTaskPool(TaskPool::new());
+          let entities: Vec<_> = (0..count).map(|_| world.spawn().id()).collect();
+          for entity in entities.iter() {
+              pipeline.submit_voxels(&thread_pool, *entity, Box::new(ChunkVoxelData::default));
+          }
+          let kept: Vec<_> = entities.iter().zip(cancel.iter()).filter(|(entity, cancel)| {
+              if **cancel {
+                  pipeline.cancel(**entity);
+              }
+              !**cancel
+          }).map(|(entity, _)| *entity).collect();
+          world.insert_resource(pipeline);
+
+          SystemStage::single_threaded().with_system(run_chunk_jobs).run(&mut world);
+          let pipeline = world.resource::<ChunkPipeline>();
+          prop_assert_eq!(pipeline.in_flight(), kept.len());
+          let finished: Vec<_> = pipeline.voxels.1.try_iter().map(|(entity, _, _)| entity).collect();
+          prop_assert_eq!(finished, kept);
+          prop_assert!(pipeline.cancelled.lock().unwrap().is_empty());
+      }
+  }
+}
@@ -0,0 +1,306 @@
+use super::{
+  chunk_material,
+  generator::WorldGenConfig,
+  mesher::{self, NormalMode},
+  registry::{VoxelRegistry, VoxelTypeId},
+  sphere::SphereVoxelLayout,
+  terrain_set, ChunkId, ChunkVoxelData, TerrainSystem,
+};
+use bevy::{
+  prelude::*,
+  tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use std::collections::{HashMap, HashSet};
+
+// streams a small planet laid out by the `SphereVoxelLayout` resource around `SphereSpawner`s
+// add it after the terrain plugin, sphere chunks use its registry, world gen config and material
+// the cubic pipeline stays on the layout it was built for, sphere chunks are spawned, generated and
+// meshed by the systems here and have a `SphereChunk` in place of a `Chunk`
+#[derive(Default)]
+pub struct SphereTerrainPlugin;
+
+impl Plugin for SphereTerrainPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .init_resource::<SphereVoxelLayout>()
+      .init_resource::<SphereTerrainSettings>()
+      .init_resource::<SphereChunks>()
+      .add_system_set(
+        terrain_set(TerrainSystem::Generate)
+          .after(TerrainSystem::Spawn)
+          .with_system(spawn_sphere_chunks)
+          .with_system(finish_sphere_voxels.after(spawn_sphere_chunks)),
+      )
+      .add_system_set(
+        terrain_set(TerrainSystem::Mesh)
+          .after(TerrainSystem::Track)
+          .with_system(mesh_sphere_chunks),
+      );
+  }
+}
+
+pub struct SphereTerrainSettings {
+  // rings of chunks loaded around each spawner, chunks a ring farther out are kept until the
+  // spawner moves on
+  pub spawn_radius: i64,
+  pub spawns_per_frame: usize,
+  pub meshes_per_frame: usize,
+}
+impl Default for SphereTerrainSettings {
+  fn default() -> Self {
+    Self {
+      spawn_radius: 2,
+      spawns_per_frame: 4,
+      meshes_per_frame: 4,
+    }
+  }
+}
+
+// the loaded chunks of the planet and their entities
+#[derive(Debug, Default)]
+pub struct SphereChunks {
+  entities: HashMap<ChunkId, Entity>,
+}
+
+impl SphereChunks {
+  pub fn entity(&self, chunk: &ChunkId) -> Option<Entity> {
+    self.entities.get(chunk).copied()
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&ChunkId, &Entity)> {
+    self.entities.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.entities.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entities.is_empty()
+  }
+}
+
+// loads the planet's chunks around the entity, e.g. the player walking on it
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct SphereSpawner;
+
+// a chunk of the planet, its entity sits at `chunk_to_space` rotated by `chunk_rotation`
+#[derive(Debug, Clone, Copy, Component)]
+pub struct SphereChunk {
+  pub id: ChunkId,
+}
+
+#[derive(Component)]
+struct SphereVoxelTask(Task<ChunkVoxelData>);
+
+#[derive(Component)]
+struct SphereMeshTask(Task<Mesh>);
+
+// marks a chunk whose voxels or neighbors changed since it was meshed
+#[derive(Debug, Default, Component)]
+struct SphereChunkDirty;
+
+// the voxels of a chunk of the planet, the world gen noise sampled around the sphere so the ground
+// is `base_height` layers above the planet's radius give or take `amplitude`
+// continents, rivers and biomes are left to the cubic generator
+pub fn generate_sphere_voxels(
+  layout: &SphereVoxelLayout,
+  config: &WorldGenConfig,
+  chunk: &ChunkId,
+) -> ChunkVoxelData {
+  let noise = Fbm::new()
+    .set_seed(config.seed as u32)
+    .set_octaves(config.octaves.clamp(1, Fbm::MAX_OCTAVES))
+    .set_persistence(config.persistence)
+    .set_lacunarity(config.lacunarity);
+  let mut heights = HashMap::new();
+  let voxels = layout
+    .iter_chunk_voxels(chunk)
+    .map(|voxel| {
+      let height = *heights.entry((voxel.x(), voxel.z())).or_insert_with(|| {
+        // the same point of the noise at every radius, columns point away from the center
+        let direction = layout.up(&layout.voxel_center(&voxel));
+        let point = direction
+          .to_array()
+          .map(|d| d as f64 * layout.radius() as f64 / config.scale);
+        config.base_height + noise.get(point) * config.amplitude
+      });
+      let voxel_type = match (voxel.y() as f64) < height {
+        true => VoxelTypeId::DIRT,
+        false => VoxelTypeId::AIR,
+      };
+      (voxel, voxel_type)
+    })
+    .collect();
+  ChunkVoxelData { voxels }
+}
+
+// spawns the chunks around the spawners nearest first and despawns the ones they left behind
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_sphere_chunks(
+  mut commands: Commands,
+  thread_pool: Res<AsyncComputeTaskPool>,
+  layout: Res<SphereVoxelLayout>,
+  config: Res<WorldGenConfig>,
+  settings: Res<SphereTerrainSettings>,
+  mut chunks: ResMut<SphereChunks>,
+  spawners: Query<&Transform, With<SphereSpawner>>,
+) {
+  let mut wanted = Vec::new();
+  let mut kept = HashSet::new();
+  for transform in spawners.iter() {
+    let center = layout.space_to_chunk(&transform.translation).with_y(0);
+    let columns =
+      std::iter::once(center).chain(layout.get_chunk_neighbors(&center, settings.spawn_radius + 1));
+    for column in columns {
+      let rings = layout.chunk_step_distance(&center, &column);
+      for section in 0..layout.vertical_sections() {
+        let chunk = column.with_y(section);
+        kept.insert(chunk);
+        if rings <= settings.spawn_radius {
+          wanted.push((rings, chunk));
+        }
+      }
+    }
+  }
+
+  chunks.entities.retain(|chunk, entity| {
+    let keep = kept.contains(chunk);
+    if !keep {
+      commands.entity(*entity).despawn_recursive();
+    }
+    keep
+  });
+
+  wanted.sort_by_key(|(rings, chunk)| (*rings, chunk.y()));
+  let mut spawned = 0;
+  for (_, chunk) in wanted {
+    if spawned >= settings.spawns_per_frame {
+      break;
+    }
+    if chunks.entities.contains_key(&chunk) {
+      continue;
+    }
+    let (task_layout, config) = ((*layout).clone(), config.clone());
+    let task =
+      thread_pool.spawn(async move { generate_sphere_voxels(&task_layout, &config, &chunk) });
+    let entity = commands
+      .spawn()
+      .insert(Transform {
+        translation: layout.chunk_to_space(&chunk),
+        rotation: layout.chunk_rotation(&chunk),
+        ..default()
+      })
+      .insert(GlobalTransform::default())
+      .insert(SphereChunk { id: chunk })
+      .insert(SphereVoxelTask(task))
+      .id();
+    chunks.entities.insert(chunk, entity);
+    spawned += 1;
+  }
+}
+
+// neighbors meshed before the voxels of a chunk arrived walled it off, they're meshed again
+fn finish_sphere_voxels(
+  mut commands: Commands,
+  layout: Res<SphereVoxelLayout>,
+  chunks: Res<SphereChunks>,
+  mut tasks: Query<(Entity, &SphereChunk, &mut SphereVoxelTask)>,
+) {
+  for (entity, chunk, mut task) in tasks.iter_mut() {
+    let voxel_data = match future::block_on(future::poll_once(&mut task.0)) {
+      Some(voxel_data) => voxel_data,
+      None => continue,
+    };
+    commands
+      .entity(entity)
+      .remove::<SphereVoxelTask>()
+      .insert(voxel_data)
+      .insert(SphereChunkDirty);
+    let neighbors = layout.get_chunk_neighbors(&chunk.id, 1).into_iter().chain([
+      chunk.id.with_y(chunk.id.y() - 1),
+      chunk.id.with_y(chunk.id.y() + 1),
+    ]);
+    for neighbor in neighbors.filter_map(|neighbor| chunks.entity(&neighbor)) {
+      commands.entity(neighbor).insert(SphereChunkDirty);
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn mesh_sphere_chunks(
+  mut commands: Commands,
+  thread_pool: Res<AsyncComputeTaskPool>,
+  layout: Res<SphereVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  normal_mode: Res<NormalMode>,
+  settings: Res<SphereTerrainSettings>,
+  chunks: Res<SphereChunks>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  voxels: Query<&ChunkVoxelData, With<SphereChunk>>,
+  dirty: Query<
+    (Entity, &SphereChunk, &ChunkVoxelData),
+    (With<SphereChunkDirty>, Without<SphereMeshTask>),
+  >,
+  mut tasks: Query<(Entity, &mut SphereMeshTask)>,
+) {
+  for (entity, mut task) in tasks.iter_mut() {
+    if let Some(mesh) = future::block_on(future::poll_once(&mut task.0)) {
+      // not a bundle, it would move the chunk back to the origin
+      commands
+        .entity(entity)
+        .remove::<SphereMeshTask>()
+        .insert(meshes.add(mesh))
+        .insert(chunk_material())
+        .insert(Visibility::default())
+        .insert(ComputedVisibility::default());
+    }
+  }
+
+  for (entity, chunk, voxel_data) in dirty.iter().take(settings.meshes_per_frame) {
+    let neighbors = mesher::sphere_border_voxels(&layout, &voxel_data.voxels, |voxel| {
+      let neighbor = chunks.entity(&layout.voxel_to_chunk(voxel))?;
+      voxels.get(neighbor).ok()?.voxels.get(voxel).copied()
+    });
+    let job = mesher::generate_sphere_chunk_mesh(
+      &layout,
+      &registry,
+      chunk.id,
+      &voxel_data.voxels,
+      neighbors,
+      *normal_mode,
+    );
+    commands
+      .entity(entity)
+      .remove::<SphereChunkDirty>()
+      .insert(SphereMeshTask(thread_pool.spawn(async move { job() })));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::sphere::CubeFace;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn sphere_chunks_should_be_solid_up_to_their_surface(face in 0usize..6, u in 0i64..4, v in 0i64..4, seed in any::<u64>()) {
+          let layout = SphereVoxelLayout::new(32., 1., 4, 4, 8);
+          let config = WorldGenConfig { seed, ..Default::default() };
+          let chunk = layout.chunk_id(CubeFace::ALL[face], u, v, 0);
+          let voxels = generate_sphere_voxels(&layout, &config, &chunk).voxels;
+          prop_assert_eq!(voxels.len(), layout.get_chunk_voxels(&chunk).len());
+
+          // columns are solid from the bottom up and air above
+          for (voxel, voxel_type) in voxels.iter() {
+              if *voxel_type == VoxelTypeId::DIRT && voxel.y() > 0 {
+                  let below = *voxel - crate::voxel::VoxelId::new(0, 1, 0);
+                  prop_assert_eq!(voxels.get(&below), Some(&VoxelTypeId::DIRT));
+              }
+          }
+      }
+  }
+}
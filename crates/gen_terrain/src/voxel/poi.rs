@@ -0,0 +1,547 @@
+use super::{
+  generator::{HeightMap, WorldGenConfig},
+  layout::CubicVoxelLayout,
+  region::WorldRegions,
+  registry::VoxelRegistry,
+  seed::ChunkRng,
+  tile::TileChunk,
+  tracker::ChunkTracker,
+  world::TerrainWorld,
+  Chunk, ChunkId, ChunkVoxelData, VoxelId,
+};
+use bevy::{
+  prelude::*,
+  tasks::{AsyncComputeTaskPool, Task},
+};
+use futures_lite::future;
+use std::{
+  collections::{HashMap, VecDeque},
+  fmt,
+  sync::{Arc, RwLock},
+};
+
+// regions of points of interest kept around, placing them again is cheap but not free
+const MAX_CACHED_POI_REGIONS: usize = 256;
+
+// a kind of point of interest registered in the `PoiRegistry`, ids are handed out in
+// registration order
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PoiKindId(pub u16);
+
+// the built-in kinds are always registered first, in this order
+impl PoiKindId {
+  pub const RUINS: PoiKindId = PoiKindId(0);
+  pub const RESOURCE_NODE: PoiKindId = PoiKindId(1);
+  pub const SPAWN_CAMP: PoiKindId = PoiKindId(2);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoiKind {
+  // unique, the placement of a kind is seeded by its name so registration order can change
+  pub name: String,
+  // sites drawn per region, those that land in water or too close to another site are dropped
+  pub per_region: usize,
+  // voxels to the sites of every kind placed before it in the same region
+  pub spacing: f64,
+}
+
+impl PoiKind {
+  pub fn new(name: &str, per_region: usize) -> Self {
+    Self {
+      name: name.to_string(),
+      per_region,
+      spacing: 16.,
+    }
+  }
+}
+
+// identifies a point of interest across runs, the same seed always places it at the same voxel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PoiId {
+  pub region: (i64, i64),
+  pub kind: PoiKindId,
+  pub index: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Poi {
+  pub id: PoiId,
+  pub kind: PoiKindId,
+  // the first voxel above the ground, where gameplay entities stand
+  // placed on the default height map, `PoiChunkMeshed` has it on the ground that was generated
+  pub voxel: VoxelId,
+}
+
+#[derive(Default)]
+struct PoiCache {
+  regions: HashMap<(u64, i64, i64), Arc<Vec<Poi>>>,
+  // keys from the oldest to the newest
+  order: VecDeque<(u64, i64, i64)>,
+  // what the cached regions were placed with
+  placed_with: Option<WorldGenConfig>,
+}
+
+// special sites placed deterministically at region scale, e.g. ruins, resource nodes and spawn
+// camps, register kinds at startup before chunks load
+// regions of `region_size` voxels are placed on first use and cached, queries by area see the
+// same sites whichever chunks are loaded
+// `PoiChunkMeshed` tells games when a chunk containing some is meshed
+#[derive(Clone)]
+pub struct PoiRegistry {
+  kinds: Arc<Vec<PoiKind>>,
+  region_size: i64,
+  cache: Arc<RwLock<PoiCache>>,
+}
+
+impl Default for PoiRegistry {
+  fn default() -> Self {
+    let mut registry = Self {
+      kinds: Arc::new(Vec::new()),
+      region_size: 256,
+      cache: default(),
+    };
+    registry.register(PoiKind::new("ruins", 2));
+    registry.register(PoiKind::new("resource node", 6));
+    registry.register(PoiKind {
+      spacing: 48.,
+      ..PoiKind::new("spawn camp", 1)
+    });
+    registry
+  }
+}
+
+impl PoiRegistry {
+  // registering a name again replaces its kind and keeps its id, sites are placed again
+  pub fn register(&mut self, kind: PoiKind) -> PoiKindId {
+    let kinds = Arc::make_mut(&mut self.kinds);
+    let id = match kinds.iter().position(|k| k.name == kind.name) {
+      Some(index) => {
+        kinds[index] = kind;
+        PoiKindId(index as u16)
+      }
+      None => {
+        assert!(kinds.len() <= u16::MAX as usize, "too many poi kinds");
+        kinds.push(kind);
+        PoiKindId(kinds.len() as u16 - 1)
+      }
+    };
+    self.clear();
+    id
+  }
+
+  pub fn get(&self, id: PoiKindId) -> Option<&PoiKind> {
+    self.kinds.get(id.0 as usize)
+  }
+
+  pub fn id_of(&self, name: &str) -> Option<PoiKindId> {
+    self
+      .kinds
+      .iter()
+      .position(|k| k.name == name)
+      .map(|index| PoiKindId(index as u16))
+  }
+
+  pub fn kinds(&self) -> impl Iterator<Item = (PoiKindId, &PoiKind)> {
+    self
+      .kinds
+      .iter()
+      .enumerate()
+      .map(|(index, kind)| (PoiKindId(index as u16), kind))
+  }
+
+  pub fn region_size(&self) -> i64 {
+    self.region_size
+  }
+
+  // changing it places every site again
+  pub fn set_region_size(&mut self, region_size: i64) {
+    self.region_size = region_size.max(1);
+    self.clear();
+  }
+
+  // the region containing the column at voxel (x, z)
+  pub fn region_of(&self, x: i64, z: i64) -> (i64, i64) {
+    (
+      x.div_euclid(self.region_size),
+      z.div_euclid(self.region_size),
+    )
+  }
+
+  // the sites of a region, placed on first use
+  pub fn region(
+    &self,
+    config: &WorldGenConfig,
+    regions: &WorldRegions,
+    region: (i64, i64),
+  ) -> Arc<Vec<Poi>> {
+    if let Some(pois) = self.placed(config, region) {
+      return pois;
+    }
+    let key = (config.seed, region.0, region.1);
+
+    // placed without the lock so other queries aren't held up
+    let pois = Arc::new(place_pois(
+      config,
+      regions,
+      &self.kinds,
+      self.region_size,
+      region,
+    ));
+    let mut cache = self.cache.write().unwrap();
+    if cache.placed_with.as_ref() != Some(config) {
+      cache.regions.clear();
+      cache.order.clear();
+      cache.placed_with = Some(config.clone());
+    }
+    if cache.regions.insert(key, pois.clone()).is_none() {
+      cache.order.push_back(key);
+    }
+    while cache.order.len() > MAX_CACHED_POI_REGIONS {
+      if let Some(oldest) = cache.order.pop_front() {
+        cache.regions.remove(&oldest);
+      }
+    }
+    pois
+  }
+
+  // the sites with columns between voxel (x, z) `min` and `max`, both included
+  pub fn in_area(
+    &self,
+    config: &WorldGenConfig,
+    regions: &WorldRegions,
+    min: (i64, i64),
+    max: (i64, i64),
+  ) -> Vec<Poi> {
+    let (from, to) = (self.region_of(min.0, min.1), self.region_of(max.0, max.1));
+    let mut found = Vec::new();
+    for rz in from.1..=to.1 {
+      for rx in from.0..=to.0 {
+        found.extend(self.region(config, regions, (rx, rz)).iter().filter(|poi| {
+          (min.0..=max.0).contains(&poi.voxel.x()) && (min.1..=max.1).contains(&poi.voxel.z())
+        }));
+      }
+    }
+    found
+  }
+
+  // the sites whose voxel is in `chunk`
+  pub fn in_chunk(
+    &self,
+    config: &WorldGenConfig,
+    regions: &WorldRegions,
+    layout: &CubicVoxelLayout,
+    chunk: &ChunkId,
+  ) -> Vec<Poi> {
+    let center = layout.get_center_voxel(chunk);
+    let reach = layout.chunk_voxel_length();
+    let mut pois = self.in_area(
+      config,
+      regions,
+      (center.x() - reach, center.z() - reach),
+      (center.x() + reach, center.z() + reach),
+    );
+    pois.retain(|poi| layout.voxel_owner(&poi.voxel) == Some(*chunk));
+    pois
+  }
+
+  // the sites of a region if it's placed already
+  fn placed(&self, config: &WorldGenConfig, region: (i64, i64)) -> Option<Arc<Vec<Poi>>> {
+    let cache = self.cache.read().unwrap();
+    match cache.placed_with.as_ref() == Some(config) {
+      true => cache
+        .regions
+        .get(&(config.seed, region.0, region.1))
+        .cloned(),
+      false => None,
+    }
+  }
+
+  fn clear(&mut self) {
+    // clones sharing the cache keep it, this one starts over
+    self.cache = default();
+  }
+}
+
+impl fmt::Debug for PoiRegistry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("PoiRegistry")
+      .field("kinds", &self.kinds)
+      .field("region_size", &self.region_size)
+      .field("cached", &self.cache.read().unwrap().regions.len())
+      .finish()
+  }
+}
+
+// the sites of a region, kinds in registration order each draw their sites from their own stream
+// sites in water, at sea or closer than their kind's spacing to an earlier site are dropped
+pub fn place_pois(
+  config: &WorldGenConfig,
+  regions: &WorldRegions,
+  kinds: &[PoiKind],
+  region_size: i64,
+  region: (i64, i64),
+) -> Vec<Poi> {
+  let height_map = HeightMap::new(config).with_regions(regions.clone());
+  let mut pois: Vec<Poi> = Vec::new();
+  for (index, kind) in kinds.iter().enumerate() {
+    let mut rng = ChunkRng::new(
+      config.seed,
+      &ChunkId::new(region.0, 0, region.1),
+      &format!("poi {}", kind.name),
+    );
+    for site in 0..kind.per_region {
+      // both drawn for every site so dropped ones don't shift the next ones
+      let x = region.0 * region_size + (rng.next_f32() * region_size as f32) as i64;
+      let z = region.1 * region_size + (rng.next_f32() * region_size as f32) as i64;
+      let x = x.clamp(region.0 * region_size, (region.0 + 1) * region_size - 1);
+      let z = z.clamp(region.1 * region_size, (region.1 + 1) * region_size - 1);
+      let crowded = pois.iter().any(|poi| {
+        let (dx, dz) = ((poi.voxel.x() - x) as f64, (poi.voxel.z() - z) as f64);
+        (dx * dx + dz * dz).sqrt() < kind.spacing
+      });
+      if crowded || height_map.water_level(x, z).is_some() || at_sea(config, regions, x, z) {
+        continue;
+      }
+      let kind_id = PoiKindId(index as u16);
+      pois.push(Poi {
+        id: PoiId {
+          region,
+          kind: kind_id,
+          index: site as u32,
+        },
+        kind: kind_id,
+        voxel: VoxelId::new(x, height_map.height(x, z).ceil() as i64, z),
+      });
+    }
+  }
+  pois
+}
+
+fn at_sea(config: &WorldGenConfig, regions: &WorldRegions, x: i64, z: i64) -> bool {
+  match (&config.regions, regions.sample(config, x, z)) {
+    (Some(settings), Some(sample)) => sample.continent < settings.sea_level,
+    _ => false,
+  }
+}
+
+// sent when a chunk of the primary world containing points of interest is meshed, so games can
+// spawn gameplay entities there, e.g. enemies at a spawn camp
+// it's sent again when the chunk is loaded again after a despawn
+// the sites stand on the ground of the chunk's voxels, sites whose ground tops out in another
+// section or is under water are left out
+#[derive(Debug, Clone)]
+pub struct PoiChunkMeshed {
+  pub entity: Entity,
+  pub chunk: ChunkId,
+  pub pois: Vec<Poi>,
+}
+
+#[derive(Default)]
+pub(super) struct PoiPlacements {
+  // regions being placed on the task pool
+  tasks: HashMap<(i64, i64), Task<()>>,
+  // chunks announced once the regions they overlap are placed
+  waiting: Vec<Entity>,
+}
+
+// the first voxel above the ground of column (x, z) if the top of the ground is in `chunk`
+// the section above is checked when it's loaded, so the floor of a cave under it doesn't count
+fn surface_in_chunk(
+  registry: &VoxelRegistry,
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+  voxels: &ChunkVoxelData,
+  above: Option<&ChunkVoxelData>,
+  x: i64,
+  z: i64,
+) -> Option<VoxelId> {
+  let get = |y: i64| {
+    let voxel = VoxelId::new(x, y, z);
+    voxels
+      .voxels
+      .get(&voxel)
+      .or_else(|| above?.voxels.get(&voxel))
+      .copied()
+  };
+  let bottom = chunk.y() * layout.chunk_voxel_height();
+  let top = bottom + layout.chunk_voxel_height();
+  let ground = |y: i64| matches!(get(y), Some(v) if registry.is_collidable(v));
+  if above.is_some() && (top..top + layout.chunk_voxel_height()).any(ground) {
+    return None;
+  }
+  let surface = (bottom..top).rev().find(|y| ground(*y))? + 1;
+  // water on top of the ground
+  if matches!(get(surface), Some(v) if registry.is_solid(v)) {
+    return None;
+  }
+  Some(VoxelId::new(x, surface, z))
+}
+
+// announces the points of interest of chunks that got their first mesh or tile map
+// regions that aren't placed yet are placed on the task pool, their chunks are announced once
+// they're done
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub(super) fn announce_poi_chunks(
+  mut placements: Local<PoiPlacements>,
+  thread_pool: Res<AsyncComputeTaskPool>,
+  layout: Res<CubicVoxelLayout>,
+  config: Res<WorldGenConfig>,
+  regions: Res<WorldRegions>,
+  registry: Res<VoxelRegistry>,
+  pois: Res<PoiRegistry>,
+  tracker: Res<ChunkTracker>,
+  mut events: EventWriter<PoiChunkMeshed>,
+  added: Query<Entity, (With<Chunk>, Or<(Added<Handle<Mesh>>, Added<TileChunk>)>)>,
+  chunks: Query<(&Chunk, &ChunkVoxelData, Option<&TerrainWorld>)>,
+) {
+  let placements = &mut *placements;
+  placements
+    .tasks
+    .retain(|_, task| future::block_on(future::poll_once(task)).is_none());
+  for entity in added.iter() {
+    if !placements.waiting.contains(&entity) {
+      placements.waiting.push(entity);
+    }
+  }
+
+  let tasks = &mut placements.tasks;
+  placements.waiting.retain(|entity| {
+    let (chunk, voxels, world) = match chunks.get(*entity) {
+      Ok(chunk) => chunk,
+      Err(_) => return false,
+    };
+    if !world.copied().unwrap_or_default().is_primary() {
+      return false;
+    }
+    let center = layout.get_center_voxel(&chunk.id);
+    let reach = layout.chunk_voxel_length();
+    let (min, max) = (
+      (center.x() - reach, center.z() - reach),
+      (center.x() + reach, center.z() + reach),
+    );
+    let (from, to) = (pois.region_of(min.0, min.1), pois.region_of(max.0, max.1));
+    let mut placed = Vec::new();
+    let mut pending = false;
+    for rz in from.1..=to.1 {
+      for rx in from.0..=to.0 {
+        match pois.placed(&config, (rx, rz)) {
+          Some(sites) => placed.push(sites),
+          None => {
+            pending = true;
+            tasks.entry((rx, rz)).or_insert_with(|| {
+              let (pois, config, regions) =
+                ((*pois).clone(), (*config).clone(), (*regions).clone());
+              thread_pool.spawn(async move {
+                pois.region(&config, &regions, (rx, rz));
+              })
+            });
+          }
+        }
+      }
+    }
+    if pending {
+      return true;
+    }
+
+    let above = tracker
+      .entity(&chunk.id.with_y(chunk.id.y() + 1))
+      .and_then(|above| chunks.get(above).ok())
+      .map(|(_, voxels, _)| voxels);
+    let found: Vec<Poi> = placed
+      .iter()
+      .flat_map(|sites| sites.iter())
+      .filter(|poi| {
+        (min.0..=max.0).contains(&poi.voxel.x()) && (min.1..=max.1).contains(&poi.voxel.z())
+      })
+      .filter_map(|poi| {
+        let (x, z) = (poi.voxel.x(), poi.voxel.z());
+        Some(Poi {
+          voxel: surface_in_chunk(&registry, &layout, &chunk.id, voxels, above, x, z)?,
+          ..*poi
+        })
+      })
+      .collect();
+    if !found.is_empty() {
+      events.send(PoiChunkMeshed {
+        entity: *entity,
+        chunk: chunk.id,
+        pois: found,
+      });
+    }
+    false
+  });
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::registry::VoxelTypeId;
+  use proptest::prelude::*;
+
+  proptest! {
+      #![proptest_config(ProptestConfig::with_cases(16))]
+
+      #[test]
+      fn pois_should_be_the_same_whichever_area_is_queried(seed in 0u64..1000, x in -600i64..600, z in -600i64..600, split in 1i64..200) {
+          let config = WorldGenConfig { seed, ..default() };
+          let regions = WorldRegions::default();
+          let mut pois = PoiRegistry::default();
+          pois.set_region_size(128);
+          let (min, max) = ((x, z), (x + 200, z + 200));
+          let whole = pois.in_area(&config, &regions, min, max);
+
+          // the same sites come back in two halves, from a registry that placed them from scratch
+          let fresh = PoiRegistry { cache: default(), ..pois.clone() };
+          let mut halves = fresh.in_area(&config, &regions, min, (x + split - 1, z + 200));
+          halves.extend(fresh.in_area(&config, &regions, (x + split, z), max));
+          prop_assert_eq!(whole.len(), halves.len());
+          for poi in whole.iter() {
+              prop_assert!(halves.contains(poi));
+              prop_assert!((min.0..=max.0).contains(&poi.voxel.x()) && (min.1..=max.1).contains(&poi.voxel.z()));
+              prop_assert_eq!(pois.region_of(poi.voxel.x(), poi.voxel.z()), poi.id.region);
+              prop_assert!(pois.get(poi.kind).is_some());
+          }
+
+          // sites keep their distance
+          for region in [pois.region_of(x, z), pois.region_of(x + 200, z + 200)] {
+              let placed = pois.region(&config, &regions, region);
+              for (i, a) in placed.iter().enumerate() {
+                  for b in placed[..i].iter() {
+                      let (dx, dz) = ((a.voxel.x() - b.voxel.x()) as f64, (a.voxel.z() - b.voxel.z()) as f64);
+                      prop_assert!((dx * dx + dz * dz).sqrt() >= pois.get(a.kind).unwrap().spacing);
+                  }
+              }
+          }
+      }
+
+      #[test]
+      fn pois_should_stand_on_the_top_of_the_ground_in_one_section(x in -2i64..3, z in -2i64..3, height in 0i64..16, water in 0i64..16) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 0.5, 2, 4)
+              .with_vertical_sections(4);
+          let registry = VoxelRegistry::default();
+          let sections: Vec<ChunkVoxelData> = (0..4)
+              .map(|y| ChunkVoxelData {
+                  voxels: layout
+                      .iter_chunk_voxels(&ChunkId::new(0, y, 0))
+                      .map(|voxel| match voxel.y() {
+                          y if y < height => (voxel, VoxelTypeId::DIRT),
+                          y if y < water => (voxel, VoxelTypeId::WATER),
+                          _ => (voxel, VoxelTypeId::AIR),
+                      })
+                      .collect(),
+              })
+              .collect();
+
+          let found: Vec<VoxelId> = (0..4)
+              .filter_map(|y| {
+                  let chunk = ChunkId::new(0, y, 0);
+                  surface_in_chunk(&registry, &layout, &chunk, &sections[y as usize], sections.get(y as usize + 1), x, z)
+              })
+              .collect();
+          match height > 0 && water <= height {
+              true => prop_assert_eq!(found, vec![VoxelId::new(x, height, z)]),
+              false => prop_assert!(found.is_empty()),
+          }
+      }
+  }
+}
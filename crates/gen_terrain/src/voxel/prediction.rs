@@ -0,0 +1,142 @@
+use super::{layout::CubicVoxelLayout, lod::ChunkLodSettings, ChunkId};
+use bevy::prelude::*;
+
+// how far around and ahead of spawners chunks are loaded
+pub struct ChunkSpawnerConfig {
+  // rings of chunk columns loaded around a fully zoomed in spawner, chunks past
+  // `ChunkLodSettings::mesh_radius` are loaded without a mesh
+  pub spawn_radius: i64,
+  // sections above and below the spawner's section that are loaded in every column
+  pub vertical_radius: i64,
+  // chunks are preloaded along where the spawner will be this many seconds from now
+  pub prediction_seconds: f32,
+  // longest predicted path in chunks, so very fast spawners don't flood the generator
+  pub max_prediction_chunks: i64,
+  // rings of chunks loaded around each chunk of the predicted path
+  pub prediction_radius: i64,
+  // a spawner moving farther than this many chunks in one frame has teleported, it isn't given a
+  // velocity and chunks still generating around where it was are cancelled
+  pub teleport_distance: i64,
+  // chunks are preloaded along where spawners look out to this many world units, so first and
+  // third person cameras load the terrain at the end of long sight lines before the ground behind
+  // them, off when None
+  pub view_distance: Option<f32>,
+  // longest view path in chunks
+  pub max_view_chunks: i64,
+  // rings of chunks loaded around each chunk of the view path
+  pub view_radius: i64,
+}
+impl Default for ChunkSpawnerConfig {
+  fn default() -> Self {
+    Self {
+      spawn_radius: 2,
+      vertical_radius: 1,
+      prediction_seconds: 1.5,
+      max_prediction_chunks: 8,
+      prediction_radius: 1,
+      teleport_distance: 8,
+      view_distance: None,
+      max_view_chunks: 16,
+      view_radius: 1,
+    }
+  }
+}
+
+impl ChunkSpawnerConfig {
+  // rings of chunk columns loaded around a spawner at the given zoom, fully zoomed out spawners
+  // load `ChunkLodSettings::zoom_spawn_radius` more
+  pub fn spawn_rings(&self, lod_settings: &ChunkLodSettings, zoom: f32) -> i64 {
+    self.spawn_radius + (zoom.clamp(0., 1.) * lod_settings.zoom_spawn_radius as f32).round() as i64
+  }
+}
+
+// chunks on the way from `from` towards `to` excluding `from`, each adjacent to the previous one
+// the path stays in the section of `from`
+pub fn predicted_path(from: ChunkId, to: ChunkId, max_steps: i64) -> Vec<ChunkId> {
+  let (dx, dz) = (to.x() - from.x(), to.z() - from.z());
+  let steps = dx.abs().max(dz.abs());
+  if steps == 0 {
+    return Vec::new();
+  }
+
+  (1..=steps.min(max_steps))
+    .map(|step| {
+      let t = step as f64 / steps as f64;
+      from
+        + ChunkId::new(
+          (dx as f64 * t).round() as i64,
+          0,
+          (dz as f64 * t).round() as i64,
+        )
+    })
+    .collect()
+}
+
+// chunks along where `transform` looks out to `distance` world units, see `predicted_path`
+// only the horizontal part of the view counts, a spawner looking straight down has no view path
+pub fn view_path(
+  layout: &CubicVoxelLayout,
+  transform: &Transform,
+  distance: f32,
+  max_steps: i64,
+) -> Vec<ChunkId> {
+  let from = layout.clamp_to_world(&layout.space_to_chunk(&transform.translation));
+  let looking_at = transform.translation + transform.forward() * distance;
+  let to = layout.space_to_chunk(&looking_at).with_y(from.y());
+  wrapped_path(layout, from, to, max_steps)
+}
+
+// `predicted_path` the short way around a wrapping world
+pub fn wrapped_path(
+  layout: &CubicVoxelLayout,
+  from: ChunkId,
+  to: ChunkId,
+  max_steps: i64,
+) -> Vec<ChunkId> {
+  predicted_path(from, layout.nearest_image(&to, &from), max_steps)
+    .into_iter()
+    .map(|chunk| layout.wrap_chunk(&chunk))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn predicted_path_should_be_connected(x in -100i64..=100, y in -100i64..=100, dx in -20i64..=20, dy in -20i64..=20, max_steps in 0i64..30) {
+          let from = ChunkId::new(x, 0, y);
+          let to = from + ChunkId::new(dx, 0, dy);
+          let path = predicted_path(from, to, max_steps);
+
+          assert_eq!(path.len() as i64, dx.abs().max(dy.abs()).min(max_steps));
+          if max_steps >= dx.abs().max(dy.abs()) && !path.is_empty() {
+              assert_eq!(*path.last().unwrap(), to);
+          }
+          let mut previous = from;
+          for chunk in path {
+              assert!(previous.adjacent().any(|c| c == chunk));
+              previous = chunk;
+          }
+      }
+
+      #[test]
+      fn view_path_should_follow_the_horizontal_view(x in -100f32..100., z in -100f32..100., yaw in 0f32..std::f32::consts::TAU, pitch in -1.5f32..1.5, distance in 0f32..64.) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4).with_vertical_sections(4);
+          let transform = Transform::from_xyz(x, 6., z)
+              .with_rotation(Quat::from_euler(EulerRot::YXZ, yaw, pitch, 0.));
+          let path = view_path(&layout, &transform, distance, i64::MAX);
+
+          let from = layout.space_to_chunk(&transform.translation);
+          let looking_at = transform.translation + transform.forward() * distance;
+          let to = layout.space_to_chunk(&looking_at);
+          prop_assert_eq!(path.last().copied().unwrap_or(from), to.with_y(from.y()));
+          prop_assert!(path.iter().all(|chunk| chunk.y() == from.y()));
+
+          let looking_down = transform.with_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2));
+          prop_assert!(view_path(&layout, &looking_down, distance, i64::MAX).len() <= 1);
+      }
+  }
+}
@@ -0,0 +1,306 @@
+use super::{
+  cache::CompressedVoxels,
+  error::{TerrainError, TerrainErrorEvent},
+  generator::{ChunkStep, WorldGenConfig},
+  jobs::{self, TerrainJobId, TerrainJobs},
+  layout::CubicVoxelLayout,
+  prediction::ChunkSpawnerConfig,
+  store::{self, ChunkStore, RegionId},
+  ChunkGeneration, ChunkId,
+};
+use bevy::{
+  ecs::system::SystemParam,
+  prelude::*,
+  tasks::{IoTaskPool, Task},
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use futures_lite::future;
+use std::{
+  collections::{HashMap, VecDeque},
+  marker::PhantomData,
+};
+
+// a chunk that was pregenerated, its voxels are `None` if it was pregenerated with the same config
+// before
+type Pregenerated = (TerrainJobId, ChunkId, Option<CompressedVoxels>);
+
+// chunks waiting to be pregenerated and the ones being generated and saved, throttled so
+// pregeneration doesn't hold up the chunks spawners are waiting for
+// chunks are saved a region at a time, once every queued chunk of their region is generated
+pub struct TerrainPregeneration {
+  // chunks generated at once
+  pub max_in_flight: usize,
+  pub starts_per_frame: usize,
+  queue: VecDeque<(TerrainJobId, ChunkId)>,
+  // chunks of each region that are queued or generating
+  unfinished: HashMap<RegionId, usize>,
+  generating: usize,
+  results: (Sender<Pregenerated>, Receiver<Pregenerated>),
+  // chunks waiting for the rest of their region
+  generated: HashMap<RegionId, Vec<Pregenerated>>,
+  writing: HashMap<RegionId, (Vec<(TerrainJobId, ChunkId)>, Task<Result<(), TerrainError>>)>,
+}
+impl Default for TerrainPregeneration {
+  fn default() -> Self {
+    Self {
+      max_in_flight: 4,
+      starts_per_frame: 2,
+      queue: VecDeque::new(),
+      unfinished: HashMap::new(),
+      generating: 0,
+      results: unbounded(),
+      generated: HashMap::new(),
+      writing: HashMap::new(),
+    }
+  }
+}
+
+impl TerrainPregeneration {
+  // chunks queued, generating or being written
+  pub fn pending(&self) -> usize {
+    self.queue.len()
+      + self.generating
+      + self.generated.values().map(Vec::len).sum::<usize>()
+      + self
+        .writing
+        .values()
+        .map(|(chunks, _)| chunks.len())
+        .sum::<usize>()
+  }
+
+  // the chunk won't be generated, its region is saved once the rest of it is
+  fn finish(&mut self, region: RegionId) {
+    if let Some(unfinished) = self.unfinished.get_mut(&region) {
+      *unfinished -= 1;
+      if *unfinished == 0 {
+        self.unfinished.remove(&region);
+      }
+    }
+  }
+}
+
+// generates chunks of the primary world into the `ChunkStore` without spawning them, e.g. on a
+// server before players join, they're read from the store instead of generated when they're
+// spawned later
+#[derive(SystemParam)]
+pub struct TerrainPregenerator<'w, 's> {
+  layout: Res<'w, CubicVoxelLayout>,
+  spawner_config: Res<'w, ChunkSpawnerConfig>,
+  store: Res<'w, ChunkStore>,
+  jobs: ResMut<'w, TerrainJobs>,
+  pregeneration: ResMut<'w, TerrainPregeneration>,
+  #[system_param(ignore)]
+  marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> TerrainPregenerator<'w, 's> {
+  // every section spawners load around `radius` rings of chunk columns around `center`, as a
+  // `TerrainJob` that's done once they're all saved
+  // `None` if the store has no directory to save them to
+  pub fn pregenerate_area(&mut self, center: ChunkId, radius: i64) -> Option<TerrainJobId> {
+    if !self.store.is_enabled() {
+      return None;
+    }
+    let chunks = jobs::area_chunks(&self.layout, &self.spawner_config, center, radius);
+    let job = self.jobs.submit_detached(
+      format!("pregenerating {} rings around {:?}", radius, center),
+      chunks.iter().copied(),
+    );
+    info!("pregenerating {} chunks around {:?}", chunks.len(), center);
+    for chunk in chunks.iter() {
+      *self
+        .pregeneration
+        .unfinished
+        .entry(self.store.region_of(chunk))
+        .or_default() += 1;
+    }
+    self
+      .pregeneration
+      .queue
+      .extend(chunks.into_iter().map(|chunk| (job, chunk)));
+    Some(job)
+  }
+}
+
+// chunks are generated by the pipeline's generation jobs, regions are written on the io pool
+#[allow(clippy::too_many_arguments)]
+pub fn pregenerate_chunks(
+  generation: ChunkGeneration,
+  io_pool: Res<IoTaskPool>,
+  layout: Res<CubicVoxelLayout>,
+  config: Res<WorldGenConfig>,
+  mut pregeneration: ResMut<TerrainPregeneration>,
+  mut jobs: ResMut<TerrainJobs>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  if pregeneration.pending() == 0 {
+    return;
+  }
+  let pregeneration = &mut *pregeneration;
+  let generated_voxels = generation.store.generated_voxels();
+
+  // chunks of a region that couldn't be saved are reported and don't hold up their jobs
+  pregeneration.writing.retain(|region, (chunks, task)| {
+    match future::block_on(future::poll_once(task)) {
+      Some(result) => {
+        if let Err(err) = result {
+          let context = format!("saving pregenerated region {:?}", region);
+          errors.send(TerrainErrorEvent::new(err, context));
+        }
+        for (job, chunk) in chunks.iter() {
+          jobs.complete(*job, chunk);
+        }
+        false
+      }
+      None => true,
+    }
+  });
+
+  let results: Vec<Pregenerated> = pregeneration.results.1.try_iter().collect();
+  for (job, chunk, voxels) in results {
+    let region = generation.store.region_of(&chunk);
+    pregeneration.generating -= 1;
+    pregeneration.finish(region);
+    pregeneration
+      .generated
+      .entry(region)
+      .or_default()
+      .push((job, chunk, voxels));
+  }
+
+  let fingerprint = store::config_fingerprint(&config);
+  let mut started = 0;
+  while started < pregeneration.starts_per_frame
+    && pregeneration.generating < pregeneration.max_in_flight
+  {
+    let (job, chunk) = match pregeneration.queue.pop_front() {
+      Some(queued) => queued,
+      None => break,
+    };
+    let region = generation.store.region_of(&chunk);
+    // chunks of cancelled jobs are dropped
+    if !matches!(jobs.get(job), Some(pending) if pending.is_pending(&chunk)) {
+      pregeneration.finish(region);
+      continue;
+    }
+    let generated_voxels = match &generated_voxels {
+      Some(generated_voxels) => generated_voxels.clone(),
+      None => {
+        pregeneration.finish(region);
+        jobs.complete(job, &chunk);
+        continue;
+      }
+    };
+
+    let generate = generation.load_voxels(&layout, generation.context(chunk, config.clone()));
+    let layout = (*layout).clone();
+    let registry = (*generation.registry).clone();
+    let steps: ChunkStep<Pregenerated> = ChunkStep::Next(Box::new(move || {
+      // chunks pregenerated before with the same config are kept
+      if generated_voxels
+        .read(fingerprint, &layout, &chunk, &registry)
+        .is_some()
+      {
+        return ChunkStep::Done((job, chunk, None));
+      }
+      generate.map(move |voxel_data| {
+        let voxels = CompressedVoxels::compress(&layout, &chunk, &voxel_data);
+        (job, chunk, Some(voxels))
+      })
+    }));
+    generation.pipeline.submit_detached(
+      &generation.thread_pool,
+      steps,
+      pregeneration.results.0.clone(),
+    );
+    pregeneration.generating += 1;
+    started += 1;
+  }
+
+  // regions are written once none of their chunks are left to generate, and not while they're
+  // still being written
+  let done: Vec<RegionId> = pregeneration
+    .generated
+    .keys()
+    .filter(|region| {
+      !pregeneration.unfinished.contains_key(region) && !pregeneration.writing.contains_key(region)
+    })
+    .copied()
+    .collect();
+  for region in done {
+    let generated = pregeneration.generated.remove(&region).unwrap_or_default();
+    let chunks: Vec<_> = generated
+      .iter()
+      .map(|(job, chunk, _)| (*job, *chunk))
+      .collect();
+    let voxels: Vec<_> = generated
+      .into_iter()
+      .filter_map(|(_, chunk, voxels)| Some((chunk, voxels?)))
+      .collect();
+    let generated_voxels = match &generated_voxels {
+      Some(generated_voxels) if !voxels.is_empty() => generated_voxels.clone(),
+      _ => {
+        for (job, chunk) in chunks.iter() {
+          jobs.complete(*job, chunk);
+        }
+        continue;
+      }
+    };
+    let registry = (*generation.registry).clone();
+    let task =
+      io_pool.spawn(async move { generated_voxels.write(region, fingerprint, voxels, &registry) });
+    pregeneration.writing.insert(region, (chunks, task));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  struct Area(ChunkId, i64);
+  struct Pregenerated(Option<TerrainJobId>);
+
+  fn pregenerate(
+    mut pregenerator: TerrainPregenerator,
+    area: Res<Area>,
+    mut pregenerated: ResMut<Pregenerated>,
+  ) {
+    pregenerated.0 = pregenerator.pregenerate_area(area.0, area.1);
+  }
+
+  proptest! {
+      #[test]
+      fn pregeneration_should_queue_the_area_as_a_job(x in -20i64..20, z in -20i64..20, radius in 0i64..4, enabled in any::<bool>()) {
+          let mut world = World::new();
+          let layout = CubicVoxelLayout::default();
+          let spawner_config = ChunkSpawnerConfig::default();
+          let expected = jobs::area_chunks(&layout, &spawner_config, ChunkId::new(x, 0, z), radius);
+          world.insert_resource(layout);
+          world.insert_resource(spawner_config);
+          world.insert_resource(match enabled {
+              true => ChunkStore::new("pregenerated"),
+              false => ChunkStore::default(),
+          });
+          world.insert_resource(TerrainJobs::default());
+          world.insert_resource(TerrainPregeneration::default());
+          world.insert_resource(Area(ChunkId::new(x, 0, z), radius));
+          world.insert_resource(Pregenerated(None));
+
+          let mut stage = SystemStage::single_threaded().with_system(pregenerate);
+          stage.run(&mut world);
+
+          // nothing is queued without a directory to save to
+          let job = world.get_resource::<Pregenerated>().unwrap().0;
+          prop_assert_eq!(job.is_some(), enabled);
+          let pregeneration = world.get_resource::<TerrainPregeneration>().unwrap();
+          prop_assert_eq!(pregeneration.pending(), if enabled { expected.len() } else { 0 });
+          if let Some(job) = job {
+              let jobs = world.get_resource::<TerrainJobs>().unwrap();
+              let job = jobs.get(job).expect("the job is running");
+              prop_assert_eq!(job.total(), expected.len());
+              prop_assert!(expected.iter().all(|chunk| job.is_pending(chunk)));
+          }
+      }
+  }
+}
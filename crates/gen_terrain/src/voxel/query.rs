@@ -0,0 +1,302 @@
+use super::{
+  biome::{biome_at, Biome},
+  generator::WorldGenConfig,
+  heightmap::HeightmapTerrain,
+  layout::CubicVoxelLayout,
+  registry::{VoxelRegistry, VoxelTypeId},
+  tracker::ChunkTracker,
+  ChunkVoxelData, VoxelId,
+};
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+// the first solid voxel along a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainHit {
+  pub voxel: VoxelId,
+  pub voxel_type: VoxelTypeId,
+  // where the ray entered the voxel
+  pub position: Vec3,
+  // of the face the ray entered through, zero if the ray started inside the voxel
+  pub normal: Vec3,
+  pub distance: f32,
+}
+
+impl TerrainHit {
+  // the voxel in front of the face that was hit, e.g. where a block is placed
+  pub fn adjacent_voxel(&self) -> VoxelId {
+    self.voxel
+      + VoxelId::new(
+        self.normal.x as i64,
+        self.normal.y as i64,
+        self.normal.z as i64,
+      )
+  }
+}
+
+// read-only access to the voxels of loaded chunks of the primary world
+#[derive(SystemParam)]
+pub struct TerrainQuery<'w, 's> {
+  layout: Res<'w, CubicVoxelLayout>,
+  tracker: Res<'w, ChunkTracker>,
+  registry: Res<'w, VoxelRegistry>,
+  config: Res<'w, WorldGenConfig>,
+  heightmap: Option<Res<'w, HeightmapTerrain>>,
+  chunks: Query<'w, 's, &'static ChunkVoxelData>,
+}
+
+// what simple character controllers collide with, `is_solid` and `overlapping_solid_voxels`
+// the voxels live on the chunk entities, a resource would have to copy every chunk as it loads
+// and changes, so it's the `TerrainQuery` of the primary world under another name
+pub type VoxelWorld<'w, 's> = TerrainQuery<'w, 's>;
+
+impl<'w, 's> TerrainQuery<'w, 's> {
+  fn chunk_data(&self, voxel: &VoxelId) -> Option<&ChunkVoxelData> {
+    let entity = self.tracker.entity(&self.layout.voxel_owner(voxel)?)?;
+    self.chunks.get(entity).ok()
+  }
+
+  // returns None if the chunk containing the voxel isn't loaded or is still generating
+  pub fn get_voxel(&self, voxel: &VoxelId) -> Option<VoxelTypeId> {
+    self.chunk_data(voxel)?.voxels.get(voxel).copied()
+  }
+
+  // world-space height of the top of the highest solid voxel in the column at (x, z)
+  // sections that aren't loaded are skipped
+  pub fn surface_height(&self, x: f32, z: f32) -> Option<f32> {
+    let column = self.layout.space_to_voxel(&Vec3::new(x, 0.0, z));
+
+    (0..self.layout.world_voxel_height())
+      .rev()
+      .map(|y| VoxelId::new(column.x(), y, column.z()))
+      .find(|voxel| matches!(self.get_voxel(voxel), Some(v) if self.registry.is_collidable(v)))
+      .map(|voxel| self.layout.voxel_to_space(&voxel).y + self.layout.voxel_side_length())
+  }
+
+  // up vector of the ground at (x, z) from the heights of the columns next to it, e.g. to align
+  // vehicles to the terrain, heightmap terrains use their smooth surface instead of the voxels
+  // None if the column or both neighbors on an axis aren't loaded
+  pub fn surface_normal(&self, x: f32, z: f32) -> Option<Vec3> {
+    let side = self.layout.voxel_side_length();
+    let height = |x: f32, z: f32| match self.heightmap.as_ref().filter(|h| h.is_loaded()) {
+      Some(heightmap) => heightmap.surface_height(&self.layout, x, z),
+      None => self.surface_height(x, z),
+    };
+    let center = height(x, z)?;
+    // rise over run along one axis, one sided where a neighbor isn't loaded
+    let gradient = |step: Vec2| match (
+      height(x + step.x, z + step.y),
+      height(x - step.x, z - step.y),
+    ) {
+      (Some(ahead), Some(behind)) => Some((ahead - behind) / (2. * side)),
+      (Some(ahead), None) => Some((ahead - center) / side),
+      (None, Some(behind)) => Some((center - behind) / side),
+      (None, None) => None,
+    };
+    let dx = gradient(Vec2::new(side, 0.))?;
+    let dz = gradient(Vec2::new(0., side))?;
+    Some(Vec3::new(-dx, 1., -dz).normalize())
+  }
+
+  // angle between the ground at (x, z) and the horizontal, e.g. to reject placing buildings on
+  // slopes that are too steep, see `surface_normal`
+  pub fn slope_degrees(&self, x: f32, z: f32) -> Option<f32> {
+    self
+      .surface_normal(x, z)
+      .map(|normal| normal.angle_between(Vec3::Y).to_degrees())
+  }
+
+  // whether the voxel at a world position is solid, None if its chunk isn't loaded
+  // everything above the world is empty and everything below it is solid, so bodies can't fall
+  // out of the world
+  pub fn is_solid(&self, position: Vec3) -> Option<bool> {
+    self.is_voxel_solid(&self.layout.space_to_voxel(&position))
+  }
+
+  // like `is_solid`, for a voxel instead of a world position
+  pub fn is_voxel_solid(&self, voxel: &VoxelId) -> Option<bool> {
+    if voxel.y() < 0 {
+      return Some(true);
+    }
+    if voxel.y() >= self.layout.world_voxel_height() {
+      return Some(false);
+    }
+    self
+      .get_voxel(voxel)
+      .map(|voxel_type| self.registry.is_collidable(voxel_type))
+  }
+
+  // solid voxels overlapping the box between `min` and `max`, e.g. the broadphase of a swept
+  // character collider, voxels in chunks that aren't loaded are skipped
+  pub fn overlapping_solid_voxels(
+    &self,
+    min: Vec3,
+    max: Vec3,
+  ) -> impl Iterator<Item = (VoxelId, VoxelTypeId)> + '_ {
+    self
+      .layout
+      .get_voxels_in_aabb(&min.min(max), &min.max(max))
+      .filter_map(move |voxel| Some((voxel, self.get_voxel(&voxel)?)))
+      .filter(move |(_, voxel_type)| self.registry.is_collidable(*voxel_type))
+  }
+
+  // the biome of the column at a world position, whether or not its chunk is loaded
+  pub fn biome(&self, position: Vec3) -> Biome {
+    biome_at(&self.config, &self.layout.space_to_voxel(&position))
+  }
+
+  // voxels in chunks that aren't loaded are passed through
+  pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<TerrainHit> {
+    self
+      .layout
+      .get_voxels_along_ray(origin, direction, max_distance)
+      .find_map(|(voxel, distance, normal)| {
+        let voxel_type = self.get_voxel(&voxel)?;
+        self.registry.is_collidable(voxel_type).then(|| TerrainHit {
+          voxel,
+          voxel_type,
+          position: origin + direction.normalize() * distance,
+          normal,
+          distance,
+        })
+      })
+  }
+
+  pub fn snap_to_ground(&self, position: Vec3) -> Option<Vec3> {
+    self
+      .surface_height(position.x, position.z)
+      .map(|height| Vec3::new(position.x, height, position.z))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::ChunkId;
+  use bevy::ecs::system::SystemState;
+  use proptest::prelude::*;
+  use std::collections::HashSet;
+
+  // the chunk at the origin is loaded, the rest of its section isn't
+  fn world_with_chunk(seed: u64) -> (World, CubicVoxelLayout) {
+    let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 4);
+    let chunk = ChunkId::new(0, 0, 0);
+    let voxels = layout
+      .iter_chunk_voxels(&chunk)
+      .map(|voxel| (voxel, voxel_type(seed, &voxel)))
+      .collect();
+    let mut world = World::new();
+    let entity = world.spawn().insert(ChunkVoxelData { voxels }).id();
+    let mut tracker = ChunkTracker::default();
+    tracker.register_entity(chunk, entity);
+    world.insert_resource(layout.clone());
+    world.insert_resource(tracker);
+    world.insert_resource(VoxelRegistry::default());
+    world.insert_resource(WorldGenConfig::default());
+    (world, layout)
+  }
+
+  // the chunk at the origin is loaded with its columns solid up to `height` of the column
+  fn world_with_heights(layout: &CubicVoxelLayout, height: impl Fn(i64, i64) -> i64) -> World {
+    let chunk = ChunkId::new(0, 0, 0);
+    let voxels = layout
+      .iter_chunk_voxels(&chunk)
+      .map(|voxel| match voxel.y() < height(voxel.x(), voxel.z()) {
+        true => (voxel, VoxelTypeId::DIRT),
+        false => (voxel, VoxelTypeId::AIR),
+      })
+      .collect();
+    let mut world = World::new();
+    let entity = world.spawn().insert(ChunkVoxelData { voxels }).id();
+    let mut tracker = ChunkTracker::default();
+    tracker.register_entity(chunk, entity);
+    world.insert_resource(layout.clone());
+    world.insert_resource(tracker);
+    world.insert_resource(VoxelRegistry::default());
+    world.insert_resource(WorldGenConfig::default());
+    world
+  }
+
+  fn voxel_type(seed: u64, voxel: &VoxelId) -> VoxelTypeId {
+    match (voxel.stable_hash() ^ seed) % 2 {
+      0 => VoxelTypeId::DIRT,
+      _ => VoxelTypeId::AIR,
+    }
+  }
+
+  proptest! {
+      #[test]
+      fn solid_cells_should_follow_the_loaded_voxels(seed in any::<u64>(), x in -4f32..4., y in -2f32..6., z in -4f32..4.) {
+          let (mut world, layout) = world_with_chunk(seed);
+          let mut state: SystemState<VoxelWorld> = SystemState::new(&mut world);
+          let voxel_world = state.get_mut(&mut world);
+
+          let voxel = layout.space_to_voxel(&Vec3::new(x, y, z));
+          let expected = if voxel.y() < 0 {
+              Some(true)
+          } else if voxel.y() >= layout.world_voxel_height() {
+              Some(false)
+          } else if layout.voxel_to_chunk(&voxel) == ChunkId::new(0, 0, 0) {
+              Some(voxel_type(seed, &voxel) == VoxelTypeId::DIRT)
+          } else {
+              None
+          };
+          prop_assert_eq!(voxel_world.is_solid(Vec3::new(x, y, z)), expected);
+      }
+
+      #[test]
+      fn overlapping_voxels_should_be_the_solid_ones_in_the_box(seed in any::<u64>(), min in prop::array::uniform3(-4f32..4.), size in prop::array::uniform3(0f32..3.)) {
+          let (mut world, layout) = world_with_chunk(seed);
+          let mut state: SystemState<VoxelWorld> = SystemState::new(&mut world);
+          let voxel_world = state.get_mut(&mut world);
+
+          let (min, max) = (Vec3::from(min), Vec3::from(min) + Vec3::from(size));
+          // the box is given corner to corner in any order
+          let found: HashSet<_> = voxel_world.overlapping_solid_voxels(max, min).collect();
+          let (low, high) = (layout.space_to_voxel(&min), layout.space_to_voxel(&max));
+          let expected: HashSet<_> = layout
+              .iter_chunk_voxels(&ChunkId::new(0, 0, 0))
+              .filter(|voxel| {
+                  (low.x()..=high.x()).contains(&voxel.x())
+                      && (low.y()..=high.y()).contains(&voxel.y())
+                      && (low.z()..=high.z()).contains(&voxel.z())
+              })
+              .map(|voxel| (voxel, voxel_type(seed, &voxel)))
+              .filter(|(_, voxel_type)| *voxel_type == VoxelTypeId::DIRT)
+              .collect();
+          prop_assert_eq!(found, expected);
+      }
+
+      #[test]
+      fn flat_ground_should_face_up(height in 1i64..24, x in -4i64..=4, z in -4i64..=4) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 4, 24);
+          let mut world = world_with_heights(&layout, |_, _| height);
+          let mut state: SystemState<TerrainQuery> = SystemState::new(&mut world);
+          let query = state.get_mut(&mut world);
+
+          let at = layout.voxel_center(&VoxelId::new(x, 0, z));
+          let normal = query.surface_normal(at.x, at.z).expect("the column is loaded");
+          prop_assert!((normal - Vec3::Y).length() < 1e-5, "{:?}", normal);
+          prop_assert!(query.slope_degrees(at.x, at.z).unwrap().abs() < 1e-3);
+      }
+
+      // inner columns have both neighbors, the ones at the edge of the loaded chunk only one
+      #[test]
+      fn ramps_should_slope_by_their_rise(rise in 0i64..3, along_x in any::<bool>(), x in -4i64..=4, z in -4i64..=4) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 4, 24);
+          let mut world = world_with_heights(&layout, |x, z| 12 + rise * if along_x { x } else { z });
+          let mut state: SystemState<TerrainQuery> = SystemState::new(&mut world);
+          let query = state.get_mut(&mut world);
+
+          let at = layout.voxel_center(&VoxelId::new(x, 0, z));
+          let normal = query.surface_normal(at.x, at.z).expect("the column is loaded");
+          let expected = match along_x {
+              true => Vec3::new(-rise as f32, 1., 0.),
+              false => Vec3::new(0., 1., -rise as f32),
+          }
+          .normalize();
+          prop_assert!((normal - expected).length() < 1e-5, "{:?} {:?}", normal, expected);
+          let slope = query.slope_degrees(at.x, at.z).unwrap();
+          prop_assert!((slope - (rise as f32).atan().to_degrees()).abs() < 1e-2, "{}", slope);
+      }
+  }
+}
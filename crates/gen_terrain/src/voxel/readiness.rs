@@ -0,0 +1,187 @@
+use super::{
+  layout::CubicVoxelLayout,
+  lod::DataOnlyChunk,
+  prediction::ChunkSpawnerConfig,
+  tile::TileChunk,
+  tracker::ChunkTracker,
+  visibility::{EmptyChunk, EnclosedChunk},
+  world::{TerrainWorld, TerrainWorlds},
+  ChunkSpawner, ChunkVoxelData,
+};
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// how much of the terrain around each spawner is ready to be shown, e.g. to keep a loading
+// screen up until the player has ground to stand on
+// a chunk is ready once it has a mesh or tile map or won't get one: it's empty, walled in, or a
+// data-only chunk whose voxels arrived
+pub struct TerrainReadiness {
+  // rings of chunk columns around each spawner that have to be ready, in every section the
+  // spawner loads
+  pub radius: i64,
+  // the fraction of ready chunks at which a spawner's area counts as ready
+  pub threshold: f32,
+  spawners: HashMap<Entity, f32>,
+  initial_area_ready: bool,
+}
+impl Default for TerrainReadiness {
+  fn default() -> Self {
+    Self {
+      radius: 2,
+      threshold: 1.,
+      spawners: HashMap::new(),
+      initial_area_ready: false,
+    }
+  }
+}
+
+impl TerrainReadiness {
+  // ready chunks around `spawner` in [0, 1]
+  pub fn fraction(&self, spawner: Entity) -> Option<f32> {
+    self.spawners.get(&spawner).copied()
+  }
+
+  // the fraction of the spawner that's furthest from ready, 0 without spawners
+  pub fn lowest_fraction(&self) -> f32 {
+    self
+      .spawners
+      .values()
+      .copied()
+      .reduce(f32::min)
+      .unwrap_or(0.)
+  }
+
+  pub fn is_ready(&self, spawner: Entity) -> bool {
+    matches!(self.fraction(spawner), Some(fraction) if fraction >= self.threshold)
+  }
+
+  // set once every spawner's area was ready at the same time and stays set until `reset`
+  pub fn initial_area_ready(&self) -> bool {
+    self.initial_area_ready
+  }
+
+  // waits for the area to be ready again, e.g. after teleporting the player
+  pub fn reset(&mut self) {
+    self.initial_area_ready = false;
+  }
+}
+
+// sent when the area around a spawner becomes ready or stops being ready
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainReadinessChanged {
+  pub spawner: Entity,
+  pub ready: bool,
+  pub fraction: f32,
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn update_terrain_readiness(
+  mut readiness: ResMut<TerrainReadiness>,
+  layout: Res<CubicVoxelLayout>,
+  spawner_config: Res<ChunkSpawnerConfig>,
+  tracker: Res<ChunkTracker>,
+  worlds: Res<TerrainWorlds>,
+  mut changed: EventWriter<TerrainReadinessChanged>,
+  spawners: Query<(Entity, &ChunkSpawner, Option<&TerrainWorld>)>,
+  chunks: Query<(
+    Option<&Handle<Mesh>>,
+    Option<&ChunkVoxelData>,
+    Option<&EmptyChunk>,
+    Option<&EnclosedChunk>,
+    Option<&DataOnlyChunk>,
+    Option<&TileChunk>,
+  )>,
+) {
+  let is_ready = |entity: Entity| match chunks.get(entity) {
+    Ok((mesh, voxels, empty, enclosed, data_only, tiles)) => {
+      mesh.is_some()
+        || tiles.is_some()
+        || empty.is_some()
+        || enclosed.is_some()
+        || (data_only.is_some() && voxels.is_some())
+    }
+    Err(_) => false,
+  };
+
+  let mut fractions = HashMap::new();
+  for (spawner, site, world) in spawners.iter() {
+    let tracker = worlds.tracker(&world.copied().unwrap_or_default(), &tracker);
+    let fraction = match (site.last_loaded_chunk, tracker) {
+      (Some(center), Some(tracker)) => {
+        let area: Vec<_> = layout
+          .iter_chunks_spiral(&center, readiness.radius)
+          .flat_map(|column| layout.get_column_sections(&column, spawner_config.vertical_radius))
+          .collect();
+        let ready = area
+          .iter()
+          .filter(|chunk| matches!(tracker.entity(chunk), Some(entity) if is_ready(entity)))
+          .count();
+        ready as f32 / area.len().max(1) as f32
+      }
+      _ => 0.,
+    };
+
+    let was_ready = readiness.is_ready(spawner);
+    let ready = fraction >= readiness.threshold;
+    if ready != was_ready {
+      changed.send(TerrainReadinessChanged {
+        spawner,
+        ready,
+        fraction,
+      });
+    }
+    fractions.insert(spawner, fraction);
+  }
+
+  // forgets despawned spawners
+  readiness.spawners = fractions;
+  if !readiness.spawners.is_empty() && readiness.lowest_fraction() >= readiness.threshold {
+    readiness.initial_area_ready = true;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::layout::ChunkId;
+  use bevy::ecs::event::Events;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn readiness_should_follow_meshed_chunks(meshed in prop::collection::vec(any::<bool>(), 9)) {
+          let mut world = World::new();
+          world.insert_resource(TerrainReadiness { radius: 1, ..Default::default() });
+          world.insert_resource(CubicVoxelLayout::default());
+          world.insert_resource(ChunkSpawnerConfig::default());
+          world.insert_resource(TerrainWorlds::default());
+          world.insert_resource(Events::<TerrainReadinessChanged>::default());
+
+          let center = ChunkId::new(3, 0, -2);
+          let layout = CubicVoxelLayout::default();
+          let mut tracker = ChunkTracker::default();
+          for (chunk, meshed) in layout.iter_chunks_spiral(&center, 1).zip(meshed.iter()) {
+              let mut entity = world.spawn();
+              if *meshed {
+                  entity.insert(Handle::<Mesh>::default());
+              }
+              tracker.register_entity(chunk, entity.id());
+          }
+          world.insert_resource(tracker);
+          let spawner = world
+              .spawn()
+              .insert(ChunkSpawner { last_loaded_chunk: Some(center), ..Default::default() })
+              .id();
+
+          let mut stage = SystemStage::single_threaded().with_system(update_terrain_readiness);
+          stage.run(&mut world);
+
+          let readiness = world.get_resource::<TerrainReadiness>().unwrap();
+          let expected = meshed.iter().filter(|meshed| **meshed).count() as f32 / 9.;
+          prop_assert_eq!(readiness.fraction(spawner), Some(expected));
+          prop_assert_eq!(readiness.initial_area_ready(), expected >= 1.);
+          let events = world.get_resource::<Events<TerrainReadinessChanged>>().unwrap();
+          prop_assert_eq!(events.iter_current_update_events().count(), usize::from(expected >= 1.));
+      }
+  }
+}
@@ -0,0 +1,70 @@
+use super::{
+  cache::{ChunkMeshCache, ChunkVoxelCache},
+  generator::WorldGenConfig,
+  jobs::TerrainJobs,
+  meta::VoxelMeta,
+  snapshot::ApplyWorldSnapshot,
+  store::ChunkStore,
+  tracker::ChunkTracker,
+  world::TerrainWorlds,
+  Chunk, ChunkSpawner,
+};
+use bevy::prelude::*;
+
+// throws away all loaded chunks so they're generated again, e.g. after tweaking generation
+// parameters, changing `WorldGenConfig` sends this implicitly
+// edits recorded in `ChunkDiffs` are kept and applied to the regenerated chunks, decals are
+// dropped since they were painted on the old terrain, see `ChunkStore::drop_saved_decals`
+// the chunks of the primary world that were loaded are regenerated as a `TerrainJob`
+#[derive(Debug, Default)]
+pub struct RegenerateTerrain;
+
+#[allow(clippy::too_many_arguments)]
+pub fn regenerate_terrain(
+  mut commands: Commands,
+  config: Res<WorldGenConfig>,
+  mut events: EventReader<RegenerateTerrain>,
+  mut tracker: ResMut<ChunkTracker>,
+  mut worlds: ResMut<TerrainWorlds>,
+  mut mesh_cache: ResMut<ChunkMeshCache>,
+  mut voxel_cache: ResMut<ChunkVoxelCache>,
+  mut jobs: ResMut<TerrainJobs>,
+  mut meta: ResMut<VoxelMeta>,
+  mut store: ResMut<ChunkStore>,
+  mut snapshots: EventReader<ApplyWorldSnapshot>,
+  chunks: Query<Entity, With<Chunk>>,
+  mut sites: Query<&mut ChunkSpawner>,
+) {
+  let requested = events.iter().count() > 0;
+  // decals that came with the new config in a snapshot belong to the new terrain
+  let from_snapshot = snapshots.iter().count() > 0;
+  let config_changed = config.is_changed() && !config.is_added();
+  if !requested && !config_changed {
+    return;
+  }
+  info!("regenerating terrain");
+  if !tracker.is_empty() {
+    jobs.submit("regenerating terrain", tracker.iter().copied(), 0);
+  }
+
+  // results of tasks still in flight are dropped once their entity is gone
+  for entity in chunks.iter() {
+    commands.entity(entity).despawn_recursive();
+  }
+  tracker.clear();
+  worlds.clear_trackers();
+  mesh_cache.clear();
+  voxel_cache.clear();
+
+  if !from_snapshot {
+    for chunk in meta.clear_decals() {
+      store.mark_dirty(chunk);
+    }
+    store.drop_saved_decals();
+  }
+
+  // spawners load their surroundings again on the next update
+  for mut site in sites.iter_mut() {
+    site.last_loaded_chunk = None;
+  }
+}
@@ -0,0 +1,885 @@
+use super::{
+  biome::ClimateMap,
+  error::TerrainError,
+  generator::{sample_noise, HeightMap, WorldGenConfig},
+  seed::ChunkRng,
+  ChunkId,
+};
+use noise::{Fbm, MultiFractal, Seedable};
+use serde::{Deserialize, Serialize};
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  fmt,
+  sync::{Arc, Condvar, Mutex, RwLock},
+};
+
+// keeps the continent noise independent of the height and climate noise
+const CONTINENT_SEED_TAG: u64 = 0x0c0a_57a1;
+// regions kept in a `WorldRegions`, the ones generated first are dropped first
+const MAX_CACHED_REGIONS: usize = 64;
+// rivers fall as a waterfall where their water level drops by at least this many voxels
+const WATERFALL_DROP: f64 = 2.0;
+
+// features larger than a chunk, continents, rivers and roads, worked out on a coarse grid one
+// region at a time so every chunk of a region samples the same precomputed features
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldRegionSettings {
+  // voxel columns between the points of a region's grid
+  pub cell_size: i64,
+  // grid cells along each side of a region
+  pub cells: i64,
+  // voxels per unit of continent noise
+  pub continent_scale: f64,
+  // continent values below this are sea, continent values are in [-1, 1]
+  pub sea_level: f64,
+  // voxels the surface is raised per unit of continent value above the sea level, and lowered
+  // per unit below it
+  pub continent_height: f64,
+  // river sources picked per region, sources at sea are dropped
+  pub rivers_per_region: usize,
+  // rivers are this many voxels wide and cut up to `river_depth` voxels into the surface
+  pub river_width: f64,
+  pub river_depth: f64,
+  // points of interest picked per region and connected by roads, e.g. the bases of an RTS map,
+  // sites at sea are dropped, 0 for a world without roads
+  pub road_sites_per_region: usize,
+  // the ground within `road_width` voxels of a road is flattened to it and blends back into the
+  // terrain over `road_shoulder` more voxels
+  pub road_width: f64,
+  pub road_shoulder: f64,
+}
+impl Default for WorldRegionSettings {
+  fn default() -> Self {
+    Self {
+      cell_size: 16,
+      cells: 32,
+      continent_scale: 2048.0,
+      sea_level: -0.2,
+      continent_height: 16.0,
+      rivers_per_region: 4,
+      river_width: 3.0,
+      river_depth: 4.0,
+      road_sites_per_region: 0,
+      road_width: 2.0,
+      road_shoulder: 3.0,
+    }
+  }
+}
+
+impl WorldRegionSettings {
+  pub fn validate(&self) -> Result<(), TerrainError> {
+    if self.cell_size <= 0 || self.cells <= 0 {
+      return Err(TerrainError::InvalidConfig(
+        "region cells and their size should be positive",
+      ));
+    }
+    if !(self.continent_scale.is_finite() && self.continent_scale > 0.) {
+      return Err(TerrainError::InvalidConfig(
+        "continent scale should be positive",
+      ));
+    }
+    // rivers are only looked up in the regions next to the one they start in
+    if !(self.river_width >= 0. && self.river_width <= self.cell_size as f64) {
+      return Err(TerrainError::InvalidConfig(
+        "river width should be between 0 and the region cell size",
+      ));
+    }
+    // and so are roads
+    if !(self.road_width >= 0.
+      && self.road_shoulder >= 0.
+      && self.road_width + self.road_shoulder <= self.cell_size as f64)
+    {
+      return Err(TerrainError::InvalidConfig(
+        "road width and shoulder should add up to between 0 and the region cell size",
+      ));
+    }
+    if !(self.sea_level.is_finite()
+      && self.continent_height.is_finite()
+      && self.river_depth.is_finite())
+    {
+      return Err(TerrainError::InvalidConfig(
+        "sea level, continent height and river depth should be finite",
+      ));
+    }
+    Ok(())
+  }
+
+  // voxel columns along each side of a region
+  pub fn region_size(&self) -> i64 {
+    self.cell_size * self.cells
+  }
+
+  // the region the column at voxel (x, z) is in
+  pub fn region_of(&self, x: i64, z: i64) -> (i64, i64) {
+    let size = self.region_size().max(1);
+    (x.div_euclid(size), z.div_euclid(size))
+  }
+}
+
+// the continent noise of a world, values below `WorldRegionSettings::sea_level` are sea
+struct ContinentMap {
+  noise: Fbm,
+  scale: f64,
+  wrapping: Option<(i64, i64)>,
+}
+
+impl ContinentMap {
+  fn new(config: &WorldGenConfig, settings: &WorldRegionSettings) -> Self {
+    Self {
+      noise: Fbm::new()
+        .set_seed((config.seed ^ CONTINENT_SEED_TAG) as u32)
+        .set_octaves(4),
+      scale: settings.continent_scale,
+      wrapping: config.wrapping,
+    }
+  }
+
+  fn get(&self, x: f64, z: f64) -> f64 {
+    sample_noise(&self.noise, x, z, self.scale, self.wrapping)
+  }
+}
+
+// the id the rivers, roads and sites of a region are drawn for
+// in a wrapping world the regions past its edge draw the ones of the regions they're an image of,
+// so rivers and roads line up across the edge as long as the world is a whole number of regions
+// across
+fn region_seed(
+  config: &WorldGenConfig,
+  settings: &WorldRegionSettings,
+  region: (i64, i64),
+) -> ChunkId {
+  let size = settings.region_size().max(1);
+  let (x, z) = match config.wrapping {
+    Some((period_x, period_z)) => (
+      region.0.rem_euclid(((period_x + size - 1) / size).max(1)),
+      region.1.rem_euclid(((period_z + size - 1) / size).max(1)),
+    ),
+    None => region,
+  };
+  ChunkId::new(x, 0, z)
+}
+
+// the column at voxel (x, z) within the world, columns of a wrapping world are repeated after its
+// size
+fn wrap_column(config: &WorldGenConfig, x: i64, z: i64) -> (i64, i64) {
+  match config.wrapping {
+    Some((period_x, period_z)) => (x.rem_euclid(period_x.max(1)), z.rem_euclid(period_z.max(1))),
+    None => (x, z),
+  }
+}
+
+// what a region knows about a column
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RegionSample {
+  // in [-1, 1], land above the sea level
+  pub continent: f64,
+  // in [-1, 1], like `ClimateMap`
+  pub temperature: f64,
+  pub moisture: f64,
+  // 1 in the middle of a river fading to 0 at its banks, 0 away from rivers
+  pub river: f64,
+  // the water of the nearest river, for columns in a river
+  pub flow: Option<RiverFlow>,
+  // 1 on a road fading to 0 across its shoulder, 0 away from roads
+  pub road: f64,
+  // height in voxels of the nearest road, for columns on a road or its shoulder
+  pub road_level: Option<f64>,
+}
+
+// the water of a river at a column
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RiverFlow {
+  // height of the water surface in voxels
+  pub level: f64,
+  // height the riverbed is carved down from, the level itself except under waterfalls where it's
+  // the level below the fall so the water stands in a column from there up to the level above
+  pub bed: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RiverSegment {
+  // the voxel (x, z) of its ends
+  ends: [(f64, f64); 2],
+  level: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RoadSegment {
+  // the voxel (x, z) of its ends and the height of the road there
+  ends: [(f64, f64); 2],
+  levels: [f64; 2],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Waterfall {
+  point: (f64, f64),
+  flow: RiverFlow,
+}
+
+// the precomputed features of one region, the grid points on its far edges are shared with the
+// next regions so sampling is seamless across region borders
+pub struct WorldRegion {
+  pub id: (i64, i64),
+  settings: WorldRegionSettings,
+  wrapping: Option<(i64, i64)>,
+  // voxel column of the first grid point
+  origin: (i64, i64),
+  // grid points row by row, `cells + 1` to a row
+  continent: Vec<f64>,
+  temperature: Vec<f64>,
+  moisture: Vec<f64>,
+  // the river segments and waterfalls within reach of each cell
+  rivers: Vec<Vec<RiverSegment>>,
+  waterfalls: Vec<Vec<Waterfall>>,
+  // the road segments within reach of each cell
+  roads: Vec<Vec<RoadSegment>>,
+}
+
+impl WorldRegion {
+  pub fn generate(config: &WorldGenConfig, settings: &WorldRegionSettings, id: (i64, i64)) -> Self {
+    let continents = ContinentMap::new(config, settings);
+    let climate = ClimateMap::new(config);
+    let (cells, cell_size) = (settings.cells, settings.cell_size);
+    let origin = (id.0 * settings.region_size(), id.1 * settings.region_size());
+
+    let points = (cells + 1) as usize;
+    let mut region = Self {
+      id,
+      settings: settings.clone(),
+      wrapping: config.wrapping,
+      origin,
+      continent: Vec::with_capacity(points * points),
+      temperature: Vec::with_capacity(points * points),
+      moisture: Vec::with_capacity(points * points),
+      rivers: vec![Vec::new(); (cells * cells) as usize],
+      waterfalls: vec![Vec::new(); (cells * cells) as usize],
+      roads: vec![Vec::new(); (cells * cells) as usize],
+    };
+    for gz in 0..=cells {
+      for gx in 0..=cells {
+        let (x, z) = (origin.0 + gx * cell_size, origin.1 + gz * cell_size);
+        region.continent.push(continents.get(x as f64, z as f64));
+        region.temperature.push(climate.temperature(x, z));
+        region.moisture.push(climate.moisture(x, z));
+      }
+    }
+
+    // rivers are shorter than a region so only the neighbors' rivers can reach into this one
+    // roads join the sites of neighboring regions and don't stray past the regions next to those
+    let river_reach = settings.river_width;
+    let road_reach = settings.road_width + settings.road_shoulder;
+    for dz in -1..=1 {
+      for dx in -1..=1 {
+        for road in trace_roads(config, settings, (id.0 + dx, id.1 + dz)) {
+          let levels = road_levels(config, settings, &road);
+          for (i, segment) in road.windows(2).enumerate() {
+            let segment = RoadSegment {
+              ends: [segment[0], segment[1]],
+              levels: [levels[i], levels[i + 1]],
+            };
+            for cell in region.cells_near(segment.ends, road_reach) {
+              region.roads[cell].push(segment);
+            }
+          }
+        }
+        for river in trace_rivers(config, settings, (id.0 + dx, id.1 + dz)) {
+          let levels = river_levels(config, settings, &river);
+          // a segment holds the water at its lower end so it never stands above the ground
+          for (i, segment) in river.windows(2).enumerate() {
+            let ends = [segment[0], segment[1]];
+            let level = levels[i + 1];
+            for cell in region.cells_near(ends, river_reach) {
+              region.rivers[cell].push(RiverSegment { ends, level });
+            }
+          }
+          for (i, point) in river.iter().enumerate().skip(1) {
+            let (upper, lower) = (levels[i], levels[(i + 1).min(levels.len() - 1)]);
+            if upper - lower < WATERFALL_DROP {
+              continue;
+            }
+            let waterfall = Waterfall {
+              point: *point,
+              flow: RiverFlow {
+                level: upper,
+                bed: lower,
+              },
+            };
+            for cell in region.cells_near([*point, *point], river_reach) {
+              region.waterfalls[cell].push(waterfall);
+            }
+          }
+        }
+      }
+    }
+    region
+  }
+
+  // the cells of this region within `reach` of the segment
+  fn cells_near(&self, segment: [(f64, f64); 2], reach: f64) -> impl Iterator<Item = usize> {
+    let [(ax, az), (bx, bz)] = segment;
+    let (cell_size, cells) = (self.settings.cell_size as f64, self.settings.cells);
+    let cell = |value: f64, origin: i64| ((value - origin as f64) / cell_size).floor() as i64;
+    let (min_x, max_x) = (
+      cell(ax.min(bx) - reach, self.origin.0).max(0),
+      cell(ax.max(bx) + reach, self.origin.0).min(cells - 1),
+    );
+    let (min_z, max_z) = (
+      cell(az.min(bz) - reach, self.origin.1).max(0),
+      cell(az.max(bz) + reach, self.origin.1).min(cells - 1),
+    );
+    (min_z..=max_z).flat_map(move |gz| (min_x..=max_x).map(move |gx| (gz * cells + gx) as usize))
+  }
+
+  // the column at voxel (x, z), columns outside the region are clamped to its border
+  pub fn sample(&self, x: i64, z: i64) -> RegionSample {
+    let (cells, cell_size) = (self.settings.cells, self.settings.cell_size);
+    let local = |value: i64, origin: i64| {
+      let offset = (value - origin).clamp(0, cells * cell_size - 1);
+      (
+        offset / cell_size,
+        (offset % cell_size) as f64 / cell_size as f64,
+      )
+    };
+    let ((gx, tx), (gz, tz)) = (local(x, self.origin.0), local(z, self.origin.1));
+    let points = cells + 1;
+    let bilinear = |grid: &[f64]| {
+      let at = |gx: i64, gz: i64| grid[(gz * points + gx) as usize];
+      let top = at(gx, gz) * (1. - tx) + at(gx + 1, gz) * tx;
+      let bottom = at(gx, gz + 1) * (1. - tx) + at(gx + 1, gz + 1) * tx;
+      top * (1. - tz) + bottom * tz
+    };
+
+    let center = (x as f64 + 0.5, z as f64 + 0.5);
+    let cell = (gz * cells + gx) as usize;
+    let nearest = self.rivers[cell]
+      .iter()
+      .map(|segment| (segment_distance(center, segment.ends), segment.level))
+      .fold(
+        None,
+        |nearest: Option<(f64, f64)>, (distance, level)| match nearest {
+          Some((closest, _)) if closest <= distance => nearest,
+          _ => Some((distance, level)),
+        },
+      );
+    let width = self.settings.river_width;
+    let (river, flow) = match nearest {
+      Some((distance, level)) if width > 0. && distance < width => {
+        let waterfall = self.waterfalls[cell]
+          .iter()
+          .find(|waterfall| segment_distance(center, [waterfall.point; 2]) < width);
+        let flow = match waterfall {
+          Some(waterfall) => waterfall.flow,
+          None => RiverFlow { level, bed: level },
+        };
+        (1. - distance / width, Some(flow))
+      }
+      _ => (0., None),
+    };
+
+    let nearest = self.roads[cell]
+      .iter()
+      .map(|segment| {
+        let (distance, t) = segment_projection(center, segment.ends);
+        let [from, to] = segment.levels;
+        (distance, from + (to - from) * t)
+      })
+      .fold(
+        None,
+        |nearest: Option<(f64, f64)>, (distance, level)| match nearest {
+          Some((closest, _)) if closest <= distance => nearest,
+          _ => Some((distance, level)),
+        },
+      );
+    let (width, shoulder) = (self.settings.road_width, self.settings.road_shoulder);
+    let (road, road_level) = match nearest {
+      Some((distance, level)) if distance < width => (1., Some(level)),
+      Some((distance, level)) if distance < width + shoulder => {
+        (1. - (distance - width) / shoulder, Some(level))
+      }
+      _ => (0., None),
+    };
+    RegionSample {
+      continent: bilinear(&self.continent),
+      temperature: bilinear(&self.temperature),
+      moisture: bilinear(&self.moisture),
+      river,
+      flow,
+      road,
+      road_level,
+    }
+  }
+}
+
+// the rivers starting in a region as the voxel (x, z) of their points from source to mouth
+// rivers run down the continent noise toward the sea one cell at a time, meandering a bit, and
+// end at the sea or after `cells - 1` steps, so they never reach past the neighboring regions
+pub fn trace_rivers(
+  config: &WorldGenConfig,
+  settings: &WorldRegionSettings,
+  region: (i64, i64),
+) -> Vec<Vec<(f64, f64)>> {
+  let continents = ContinentMap::new(config, settings);
+  let mut rng = ChunkRng::new(
+    config.seed,
+    &region_seed(config, settings, region),
+    "rivers",
+  );
+  let size = settings.region_size() as f64;
+  let step = settings.cell_size as f64;
+
+  let mut rivers = Vec::new();
+  for _ in 0..settings.rivers_per_region {
+    let mut point = (
+      (region.0 as f64 + rng.next_f32() as f64) * size,
+      (region.1 as f64 + rng.next_f32() as f64) * size,
+    );
+    // drawn for every river so dropped ones don't shift the next ones
+    let meander = rng.next_u64();
+    if continents.get(point.0, point.1) < settings.sea_level {
+      continue;
+    }
+
+    let mut bends = ChunkRng::new(meander, &ChunkId::default(), "meander");
+    let mut river = vec![point];
+    for _ in 0..settings.cells - 1 {
+      let slope = (
+        continents.get(point.0 + step, point.1) - continents.get(point.0 - step, point.1),
+        continents.get(point.0, point.1 + step) - continents.get(point.0, point.1 - step),
+      );
+      let length = (slope.0 * slope.0 + slope.1 * slope.1).sqrt();
+      if length < f64::EPSILON {
+        break;
+      }
+      let angle = slope.1.atan2(slope.0) + std::f64::consts::PI + bends.range_f32(-0.6..0.6) as f64;
+      point = (point.0 + angle.cos() * step, point.1 + angle.sin() * step);
+      river.push(point);
+      if continents.get(point.0, point.1) < settings.sea_level {
+        break;
+      }
+    }
+    if river.len() > 1 {
+      rivers.push(river);
+    }
+  }
+  rivers
+}
+
+// the water level of a river at each of its points, rivers are laid a voxel below the lowest ground
+// they crossed so far so they never run uphill
+pub fn river_levels(
+  config: &WorldGenConfig,
+  settings: &WorldRegionSettings,
+  river: &[(f64, f64)],
+) -> Vec<f64> {
+  let continents = ContinentMap::new(config, settings);
+  // the surface without rivers, a region sampling its own rivers can't go through the cache
+  let height_map = HeightMap::new(config);
+  let mut lowest = f64::INFINITY;
+  river
+    .iter()
+    .map(|&(x, z)| {
+      let ground = height_map.noise_height(x.floor() as i64, z.floor() as i64)
+        + settings.continent_height * (continents.get(x, z) - settings.sea_level);
+      lowest = lowest.min(ground);
+      lowest.floor() - 1.
+    })
+    .collect()
+}
+
+// the points of interest of a region as voxel (x, z), at least a cell away from its border
+pub fn road_sites(
+  config: &WorldGenConfig,
+  settings: &WorldRegionSettings,
+  region: (i64, i64),
+) -> Vec<(f64, f64)> {
+  let continents = ContinentMap::new(config, settings);
+  let mut rng = ChunkRng::new(
+    config.seed,
+    &region_seed(config, settings, region),
+    "road sites",
+  );
+  let (size, inset) = (settings.region_size() as f64, settings.cell_size as f64);
+  let span = (size - 2. * inset).max(0.);
+
+  let mut sites = Vec::new();
+  for _ in 0..settings.road_sites_per_region {
+    // both drawn for every site so dropped ones don't shift the next ones
+    let site = (
+      region.0 as f64 * size + inset + rng.next_f32() as f64 * span,
+      region.1 as f64 * size + inset + rng.next_f32() as f64 * span,
+    );
+    if continents.get(site.0, site.1) >= settings.sea_level {
+      sites.push(site);
+    }
+  }
+  sites
+}
+
+// the roads a region lays out as the voxel (x, z) of their points, every site joins the nearest
+// site placed before it, and the region joins its closest sites to the ones of the regions east
+// and south of it, so the sites of neighboring regions are connected across their border
+// roads bend a bit between their ends and are sampled about once per cell
+pub fn trace_roads(
+  config: &WorldGenConfig,
+  settings: &WorldRegionSettings,
+  region: (i64, i64),
+) -> Vec<Vec<(f64, f64)>> {
+  let sites = road_sites(config, settings, region);
+  let distance = |a: (f64, f64), b: (f64, f64)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+  let closest = |pairs: Vec<((f64, f64), (f64, f64))>| {
+    pairs
+      .into_iter()
+      .min_by(|(a, b), (c, d)| distance(*a, *b).total_cmp(&distance(*c, *d)))
+  };
+
+  let mut links = Vec::new();
+  for (i, site) in sites.iter().enumerate().skip(1) {
+    links.extend(closest(
+      sites[..i].iter().map(|other| (*other, *site)).collect(),
+    ));
+  }
+  for neighbor in [(region.0 + 1, region.1), (region.0, region.1 + 1)] {
+    let others = road_sites(config, settings, neighbor);
+    let pairs = sites
+      .iter()
+      .flat_map(|site| others.iter().map(move |other| (*site, *other)))
+      .collect();
+    links.extend(closest(pairs));
+  }
+
+  let mut bends = ChunkRng::new(config.seed, &region_seed(config, settings, region), "roads");
+  let step = settings.cell_size.max(1) as f64;
+  links
+    .into_iter()
+    .map(|(from, to)| {
+      // a quadratic curve through a control point pushed to the side of the straight line
+      let bend = bends.range_f32(-0.25..0.25) as f64;
+      let (dx, dz) = (to.0 - from.0, to.1 - from.1);
+      let control = (
+        (from.0 + to.0) * 0.5 - dz * bend,
+        (from.1 + to.1) * 0.5 + dx * bend,
+      );
+      let points = (distance(from, to) / step).ceil().max(1.) as usize;
+      (0..=points)
+        .map(|i| {
+          let t = i as f64 / points as f64;
+          let (a, b, c) = ((1. - t) * (1. - t), 2. * (1. - t) * t, t * t);
+          (
+            a * from.0 + b * control.0 + c * to.0,
+            a * from.1 + b * control.1 + c * to.1,
+          )
+        })
+        .collect()
+    })
+    .collect()
+}
+
+// the height of a road at each of its points, the ground along it smoothed over a few points so
+// the road climbs and falls gently
+pub fn road_levels(
+  config: &WorldGenConfig,
+  settings: &WorldRegionSettings,
+  road: &[(f64, f64)],
+) -> Vec<f64> {
+  const SMOOTHING: usize = 2;
+  let continents = ContinentMap::new(config, settings);
+  // the surface without roads, like `river_levels`
+  let height_map = HeightMap::new(config);
+  let ground: Vec<f64> = road
+    .iter()
+    .map(|&(x, z)| {
+      height_map.noise_height(x.floor() as i64, z.floor() as i64)
+        + settings.continent_height * (continents.get(x, z) - settings.sea_level)
+    })
+    .collect();
+  (0..ground.len())
+    .map(|i| {
+      let window = &ground[i.saturating_sub(SMOOTHING)..(i + SMOOTHING + 1).min(ground.len())];
+      window.iter().sum::<f64>() / window.len() as f64
+    })
+    .collect()
+}
+
+fn segment_distance(point: (f64, f64), segment: [(f64, f64); 2]) -> f64 {
+  segment_projection(point, segment).0
+}
+
+// the distance from a point to a segment and how far along the segment the closest point is
+fn segment_projection(point: (f64, f64), [(ax, az), (bx, bz)]: [(f64, f64); 2]) -> (f64, f64) {
+  let (dx, dz) = (bx - ax, bz - az);
+  let length = dx * dx + dz * dz;
+  let t = match length {
+    length if length > 0. => (((point.0 - ax) * dx + (point.1 - az) * dz) / length).clamp(0., 1.),
+    _ => 0.,
+  };
+  let (x, z) = (ax + dx * t - point.0, az + dz * t - point.1);
+  ((x * x + z * z).sqrt(), t)
+}
+
+type RegionKey = (u64, i64, i64);
+
+#[derive(Default)]
+struct RegionCache {
+  regions: HashMap<RegionKey, Arc<WorldRegion>>,
+  // keys from the oldest to the newest
+  order: VecDeque<RegionKey>,
+}
+
+// regions being generated, jobs that need one of them wait for it on the condvar
+#[derive(Default)]
+struct PendingRegions {
+  keys: Mutex<HashSet<RegionKey>>,
+  generated: Condvar,
+}
+
+// takes the key out of the pending regions once its region is cached, or its generation panicked
+struct PendingGuard<'a> {
+  pending: &'a PendingRegions,
+  key: RegionKey,
+}
+
+impl Drop for PendingGuard<'_> {
+  fn drop(&mut self) {
+    if let Ok(mut keys) = self.pending.keys.lock() {
+      keys.remove(&self.key);
+    }
+    self.pending.generated.notify_all();
+  }
+}
+
+// regions generated so far, shared by every chunk generation job, regions are generated on
+// first use by whichever job needs them first, other jobs wait for it instead of generating it too
+// changing the seed, the region settings or the size of a wrapping world generates them again
+#[derive(Clone, Default)]
+pub struct WorldRegions {
+  cache: Arc<RwLock<RegionCache>>,
+  pending: Arc<PendingRegions>,
+}
+
+impl WorldRegions {
+  // the region containing the column at voxel (x, z), `None` without region settings
+  pub fn region_at(&self, config: &WorldGenConfig, x: i64, z: i64) -> Option<Arc<WorldRegion>> {
+    let settings = config.regions.as_ref()?;
+    let (x, z) = wrap_column(config, x, z);
+    let id = settings.region_of(x, z);
+    let key = (config.seed, id.0, id.1);
+    let cached = || {
+      let cache = self.cache.read().unwrap();
+      let region = cache.regions.get(&key)?;
+      (region.settings == *settings && region.wrapping == config.wrapping).then(|| region.clone())
+    };
+    if let Some(region) = cached() {
+      return Some(region);
+    }
+
+    // the region is cached before its key is taken out of the pending ones, so it's either
+    // cached or pending while the pending keys are locked
+    let mut keys = self.pending.keys.lock().unwrap();
+    loop {
+      if let Some(region) = cached() {
+        return Some(region);
+      }
+      if keys.insert(key) {
+        break;
+      }
+      keys = self.pending.generated.wait(keys).unwrap();
+    }
+    drop(keys);
+    let _pending = PendingGuard {
+      pending: &self.pending,
+      key,
+    };
+
+    // generated without the locks so jobs that need other regions aren't held up
+    let region = Arc::new(WorldRegion::generate(config, settings, id));
+    let mut cache = self.cache.write().unwrap();
+    if cache.regions.insert(key, region.clone()).is_none() {
+      cache.order.push_back(key);
+    }
+    while cache.order.len() > MAX_CACHED_REGIONS {
+      if let Some(oldest) = cache.order.pop_front() {
+        cache.regions.remove(&oldest);
+      }
+    }
+    Some(region)
+  }
+
+  pub fn sample(&self, config: &WorldGenConfig, x: i64, z: i64) -> Option<RegionSample> {
+    let region = self.region_at(config, x, z)?;
+    let (x, z) = wrap_column(config, x, z);
+    Some(region.sample(x, z))
+  }
+
+  pub fn len(&self) -> usize {
+    self.cache.read().unwrap().regions.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl fmt::Debug for WorldRegions {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("WorldRegions")
+      .field("len", &self.len())
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  fn settings() -> WorldRegionSettings {
+    // small regions with plenty of land and rivers
+    WorldRegionSettings {
+      cell_size: 4,
+      cells: 8,
+      continent_scale: 256.,
+      sea_level: -0.5,
+      rivers_per_region: 8,
+      ..Default::default()
+    }
+  }
+
+  proptest! {
+      #[test]
+      fn rivers_should_cross_region_borders(seed in any::<u64>(), x in -200i64..200, z in -200i64..200) {
+          let settings = settings();
+          let config = WorldGenConfig { seed, regions: Some(settings.clone()), ..Default::default() };
+          let regions = WorldRegions::default();
+          let sample = regions.sample(&config, x, z).unwrap();
+
+          // every river that starts anywhere near the column, not just in the regions next to it
+          let (rx, rz) = settings.region_of(x, z);
+          let center = (x as f64 + 0.5, z as f64 + 0.5);
+          let distance = (-2..=2)
+              .flat_map(|dz| (-2..=2).map(move |dx| (rx + dx, rz + dz)))
+              .flat_map(|region| trace_rivers(&config, &settings, region))
+              .flat_map(|river| river.windows(2).map(|segment| [segment[0], segment[1]]).collect::<Vec<_>>())
+              .map(|segment| segment_distance(center, segment))
+              .fold(f64::INFINITY, f64::min);
+          let expected = (1. - distance / settings.river_width).max(0.);
+          prop_assert!((sample.river - expected).abs() < 1e-9);
+      }
+
+      #[test]
+      fn roads_should_be_flat_and_continuous_across_region_borders(seed in any::<u64>(), x in -200i64..200, z in -200i64..200) {
+          let settings = WorldRegionSettings { road_sites_per_region: 3, road_shoulder: 2., river_width: 0., ..settings() };
+          let config = WorldGenConfig { seed, regions: Some(settings.clone()), ..Default::default() };
+          let regions = WorldRegions::default();
+          let sample = regions.sample(&config, x, z).unwrap();
+
+          // every road laid out anywhere near the column, not just by the regions next to it
+          let (rx, rz) = settings.region_of(x, z);
+          let center = (x as f64 + 0.5, z as f64 + 0.5);
+          let distance = (-2..=2)
+              .flat_map(|dz| (-2..=2).map(move |dx| (rx + dx, rz + dz)))
+              .flat_map(|region| trace_roads(&config, &settings, region))
+              .flat_map(|road| road.windows(2).map(|segment| [segment[0], segment[1]]).collect::<Vec<_>>())
+              .map(|segment| segment_distance(center, segment))
+              .fold(f64::INFINITY, f64::min);
+          let on_road = distance < settings.road_width;
+          prop_assert_eq!(sample.road >= 1., on_road);
+          prop_assert_eq!(sample.road_level.is_some(), distance < settings.road_width + settings.road_shoulder);
+
+          // the ground on a road is the road
+          let height_map = HeightMap::new(&config);
+          if let (true, Some(level)) = (on_road, sample.road_level) {
+              prop_assert!((height_map.height(x, z) - level).abs() < 1e-9);
+              prop_assert!(height_map.on_road(x, z));
+          }
+      }
+
+      #[test]
+      fn rivers_should_never_run_uphill(seed in any::<u64>(), x in -10i64..10, z in -10i64..10) {
+          let settings = settings();
+          let config = WorldGenConfig { seed, regions: Some(settings.clone()), ..Default::default() };
+          for river in trace_rivers(&config, &settings, (x, z)) {
+              let levels = river_levels(&config, &settings, &river);
+              prop_assert_eq!(levels.len(), river.len());
+              prop_assert!(levels.windows(2).all(|pair| pair[1] <= pair[0]));
+          }
+
+          // water stands from the bed below a fall up to the level above it
+          let region = WorldRegion::generate(&config, &settings, (x, z));
+          let size = settings.region_size();
+          for column_z in z * size..(z + 1) * size {
+              for column_x in x * size..(x + 1) * size {
+                  let sample = region.sample(column_x, column_z);
+                  prop_assert_eq!(sample.flow.is_some(), sample.river > 0.);
+                  if let Some(flow) = sample.flow {
+                      prop_assert!(flow.bed <= flow.level);
+                  }
+              }
+          }
+      }
+
+      #[test]
+      fn region_grids_should_meet_at_their_borders(seed in any::<u64>(), x in -10i64..10, z in -10i64..10, along in 0i64..32) {
+          let settings = settings();
+          let config = WorldGenConfig { seed, regions: Some(settings.clone()), ..Default::default() };
+          // the first column of the region east of (x, z) and the last column of (x, z)
+          let size = settings.region_size();
+          let (east_x, column_z) = ((x + 1) * size, z * size + along);
+          let region = WorldRegion::generate(&config, &settings, (x, z));
+          let east = WorldRegion::generate(&config, &settings, (x + 1, z));
+          let (inside, next) = (region.sample(east_x - 1, column_z), east.sample(east_x, column_z));
+          // one column apart on the same grid
+          let slope = (settings.cell_size as f64).recip() * 2.;
+          prop_assert!((inside.continent - next.continent).abs() <= slope);
+          // a column past the border is clamped to it
+          prop_assert_eq!(region.sample(east_x + 5, column_z).continent, region.sample(east_x - 1, column_z).continent);
+      }
+
+      #[test]
+      fn wrapping_worlds_should_repeat_across_their_edge(seed in any::<u64>(), across in 2i64..5, x in 0i64..160, z in 0i64..160, roads in any::<bool>()) {
+          let settings = WorldRegionSettings { road_sites_per_region: usize::from(roads) * 3, ..settings() };
+          let size = settings.region_size();
+          let period = across * size;
+          let config = WorldGenConfig { seed, regions: Some(settings.clone()), wrapping: Some((period, period)), ..Default::default() };
+          let (x, z) = (x % period, z % period);
+
+          // the regions past the edge are images of the ones at the other side
+          let matches = |past: Vec<Vec<(f64, f64)>>, image: Vec<Vec<(f64, f64)>>| {
+              past.len() == image.len() && past.iter().zip(image.iter()).all(|(past, image)| {
+                  past.len() == image.len() && past.iter().zip(image.iter()).all(|(a, b)| {
+                      (a.0 - period as f64 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+                  })
+              })
+          };
+          prop_assert!(matches(trace_rivers(&config, &settings, (across, 0)), trace_rivers(&config, &settings, (0, 0))));
+          prop_assert!(matches(trace_roads(&config, &settings, (across, 0)), trace_roads(&config, &settings, (0, 0))));
+
+          let height_map = HeightMap::new(&config);
+          for (other_x, other_z) in [(x + period, z), (x, z - period), (x - period, z + period)] {
+              prop_assert!((height_map.height(x, z) - height_map.height(other_x, other_z)).abs() < 1e-6);
+              prop_assert_eq!(height_map.water_level(x, z).is_some(), height_map.water_level(other_x, other_z).is_some());
+          }
+      }
+  }
+
+  proptest! {
+      #![proptest_config(ProptestConfig::with_cases(8))]
+      #[test]
+      fn regions_should_be_generated_once_for_concurrent_jobs(seed in any::<u64>(), x in -200i64..200, z in -200i64..200) {
+          let config = WorldGenConfig { seed, regions: Some(settings()), ..Default::default() };
+          let regions = WorldRegions::default();
+          let jobs: Vec<_> = (0..4)
+              .map(|_| {
+                  let (config, regions) = (config.clone(), regions.clone());
+                  std::thread::spawn(move || regions.region_at(&config, x, z).unwrap())
+              })
+              .collect();
+          let generated: Vec<_> = jobs.into_iter().map(|job| job.join().unwrap()).collect();
+          // a region generated twice would be a different allocation
+          for region in generated.iter() {
+              prop_assert!(Arc::ptr_eq(region, &generated[0]));
+          }
+          prop_assert_eq!(regions.len(), 1);
+      }
+  }
+}
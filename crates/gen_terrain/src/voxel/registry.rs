@@ -0,0 +1,208 @@
+#[cfg(feature = "render")]
+use bevy::prelude::*;
+use std::sync::Arc;
+
+// a kind of voxel registered in the `VoxelRegistry`, ids are handed out in registration order
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VoxelTypeId(pub u16);
+
+// the built-in types are always registered first, in this order
+impl VoxelTypeId {
+  pub const AIR: VoxelTypeId = VoxelTypeId(0);
+  pub const DIRT: VoxelTypeId = VoxelTypeId(1);
+  pub const LAMP: VoxelTypeId = VoxelTypeId(2);
+  // stands in for voxels far below the surface of distant chunks that weren't generated, see
+  // `ChunkLodSettings::clip_rings`
+  pub const UNKNOWN: VoxelTypeId = VoxelTypeId(3);
+  pub const WATER: VoxelTypeId = VoxelTypeId(4);
+  // paves the roads of `WorldRegionSettings`
+  pub const ROAD: VoxelTypeId = VoxelTypeId(5);
+}
+
+#[derive(Debug, Clone)]
+pub struct VoxelTypeInfo {
+  // unique, saved worlds refer to voxel types by name so registration order can change
+  pub name: String,
+  // solid voxels are meshed and collided with
+  pub solid: bool,
+  // liquids are meshed like solids but things move through them, stand on the ground under them
+  // and don't grow or get placed on them
+  pub liquid: bool,
+  // transparent voxels let light through and don't hide the faces of voxels behind them
+  pub transparent: bool,
+  #[cfg(feature = "render")]
+  pub color: Color,
+  // tile of `TerrainMaterialConfig::atlas` drawn on the voxel
+  pub atlas_index: Option<u32>,
+  // how long the voxel takes to dig, games decide what it means
+  pub hardness: f32,
+  // light level the voxel emits, 0 for voxels that don't glow
+  pub light_emission: u8,
+}
+
+impl VoxelTypeInfo {
+  // a plain white solid voxel
+  pub fn new(name: &str) -> Self {
+    Self {
+      name: name.to_string(),
+      solid: true,
+      liquid: false,
+      transparent: false,
+      #[cfg(feature = "render")]
+      color: Color::WHITE,
+      atlas_index: None,
+      hardness: 1.0,
+      light_emission: 0,
+    }
+  }
+
+  #[cfg(feature = "render")]
+  pub fn solid(name: &str, color: Color) -> Self {
+    Self {
+      color,
+      ..Self::new(name)
+    }
+  }
+
+  // colors of the built-in types, they're left out without the `render` feature
+  #[cfg_attr(not(feature = "render"), allow(unused_variables))]
+  fn tinted(self, [r, g, b, a]: [f32; 4]) -> Self {
+    Self {
+      #[cfg(feature = "render")]
+      color: Color::rgba(r, g, b, a),
+      ..self
+    }
+  }
+}
+
+// every voxel type the terrain knows about, register types at startup before chunks load
+// cloning is cheap so generation and meshing tasks get their own copy
+#[derive(Debug, Clone)]
+pub struct VoxelRegistry {
+  types: Arc<Vec<VoxelTypeInfo>>,
+}
+impl Default for VoxelRegistry {
+  fn default() -> Self {
+    let mut registry = Self {
+      types: Arc::new(Vec::new()),
+    };
+    registry.register(VoxelTypeInfo {
+      solid: false,
+      transparent: true,
+      hardness: 0.,
+      ..VoxelTypeInfo::new("air").tinted([0., 0., 0., 0.])
+    });
+    registry.register(VoxelTypeInfo::new("dirt").tinted([0.5, 0.0, 0.3, 1.]));
+    registry.register(VoxelTypeInfo {
+      light_emission: 14,
+      ..VoxelTypeInfo::new("lamp").tinted([1.0, 0.9, 0.6, 1.])
+    });
+    registry.register(VoxelTypeInfo::new("unknown").tinted([0.2, 0.2, 0.2, 1.]));
+    // meshed like a solid, light and the faces behind it show through
+    registry.register(VoxelTypeInfo {
+      liquid: true,
+      transparent: true,
+      hardness: 0.,
+      ..VoxelTypeInfo::new("water").tinted([0.1, 0.3, 0.7, 1.])
+    });
+    registry.register(VoxelTypeInfo::new("road").tinted([0.45, 0.4, 0.35, 1.]));
+    registry
+  }
+}
+
+impl VoxelRegistry {
+  // registering a name again replaces its info and keeps its id
+  pub fn register(&mut self, info: VoxelTypeInfo) -> VoxelTypeId {
+    let types = Arc::make_mut(&mut self.types);
+    if let Some(index) = types.iter().position(|t| t.name == info.name) {
+      types[index] = info;
+      return VoxelTypeId(index as u16);
+    }
+    assert!(types.len() <= u16::MAX as usize, "too many voxel types");
+    types.push(info);
+    VoxelTypeId(types.len() as u16 - 1)
+  }
+
+  pub fn get(&self, id: VoxelTypeId) -> Option<&VoxelTypeInfo> {
+    self.types.get(id.0 as usize)
+  }
+
+  pub fn id_of(&self, name: &str) -> Option<VoxelTypeId> {
+    self
+      .types
+      .iter()
+      .position(|t| t.name == name)
+      .map(|index| VoxelTypeId(index as u16))
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (VoxelTypeId, &VoxelTypeInfo)> {
+    self
+      .types
+      .iter()
+      .enumerate()
+      .map(|(index, info)| (VoxelTypeId(index as u16), info))
+  }
+
+  pub fn len(&self) -> usize {
+    self.types.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.types.is_empty()
+  }
+
+  // unknown ids are treated as plain solid voxels so they stay visible
+  pub fn is_solid(&self, id: VoxelTypeId) -> bool {
+    !matches!(self.get(id), Some(info) if !info.solid)
+  }
+
+  // solid voxels that aren't liquid, what queries, collisions and placement treat as ground
+  pub fn is_collidable(&self, id: VoxelTypeId) -> bool {
+    !matches!(self.get(id), Some(info) if !info.solid || info.liquid)
+  }
+
+  // whether the voxel blocks light and hides the faces of its neighbors
+  pub fn is_opaque(&self, id: VoxelTypeId) -> bool {
+    !matches!(self.get(id), Some(info) if !info.solid || info.transparent)
+  }
+
+  pub fn light_emission(&self, id: VoxelTypeId) -> u8 {
+    self.get(id).map_or(0, |info| info.light_emission)
+  }
+
+  #[cfg(feature = "render")]
+  pub fn color(&self, id: VoxelTypeId) -> Color {
+    self.get(id).map_or(Color::WHITE, |info| info.color)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn registered_types_should_keep_their_ids(names in prop::collection::vec("[a-e]{1,2}", 1..20)) {
+          let mut registry = VoxelRegistry::default();
+          let builtin = registry.len();
+          let ids: Vec<_> = names
+              .iter()
+              .map(|name| registry.register(VoxelTypeInfo::new(name)))
+              .collect();
+
+          for (name, id) in names.iter().zip(ids) {
+              assert!(id.0 as usize >= builtin);
+              assert_eq!(registry.id_of(name), Some(id));
+              assert_eq!(&registry.get(id).unwrap().name, name);
+          }
+          assert_eq!(registry.id_of("air"), Some(VoxelTypeId::AIR));
+          assert_eq!(registry.id_of("dirt"), Some(VoxelTypeId::DIRT));
+          assert_eq!(registry.id_of("lamp"), Some(VoxelTypeId::LAMP));
+          assert_eq!(registry.id_of("water"), Some(VoxelTypeId::WATER));
+          assert_eq!(registry.id_of("road"), Some(VoxelTypeId::ROAD));
+          assert!(!registry.is_solid(VoxelTypeId::AIR));
+          assert!(registry.is_solid(VoxelTypeId::WATER) && !registry.is_collidable(VoxelTypeId::WATER));
+      }
+  }
+}
@@ -0,0 +1,44 @@
+use super::ChunkSpawner;
+
+// decides how long chunks stay loaded once they're out of range
+pub struct ChunkRetentionPolicy {
+  // rings of chunks beyond a spawner's spawn radius that are kept loaded, chunks farther than
+  // this from every spawner start their despawn countdown
+  pub despawn_ring_margin: i64,
+  // seconds a chunk stays loaded after spawning regardless of distance
+  pub min_resident_seconds: f64,
+  // seconds an out of range chunk is kept when spawners are standing still
+  pub base_grace_seconds: f32,
+  // extra seconds per world unit/second of spawner speed
+  pub grace_seconds_per_speed: f32,
+  // extra seconds per radian/second of spawner heading change
+  pub grace_seconds_per_turn_rate: f32,
+  pub max_grace_seconds: f32,
+}
+impl Default for ChunkRetentionPolicy {
+  fn default() -> Self {
+    Self {
+      despawn_ring_margin: 2,
+      min_resident_seconds: 5.0,
+      base_grace_seconds: 2.0,
+      grace_seconds_per_speed: 0.1,
+      grace_seconds_per_turn_rate: 1.0,
+      max_grace_seconds: 15.0,
+    }
+  }
+}
+
+impl ChunkRetentionPolicy {
+  // fast or turning spawners are likely to double back, so chunks they leave behind are kept
+  // longer to avoid regenerating them
+  pub fn grace_seconds<'a>(&self, spawners: impl Iterator<Item = &'a ChunkSpawner>) -> f32 {
+    spawners
+      .map(|site| {
+        self.base_grace_seconds
+          + site.velocity.length() * self.grace_seconds_per_speed
+          + site.turn_rate * self.grace_seconds_per_turn_rate
+      })
+      .fold(self.base_grace_seconds, f32::max)
+      .min(self.max_grace_seconds)
+  }
+}
@@ -0,0 +1,135 @@
+use super::ChunkId;
+use bevy::prelude::*;
+use std::ops::Range;
+
+// deterministic per-chunk seed, the same world seed always gives a chunk the same seed no matter
+// when or in which order it is loaded
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct ChunkSeed(pub u64);
+
+impl ChunkSeed {
+  pub fn new(world_seed: u64, chunk: &ChunkId) -> Self {
+    let x = mix(world_seed ^ chunk.x() as u64);
+    let z = mix(x ^ (chunk.z() as u64).rotate_left(32));
+    Self(mix(z ^ (chunk.y() as u64).rotate_left(16)))
+  }
+
+  // independent value for a single use, e.g. `seed.derive(PROP_PHASE)`
+  pub fn derive(&self, tag: u64) -> u64 {
+    mix(self.0 ^ mix(tag))
+  }
+
+  // uniform value in [0, 1) for a single use
+  pub fn unit(&self, tag: u64) -> f32 {
+    // the top 24 bits fit exactly in an f32 mantissa
+    (self.derive(tag) >> 40) as f32 / (1u64 << 24) as f32
+  }
+
+  // random stream for one purpose (e.g. "structures", "decorations") in this chunk
+  pub fn rng(&self, purpose: &str) -> ChunkRng {
+    ChunkRng {
+      state: self.derive(purpose_tag(purpose)),
+    }
+  }
+}
+
+// reproducible random stream derived from (world seed, chunk, purpose), so every pass gets the
+// same numbers regardless of chunk load order and passes don't disturb each other's streams
+#[derive(Debug, Clone)]
+pub struct ChunkRng {
+  state: u64,
+}
+
+impl ChunkRng {
+  pub fn new(world_seed: u64, chunk: &ChunkId, purpose: &str) -> Self {
+    ChunkSeed::new(world_seed, chunk).rng(purpose)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    mix(self.state)
+  }
+
+  // uniform in [0, 1)
+  pub fn next_f32(&mut self) -> f32 {
+    (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+  }
+
+  pub fn range_f32(&mut self, range: Range<f32>) -> f32 {
+    range.start + (range.end - range.start) * self.next_f32()
+  }
+
+  pub fn range_i64(&mut self, range: Range<i64>) -> i64 {
+    let span = range.end.wrapping_sub(range.start) as u64;
+    if span == 0 {
+      return range.start;
+    }
+    range.start.wrapping_add((self.next_u64() % span) as i64)
+  }
+
+  // true with the given probability
+  pub fn chance(&mut self, probability: f32) -> bool {
+    self.next_f32() < probability
+  }
+}
+
+// fnv-1a, std's hasher isn't guaranteed to be stable across releases
+fn purpose_tag(purpose: &str) -> u64 {
+  purpose.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+    (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+  })
+}
+
+// splitmix64 finalizer
+fn mix(mut z: u64) -> u64 {
+  z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+  z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+  z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+  z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn adjacent_chunks_should_have_distinct_seeds(world_seed in any::<u64>(), x in -10000i64..=10000, y in -10000i64..=10000) {
+          let chunk = ChunkId::new(x, 0, y);
+          let seed = ChunkSeed::new(world_seed, &chunk);
+          assert_eq!(seed, ChunkSeed::new(world_seed, &chunk));
+          for neighbor in chunk.adjacent() {
+              assert_ne!(seed, ChunkSeed::new(world_seed, &neighbor));
+          }
+      }
+
+      #[test]
+      fn chunk_rng_should_be_reproducible(world_seed in any::<u64>(), x in -10000i64..=10000, y in -10000i64..=10000) {
+          let chunk = ChunkId::new(x, 0, y);
+          let mut a = ChunkRng::new(world_seed, &chunk, "structures");
+          let mut b = ChunkRng::new(world_seed, &chunk, "structures");
+          let mut other = ChunkRng::new(world_seed, &chunk, "decorations");
+          let a: Vec<_> = (0..16).map(|_| a.next_u64()).collect();
+          let b: Vec<_> = (0..16).map(|_| b.next_u64()).collect();
+          let other: Vec<_> = (0..16).map(|_| other.next_u64()).collect();
+          assert_eq!(a, b);
+          assert_ne!(a, other);
+      }
+
+      #[test]
+      fn range_should_stay_in_bounds(seed in any::<u64>(), start in -1000i64..1000, len in 0i64..1000) {
+          let mut rng = ChunkSeed(seed).rng("test");
+          for _ in 0..16 {
+              let value = rng.range_i64(start..start + len);
+              assert!(len == 0 && value == start || (start..start + len).contains(&value));
+          }
+      }
+
+      #[test]
+      fn unit_should_be_in_range(seed in any::<u64>(), tag in any::<u64>()) {
+          let value = ChunkSeed(seed).unit(tag);
+          assert!((0.0..1.0).contains(&value));
+      }
+  }
+}
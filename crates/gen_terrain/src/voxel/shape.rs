@@ -0,0 +1,116 @@
+use super::{layout::CubicVoxelLayout, ChunkId};
+use bevy::prelude::*;
+
+// how much a spawner has to turn before chunks are loaded again for its new heading
+pub const LOAD_HEADING_TOLERANCE: f32 = 0.2;
+
+// which chunks around a spawner are loaded for a radius in chunk steps
+// e.g. a top-down camera loads a rectangle matching its viewport while a first person camera
+// loads a circle
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum LoadShape {
+  // every chunk within `radius` rings, what `get_chunk_neighbors` returns
+  #[default]
+  Square,
+  // chunks whose center is within `radius` chunks
+  Circle,
+  // a rectangle in front of the spawner roughly covering what a camera looking along its heading
+  // sees, `aspect` is the viewport width over its height
+  // it's `radius` chunks deep on either side of a center pushed forward by half the radius, so
+  // more is loaded in front of the camera than behind it
+  Frustum {
+    aspect: f32,
+  },
+}
+impl LoadShape {
+  // whether the column `dx`, `dz` chunks away from the spawner's column is loaded
+  // `heading` is the direction the spawner faces on the ground plane
+  pub fn contains(&self, dx: i64, dz: i64, radius: i64, heading: Vec2) -> bool {
+    let (x, z, r) = (dx as f32, dz as f32, radius as f32);
+    match self {
+      LoadShape::Square => dx.abs().max(dz.abs()) <= radius,
+      // the extra half chunk keeps the chunks on the axes at the edge
+      LoadShape::Circle => x * x + z * z <= (r + 0.5) * (r + 0.5),
+      LoadShape::Frustum { aspect } => {
+        let heading = heading.try_normalize().unwrap_or(-Vec2::Y);
+        let forward = x * heading.x + z * heading.y;
+        let right = x * -heading.y + z * heading.x;
+        (forward - r * 0.5).abs() <= r + 0.5 && right.abs() <= r * aspect.max(0.) + 0.5
+      }
+    }
+  }
+
+  // the farthest ring a chunk of the shape can be on
+  pub fn bounding_radius(&self, radius: i64) -> i64 {
+    match self {
+      LoadShape::Square | LoadShape::Circle => radius,
+      LoadShape::Frustum { aspect } => {
+        let r = radius as f32;
+        Vec2::new(r * 1.5 + 0.5, r * aspect.max(0.) + 0.5)
+          .length()
+          .ceil() as i64
+      }
+    }
+  }
+
+  pub fn depends_on_heading(&self) -> bool {
+    matches!(self, LoadShape::Frustum { .. })
+  }
+
+  // columns of the shape around `center` excluding `center` itself, nearest first
+  pub fn get_columns(
+    &self,
+    layout: &CubicVoxelLayout,
+    center: &ChunkId,
+    radius: i64,
+    heading: Vec2,
+  ) -> Vec<ChunkId> {
+    layout
+      .iter_chunks_spiral(center, self.bounding_radius(radius))
+      .skip(1)
+      .filter(|chunk| {
+        let (x, z) = layout.column_offset(center, chunk);
+        self.contains(x, z, radius, heading)
+      })
+      .collect()
+  }
+}
+
+// the direction a spawner faces on the ground plane, cameras looking straight down use the top
+// of the screen instead
+pub fn heading(transform: &Transform) -> Vec2 {
+  let forward = transform.forward();
+  let forward = Vec2::new(forward.x, forward.z);
+  if forward.length_squared() > 1e-4 {
+    return forward.normalize();
+  }
+  let up = transform.up();
+  Vec2::new(up.x, up.z).try_normalize().unwrap_or(-Vec2::Y)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn shapes_should_stay_within_bounding_radius(radius in 0i64..8, aspect in 0.1f32..4.0, angle in 0f32..6.3) {
+          let layout = CubicVoxelLayout::default();
+          let center = ChunkId::new(3, 0, -2);
+          let heading = Vec2::new(angle.cos(), angle.sin());
+          let far = 30;
+
+          for shape in [LoadShape::Square, LoadShape::Circle, LoadShape::Frustum { aspect }] {
+              let bound = shape.bounding_radius(radius);
+              assert!(shape.contains(0, 0, radius, heading));
+              for chunk in layout.get_chunk_neighbors(&center, far) {
+                  let (dx, dz) = (chunk.x() - center.x(), chunk.z() - center.z());
+                  if shape.contains(dx, dz, radius, heading) {
+                      assert!(dx.abs().max(dz.abs()) <= bound, "{:?} {} {}", shape, dx, dz);
+                  }
+              }
+          }
+      }
+  }
+}
@@ -0,0 +1,485 @@
+use super::{
+  decal::ChunkDecals,
+  edit::TerrainEdits,
+  generator::WorldGenConfig,
+  meta::{VoxelMeta, VoxelMetaEditor},
+  region::WorldRegionSettings,
+  registry::{VoxelRegistry, VoxelTypeId},
+  store::ChunkStore,
+  tracker::ChunkTracker,
+  ChunkId, DirtyChunk, VoxelId,
+};
+use bevy::prelude::*;
+use std::{
+  collections::{HashMap, HashSet},
+  fmt,
+};
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"VXDF";
+const SNAPSHOT_VERSION: u8 = 8;
+// version 7 snapshots have no decals
+const SNAPSHOT_VERSION_NO_DECALS: u8 = 7;
+// version 6 snapshots have no road settings, their worlds have no roads
+const SNAPSHOT_VERSION_NO_ROADS: u8 = 6;
+// version 5 snapshots have no region settings
+const SNAPSHOT_VERSION_NO_REGIONS: u8 = 5;
+// version 4 snapshots save voxel types as the ids of the built-in types
+const SNAPSHOT_VERSION_BUILTIN_TYPES: u8 = 4;
+// version 3 snapshots have no voxel metadata
+const SNAPSHOT_VERSION_NO_META: u8 = 3;
+// version 2 snapshots have no vertical sections, their chunks are all in section 0
+const SNAPSHOT_VERSION_FLAT: u8 = 2;
+// version 1 snapshots also only carry the seed, the rest of the config is left at its defaults
+const SNAPSHOT_VERSION_SEED_ONLY: u8 = 1;
+
+// every voxel that deviates from what the generator produces, grouped by owning chunk
+// edits are recorded here as they're applied so they survive chunks being despawned
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ChunkDiffs {
+  chunks: HashMap<ChunkId, HashMap<VoxelId, VoxelTypeId>>,
+}
+
+impl ChunkDiffs {
+  pub fn record(&mut self, chunk: ChunkId, voxel: VoxelId, voxel_type: VoxelTypeId) {
+    self
+      .chunks
+      .entry(chunk)
+      .or_default()
+      .insert(voxel, voxel_type);
+  }
+
+  pub fn get(&self, chunk: &ChunkId) -> Option<&HashMap<VoxelId, VoxelTypeId>> {
+    self.chunks.get(chunk)
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item = (&ChunkId, &HashMap<VoxelId, VoxelTypeId>)> {
+    self.chunks.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.chunks.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.chunks.is_empty()
+  }
+
+  pub fn clear(&mut self) {
+    self.chunks.clear();
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+  UnexpectedEnd,
+  BadMagic,
+  UnsupportedVersion(u8),
+  // saved by a newer version of the game than the one reading it
+  FutureVersion(u16),
+  // no `ChunkMigrator` upgrades chunks saved with this version
+  MissingMigration(u16),
+  UnknownVoxelType(u16),
+  // the voxel type isn't registered in the `VoxelRegistry` of the reader
+  UnknownVoxelName(String),
+  // the sender registered other voxel types, or registered them in another order
+  RegistryMismatch,
+  UnknownMessage(u8),
+  // decals are saved on one of the six faces of a voxel
+  UnknownDecalFace(u8),
+}
+impl fmt::Display for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      SnapshotError::UnexpectedEnd => write!(f, "snapshot ended unexpectedly"),
+      SnapshotError::BadMagic => write!(f, "not a terrain snapshot"),
+      SnapshotError::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {}", v),
+      SnapshotError::FutureVersion(v) => write!(f, "saved with newer format version {}", v),
+      SnapshotError::MissingMigration(v) => write!(f, "no migration from format version {}", v),
+      SnapshotError::UnknownVoxelType(t) => write!(f, "unknown voxel type {}", t),
+      SnapshotError::UnknownVoxelName(name) => write!(f, "voxel type {:?} isn't registered", name),
+      SnapshotError::RegistryMismatch => write!(f, "voxel types are registered differently"),
+      SnapshotError::UnknownMessage(tag) => write!(f, "unknown terrain message {}", tag),
+      SnapshotError::UnknownDecalFace(face) => write!(f, "unknown decal face {}", face),
+    }
+  }
+}
+impl std::error::Error for SnapshotError {}
+
+// what a late-joining client needs to rebuild the world: the generator config so it can
+// generate unedited chunks locally, plus the deviations from it and the voxel metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorldSnapshot {
+  pub config: WorldGenConfig,
+  pub diffs: ChunkDiffs,
+  pub meta: VoxelMeta,
+}
+
+impl WorldSnapshot {
+  pub fn capture(config: &WorldGenConfig, diffs: &ChunkDiffs, meta: &VoxelMeta) -> Self {
+    Self {
+      config: config.clone(),
+      diffs: diffs.clone(),
+      meta: meta.clone(),
+    }
+  }
+
+  pub fn to_bytes(&self, registry: &VoxelRegistry) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(SNAPSHOT_MAGIC);
+    bytes.push(SNAPSHOT_VERSION);
+    bytes.extend_from_slice(&self.config.seed.to_le_bytes());
+    bytes.extend_from_slice(&self.config.scale.to_le_bytes());
+    bytes.extend_from_slice(&self.config.base_height.to_le_bytes());
+    bytes.extend_from_slice(&self.config.amplitude.to_le_bytes());
+    bytes.extend_from_slice(&(self.config.octaves as u32).to_le_bytes());
+    bytes.extend_from_slice(&self.config.persistence.to_le_bytes());
+    bytes.extend_from_slice(&self.config.lacunarity.to_le_bytes());
+    match &self.config.regions {
+      Some(regions) => {
+        bytes.push(1);
+        bytes.extend_from_slice(&regions.cell_size.to_le_bytes());
+        bytes.extend_from_slice(&regions.cells.to_le_bytes());
+        bytes.extend_from_slice(&regions.continent_scale.to_le_bytes());
+        bytes.extend_from_slice(&regions.sea_level.to_le_bytes());
+        bytes.extend_from_slice(&regions.continent_height.to_le_bytes());
+        bytes.extend_from_slice(&(regions.rivers_per_region as u32).to_le_bytes());
+        bytes.extend_from_slice(&regions.river_width.to_le_bytes());
+        bytes.extend_from_slice(&regions.river_depth.to_le_bytes());
+        bytes.extend_from_slice(&(regions.road_sites_per_region as u32).to_le_bytes());
+        bytes.extend_from_slice(&regions.road_width.to_le_bytes());
+        bytes.extend_from_slice(&regions.road_shoulder.to_le_bytes());
+      }
+      None => bytes.push(0),
+    }
+    VoxelPalette::write(&mut bytes, registry);
+    bytes.extend_from_slice(&(self.diffs.len() as u32).to_le_bytes());
+    for (chunk, voxels) in self.diffs.iter() {
+      bytes.extend_from_slice(&chunk.x().to_le_bytes());
+      bytes.extend_from_slice(&chunk.y().to_le_bytes());
+      bytes.extend_from_slice(&chunk.z().to_le_bytes());
+      bytes.extend_from_slice(&(voxels.len() as u32).to_le_bytes());
+      for (voxel, voxel_type) in voxels {
+        bytes.extend_from_slice(&voxel.x().to_le_bytes());
+        bytes.extend_from_slice(&voxel.y().to_le_bytes());
+        bytes.extend_from_slice(&voxel.z().to_le_bytes());
+        bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+      }
+    }
+    bytes.extend_from_slice(&(self.meta.len() as u32).to_le_bytes());
+    for (chunk, meta) in self.meta.iter() {
+      bytes.extend_from_slice(&chunk.x().to_le_bytes());
+      bytes.extend_from_slice(&chunk.y().to_le_bytes());
+      bytes.extend_from_slice(&chunk.z().to_le_bytes());
+      bytes.extend_from_slice(&(meta.len() as u32).to_le_bytes());
+      for (voxel, value) in meta.iter() {
+        bytes.extend_from_slice(&voxel.x().to_le_bytes());
+        bytes.extend_from_slice(&voxel.y().to_le_bytes());
+        bytes.extend_from_slice(&voxel.z().to_le_bytes());
+        bytes.extend_from_slice(&value.to_le_bytes());
+      }
+      meta.decals().write(&mut bytes);
+    }
+    bytes
+  }
+
+  // voxel types are matched to the types of `registry` by name
+  pub fn from_bytes(bytes: &[u8], registry: &VoxelRegistry) -> Result<Self, SnapshotError> {
+    let mut reader = Reader(bytes);
+    if reader.take(4)? != SNAPSHOT_MAGIC {
+      return Err(SnapshotError::BadMagic);
+    }
+    let version = reader.u8()?;
+    let config = match version {
+      SNAPSHOT_VERSION
+      | SNAPSHOT_VERSION_NO_DECALS
+      | SNAPSHOT_VERSION_NO_ROADS
+      | SNAPSHOT_VERSION_NO_REGIONS
+      | SNAPSHOT_VERSION_BUILTIN_TYPES
+      | SNAPSHOT_VERSION_NO_META
+      | SNAPSHOT_VERSION_FLAT => WorldGenConfig {
+        seed: reader.u64()?,
+        scale: reader.f64()?,
+        base_height: reader.f64()?,
+        amplitude: reader.f64()?,
+        octaves: reader.u32()? as usize,
+        persistence: reader.f64()?,
+        lacunarity: reader.f64()?,
+        regions: None,
+        wrapping: None,
+      },
+      SNAPSHOT_VERSION_SEED_ONLY => WorldGenConfig {
+        seed: reader.u64()?,
+        ..default()
+      },
+      _ => return Err(SnapshotError::UnsupportedVersion(version)),
+    };
+    let config = match version >= SNAPSHOT_VERSION_NO_ROADS && reader.u8()? != 0 {
+      true => {
+        let mut regions = WorldRegionSettings {
+          cell_size: reader.i64()?,
+          cells: reader.i64()?,
+          continent_scale: reader.f64()?,
+          sea_level: reader.f64()?,
+          continent_height: reader.f64()?,
+          rivers_per_region: reader.u32()? as usize,
+          river_width: reader.f64()?,
+          river_depth: reader.f64()?,
+          road_sites_per_region: 0,
+          ..default()
+        };
+        if version >= SNAPSHOT_VERSION_NO_DECALS {
+          regions.road_sites_per_region = reader.u32()? as usize;
+          regions.road_width = reader.f64()?;
+          regions.road_shoulder = reader.f64()?;
+        }
+        WorldGenConfig {
+          regions: Some(regions),
+          ..config
+        }
+      }
+      false => config,
+    };
+    let palette = if version >= SNAPSHOT_VERSION_NO_REGIONS {
+      VoxelPalette::read(&mut reader, registry)?
+    } else {
+      VoxelPalette::builtin()
+    };
+    let mut diffs = ChunkDiffs::default();
+    for _ in 0..reader.u32()? {
+      let chunk = if version >= SNAPSHOT_VERSION_NO_META {
+        ChunkId::new(reader.i64()?, reader.i64()?, reader.i64()?)
+      } else {
+        let (x, z) = (reader.i64()?, reader.i64()?);
+        ChunkId::new(x, 0, z)
+      };
+      for _ in 0..reader.u32()? {
+        let voxel = VoxelId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+        let id = if version >= SNAPSHOT_VERSION_NO_REGIONS {
+          reader.u16()?
+        } else {
+          reader.u8()? as u16
+        };
+        diffs.record(chunk, voxel, palette.resolve(id)?);
+      }
+    }
+    let mut meta = VoxelMeta::default();
+    if version >= SNAPSHOT_VERSION_BUILTIN_TYPES {
+      for _ in 0..reader.u32()? {
+        let chunk = ChunkId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+        for _ in 0..reader.u32()? {
+          let voxel = VoxelId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+          meta.set(chunk, voxel, reader.u32()?);
+        }
+        if version >= SNAPSHOT_VERSION {
+          for ((voxel, face), tint) in ChunkDecals::read(&mut reader)?.iter() {
+            meta.set_decal(chunk, *voxel, *face, *tint);
+          }
+        }
+      }
+    }
+
+    Ok(Self {
+      config,
+      diffs,
+      meta,
+    })
+  }
+}
+
+// the names of the voxel types a snapshot or region was saved with, indexed by their saved id
+// saving names instead of ids keeps saved worlds loadable when types are registered in another
+// order, types that are no longer registered only fail to load when a voxel uses them
+pub(super) struct VoxelPalette(Vec<(String, Option<VoxelTypeId>)>);
+
+impl VoxelPalette {
+  pub(super) fn write(bytes: &mut Vec<u8>, registry: &VoxelRegistry) {
+    bytes.extend_from_slice(&(registry.len() as u16).to_le_bytes());
+    for (_, info) in registry.iter() {
+      bytes.extend_from_slice(&(info.name.len() as u16).to_le_bytes());
+      bytes.extend_from_slice(info.name.as_bytes());
+    }
+  }
+
+  pub(super) fn read(
+    reader: &mut Reader<'_>,
+    registry: &VoxelRegistry,
+  ) -> Result<Self, SnapshotError> {
+    let mut names = Vec::new();
+    for _ in 0..reader.u16()? {
+      let len = reader.u16()? as usize;
+      let name = String::from_utf8_lossy(reader.take(len)?).into_owned();
+      let id = registry.id_of(&name);
+      names.push((name, id));
+    }
+    Ok(Self(names))
+  }
+
+  // formats from before voxel types were registered only had the built-in types
+  pub(super) fn builtin() -> Self {
+    Self(
+      [
+        ("air", VoxelTypeId::AIR),
+        ("dirt", VoxelTypeId::DIRT),
+        ("lamp", VoxelTypeId::LAMP),
+      ]
+      .into_iter()
+      .map(|(name, id)| (name.to_string(), Some(id)))
+      .collect(),
+    )
+  }
+
+  pub(super) fn resolve(&self, id: u16) -> Result<VoxelTypeId, SnapshotError> {
+    match self.0.get(id as usize) {
+      Some((_, Some(voxel_type))) => Ok(*voxel_type),
+      Some((name, None)) => Err(SnapshotError::UnknownVoxelName(name.clone())),
+      None => Err(SnapshotError::UnknownVoxelType(id)),
+    }
+  }
+}
+
+pub(super) struct Reader<'a>(pub(super) &'a [u8]);
+impl<'a> Reader<'a> {
+  pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+    if self.0.len() < len {
+      return Err(SnapshotError::UnexpectedEnd);
+    }
+    let (head, tail) = self.0.split_at(len);
+    self.0 = tail;
+    Ok(head)
+  }
+
+  pub(super) fn u8(&mut self) -> Result<u8, SnapshotError> {
+    Ok(self.take(1)?[0])
+  }
+
+  pub(super) fn u16(&mut self) -> Result<u16, SnapshotError> {
+    Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+  }
+
+  pub(super) fn f32(&mut self) -> Result<f32, SnapshotError> {
+    Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  pub(super) fn u32(&mut self) -> Result<u32, SnapshotError> {
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+  }
+
+  pub(super) fn u64(&mut self) -> Result<u64, SnapshotError> {
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  pub(super) fn i64(&mut self) -> Result<i64, SnapshotError> {
+    Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  pub(super) fn f64(&mut self) -> Result<f64, SnapshotError> {
+    Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+}
+
+// replaces the local world config, deviations and voxel metadata with the ones received from a
+// server, if the config differs the loaded terrain is regenerated, otherwise chunks that are already
+// loaded only get the deviations applied, and are meshed again if their decals changed
+pub struct ApplyWorldSnapshot(pub WorldSnapshot);
+
+#[allow(clippy::too_many_arguments)]
+pub fn apply_world_snapshots(
+  mut commands: Commands,
+  tracker: Res<ChunkTracker>,
+  mut events: EventReader<ApplyWorldSnapshot>,
+  mut config: ResMut<WorldGenConfig>,
+  mut diffs: ResMut<ChunkDiffs>,
+  mut edits: ResMut<TerrainEdits>,
+  mut meta: VoxelMetaEditor,
+  mut store: ResMut<ChunkStore>,
+) {
+  for ApplyWorldSnapshot(snapshot) in events.iter() {
+    if *config != snapshot.config {
+      *config = snapshot.config.clone();
+    }
+    // what's saved of chunks the snapshot touches is replaced rather than merged
+    let replaced: HashSet<ChunkId> = diffs
+      .iter()
+      .map(|(chunk, _)| *chunk)
+      .chain(meta.chunks().copied())
+      .chain(snapshot.diffs.iter().map(|(chunk, _)| *chunk))
+      .chain(snapshot.meta.iter().map(|(chunk, _)| *chunk))
+      .collect();
+    for chunk in replaced {
+      store.replace_chunk(chunk);
+      if meta.decals(&chunk) != snapshot.meta.decals(&chunk) {
+        if let Some(entity) = tracker.entity(&chunk) {
+          commands.entity(entity).insert(DirtyChunk);
+        }
+      }
+    }
+    *diffs = snapshot.diffs.clone();
+    meta.replace(snapshot.meta.clone());
+
+    // chunks that load later get their deviations when their voxels arrive
+    for (_, voxels) in diffs.iter() {
+      for (voxel, voxel_type) in voxels {
+        edits.set_voxel(*voxel, *voxel_type);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{decal::VoxelFace, registry::VoxelTypeInfo};
+  use proptest::prelude::*;
+
+  // the built-in types plus `extra`, and the ids of air, dirt and the first two extra types
+  fn registry(extra: &[&str]) -> (VoxelRegistry, Vec<VoxelTypeId>) {
+    let mut registry = VoxelRegistry::default();
+    for name in extra {
+      registry.register(VoxelTypeInfo::solid(name, Color::WHITE));
+    }
+    let ids = ["air", "dirt", "stone", "sand"]
+      .iter()
+      .map(|name| registry.id_of(name).unwrap())
+      .collect();
+    (registry, ids)
+  }
+
+  proptest! {
+      #[test]
+      fn snapshot_should_roundtrip(seed in any::<u64>(), scale in 1f64..256., octaves in 1usize..8, rivers in prop::option::of(0usize..8), voxels in prop::collection::vec((-100i64..100, 0i64..4, -100i64..100, -1000i64..1000, 0i64..50, -1000i64..1000, 0usize..4, prop::option::of(any::<u32>()), prop::option::of((0usize..6, 0f32..=1.))), 0..100)) {
+          // the reader registered the same types in another order
+          let (writer, writer_types) = registry(&["stone", "sand"]);
+          let (reader, reader_types) = registry(&["sand", "stone"]);
+          let mut diffs = ChunkDiffs::default();
+          let mut expected = ChunkDiffs::default();
+          let mut meta = VoxelMeta::default();
+          for (cx, cy, cz, x, y, z, kind, value, decal) in voxels {
+              diffs.record(ChunkId::new(cx, cy, cz), VoxelId::new(x, y, z), writer_types[kind]);
+              let name = &writer.get(writer_types[kind]).unwrap().name;
+              expected.record(ChunkId::new(cx, cy, cz), VoxelId::new(x, y, z), reader.id_of(name).unwrap());
+              if let Some(value) = value {
+                  meta.set(ChunkId::new(cx, cy, cz), VoxelId::new(x, y, z), value);
+              }
+              if let Some((face, tint)) = decal {
+                  meta.set_decal(ChunkId::new(cx, cy, cz), VoxelId::new(x, y, z), VoxelFace::ALL[face], Color::rgba(tint, tint, 1. - tint, tint));
+              }
+          }
+          assert_eq!(reader_types.len(), writer_types.len());
+          let regions = rivers.map(|rivers_per_region| WorldRegionSettings { rivers_per_region, road_sites_per_region: rivers_per_region / 2, ..default() });
+          let config = WorldGenConfig { seed, scale, octaves, regions, ..default() };
+          let snapshot = WorldSnapshot { config: config.clone(), diffs, meta: meta.clone() };
+          let result = WorldSnapshot::from_bytes(&snapshot.to_bytes(&writer), &reader);
+          assert_eq!(result, Ok(WorldSnapshot { config, diffs: expected, meta }));
+      }
+
+      #[test]
+      fn truncated_snapshot_should_fail(len in 0usize..20) {
+          let mut diffs = ChunkDiffs::default();
+          diffs.record(ChunkId::new(1, 0, 2), VoxelId::new(3, 4, 5), VoxelTypeId::DIRT);
+          let registry = VoxelRegistry::default();
+          let bytes = WorldSnapshot { config: WorldGenConfig::default(), diffs, meta: VoxelMeta::default() }.to_bytes(&registry);
+          let result = WorldSnapshot::from_bytes(&bytes[..len.min(bytes.len() - 1)], &registry);
+          assert_eq!(result, Err(SnapshotError::UnexpectedEnd));
+      }
+  }
+}
@@ -0,0 +1,463 @@
+use super::{ChunkId, VoxelId};
+use bevy::prelude::*;
+use std::{collections::HashSet, f32::consts::FRAC_PI_4};
+
+// the faces of the cube a `SphereVoxelLayout` is projected from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+  PositiveX,
+  NegativeX,
+  PositiveY,
+  NegativeY,
+  PositiveZ,
+  NegativeZ,
+}
+
+impl CubeFace {
+  pub const ALL: [CubeFace; 6] = [
+    CubeFace::PositiveX,
+    CubeFace::NegativeX,
+    CubeFace::PositiveY,
+    CubeFace::NegativeY,
+    CubeFace::PositiveZ,
+    CubeFace::NegativeZ,
+  ];
+
+  pub fn normal(&self) -> Vec3 {
+    match self {
+      CubeFace::PositiveX => Vec3::X,
+      CubeFace::NegativeX => -Vec3::X,
+      CubeFace::PositiveY => Vec3::Y,
+      CubeFace::NegativeY => -Vec3::Y,
+      CubeFace::PositiveZ => Vec3::Z,
+      CubeFace::NegativeZ => -Vec3::Z,
+    }
+  }
+
+  // the directions u and v grow in on the face, u cross v is the normal
+  pub fn axes(&self) -> (Vec3, Vec3) {
+    match self {
+      CubeFace::PositiveX => (Vec3::Y, Vec3::Z),
+      CubeFace::NegativeX => (Vec3::Z, Vec3::Y),
+      CubeFace::PositiveY => (Vec3::Z, Vec3::X),
+      CubeFace::NegativeY => (Vec3::X, Vec3::Z),
+      CubeFace::PositiveZ => (Vec3::X, Vec3::Y),
+      CubeFace::NegativeZ => (Vec3::Y, Vec3::X),
+    }
+  }
+
+  // the face `direction` points through
+  pub fn of_direction(direction: Vec3) -> Self {
+    let abs = direction.abs();
+    if abs.x >= abs.y && abs.x >= abs.z {
+      if direction.x >= 0. {
+        CubeFace::PositiveX
+      } else {
+        CubeFace::NegativeX
+      }
+    } else if abs.y >= abs.z {
+      if direction.y >= 0. {
+        CubeFace::PositiveY
+      } else {
+        CubeFace::NegativeY
+      }
+    } else if direction.z >= 0. {
+      CubeFace::PositiveZ
+    } else {
+      CubeFace::NegativeZ
+    }
+  }
+
+  fn index(&self) -> i64 {
+    Self::ALL.iter().position(|face| face == self).unwrap() as i64
+  }
+
+  fn from_index(index: i64) -> Self {
+    Self::ALL[index.rem_euclid(6) as usize]
+  }
+
+  // where `direction` hits the face in face coordinates, see `SphereVoxelLayout::face_direction`
+  // the coordinates keep going past the edges of the face, None for directions facing away
+  fn coordinates(&self, direction: Vec3) -> Option<(f32, f32)> {
+    let along = direction.dot(self.normal());
+    if along <= 0. {
+      return None;
+    }
+    let (u, v) = self.axes();
+    let warp = |across: f32| (across / along).atan() / FRAC_PI_4;
+    Some((warp(direction.dot(u)), warp(direction.dot(v))))
+  }
+}
+
+// a quad-sphere: the six faces of a cube divided into square chunks and puffed out onto a sphere,
+// e.g. for small planets
+// voxels are stacked in gravity aligned columns from the surface of the sphere at `radius` outwards
+// and sections of `chunk_voxel_height` layers stack up the same way
+// to go through the same pipeline as the other layouts, chunks and voxels are packed in the usual
+// ids: x is the face followed by the column along u on it, z the column along v and y the section
+// or layer, see `chunk_id` and `chunk_face`
+#[derive(Debug, Clone)]
+pub struct SphereVoxelLayout {
+  radius: f32,
+  voxel_height: f32,
+  // chunks along each edge of a face
+  face_chunks: i64,
+  // voxels along each edge of a chunk
+  chunk_voxels: i64,
+  chunk_voxel_height: i64,
+  vertical_sections: i64,
+}
+
+impl SphereVoxelLayout {
+  pub fn new(
+    radius: f32,
+    voxel_height: f32,
+    face_chunks: i64,
+    chunk_voxels: i64,
+    chunk_voxel_height: i64,
+  ) -> Self {
+    Self {
+      radius,
+      voxel_height,
+      face_chunks: face_chunks.max(1),
+      chunk_voxels: chunk_voxels.max(1),
+      chunk_voxel_height: chunk_voxel_height.max(1),
+      vertical_sections: 1,
+    }
+  }
+
+  pub fn with_vertical_sections(mut self, vertical_sections: i64) -> Self {
+    self.vertical_sections = vertical_sections.max(1);
+    self
+  }
+
+  #[inline]
+  pub fn radius(&self) -> f32 {
+    self.radius
+  }
+
+  #[inline]
+  pub fn voxel_height(&self) -> f32 {
+    self.voxel_height
+  }
+
+  #[inline]
+  pub fn face_chunks(&self) -> i64 {
+    self.face_chunks
+  }
+
+  #[inline]
+  pub fn chunk_voxels(&self) -> i64 {
+    self.chunk_voxels
+  }
+
+  #[inline]
+  pub fn chunk_voxel_height(&self) -> i64 {
+    self.chunk_voxel_height
+  }
+
+  #[inline]
+  pub fn vertical_sections(&self) -> i64 {
+    self.vertical_sections
+  }
+
+  // voxel columns along each edge of a face
+  #[inline]
+  pub fn face_voxels(&self) -> i64 {
+    self.face_chunks * self.chunk_voxels
+  }
+
+  pub fn chunk_id(&self, face: CubeFace, u: i64, v: i64, section: i64) -> ChunkId {
+    ChunkId::new(face.index() * self.face_chunks + u, section, v)
+  }
+
+  // the face of a chunk and its column on it
+  pub fn chunk_face(&self, chunk: &ChunkId) -> (CubeFace, i64, i64) {
+    (
+      CubeFace::from_index(chunk.x().div_euclid(self.face_chunks)),
+      chunk.x().rem_euclid(self.face_chunks),
+      chunk.z(),
+    )
+  }
+
+  pub fn voxel_id(&self, face: CubeFace, u: i64, v: i64, layer: i64) -> VoxelId {
+    VoxelId::new(face.index() * self.face_voxels() + u, layer, v)
+  }
+
+  // the face of a voxel and its column on it
+  pub fn voxel_face(&self, voxel: &VoxelId) -> (CubeFace, i64, i64) {
+    (
+      CubeFace::from_index(voxel.x().div_euclid(self.face_voxels())),
+      voxel.x().rem_euclid(self.face_voxels()),
+      voxel.z(),
+    )
+  }
+
+  // the unit direction through a point of a face, `s` and `t` go from -1 to 1 across the face
+  // along its u and v axes
+  // they're warped so the cells of a face cover about the same area of the sphere, the plain
+  // projection squeezes them towards the edges
+  pub fn face_direction(face: CubeFace, s: f32, t: f32) -> Vec3 {
+    let (u, v) = face.axes();
+    (face.normal() + u * unwarp(s) + v * unwarp(t)).normalize()
+  }
+
+  // face coordinates of the point `u` and `v` cells from the corner of a face `cells` cells across
+  fn cell_coordinates(u: f32, v: f32, cells: i64) -> (f32, f32) {
+    let scale = 2. / cells as f32;
+    (u * scale - 1., v * scale - 1.)
+  }
+
+  // away from the center of the planet, the way voxel columns grow
+  pub fn up(&self, space: &Vec3) -> Vec3 {
+    space.try_normalize().unwrap_or(Vec3::Y)
+  }
+
+  // gravity of `strength` pulling towards the center of the planet
+  pub fn gravity(&self, space: &Vec3, strength: f32) -> Vec3 {
+    -self.up(space) * strength
+  }
+
+  // where the column of a voxel at the bottom of its layer is, relative to the center of the planet
+  pub fn voxel_to_space(&self, voxel: &VoxelId) -> Vec3 {
+    let (face, u, v) = self.voxel_face(voxel);
+    let (s, t) = Self::cell_coordinates(u as f32 + 0.5, v as f32 + 0.5, self.face_voxels());
+    Self::face_direction(face, s, t) * self.layer_radius(voxel.y())
+  }
+
+  pub fn voxel_center(&self, voxel: &VoxelId) -> Vec3 {
+    let (face, u, v) = self.voxel_face(voxel);
+    let (s, t) = Self::cell_coordinates(u as f32 + 0.5, v as f32 + 0.5, self.face_voxels());
+    Self::face_direction(face, s, t) * (self.layer_radius(voxel.y()) + self.voxel_height * 0.5)
+  }
+
+  // the corners of a voxel, the bottom four first, both counter-clockwise seen from above
+  pub fn voxel_corners(&self, voxel: &VoxelId) -> [Vec3; 8] {
+    let (face, u, v) = self.voxel_face(voxel);
+    let cells = self.face_voxels();
+    let (u, v) = (u as f32, v as f32);
+    let directions = [(u, v), (u + 1., v), (u + 1., v + 1.), (u, v + 1.)].map(|(u, v)| {
+      let (s, t) = Self::cell_coordinates(u, v, cells);
+      Self::face_direction(face, s, t)
+    });
+    let (bottom, top) = (
+      self.layer_radius(voxel.y()),
+      self.layer_radius(voxel.y() + 1),
+    );
+    [
+      directions[0] * bottom,
+      directions[1] * bottom,
+      directions[2] * bottom,
+      directions[3] * bottom,
+      directions[0] * top,
+      directions[1] * top,
+      directions[2] * top,
+      directions[3] * top,
+    ]
+  }
+
+  // distance from the center of the planet to the bottom of a layer of voxels
+  #[inline]
+  pub fn layer_radius(&self, layer: i64) -> f32 {
+    self.radius + layer as f32 * self.voxel_height
+  }
+
+  pub fn space_to_voxel(&self, space: &Vec3) -> VoxelId {
+    let direction = self.up(space);
+    let face = CubeFace::of_direction(direction);
+    let (s, t) = face
+      .coordinates(direction)
+      .expect("directions face the face they point through");
+    let cells = self.face_voxels();
+    let cell = |coordinate: f32| {
+      (((coordinate + 1.) * 0.5 * cells as f32).floor() as i64).clamp(0, cells - 1)
+    };
+    let layer = ((space.length() - self.radius) / self.voxel_height).floor() as i64;
+    self.voxel_id(face, cell(s), cell(t), layer)
+  }
+
+  pub fn voxel_to_chunk(&self, voxel: &VoxelId) -> ChunkId {
+    let (face, u, v) = self.voxel_face(voxel);
+    self.chunk_id(
+      face,
+      u.div_euclid(self.chunk_voxels),
+      v.div_euclid(self.chunk_voxels),
+      voxel.y().div_euclid(self.chunk_voxel_height),
+    )
+  }
+
+  pub fn space_to_chunk(&self, space: &Vec3) -> ChunkId {
+    self.voxel_to_chunk(&self.space_to_voxel(space))
+  }
+
+  // the unit direction through the middle of a chunk's column
+  pub fn chunk_direction(&self, chunk: &ChunkId) -> Vec3 {
+    let (face, u, v) = self.chunk_face(chunk);
+    let (s, t) = Self::cell_coordinates(u as f32 + 0.5, v as f32 + 0.5, self.face_chunks);
+    Self::face_direction(face, s, t)
+  }
+
+  // the middle of the bottom of a chunk, where its entity goes
+  pub fn chunk_to_space(&self, chunk: &ChunkId) -> Vec3 {
+    self.chunk_direction(chunk) * self.layer_radius(chunk.y() * self.chunk_voxel_height)
+  }
+
+  // turns +y into the chunk's up, e.g. for the rotation of things standing on it
+  pub fn chunk_rotation(&self, chunk: &ChunkId) -> Quat {
+    Quat::from_rotation_arc(Vec3::Y, self.chunk_direction(chunk))
+  }
+
+  pub fn get_chunk_voxels(&self, chunk: &ChunkId) -> Vec<VoxelId> {
+    self.iter_chunk_voxels(chunk).collect()
+  }
+
+  // same as `get_chunk_voxels` without collecting them first
+  pub fn iter_chunk_voxels(&self, chunk: &ChunkId) -> impl Iterator<Item = VoxelId> {
+    let (face, u, v) = self.chunk_face(chunk);
+    let (size, height) = (self.chunk_voxels, self.chunk_voxel_height);
+    let (face_voxels, bottom) = (self.face_voxels(), chunk.y() * height);
+    let first = face.index() * face_voxels + u * size;
+    (0..size).flat_map(move |x| {
+      (0..size).flat_map(move |z| {
+        (0..height).map(move |y| VoxelId::new(first + x, bottom + y, v * size + z))
+      })
+    })
+  }
+
+  // the chunk `du` and `dv` chunks away along the axes of `chunk`'s face in the same section
+  // past an edge of the face the offset folds over onto the neighboring face, less than a face
+  // away, there's nothing past two edges at once at the corners of the cube
+  pub fn offset_chunk(&self, chunk: &ChunkId, du: i64, dv: i64) -> Option<ChunkId> {
+    let (face, u, v) = self.chunk_face(chunk);
+    let (u, v) = (u + du, v + dv);
+    if (0..self.face_chunks).contains(&u) && (0..self.face_chunks).contains(&v) {
+      return Some(self.chunk_id(face, u, v, chunk.y()));
+    }
+    let direction = Self::folded_direction(face, u, v, self.face_chunks)?;
+    Some(
+      self
+        .space_to_chunk(&(direction * self.radius))
+        .with_y(chunk.y()),
+    )
+  }
+
+  // like `offset_chunk`, for a voxel column in the same layer
+  pub fn offset_voxel(&self, voxel: &VoxelId, du: i64, dv: i64) -> Option<VoxelId> {
+    let (face, u, v) = self.voxel_face(voxel);
+    let (u, v) = (u + du, v + dv);
+    let cells = self.face_voxels();
+    if (0..cells).contains(&u) && (0..cells).contains(&v) {
+      return Some(self.voxel_id(face, u, v, voxel.y()));
+    }
+    let direction = Self::folded_direction(face, u, v, cells)?;
+    let folded = self.space_to_voxel(&(direction * self.radius));
+    Some(VoxelId::new(folded.x(), voxel.y(), folded.z()))
+  }
+
+  // the unit direction through the middle of cell (u, v) of a face `cells` cells across, cells
+  // past an edge of the face fold over onto the neighboring face, None past two edges at once
+  fn folded_direction(face: CubeFace, u: i64, v: i64, cells: i64) -> Option<Vec3> {
+    let (s, t) = Self::cell_coordinates(u as f32 + 0.5, v as f32 + 0.5, cells);
+    let (u_axis, v_axis) = face.axes();
+    let normal = face.normal();
+    let direction = match (s.abs() > 1., t.abs() > 1.) {
+      (false, false) => return Some(Self::face_direction(face, s, t)),
+      (true, true) => return None,
+      // the neighboring face's coordinate towards this face shrinks as far as the offset goes
+      // past the edge, the other one carries over
+      (true, false) => u_axis * s.signum() + normal * unwarp(2. - s.abs()) + v_axis * unwarp(t),
+      (false, true) => v_axis * t.signum() + normal * unwarp(2. - t.abs()) + u_axis * unwarp(s),
+    };
+    Some(direction.normalize())
+  }
+
+  // chunks around `chunk` in its section out to `distance` chunks, nearest rings first
+  // the corners of the cube have three chunks around them instead of four, so rings crossing them
+  // have fewer chunks
+  pub fn get_chunk_neighbors(&self, chunk: &ChunkId, distance: i64) -> Vec<ChunkId> {
+    let mut seen = HashSet::from([*chunk]);
+    let mut neighbors = Vec::new();
+    for ring in 1..=distance {
+      for du in -ring..=ring {
+        for dv in -ring..=ring {
+          if du.abs().max(dv.abs()) != ring {
+            continue;
+          }
+          match self.offset_chunk(chunk, du, dv) {
+            Some(neighbor) if seen.insert(neighbor) => neighbors.push(neighbor),
+            _ => {}
+          }
+        }
+      }
+    }
+    neighbors
+  }
+
+  // rings of chunks between two columns, vertical sections aren't counted
+  // measured across the face of `a` and the faces next to it, chunks farther around the planet
+  // are estimated from the angle between them
+  pub fn chunk_step_distance(&self, a: &ChunkId, b: &ChunkId) -> i64 {
+    let (face, ..) = self.chunk_face(a);
+    let (from, to) = (self.chunk_direction(a), self.chunk_direction(b));
+    let chunks = self.face_chunks as f32 * 0.5;
+    match (face.coordinates(from), face.coordinates(to)) {
+      (Some((sa, ta)), Some((sb, tb))) if sb.abs() < 2. && tb.abs() < 2. => {
+        ((sb - sa).abs().max((tb - ta).abs()) * chunks).round() as i64
+      }
+      _ => (from.angle_between(to) / FRAC_PI_4 * chunks).round() as i64,
+    }
+  }
+
+  // world units between the bottoms of two chunks
+  pub fn world_distance(&self, a: &ChunkId, b: &ChunkId) -> f32 {
+    (self.chunk_to_space(a) - self.chunk_to_space(b)).length()
+  }
+}
+// from warped face coordinates back to the plain projection onto the cube, see
+// `SphereVoxelLayout::face_direction`
+fn unwarp(coordinate: f32) -> f32 {
+  (coordinate * FRAC_PI_4).tan()
+}
+
+impl Default for SphereVoxelLayout {
+  fn default() -> Self {
+    Self::new(128., 1., 4, 16, 16)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn voxels_should_roundtrip_through_space(face in 0usize..6, u in 0i64..64, v in 0i64..64, layer in 0i64..16) {
+          let layout = SphereVoxelLayout::default();
+          let voxel = layout.voxel_id(CubeFace::ALL[face], u, v, layer);
+          prop_assert_eq!(layout.space_to_voxel(&layout.voxel_center(&voxel)), voxel);
+
+          let chunk = layout.voxel_to_chunk(&voxel);
+          prop_assert!(layout.get_chunk_voxels(&chunk).contains(&voxel));
+          // columns point away from the center of the planet
+          let up = layout.up(&layout.voxel_center(&voxel));
+          prop_assert!(up.angle_between(layout.voxel_to_space(&voxel)) < 1e-3);
+          prop_assert!((layout.voxel_center(&voxel).length() - layout.layer_radius(layer) - 0.5).abs() < 1e-3);
+      }
+
+      #[test]
+      fn neighbors_should_cross_face_edges(face in 0usize..6, u in 0i64..4, v in 0i64..4) {
+          let layout = SphereVoxelLayout::default();
+          let chunk = layout.chunk_id(CubeFace::ALL[face], u, v, 0);
+          let neighbors = layout.get_chunk_neighbors(&chunk, 1);
+          // 8 around a chunk, 7 next to a corner of the cube
+          let corner = (u == 0 || u == 3) && (v == 0 || v == 3);
+          prop_assert_eq!(neighbors.len(), if corner { 7 } else { 8 });
+          for neighbor in neighbors {
+              prop_assert_eq!(layout.chunk_step_distance(&chunk, &neighbor), 1);
+              let side = layout.radius() * std::f32::consts::FRAC_PI_2 / layout.face_chunks() as f32;
+              prop_assert!(layout.world_distance(&chunk, &neighbor) < side * 2.);
+          }
+      }
+  }
+}
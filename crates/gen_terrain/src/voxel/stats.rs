@@ -0,0 +1,260 @@
+use super::{
+  cache::{self, ChunkMeshCache, ChunkVoxelCache, CompressedVoxels},
+  despawn::DespawningChunk,
+  layout::CubicVoxelLayout,
+  lod::DataOnlyChunk,
+  registry::VoxelTypeId,
+  visibility::{EmptyChunk, EnclosedChunk},
+  Chunk, ChunkId, ChunkVoxelData, VoxelId,
+};
+use bevy::prelude::*;
+use std::{collections::HashMap, mem};
+
+// loaded chunks by what they're waiting for, every chunk is counted once, in the first state that
+// applies in this order
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkStateCounts {
+  // announced with `ChunkDespawning` and waiting to be despawned
+  pub despawning: usize,
+  // spawned but its voxels haven't arrived yet
+  pub generating: usize,
+  pub empty: usize,
+  pub enclosed: usize,
+  pub data_only: usize,
+  pub meshed: usize,
+  // voxels arrived but the mesh hasn't
+  pub awaiting_mesh: usize,
+}
+
+impl ChunkStateCounts {
+  pub fn total(&self) -> usize {
+    self.despawning
+      + self.generating
+      + self.empty
+      + self.enclosed
+      + self.data_only
+      + self.meshed
+      + self.awaiting_mesh
+  }
+}
+
+// how much of the terrain is resident and roughly how much memory it takes, e.g. to find out what
+// grows in a long session
+// the counts cover the chunks of every world and are refreshed every `interval_seconds`, the
+// byte counts are estimates of the data, not of what the allocator holds on to
+#[derive(Debug, Clone)]
+pub struct TerrainStats {
+  pub interval_seconds: f64,
+  pub loaded_chunks: usize,
+  pub resident_voxels: usize,
+  // the voxel maps of the loaded chunks as they're held
+  pub raw_voxel_bytes: usize,
+  // the same voxels as `CompressedVoxels`, what they'd take compressed
+  pub compressed_voxel_bytes: usize,
+  // meshes on loaded chunks
+  pub resident_meshes: usize,
+  pub mesh_bytes: usize,
+  // meshes of unloaded chunks kept in `ChunkMeshCache`
+  pub cached_meshes: usize,
+  pub cached_mesh_bytes: usize,
+  // voxels of unloaded chunks kept compressed in `ChunkVoxelCache`
+  pub cached_voxel_chunks: usize,
+  pub cached_voxel_bytes: usize,
+  pub states: ChunkStateCounts,
+  updated_at: Option<f64>,
+  // compressed bytes of each chunk's voxels, recounted when they change
+  compressed: HashMap<Entity, usize>,
+}
+
+impl Default for TerrainStats {
+  fn default() -> Self {
+    Self {
+      interval_seconds: 1.,
+      loaded_chunks: 0,
+      resident_voxels: 0,
+      raw_voxel_bytes: 0,
+      compressed_voxel_bytes: 0,
+      resident_meshes: 0,
+      mesh_bytes: 0,
+      cached_meshes: 0,
+      cached_mesh_bytes: 0,
+      cached_voxel_chunks: 0,
+      cached_voxel_bytes: 0,
+      states: ChunkStateCounts::default(),
+      updated_at: None,
+      compressed: HashMap::new(),
+    }
+  }
+}
+
+impl TerrainStats {
+  // seconds since startup of the last refresh, None before the first one
+  pub fn updated_at(&self) -> Option<f64> {
+    self.updated_at
+  }
+
+  // raw voxel bytes per compressed byte, 1 without voxels
+  pub fn compression_ratio(&self) -> f32 {
+    if self.compressed_voxel_bytes == 0 {
+      return 1.;
+    }
+    self.raw_voxel_bytes as f32 / self.compressed_voxel_bytes as f32
+  }
+
+  pub fn log_report(&self) {
+    let states = &self.states;
+    info!(
+      "terrain: {} chunks loaded ({} despawning, {} generating, {} empty, {} enclosed, {} data only, {} meshed, {} awaiting mesh)",
+      self.loaded_chunks,
+      states.despawning,
+      states.generating,
+      states.empty,
+      states.enclosed,
+      states.data_only,
+      states.meshed,
+      states.awaiting_mesh,
+    );
+    info!(
+      "terrain: {} voxels resident, {} raw, {} compressed ({:.1}x)",
+      self.resident_voxels,
+      format_bytes(self.raw_voxel_bytes),
+      format_bytes(self.compressed_voxel_bytes),
+      self.compression_ratio(),
+    );
+    info!(
+      "terrain: {} meshes resident ({}), {} cached ({})",
+      self.resident_meshes,
+      format_bytes(self.mesh_bytes),
+      self.cached_meshes,
+      format_bytes(self.cached_mesh_bytes),
+    );
+    info!(
+      "terrain: {} chunks of voxels cached ({})",
+      self.cached_voxel_chunks,
+      format_bytes(self.cached_voxel_bytes),
+    );
+  }
+}
+
+fn format_bytes(bytes: usize) -> String {
+  const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+  while value >= 1024. && unit < UNITS.len() - 1 {
+    value /= 1024.;
+    unit += 1;
+  }
+  format!("{:.1} {}", value, UNITS[unit])
+}
+
+pub fn raw_voxel_bytes(voxel_data: &ChunkVoxelData) -> usize {
+  voxel_data.voxels.len() * mem::size_of::<(VoxelId, VoxelTypeId)>()
+}
+
+// recounts the compressed size of chunks whose voxels changed every frame, so none are missed
+// between refreshes, and refreshes the rest once every interval
+#[allow(clippy::type_complexity)]
+pub fn update_terrain_stats(
+  time: Res<Time>,
+  layout: Res<CubicVoxelLayout>,
+  meshes: Res<Assets<Mesh>>,
+  mesh_cache: Res<ChunkMeshCache>,
+  voxel_cache: Res<ChunkVoxelCache>,
+  mut stats: ResMut<TerrainStats>,
+  changed: Query<(Entity, &Chunk, &ChunkVoxelData), Changed<ChunkVoxelData>>,
+  chunks: Query<
+    (
+      Entity,
+      Option<&ChunkVoxelData>,
+      Option<&Handle<Mesh>>,
+      Option<&DespawningChunk>,
+      Option<&EmptyChunk>,
+      Option<&EnclosedChunk>,
+      Option<&DataOnlyChunk>,
+    ),
+    With<Chunk>,
+  >,
+) {
+  for (entity, chunk, voxel_data) in changed.iter() {
+    let bytes = CompressedVoxels::compress(&layout, &chunk.id, voxel_data).bytes();
+    stats.compressed.insert(entity, bytes);
+  }
+
+  let now = time.seconds_since_startup();
+  if matches!(stats.updated_at, Some(at) if now - at < stats.interval_seconds) {
+    return;
+  }
+
+  let stats = &mut *stats;
+  let mut compressed = HashMap::with_capacity(stats.compressed.len());
+  stats.loaded_chunks = 0;
+  stats.resident_voxels = 0;
+  stats.raw_voxel_bytes = 0;
+  stats.resident_meshes = 0;
+  stats.mesh_bytes = 0;
+  stats.states = ChunkStateCounts::default();
+  for (entity, voxel_data, mesh, despawning, empty, enclosed, data_only) in chunks.iter() {
+    stats.loaded_chunks += 1;
+    if let Some(voxel_data) = voxel_data {
+      stats.resident_voxels += voxel_data.voxels.len();
+      stats.raw_voxel_bytes += raw_voxel_bytes(voxel_data);
+      // despawned chunks are dropped from the map along the way
+      if let Some(bytes) = stats.compressed.get(&entity) {
+        compressed.insert(entity, *bytes);
+      }
+    }
+    if let Some(mesh) = mesh {
+      stats.resident_meshes += 1;
+      stats.mesh_bytes += meshes.get(mesh).map_or(0, cache::estimate_mesh_bytes);
+    }
+
+    let states = &mut stats.states;
+    match (voxel_data, mesh) {
+      _ if despawning.is_some() => states.despawning += 1,
+      (None, _) => states.generating += 1,
+      _ if empty.is_some() => states.empty += 1,
+      _ if enclosed.is_some() => states.enclosed += 1,
+      _ if data_only.is_some() => states.data_only += 1,
+      (_, Some(_)) => states.meshed += 1,
+      (_, None) => states.awaiting_mesh += 1,
+    }
+  }
+  stats.compressed_voxel_bytes = compressed.values().sum();
+  stats.compressed = compressed;
+  stats.cached_meshes = mesh_cache.len();
+  stats.cached_mesh_bytes = mesh_cache.total_bytes();
+  stats.cached_voxel_chunks = voxel_cache.len();
+  stats.cached_voxel_bytes = voxel_cache.total_bytes();
+  stats.updated_at = Some(now);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{testing::TerrainTestApp, tracker::ChunkTracker};
+  use proptest::prelude::*;
+
+  proptest! {
+      #![proptest_config(ProptestConfig::with_cases(8))]
+
+      #[test]
+      fn stats_should_count_every_loaded_chunk(x in -20i64..20, z in -20i64..20) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4);
+          let position = layout.chunk_to_space(&ChunkId::new(x, 0, z)) + Vec3::splat(0.5);
+          let mut test = TerrainTestApp::new(layout).with_path([position]);
+          test.app.world.resource_mut::<TerrainStats>().interval_seconds = 0.;
+          test.tick_until_ready(50);
+          test.tick();
+
+          let loaded = test.app.world.resource::<ChunkTracker>().len();
+          let stats = test.app.world.resource::<TerrainStats>();
+          prop_assert_eq!(stats.loaded_chunks, loaded);
+          prop_assert_eq!(stats.states.total(), loaded);
+          prop_assert_eq!(stats.states.generating, 0);
+          prop_assert!(stats.resident_voxels > 0);
+          prop_assert!(stats.compressed_voxel_bytes > 0);
+          prop_assert!(stats.compressed_voxel_bytes < stats.raw_voxel_bytes);
+          prop_assert!(stats.resident_meshes >= stats.states.meshed);
+      }
+  }
+}
@@ -0,0 +1,1172 @@
+use super::{
+  cache::CompressedVoxels,
+  decal::ChunkDecals,
+  edit::VoxelChanged,
+  error::{TerrainError, TerrainErrorEvent},
+  generator::WorldGenConfig,
+  layout::CubicVoxelLayout,
+  meta::{VoxelMeta, VoxelMetaChanged},
+  registry::{VoxelRegistry, VoxelTypeId},
+  snapshot::{ChunkDiffs, Reader, SnapshotError, VoxelPalette},
+  world::TerrainWorld,
+  Chunk, ChunkId, ChunkVoxelData, DirtyChunk, EditedChunk, VoxelId,
+};
+use bevy::{
+  app::AppExit,
+  prelude::*,
+  render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+  tasks::{IoTaskPool, Task},
+};
+use futures_lite::future;
+use std::{
+  collections::{HashMap, HashSet, VecDeque},
+  fs, io,
+  path::{Path, PathBuf},
+  sync::{Arc, Mutex},
+};
+
+const REGION_MAGIC: &[u8; 4] = b"VXRG";
+const REGION_VERSION: u8 = 3;
+// version 2 regions save chunks without a version header of their own
+const REGION_VERSION_UNVERSIONED_CHUNKS: u8 = 2;
+// version 1 regions save voxel types as the ids of the built-in types
+const REGION_VERSION_BUILTIN_TYPES: u8 = 1;
+// the format of the chunks saved in a region, each chunk is saved with the version it was
+// written with so older chunks can be upgraded by `ChunkMigrator`s as they're loaded
+pub const CHUNK_FORMAT_VERSION: u16 = 2;
+const THUMBNAIL_MAGIC: &[u8; 4] = b"VXTH";
+const THUMBNAIL_VERSION: u8 = 1;
+const THUMBNAIL_FILE: &str = "thumbnail.vxt";
+const GENERATED_MAGIC: &[u8; 4] = b"VXGN";
+// version 1 saved every chunk to a file of its own, those are generated again
+const GENERATED_VERSION: u8 = 2;
+// pregenerated chunks are saved in region files of their own in here, next to the regions
+const GENERATED_DIRECTORY: &str = "generated";
+// regions of pregenerated voxels kept in memory for the chunks spawned in them
+const MAX_CACHED_GENERATED_REGIONS: usize = 4;
+
+// a square of `region_size` x `region_size` chunk columns saved together in one file
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct RegionId(i64, i64);
+impl RegionId {
+  fn of(chunk: &ChunkId, region_size: i64) -> Self {
+    let size = region_size.max(1);
+    Self(chunk.x().div_euclid(size), chunk.z().div_euclid(size))
+  }
+
+  fn file_name(&self) -> String {
+    format!("r.{}.{}.vxr", self.0, self.1)
+  }
+}
+
+// what's persisted of a chunk, everything else is generated again when it's loaded
+#[derive(Debug, Default, Clone, PartialEq)]
+struct StoredChunk {
+  diffs: HashMap<VoxelId, VoxelTypeId>,
+  meta: HashMap<VoxelId, u32>,
+  decals: ChunkDecals,
+}
+
+// upgrades the bytes of a chunk saved with `source_version` to the next version, chunks are
+// passed through migrators one version at a time until they reach `CHUNK_FORMAT_VERSION`
+pub trait ChunkMigrator: Send + Sync + 'static {
+  fn source_version(&self) -> u16;
+  fn migrate(&self, chunk: ChunkId, bytes: Vec<u8>) -> Result<Vec<u8>, SnapshotError>;
+}
+
+// version 1 chunks were saved without decals
+struct AddDecals;
+impl ChunkMigrator for AddDecals {
+  fn source_version(&self) -> u16 {
+    1
+  }
+  fn migrate(&self, _chunk: ChunkId, mut bytes: Vec<u8>) -> Result<Vec<u8>, SnapshotError> {
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    Ok(bytes)
+  }
+}
+
+// saves the deviations from the generator (`ChunkDiffs`) and the voxel metadata and decals of
+// chunks to region files in a directory, and restores them when chunks in a region are loaded again
+// chunks are flushed when they're dirty, periodically in the background and on `AppExit`
+// chunks pregenerated with `TerrainPregenerator` have their generated voxels saved as well, so
+// they're read instead of generated when they're spawned
+// the default store has no directory and doesn't persist anything
+pub struct ChunkStore {
+  directory: Option<PathBuf>,
+  // chunk columns per region side
+  pub region_size: i64,
+  pub flush_interval_seconds: f64,
+  dirty: HashSet<ChunkId>,
+  generated: Arc<Mutex<GeneratedCache>>,
+  // regions read from disk, the in-memory state of these is the source of truth
+  loaded_regions: HashSet<RegionId>,
+  // reads in flight, their chunks are merged into memory once they're done
+  loading: HashMap<RegionId, Task<io::Result<Vec<u8>>>>,
+  // chunks whose saved state was superseded before their region was read, e.g. by a snapshot,
+  // what's saved for them is ignored when the region is merged
+  replaced: HashSet<ChunkId>,
+  // regions that couldn't be read, e.g. saved by a newer version, these are never written so
+  // they aren't overwritten with what's in memory
+  refused_regions: HashSet<RegionId>,
+  // set once the terrain is regenerated, the decals saved in regions read after that are dropped
+  // since they were painted on the old terrain
+  drop_saved_decals: bool,
+  migrators: HashMap<u16, Box<dyn ChunkMigrator>>,
+  // writes in flight, a region isn't written again until its previous write is done
+  writes: HashMap<RegionId, Task<io::Result<()>>>,
+  last_flush: f64,
+}
+impl Default for ChunkStore {
+  fn default() -> Self {
+    Self {
+      directory: None,
+      region_size: 32,
+      flush_interval_seconds: 30.,
+      dirty: HashSet::new(),
+      generated: default(),
+      loaded_regions: HashSet::new(),
+      loading: HashMap::new(),
+      replaced: HashSet::new(),
+      refused_regions: HashSet::new(),
+      drop_saved_decals: false,
+      migrators: HashMap::from([(
+        AddDecals.source_version(),
+        Box::new(AddDecals) as Box<dyn ChunkMigrator>,
+      )]),
+      writes: HashMap::new(),
+      last_flush: 0.,
+    }
+  }
+}
+
+impl ChunkStore {
+  pub fn new(directory: impl Into<PathBuf>) -> Self {
+    Self {
+      directory: Some(directory.into()),
+      ..default()
+    }
+  }
+
+  // replaces the migrator of the same version, if any
+  pub fn register_migrator(&mut self, migrator: impl ChunkMigrator) {
+    self
+      .migrators
+      .insert(migrator.source_version(), Box::new(migrator));
+  }
+
+  pub fn is_enabled(&self) -> bool {
+    self.directory.is_some()
+  }
+
+  pub fn region_of(&self, chunk: &ChunkId) -> RegionId {
+    RegionId::of(chunk, self.region_size)
+  }
+
+  // chunks of regions that haven't been read yet are written once their region has been read and
+  // merged, so the rest of what's saved in the region is kept
+  pub fn mark_dirty(&mut self, chunk: ChunkId) {
+    if self.is_enabled() {
+      self.dirty.insert(chunk);
+    }
+  }
+
+  // marks a chunk whose diffs and metadata in memory replace whatever was saved for it, instead
+  // of being merged with it voxel by voxel
+  pub fn replace_chunk(&mut self, chunk: ChunkId) {
+    if !self.is_enabled() {
+      return;
+    }
+    if !self.loaded_regions.contains(&self.region_of(&chunk)) {
+      self.replaced.insert(chunk);
+    }
+    self.dirty.insert(chunk);
+  }
+
+  // decals of regions that are read from now on are dropped, and the regions are written without
+  // them, regions that aren't read again before the app exits keep their saved decals
+  pub fn drop_saved_decals(&mut self) {
+    self.drop_saved_decals = true;
+  }
+
+  pub fn is_dirty(&self, chunk: &ChunkId) -> bool {
+    self.dirty.contains(chunk)
+  }
+
+  // starts reading the region of `chunk` in the background unless it's been read or is being read
+  pub fn request_region(&mut self, chunk: &ChunkId, io_pool: &IoTaskPool) {
+    let region = self.region_of(chunk);
+    let directory = match &self.directory {
+      Some(directory) => directory.clone(),
+      None => return,
+    };
+    if self.loaded_regions.contains(&region)
+      || self.loading.contains_key(&region)
+      || self.refused_regions.contains(&region)
+    {
+      return;
+    }
+    let task = io_pool.spawn(async move { read_region(&directory, region) });
+    self.loading.insert(region, task);
+  }
+
+  // merges the regions that were read since the last poll into `diffs` and `meta`, returning
+  // the chunks that got saved diffs or decals and the regions that couldn't be read
+  pub(super) fn poll_regions(
+    &mut self,
+    registry: &VoxelRegistry,
+    diffs: &mut ChunkDiffs,
+    meta: &mut VoxelMeta,
+  ) -> (Vec<ChunkId>, Vec<(RegionId, TerrainError)>) {
+    let mut read = Vec::new();
+    self.loading.retain(
+      |region, task| match future::block_on(future::poll_once(task)) {
+        Some(result) => {
+          read.push((*region, result));
+          false
+        }
+        None => true,
+      },
+    );
+
+    let mut restored = Vec::new();
+    let mut failed = Vec::new();
+    for (region, result) in read {
+      match self.finish_region(region, result, registry, diffs, meta) {
+        Ok(chunks) => restored.extend(chunks),
+        Err(err) => failed.push((region, err)),
+      }
+    }
+    (restored, failed)
+  }
+
+  // decodes a region that was read and merges it, regions that can't be decoded are refused so
+  // they're never overwritten
+  fn finish_region(
+    &mut self,
+    region: RegionId,
+    result: io::Result<Vec<u8>>,
+    registry: &VoxelRegistry,
+    diffs: &mut ChunkDiffs,
+    meta: &mut VoxelMeta,
+  ) -> Result<Vec<ChunkId>, TerrainError> {
+    let bytes = match result {
+      Ok(bytes) => bytes,
+      Err(err) => {
+        self.refused_regions.insert(region);
+        return Err(err.into());
+      }
+    };
+    let chunks = match bytes.is_empty() {
+      true => HashMap::new(),
+      false => match decode_region(&bytes, registry, &self.migrators) {
+        Ok(chunks) => chunks,
+        Err(err) => {
+          self.refused_regions.insert(region);
+          return Err(err.into());
+        }
+      },
+    };
+    Ok(self.merge_region(region, chunks, diffs, meta))
+  }
+
+  // what's in memory is newer than what was saved, saved voxels only fill in the voxels that
+  // weren't changed since, replaced chunks keep nothing of what was saved
+  fn merge_region(
+    &mut self,
+    region: RegionId,
+    chunks: HashMap<ChunkId, StoredChunk>,
+    diffs: &mut ChunkDiffs,
+    meta: &mut VoxelMeta,
+  ) -> Vec<ChunkId> {
+    let mut restored = Vec::new();
+    for (id, stored) in chunks {
+      if self.replaced.contains(&id) {
+        continue;
+      }
+      // voxels edited since lost their saved metadata with the edit
+      let edited: HashSet<_> = diffs
+        .get(&id)
+        .map(|chunk| chunk.keys().copied().collect())
+        .unwrap_or_default();
+      for (voxel, value) in stored.meta {
+        if !edited.contains(&voxel) && meta.get(&id, &voxel).is_none() {
+          meta.set(id, voxel, value);
+        }
+      }
+      let mut changed = false;
+      if self.drop_saved_decals {
+        if !stored.decals.is_empty() {
+          self.dirty.insert(id);
+        }
+      } else {
+        for ((voxel, face), tint) in stored.decals.iter() {
+          let painted = meta.decals(&id).and_then(|decals| decals.get(voxel, *face));
+          if !edited.contains(voxel) && painted.is_none() {
+            meta.set_decal(id, *voxel, *face, *tint);
+            changed = true;
+          }
+        }
+      }
+      for (voxel, voxel_type) in stored.diffs {
+        if !edited.contains(&voxel) {
+          diffs.record(id, voxel, voxel_type);
+          changed = true;
+        }
+      }
+      if changed {
+        restored.push(id);
+      }
+    }
+    self.loaded_regions.insert(region);
+    self
+      .replaced
+      .retain(|chunk| RegionId::of(chunk, self.region_size) != region);
+    restored
+  }
+
+  // writes every dirty chunk and waits for writes in flight, e.g. for manual saves
+  pub fn flush_all(
+    &mut self,
+    registry: &VoxelRegistry,
+    diffs: &mut ChunkDiffs,
+    meta: &mut VoxelMeta,
+  ) -> Result<(), TerrainError> {
+    for (_, task) in self.writes.drain() {
+      future::block_on(task)?;
+    }
+    let directory = match &self.directory {
+      Some(directory) => directory.clone(),
+      None => return Ok(()),
+    };
+
+    // dirty chunks of regions that weren't read yet are merged with what's saved first, regions
+    // that can't be read are left alone and the others are still written
+    let mut unread: HashSet<_> = self
+      .unread_dirty_chunks()
+      .iter()
+      .map(|chunk| self.region_of(chunk))
+      .filter(|region| !self.refused_regions.contains(region))
+      .collect();
+    let mut reads: Vec<_> = self
+      .loading
+      .drain()
+      .map(|(region, task)| (region, future::block_on(task)))
+      .collect();
+    unread.retain(|region| !reads.iter().any(|(read, _)| read == region));
+    reads.extend(
+      unread
+        .into_iter()
+        .map(|region| (region, read_region(&directory, region))),
+    );
+    let mut failed = None;
+    for (region, result) in reads {
+      if let Err(err) = self.finish_region(region, result, registry, diffs, meta) {
+        failed.get_or_insert(err);
+      }
+    }
+
+    for region in self.dirty_regions() {
+      let bytes = self.encode(region, registry, diffs, meta);
+      write_region(&directory, region, &bytes)?;
+    }
+    self.dirty.clear();
+    failed.map_or(Ok(()), Err)
+  }
+
+  // saves a picture of the world next to its regions, e.g. a `ThumbnailCaptured` image for its
+  // save slot, replacing the one saved before
+  // images are saved as rgba8, others are refused
+  pub fn save_thumbnail(&self, image: &Image) -> Result<(), TerrainError> {
+    let directory = match &self.directory {
+      Some(directory) => directory,
+      None => return Ok(()),
+    };
+    let bytes = encode_thumbnail(image)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "thumbnails have to be rgba8"))?;
+    fs::create_dir_all(directory)?;
+    let path = directory.join(THUMBNAIL_FILE);
+    let temp = path.with_extension("vxt.tmp");
+    fs::write(&temp, bytes)?;
+    fs::rename(&temp, &path)?;
+    Ok(())
+  }
+
+  // the thumbnail saved in a store's directory, without a store so save slot menus can show
+  // worlds that aren't loaded, `None` if it has none
+  pub fn read_thumbnail(directory: impl AsRef<Path>) -> Result<Option<Image>, TerrainError> {
+    match fs::read(directory.as_ref().join(THUMBNAIL_FILE)) {
+      Ok(bytes) => Ok(Some(decode_thumbnail(&bytes)?)),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(err.into()),
+    }
+  }
+
+  // reads and writes the regions chunks are pregenerated into, `None` without a directory
+  pub(super) fn generated_voxels(&self) -> Option<GeneratedVoxels> {
+    Some(GeneratedVoxels {
+      directory: self.directory.as_ref()?.join(GENERATED_DIRECTORY),
+      region_size: self.region_size,
+      cache: self.generated.clone(),
+    })
+  }
+
+  // starts background writes of the regions with dirty chunks that aren't being written already
+  fn flush_dirty(
+    &mut self,
+    io_pool: &IoTaskPool,
+    registry: &VoxelRegistry,
+    diffs: &ChunkDiffs,
+    meta: &VoxelMeta,
+  ) {
+    let directory = match &self.directory {
+      Some(directory) => directory.clone(),
+      None => return,
+    };
+
+    let regions: Vec<_> = self
+      .dirty_regions()
+      .into_iter()
+      .filter(|region| !self.writes.contains_key(region))
+      .collect();
+    for region in regions.iter() {
+      let bytes = self.encode(*region, registry, diffs, meta);
+      let directory = directory.clone();
+      let region = *region;
+      let task = io_pool.spawn(async move { write_region(&directory, region, &bytes) });
+      self.writes.insert(region, task);
+    }
+    // chunks of regions that are still being written stay dirty for the next flush
+    self.dirty = self
+      .dirty
+      .iter()
+      .filter(|chunk| !regions.contains(&self.region_of(chunk)))
+      .copied()
+      .collect();
+  }
+
+  // removes finished writes, returning the ones that failed
+  fn poll_writes(&mut self) -> Vec<(RegionId, io::Error)> {
+    let mut failed = Vec::new();
+    self.writes.retain(
+      |region, task| match future::block_on(future::poll_once(task)) {
+        Some(Ok(())) => false,
+        Some(Err(err)) => {
+          failed.push((*region, err));
+          false
+        }
+        None => true,
+      },
+    );
+    failed
+  }
+
+  // regions that haven't been read yet aren't written, they'd lose what's saved in them
+  fn dirty_regions(&self) -> HashSet<RegionId> {
+    self
+      .dirty
+      .iter()
+      .map(|chunk| self.region_of(chunk))
+      .filter(|region| self.loaded_regions.contains(region))
+      .filter(|region| !self.refused_regions.contains(region))
+      .collect()
+  }
+
+  // regions with dirty chunks that haven't been read yet
+  fn unread_dirty_chunks(&self) -> Vec<ChunkId> {
+    self
+      .dirty
+      .iter()
+      .filter(|chunk| !self.loaded_regions.contains(&self.region_of(chunk)))
+      .copied()
+      .collect()
+  }
+
+  // every chunk of the region, including the ones that aren't dirty
+  fn encode(
+    &self,
+    region: RegionId,
+    registry: &VoxelRegistry,
+    diffs: &ChunkDiffs,
+    meta: &VoxelMeta,
+  ) -> Vec<u8> {
+    let mut chunks: HashMap<ChunkId, StoredChunk> = HashMap::new();
+    for (id, voxels) in diffs.iter().filter(|(id, _)| self.region_of(id) == region) {
+      chunks.entry(*id).or_default().diffs = voxels.clone();
+    }
+    for (id, values) in meta.iter().filter(|(id, _)| self.region_of(id) == region) {
+      let stored = chunks.entry(*id).or_default();
+      stored.meta = values.iter().map(|(v, m)| (*v, *m)).collect();
+      stored.decals = values.decals().clone();
+    }
+    encode_region(&chunks, registry)
+  }
+}
+
+// regions that were never saved read as empty
+fn read_region(directory: &Path, region: RegionId) -> io::Result<Vec<u8>> {
+  match fs::read(directory.join(region.file_name())) {
+    Ok(bytes) => Ok(bytes),
+    Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+    Err(err) => Err(err),
+  }
+}
+
+fn write_region(directory: &Path, region: RegionId, bytes: &[u8]) -> io::Result<()> {
+  fs::create_dir_all(directory)?;
+  // write next to the region first so a crash mid-write doesn't corrupt it
+  let path = directory.join(region.file_name());
+  let temp = path.with_extension("vxr.tmp");
+  fs::write(&temp, bytes)?;
+  fs::rename(&temp, &path)
+}
+
+fn encode_thumbnail(image: &Image) -> Option<Vec<u8>> {
+  let size = image.texture_descriptor.size;
+  if image.data.len() != size.width as usize * size.height as usize * 4 {
+    return None;
+  }
+  let mut bytes = Vec::new();
+  bytes.extend_from_slice(THUMBNAIL_MAGIC);
+  bytes.push(THUMBNAIL_VERSION);
+  bytes.extend_from_slice(&size.width.to_le_bytes());
+  bytes.extend_from_slice(&size.height.to_le_bytes());
+  bytes.extend_from_slice(&image.data);
+  Some(bytes)
+}
+
+fn decode_thumbnail(bytes: &[u8]) -> Result<Image, SnapshotError> {
+  let mut reader = Reader(bytes);
+  if reader.take(4)? != THUMBNAIL_MAGIC {
+    return Err(SnapshotError::BadMagic);
+  }
+  match reader.u8()? {
+    THUMBNAIL_VERSION => {}
+    version if version > THUMBNAIL_VERSION => {
+      return Err(SnapshotError::FutureVersion(version as u16))
+    }
+    version => return Err(SnapshotError::UnsupportedVersion(version)),
+  }
+  let (width, height) = (reader.u32()?, reader.u32()?);
+  let data = reader.take(width as usize * height as usize * 4)?.to_vec();
+  Ok(Image::new(
+    Extent3d {
+      width,
+      height,
+      depth_or_array_layers: 1,
+    },
+    TextureDimension::D2,
+    data,
+    TextureFormat::Rgba8UnormSrgb,
+  ))
+}
+
+// identifies the config voxels were generated with, pregenerated voxels of another config are
+// generated again
+// fnv-1a of the config, unlike the std hasher it's the same across builds
+pub(super) fn config_fingerprint(config: &WorldGenConfig) -> u64 {
+  ron::to_string(config)
+    .unwrap_or_default()
+    .bytes()
+    .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+      (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// the pregenerated voxels of the chunks of a region, all generated with the config of `fingerprint`
+#[derive(Debug, Default, Clone, PartialEq)]
+struct GeneratedRegion {
+  fingerprint: u64,
+  chunks: HashMap<ChunkId, CompressedVoxels>,
+}
+
+// the generated regions read last, each behind a lock of its own so jobs of the same region wait
+// for the first one to read it instead of reading it again
+#[derive(Default)]
+struct GeneratedCache {
+  regions: HashMap<RegionId, Arc<Mutex<Option<Arc<GeneratedRegion>>>>>,
+  // from the oldest to the newest
+  order: VecDeque<RegionId>,
+}
+
+impl GeneratedCache {
+  fn slot(&mut self, region: RegionId) -> Arc<Mutex<Option<Arc<GeneratedRegion>>>> {
+    if let Some(slot) = self.regions.get(&region) {
+      return slot.clone();
+    }
+    let slot = Arc::new(Mutex::new(None));
+    self.regions.insert(region, slot.clone());
+    self.order.push_back(region);
+    while self.order.len() > MAX_CACHED_GENERATED_REGIONS {
+      if let Some(oldest) = self.order.pop_front() {
+        self.regions.remove(&oldest);
+      }
+    }
+    slot
+  }
+
+  fn forget(&mut self, region: RegionId) {
+    self.regions.remove(&region);
+    self.order.retain(|cached| *cached != region);
+  }
+}
+
+// the regions of pregenerated voxels in the store's directory, used from generation jobs and io
+// tasks, the regions read last are kept in memory
+#[derive(Clone)]
+pub(super) struct GeneratedVoxels {
+  directory: PathBuf,
+  region_size: i64,
+  cache: Arc<Mutex<GeneratedCache>>,
+}
+
+impl GeneratedVoxels {
+  pub(super) fn region_of(&self, chunk: &ChunkId) -> RegionId {
+    RegionId::of(chunk, self.region_size)
+  }
+
+  // the pregenerated voxels of `chunk`, `None` if there are none or they were generated with
+  // another config or chunk size
+  // the generator itself isn't part of the fingerprint, clear the directory after swapping it
+  pub(super) fn read(
+    &self,
+    fingerprint: u64,
+    layout: &CubicVoxelLayout,
+    chunk: &ChunkId,
+    registry: &VoxelRegistry,
+  ) -> Option<CompressedVoxels> {
+    let region = self.region_of(chunk);
+    let slot = self.cache.lock().unwrap().slot(region);
+    let saved = {
+      let mut slot = slot.lock().unwrap();
+      slot
+        .get_or_insert_with(|| {
+          // regions that can't be read are generated again without being cached for good
+          Arc::new(
+            read_generated_region(&self.directory, region, registry).unwrap_or_else(|err| {
+              warn!("couldn't read pregenerated region {:?}: {}", region, err);
+              GeneratedRegion::default()
+            }),
+          )
+        })
+        .clone()
+    };
+    match saved.chunks.get(chunk) {
+      Some(voxels)
+        if saved.fingerprint == fingerprint
+          && voxels.voxel_count() == layout.iter_chunk_voxels(chunk).count() =>
+      {
+        Some(voxels.clone())
+      }
+      _ => None,
+    }
+  }
+
+  // adds the chunks to their pregenerated region, what it has of another config is dropped
+  // regions that can't be read aren't overwritten
+  pub(super) fn write(
+    &self,
+    region: RegionId,
+    fingerprint: u64,
+    chunks: Vec<(ChunkId, CompressedVoxels)>,
+    registry: &VoxelRegistry,
+  ) -> Result<(), TerrainError> {
+    let mut saved = read_generated_region(&self.directory, region, registry)?;
+    if saved.fingerprint != fingerprint {
+      saved = GeneratedRegion {
+        fingerprint,
+        chunks: HashMap::new(),
+      };
+    }
+    saved.chunks.extend(chunks);
+    fs::create_dir_all(&self.directory)?;
+    let path = self.directory.join(region.file_name());
+    let temp = path.with_extension("vxr.tmp");
+    fs::write(&temp, encode_generated_region(&saved, registry))?;
+    fs::rename(&temp, &path)?;
+    self.cache.lock().unwrap().forget(region);
+    Ok(())
+  }
+}
+
+// regions that were never saved read as empty
+fn read_generated_region(
+  directory: &Path,
+  region: RegionId,
+  registry: &VoxelRegistry,
+) -> Result<GeneratedRegion, TerrainError> {
+  let bytes = read_region(directory, region)?;
+  match bytes.is_empty() {
+    true => Ok(GeneratedRegion::default()),
+    false => Ok(decode_generated_region(&bytes, registry)?),
+  }
+}
+
+fn encode_generated_region(region: &GeneratedRegion, registry: &VoxelRegistry) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  bytes.extend_from_slice(GENERATED_MAGIC);
+  bytes.push(GENERATED_VERSION);
+  bytes.extend_from_slice(&region.fingerprint.to_le_bytes());
+  VoxelPalette::write(&mut bytes, registry);
+  bytes.extend_from_slice(&(region.chunks.len() as u32).to_le_bytes());
+  for (chunk, voxels) in region.chunks.iter() {
+    bytes.extend_from_slice(&chunk.x().to_le_bytes());
+    bytes.extend_from_slice(&chunk.y().to_le_bytes());
+    bytes.extend_from_slice(&chunk.z().to_le_bytes());
+    bytes.extend_from_slice(&(voxels.runs().len() as u32).to_le_bytes());
+    for (voxel_type, length) in voxels.runs() {
+      bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+      bytes.extend_from_slice(&length.to_le_bytes());
+    }
+  }
+  bytes
+}
+
+fn decode_generated_region(
+  bytes: &[u8],
+  registry: &VoxelRegistry,
+) -> Result<GeneratedRegion, SnapshotError> {
+  let mut reader = Reader(bytes);
+  if reader.take(4)? != GENERATED_MAGIC {
+    return Err(SnapshotError::BadMagic);
+  }
+  match reader.u8()? {
+    GENERATED_VERSION => {}
+    version if version > GENERATED_VERSION => {
+      return Err(SnapshotError::FutureVersion(version as u16))
+    }
+    version => return Err(SnapshotError::UnsupportedVersion(version)),
+  }
+  let fingerprint = reader.u64()?;
+  let palette = VoxelPalette::read(&mut reader, registry)?;
+  let mut chunks = HashMap::new();
+  for _ in 0..reader.u32()? {
+    let chunk = ChunkId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+    let mut runs = Vec::new();
+    for _ in 0..reader.u32()? {
+      runs.push((palette.resolve(reader.u16()?)?, reader.u16()?));
+    }
+    chunks.insert(chunk, CompressedVoxels::from_runs(runs));
+  }
+  Ok(GeneratedRegion {
+    fingerprint,
+    chunks,
+  })
+}
+
+fn encode_region(chunks: &HashMap<ChunkId, StoredChunk>, registry: &VoxelRegistry) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  bytes.extend_from_slice(REGION_MAGIC);
+  bytes.push(REGION_VERSION);
+  VoxelPalette::write(&mut bytes, registry);
+  bytes.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+  for (chunk, stored) in chunks {
+    write_chunk_header(&mut bytes, chunk, CHUNK_FORMAT_VERSION);
+    let payload = encode_chunk(stored);
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&payload);
+  }
+  bytes
+}
+
+fn write_chunk_header(bytes: &mut Vec<u8>, chunk: &ChunkId, version: u16) {
+  bytes.extend_from_slice(&chunk.x().to_le_bytes());
+  bytes.extend_from_slice(&chunk.y().to_le_bytes());
+  bytes.extend_from_slice(&chunk.z().to_le_bytes());
+  bytes.extend_from_slice(&version.to_le_bytes());
+}
+
+fn encode_chunk(stored: &StoredChunk) -> Vec<u8> {
+  let mut bytes = Vec::new();
+  bytes.extend_from_slice(&(stored.diffs.len() as u32).to_le_bytes());
+  for (voxel, voxel_type) in stored.diffs.iter() {
+    bytes.extend_from_slice(&voxel.x().to_le_bytes());
+    bytes.extend_from_slice(&voxel.y().to_le_bytes());
+    bytes.extend_from_slice(&voxel.z().to_le_bytes());
+    bytes.extend_from_slice(&voxel_type.0.to_le_bytes());
+  }
+  bytes.extend_from_slice(&(stored.meta.len() as u32).to_le_bytes());
+  for (voxel, value) in stored.meta.iter() {
+    bytes.extend_from_slice(&voxel.x().to_le_bytes());
+    bytes.extend_from_slice(&voxel.y().to_le_bytes());
+    bytes.extend_from_slice(&voxel.z().to_le_bytes());
+    bytes.extend_from_slice(&value.to_le_bytes());
+  }
+  stored.decals.write(&mut bytes);
+  bytes
+}
+
+fn decode_region(
+  bytes: &[u8],
+  registry: &VoxelRegistry,
+  migrators: &HashMap<u16, Box<dyn ChunkMigrator>>,
+) -> Result<HashMap<ChunkId, StoredChunk>, SnapshotError> {
+  let mut reader = Reader(bytes);
+  if reader.take(4)? != REGION_MAGIC {
+    return Err(SnapshotError::BadMagic);
+  }
+  let version = reader.u8()?;
+  let palette = match version {
+    REGION_VERSION | REGION_VERSION_UNVERSIONED_CHUNKS => {
+      VoxelPalette::read(&mut reader, registry)?
+    }
+    REGION_VERSION_BUILTIN_TYPES => VoxelPalette::builtin(),
+    _ if version > REGION_VERSION => return Err(SnapshotError::FutureVersion(version as u16)),
+    _ => return Err(SnapshotError::UnsupportedVersion(version)),
+  };
+
+  let mut chunks = HashMap::new();
+  for _ in 0..reader.u32()? {
+    let chunk = ChunkId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+    let stored = if version == REGION_VERSION {
+      let chunk_version = reader.u16()?;
+      let len = reader.u32()? as usize;
+      let payload = migrate_chunk(chunk, chunk_version, reader.take(len)?.to_vec(), migrators)?;
+      let mut payload_reader = Reader(&payload);
+      let mut stored = decode_chunk(&mut payload_reader, &palette, true)?;
+      stored.decals = ChunkDecals::read(&mut payload_reader)?;
+      stored
+    } else {
+      decode_chunk(
+        &mut reader,
+        &palette,
+        version == REGION_VERSION_UNVERSIONED_CHUNKS,
+      )?
+    };
+    chunks.insert(chunk, stored);
+  }
+  Ok(chunks)
+}
+
+// upgrades the bytes of a chunk saved with `version` to `CHUNK_FORMAT_VERSION`
+fn migrate_chunk(
+  chunk: ChunkId,
+  mut version: u16,
+  mut bytes: Vec<u8>,
+  migrators: &HashMap<u16, Box<dyn ChunkMigrator>>,
+) -> Result<Vec<u8>, SnapshotError> {
+  if version > CHUNK_FORMAT_VERSION {
+    return Err(SnapshotError::FutureVersion(version));
+  }
+  while version < CHUNK_FORMAT_VERSION {
+    let migrator = migrators
+      .get(&version)
+      .ok_or(SnapshotError::MissingMigration(version))?;
+    bytes = migrator.migrate(chunk, bytes)?;
+    version += 1;
+  }
+  Ok(bytes)
+}
+
+// voxel types are u16 palette ids, or u8 ids of the built-in types in version 1 regions
+// the decals that follow in versioned chunks are read by the caller
+fn decode_chunk(
+  reader: &mut Reader,
+  palette: &VoxelPalette,
+  wide_types: bool,
+) -> Result<StoredChunk, SnapshotError> {
+  let mut stored = StoredChunk::default();
+  for _ in 0..reader.u32()? {
+    let voxel = VoxelId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+    let id = if wide_types {
+      reader.u16()?
+    } else {
+      reader.u8()? as u16
+    };
+    stored.diffs.insert(voxel, palette.resolve(id)?);
+  }
+  for _ in 0..reader.u32()? {
+    let voxel = VoxelId::new(reader.i64()?, reader.i64()?, reader.i64()?);
+    stored.meta.insert(voxel, reader.u32()?);
+  }
+  Ok(stored)
+}
+
+// reads what was saved for chunks as they're spawned, and for chunks changed before their region
+// was read so they're merged before they're written
+// chunks whose voxels arrived before their region was read get the saved diffs applied here
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn load_chunk_regions(
+  mut commands: Commands,
+  io_pool: Res<IoTaskPool>,
+  mut store: ResMut<ChunkStore>,
+  registry: Res<VoxelRegistry>,
+  mut diffs: ResMut<ChunkDiffs>,
+  mut meta: ResMut<VoxelMeta>,
+  added: Query<(&Chunk, Option<&TerrainWorld>), Added<Chunk>>,
+  mut chunks: Query<(Entity, &Chunk, Option<&TerrainWorld>, &mut ChunkVoxelData)>,
+  mut changed: EventWriter<VoxelChanged>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  if !store.is_enabled() {
+    return;
+  }
+  // only the primary world is persisted
+  let is_primary = |world: Option<&TerrainWorld>| world.copied().unwrap_or_default().is_primary();
+  for (chunk, _) in added.iter().filter(|(_, world)| is_primary(*world)) {
+    store.request_region(&chunk.id, &io_pool);
+  }
+  for chunk in store.unread_dirty_chunks() {
+    store.request_region(&chunk, &io_pool);
+  }
+
+  let (restored, failed) = store.poll_regions(&registry, &mut diffs, &mut meta);
+  for (region, err) in failed {
+    errors.send(TerrainErrorEvent::new(
+      err,
+      format!("loading region {:?}", region),
+    ));
+  }
+  if restored.is_empty() {
+    return;
+  }
+  let restored: HashSet<_> = restored.into_iter().collect();
+  for (entity, chunk, world, mut voxel_data) in chunks.iter_mut() {
+    if !is_primary(world) || !restored.contains(&chunk.id) {
+      continue;
+    }
+    for (voxel, voxel_type) in diffs.get(&chunk.id).into_iter().flatten() {
+      match voxel_data.voxels.get_mut(voxel) {
+        Some(existing) if existing != voxel_type => {
+          changed.send(VoxelChanged {
+            chunk: chunk.id,
+            voxel: *voxel,
+            old: *existing,
+            new: *voxel_type,
+          });
+          *existing = *voxel_type;
+        }
+        _ => {}
+      }
+    }
+    commands
+      .entity(entity)
+      .insert_bundle((DirtyChunk, EditedChunk));
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn flush_chunk_store(
+  time: Res<Time>,
+  io_pool: Res<IoTaskPool>,
+  mut store: ResMut<ChunkStore>,
+  registry: Res<VoxelRegistry>,
+  diffs: Res<ChunkDiffs>,
+  meta: Res<VoxelMeta>,
+  mut meta_events: EventReader<VoxelMetaChanged>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  for event in meta_events.iter() {
+    store.mark_dirty(event.chunk);
+  }
+  for (region, err) in store.poll_writes() {
+    errors.send(TerrainErrorEvent::new(
+      err,
+      format!("writing region {:?}", region),
+    ));
+  }
+
+  let now = time.seconds_since_startup();
+  if now - store.last_flush >= store.flush_interval_seconds {
+    store.last_flush = now;
+    store.flush_dirty(&io_pool, &registry, &diffs, &meta);
+  }
+}
+
+// runs in the last stage so exits requested anywhere during the frame are seen before the app
+// shuts down
+pub fn flush_chunk_store_on_exit(
+  mut exits: EventReader<AppExit>,
+  mut store: ResMut<ChunkStore>,
+  registry: Res<VoxelRegistry>,
+  mut diffs: ResMut<ChunkDiffs>,
+  mut meta: ResMut<VoxelMeta>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  if exits.iter().count() == 0 {
+    return;
+  }
+  if let Err(err) = store.flush_all(&registry, &mut diffs, &mut meta) {
+    errors.send(TerrainErrorEvent::new(err, "saving chunks on exit"));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::decal::VoxelFace;
+  use proptest::prelude::*;
+
+  // version 0 chunks only had metadata
+  struct AddDiffs;
+  impl ChunkMigrator for AddDiffs {
+    fn source_version(&self) -> u16 {
+      0
+    }
+    fn migrate(&self, _chunk: ChunkId, bytes: Vec<u8>) -> Result<Vec<u8>, SnapshotError> {
+      let mut migrated = 0u32.to_le_bytes().to_vec();
+      migrated.extend(bytes);
+      Ok(migrated)
+    }
+  }
+
+  fn region_with_chunk(chunk: ChunkId, version: u16, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = REGION_MAGIC.to_vec();
+    bytes.push(REGION_VERSION);
+    VoxelPalette::write(&mut bytes, &VoxelRegistry::default());
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    write_chunk_header(&mut bytes, &chunk, version);
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+  }
+
+  proptest! {
+      #[test]
+      fn region_should_roundtrip(chunks in prop::collection::vec((-4i64..4, 0i64..2, -4i64..4, prop::collection::vec((-50i64..50, 0i64..20, -50i64..50, any::<bool>(), prop::option::of(any::<u32>()), prop::option::of((0usize..6, 0f32..=1.))), 0..20)), 0..10)) {
+          let mut expected: HashMap<ChunkId, StoredChunk> = HashMap::new();
+          for (x, y, z, voxels) in chunks {
+              let stored = expected.entry(ChunkId::new(x, y, z)).or_default();
+              for (vx, vy, vz, solid, value, decal) in voxels {
+                  let voxel = VoxelId::new(vx, vy, vz);
+                  stored.diffs.insert(voxel, if solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR });
+                  if let Some(value) = value {
+                      stored.meta.insert(voxel, value);
+                  }
+                  if let Some((face, tint)) = decal {
+                      stored.decals.set(voxel, VoxelFace::ALL[face], Color::rgba(tint, 1. - tint, tint, tint));
+                  }
+              }
+          }
+          let registry = VoxelRegistry::default();
+          assert_eq!(decode_region(&encode_region(&expected, &registry), &registry, &HashMap::new()), Ok(expected));
+      }
+
+      #[test]
+      fn old_chunks_should_be_migrated_and_future_ones_refused(x in -4i64..4, z in -4i64..4, value in any::<u32>()) {
+          let chunk = ChunkId::new(x, 0, z);
+          let voxel = VoxelId::new(x * 16, 3, z * 16);
+          let mut payload = 1u32.to_le_bytes().to_vec();
+          payload.extend_from_slice(&voxel.x().to_le_bytes());
+          payload.extend_from_slice(&voxel.y().to_le_bytes());
+          payload.extend_from_slice(&voxel.z().to_le_bytes());
+          payload.extend_from_slice(&value.to_le_bytes());
+          let old = region_with_chunk(chunk, 0, &payload);
+          let registry = VoxelRegistry::default();
+
+          let mut store = ChunkStore::default();
+          prop_assert_eq!(decode_region(&old, &registry, &store.migrators), Err(SnapshotError::MissingMigration(0)));
+          store.register_migrator(AddDiffs);
+          let mut expected = StoredChunk::default();
+          expected.meta.insert(voxel, value);
+          prop_assert_eq!(decode_region(&old, &registry, &store.migrators), Ok(HashMap::from([(chunk, expected)])));
+
+          let future = region_with_chunk(chunk, CHUNK_FORMAT_VERSION + 1, &[]);
+          prop_assert_eq!(decode_region(&future, &registry, &store.migrators), Err(SnapshotError::FutureVersion(CHUNK_FORMAT_VERSION + 1)));
+          let mut future = old;
+          future[4] = REGION_VERSION + 1;
+          prop_assert_eq!(decode_region(&future, &registry, &store.migrators), Err(SnapshotError::FutureVersion(REGION_VERSION as u16 + 1)));
+      }
+
+      #[test]
+      fn thumbnails_should_roundtrip(width in 1u32..64, height in 1u32..64, seed in any::<u8>()) {
+          let data: Vec<u8> = (0..width * height * 4).map(|i| (i as u8).wrapping_mul(seed)).collect();
+          let image = Image::new(Extent3d { width, height, depth_or_array_layers: 1 }, TextureDimension::D2, data, TextureFormat::Rgba8UnormSrgb);
+          let bytes = encode_thumbnail(&image).expect("rgba8 images are saved");
+          let decoded = decode_thumbnail(&bytes).expect("saved thumbnails are read");
+          prop_assert_eq!(decoded.texture_descriptor.size, image.texture_descriptor.size);
+          prop_assert_eq!(&decoded.data, &image.data);
+
+          prop_assert_eq!(decode_thumbnail(&bytes[..bytes.len() - 1]).err(), Some(SnapshotError::UnexpectedEnd));
+          let mut future = bytes;
+          future[4] = THUMBNAIL_VERSION + 1;
+          prop_assert_eq!(decode_thumbnail(&future).err(), Some(SnapshotError::FutureVersion(THUMBNAIL_VERSION as u16 + 1)));
+      }
+
+      #[test]
+      fn generated_regions_should_roundtrip(chunks in prop::collection::vec((-100i64..100, 0i64..4, -100i64..100, prop::collection::vec(any::<bool>(), 36)), 0..8), fingerprint in any::<u64>()) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4);
+          let mut region = GeneratedRegion { fingerprint, chunks: HashMap::new() };
+          for (x, y, z, solid) in chunks {
+              let chunk = ChunkId::new(x, y, z);
+              let voxel_data = ChunkVoxelData {
+                  voxels: layout
+                      .iter_chunk_voxels(&chunk)
+                      .zip(solid.iter())
+                      .map(|(voxel, solid)| (voxel, if *solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+                      .collect(),
+              };
+              region.chunks.insert(chunk, CompressedVoxels::compress(&layout, &chunk, &voxel_data));
+          }
+          let registry = VoxelRegistry::default();
+          let bytes = encode_generated_region(&region, &registry);
+          prop_assert_eq!(decode_generated_region(&bytes, &registry), Ok(region));
+          prop_assert_eq!(decode_generated_region(&bytes[..bytes.len() - 1], &registry).err(), Some(SnapshotError::UnexpectedEnd));
+
+          // another seed is another world
+          let config = WorldGenConfig::default();
+          let reseeded = WorldGenConfig { seed: config.seed.wrapping_add(1), ..config.clone() };
+          prop_assert_eq!(config_fingerprint(&config), config_fingerprint(&config.clone()));
+          prop_assert_ne!(config_fingerprint(&config), config_fingerprint(&reseeded));
+      }
+
+      #[test]
+      fn saved_regions_should_merge_under_changes_made_before_they_were_read(saved in prop::collection::vec((0i64..4, 0i64..8, any::<bool>(), prop::option::of(any::<u32>())), 0..20), edited in prop::collection::vec((0i64..4, 0i64..8, any::<bool>()), 0..20), replace in any::<bool>()) {
+          let chunk = ChunkId::new(0, 0, 0);
+          let other = ChunkId::new(1, 0, 0);
+          let region = RegionId(0, 0);
+          let mut stored = StoredChunk::default();
+          for (x, z, solid, value) in saved.iter() {
+              let voxel = VoxelId::new(*x, 0, *z);
+              stored.diffs.insert(voxel, if *solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR });
+              if let Some(value) = value {
+                  stored.meta.insert(voxel, *value);
+              }
+          }
+          // the other chunk of the region isn't loaded at all
+          let saved_chunks = HashMap::from([(chunk, stored.clone()), (other, stored.clone())]);
+
+          let mut store = ChunkStore::new("merged");
+          let mut diffs = ChunkDiffs::default();
+          let mut meta = VoxelMeta::default();
+          for (x, z, solid) in edited.iter() {
+              diffs.record(chunk, VoxelId::new(*x, 0, *z), if *solid { VoxelTypeId::AIR } else { VoxelTypeId::DIRT });
+          }
+          match replace {
+              true => store.replace_chunk(chunk),
+              false => store.mark_dirty(chunk),
+          }
+          // dirty chunks of unread regions wait for their region
+          prop_assert!(store.dirty_regions().is_empty());
+
+          let edits = diffs.get(&chunk).cloned().unwrap_or_default();
+          store.merge_region(region, saved_chunks, &mut diffs, &mut meta);
+          prop_assert_eq!(store.dirty_regions(), HashSet::from([region]));
+          prop_assert_eq!(diffs.get(&other).cloned().unwrap_or_default(), stored.diffs.clone());
+          for (voxel, voxel_type) in stored.diffs.iter() {
+              let expected = match (edits.get(voxel), replace) {
+                  (Some(edit), _) => Some(edit),
+                  (None, true) => None,
+                  (None, false) => Some(voxel_type),
+              };
+              prop_assert_eq!(diffs.get(&chunk).and_then(|chunk| chunk.get(voxel)), expected);
+              let value = stored.meta.get(voxel).copied().filter(|_| !replace && !edits.contains_key(voxel));
+              prop_assert_eq!(meta.get(&chunk, voxel), value);
+          }
+          // the whole region is written, including the chunk that wasn't loaded
+          let written = decode_region(&store.encode(region, &VoxelRegistry::default(), &diffs, &meta), &VoxelRegistry::default(), &HashMap::new()).unwrap();
+          prop_assert_eq!(written.get(&other), (!stored.diffs.is_empty() || !stored.meta.is_empty()).then(|| &stored));
+      }
+
+      #[test]
+      fn chunks_should_share_region_with_their_region_neighbors(x in -1000i64..1000, z in -1000i64..1000, size in 1i64..64) {
+          let store = ChunkStore { region_size: size, ..default() };
+          let region = store.region_of(&ChunkId::new(x, 0, z));
+          let origin = ChunkId::new(region.0 * size, 0, region.1 * size);
+          let corner = ChunkId::new(region.0 * size + size - 1, 3, region.1 * size + size - 1);
+          assert_eq!(store.region_of(&origin), region);
+          assert_eq!(store.region_of(&corner), region);
+          assert_ne!(store.region_of(&ChunkId::new(origin.x() - 1, 0, origin.z())), region);
+      }
+  }
+}
@@ -0,0 +1,22 @@
+// switches chunk loading and unloading on and off for all spawners, e.g. while a cutscene moves
+// the camera far away
+// while paused loaded chunks stay loaded and no new chunks are spawned, spawners catch up on
+// whatever they missed once streaming resumes
+#[derive(Debug, Default)]
+pub struct TerrainStreaming {
+  paused: bool,
+}
+
+impl TerrainStreaming {
+  pub fn pause(&mut self) {
+    self.paused = true;
+  }
+
+  pub fn resume(&mut self) {
+    self.paused = false;
+  }
+
+  pub fn is_paused(&self) -> bool {
+    self.paused
+  }
+}
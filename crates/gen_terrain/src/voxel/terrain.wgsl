@@ -0,0 +1,158 @@
+#import bevy_pbr::mesh_view_bind_group
+#import bevy_pbr::mesh_struct
+
+struct TerrainMaterial {
+    ambient: vec4<f32>;
+    sun_direction: vec4<f32>;
+    // x is the texture scale, y and z are the columns and rows of the atlas
+    texture_scale: vec4<f32>;
+    fog_color: vec4<f32>;
+    // x and y are where the fog starts and ends, z is 1 with fog and 0 without
+    fog: vec4<f32>;
+};
+
+[[group(1), binding(0)]]
+var<uniform> material: TerrainMaterial;
+[[group(1), binding(1)]]
+var atlas: texture_2d<f32>;
+[[group(1), binding(2)]]
+var texture_sampler: sampler;
+[[group(1), binding(3)]]
+var normal_atlas: texture_2d<f32>;
+// a column per voxel type, the color in the first row, the atlas and normal atlas tiles in the
+// second, -1 for types without them
+[[group(1), binding(4)]]
+var palette: texture_2d<f32>;
+
+[[group(2), binding(0)]]
+var<uniform> mesh: Mesh;
+
+struct Vertex {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+#ifdef VERTEX_COLORS
+    [[location(2)]] color: vec4<f32>;
+#endif
+#ifdef VOXEL_TYPES
+    [[location(3)]] voxel_type: u32;
+#endif
+#ifdef NORMAL_MAPS
+    [[location(4)]] tangent: vec4<f32>;
+    [[location(5)]] uv: vec2<f32>;
+#endif
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] world_position: vec4<f32>;
+    [[location(1)]] world_normal: vec3<f32>;
+    [[location(2)]] light: vec4<f32>;
+    [[location(3), interpolate(flat)]] voxel_type: u32;
+#ifdef NORMAL_MAPS
+    [[location(4)]] world_tangent: vec4<f32>;
+    [[location(5)]] uv: vec2<f32>;
+#endif
+};
+
+[[stage(vertex)]]
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.world_position = mesh.model * vec4<f32>(vertex.position, 1.0);
+    out.world_normal = mat3x3<f32>(
+        mesh.inverse_transpose_model[0].xyz,
+        mesh.inverse_transpose_model[1].xyz,
+        mesh.inverse_transpose_model[2].xyz
+    ) * vertex.normal;
+    out.clip_position = view.view_proj * out.world_position;
+#ifdef VERTEX_COLORS
+    out.light = vertex.color;
+#else
+    out.light = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+#endif
+#ifdef VOXEL_TYPES
+    out.voxel_type = vertex.voxel_type;
+#else
+    // dirt
+    out.voxel_type = 1u;
+#endif
+#ifdef NORMAL_MAPS
+    out.world_tangent = vec4<f32>(
+        mat3x3<f32>(mesh.model[0].xyz, mesh.model[1].xyz, mesh.model[2].xyz) * vertex.tangent.xyz,
+        vertex.tangent.w
+    );
+    out.uv = vertex.uv;
+#endif
+    return out;
+}
+
+// repeats one tile of the atlas, tiles are numbered row by row
+fn sample_tile(tile: i32, uv: vec2<f32>) -> vec4<f32> {
+    let size = max(material.texture_scale.yz, vec2<f32>(1.0, 1.0));
+    let index = f32(max(tile, 0));
+    let cell = vec2<f32>(index % size.x, floor(index / size.x));
+    return textureSample(atlas, texture_sampler, (cell + fract(uv)) / size);
+}
+
+// one tile of the normal atlas stretched over a face, as a tangent space normal
+fn sample_normal_tile(tile: i32, uv: vec2<f32>) -> vec3<f32> {
+    let size = max(material.texture_scale.yz, vec2<f32>(1.0, 1.0));
+    let index = f32(max(tile, 0));
+    let cell = vec2<f32>(index % size.x, floor(index / size.x));
+    let face_uv = clamp(uv, vec2<f32>(0.0, 0.0), vec2<f32>(1.0, 1.0));
+    let texel = textureSample(normal_atlas, texture_sampler, (cell + face_uv) / size);
+    return texel.xyz * 2.0 - 1.0;
+}
+
+// projects the tile along each world axis and blends by how much the surface faces it
+fn triplanar(tile: i32, position: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+    let p = position * material.texture_scale.x;
+    var weights = abs(normal);
+    weights = weights / (weights.x + weights.y + weights.z);
+    return sample_tile(tile, p.zy) * weights.x
+        + sample_tile(tile, p.xz) * weights.y
+        + sample_tile(tile, p.xy) * weights.z;
+}
+
+// types past the end of the palette are drawn like the last one
+fn palette_entry(voxel_type: u32, row: i32) -> vec4<f32> {
+    let last = textureDimensions(palette).x - 1;
+    return textureLoad(palette, vec2<i32>(min(i32(voxel_type), last), row), 0);
+}
+
+[[stage(fragment)]]
+fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let normal = normalize(in.world_normal);
+    var color = palette_entry(in.voxel_type, 0);
+    let tiles = vec2<i32>(palette_entry(in.voxel_type, 1).xy);
+    let tile = tiles.x;
+    // sampled before branching on the tile, the mip level comes from derivatives that are only
+    // defined outside branches that differ between neighboring fragments
+    let texel = triplanar(tile, in.world_position.xyz, normal);
+    if (tile >= 0) {
+        color = color * texel;
+    }
+
+    // the atlas is projected along the surface, the normal map only changes the shading
+    var shading_normal = normal;
+#ifdef NORMAL_MAPS
+    let normal_tile = tiles.y;
+    // sampled before the branch like the atlas
+    let mapped = sample_normal_tile(normal_tile, in.uv);
+    if (normal_tile >= 0) {
+        let tangent = normalize(in.world_tangent.xyz - normal * dot(normal, in.world_tangent.xyz));
+        let bitangent = cross(normal, tangent) * in.world_tangent.w;
+        shading_normal = normalize(tangent * mapped.x + bitangent * mapped.y + normal * mapped.z);
+    }
+#endif
+
+    // faces turned away from the sun are shaded down to half
+    let sun = max(dot(shading_normal, -material.sun_direction.xyz), 0.0);
+    let shade = 0.5 + 0.5 * sun;
+    let lit = color.rgb * in.light.rgb * material.ambient.rgb * shade;
+
+    // the fog thickens from its start to its end distance from the camera
+    let view_distance = length(in.world_position.xyz - view.world_position.xyz);
+    let fog_range = material.fog.y - material.fog.x;
+    let fog_amount = clamp((view_distance - material.fog.x) / fog_range, 0.0, 1.0) * material.fog.z;
+    return vec4<f32>(mix(lit, material.fog_color.rgb, fog_amount), color.a);
+}
@@ -0,0 +1,259 @@
+use super::{
+  layout::CubicVoxelLayout,
+  pipeline::{ChunkPipeline, GenerationMode},
+  readiness::TerrainReadiness,
+  retention::ChunkRetentionPolicy,
+  tracker::ChunkTracker,
+  Chunk, ChunkId, ChunkSpawner, ChunkVoxelData, VoxelTerrainPlugin,
+};
+use bevy::{asset::AssetPlugin, prelude::*};
+use std::collections::VecDeque;
+
+// the terrain plugin in a headless app with one spawner, for tests of the chunk pipeline
+// chunk jobs run inline and chunks are despawned as soon as they're out of range, so the same
+// script loads and unloads the same chunks on the same frames
+pub struct TerrainTestApp {
+  pub app: App,
+  pub spawner: Entity,
+  // positions the spawner is moved to, one per frame, it stays at the last one
+  path: VecDeque<Vec3>,
+}
+
+impl TerrainTestApp {
+  pub fn new(layout: CubicVoxelLayout) -> Self {
+    Self::with_plugin(layout, VoxelTerrainPlugin)
+  }
+
+  // the same app with another plugin that adds the terrain plugin, e.g. `TileTerrainPlugin`
+  pub fn with_plugin(layout: CubicVoxelLayout, plugin: impl Plugin) -> Self {
+    let mut app = App::new();
+    app
+      .add_plugins(MinimalPlugins)
+      .add_plugin(AssetPlugin)
+      .add_plugin(TransformPlugin)
+      .add_asset::<Mesh>()
+      .add_asset::<Image>()
+      // the cursor systems look for windows, there are none
+      .init_resource::<Windows>()
+      .insert_resource(layout)
+      .insert_resource(GenerationMode::Synchronous {
+        max_micros_per_frame: u64::MAX,
+      })
+      .insert_resource(ChunkRetentionPolicy {
+        min_resident_seconds: 0.,
+        base_grace_seconds: 0.,
+        grace_seconds_per_speed: 0.,
+        grace_seconds_per_turn_rate: 0.,
+        max_grace_seconds: 0.,
+        ..Default::default()
+      })
+      .add_plugin(plugin);
+    let spawner = app
+      .world
+      .spawn()
+      .insert_bundle((
+        Transform::default(),
+        GlobalTransform::default(),
+        ChunkSpawner::default(),
+      ))
+      .id();
+
+    Self {
+      app,
+      spawner,
+      path: VecDeque::new(),
+    }
+  }
+
+  pub fn with_path(mut self, path: impl IntoIterator<Item = Vec3>) -> Self {
+    self.path.extend(path);
+    self
+  }
+
+  // moves the spawner along its path and runs a frame
+  pub fn tick(&mut self) {
+    if let Some(position) = self.path.pop_front() {
+      self
+        .app
+        .world
+        .get_mut::<Transform>(self.spawner)
+        .unwrap()
+        .translation = position;
+    }
+    self.app.update();
+  }
+
+  // ticks until the spawner reached the end of its path, its area is ready and no chunk jobs are
+  // left, returns the frames it took
+  pub fn tick_until_ready(&mut self, max_frames: usize) -> usize {
+    for frame in 1..=max_frames {
+      self.tick();
+      if self.path.is_empty() && self.is_ready() {
+        return frame;
+      }
+    }
+    panic!("terrain wasn't ready after {} frames", max_frames);
+  }
+
+  pub fn is_ready(&self) -> bool {
+    let world = &self.app.world;
+    world.resource::<TerrainReadiness>().is_ready(self.spawner)
+      && world.resource::<ChunkPipeline>().in_flight() == 0
+      && world
+        .get::<ChunkSpawner>(self.spawner)
+        .unwrap()
+        .pending_chunks()
+        == 0
+  }
+
+  pub fn chunk_entity(&self, chunk: &ChunkId) -> Option<Entity> {
+    self.app.world.resource::<ChunkTracker>().entity(chunk)
+  }
+
+  // the chunk is spawned and its voxels arrived
+  pub fn assert_chunk_loaded(&self, chunk: &ChunkId) {
+    let entity = self
+      .chunk_entity(chunk)
+      .unwrap_or_else(|| panic!("chunk {:?} isn't loaded", chunk));
+    assert!(
+      self.app.world.get::<ChunkVoxelData>(entity).is_some(),
+      "chunk {:?} is spawned but has no voxels",
+      chunk
+    );
+  }
+
+  pub fn assert_chunk_unloaded(&self, chunk: &ChunkId) {
+    assert!(
+      self.chunk_entity(chunk).is_none(),
+      "chunk {:?} is still loaded",
+      chunk
+    );
+  }
+
+  pub fn chunk(&self, chunk: &ChunkId) -> &Chunk {
+    let entity = self.chunk_entity(chunk).unwrap();
+    self.app.world.get::<Chunk>(entity).unwrap()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{
+    lod::{ChunkLodSettings, LodBucket},
+    pipeline::ChunkPipelineBudget,
+    prediction::ChunkSpawnerConfig,
+  };
+  use proptest::prelude::*;
+  use std::collections::HashSet;
+
+  fn layout() -> CubicVoxelLayout {
+    CubicVoxelLayout::new(ChunkId::default(), 1.0, 1, 4)
+  }
+
+  // runs a frame and returns the chunks spawned in it
+  fn tick_spawned(test: &mut TerrainTestApp) -> HashSet<ChunkId> {
+    let loaded = |test: &TerrainTestApp| -> HashSet<ChunkId> {
+      let tracker = test.app.world.resource::<ChunkTracker>();
+      tracker.iter().copied().collect()
+    };
+    let before = loaded(test);
+    test.tick();
+    loaded(test).difference(&before).copied().collect()
+  }
+
+  proptest! {
+      #![proptest_config(ProptestConfig::with_cases(8))]
+
+      #[test]
+      fn chunks_should_load_around_the_spawner(x in -20i64..20, z in -20i64..20) {
+          let layout = layout();
+          let center = ChunkId::new(x, 0, z);
+          let position = layout.chunk_to_space(&center) + Vec3::splat(0.5);
+          let mut test = TerrainTestApp::new(layout.clone()).with_path([position]);
+          test.tick_until_ready(50);
+
+          for chunk in layout.iter_chunks_spiral(&center, 2) {
+              test.assert_chunk_loaded(&chunk);
+              prop_assert_eq!(test.chunk(&chunk).steps_to_nearest_spawner, layout.chunk_step_distance(&chunk, &center));
+          }
+          prop_assert_eq!(test.chunk(&center).distance_to_nearest_spawner, 0.);
+      }
+
+      #[test]
+      fn chunks_should_unload_behind_the_spawner(steps in 10i64..20) {
+          let layout = layout();
+          let start = ChunkId::new(0, 0, 0);
+          let end = ChunkId::new(steps, 0, 0);
+          // walks a chunk a frame so it doesn't count as a teleport
+          let path = (0..=steps).map(|x| layout.chunk_to_space(&ChunkId::new(x, 0, 0)) + Vec3::splat(0.5));
+          let mut test = TerrainTestApp::new(layout.clone()).with_path(path);
+          test.tick_until_ready((steps * 10) as usize);
+
+          test.assert_chunk_loaded(&end);
+          test.assert_chunk_unloaded(&start);
+      }
+
+      #[test]
+      fn chunks_should_be_bucketed_by_their_nearest_spawner(steps in 2i64..8) {
+          let layout = layout();
+          let end = ChunkId::new(steps, 0, 0);
+          let path = (0..=steps).map(|x| layout.chunk_to_space(&ChunkId::new(x, 0, 0)) + Vec3::splat(0.5));
+          let mut test = TerrainTestApp::new(layout.clone()).with_path(path);
+          test.tick_until_ready((steps * 10) as usize);
+          test.tick();
+
+          let settings = test.app.world.resource::<ChunkLodSettings>();
+          let loaded: Vec<_> = test.app.world.resource::<ChunkTracker>().iter().copied().collect();
+          prop_assert!(!loaded.is_empty());
+          for chunk in loaded {
+              let rings = layout.chunk_step_distance(&chunk, &end);
+              prop_assert_eq!(test.chunk(&chunk).steps_to_nearest_spawner, rings);
+              let entity = test.chunk_entity(&chunk).unwrap();
+              prop_assert_eq!(test.app.world.get::<LodBucket>(entity), Some(&LodBucket(settings.lod(rings, 0.))));
+          }
+      }
+
+      #[test]
+      fn chunks_should_spawn_nearest_first_within_the_budget(budget in 1usize..6, steps in 10i64..14) {
+          let layout = layout();
+          let start = ChunkId::new(0, 0, 0);
+          // far enough to count as a teleport, so nothing is predicted along the way
+          let end = ChunkId::new(steps, 0, 0);
+          let [start_position, end_position] = [start, end].map(|chunk| layout.chunk_to_space(&chunk) + Vec3::splat(0.5));
+          let mut test = TerrainTestApp::new(layout.clone()).with_path([start_position, start_position, end_position]);
+          test.app.world.resource_mut::<ChunkPipelineBudget>().spawns_per_frame = budget;
+
+          // two frames around the start, the rest of its queue is still waiting when the spawner
+          // moves on
+          let mut farthest = 0;
+          for _ in 0..2 {
+              let spawned = tick_spawned(&mut test);
+              prop_assert_eq!(spawned.len(), budget);
+              let rings: Vec<_> = spawned.iter().map(|chunk| layout.chunk_step_distance(chunk, &start)).collect();
+              prop_assert!(rings.iter().all(|rings| *rings >= farthest));
+              farthest = *rings.iter().max().unwrap();
+          }
+          prop_assert!(test.app.world.get::<ChunkSpawner>(test.spawner).unwrap().pending_chunks() > 0);
+
+          // the queue is rebuilt around the end, none of what was left of the start's is spawned
+          let spawn_radius = ChunkSpawnerConfig::default().spawn_radius;
+          let mut farthest = 0;
+          loop {
+              let spawned = tick_spawned(&mut test);
+              prop_assert!(spawned.len() <= budget);
+              if spawned.is_empty() {
+                  break;
+              }
+              let rings: Vec<_> = spawned.iter().map(|chunk| layout.chunk_step_distance(chunk, &end)).collect();
+              prop_assert!(rings.iter().all(|rings| *rings >= farthest && *rings <= spawn_radius));
+              farthest = *rings.iter().max().unwrap();
+          }
+          prop_assert_eq!(test.app.world.get::<ChunkSpawner>(test.spawner).unwrap().pending_chunks(), 0);
+          test.tick_until_ready(50);
+          for chunk in layout.iter_chunks_spiral(&end, spawn_radius) {
+              test.assert_chunk_loaded(&chunk);
+          }
+      }
+  }
+}
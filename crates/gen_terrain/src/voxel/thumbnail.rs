@@ -0,0 +1,417 @@
+use super::{
+  error::TerrainErrorEvent, readiness::TerrainReadiness, store::ChunkStore, ChunkSpawner,
+};
+use bevy::{
+  core_pipeline::{draw_3d_graph, node, AlphaMask3d, Opaque3d, Transparent3d},
+  prelude::*,
+  render::{
+    camera::{ActiveCamera, Camera, CameraTypePlugin, RenderTarget},
+    render_asset::RenderAssets,
+    render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotValue},
+    render_phase::RenderPhase,
+    render_resource::*,
+    renderer::{RenderContext, RenderDevice},
+    RenderApp, RenderStage,
+  },
+};
+use crossbeam_channel::{Receiver, Sender};
+use std::{collections::VecDeque, num::NonZeroU32};
+
+const THUMBNAIL_PASS_DRIVER: &str = "terrain_thumbnail_pass_driver";
+const THUMBNAIL_READBACK: &str = "terrain_thumbnail_readback";
+const THUMBNAIL_FORMAT: TextureFormat = TextureFormat::Rgba8UnormSrgb;
+
+// renders the terrain around a position to an image, e.g. for the thumbnails of save slots
+// `TerrainThumbnails::capture` queues a request, the image comes back with `ThumbnailCaptured`
+// once the terrain around the position is loaded and drawn
+// without a renderer (headless apps) requests are never captured
+#[derive(Default)]
+pub struct TerrainThumbnailPlugin;
+
+impl Plugin for TerrainThumbnailPlugin {
+  fn build(&self, app: &mut App) {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    app
+      .insert_resource(TerrainThumbnails {
+        next_id: 0,
+        queued: VecDeque::new(),
+        captured: receiver,
+      })
+      .add_event::<ThumbnailCaptured>()
+      .add_system(start_thumbnail_captures)
+      .add_system(arm_thumbnail_captures.after(start_thumbnail_captures))
+      .add_system(finish_thumbnail_captures.before(start_thumbnail_captures));
+
+    if app.get_sub_app_mut(RenderApp).is_err() {
+      return;
+    }
+    app.add_plugin(CameraTypePlugin::<ThumbnailCamera>::default());
+    let render_app = app.sub_app_mut(RenderApp);
+    let (readback_sender, readback_receiver) = crossbeam_channel::unbounded();
+    render_app
+      .insert_resource(ExtractedThumbnail(None))
+      .insert_resource(ThumbnailReadbacks {
+        sender: readback_sender,
+        receiver: readback_receiver,
+        captured: sender,
+      })
+      .add_system_to_stage(RenderStage::Extract, extract_thumbnail_camera)
+      .add_system_to_stage(RenderStage::Cleanup, read_thumbnails);
+
+    let driver = ThumbnailPassDriver::new(&mut render_app.world);
+    let mut graph = render_app.world.resource_mut::<RenderGraph>();
+    graph.add_node(THUMBNAIL_PASS_DRIVER, driver);
+    graph.add_node(THUMBNAIL_READBACK, ThumbnailReadbackNode);
+    graph
+      .add_node_edge(node::CLEAR_PASS_DRIVER, THUMBNAIL_PASS_DRIVER)
+      .unwrap();
+    graph
+      .add_node_edge(THUMBNAIL_PASS_DRIVER, THUMBNAIL_READBACK)
+      .unwrap();
+    graph
+      .add_node_edge(THUMBNAIL_READBACK, node::MAIN_PASS_DRIVER)
+      .unwrap();
+  }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ThumbnailId(pub u64);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailRequest {
+  // the point the camera looks down at
+  pub position: Vec3,
+  pub width: u32,
+  pub height: u32,
+  // world units from the camera to `position`
+  pub distance: f32,
+  // frames to wait for the terrain to be ready before capturing whatever is loaded
+  pub max_wait_frames: u32,
+  // also saves the image next to the chunks of the `ChunkStore`, see `ChunkStore::save_thumbnail`
+  pub save: bool,
+}
+impl Default for ThumbnailRequest {
+  fn default() -> Self {
+    Self {
+      position: Vec3::ZERO,
+      width: 320,
+      height: 180,
+      distance: 48.,
+      max_wait_frames: 600,
+      save: false,
+    }
+  }
+}
+
+// sent with the image of a `ThumbnailRequest`, rgba8 in srgb
+#[derive(Debug, Clone)]
+pub struct ThumbnailCaptured {
+  pub id: ThumbnailId,
+  pub image: Image,
+}
+
+// queued thumbnail requests, captured one at a time
+pub struct TerrainThumbnails {
+  next_id: u64,
+  queued: VecDeque<(ThumbnailId, ThumbnailRequest)>,
+  captured: Receiver<(ThumbnailId, Image)>,
+}
+
+impl TerrainThumbnails {
+  pub fn capture(&mut self, request: ThumbnailRequest) -> ThumbnailId {
+    let id = ThumbnailId(self.next_id);
+    self.next_id += 1;
+    self.queued.push_back((id, request));
+    id
+  }
+
+  // requests that haven't been started yet
+  pub fn queued(&self) -> usize {
+    self.queued.len()
+  }
+}
+
+// the temporary camera of a thumbnail, it's also a spawner so the terrain it looks at is loaded
+#[derive(Debug, Default, Component)]
+pub struct ThumbnailCamera;
+
+#[derive(Debug, Component)]
+struct ThumbnailCapture {
+  id: ThumbnailId,
+  request: ThumbnailRequest,
+  image: Handle<Image>,
+  frames_waited: u32,
+  // the terrain is ready, the image is read back the next time it's drawn
+  armed: bool,
+}
+
+// the capture to read back this frame, in the render world
+struct ExtractedThumbnail(Option<(ThumbnailId, Handle<Image>, UVec2)>);
+
+// in the render world, buffers copied to by the graph are mapped after the frame is submitted
+struct ThumbnailReadbacks {
+  sender: Sender<(ThumbnailId, Buffer, UVec2)>,
+  receiver: Receiver<(ThumbnailId, Buffer, UVec2)>,
+  captured: Sender<(ThumbnailId, Image)>,
+}
+
+// spawns the camera of the next queued request once the previous one is done
+fn start_thumbnail_captures(
+  mut commands: Commands,
+  mut images: ResMut<Assets<Image>>,
+  mut thumbnails: ResMut<TerrainThumbnails>,
+  captures: Query<(), With<ThumbnailCapture>>,
+) {
+  if !captures.is_empty() {
+    return;
+  }
+  let (id, request) = match thumbnails.queued.pop_front() {
+    Some(queued) => queued,
+    None => return,
+  };
+  let size = Extent3d {
+    width: request.width.max(1),
+    height: request.height.max(1),
+    depth_or_array_layers: 1,
+  };
+  let mut image = Image::new_fill(size, TextureDimension::D2, &[0; 4], THUMBNAIL_FORMAT);
+  image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+    | TextureUsages::COPY_SRC
+    | TextureUsages::COPY_DST
+    | TextureUsages::RENDER_ATTACHMENT;
+  let image = images.add(image);
+
+  let eye = request.position + Vec3::new(0., 1., 1.).normalize() * request.distance;
+  commands
+    .spawn_bundle(PerspectiveCameraBundle::<ThumbnailCamera> {
+      camera: Camera {
+        target: RenderTarget::Image(image.clone()),
+        ..default()
+      },
+      transform: Transform::from_translation(eye).looking_at(request.position, Vec3::Y),
+      ..PerspectiveCameraBundle::new()
+    })
+    .insert(ChunkSpawner::default())
+    .insert(ThumbnailCapture {
+      id,
+      request,
+      image,
+      frames_waited: 0,
+      armed: false,
+    });
+}
+
+// captures once the terrain around the camera is ready or it waited long enough
+fn arm_thumbnail_captures(
+  readiness: Res<TerrainReadiness>,
+  mut captures: Query<(Entity, &mut ThumbnailCapture)>,
+) {
+  for (entity, mut capture) in captures.iter_mut() {
+    if capture.armed {
+      continue;
+    }
+    capture.frames_waited += 1;
+    if readiness.is_ready(entity) || capture.frames_waited > capture.request.max_wait_frames {
+      capture.armed = true;
+    }
+  }
+}
+
+// hands out the images read back last frame and despawns their cameras
+fn finish_thumbnail_captures(
+  mut commands: Commands,
+  mut images: ResMut<Assets<Image>>,
+  thumbnails: Res<TerrainThumbnails>,
+  store: Res<ChunkStore>,
+  captures: Query<(Entity, &ThumbnailCapture)>,
+  mut captured: EventWriter<ThumbnailCaptured>,
+  mut errors: EventWriter<TerrainErrorEvent>,
+) {
+  for (id, image) in thumbnails.captured.try_iter() {
+    let (entity, capture) = match captures.iter().find(|(_, capture)| capture.id == id) {
+      Some(found) => found,
+      // read back twice before the camera was despawned
+      None => continue,
+    };
+    if capture.request.save {
+      if let Err(err) = store.save_thumbnail(&image) {
+        errors.send(TerrainErrorEvent::new(err, "saving a terrain thumbnail"));
+      }
+    }
+    images.remove(&capture.image);
+    commands.entity(entity).despawn_recursive();
+    captured.send(ThumbnailCaptured { id, image });
+  }
+}
+
+fn extract_thumbnail_camera(
+  mut commands: Commands,
+  active: Res<ActiveCamera<ThumbnailCamera>>,
+  captures: Query<&ThumbnailCapture>,
+) {
+  if let Some(entity) = active.get() {
+    commands.get_or_spawn(entity).insert_bundle((
+      RenderPhase::<Opaque3d>::default(),
+      RenderPhase::<AlphaMask3d>::default(),
+      RenderPhase::<Transparent3d>::default(),
+    ));
+  }
+  let armed = captures
+    .iter()
+    .find(|capture| capture.armed)
+    .map(|capture| {
+      let size = UVec2::new(capture.request.width.max(1), capture.request.height.max(1));
+      (capture.id, capture.image.clone(), size)
+    });
+  commands.insert_resource(ExtractedThumbnail(armed));
+}
+
+// draws the thumbnail camera before the main pass
+struct ThumbnailPassDriver {
+  query: QueryState<Entity, With<ThumbnailCamera>>,
+}
+
+impl ThumbnailPassDriver {
+  fn new(render_world: &mut World) -> Self {
+    Self {
+      query: QueryState::new(render_world),
+    }
+  }
+}
+
+impl Node for ThumbnailPassDriver {
+  fn update(&mut self, world: &mut World) {
+    self.query.update_archetypes(world);
+  }
+
+  fn run(
+    &self,
+    graph: &mut RenderGraphContext,
+    _render_context: &mut RenderContext,
+    world: &World,
+  ) -> Result<(), NodeRunError> {
+    for camera in self.query.iter_manual(world) {
+      graph.run_sub_graph(draw_3d_graph::NAME, vec![SlotValue::Entity(camera)])?;
+    }
+    Ok(())
+  }
+}
+
+// copies the armed capture's image to a buffer that's read once the frame is submitted
+struct ThumbnailReadbackNode;
+
+impl Node for ThumbnailReadbackNode {
+  fn run(
+    &self,
+    _graph: &mut RenderGraphContext,
+    render_context: &mut RenderContext,
+    world: &World,
+  ) -> Result<(), NodeRunError> {
+    let (id, handle, size) = match &world.resource::<ExtractedThumbnail>().0 {
+      Some(armed) => armed,
+      None => return Ok(()),
+    };
+    let gpu_image = match world.resource::<RenderAssets<Image>>().get(handle) {
+      Some(gpu_image) => gpu_image,
+      None => return Ok(()),
+    };
+    let padded = padded_bytes_per_row(size.x);
+    let buffer = world
+      .resource::<RenderDevice>()
+      .create_buffer(&BufferDescriptor {
+        label: Some("terrain_thumbnail_readback"),
+        size: padded as u64 * size.y as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+      });
+    render_context.command_encoder.copy_texture_to_buffer(
+      gpu_image.texture.as_image_copy(),
+      ImageCopyBuffer {
+        buffer: &*buffer,
+        layout: ImageDataLayout {
+          offset: 0,
+          bytes_per_row: NonZeroU32::new(padded),
+          rows_per_image: None,
+        },
+      },
+      Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: 1,
+      },
+    );
+    let _ = world
+      .resource::<ThumbnailReadbacks>()
+      .sender
+      .send((*id, buffer, *size));
+    Ok(())
+  }
+}
+
+// maps the buffers copied to this frame and sends their images to the main world
+fn read_thumbnails(device: Res<RenderDevice>, readbacks: Res<ThumbnailReadbacks>) {
+  for (id, buffer, size) in readbacks.receiver.try_iter() {
+    let slice = buffer.slice(..);
+    device.map_buffer(&slice, MapMode::Read);
+    let data = unpad_rows(&slice.get_mapped_range(), size);
+    buffer.unmap();
+    let image = Image::new(
+      Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: 1,
+      },
+      TextureDimension::D2,
+      data,
+      THUMBNAIL_FORMAT,
+    );
+    let _ = readbacks.captured.send((id, image));
+  }
+}
+
+// rows copied out of a texture are padded to `COPY_BYTES_PER_ROW_ALIGNMENT`
+fn padded_bytes_per_row(width: u32) -> u32 {
+  let unpadded = width * 4;
+  let align = COPY_BYTES_PER_ROW_ALIGNMENT;
+  (unpadded + align - 1) / align * align
+}
+
+fn unpad_rows(padded: &[u8], size: UVec2) -> Vec<u8> {
+  let (row, padded_row) = (size.x as usize * 4, padded_bytes_per_row(size.x) as usize);
+  padded
+    .chunks(padded_row)
+    .take(size.y as usize)
+    .flat_map(|padded| &padded[..row])
+    .copied()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn unpadded_rows_should_keep_every_pixel(width in 1u32..300, height in 1u32..8) {
+          let padded_row = padded_bytes_per_row(width);
+          prop_assert_eq!(padded_row % COPY_BYTES_PER_ROW_ALIGNMENT, 0);
+          prop_assert!(padded_row >= width * 4 && padded_row < width * 4 + COPY_BYTES_PER_ROW_ALIGNMENT);
+
+          // every pixel holds its own coordinates, the padding is garbage
+          let mut padded = vec![0xee; (padded_row * height) as usize];
+          for y in 0..height {
+              for x in 0..width {
+                  let at = (y * padded_row + x * 4) as usize;
+                  padded[at..at + 4].copy_from_slice(&[x as u8, (x >> 8) as u8, y as u8, 0xff]);
+              }
+          }
+          let pixels = unpad_rows(&padded, UVec2::new(width, height));
+          prop_assert_eq!(pixels.len(), (width * height * 4) as usize);
+          for (index, pixel) in pixels.chunks(4).enumerate() {
+              let (x, y) = (index as u32 % width, index as u32 / width);
+              prop_assert_eq!(pixel, &[x as u8, (x >> 8) as u8, y as u8, 0xff][..]);
+          }
+      }
+  }
+}
@@ -0,0 +1,340 @@
+use super::{
+  builder::VoxelTerrainPluginBuilder, layout::CubicVoxelLayout, mesher, registry::VoxelTypeId,
+  terrain_set, world::TerrainWorld, wrap, Chunk, ChunkSpawner, ChunkVoxelData, TerrainSchedule,
+  TerrainSystem, VoxelId,
+};
+use bevy::{
+  prelude::*,
+  sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+};
+use std::collections::HashMap;
+
+// streams a 2D tile map for top-down games, the terrain plugin on a layout one voxel high with a
+// single section, every voxel is a tile drawn from `TileSet`'s texture atlas
+// chunks are spawned around `TileSpawner`s and generated, edited and saved like any other terrain,
+// only their meshes are replaced by one 2D mesh of tiles per chunk
+// 2D x is terrain x and 2D y is terrain -z, see `tile_to_terrain`
+#[derive(Default)]
+pub struct TileTerrainPlugin {
+  pub(super) settings: VoxelTerrainPluginBuilder,
+}
+
+impl TileTerrainPlugin {
+  // the same settings as the terrain plugin, the chunk height and vertical sections are ignored
+  pub fn builder() -> VoxelTerrainPluginBuilder {
+    default()
+  }
+}
+
+impl Plugin for TileTerrainPlugin {
+  fn build(&self, app: &mut App) {
+    let layout = app
+      .world
+      .get_resource::<CubicVoxelLayout>()
+      .cloned()
+      .unwrap_or_default();
+    app
+      .add_plugin(self.settings.flattened(&layout).build())
+      .init_resource::<TileSet>()
+      .add_system_set(terrain_set(TerrainSystem::Prepare).with_system(follow_tile_spawners))
+      .add_system_set(
+        terrain_set(TerrainSystem::Track)
+          .after(TerrainSystem::Apply)
+          .with_system(place_wrapped_tile_maps)
+          .with_system(build_tile_maps),
+      );
+    // tile maps take the place of the chunk meshes
+    app
+      .world
+      .resource_mut::<TerrainSchedule>()
+      .disable(TerrainSystem::Mesh);
+  }
+}
+
+// the texture atlas tiles are drawn from and the tile of each voxel type, voxel types without a
+// tile aren't drawn, e.g. air
+// tile maps are rebuilt when it changes
+#[derive(Debug, Clone, Default)]
+pub struct TileSet {
+  pub atlas: Handle<TextureAtlas>,
+  // z of the tile maps in 2D
+  pub depth: f32,
+  tiles: HashMap<VoxelTypeId, usize>,
+}
+
+impl TileSet {
+  pub fn new(atlas: Handle<TextureAtlas>) -> Self {
+    Self { atlas, ..default() }
+  }
+
+  // draws voxels of `voxel_type` with the atlas texture at `index`
+  pub fn with_tile(mut self, voxel_type: VoxelTypeId, index: usize) -> Self {
+    self.set_tile(voxel_type, index);
+    self
+  }
+
+  pub fn set_tile(&mut self, voxel_type: VoxelTypeId, index: usize) {
+    self.tiles.insert(voxel_type, index);
+  }
+
+  pub fn remove_tile(&mut self, voxel_type: VoxelTypeId) -> Option<usize> {
+    self.tiles.remove(&voxel_type)
+  }
+
+  pub fn tile(&self, voxel_type: VoxelTypeId) -> Option<usize> {
+    self.tiles.get(&voxel_type).copied()
+  }
+
+  // the uv rects of the tiles in `atlas`, tiles past the end of the atlas are left out
+  fn uv_rects(&self, atlas: &TextureAtlas) -> HashMap<VoxelTypeId, (Vec2, Vec2)> {
+    self
+      .tiles
+      .iter()
+      .filter_map(|(voxel_type, index)| {
+        let rect = atlas.textures.get(*index)?;
+        Some((*voxel_type, (rect.min / atlas.size, rect.max / atlas.size)))
+      })
+      .collect()
+  }
+}
+
+// marks a chunk drawn as a tile map, it has a 2D mesh in place of a chunk mesh
+#[derive(Debug, Default, Clone, Copy, Component)]
+pub struct TileChunk;
+
+// loads tiles around an entity that moves in 2D, e.g. the player of a top-down game
+// the terrain follows a `ChunkSpawner` kept at the matching terrain position, it's despawned with
+// this component
+#[derive(Debug, Default, Component)]
+pub struct TileSpawner {
+  proxy: Option<Entity>,
+}
+
+impl TileSpawner {
+  // the entity with the `ChunkSpawner`, None until the terrain first follows this spawner
+  pub fn proxy(&self) -> Option<Entity> {
+    self.proxy
+  }
+}
+
+// links a proxy `ChunkSpawner` to the `TileSpawner` it follows
+#[derive(Debug, Clone, Copy, Component)]
+struct TileSpawnerProxy(Entity);
+
+// the point in the middle of the tile layer under a 2D position
+pub fn tile_to_terrain(layout: &CubicVoxelLayout, position: Vec2) -> Vec3 {
+  let layer = layout.voxel_center(&VoxelId::new(0, 0, 0)).y;
+  Vec3::new(position.x, layer, -position.y)
+}
+
+// where a terrain position is drawn in 2D
+pub fn terrain_to_tile(position: Vec3) -> Vec2 {
+  Vec2::new(position.x, -position.z)
+}
+
+// keeps a proxy `ChunkSpawner` under every `TileSpawner` and despawns those left behind
+#[allow(clippy::type_complexity)]
+pub fn follow_tile_spawners(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  mut spawners: Query<
+    (Entity, &Transform, &mut TileSpawner, Option<&TerrainWorld>),
+    Without<ChunkSpawner>,
+  >,
+  mut proxies: Query<(Entity, &TileSpawnerProxy, &mut Transform), With<ChunkSpawner>>,
+) {
+  for (entity, transform, mut spawner, world) in spawners.iter_mut() {
+    let position = tile_to_terrain(&layout, transform.translation.truncate());
+    match spawner.proxy.and_then(|proxy| proxies.get_mut(proxy).ok()) {
+      Some((_, _, mut proxy)) => {
+        if proxy.translation != position {
+          proxy.translation = position;
+        }
+      }
+      None => {
+        let mut proxy = commands.spawn();
+        proxy.insert_bundle((
+          Transform::from_translation(position),
+          GlobalTransform::default(),
+          ChunkSpawner::default(),
+          TileSpawnerProxy(entity),
+        ));
+        if let Some(world) = world {
+          proxy.insert(*world);
+        }
+        spawner.proxy = Some(proxy.id());
+      }
+    }
+  }
+
+  for (proxy, source, _) in proxies.iter() {
+    if !matches!(spawners.get(source.0), Ok((_, _, spawner, _)) if spawner.proxy == Some(proxy)) {
+      commands.entity(proxy).despawn();
+    }
+  }
+}
+
+// meshes the tiles of chunks whose voxels arrived or changed, and of every chunk when the tile
+// set changes, once its atlas is loaded
+#[allow(clippy::type_complexity)]
+pub fn build_tile_maps(
+  mut commands: Commands,
+  layout: Res<CubicVoxelLayout>,
+  tile_set: Res<TileSet>,
+  atlases: Res<Assets<TextureAtlas>>,
+  mut meshes: ResMut<Assets<Mesh>>,
+  mut materials: ResMut<Assets<ColorMaterial>>,
+  mut material: Local<Option<(Handle<TextureAtlas>, Handle<ColorMaterial>)>>,
+  chunks: Query<(
+    Entity,
+    &ChunkVoxelData,
+    &Chunk,
+    &Transform,
+    Option<&TileChunk>,
+    ChangeTrackers<ChunkVoxelData>,
+  )>,
+) {
+  let atlas = match atlases.get(&tile_set.atlas) {
+    Some(atlas) => atlas,
+    None => return,
+  };
+  let material = match &*material {
+    Some((handle, material)) if *handle == tile_set.atlas => material.clone(),
+    _ => {
+      let created = materials.add(ColorMaterial::from(atlas.texture.clone()));
+      *material = Some((tile_set.atlas.clone(), created.clone()));
+      created
+    }
+  };
+  let tiles = tile_set.uv_rects(atlas);
+  let rebuild_all = tile_set.is_changed() || atlases.is_changed();
+
+  for (entity, voxel_data, chunk, transform, tile_chunk, changes) in chunks.iter() {
+    let mesh = match tile_chunk {
+      Some(_) if !changes.is_changed() && !rebuild_all => continue,
+      _ => Mesh2dHandle(meshes.add(mesher::mesh_tile_chunk(
+        &layout,
+        &chunk.id,
+        &voxel_data.voxels,
+        &tiles,
+      ))),
+    };
+    if tile_chunk.is_some() {
+      commands
+        .entity(entity)
+        .insert(mesh)
+        .insert(material.clone());
+      continue;
+    }
+    // chunks are spawned where they are in the terrain, the first time they're drawn they're
+    // moved to where that is in 2D and get the rest of what 2D meshes need to be drawn
+    let position = terrain_to_tile(transform.translation).extend(tile_set.depth);
+    commands
+      .entity(entity)
+      .insert_bundle(MaterialMesh2dBundle {
+        mesh,
+        material: material.clone(),
+        transform: Transform::from_translation(position),
+        ..default()
+      })
+      .insert(TileChunk);
+  }
+}
+
+// `wrap::place_wrapped_chunks` for tile maps, placed next to the spawners in 2D
+pub fn place_wrapped_tile_maps(
+  layout: Res<CubicVoxelLayout>,
+  tile_set: Res<TileSet>,
+  spawners: Query<(&Transform, Option<&TerrainWorld>), (With<ChunkSpawner>, Without<Chunk>)>,
+  mut chunks: Query<(&Chunk, &mut Transform, Option<&TerrainWorld>), With<TileChunk>>,
+) {
+  if layout.wrapping().is_none() {
+    return;
+  }
+  for (chunk, mut transform, world) in chunks.iter_mut() {
+    let world = world.copied().unwrap_or_default();
+    let spawners = spawners
+      .iter()
+      .filter(|(_, spawner_world)| spawner_world.copied().unwrap_or_default() == world)
+      .map(|(spawner, _)| spawner.translation);
+    let placed =
+      terrain_to_tile(wrap::chunk_placement(&layout, &chunk.id, spawners)).extend(tile_set.depth);
+    if transform.translation != placed {
+      transform.translation = placed;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{testing::TerrainTestApp, tracker::ChunkTracker, ChunkId};
+  use bevy::{
+    render::{mesh::VertexAttributeValues, view::ComputedVisibility},
+    sprite::TextureAtlas,
+  };
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn tiles_should_be_drawn_where_their_voxels_are(x in -20i64..20, z in -20i64..20, length in 0i64..6, size in 0.5f32..4.) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), size, length, 1);
+          let chunk = ChunkId::new(x, 0, z);
+          let voxels: HashMap<_, _> = layout
+              .get_chunk_voxels(&chunk)
+              .into_iter()
+              .map(|voxel| (voxel, if (voxel.x() + voxel.z()) % 2 == 0 { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+              .collect();
+          let tiles = HashMap::from([(VoxelTypeId::DIRT, (Vec2::ZERO, Vec2::ONE))]);
+          let mesh = mesher::mesh_tile_chunk(&layout, &chunk, &voxels, &tiles);
+
+          let drawn = voxels.values().filter(|voxel_type| **voxel_type == VoxelTypeId::DIRT).count();
+          prop_assert_eq!(mesh.count_vertices(), drawn * 4);
+          let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+              Some(VertexAttributeValues::Float32x3(positions)) => positions.clone(),
+              _ => panic!("tile maps have positions"),
+          };
+          // every tile covers the 2D square of its voxel, relative to the chunk
+          let origin = terrain_to_tile(layout.chunk_to_space(&chunk));
+          for quad in positions.chunks_exact(4) {
+              let center = quad.iter().map(|corner| Vec2::new(corner[0], corner[1])).sum::<Vec2>() / 4. + origin;
+              let terrain = tile_to_terrain(&layout, center);
+              let voxel = layout.space_to_voxel(&terrain);
+              prop_assert_eq!(voxel.y(), 0);
+              prop_assert_eq!(voxels.get(&voxel), Some(&VoxelTypeId::DIRT));
+              prop_assert_eq!(layout.voxel_to_chunk(&voxel), chunk);
+          }
+      }
+
+      #[test]
+      fn tile_maps_should_be_drawn_in_2d(depth in -10f32..10.) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 4, 1);
+          let mut test = TerrainTestApp::with_plugin(layout.clone(), TileTerrainPlugin::default());
+          test.app.add_asset::<TextureAtlas>().add_asset::<ColorMaterial>();
+          let mut atlas = TextureAtlas::new_empty(Handle::default(), Vec2::splat(16.));
+          atlas.add_texture(bevy::sprite::Rect { min: Vec2::ZERO, max: Vec2::splat(16.) });
+          let atlas = test.app.world.resource_mut::<Assets<TextureAtlas>>().add(atlas);
+          let tile_set = TileSet { depth, ..TileSet::new(atlas).with_tile(VoxelTypeId::DIRT, 0) };
+          test.app.insert_resource(tile_set);
+          for _ in 0..10 {
+              test.tick();
+          }
+
+          let loaded: Vec<_> = test.app.world.resource::<ChunkTracker>().iter().copied().collect();
+          prop_assert!(!loaded.is_empty());
+          for chunk in loaded {
+              let entity = test.chunk_entity(&chunk).unwrap();
+              if test.app.world.get::<ChunkVoxelData>(entity).is_none() {
+                  continue;
+              }
+              let entity = test.app.world.entity(entity);
+              prop_assert!(entity.contains::<TileChunk>());
+              prop_assert!(entity.contains::<Mesh2dHandle>());
+              prop_assert!(entity.contains::<Visibility>());
+              prop_assert!(entity.contains::<ComputedVisibility>());
+              let expected = terrain_to_tile(layout.chunk_to_space(&chunk)).extend(depth);
+              prop_assert_eq!(entity.get::<Transform>().unwrap().translation, expected);
+          }
+      }
+  }
+}
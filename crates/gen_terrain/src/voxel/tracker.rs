@@ -1,15 +1,32 @@
-use super::ChunkId;
+use super::{layout::CubicVoxelLayout, ChunkId};
 use bevy::prelude::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+
+// columns on each side of a cell of the spatial hash
+const CELL_CHUNKS: i64 = 8;
 
 #[derive(Default)]
 pub struct ChunkTracker {
-  pub loaded_chunks: HashSet<ChunkId>,
+  // only changed through `try_spawn` and `try_despawn`, which keep the frontier, cells and
+  // entities in step with it
+  loaded_chunks: HashSet<ChunkId>,
+  frontier_chunks: HashSet<ChunkId>,
+  entities: HashMap<ChunkId, Entity>,
+  spawned_at: HashMap<ChunkId, f64>,
+  // chunks that are never despawned, e.g. around a player's base while the camera is elsewhere
+  pinned: HashSet<ChunkId>,
+  // chunks kept loaded for streaming anchors with the highest priority of the anchors near them
+  required: HashMap<ChunkId, u8>,
+  // loaded chunks bucketed by the cell their column falls in, for the spatial queries
+  cells: HashMap<(i64, i64), HashSet<ChunkId>>,
 }
 impl ChunkTracker {
-  pub fn try_spawn(&mut self, chunk: &ChunkId) -> bool {
+  pub fn try_spawn(&mut self, chunk: &ChunkId, now: f64) -> bool {
     if !self.loaded_chunks.contains(chunk) {
-      self.loaded_chunks.insert(chunk.clone());
+      self.loaded_chunks.insert(*chunk);
+      self.spawned_at.insert(*chunk, now);
+      self.cells.entry(cell(chunk)).or_default().insert(*chunk);
+      self.refresh_frontier(chunk);
       info!("spawned chunk {:?}", chunk);
       true
     } else {
@@ -17,11 +34,335 @@ impl ChunkTracker {
     }
   }
 
-  pub fn try_despawn(&mut self, chunk: &ChunkId) -> bool {
+  // chunks are kept for at least `min_resident_seconds` after spawning so that spawners moving
+  // back and forth over a border don't reload the same chunks over and over
+  pub fn try_despawn(&mut self, chunk: &ChunkId, now: f64, min_resident_seconds: f64) -> bool {
+    if !self.can_despawn(chunk, now, min_resident_seconds) {
+      return false;
+    }
+
     let retval = self.loaded_chunks.remove(chunk);
     if retval {
+      self.entities.remove(chunk);
+      self.spawned_at.remove(chunk);
+      if let Some(cell_chunks) = self.cells.get_mut(&cell(chunk)) {
+        cell_chunks.remove(chunk);
+        if cell_chunks.is_empty() {
+          self.cells.remove(&cell(chunk));
+        }
+      }
+      self.refresh_frontier(chunk);
       info!("despawned chunk {:?}", chunk);
     }
     retval
   }
+
+  pub fn is_loaded(&self, chunk: &ChunkId) -> bool {
+    self.loaded_chunks.contains(chunk)
+  }
+
+  // every loaded chunk in no particular order
+  pub fn iter(&self) -> impl Iterator<Item = &ChunkId> {
+    self.loaded_chunks.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.loaded_chunks.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.loaded_chunks.is_empty()
+  }
+
+  // loaded chunks of every section within `radius` rings of the column of `center`, in no
+  // particular order
+  pub fn loaded_chunks_in_radius(
+    &self,
+    center: &ChunkId,
+    radius: i64,
+  ) -> impl Iterator<Item = ChunkId> + '_ {
+    let center = *center;
+    let radius = radius.max(0);
+    // radii reaching past the coordinate limits stop at them
+    let (min_x, min_z) = cell(&center.saturating_offset(-radius, -radius));
+    let (max_x, max_z) = cell(&center.saturating_offset(radius, radius));
+    let cell_count = (max_x - min_x + 1).saturating_mul(max_z - min_z + 1);
+    // a radius spanning more cells than there are occupied ones is cheaper to answer from the
+    // occupied cells
+    let cells: Vec<_> = if cell_count > self.cells.len() as i64 {
+      self
+        .cells
+        .iter()
+        .filter(|((x, z), _)| (min_x..=max_x).contains(x) && (min_z..=max_z).contains(z))
+        .map(|(_, chunks)| chunks)
+        .collect()
+    } else {
+      (min_x..=max_x)
+        .flat_map(|x| (min_z..=max_z).map(move |z| (x, z)))
+        .filter_map(|cell| self.cells.get(&cell))
+        .collect()
+    };
+    cells
+      .into_iter()
+      .flatten()
+      .filter(move |chunk| ring_distance(&center, chunk) <= radius)
+      .copied()
+  }
+
+  // the loaded chunk fewest rings away from the column of `chunk`, ties go to the nearest
+  // section and then to the lowest id so the answer doesn't depend on the order of the hash
+  pub fn nearest_loaded_chunk(&self, chunk: &ChunkId) -> Option<ChunkId> {
+    let key = |candidate: &ChunkId| {
+      (
+        ring_distance(chunk, candidate),
+        (candidate.y() - chunk.y()).abs(),
+        candidate.x(),
+        candidate.y(),
+        candidate.z(),
+      )
+    };
+    let (x, z) = cell(chunk);
+    let mut nearest: Option<ChunkId> = None;
+    let mut visited = 0;
+    for cell_ring in 0.. {
+      // chunks in cells `cell_ring` cells away are at least this many rings away
+      let closest = (cell_ring - 1).max(0) * CELL_CHUNKS + cell_ring.min(1);
+      if visited == self.cells.len()
+        || matches!(nearest, Some(nearest) if ring_distance(chunk, &nearest) < closest)
+      {
+        break;
+      }
+      for cell in CubicVoxelLayout::iter_chunks_ring(ChunkId::new(x, 0, z), cell_ring) {
+        if let Some(chunks) = self.cells.get(&(cell.x(), cell.z())) {
+          visited += 1;
+          nearest = chunks
+            .iter()
+            .chain(nearest.as_ref())
+            .min_by_key(|candidate| key(candidate))
+            .copied();
+        }
+      }
+    }
+    nearest
+  }
+
+  // whether `try_despawn` would let go of the chunk if it's loaded
+  pub fn can_despawn(&self, chunk: &ChunkId, now: f64, min_resident_seconds: f64) -> bool {
+    !self.is_pinned(chunk)
+      && !matches!(self.resident_seconds(chunk, now), Some(resident) if resident < min_resident_seconds)
+  }
+
+  // pinned chunks stay loaded regardless of distance, pinned chunks that aren't loaded are
+  // spawned with the next batch of chunks
+  pub fn pin(&mut self, chunk: ChunkId) {
+    self.pinned.insert(chunk);
+  }
+
+  pub fn unpin(&mut self, chunk: &ChunkId) {
+    self.pinned.remove(chunk);
+  }
+
+  pub fn is_pinned(&self, chunk: &ChunkId) -> bool {
+    self.pinned.contains(chunk)
+  }
+
+  pub fn pinned(&self) -> impl Iterator<Item = &ChunkId> {
+    self.pinned.iter()
+  }
+
+  // keeps `chunk` loaded until the requirements are cleared, a chunk required by several anchors
+  // keeps the highest of their priorities
+  pub fn require(&mut self, chunk: ChunkId, priority: u8) {
+    let required = self.required.entry(chunk).or_insert(priority);
+    *required = (*required).max(priority);
+  }
+
+  pub fn required_priority(&self, chunk: &ChunkId) -> Option<u8> {
+    self.required.get(chunk).copied()
+  }
+
+  pub fn is_required(&self, chunk: &ChunkId) -> bool {
+    self.required.contains_key(chunk)
+  }
+
+  // required chunks that aren't loaded with their priority
+  pub fn unloaded_required(&self) -> impl Iterator<Item = (ChunkId, u8)> + '_ {
+    self
+      .required
+      .iter()
+      .filter(|(chunk, _)| !self.loaded_chunks.contains(chunk))
+      .map(|(chunk, priority)| (*chunk, *priority))
+  }
+
+  // anchors are gathered again every frame, so requirements of anchors that moved or are gone
+  // don't linger
+  pub(super) fn clear_required(&mut self) {
+    self.required.clear();
+  }
+
+  // forgets every loaded chunk, pins are kept
+  pub fn clear(&mut self) {
+    *self = Self {
+      pinned: std::mem::take(&mut self.pinned),
+      ..default()
+    };
+  }
+
+  pub fn register_entity(&mut self, chunk: ChunkId, entity: Entity) {
+    self.entities.insert(chunk, entity);
+  }
+
+  pub fn entity(&self, chunk: &ChunkId) -> Option<Entity> {
+    self.entities.get(chunk).copied()
+  }
+
+  pub fn resident_seconds(&self, chunk: &ChunkId, now: f64) -> Option<f64> {
+    self
+      .spawned_at
+      .get(chunk)
+      .map(|spawned_at| now - spawned_at)
+  }
+
+  // loaded chunks with at least one unloaded neighbor in the same section
+  pub fn frontier(&self) -> &HashSet<ChunkId> {
+    &self.frontier_chunks
+  }
+
+  pub fn is_frontier(&self, chunk: &ChunkId) -> bool {
+    self.frontier_chunks.contains(chunk)
+  }
+
+  fn refresh_frontier(&mut self, chunk: &ChunkId) {
+    // loading or unloading a chunk can only change the frontier status
+    // of the chunk itself and the chunks adjacent to it
+    for candidate in std::iter::once(*chunk).chain(chunk.adjacent()) {
+      let on_frontier = self.loaded_chunks.contains(&candidate)
+        && candidate
+          .adjacent()
+          .any(|neighbor| !self.loaded_chunks.contains(&neighbor));
+
+      if on_frontier {
+        self.frontier_chunks.insert(candidate);
+      } else {
+        self.frontier_chunks.remove(&candidate);
+      }
+    }
+  }
+}
+
+fn cell(chunk: &ChunkId) -> (i64, i64) {
+  (
+    chunk.x().div_euclid(CELL_CHUNKS),
+    chunk.z().div_euclid(CELL_CHUNKS),
+  )
+}
+
+// see `CubicVoxelLayout::chunk_step_distance`
+fn ring_distance(a: &ChunkId, b: &ChunkId) -> i64 {
+  let x = a.x().saturating_sub(b.x()).saturating_abs();
+  x.max(a.z().saturating_sub(b.z()).saturating_abs())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn frontier_should_match_full_scan(ops in prop::collection::vec((any::<bool>(), -5i64..=5, -5i64..=5), 1..200)) {
+          let mut tracker = ChunkTracker::default();
+          for (spawn, x, y) in ops {
+              let chunk = ChunkId::new(x, 0, y);
+              if spawn {
+                  tracker.try_spawn(&chunk, 0.);
+              } else {
+                  tracker.try_despawn(&chunk, 0., 0.);
+              }
+          }
+
+          let expected: HashSet<_> = tracker
+              .loaded_chunks
+              .iter()
+              .filter(|c| c.adjacent().any(|n| !tracker.loaded_chunks.contains(&n)))
+              .cloned()
+              .collect();
+          assert_eq!(&expected, tracker.frontier());
+      }
+
+      #[test]
+      fn spatial_queries_should_match_full_scan(ops in prop::collection::vec((any::<bool>(), -40i64..=40, 0i64..3, -40i64..=40), 1..200), center in (-50i64..=50, 0i64..3, -50i64..=50), radius in 0i64..30) {
+          let mut tracker = ChunkTracker::default();
+          for (spawn, x, y, z) in ops {
+              let chunk = ChunkId::new(x, y, z);
+              if spawn {
+                  tracker.try_spawn(&chunk, 0.);
+              } else {
+                  tracker.try_despawn(&chunk, 0., 0.);
+              }
+          }
+          let center = ChunkId::new(center.0, center.1, center.2);
+
+          let expected: HashSet<_> = tracker
+              .loaded_chunks
+              .iter()
+              .filter(|chunk| ring_distance(&center, chunk) <= radius)
+              .cloned()
+              .collect();
+          let found: Vec<_> = tracker.loaded_chunks_in_radius(&center, radius).collect();
+          prop_assert_eq!(found.len(), expected.len());
+          prop_assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected);
+
+          let key = |chunk: &ChunkId| (ring_distance(&center, chunk), (chunk.y() - center.y()).abs(), chunk.x(), chunk.y(), chunk.z());
+          let nearest = tracker.loaded_chunks.iter().min_by_key(|chunk| key(chunk)).copied();
+          prop_assert_eq!(tracker.nearest_loaded_chunk(&center), nearest);
+          for chunk in tracker.loaded_chunks.iter() {
+              prop_assert!(tracker.is_loaded(chunk));
+          }
+      }
+
+      #[test]
+      fn spatial_queries_should_reach_the_coordinate_limits(offsets in prop::collection::vec((0i64..20, 0i64..20), 1..20), radius in prop::sample::select(vec![0, 10, i64::MAX])) {
+          let mut tracker = ChunkTracker::default();
+          for (x, z) in offsets.iter() {
+              tracker.try_spawn(&ChunkId::new(i64::MAX - x, 0, i64::MIN + z), 0.);
+          }
+          let center = ChunkId::new(i64::MAX, 0, i64::MIN);
+          let found: HashSet<_> = tracker.loaded_chunks_in_radius(&center, radius).collect();
+          let expected: HashSet<_> = tracker
+              .loaded_chunks
+              .iter()
+              .filter(|chunk| ring_distance(&center, chunk) <= radius)
+              .cloned()
+              .collect();
+          prop_assert_eq!(found, expected);
+      }
+
+      #[test]
+      fn despawn_should_respect_min_resident_time(spawned_at in 0f64..1000., elapsed in 0f64..10., min_resident in 0f64..10.) {
+          let mut tracker = ChunkTracker::default();
+          let chunk = ChunkId::new(1, 0, 2);
+          assert!(tracker.try_spawn(&chunk, spawned_at));
+          let despawned = tracker.try_despawn(&chunk, spawned_at + elapsed, min_resident);
+          assert_eq!(despawned, spawned_at + elapsed - spawned_at >= min_resident);
+          assert_eq!(tracker.loaded_chunks.contains(&chunk), !despawned);
+      }
+
+      #[test]
+      fn pinned_chunks_should_not_despawn(x in -5i64..=5, z in -5i64..=5, elapsed in 0f64..10.) {
+          let mut tracker = ChunkTracker::default();
+          let chunk = ChunkId::new(x, 0, z);
+          tracker.pin(chunk);
+          assert!(tracker.try_spawn(&chunk, 0.));
+          assert!(!tracker.try_despawn(&chunk, elapsed, 0.));
+
+          tracker.clear();
+          assert!(tracker.is_pinned(&chunk));
+          assert!(!tracker.loaded_chunks.contains(&chunk));
+
+          tracker.unpin(&chunk);
+          assert!(tracker.try_spawn(&chunk, 0.));
+          assert!(tracker.try_despawn(&chunk, elapsed, 0.));
+      }
+  }
 }
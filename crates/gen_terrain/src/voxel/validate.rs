@@ -0,0 +1,188 @@
+use super::{
+  layout::CubicVoxelLayout,
+  light::NEIGHBOR_OFFSETS,
+  lod::ChunkLod,
+  mesher::mesh_faces,
+  pipeline::MeshPending,
+  registry::{VoxelRegistry, VoxelTypeId},
+  Chunk, ChunkVoxelData, DirtyChunk, VoxelId,
+};
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+// a face of a chunk's mesh that doesn't match the voxels on either side of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StitchProblem {
+  // a face between two voxels that hide each other
+  HiddenFace { voxel: VoxelId, facing: VoxelId },
+  // no face where a solid voxel meets one that can be seen through
+  MissingFace { voxel: VoxelId, facing: VoxelId },
+}
+impl StitchProblem {
+  pub fn facing(&self) -> VoxelId {
+    match self {
+      StitchProblem::HiddenFace { facing, .. } | StitchProblem::MissingFace { facing, .. } => {
+        *facing
+      }
+    }
+  }
+}
+
+// compares the faces of a chunk's mesh with its voxels
+// chunks close their borders, so every solid voxel needs its faces toward other chunks whatever
+// is across them, solid voxels of a loaded neighbor included, or the border opens up as soon as
+// the neighbor changes
+pub fn stitching_problems(
+  registry: &VoxelRegistry,
+  voxels: &HashMap<VoxelId, VoxelTypeId>,
+  faces: &[(VoxelId, VoxelId)],
+) -> Vec<StitchProblem> {
+  let faces: HashSet<_> = faces.iter().copied().collect();
+  let mut problems: Vec<_> = faces
+    .iter()
+    .filter(|(voxel, facing)| {
+      let solid = matches!(voxels.get(voxel), Some(voxel_type) if registry.is_solid(*voxel_type));
+      !solid || matches!(voxels.get(facing), Some(other) if hides(registry, voxels[voxel], *other))
+    })
+    .map(|(voxel, facing)| StitchProblem::HiddenFace {
+      voxel: *voxel,
+      facing: *facing,
+    })
+    .collect();
+
+  for (voxel, voxel_type) in voxels.iter() {
+    if !registry.is_solid(*voxel_type) {
+      continue;
+    }
+    for (x, y, z) in NEIGHBOR_OFFSETS {
+      let facing = *voxel + VoxelId::new(x, y, z);
+      let visible = match voxels.get(&facing) {
+        Some(other) => !hides(registry, *voxel_type, *other),
+        None => true,
+      };
+      if visible && !faces.contains(&(*voxel, facing)) {
+        problems.push(StitchProblem::MissingFace {
+          voxel: *voxel,
+          facing,
+        });
+      }
+    }
+  }
+  problems
+}
+
+// the same rule the mesher culls faces by
+fn hides(registry: &VoxelRegistry, voxel_type: VoxelTypeId, other: VoxelTypeId) -> bool {
+  registry.is_opaque(other) || other == voxel_type
+}
+
+// checks every chunk whose mesh was just built or patched and fails on faces that don't match
+// the voxels
+#[allow(clippy::type_complexity)]
+pub fn validate_chunk_stitching(
+  layout: Res<CubicVoxelLayout>,
+  registry: Res<VoxelRegistry>,
+  meshes: Res<Assets<Mesh>>,
+  mut mesh_events: EventReader<AssetEvent<Mesh>>,
+  chunks: Query<
+    (&Chunk, &ChunkVoxelData, &Handle<Mesh>, Option<&ChunkLod>),
+    (Without<DirtyChunk>, Without<MeshPending>),
+  >,
+) {
+  let changed: HashSet<_> = mesh_events
+    .iter()
+    .filter_map(|event| match event {
+      AssetEvent::Created { handle } | AssetEvent::Modified { handle } => Some(handle.id),
+      AssetEvent::Removed { .. } => None,
+    })
+    .collect();
+  if changed.is_empty() {
+    return;
+  }
+
+  for (chunk, voxels, handle, lod) in chunks.iter() {
+    // lower detail meshes don't follow the voxels, their sides are skirts
+    if !changed.contains(&handle.id) || matches!(lod, Some(lod) if lod.0 > 0) {
+      continue;
+    }
+    let faces = match meshes
+      .get(handle)
+      .and_then(|mesh| mesh_faces(mesh, &layout, &chunk.id))
+    {
+      Some(faces) => faces,
+      None => continue,
+    };
+
+    let problems = stitching_problems(&registry, &voxels.voxels, &faces);
+    let facing: HashSet<_> = problems
+      .iter()
+      .map(|problem| layout.voxel_to_chunk(&problem.facing()))
+      .collect();
+    debug_assert!(
+      problems.is_empty(),
+      "faces of chunk {:?} toward chunks {:?} don't match their voxels, {} problems e.g. {:?}",
+      chunk.id,
+      facing,
+      problems.len(),
+      problems.first()
+    );
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::{light::ChunkLight, mesher::mesh_chunk, ChunkId};
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn meshed_chunks_should_stitch(seed in any::<u64>(), flip in any::<prop::sample::Index>()) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 4);
+          let registry = VoxelRegistry::default();
+          let chunk = ChunkId::new(0, 0, 0);
+          let world: HashMap<_, _> = [chunk, ChunkId::new(1, 0, 0)]
+              .iter()
+              .flat_map(|chunk| layout.iter_chunk_voxels(chunk))
+              .map(|voxel| {
+                  let solid = (voxel.stable_hash() ^ seed) % 2 == 0;
+                  (voxel, if solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR })
+              })
+              .collect();
+          let mut voxels: HashMap<_, _> = world
+              .iter()
+              .filter(|(voxel, _)| layout.voxel_to_chunk(voxel) == chunk)
+              .map(|(voxel, voxel_type)| (*voxel, *voxel_type))
+              .collect();
+          let mesh = mesh_chunk(&layout, &registry, &chunk, &voxels, &ChunkLight::compute(&voxels, &registry));
+          let faces = mesh_faces(&mesh, &layout, &chunk).unwrap();
+          assert_eq!(stitching_problems(&registry, &voxels, &faces), vec![]);
+
+          // faces toward solid voxels of the next chunk close the border, they can't go missing
+          // either
+          let border = faces.iter().copied().find(|(_, facing)| {
+              layout.voxel_to_chunk(facing) != chunk && world.get(facing) == Some(&VoxelTypeId::DIRT)
+          });
+          if let Some((voxel, facing)) = border {
+              let open: Vec<_> = faces.iter().copied().filter(|face| *face != (voxel, facing)).collect();
+              assert_eq!(
+                  stitching_problems(&registry, &voxels, &open),
+                  vec![StitchProblem::MissingFace { voxel, facing }]
+              );
+          }
+
+          // the mesh no longer matches once a voxel changes
+          let mut sorted: Vec<_> = voxels.keys().copied().collect();
+          sorted.sort_unstable_by_key(|voxel| (voxel.x(), voxel.y(), voxel.z()));
+          let flipped = sorted[flip.index(sorted.len())];
+          let voxel_type = voxels.get_mut(&flipped).unwrap();
+          *voxel_type = if *voxel_type == VoxelTypeId::AIR { VoxelTypeId::DIRT } else { VoxelTypeId::AIR };
+          let problems = stitching_problems(&registry, &voxels, &faces);
+          let exposed = NEIGHBOR_OFFSETS.iter().any(|(x, y, z)| {
+              let facing = flipped + VoxelId::new(*x, *y, *z);
+              voxels.get(&facing).or_else(|| world.get(&facing)) == Some(&VoxelTypeId::AIR)
+          });
+          assert!(!exposed || !problems.is_empty());
+      }
+  }
+}
@@ -0,0 +1,328 @@
+use super::{
+  bounds::ChunkBounds,
+  far_chunks::FarChunk,
+  layout::CubicVoxelLayout,
+  registry::VoxelRegistry,
+  tracker::ChunkTracker,
+  world::{TerrainWorld, TerrainWorlds},
+  wrap::chunk_placement,
+  Chunk, ChunkId, ChunkSpawner, ChunkVoxelData, DirtyChunk,
+};
+use bevy::{
+  ecs::system::EntityCommands,
+  math::Vec3A,
+  prelude::*,
+  render::primitives::{Frustum, Sphere},
+};
+
+pub struct ChunkVisibilitySettings {
+  // world units a chunk has to be outside of every spawner's view before its mesh is hidden
+  // chunks just outside the view stay visible so turning the camera doesn't show gaps
+  pub hide_margin: f32,
+  // solid chunks walled in by solid chunks on every side aren't meshed
+  pub cull_enclosed: bool,
+}
+impl Default for ChunkVisibilitySettings {
+  fn default() -> Self {
+    Self {
+      hide_margin: 32.0,
+      cull_enclosed: true,
+    }
+  }
+}
+
+// marks a chunk outside the view of every spawner with a camera, it's meshed after chunks in view
+#[derive(Debug, Default, Component)]
+pub struct OutsideView;
+
+// marks a chunk that was left unmeshed because nothing can see it, it's meshed once it or a chunk
+// next to it opens up
+#[derive(Debug, Default, Component)]
+pub struct EnclosedChunk;
+
+// marks a chunk without any solid voxels, e.g. high in the sky, it has nothing to mesh
+#[derive(Debug, Default, Component)]
+pub struct EmptyChunk;
+
+// the chunks sharing a face with `chunk`, including the sections above and below
+pub fn face_neighbors(chunk: &ChunkId) -> impl Iterator<Item = ChunkId> {
+  let chunk = *chunk;
+  [
+    (1, 0, 0),
+    (-1, 0, 0),
+    (0, 1, 0),
+    (0, -1, 0),
+    (0, 0, 1),
+    (0, 0, -1),
+  ]
+  .into_iter()
+  .map(move |(x, y, z)| chunk + ChunkId::new(x, y, z))
+}
+
+pub fn is_empty_chunk(voxel_data: &ChunkVoxelData, registry: &VoxelRegistry) -> bool {
+  !voxel_data
+    .voxels
+    .values()
+    .any(|voxel_type| registry.is_solid(*voxel_type))
+}
+
+// also drops the mesh of a chunk that was emptied out
+pub fn mark_empty_chunk(entity: &mut EntityCommands) {
+  entity
+    .insert(EmptyChunk)
+    .remove::<DirtyChunk>()
+    .remove::<Handle<Mesh>>();
+}
+
+pub fn is_opaque_chunk(voxel_data: &ChunkVoxelData, registry: &VoxelRegistry) -> bool {
+  voxel_data
+    .voxels
+    .values()
+    .all(|voxel_type| registry.is_opaque(*voxel_type))
+}
+
+// whether every face neighbor of a chunk is opaque, `opaque` returns None for chunks that aren't
+// loaded, which might be open
+// nothing is below the world so the bottom sections only need walls on the sides
+pub fn is_walled_in(
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+  opaque: impl Fn(&ChunkId) -> Option<bool>,
+) -> bool {
+  face_neighbors(chunk).all(|neighbor| {
+    neighbor.y() < 0
+      || (neighbor.y() < layout.vertical_sections() && opaque(&neighbor) == Some(true))
+  })
+}
+
+// chunks are classified again when their voxels change, e.g. by being dug into or built on
+// a chunk that opened up exposes itself and its neighbors
+#[allow(clippy::type_complexity)]
+pub fn classify_changed_chunks(
+  mut commands: Commands,
+  registry: Res<VoxelRegistry>,
+  tracker: Res<ChunkTracker>,
+  worlds: Res<TerrainWorlds>,
+  changed: Query<
+    (
+      Entity,
+      &Chunk,
+      &ChunkVoxelData,
+      Option<&EmptyChunk>,
+      Option<&TerrainWorld>,
+    ),
+    Changed<ChunkVoxelData>,
+  >,
+  enclosed: Query<(), With<EnclosedChunk>>,
+) {
+  for (entity, chunk, voxel_data, empty, world) in changed.iter() {
+    match (is_empty_chunk(voxel_data, &registry), empty) {
+      (true, None) => mark_empty_chunk(&mut commands.entity(entity)),
+      (false, Some(_)) => {
+        commands.entity(entity).remove::<EmptyChunk>();
+      }
+      _ => {}
+    }
+    if is_opaque_chunk(voxel_data, &registry) {
+      continue;
+    }
+    let tracker = match worlds.tracker(&world.copied().unwrap_or_default(), &tracker) {
+      Some(tracker) => tracker,
+      None => continue,
+    };
+    for uncovered in std::iter::once(chunk.id).chain(face_neighbors(&chunk.id)) {
+      if let Some(entity) = tracker.entity(&uncovered) {
+        if enclosed.get(entity).is_ok() {
+          commands.entity(entity).remove::<EnclosedChunk>();
+        }
+      }
+    }
+  }
+}
+
+// a sphere enclosing every voxel of the chunk
+pub fn chunk_bounding_sphere(layout: &CubicVoxelLayout, chunk: &ChunkId) -> Sphere {
+  chunk_bounding_sphere_at(layout, layout.chunk_to_space(chunk))
+}
+
+// the same for a chunk placed at `origin`, e.g. by `chunk_placement` in a wrapping world
+pub fn chunk_bounding_sphere_at(layout: &CubicVoxelLayout, origin: Vec3) -> Sphere {
+  // chunks start at their center voxel's column and extend upward by their height
+  let side = layout.voxel_side_length();
+  let half_length = layout.chunk_side_length() * 0.5;
+  let half_height = layout.chunk_voxel_height() as f32 * side * 0.5;
+  let center = origin + Vec3::new(side * 0.5, half_height, side * 0.5);
+  Sphere {
+    center: Vec3A::from(center),
+    radius: Vec3::new(half_length, half_height, half_length).length(),
+  }
+}
+
+// whether the chunk is in view of any of `frusta`, `margin` grows the chunk's bounds
+pub fn in_view(frusta: &[&Frustum], sphere: &Sphere, margin: f32) -> bool {
+  let sphere = Sphere {
+    center: sphere.center,
+    radius: sphere.radius + margin,
+  };
+  frusta
+    .iter()
+    .any(|frustum| frustum.intersects_sphere(&sphere, true))
+}
+
+// meshes of far chunks and chunks well outside the view are hidden
+#[allow(clippy::type_complexity)]
+pub fn update_chunk_visibility(
+  mut commands: Commands,
+  settings: Res<ChunkVisibilitySettings>,
+  layout: Res<CubicVoxelLayout>,
+  sites: Query<(Option<&Frustum>, &Transform, Option<&TerrainWorld>), With<ChunkSpawner>>,
+  mut chunks: Query<(
+    Entity,
+    &Chunk,
+    Option<&TerrainWorld>,
+    Option<&ChunkBounds>,
+    Option<&OutsideView>,
+    Option<&FarChunk>,
+    Option<&mut Visibility>,
+  )>,
+) {
+  let frusta: Vec<_> = sites.iter().filter_map(|(frustum, ..)| frustum).collect();
+
+  for (entity, chunk, world, bounds, outside_view, far_chunk, visibility) in chunks.iter_mut() {
+    // without cameras everything is in view
+    let (visible, hidden) = if frusta.is_empty() {
+      (true, false)
+    } else {
+      // the bounds of the solid voxels are tighter than the whole chunk's
+      let sphere = match bounds {
+        Some(bounds) => bounds.bounding_sphere(),
+        None => {
+          let world = world.copied().unwrap_or_default();
+          let spawners = sites
+            .iter()
+            .filter(|(.., site_world)| site_world.copied().unwrap_or_default() == world)
+            .map(|(_, transform, _)| transform.translation);
+          chunk_bounding_sphere_at(&layout, chunk_placement(&layout, &chunk.id, spawners))
+        }
+      };
+      (
+        in_view(&frusta, &sphere, 0.),
+        !in_view(&frusta, &sphere, settings.hide_margin),
+      )
+    };
+
+    match (visible, outside_view) {
+      (false, None) => {
+        commands.entity(entity).insert(OutsideView);
+      }
+      (true, Some(_)) => {
+        commands.entity(entity).remove::<OutsideView>();
+      }
+      _ => {}
+    }
+
+    // full meshes of far chunks are kept around but hidden
+    let show = !hidden && far_chunk.is_none();
+    if let Some(mut visibility) = visibility {
+      if visibility.is_visible != show {
+        visibility.is_visible = show;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::voxel::registry::VoxelTypeId;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn chunk_voxels_should_be_within_bounding_sphere(x in -100i64..100, y in 0i64..4, z in -100i64..100, voxel_length in 1i64..6, height in 1i64..10) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 1.0, voxel_length, height)
+              .with_vertical_sections(4);
+          let chunk = ChunkId::new(x, y, z);
+          let sphere = chunk_bounding_sphere(&layout, &chunk);
+
+          for voxel in layout.get_chunk_voxels(&chunk) {
+              let corner = layout.voxel_to_space(&voxel);
+              for offset in [Vec3::ZERO, Vec3::ONE] {
+                  let distance = (Vec3A::from(corner + offset) - sphere.center).length();
+                  assert!(distance <= sphere.radius + 1e-3, "{:?} {}", voxel, distance);
+              }
+          }
+      }
+
+      #[test]
+      fn only_chunks_surrounded_by_opaque_chunks_should_be_walled_in(x in -100i64..100, y in 0i64..4, z in -100i64..100, open in 0usize..6) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 1.0, 4, 4)
+              .with_vertical_sections(4);
+          let chunk = ChunkId::new(x, y, z);
+          let open_chunk = face_neighbors(&chunk).nth(open).unwrap();
+
+          // the bottom is always walled in and the top section is open to the sky
+          prop_assert_eq!(is_walled_in(&layout, &chunk, |_| Some(true)), y < 3);
+          prop_assert!(!is_walled_in(&layout, &chunk, |_| None));
+          prop_assert_eq!(
+              is_walled_in(&layout, &chunk, |neighbor| Some(neighbor != &open_chunk)),
+              y < 3 && open_chunk.y() < 0
+          );
+      }
+
+      #[test]
+      fn chunks_should_be_classified_by_their_solid_voxels(solid in prop::collection::vec(any::<bool>(), 64)) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 1.0, 4, 4);
+          let registry = VoxelRegistry::default();
+          let voxel_data = ChunkVoxelData {
+              voxels: layout
+                  .get_chunk_voxels(&ChunkId::new(0, 0, 0))
+                  .into_iter()
+                  .zip(solid.iter())
+                  .map(|(voxel, solid)| (voxel, if *solid { VoxelTypeId::DIRT } else { VoxelTypeId::AIR }))
+                  .collect(),
+          };
+
+          prop_assert_eq!(is_empty_chunk(&voxel_data, &registry), solid.iter().all(|solid| !solid));
+          prop_assert_eq!(is_opaque_chunk(&voxel_data, &registry), solid.iter().all(|solid| *solid));
+      }
+
+      #[test]
+      fn enclosed_chunks_should_be_uncovered_once_a_neighbor_opens(neighbor in 0usize..6, dug in 0usize..64, open in any::<bool>()) {
+          let layout = CubicVoxelLayout::new(ChunkId::new(0, 0, 0), 1.0, 4, 4);
+          let solid_voxels = |chunk: &ChunkId| ChunkVoxelData {
+              voxels: layout.get_chunk_voxels(chunk).into_iter().map(|voxel| (voxel, VoxelTypeId::DIRT)).collect(),
+          };
+          let mut world = World::new();
+          let mut tracker = ChunkTracker::default();
+          let enclosed_id = ChunkId::new(0, 1, 0);
+          let enclosed = world
+              .spawn()
+              .insert_bundle((Chunk { id: enclosed_id, ..Default::default() }, solid_voxels(&enclosed_id), EnclosedChunk))
+              .id();
+          tracker.register_entity(enclosed_id, enclosed);
+          let neighbor_id = face_neighbors(&enclosed_id).nth(neighbor).unwrap();
+          let neighbor = world
+              .spawn()
+              .insert_bundle((Chunk { id: neighbor_id, ..Default::default() }, solid_voxels(&neighbor_id)))
+              .id();
+          tracker.register_entity(neighbor_id, neighbor);
+          world.insert_resource(tracker);
+          world.insert_resource(TerrainWorlds::default());
+          world.insert_resource(VoxelRegistry::default());
+
+          // loading opaque chunks leaves the enclosed chunk be
+          let mut stage = SystemStage::single_threaded().with_system(classify_changed_chunks);
+          stage.run(&mut world);
+          prop_assert!(world.get::<EnclosedChunk>(enclosed).is_some());
+
+          // digging into the neighbor uncovers it, changes that leave it opaque don't
+          let voxel = layout.get_chunk_voxels(&neighbor_id)[dug];
+          let mut voxel_data = world.get_mut::<ChunkVoxelData>(neighbor).unwrap();
+          let voxel_type = if open { VoxelTypeId::AIR } else { VoxelTypeId::DIRT };
+          voxel_data.voxels.insert(voxel, voxel_type);
+          stage.run(&mut world);
+          prop_assert_eq!(world.get::<EnclosedChunk>(enclosed).is_none(), open);
+      }
+  }
+}
@@ -0,0 +1,179 @@
+use super::{generator::WorldGenConfig, tracker::ChunkTracker, ChunkId};
+use bevy::{prelude::*, render::view::RenderLayers};
+use std::collections::HashMap;
+
+// the voxel world a spawner streams and a chunk belongs to
+// spawners without one stream the primary world, which is the one described by `ChunkTracker`
+// and `WorldGenConfig`, it's the only full world: other worlds have to be added to
+// `TerrainWorlds` first and are generation only, e.g. a dungeon dimension shown through a portal
+// with its own seed
+// other worlds are streamed, generated with their own config and meshed, nothing else knows about
+// them: they can't be edited, aren't saved to the `ChunkStore`, cached or snapshotted, and
+// `TerrainQuery`, spawner environments and biome events only see the primary world
+// every world uses the same `CubicVoxelLayout` and `ActiveGenerator`, chunks of all worlds are
+// placed in the same space, the chunks of other worlds are drawn on their own render layer so
+// they only show up for cameras on it
+// a game that moves between full worlds replaces the primary world's `WorldGenConfig` and
+// `ChunkStore` instead
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct TerrainWorld(pub u32);
+impl TerrainWorld {
+  pub const PRIMARY: TerrainWorld = TerrainWorld(0);
+
+  pub fn is_primary(&self) -> bool {
+    *self == Self::PRIMARY
+  }
+
+  // the layer the world's chunks and decorations are drawn on, the world's id up to the last
+  // layer, the primary world is on the default layer 0
+  pub fn render_layer(&self) -> RenderLayers {
+    RenderLayers::layer(self.0.min(RenderLayers::TOTAL_LAYERS as u32 - 1) as u8)
+  }
+}
+
+struct SecondaryWorld {
+  config: WorldGenConfig,
+  tracker: ChunkTracker,
+}
+
+// the generation only worlds streamed alongside the primary one, each with its own config and
+// loaded chunks, see `TerrainWorld`
+#[derive(Default)]
+pub struct TerrainWorlds {
+  worlds: HashMap<TerrainWorld, SecondaryWorld>,
+}
+
+impl TerrainWorlds {
+  // replaces the config of a world that's already there, its loaded chunks aren't regenerated
+  // the primary world can't be added, its config is the `WorldGenConfig` resource
+  pub fn insert(&mut self, world: TerrainWorld, config: WorldGenConfig) -> bool {
+    if world.is_primary() {
+      return false;
+    }
+    match self.worlds.get_mut(&world) {
+      Some(existing) => existing.config = config,
+      None => {
+        self.worlds.insert(
+          world,
+          SecondaryWorld {
+            config,
+            tracker: default(),
+          },
+        );
+      }
+    }
+    true
+  }
+
+  // the chunks of the world are despawned, its spawners stop loading chunks
+  pub fn remove(&mut self, world: &TerrainWorld) -> Option<WorldGenConfig> {
+    self.worlds.remove(world).map(|removed| removed.config)
+  }
+
+  pub fn contains(&self, world: &TerrainWorld) -> bool {
+    world.is_primary() || self.worlds.contains_key(world)
+  }
+
+  pub fn worlds(&self) -> impl Iterator<Item = &TerrainWorld> {
+    self.worlds.keys()
+  }
+
+  // the config of `world`, the primary world's is `primary`
+  pub fn config<'a>(
+    &'a self,
+    world: &TerrainWorld,
+    primary: &'a WorldGenConfig,
+  ) -> Option<&'a WorldGenConfig> {
+    match world.is_primary() {
+      true => Some(primary),
+      false => self.worlds.get(world).map(|world| &world.config),
+    }
+  }
+
+  // the loaded chunks of `world`, the primary world's are tracked by `primary`
+  pub fn tracker<'a>(
+    &'a self,
+    world: &TerrainWorld,
+    primary: &'a ChunkTracker,
+  ) -> Option<&'a ChunkTracker> {
+    match world.is_primary() {
+      true => Some(primary),
+      false => self.worlds.get(world).map(|world| &world.tracker),
+    }
+  }
+
+  pub fn tracker_mut<'a>(
+    &'a mut self,
+    world: &TerrainWorld,
+    primary: &'a mut ChunkTracker,
+  ) -> Option<&'a mut ChunkTracker> {
+    match world.is_primary() {
+      true => Some(primary),
+      false => self.worlds.get_mut(world).map(|world| &mut world.tracker),
+    }
+  }
+
+  // forgets the loaded chunks of every world besides the primary one, pins are kept
+  pub(super) fn clear_trackers(&mut self) {
+    for world in self.worlds.values_mut() {
+      world.tracker.clear();
+    }
+  }
+
+  // the loaded chunks of every world besides the primary one
+  pub(super) fn trackers_mut(&mut self) -> impl Iterator<Item = (TerrainWorld, &mut ChunkTracker)> {
+    self
+      .worlds
+      .iter_mut()
+      .map(|(id, world)| (*id, &mut world.tracker))
+  }
+
+  // the chunks streaming anchors need in every world besides the primary one that aren't loaded
+  pub(super) fn unloaded_required(&self) -> impl Iterator<Item = (TerrainWorld, ChunkId, u8)> + '_ {
+    self.worlds.iter().flat_map(|(id, world)| {
+      world
+        .tracker
+        .unloaded_required()
+        .map(move |(chunk, priority)| (*id, chunk, priority))
+    })
+  }
+
+  // the pinned chunks of every world besides the primary one that aren't loaded
+  pub(super) fn unloaded_pins(&self) -> impl Iterator<Item = (TerrainWorld, ChunkId)> + '_ {
+    self.worlds.iter().flat_map(|(id, world)| {
+      world
+        .tracker
+        .pinned()
+        .filter(|chunk| !world.tracker.is_loaded(chunk))
+        .map(move |chunk| (*id, *chunk))
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn worlds_should_track_chunks_separately(world in 1u32..8, x in -10i64..10, z in -10i64..10) {
+          let world = TerrainWorld(world);
+          let chunk = ChunkId::new(x, 0, z);
+          let mut primary = ChunkTracker::default();
+          let mut worlds = TerrainWorlds::default();
+          prop_assert!(worlds.tracker_mut(&world, &mut primary).is_none());
+          prop_assert!(!worlds.insert(TerrainWorld::PRIMARY, WorldGenConfig::default()));
+          let config = WorldGenConfig { seed: 7, ..Default::default() };
+          prop_assert!(worlds.insert(world, config));
+
+          prop_assert!(worlds.tracker_mut(&world, &mut primary).unwrap().try_spawn(&chunk, 0.));
+          prop_assert!(!primary.is_loaded(&chunk));
+          prop_assert!(worlds.tracker_mut(&TerrainWorld::PRIMARY, &mut primary).unwrap().try_spawn(&chunk, 0.));
+          prop_assert_eq!(worlds.config(&world, &WorldGenConfig::default()).map(|config| config.seed), Some(7));
+          // other worlds are drawn apart from the primary one
+          prop_assert_eq!(TerrainWorld::PRIMARY.render_layer(), RenderLayers::default());
+          prop_assert_eq!(world.render_layer(), RenderLayers::layer(world.0 as u8));
+      }
+  }
+}
@@ -0,0 +1,105 @@
+use super::{
+  bounds::ChunkBounds, layout::CubicVoxelLayout, tile::TileChunk, world::TerrainWorld, Chunk,
+  ChunkId, ChunkSpawner,
+};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+// where `chunk` goes in a wrapping world: its image closest to the nearest of `spawners`, so the
+// chunks across the edge of the world show up next to the ones on this side
+// it's `chunk_to_space` in an endless world or without spawners
+pub fn chunk_placement(
+  layout: &CubicVoxelLayout,
+  chunk: &ChunkId,
+  spawners: impl IntoIterator<Item = Vec3>,
+) -> Vec3 {
+  if layout.wrapping().is_none() {
+    return layout.chunk_to_space(chunk);
+  }
+  spawners
+    .into_iter()
+    .map(|spawner| {
+      let placed = layout.chunk_to_space_near(chunk, &spawner);
+      (placed.distance_squared(spawner), placed)
+    })
+    .min_by(|(a, _), (b, _)| a.total_cmp(b))
+    .map_or_else(|| layout.chunk_to_space(chunk), |(_, placed)| placed)
+}
+
+// moves the chunks of a wrapping world along with the spawners as they go around it, whatever is
+// parented to a chunk moves with it
+// chunks are placed when they're spawned, they only move when a spawner crossed into another chunk
+// tile maps are placed in 2D by `tile::place_wrapped_tile_maps`
+#[allow(clippy::type_complexity)]
+pub fn place_wrapped_chunks(
+  layout: Res<CubicVoxelLayout>,
+  mut last_spawners: Local<HashSet<(TerrainWorld, ChunkId)>>,
+  spawners: Query<(&Transform, Option<&TerrainWorld>), (With<ChunkSpawner>, Without<Chunk>)>,
+  mut chunks: Query<
+    (
+      &Chunk,
+      &mut Transform,
+      Option<&mut ChunkBounds>,
+      Option<&TerrainWorld>,
+    ),
+    Without<TileChunk>,
+  >,
+) {
+  if layout.wrapping().is_none() {
+    return;
+  }
+  let current: HashSet<_> = spawners
+    .iter()
+    .map(|(spawner, world)| {
+      let chunk = layout.space_to_unwrapped_chunk(&spawner.translation);
+      (world.copied().unwrap_or_default(), chunk.with_y(0))
+    })
+    .collect();
+  if *last_spawners == current {
+    return;
+  }
+  *last_spawners = current;
+
+  for (chunk, mut transform, bounds, world) in chunks.iter_mut() {
+    let world = world.copied().unwrap_or_default();
+    let spawners = spawners
+      .iter()
+      .filter(|(_, spawner_world)| spawner_world.copied().unwrap_or_default() == world)
+      .map(|(spawner, _)| spawner.translation);
+    let placed = chunk_placement(&layout, &chunk.id, spawners);
+    if transform.translation != placed {
+      // the bounds go along so culling and queries see the chunk where it's drawn
+      if let Some(mut bounds) = bounds {
+        *bounds = bounds.translated(placed - transform.translation);
+      }
+      transform.translation = placed;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  proptest! {
+      #[test]
+      fn wrapped_chunks_should_be_placed_next_to_the_spawner(columns in 4i64..32, laps in -3i64..3, x in 0i64..32, z in 0i64..32) {
+          let layout = CubicVoxelLayout::new(ChunkId::default(), 1.0, 2, 4).with_wrapping(columns, columns);
+          let (x, z) = (x % columns, z % columns);
+          // a spawner that went around the world `laps` times
+          let spawner = ChunkId::new(x + laps * columns, 0, z);
+          let position = layout.chunk_to_space(&spawner) + Vec3::splat(0.5);
+          prop_assert_eq!(layout.space_to_chunk(&position), ChunkId::new(x, 0, z));
+
+          for neighbor in layout.get_chunk_neighbors(&ChunkId::new(x, 0, z), 1) {
+              prop_assert!(neighbor.x() >= 0 && neighbor.x() < columns);
+              prop_assert_eq!(layout.chunk_step_distance(&neighbor, &spawner), 1);
+              // neighbors across the edge of the world are placed a chunk away, not a world away
+              let placed = chunk_placement(&layout, &neighbor, [position]);
+              let side = layout.chunk_side_length();
+              prop_assert!((placed - layout.chunk_to_space(&spawner)).abs().max_element() <= side + 1e-3);
+          }
+      }
+  }
+}
@@ -0,0 +1,221 @@
+// terrain drawn with a custom material that picks a layer of an array texture by the voxel type
+// of each face, so every voxel type gets its own texture without splitting chunks into meshes
+// per material
+//
+// $ cargo run --release --example array_texture_terrain
+//
+// the meshers set `ATTRIBUTE_VOXEL_TYPE` on every vertex, the material reads it with flat
+// interpolation, see assets/shaders/array_texture_terrain.wgsl
+use bevy::{
+  ecs::system::{lifetimeless::SRes, SystemParamItem},
+  pbr::{MaterialPipeline, MaterialPlugin},
+  prelude::*,
+  reflect::TypeUuid,
+  render::{
+    mesh::MeshVertexBufferLayout,
+    render_asset::{PrepareAssetError, RenderAsset, RenderAssets},
+    render_resource::*,
+    renderer::RenderDevice,
+  },
+};
+use gen_terrain::{
+  ChunkSpawner, TerrainMaterial, VoxelRegistry, VoxelTerrainPlugin, ATTRIBUTE_VOXEL_TYPE,
+};
+
+// texels per side of each layer
+const LAYER_SIZE: u32 = 16;
+
+fn main() {
+  App::new()
+    .insert_resource(WindowDescriptor {
+      title: "Array Texture Terrain".to_string(),
+      width: 1280.,
+      height: 720.,
+      ..Default::default()
+    })
+    .add_plugins(DefaultPlugins)
+    .add_plugin(VoxelTerrainPlugin)
+    .add_plugin(MaterialPlugin::<ArrayTextureMaterial>::default())
+    .add_plugin(gen_camera::RtsCameraPlugin)
+    .add_startup_system(setup)
+    .add_system(add_chunk_spawner)
+    .add_system(swap_chunk_materials)
+    .run();
+}
+
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "9d4f2c61-0b7e-4a83-b5d2-6e1f8a3c7d90"]
+struct ArrayTextureMaterial {
+  // one layer per voxel type id, types past the last layer use the last one
+  layers: Handle<Image>,
+}
+
+struct ArrayTextureMaterialHandle(Handle<ArrayTextureMaterial>);
+
+fn setup(
+  mut commands: Commands,
+  registry: Res<VoxelRegistry>,
+  mut images: ResMut<Assets<Image>>,
+  mut materials: ResMut<Assets<ArrayTextureMaterial>>,
+) {
+  let layers = images.add(voxel_type_layers(&registry));
+  commands.insert_resource(ArrayTextureMaterialHandle(
+    materials.add(ArrayTextureMaterial { layers }),
+  ));
+  commands.spawn_bundle(DirectionalLightBundle::default());
+}
+
+// a checkered layer tinted by the registry color of each voxel type
+fn voxel_type_layers(registry: &VoxelRegistry) -> Image {
+  let mut data = Vec::new();
+  for (_, info) in registry.iter() {
+    let [r, g, b, _] = info.color.as_rgba_f32();
+    for y in 0..LAYER_SIZE {
+      for x in 0..LAYER_SIZE {
+        let shade = if (x / 4 + y / 4) % 2 == 0 { 1.0 } else { 0.7 };
+        data.extend([r, g, b].map(|c| (c * shade * 255.) as u8));
+        data.push(255);
+      }
+    }
+  }
+  Image::new(
+    Extent3d {
+      width: LAYER_SIZE,
+      height: LAYER_SIZE,
+      depth_or_array_layers: registry.len() as u32,
+    },
+    TextureDimension::D2,
+    data,
+    TextureFormat::Rgba8UnormSrgb,
+  )
+}
+
+fn add_chunk_spawner(
+  mut commands: Commands,
+  cameras: Query<Entity, (With<gen_camera::TerrainAnchor>, Without<ChunkSpawner>)>,
+) {
+  for entity in cameras.iter() {
+    commands.entity(entity).insert(ChunkSpawner::default());
+  }
+}
+
+// chunks are meshed with the terrain material, they're drawn with the array texture instead
+fn swap_chunk_materials(
+  mut commands: Commands,
+  material: Res<ArrayTextureMaterialHandle>,
+  chunks: Query<Entity, Added<Handle<TerrainMaterial>>>,
+) {
+  for entity in chunks.iter() {
+    commands
+      .entity(entity)
+      .remove::<Handle<TerrainMaterial>>()
+      .insert(material.0.clone());
+  }
+}
+
+struct GpuArrayTextureMaterial {
+  bind_group: BindGroup,
+}
+
+impl RenderAsset for ArrayTextureMaterial {
+  type ExtractedAsset = ArrayTextureMaterial;
+  type PreparedAsset = GpuArrayTextureMaterial;
+  type Param = (
+    SRes<RenderDevice>,
+    SRes<MaterialPipeline<Self>>,
+    SRes<RenderAssets<Image>>,
+  );
+
+  fn extract_asset(&self) -> Self::ExtractedAsset {
+    self.clone()
+  }
+
+  fn prepare_asset(
+    material: Self::ExtractedAsset,
+    (render_device, pipeline, images): &mut SystemParamItem<Self::Param>,
+  ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
+    let layers = match images.get(&material.layers) {
+      Some(layers) => layers,
+      None => return Err(PrepareAssetError::RetryNextUpdate(material)),
+    };
+    let view = layers.texture.create_view(&TextureViewDescriptor {
+      dimension: Some(TextureViewDimension::D2Array),
+      ..default()
+    });
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+      label: Some("array texture material bind group"),
+      entries: &[
+        BindGroupEntry {
+          binding: 0,
+          resource: BindingResource::TextureView(&view),
+        },
+        BindGroupEntry {
+          binding: 1,
+          resource: BindingResource::Sampler(&layers.sampler),
+        },
+      ],
+      layout: &pipeline.material_layout,
+    });
+    Ok(GpuArrayTextureMaterial { bind_group })
+  }
+}
+
+impl Material for ArrayTextureMaterial {
+  fn vertex_shader(asset_server: &AssetServer) -> Option<Handle<Shader>> {
+    Some(asset_server.load("shaders/array_texture_terrain.wgsl"))
+  }
+
+  fn fragment_shader(asset_server: &AssetServer) -> Option<Handle<Shader>> {
+    Some(asset_server.load("shaders/array_texture_terrain.wgsl"))
+  }
+
+  fn bind_group(material: &<Self as RenderAsset>::PreparedAsset) -> &BindGroup {
+    &material.bind_group
+  }
+
+  fn bind_group_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+      label: Some("array texture material layout"),
+      entries: &[
+        BindGroupLayoutEntry {
+          binding: 0,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Texture {
+            sample_type: TextureSampleType::Float { filterable: true },
+            view_dimension: TextureViewDimension::D2Array,
+            multisampled: false,
+          },
+          count: None,
+        },
+        BindGroupLayoutEntry {
+          binding: 1,
+          visibility: ShaderStages::FRAGMENT,
+          ty: BindingType::Sampler(SamplerBindingType::Filtering),
+          count: None,
+        },
+      ],
+    })
+  }
+
+  fn specialize(
+    _pipeline: &MaterialPipeline<Self>,
+    descriptor: &mut RenderPipelineDescriptor,
+    layout: &MeshVertexBufferLayout,
+  ) -> Result<(), SpecializedMeshPipelineError> {
+    // chunk meshes always have voxel types, cube meshes also have light levels as colors
+    let mut attributes = vec![
+      Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+      Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+      ATTRIBUTE_VOXEL_TYPE.at_shader_location(2),
+    ];
+    if layout.contains(Mesh::ATTRIBUTE_COLOR) {
+      attributes.push(Mesh::ATTRIBUTE_COLOR.at_shader_location(3));
+      descriptor
+        .vertex
+        .shader_defs
+        .push(String::from("VERTEX_COLORS"));
+    }
+    descriptor.vertex.buffers = vec![layout.get_layout(&attributes)?];
+    Ok(())
+  }
+}
@@ -0,0 +1,220 @@
+// top down preview of the generator's 2d maps, for tuning the noise without loading chunks
+//
+// $ cargo run --release --example worldgen_preview
+//
+// tab cycles the map, left drag or wasd pans, the wheel zooms, n and p step the seed and
+// up/down change the octaves
+use bevy::{
+  input::mouse::{MouseMotion, MouseWheel},
+  prelude::*,
+  render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use gen_terrain::{Biome, ClimateMap, HeightMap, WorldGenConfig};
+
+// pixels of the preview texture per side
+const PREVIEW_SIZE: u32 = 256;
+const PAN_SPEED: f32 = 1.0;
+const MIN_VOXELS_PER_PIXEL: f32 = 0.25;
+const MAX_VOXELS_PER_PIXEL: f32 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PreviewMap {
+  Height,
+  Biome,
+  Temperature,
+  Moisture,
+}
+
+impl PreviewMap {
+  fn next(self) -> Self {
+    match self {
+      PreviewMap::Height => PreviewMap::Biome,
+      PreviewMap::Biome => PreviewMap::Temperature,
+      PreviewMap::Temperature => PreviewMap::Moisture,
+      PreviewMap::Moisture => PreviewMap::Height,
+    }
+  }
+}
+
+struct Preview {
+  map: PreviewMap,
+  // voxel column at the center of the texture
+  center: Vec2,
+  voxels_per_pixel: f32,
+  image: Handle<Image>,
+  redraw: bool,
+}
+
+fn main() {
+  App::new()
+    .insert_resource(WindowDescriptor {
+      title: "Worldgen Preview".to_string(),
+      width: 1024.,
+      height: 1024.,
+      ..Default::default()
+    })
+    .add_plugins(DefaultPlugins)
+    .init_resource::<WorldGenConfig>()
+    .add_startup_system(setup)
+    .add_system(control_preview)
+    .add_system(draw_preview.after(control_preview))
+    .run();
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+  let image = images.add(Image::new_fill(
+    Extent3d {
+      width: PREVIEW_SIZE,
+      height: PREVIEW_SIZE,
+      depth_or_array_layers: 1,
+    },
+    TextureDimension::D2,
+    &[0, 0, 0, 255],
+    TextureFormat::Rgba8UnormSrgb,
+  ));
+
+  commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+  commands.spawn_bundle(SpriteBundle {
+    sprite: Sprite {
+      custom_size: Some(Vec2::splat(1024.)),
+      ..default()
+    },
+    texture: image.clone(),
+    ..default()
+  });
+  commands.insert_resource(Preview {
+    map: PreviewMap::Height,
+    center: Vec2::ZERO,
+    voxels_per_pixel: 4.0,
+    image,
+    redraw: true,
+  });
+}
+
+fn control_preview(
+  keys: Res<Input<KeyCode>>,
+  buttons: Res<Input<MouseButton>>,
+  mut motion: EventReader<MouseMotion>,
+  mut wheel: EventReader<MouseWheel>,
+  mut config: ResMut<WorldGenConfig>,
+  mut preview: ResMut<Preview>,
+) {
+  if keys.just_pressed(KeyCode::Tab) {
+    preview.map = preview.map.next();
+    preview.redraw = true;
+  }
+  if keys.just_pressed(KeyCode::N) {
+    config.seed = config.seed.wrapping_add(1);
+  }
+  if keys.just_pressed(KeyCode::P) {
+    config.seed = config.seed.wrapping_sub(1);
+  }
+  if keys.just_pressed(KeyCode::Up) {
+    config.octaves = (config.octaves + 1).min(8);
+  }
+  if keys.just_pressed(KeyCode::Down) {
+    config.octaves = config.octaves.saturating_sub(1).max(1);
+  }
+
+  // pans are in pixels, the texture is drawn with y pointing down
+  let mut pan = Vec2::ZERO;
+  for (key, direction) in [
+    (KeyCode::W, -Vec2::Y),
+    (KeyCode::S, Vec2::Y),
+    (KeyCode::A, -Vec2::X),
+    (KeyCode::D, Vec2::X),
+  ] {
+    if keys.pressed(key) {
+      pan += direction * PAN_SPEED;
+    }
+  }
+  let drag = motion
+    .iter()
+    .fold(Vec2::ZERO, |sum, event| sum + event.delta);
+  if buttons.pressed(MouseButton::Left) {
+    // the texture is stretched to 4 screen pixels per pixel
+    pan -= drag / 4.;
+  }
+  if pan != Vec2::ZERO {
+    let voxels_per_pixel = preview.voxels_per_pixel;
+    preview.center += pan * voxels_per_pixel;
+    preview.redraw = true;
+  }
+
+  let scroll: f32 = wheel.iter().map(|event| event.y).sum();
+  if scroll != 0. {
+    preview.voxels_per_pixel = (preview.voxels_per_pixel * 0.9f32.powf(scroll))
+      .clamp(MIN_VOXELS_PER_PIXEL, MAX_VOXELS_PER_PIXEL);
+    preview.redraw = true;
+  }
+}
+
+fn draw_preview(
+  config: Res<WorldGenConfig>,
+  mut preview: ResMut<Preview>,
+  mut images: ResMut<Assets<Image>>,
+  mut windows: ResMut<Windows>,
+) {
+  if !preview.redraw && !config.is_changed() {
+    return;
+  }
+  preview.redraw = false;
+  let image = match images.get_mut(&preview.image) {
+    Some(image) => image,
+    None => return,
+  };
+
+  let heights = HeightMap::new(&config);
+  let climate = ClimateMap::new(&config);
+  // heights are shaded from the lowest to the highest surface the config can produce
+  let (low, high) = heights.bounds();
+  let range = (high - low).max(f64::EPSILON);
+  let half = PREVIEW_SIZE as f32 / 2.;
+  for py in 0..PREVIEW_SIZE {
+    for px in 0..PREVIEW_SIZE {
+      let column = preview.center
+        + (Vec2::new(px as f32, py as f32) - Vec2::splat(half)) * preview.voxels_per_pixel;
+      let (x, z) = (column.x.floor() as i64, column.y.floor() as i64);
+      let color = match preview.map {
+        PreviewMap::Height => {
+          let value = ((heights.height(x, z) - low) / range).clamp(0., 1.) as f32;
+          Color::rgb(value, value, value)
+        }
+        PreviewMap::Biome => biome_color(climate.biome(x, z)),
+        PreviewMap::Temperature => {
+          let value = ((climate.temperature(x, z) + 1.) / 2.).clamp(0., 1.) as f32;
+          Color::rgb(value, 0.2, 1. - value)
+        }
+        PreviewMap::Moisture => {
+          let value = ((climate.moisture(x, z) + 1.) / 2.).clamp(0., 1.) as f32;
+          Color::rgb(0.8 - value * 0.6, 0.7 - value * 0.3, 0.3 + value * 0.7)
+        }
+      };
+
+      let offset = ((py * PREVIEW_SIZE + px) * 4) as usize;
+      let [r, g, b, _] = color.as_rgba_f32();
+      image.data[offset..offset + 4].copy_from_slice(&[
+        (r * 255.) as u8,
+        (g * 255.) as u8,
+        (b * 255.) as u8,
+        255,
+      ]);
+    }
+  }
+
+  if let Some(window) = windows.get_primary_mut() {
+    window.set_title(format!(
+      "Worldgen Preview - {:?} - seed {} - {} octaves - {:.2} voxels per pixel",
+      preview.map, config.seed, config.octaves, preview.voxels_per_pixel
+    ));
+  }
+}
+
+fn biome_color(biome: Biome) -> Color {
+  match biome {
+    Biome::Plains => Color::rgb(0.5, 0.75, 0.3),
+    Biome::Forest => Color::rgb(0.1, 0.4, 0.15),
+    Biome::Desert => Color::rgb(0.9, 0.8, 0.5),
+    Biome::Tundra => Color::rgb(0.85, 0.9, 0.95),
+  }
+}
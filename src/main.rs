@@ -1,10 +1,14 @@
-use bevy::prelude::*;
-use gen_terrain::{ChunkSpawner, VoxelTerrainPlugin};
+use bevy::{diagnostic::LogDiagnosticsPlugin, prelude::*};
+use gen_terrain::{
+  Biome, ChunkSpawner, ExportWorldMesh, LoadShape, ScreenToTerrain, TerrainDecorations,
+  TerrainDiagnosticsPlugin, TerrainEditorPlugin, TerrainExtents, VoxelTerrainPlugin,
+};
 
 mod camera;
 
 fn main() {
-  App::new()
+  let mut app = App::new();
+  app
     .insert_resource(WindowDescriptor {
       title: "Procedural Generation".to_string(),
       width: 1920.,
@@ -14,17 +18,60 @@ fn main() {
     .insert_resource(Msaa { samples: 4 })
     .add_plugins(DefaultPlugins)
     .add_plugin(VoxelTerrainPlugin)
-    .add_plugin(gen_camera::RtsCameraPlugin)
+    .add_plugin(TerrainDiagnosticsPlugin)
     .add_startup_system(setup)
     .add_system(add_chunk_spawner)
-    .run();
+    .add_system(add_cursor_raycast)
+    .add_system(sync_spawner_zoom)
+    .add_system(bound_camera_to_terrain)
+    .add_system(export_terrain);
+
+  // `CAMERA=orbit` swaps the rts camera for one orbiting the origin, `CAMERA=fly` for a first
+  // person one
+  match std::env::var("CAMERA").as_deref() {
+    Ok("orbit") => app.add_plugin(gen_camera::OrbitCameraPlugin),
+    Ok("fly") => app.add_plugin(gen_camera::FlyCameraPlugin),
+    _ => app.add_plugin(gen_camera::RtsCameraPlugin),
+  };
+
+  // `TERRAIN_EDITOR=1` digs and places voxels at the cursor, the wheel sizes the brush instead of
+  // only zooming
+  if std::env::var("TERRAIN_EDITOR").is_ok() {
+    app.add_plugin(TerrainEditorPlugin);
+  }
+
+  // `TERRAIN_DIAGNOSTICS=1` logs chunk streaming diagnostics every second
+  if std::env::var("TERRAIN_DIAGNOSTICS").is_ok() {
+    app.add_plugin(LogDiagnosticsPlugin::filtered(vec![
+      TerrainDiagnosticsPlugin::CHUNKS_LOADED,
+      TerrainDiagnosticsPlugin::CHUNKS_MESHED_PER_SECOND,
+      TerrainDiagnosticsPlugin::VOXEL_GENERATION_MS,
+      TerrainDiagnosticsPlugin::MESH_GENERATION_MS,
+      TerrainDiagnosticsPlugin::PENDING_TASKS,
+    ]));
+  }
+
+  #[cfg(feature = "terrain-egui")]
+  app.add_plugin(gen_terrain::TerrainInspectorPlugin);
+
+  app.run();
 }
 
 fn setup(
   mut commands: Commands,
   mut meshes: ResMut<Assets<Mesh>>,
   mut materials: ResMut<Assets<StandardMaterial>>,
+  mut decorations: ResMut<TerrainDecorations>,
 ) {
+  // tufts of grass on the plains and in forests, pebbles in the desert
+  let tuft = meshes.add(Mesh::from(shape::Box::new(0.1, 0.4, 0.1)));
+  decorations.register_decoration(Biome::Plains, tuft.clone(), 0.2);
+  decorations.register_decoration(Biome::Forest, tuft, 0.4);
+  let pebble = meshes.add(Mesh::from(shape::Cube { size: 0.15 }));
+  decorations
+    .register_decoration(Biome::Desert, pebble, 0.05)
+    .material = Some(materials.add(Color::rgb(0.6, 0.55, 0.45).into()));
+
   // cube
   commands.spawn_bundle(PbrBundle {
     mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
@@ -46,9 +93,58 @@ fn setup(
 
 fn add_chunk_spawner(
   mut commands: Commands,
-  qry: Query<Entity, (With<gen_camera::RtsCamera>, Without<ChunkSpawner>)>,
+  windows: Res<Windows>,
+  qry: Query<Entity, (With<gen_camera::TerrainAnchor>, Without<ChunkSpawner>)>,
 ) {
+  // the camera loads what's in its viewport
+  let aspect = windows
+    .get_primary()
+    .map_or(16. / 9., |window| window.width() / window.height().max(1.));
   for entity in qry.iter() {
-    commands.entity(entity).insert(ChunkSpawner::default());
+    let mut spawner = ChunkSpawner::default();
+    spawner.load_shape = LoadShape::Frustum { aspect };
+    commands.entity(entity).insert(spawner);
+  }
+}
+
+// the rts camera picks the terrain under the cursor, see `CursorTerrainHit`
+fn add_cursor_raycast(
+  mut commands: Commands,
+  qry: Query<Entity, (With<gen_camera::RtsCamera>, Without<ScreenToTerrain>)>,
+) {
+  for entity in qry.iter() {
+    commands.entity(entity).insert(ScreenToTerrain::default());
+  }
+}
+
+fn sync_spawner_zoom(
+  mut qry: Query<(&gen_camera::CameraRig, &mut ChunkSpawner), Changed<gen_camera::CameraRig>>,
+) {
+  for (rig, mut spawner) in qry.iter_mut() {
+    spawner.zoom = rig.zoom;
+  }
+}
+
+// keeps the rts camera over terrains that have an end, e.g. heightmaps
+fn bound_camera_to_terrain(
+  extents: Option<Res<TerrainExtents>>,
+  config: Option<ResMut<gen_camera::RtsCameraConfig>>,
+) {
+  if let (Some(extents), Some(mut config)) = (extents, config) {
+    if extents.is_changed() {
+      config.bounds = Some(Rect {
+        left: extents.min.x,
+        right: extents.max.x,
+        top: extents.min.y,
+        bottom: extents.max.y,
+      });
+    }
+  }
+}
+
+// F12 writes the loaded terrain to terrain.gltf in the working directory
+fn export_terrain(keys: Res<Input<KeyCode>>, mut exports: EventWriter<ExportWorldMesh>) {
+  if keys.just_pressed(KeyCode::F12) {
+    exports.send(ExportWorldMesh("terrain.gltf".into()));
   }
 }